@@ -45,7 +45,17 @@ export default defineConfig({
           label: 'Commands',
           items: [
             { label: 'generate', slug: 'commands/generate' },
+            { label: 'audit', slug: 'commands/audit' },
             { label: 'validate', slug: 'commands/validate' },
+            { label: 'fmt', slug: 'commands/fmt' },
+            { label: 'lint', slug: 'commands/lint' },
+            { label: 'migrate', slug: 'commands/migrate' },
+            { label: 'new', slug: 'commands/new' },
+            { label: 'orbs', slug: 'commands/orbs' },
+            { label: 'run', slug: 'commands/run' },
+            { label: 'schema', slug: 'commands/schema' },
+            { label: 'stats', slug: 'commands/stats' },
+            { label: 'verify', slug: 'commands/verify' },
           ],
         },
         {
@@ -53,19 +63,25 @@ export default defineConfig({
           items: [
             { label: 'Overview', slug: 'configuration/overview' },
             { label: 'Checkout', slug: 'configuration/checkout' },
+            { label: 'Docker Builds', slug: 'configuration/docker-build' },
             { label: 'Cache System', slug: 'configuration/cache' },
             { label: 'Package Management', slug: 'configuration/packages' },
           ],
         },
         {
           label: 'Providers',
-          items: [{ label: 'CircleCI', slug: 'providers/circleci' }],
+          items: [
+            { label: 'CircleCI', slug: 'providers/circleci' },
+            { label: 'GitHub Actions', slug: 'providers/github-actions' },
+          ],
         },
         {
           label: 'Advanced Features',
           items: [
             { label: 'OR Dependencies', slug: 'advanced/or-dependencies' },
             { label: 'Job Skipping', slug: 'advanced/job-skipping' },
+            { label: 'Affected Projects', slug: 'advanced/affected-projects' },
+            { label: 'Plugins', slug: 'advanced/plugins' },
           ],
         },
         {
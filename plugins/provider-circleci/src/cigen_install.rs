@@ -0,0 +1,394 @@
+//! How the setup job gets a `cigen` binary (`setup_options.install_strategy`)
+//!
+//! - `download` fetches the release archive for the runner's OS and
+//!   architecture and checks it against the published SHA-256
+//! - `compile` builds from source, caching cargo's registry and target
+//!   directory keyed on the lockfile
+//! - `path` assumes the setup image already has `cigen` installed
+//!
+//! `compile_cigen: true` is the older spelling of `install_strategy: compile`.
+
+use anyhow::{Result, bail};
+use cigen::schema::versioned_cache_key;
+use serde_yaml::{Mapping, Value};
+
+/// Where `download` fetches release archives from
+const RELEASES_URL: &str = "https://github.com/DocSpring/cigen/releases/download";
+
+/// Checkout `compile` clones into when `compile_repository` is set
+const DEFAULT_COMPILE_PATH: &str = "/tmp/cigen";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InstallStrategy {
+    Download,
+    Compile,
+    #[default]
+    Path,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CigenInstall {
+    pub strategy: InstallStrategy,
+    /// Release `download` fetches, without the leading `v`
+    pub version: Option<String>,
+    pub compile_repository: Option<String>,
+    pub compile_ref: Option<String>,
+    pub compile_path: Option<String>,
+}
+
+impl CigenInstall {
+    /// Install settings from the `setup_options` mapping
+    pub fn from_setup_options(map: &Mapping) -> Result<Self> {
+        let string = |key: &str| {
+            map.get(&Value::String(key.into()))
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        };
+        let compile_cigen = map
+            .get(&Value::String("compile_cigen".into()))
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+
+        let strategy = match string("install_strategy").as_deref() {
+            None if compile_cigen => InstallStrategy::Compile,
+            None => InstallStrategy::Path,
+            Some("download") => InstallStrategy::Download,
+            Some("compile") => InstallStrategy::Compile,
+            Some("path") => InstallStrategy::Path,
+            Some(other) => bail!(
+                "setup_options.install_strategy must be download, compile or path, not '{other}'"
+            ),
+        };
+        if compile_cigen && strategy != InstallStrategy::Compile {
+            bail!(
+                "setup_options.compile_cigen: true conflicts with install_strategy: {}",
+                string("install_strategy").unwrap_or_default()
+            );
+        }
+
+        let version = string("cigen_version").map(|version| {
+            version
+                .strip_prefix('v')
+                .map(str::to_string)
+                .unwrap_or(version)
+        });
+        if let Some(version) = &version {
+            if strategy != InstallStrategy::Download {
+                bail!("setup_options.cigen_version only applies to install_strategy: download");
+            }
+            if version.is_empty()
+                || !version
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+'))
+            {
+                bail!("setup_options.cigen_version '{version}' isn't a release version");
+            }
+        }
+
+        Ok(Self {
+            strategy,
+            version,
+            compile_repository: string("compile_repository"),
+            compile_ref: string("compile_ref"),
+            compile_path: string("compile_path"),
+        })
+    }
+
+    /// Default setup image: `compile` needs cargo, and `path` has always run
+    /// on the Rust image; `download` only needs curl and tar
+    pub fn default_image(&self) -> &'static str {
+        match self.strategy {
+            InstallStrategy::Download => "cimg/base:stable",
+            InstallStrategy::Compile | InstallStrategy::Path => "cimg/rust:1.76",
+        }
+    }
+
+    /// Setup job steps that put `cigen` on the `PATH`
+    pub fn steps(&self, cache_version: Option<u32>) -> Vec<Value> {
+        match self.strategy {
+            InstallStrategy::Download => vec![self.download_step()],
+            InstallStrategy::Compile => self.compile_steps(cache_version),
+            InstallStrategy::Path => Vec::new(),
+        }
+    }
+
+    fn download_step(&self) -> Value {
+        let version = self
+            .version
+            .clone()
+            .unwrap_or_else(|| env!("CARGO_PKG_VERSION").to_string());
+        let command = format!(
+            r#"set -euo pipefail
+case "$(uname -s)" in
+  Linux) os=linux ;;
+  Darwin) os=macos ;;
+  *) echo "No prebuilt cigen for $(uname -s)" >&2; exit 1 ;;
+esac
+case "$(uname -m)" in
+  x86_64 | amd64) arch=amd64 ;;
+  aarch64 | arm64) arch=arm64 ;;
+  *) echo "No prebuilt cigen for $(uname -m)" >&2; exit 1 ;;
+esac
+asset="cigen-${{os}}-${{arch}}.tar.gz"
+url="{RELEASES_URL}/v{version}/${{asset}}"
+tmp="$(mktemp -d)"
+curl -fsSL --retry 3 -o "$tmp/$asset" "$url"
+curl -fsSL --retry 3 -o "$tmp/$asset.sha256" "$url.sha256"
+expected="$(awk '{{print $1}}' "$tmp/$asset.sha256")"
+actual="$( (sha256sum "$tmp/$asset" 2>/dev/null || shasum -a 256 "$tmp/$asset") | awk '{{print $1}}')"
+if [ "$expected" != "$actual" ]; then
+  echo "Checksum mismatch for $asset: expected $expected, got $actual" >&2
+  exit 1
+fi
+mkdir -p "$HOME/.local/bin"
+tar xzf "$tmp/$asset" -C "$HOME/.local/bin" cigen
+echo "export PATH=\"$HOME/.local/bin:$PATH\"" >> "$BASH_ENV"
+"#
+        );
+        run_step(&format!("Download cigen {version}"), command)
+    }
+
+    fn compile_steps(&self, cache_version: Option<u32>) -> Vec<Value> {
+        let mut steps = Vec::new();
+        let (checkout, lockfile, target) = match &self.compile_repository {
+            Some(repo) => {
+                let path = self
+                    .compile_path
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_COMPILE_PATH.to_string());
+                let mut lines = vec![
+                    "set -euo pipefail".to_string(),
+                    format!("rm -rf {path}"),
+                    format!("git clone {repo} {path}"),
+                ];
+                if let Some(rev) = &self.compile_ref {
+                    lines.push(format!("git -C {path} checkout {rev}"));
+                }
+                lines.push(String::new());
+                steps.push(run_step("Clone cigen", lines.join("\n")));
+                (
+                    Some(path.clone()),
+                    format!("{path}/Cargo.lock"),
+                    format!("{path}/target"),
+                )
+            }
+            None => (None, "Cargo.lock".to_string(), "target".to_string()),
+        };
+
+        let key_prefix = versioned_cache_key(cache_version, "cigen-cargo-{{ arch }}-");
+        let key = format!("{key_prefix}{{{{ checksum \"{lockfile}\" }}}}");
+        steps.push(cache_step(
+            "restore_cache",
+            "Restore cigen build cache",
+            [(
+                "keys",
+                Value::Sequence(vec![Value::String(key.clone()), Value::String(key_prefix)]),
+            )],
+        ));
+
+        let mut lines = vec!["set -euo pipefail".to_string()];
+        if let Some(path) = &checkout {
+            lines.push(format!("cd {path}"));
+        }
+        lines.push("cargo build --release".to_string());
+        let release_dir = match &checkout {
+            Some(path) => format!("{path}/target/release"),
+            None => "$(pwd)/target/release".to_string(),
+        };
+        lines.push(format!(
+            "echo \"export PATH=\\\"{release_dir}:$PATH\\\"\" >> \"$BASH_ENV\""
+        ));
+        lines.push(String::new());
+        steps.push(run_step("Compile cigen", lines.join("\n")));
+
+        let paths = ["~/.cargo/registry", "~/.cargo/git", target.as_str()]
+            .into_iter()
+            .map(|path| Value::String(path.into()))
+            .collect();
+        steps.push(cache_step(
+            "save_cache",
+            "Save cigen build cache",
+            [
+                ("key", Value::String(key)),
+                ("paths", Value::Sequence(paths)),
+            ],
+        ));
+        steps
+    }
+}
+
+fn run_step(name: &str, command: String) -> Value {
+    let mut run_map = Mapping::new();
+    run_map.insert(Value::String("name".into()), Value::String(name.into()));
+    run_map.insert(Value::String("command".into()), Value::String(command));
+
+    let mut wrapper = Mapping::new();
+    wrapper.insert(Value::String("run".into()), Value::Mapping(run_map));
+    Value::Mapping(wrapper)
+}
+
+fn cache_step<const N: usize>(kind: &str, name: &str, fields: [(&str, Value); N]) -> Value {
+    let mut map = Mapping::new();
+    map.insert(Value::String("name".into()), Value::String(name.into()));
+    for (field, value) in fields {
+        map.insert(Value::String(field.into()), value);
+    }
+
+    let mut wrapper = Mapping::new();
+    wrapper.insert(Value::String(kind.into()), Value::Mapping(map));
+    Value::Mapping(wrapper)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Result<CigenInstall> {
+        let map: Mapping = serde_yaml::from_str(yaml).unwrap();
+        CigenInstall::from_setup_options(&map)
+    }
+
+    fn names(steps: &[Value]) -> Vec<String> {
+        steps
+            .iter()
+            .map(|step| {
+                let (kind, body) = step.as_mapping().unwrap().iter().next().unwrap();
+                format!(
+                    "{}: {}",
+                    kind.as_str().unwrap(),
+                    body["name"].as_str().unwrap()
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn path_is_the_default_and_installs_nothing() {
+        let install = parse("image: cimg/rust:1.88\n").unwrap();
+        assert_eq!(install.strategy, InstallStrategy::Path);
+        assert!(install.steps(None).is_empty());
+        assert_eq!(install.default_image(), "cimg/rust:1.76");
+    }
+
+    #[test]
+    fn download_fetches_the_pinned_release_and_checks_its_checksum() {
+        let install = parse("install_strategy: download\ncigen_version: v0.4.2\n").unwrap();
+        let steps = install.steps(Some(2));
+        assert_eq!(names(&steps), ["run: Download cigen 0.4.2"]);
+        let command = steps[0]["run"]["command"].as_str().unwrap();
+        assert!(
+            command.contains(
+                "url=\"https://github.com/DocSpring/cigen/releases/download/v0.4.2/${asset}\""
+            ),
+            "{command}"
+        );
+        assert!(command.contains("aarch64 | arm64) arch=arm64"), "{command}");
+        assert!(command.contains("Darwin) os=macos"), "{command}");
+        assert!(command.contains("\"$url.sha256\""), "{command}");
+        assert!(
+            command.contains("if [ \"$expected\" != \"$actual\" ]"),
+            "{command}"
+        );
+        assert_eq!(install.default_image(), "cimg/base:stable");
+
+        let unpinned = parse("install_strategy: download\n").unwrap();
+        let command = unpinned.steps(None)[0]["run"]["command"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert!(
+            command.contains(&format!("/v{}/", env!("CARGO_PKG_VERSION"))),
+            "{command}"
+        );
+    }
+
+    #[test]
+    fn compile_caches_cargo_keyed_on_the_lockfile() {
+        let steps = parse("compile_cigen: true\n").unwrap().steps(Some(3));
+        assert_eq!(
+            names(&steps),
+            [
+                "restore_cache: Restore cigen build cache",
+                "run: Compile cigen",
+                "save_cache: Save cigen build cache",
+            ]
+        );
+        let keys: Vec<&str> = steps[0]["restore_cache"]["keys"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .filter_map(Value::as_str)
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                r#"v3-cigen-cargo-{{ arch }}-{{ checksum "Cargo.lock" }}"#,
+                "v3-cigen-cargo-{{ arch }}-",
+            ]
+        );
+        assert_eq!(steps[2]["save_cache"]["key"].as_str(), Some(keys[0]));
+        assert_eq!(
+            steps[2]["save_cache"]["paths"],
+            serde_yaml::from_str::<Value>("[~/.cargo/registry, ~/.cargo/git, target]").unwrap()
+        );
+    }
+
+    #[test]
+    fn compile_from_a_repository_keys_on_its_lockfile() {
+        let steps = parse(
+            "install_strategy: compile\ncompile_repository: https://github.com/DocSpring/cigen.git\ncompile_ref: main\n",
+        )
+        .unwrap()
+        .steps(None);
+        assert_eq!(
+            names(&steps),
+            [
+                "run: Clone cigen",
+                "restore_cache: Restore cigen build cache",
+                "run: Compile cigen",
+                "save_cache: Save cigen build cache",
+            ]
+        );
+        let clone = steps[0]["run"]["command"].as_str().unwrap();
+        assert!(clone.contains("git -C /tmp/cigen checkout main"), "{clone}");
+        let key = r#"cigen-cargo-{{ arch }}-{{ checksum "/tmp/cigen/Cargo.lock" }}"#;
+        assert_eq!(steps[1]["restore_cache"]["keys"][0].as_str(), Some(key));
+        assert_eq!(steps[3]["save_cache"]["key"].as_str(), Some(key));
+        assert_eq!(
+            steps[3]["save_cache"]["paths"][2].as_str(),
+            Some("/tmp/cigen/target")
+        );
+        let compile = steps[2]["run"]["command"].as_str().unwrap();
+        assert!(
+            compile.contains("cd /tmp/cigen\ncargo build --release"),
+            "{compile}"
+        );
+    }
+
+    #[test]
+    fn conflicting_or_unknown_settings_are_rejected() {
+        let error = parse("install_strategy: brew\n").unwrap_err().to_string();
+        assert!(
+            error.contains("must be download, compile or path"),
+            "{error}"
+        );
+        let error = parse("install_strategy: path\ncompile_cigen: true\n")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("conflicts with install_strategy: path"),
+            "{error}"
+        );
+        let error = parse("install_strategy: compile\ncigen_version: 0.4.2\n")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("only applies to install_strategy: download"),
+            "{error}"
+        );
+        let error = parse("install_strategy: download\ncigen_version: '1; rm -rf /'\n")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("isn't a release version"), "{error}");
+    }
+}
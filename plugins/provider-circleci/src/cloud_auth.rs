@@ -0,0 +1,113 @@
+//! `cloud_auth` through the circleci/aws-cli and circleci/gcp-cli orbs
+//!
+//! Both orbs exchange the job's `CIRCLE_OIDC_TOKEN` for short-lived
+//! credentials. The gcp-cli orb takes the names of environment variables
+//! rather than values, so the workload identity settings go into the job's
+//! environment under the names it reads by default.
+
+use cigen::plugin::protocol::{CloudAuth, GcpAuth, JobDefinition};
+use cigen::schema::WorkloadIdentityProvider;
+use serde_yaml::{Mapping, Value};
+
+pub const AWS_CLI_ALIAS: &str = "aws-cli";
+pub const DEFAULT_AWS_CLI_ORB: &str = "circleci/aws-cli@5.1.1";
+pub const GCP_CLI_ALIAS: &str = "gcp-cli";
+pub const DEFAULT_GCP_CLI_ORB: &str = "circleci/gcp-cli@3.3.1";
+
+/// Orbs the jobs' `cloud_auth` steps use, as (alias, orb) pairs
+pub fn cloud_auth_orbs<'a>(
+    jobs: impl IntoIterator<Item = &'a JobDefinition>,
+) -> Vec<(&'static str, &'static str)> {
+    let (mut aws, mut gcp) = (false, false);
+    for cloud_auth in jobs.into_iter().filter_map(|job| job.cloud_auth.as_ref()) {
+        aws |= cloud_auth.aws.is_some();
+        gcp |= cloud_auth.gcp.is_some();
+    }
+    let mut orbs = Vec::new();
+    if aws {
+        orbs.push((AWS_CLI_ALIAS, DEFAULT_AWS_CLI_ORB));
+    }
+    if gcp {
+        orbs.push((GCP_CLI_ALIAS, DEFAULT_GCP_CLI_ORB));
+    }
+    orbs
+}
+
+/// Orb `setup` steps that sign the job in to each configured cloud
+pub fn cloud_auth_steps(cloud_auth: &CloudAuth) -> Vec<Value> {
+    let mut steps = Vec::new();
+    if let Some(aws) = &cloud_auth.aws {
+        let mut params = Mapping::new();
+        params.insert(
+            Value::String("role_arn".into()),
+            Value::String(aws.role_arn.clone()),
+        );
+        if !aws.region.is_empty() {
+            params.insert(
+                Value::String("region".into()),
+                Value::String(aws.region.clone()),
+            );
+        }
+        steps.push(orb_step(AWS_CLI_ALIAS, params));
+    }
+    if cloud_auth.gcp.is_some() {
+        let mut params = Mapping::new();
+        params.insert(Value::String("use_oidc".into()), Value::Bool(true));
+        steps.push(orb_step(GCP_CLI_ALIAS, params));
+    }
+    steps
+}
+
+fn orb_step(alias: &str, params: Mapping) -> Value {
+    let mut step = Mapping::new();
+    step.insert(
+        Value::String(format!("{alias}/setup")),
+        Value::Mapping(params),
+    );
+    Value::Mapping(step)
+}
+
+/// Environment the gcp-cli orb reads its workload identity settings from
+pub fn gcp_environment(gcp: &GcpAuth) -> Vec<(&'static str, String)> {
+    let mut env = Vec::new();
+    if let Some(provider) = WorkloadIdentityProvider::parse(&gcp.workload_identity_provider) {
+        env.extend([
+            ("GOOGLE_PROJECT_NUMBER", provider.project_number.to_string()),
+            ("OIDC_WIP_ID", provider.pool.to_string()),
+            ("OIDC_WIP_PROVIDER_ID", provider.provider.to_string()),
+        ]);
+    }
+    env.push(("OIDC_SERVICE_ACCOUNT_EMAIL", gcp.service_account.clone()));
+    if !gcp.project_id.is_empty() {
+        env.push(("GOOGLE_PROJECT_ID", gcp.project_id.clone()));
+    }
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gcp_settings_go_into_the_environment() {
+        let gcp = GcpAuth {
+            workload_identity_provider:
+                "projects/123456/locations/global/workloadIdentityPools/ci/providers/circleci"
+                    .to_string(),
+            service_account: "deploy@example.iam.gserviceaccount.com".to_string(),
+            project_id: String::new(),
+        };
+        assert_eq!(
+            gcp_environment(&gcp),
+            [
+                ("GOOGLE_PROJECT_NUMBER", "123456".to_string()),
+                ("OIDC_WIP_ID", "ci".to_string()),
+                ("OIDC_WIP_PROVIDER_ID", "circleci".to_string()),
+                (
+                    "OIDC_SERVICE_ACCOUNT_EMAIL",
+                    "deploy@example.iam.gserviceaccount.com".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,330 @@
+//! Compile cigen step conditions for CircleCI
+//!
+//! Branch and parameter checks become `when:` logic statements evaluated when
+//! the pipeline is configured. Environment checks can only be evaluated at
+//! runtime, so they become a shell guard around the step's command.
+
+use anyhow::{Result, bail};
+use cigen::schema::{Comparison, Condition, Literal, branch_regex, shell_quote};
+use serde_yaml::{Mapping, Value};
+
+/// A step condition split into its CircleCI parts
+#[derive(Debug, Default, PartialEq)]
+pub struct StepCondition {
+    /// Logic statement for a `when:` wrapper
+    pub when: Option<Value>,
+    /// Shell test that must pass before the command runs
+    pub guard: Option<String>,
+}
+
+pub fn compile_step_condition(expression: &str) -> Result<StepCondition> {
+    let condition = Condition::parse(expression)?;
+
+    let mut when = Vec::new();
+    let mut guard = Vec::new();
+    for part in condition.conjuncts() {
+        if part.is_static() {
+            when.push(logic_statement(part));
+        } else if part.is_runtime() {
+            guard.push(shell_test(part));
+        } else {
+            bail!(
+                "CircleCI can't combine branch/param checks with env checks under '||' or '!' \
+                 in condition '{expression}'; split them with '&&'"
+            );
+        }
+    }
+
+    Ok(StepCondition {
+        when: match when.len() {
+            0 => None,
+            1 => when.pop(),
+            _ => Some(single("and", Value::Sequence(when))),
+        },
+        guard: (!guard.is_empty()).then(|| guard.join(" && ")),
+    })
+}
+
+/// Compile a workflow `run_when` expression into a `when:` logic statement.
+/// Workflows are chosen when the pipeline is configured, so only branch and
+/// parameter checks can take part.
+pub fn compile_workflow_condition(expression: &str) -> Result<Value> {
+    let condition = Condition::parse(expression)?;
+    if !condition.is_static() {
+        bail!(
+            "env conditions are not supported on CircleCI; use a pipeline parameter (in workflow condition '{expression}')"
+        );
+    }
+    Ok(logic_statement(&condition))
+}
+
+/// Wrap `step` in a `when:` step
+pub fn wrap_in_when(condition: Value, step: Value) -> Value {
+    let mut when = Mapping::new();
+    when.insert(Value::String("condition".into()), condition);
+    when.insert(Value::String("steps".into()), Value::Sequence(vec![step]));
+    single("when", Value::Mapping(when))
+}
+
+/// Prefix a shell command with a guard so it only runs when the guard passes
+pub fn guard_command(guard: &str, command: &str) -> String {
+    format!("if {guard}; then\n{}\nfi\n", command.trim_end_matches('\n'))
+}
+
+/// Step that ends the job early, as a success, when its `unless:` condition
+/// holds. Workflow job entries can't carry a `when:`, so this goes first in
+/// the entry's `pre-steps`.
+pub fn unless_halt_step(expression: &str) -> Result<Value> {
+    let compiled = compile_step_condition(expression)?;
+    let halt = "circleci-agent step halt";
+    let command = match &compiled.guard {
+        Some(guard) => guard_command(guard, halt),
+        None => format!("{halt}\n"),
+    };
+    let mut run = Mapping::new();
+    run.insert(
+        Value::String("name".into()),
+        Value::String(format!("Skip job unless: {expression}")),
+    );
+    run.insert(Value::String("command".into()), Value::String(command));
+    let step = single("run", Value::Mapping(run));
+    Ok(match compiled.when {
+        Some(when) => wrap_in_when(when, step),
+        None => step,
+    })
+}
+
+/// Shell test that `$CIRCLE_BRANCH` matches one of a job's `branches`, or
+/// `None` when the job runs on every branch. The setup job runs on every
+/// branch, so it checks this before doing work for a job the workflow's
+/// branch filters will leave out.
+pub fn branch_guard(branches: &[String]) -> Option<String> {
+    let tests: Vec<String> = branches
+        .iter()
+        .map(|pattern| match branch_regex(pattern) {
+            Some(regex) => format!(
+                "grep -Eqx {} <<< \"${{CIRCLE_BRANCH:-}}\"",
+                shell_quote(regex)
+            ),
+            None => format!("[ \"${{CIRCLE_BRANCH:-}}\" = {} ]", shell_quote(pattern)),
+        })
+        .collect();
+    (!tests.is_empty()).then(|| tests.join(" || "))
+}
+
+fn logic_statement(condition: &Condition) -> Value {
+    match condition {
+        Condition::Branch { op, value } => negate_if(
+            *op,
+            equal(
+                Value::String(value.clone()),
+                "<< pipeline.git.branch >>".to_string(),
+            ),
+        ),
+        Condition::Param { name, op, value } => {
+            let value = match value {
+                Literal::String(value) => Value::String(value.clone()),
+                Literal::Bool(value) => Value::Bool(*value),
+                Literal::Number(value) => Value::Number((*value).into()),
+            };
+            negate_if(
+                *op,
+                equal(value, format!("<< pipeline.parameters.{name} >>")),
+            )
+        }
+        Condition::Not(inner) => single("not", logic_statement(inner)),
+        Condition::And(left, right) => single(
+            "and",
+            Value::Sequence(vec![logic_statement(left), logic_statement(right)]),
+        ),
+        Condition::Or(left, right) => single(
+            "or",
+            Value::Sequence(vec![logic_statement(left), logic_statement(right)]),
+        ),
+        Condition::EnvDefined { .. } | Condition::Env { .. } => {
+            unreachable!("runtime conditions are compiled to shell guards")
+        }
+    }
+}
+
+fn shell_test(condition: &Condition) -> String {
+    match condition {
+        Condition::EnvDefined { name } => format!("[ -n \"${{{name}:-}}\" ]"),
+        Condition::Env { name, op, value } => {
+            let op = match op {
+                Comparison::Eq => "=",
+                Comparison::Ne => "!=",
+            };
+            format!("[ \"${{{name}:-}}\" {op} {} ]", shell_quote(value))
+        }
+        Condition::Not(inner) => format!("! {}", shell_group(inner)),
+        Condition::And(left, right) => {
+            format!("{} && {}", shell_group(left), shell_group(right))
+        }
+        Condition::Or(left, right) => {
+            format!("{} || {}", shell_group(left), shell_group(right))
+        }
+        Condition::Branch { .. } | Condition::Param { .. } => {
+            unreachable!("static conditions are compiled to when: statements")
+        }
+    }
+}
+
+fn shell_group(condition: &Condition) -> String {
+    match condition {
+        Condition::And(..) | Condition::Or(..) | Condition::Not(..) => {
+            format!("{{ {}; }}", shell_test(condition))
+        }
+        _ => shell_test(condition),
+    }
+}
+
+fn equal(value: Value, reference: String) -> Value {
+    single(
+        "equal",
+        Value::Sequence(vec![value, Value::String(reference)]),
+    )
+}
+
+fn negate_if(op: Comparison, statement: Value) -> Value {
+    match op {
+        Comparison::Eq => statement,
+        Comparison::Ne => single("not", statement),
+    }
+}
+
+fn single(key: &str, value: Value) -> Value {
+    let mut map = Mapping::new();
+    map.insert(Value::String(key.into()), value);
+    Value::Mapping(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn branch_and_param_compile_to_when() {
+        let compiled = compile_step_condition(r#"branch == "main" && param.deploy"#).unwrap();
+        let expected: Value = serde_yaml::from_str(
+            r#"
+and:
+  - equal: [main, "<< pipeline.git.branch >>"]
+  - equal: [true, "<< pipeline.parameters.deploy >>"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(compiled.when, Some(expected));
+        assert_eq!(compiled.guard, None);
+    }
+
+    #[test]
+    fn env_checks_compile_to_shell_guard() {
+        let compiled = compile_step_condition(
+            r#"branch != "main" && env.TOKEN defined && !(env.MODE == "dry")"#,
+        )
+        .unwrap();
+        assert!(compiled.when.is_some());
+        assert_eq!(
+            compiled.guard.as_deref(),
+            Some(r#"[ -n "${TOKEN:-}" ] && ! [ "${MODE:-}" = 'dry' ]"#)
+        );
+        assert_eq!(
+            guard_command("[ -n \"${TOKEN:-}\" ]", "./deploy.sh\n"),
+            "if [ -n \"${TOKEN:-}\" ]; then\n./deploy.sh\nfi\n"
+        );
+    }
+
+    #[test]
+    fn workflow_expressions_compile_to_logic_statements() {
+        let cases = [
+            (
+                r#"param.foo == "bar""#,
+                r#"{equal: [bar, "<< pipeline.parameters.foo >>"]}"#,
+            ),
+            (
+                "param.deploy",
+                r#"{equal: [true, "<< pipeline.parameters.deploy >>"]}"#,
+            ),
+            (
+                "param.deploy == false",
+                r#"{equal: [false, "<< pipeline.parameters.deploy >>"]}"#,
+            ),
+            (
+                "param.shards == 4",
+                r#"{equal: [4, "<< pipeline.parameters.shards >>"]}"#,
+            ),
+            (
+                r#"param.env != "prod""#,
+                r#"{not: {equal: [prod, "<< pipeline.parameters.env >>"]}}"#,
+            ),
+            (
+                r#"branch == "main""#,
+                r#"{equal: [main, "<< pipeline.git.branch >>"]}"#,
+            ),
+            (
+                "!param.b",
+                r#"{not: {equal: [true, "<< pipeline.parameters.b >>"]}}"#,
+            ),
+            (
+                "param.a && !param.b",
+                r#"{and: [{equal: [true, "<< pipeline.parameters.a >>"]}, {not: {equal: [true, "<< pipeline.parameters.b >>"]}}]}"#,
+            ),
+            (
+                "param.a || param.b && param.c",
+                r#"{or: [{equal: [true, "<< pipeline.parameters.a >>"]}, {and: [{equal: [true, "<< pipeline.parameters.b >>"]}, {equal: [true, "<< pipeline.parameters.c >>"]}]}]}"#,
+            ),
+            (
+                "(param.a || param.b) && param.c",
+                r#"{and: [{or: [{equal: [true, "<< pipeline.parameters.a >>"]}, {equal: [true, "<< pipeline.parameters.b >>"]}]}, {equal: [true, "<< pipeline.parameters.c >>"]}]}"#,
+            ),
+            (
+                "!(param.a && param.b)",
+                r#"{not: {and: [{equal: [true, "<< pipeline.parameters.a >>"]}, {equal: [true, "<< pipeline.parameters.b >>"]}]}}"#,
+            ),
+            (
+                r#"branch == "main" || param.force && branch != "wip""#,
+                r#"{or: [{equal: [main, "<< pipeline.git.branch >>"]}, {and: [{equal: [true, "<< pipeline.parameters.force >>"]}, {not: {equal: [wip, "<< pipeline.git.branch >>"]}}]}]}"#,
+            ),
+        ];
+        for (expression, expected) in cases {
+            let expected: Value = serde_yaml::from_str(expected).unwrap();
+            assert_eq!(
+                compile_workflow_condition(expression).unwrap(),
+                expected,
+                "{expression}"
+            );
+        }
+    }
+
+    #[test]
+    fn workflow_expressions_reject_env_checks() {
+        let error = compile_workflow_condition(r#"param.a && env.CI == "true""#)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            r#"env conditions are not supported on CircleCI; use a pipeline parameter (in workflow condition 'param.a && env.CI == "true"')"#
+        );
+        assert!(compile_workflow_condition("param.a &&").is_err());
+    }
+
+    #[test]
+    fn branch_guards_match_names_and_regexes() {
+        assert_eq!(branch_guard(&[]), None);
+        assert_eq!(
+            branch_guard(&["main".to_string(), "/release-.*/".to_string()]).as_deref(),
+            Some(
+                r#"[ "${CIRCLE_BRANCH:-}" = 'main' ] || grep -Eqx 'release-.*' <<< "${CIRCLE_BRANCH:-}""#
+            )
+        );
+    }
+
+    #[test]
+    fn mixed_disjunction_is_rejected() {
+        let error = compile_step_condition(r#"branch == "main" || env.FORCE defined"#)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("split them with '&&'"), "{error}");
+    }
+}
@@ -0,0 +1,422 @@
+use anyhow::{Result, bail};
+use serde_yaml::{Mapping, Value};
+
+/// Pipeline parameter key (cigen-only) that keeps a parameter in the setup workflow
+const CONTINUATION_KEY: &str = "continuation";
+
+/// CircleCI pipeline parameter types
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParameterType {
+    String,
+    Boolean,
+    Integer,
+    Enum,
+}
+
+impl ParameterType {
+    fn parse(name: &str, value: &str) -> Result<Self> {
+        Ok(match value {
+            "string" => Self::String,
+            "boolean" => Self::Boolean,
+            "integer" => Self::Integer,
+            "enum" => Self::Enum,
+            other => bail!(
+                "Pipeline parameter '{name}' has unsupported type '{other}' (expected string, boolean, integer, or enum)"
+            ),
+        })
+    }
+
+    /// Booleans and integers are JSON literals; strings and enums are JSON strings
+    fn is_literal(self) -> bool {
+        matches!(self, Self::Boolean | Self::Integer)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PipelineParameter {
+    pub name: String,
+    pub kind: ParameterType,
+}
+
+/// The config's `parameters:` without cigen's own keys, ready to emit
+pub fn pipeline_parameter_definitions(raw_config: &Value) -> Option<Mapping> {
+    let mut parameters = raw_config.get("parameters")?.as_mapping()?.clone();
+    for (_, definition) in parameters.iter_mut() {
+        if let Value::Mapping(definition) = definition {
+            definition.remove(CONTINUATION_KEY);
+        }
+    }
+    Some(parameters)
+}
+
+/// Parameters passed on to the continued config, skipping `continuation: false`
+pub fn continued_parameters(raw_config: &Value) -> Result<Vec<PipelineParameter>> {
+    let Some(parameters) = raw_config.get("parameters").and_then(Value::as_mapping) else {
+        return Ok(Vec::new());
+    };
+
+    let mut continued = Vec::new();
+    for (name, definition) in parameters {
+        let Some(name) = name.as_str() else { continue };
+        if definition.get(CONTINUATION_KEY).and_then(Value::as_bool) == Some(false) {
+            continue;
+        }
+        let kind = definition
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or("string");
+        continued.push(PipelineParameter {
+            name: name.to_string(),
+            kind: ParameterType::parse(name, kind)?,
+        });
+    }
+    Ok(continued)
+}
+
+/// Where the setup job writes the continued pipeline parameters
+const PARAMETERS_FILE: &str = "/tmp/pipeline-parameters.json";
+
+/// `run` step that writes the continued parameters to [`PARAMETERS_FILE`] as
+/// a JSON object.
+///
+/// CircleCI fills in `<< pipeline.parameters.* >>` as text, so each value
+/// reaches the step through its `environment:` and jq builds the JSON when the
+/// step runs: strings and enums with `--arg`, so quotes and newlines in them
+/// are escaped, and booleans and integers with `--argjson`, so they stay typed.
+pub fn parameters_step(parameters: &[PipelineParameter]) -> Value {
+    let mut environment = Mapping::new();
+    let mut args = Vec::new();
+    let mut fields = Vec::new();
+    for (index, parameter) in parameters.iter().enumerate() {
+        let variable = parameter_variable(&parameter.name);
+        environment.insert(
+            Value::String(variable.clone()),
+            Value::String(format!("<< pipeline.parameters.{} >>", parameter.name)),
+        );
+        let flag = if parameter.kind.is_literal() {
+            "--argjson"
+        } else {
+            "--arg"
+        };
+        args.push(format!("  {flag} p{index} \"${variable}\" \\\n"));
+        let key = serde_json::Value::String(parameter.name.clone()).to_string();
+        fields.push(format!("{key}: $p{index}"));
+    }
+    let command = format!(
+        "set -euo pipefail\njq -n \\\n{}  '{{{}}}' \\\n  > {PARAMETERS_FILE}\n",
+        args.concat(),
+        fields.join(", ")
+    );
+
+    let mut run = Mapping::new();
+    run.insert(
+        Value::String("name".into()),
+        Value::String("Write pipeline parameters".into()),
+    );
+    run.insert(
+        Value::String("environment".into()),
+        Value::Mapping(environment),
+    );
+    run.insert(Value::String("command".into()), Value::String(command));
+
+    let mut wrapper = Mapping::new();
+    wrapper.insert(Value::String("run".into()), Value::Mapping(run));
+    Value::Mapping(wrapper)
+}
+
+/// Environment variable carrying a parameter's value into [`parameters_step`]
+fn parameter_variable(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("PIPELINE_PARAMETER_{name}")
+}
+
+/// How the setup job continues the pipeline (`setup_options.continuation`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ContinuationMode {
+    /// The continuation orb's `continuation/continue` step
+    #[default]
+    Orb,
+    /// A `run` step that posts to CircleCI's continue API with curl
+    Api,
+}
+
+impl ContinuationMode {
+    pub fn parse(value: &str) -> Result<Self> {
+        Ok(match value {
+            "orb" => Self::Orb,
+            "api" => Self::Api,
+            other => bail!("setup_options.continuation must be orb or api, not '{other}'"),
+        })
+    }
+}
+
+/// Where the `api` continuation posts the continued config
+const CONTINUE_API_URL: &str = "https://circleci.com/api/v2/pipeline/continue";
+
+/// Steps that continue the pipeline with `configuration_path`, passing on
+/// the continued pipeline parameters
+pub fn build_continuation_steps(
+    raw_config: &Value,
+    configuration_path: &str,
+    mode: ContinuationMode,
+) -> Result<Vec<Value>> {
+    let parameters = continued_parameters(raw_config)?;
+    let mut steps = Vec::new();
+    if !parameters.is_empty() {
+        steps.push(parameters_step(&parameters));
+    }
+    let has_parameters = !parameters.is_empty();
+    steps.push(match mode {
+        ContinuationMode::Orb => orb_continuation_step(configuration_path, has_parameters),
+        ContinuationMode::Api => api_continuation_step(configuration_path, has_parameters),
+    });
+    Ok(steps)
+}
+
+/// `continuation/continue` step, which reads `parameters` from a file path
+fn orb_continuation_step(configuration_path: &str, has_parameters: bool) -> Value {
+    let mut params = Mapping::new();
+    params.insert(
+        Value::String("configuration_path".into()),
+        Value::String(configuration_path.to_string()),
+    );
+    if has_parameters {
+        params.insert(
+            Value::String("parameters".into()),
+            Value::String(PARAMETERS_FILE.into()),
+        );
+    }
+
+    let mut wrapper = Mapping::new();
+    wrapper.insert(
+        Value::String("continuation/continue".into()),
+        Value::Mapping(params),
+    );
+    Value::Mapping(wrapper)
+}
+
+/// `run` step that builds the request body with jq and posts it
+fn api_continuation_step(configuration_path: &str, has_parameters: bool) -> Value {
+    let (parameters_arg, parameters) = if has_parameters {
+        (
+            format!("--slurpfile parameters {PARAMETERS_FILE}"),
+            "$parameters[0]",
+        )
+    } else {
+        ("--argjson parameters '{}'".to_string(), "$parameters")
+    };
+    let command = format!(
+        r#"set -euo pipefail
+jq -n \
+  --arg continuation "$CIRCLE_CONTINUATION_KEY" \
+  --rawfile configuration "{configuration_path}" \
+  {parameters_arg} \
+  '{{"continuation-key": $continuation, "configuration": $configuration, "parameters": {parameters}}}' \
+  > /tmp/continuation.json
+curl --fail --silent --show-error -X POST \
+  -H "Content-Type: application/json" \
+  --data @/tmp/continuation.json \
+  {CONTINUE_API_URL}"#
+    );
+
+    let mut run = Mapping::new();
+    run.insert(
+        Value::String("name".into()),
+        Value::String("Continue pipeline".into()),
+    );
+    run.insert(Value::String("command".into()), Value::String(command));
+
+    let mut wrapper = Mapping::new();
+    wrapper.insert(Value::String("run".into()), Value::Mapping(run));
+    Value::Mapping(wrapper)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw_config() -> Value {
+        serde_yaml::from_str(
+            r#"
+parameters:
+  deploy_target:
+    type: string
+    default: 'say "hi"'
+  run_tests:
+    type: boolean
+    default: true
+  shards:
+    type: integer
+    default: 4
+  environment:
+    type: enum
+    enum: [staging, production]
+    default: staging
+  setup_only:
+    type: boolean
+    default: false
+    continuation: false
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn maps_every_parameter_type() {
+        let parameters = continued_parameters(&raw_config()).unwrap();
+        let kinds: Vec<_> = parameters
+            .iter()
+            .map(|parameter| (parameter.name.as_str(), parameter.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("deploy_target", ParameterType::String),
+                ("run_tests", ParameterType::Boolean),
+                ("shards", ParameterType::Integer),
+                ("environment", ParameterType::Enum),
+            ]
+        );
+    }
+
+    /// The `parameters_step` command with its environment values as CircleCI
+    /// would fill them in
+    fn substituted(step: &Value, values: &[(&str, &str)]) -> String {
+        let run = &step["run"];
+        let mut exports = String::new();
+        for (variable, reference) in run["environment"].as_mapping().unwrap() {
+            let reference = reference.as_str().unwrap();
+            let name = reference
+                .trim_start_matches("<< pipeline.parameters.")
+                .trim_end_matches(" >>");
+            let value = values.iter().find(|(key, _)| *key == name).unwrap().1;
+            exports.push_str(&format!(
+                "export {}={}\n",
+                variable.as_str().unwrap(),
+                cigen::schema::shell_quote(value)
+            ));
+        }
+        format!("{exports}{}", run["command"].as_str().unwrap())
+    }
+
+    #[test]
+    fn parameters_are_built_into_json_when_the_step_runs() {
+        let parameters = continued_parameters(&raw_config()).unwrap();
+        let step = parameters_step(&parameters);
+        assert_eq!(
+            step["run"]["environment"]["PIPELINE_PARAMETER_RUN_TESTS"].as_str(),
+            Some("<< pipeline.parameters.run_tests >>")
+        );
+        let command = step["run"]["command"].as_str().unwrap();
+        assert!(command.contains(r#"--arg p0 "$PIPELINE_PARAMETER_DEPLOY_TARGET""#));
+        assert!(command.contains(r#"--argjson p1 "$PIPELINE_PARAMETER_RUN_TESTS""#));
+        assert!(
+            command.ends_with(&format!("> {PARAMETERS_FILE}\n")),
+            "{command}"
+        );
+
+        // Quotes in a string value are escaped; booleans and integers stay typed
+        let script = substituted(
+            &step,
+            &[
+                ("deploy_target", r#"say "hi""#),
+                ("run_tests", "true"),
+                ("shards", "4"),
+                ("environment", "production"),
+            ],
+        )
+        .replace(PARAMETERS_FILE, "/dev/stdout");
+        let output = std::process::Command::new("bash")
+            .args(["-c", &script])
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let parsed: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(
+            parsed,
+            serde_json::json!({
+                "deploy_target": "say \"hi\"",
+                "run_tests": true,
+                "shards": 4,
+                "environment": "production",
+            })
+        );
+    }
+
+    #[test]
+    fn definitions_drop_the_continuation_key() {
+        let definitions = pipeline_parameter_definitions(&raw_config()).unwrap();
+        assert!(definitions["setup_only"].get("continuation").is_none());
+        assert_eq!(
+            definitions["deploy_target"]["default"].as_str(),
+            Some(r#"say "hi""#)
+        );
+    }
+
+    #[test]
+    fn both_modes_read_the_parameters_file() {
+        let raw = raw_config();
+        let parameters = parameters_step(&continued_parameters(&raw).unwrap());
+
+        let orb =
+            build_continuation_steps(&raw, ".circleci/main.yml", ContinuationMode::Orb).unwrap();
+        assert_eq!(orb[0], parameters);
+        let orb = &orb[1]["continuation/continue"];
+        assert_eq!(
+            orb["configuration_path"].as_str(),
+            Some(".circleci/main.yml")
+        );
+        assert_eq!(orb["parameters"].as_str(), Some(PARAMETERS_FILE));
+
+        let api =
+            build_continuation_steps(&raw, ".circleci/main.yml", ContinuationMode::Api).unwrap();
+        assert_eq!(api[0], parameters);
+        let command = api[1]["run"]["command"].as_str().unwrap();
+        assert!(command.contains(r#"--rawfile configuration ".circleci/main.yml""#));
+        assert!(
+            command.contains(&format!("--slurpfile parameters {PARAMETERS_FILE}")),
+            "{command}"
+        );
+        assert!(command.contains(CONTINUE_API_URL), "{command}");
+
+        // Without parameters the orb gets none and the API an empty object
+        let orb = build_continuation_steps(&Value::Null, "c.yml", ContinuationMode::Orb).unwrap();
+        assert_eq!(orb.len(), 1);
+        assert!(orb[0]["continuation/continue"].get("parameters").is_none());
+        let api = build_continuation_steps(&Value::Null, "c.yml", ContinuationMode::Api).unwrap();
+        assert_eq!(api.len(), 1);
+        let command = api[0]["run"]["command"].as_str().unwrap();
+        assert!(command.contains("--argjson parameters '{}'"), "{command}");
+    }
+
+    #[test]
+    fn continuation_mode_is_orb_or_api() {
+        assert_eq!(
+            ContinuationMode::parse("api").unwrap(),
+            ContinuationMode::Api
+        );
+        let error = ContinuationMode::parse("curl").unwrap_err().to_string();
+        assert!(error.contains("must be orb or api, not 'curl'"), "{error}");
+    }
+
+    #[test]
+    fn rejects_unknown_parameter_types() {
+        let raw: Value =
+            serde_yaml::from_str("parameters:\n  token:\n    type: env_var_name\n").unwrap();
+        let error = continued_parameters(&raw).unwrap_err().to_string();
+        assert!(error.contains("unsupported type 'env_var_name'"), "{error}");
+    }
+}
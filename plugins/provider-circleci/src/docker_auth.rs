@@ -0,0 +1,161 @@
+use anyhow::{Result, bail};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+
+/// Registry credentials declared under the root `docker:` block
+///
+/// ```yaml
+/// docker:
+///   default_auth: docker_hub
+///   auth:
+///     docker_hub:
+///       username: $DOCKERHUB_USERNAME
+///       password: $DOCKERHUB_TOKEN
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DockerAuthConfig {
+    pub default_auth: Option<String>,
+    pub auths: BTreeMap<String, DockerAuth>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DockerAuth {
+    pub username: String,
+    pub password: String,
+}
+
+/// A credential field that contains a literal value instead of an env var reference
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlaintextCredential {
+    pub auth_name: String,
+    pub field: &'static str,
+}
+
+impl DockerAuthConfig {
+    pub fn from_raw_config(raw_config: &Value) -> Result<Self> {
+        let mut config = Self::default();
+        let Some(docker) = raw_config.get("docker") else {
+            return Ok(config);
+        };
+
+        config.default_auth = docker
+            .get("default_auth")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+
+        if let Some(Value::Mapping(auths)) = docker.get("auth") {
+            for (name, auth) in auths {
+                let Some(name) = name.as_str() else { continue };
+                let field = |key: &str| {
+                    auth.get(key)
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                config.auths.insert(
+                    name.to_string(),
+                    DockerAuth {
+                        username: field("username"),
+                        password: field("password"),
+                    },
+                );
+            }
+        }
+
+        if let Some(default_auth) = &config.default_auth
+            && !config.auths.contains_key(default_auth)
+        {
+            bail!("docker.default_auth references unknown auth '{default_auth}'");
+        }
+
+        Ok(config)
+    }
+
+    /// `auth:` mapping to attach to docker image entries, if a default auth is configured
+    pub fn default_auth_value(&self) -> Option<Value> {
+        let auth = self.auths.get(self.default_auth.as_ref()?)?;
+        let mut map = Mapping::new();
+        map.insert(
+            Value::String("username".into()),
+            Value::String(auth.username.clone()),
+        );
+        map.insert(
+            Value::String("password".into()),
+            Value::String(auth.password.clone()),
+        );
+        Some(Value::Mapping(map))
+    }
+
+    /// Credentials that would be committed to the generated config in plaintext
+    pub fn plaintext_credentials(&self) -> Vec<PlaintextCredential> {
+        let mut found = Vec::new();
+        for (name, auth) in &self.auths {
+            for (field, value) in [("username", &auth.username), ("password", &auth.password)] {
+                if !value.is_empty() && !is_env_reference(value) {
+                    found.push(PlaintextCredential {
+                        auth_name: name.clone(),
+                        field,
+                    });
+                }
+            }
+        }
+        found
+    }
+}
+
+/// True for `$VAR` or `${VAR}` references
+pub fn is_env_reference(value: &str) -> bool {
+    let value = value.trim();
+    let name = if let Some(inner) = value.strip_prefix("${") {
+        match inner.strip_suffix('}') {
+            Some(name) => name,
+            None => return false,
+        }
+    } else if let Some(name) = value.strip_prefix('$') {
+        name
+    } else {
+        return false;
+    };
+
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_env_references() {
+        assert!(is_env_reference("$DOCKERHUB_TOKEN"));
+        assert!(is_env_reference("${DOCKERHUB_TOKEN}"));
+        assert!(!is_env_reference("hunter2"));
+        assert!(!is_env_reference("$"));
+        assert!(!is_env_reference("${TOKEN"));
+        assert!(!is_env_reference("prefix-$TOKEN"));
+    }
+
+    #[test]
+    fn reports_plaintext_fields() {
+        let raw: Value = serde_yaml::from_str(
+            r#"
+docker:
+  default_auth: hub
+  auth:
+    hub:
+      username: $DOCKERHUB_USERNAME
+      password: hunter2
+"#,
+        )
+        .unwrap();
+        let config = DockerAuthConfig::from_raw_config(&raw).unwrap();
+        assert_eq!(
+            config.plaintext_credentials(),
+            vec![PlaintextCredential {
+                auth_name: "hub".to_string(),
+                field: "password",
+            }]
+        );
+    }
+}
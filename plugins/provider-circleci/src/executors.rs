@@ -0,0 +1,363 @@
+use anyhow::{Result, bail};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+
+use crate::DEFAULT_MACHINE_IMAGE;
+use crate::resource_classes::{DEFAULT_ARCHITECTURE, ResourceClassMap};
+
+/// Keys that pick the executor type; exactly one is required
+const TYPE_KEYS: [&str; 4] = ["image", "docker", "machine", "macos"];
+
+/// Other CircleCI executor keys passed through as-is
+const OPTION_KEYS: [&str; 4] = [
+    "resource_class",
+    "environment",
+    "working_directory",
+    "shell",
+];
+
+/// Named executors that jobs reference with `executor: <name>`.
+///
+/// Configured at the root of the config:
+///
+/// ```yaml
+/// executors:
+///   ruby:
+///     image: cimg/ruby:3.3      # or docker/machine/macos as in CircleCI
+///     resource_class: large
+///     environment:
+///       RAILS_ENV: test
+///     architectures:
+///       arm64:
+///         resource_class: arm.large
+/// ```
+///
+/// `architectures` overrides keys for jobs on that architecture, which then
+/// reference a separate `<name>_<arch>` executor.
+#[derive(Clone, Debug, Default)]
+pub struct ExecutorDefinitions {
+    executors: BTreeMap<String, ExecutorDefinition>,
+}
+
+#[derive(Clone, Debug)]
+struct ExecutorDefinition {
+    body: Mapping,
+    architectures: BTreeMap<String, Mapping>,
+}
+
+impl ExecutorDefinitions {
+    pub fn from_raw_config(raw_config: &Value) -> Result<Self> {
+        let mut executors = BTreeMap::new();
+        let Some(Value::Mapping(definitions)) = raw_config.get("executors") else {
+            return Ok(Self { executors });
+        };
+
+        for (name, definition) in definitions {
+            let Some(name) = name.as_str() else { continue };
+            let Some(definition) = definition.as_mapping() else {
+                bail!("Executor '{name}' must be a mapping");
+            };
+
+            let mut body = Mapping::new();
+            let mut architectures = BTreeMap::new();
+            for (key, value) in definition {
+                match key.as_str() {
+                    Some("architectures") => {
+                        let Some(overrides) = value.as_mapping() else {
+                            bail!(
+                                "Executor '{name}' architectures must map architectures to overrides"
+                            );
+                        };
+                        for (arch, options) in overrides {
+                            let (Some(arch), Some(options)) = (arch.as_str(), options.as_mapping())
+                            else {
+                                bail!(
+                                    "Executor '{name}' architectures must map architectures to overrides"
+                                );
+                            };
+                            architectures
+                                .insert(arch.to_string(), parse_options(name, options, false)?);
+                        }
+                    }
+                    _ => {
+                        body.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+
+            executors.insert(
+                name.to_string(),
+                ExecutorDefinition {
+                    body: parse_options(name, &body, true)?,
+                    architectures,
+                },
+            );
+        }
+
+        Ok(Self { executors })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.executors.contains_key(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.executors.keys().map(String::as_str)
+    }
+
+    /// Executor a job on `architecture` references
+    pub fn reference(&self, name: &str, architecture: &str) -> String {
+        match self.executors.get(name) {
+            Some(definition) if definition.architectures.contains_key(architecture) => {
+                format!("{name}_{architecture}")
+            }
+            _ => name.to_string(),
+        }
+    }
+
+    /// `docker`, `machine`, or `macos` for the executor a job references
+    pub fn kind(&self, name: &str, architecture: &str) -> &'static str {
+        let Some(definition) = self.executors.get(name) else {
+            return "docker";
+        };
+        let body = definition.body_for(architecture);
+        if body.contains_key("macos") {
+            "macos"
+        } else if body.contains_key("machine") {
+            "machine"
+        } else {
+            "docker"
+        }
+    }
+
+    /// The `executors:` block, limited to the executors in `referenced`
+    pub fn to_circleci<'a>(
+        &self,
+        referenced: impl IntoIterator<Item = &'a str>,
+        resource_classes: &ResourceClassMap,
+        docker_auth: Option<&Value>,
+    ) -> Mapping {
+        let mut emitted = BTreeMap::new();
+        for reference in referenced {
+            for (name, definition) in &self.executors {
+                let architecture = if reference == name {
+                    DEFAULT_ARCHITECTURE
+                } else {
+                    match reference
+                        .strip_prefix(name.as_str())
+                        .and_then(|rest| rest.strip_prefix('_'))
+                    {
+                        Some(arch) if definition.architectures.contains_key(arch) => arch,
+                        _ => continue,
+                    }
+                };
+                let body = render_body(
+                    definition.body_for(architecture),
+                    architecture,
+                    resource_classes,
+                    docker_auth,
+                );
+                emitted.insert(reference.to_string(), body);
+            }
+        }
+
+        emitted
+            .into_iter()
+            .map(|(name, body)| (Value::String(name), Value::Mapping(body)))
+            .collect()
+    }
+}
+
+impl ExecutorDefinition {
+    /// The executor body with any overrides for `architecture` applied
+    fn body_for(&self, architecture: &str) -> Mapping {
+        let mut body = self.body.clone();
+        if let Some(overrides) = self.architectures.get(architecture) {
+            if TYPE_KEYS.iter().any(|key| overrides.contains_key(*key)) {
+                body.retain(|key, _| !TYPE_KEYS.iter().any(|type_key| key == *type_key));
+            }
+            for (key, value) in overrides {
+                body.insert(key.clone(), value.clone());
+            }
+        }
+        body
+    }
+}
+
+/// Check an executor body (or an architecture override) and normalize it to
+/// CircleCI's shape: `image` becomes `docker`, and `machine` gets an image
+fn parse_options(name: &str, options: &Mapping, require_type: bool) -> Result<Mapping> {
+    let mut parsed = Mapping::new();
+    let mut types = Vec::new();
+    for (key, value) in options {
+        let Some(key) = key.as_str() else { continue };
+        match key {
+            "image" => {
+                let Some(image) = value.as_str() else {
+                    bail!("Executor '{name}' image must be a string");
+                };
+                let mut entry = Mapping::new();
+                entry.insert(
+                    Value::String("image".into()),
+                    Value::String(image.to_string()),
+                );
+                parsed.insert(
+                    Value::String("docker".into()),
+                    Value::Sequence(vec![Value::Mapping(entry)]),
+                );
+            }
+            "machine" => {
+                let mut machine = match value {
+                    Value::Bool(true) | Value::Null => Mapping::new(),
+                    Value::Mapping(machine) => machine.clone(),
+                    _ => bail!("Executor '{name}' machine must be true or a mapping"),
+                };
+                if !machine.contains_key("image") {
+                    machine.insert(
+                        Value::String("image".into()),
+                        Value::String(DEFAULT_MACHINE_IMAGE.into()),
+                    );
+                }
+                parsed.insert(Value::String("machine".into()), Value::Mapping(machine));
+            }
+            "docker" | "macos" => {
+                parsed.insert(Value::String(key.into()), value.clone());
+            }
+            "env" => {
+                parsed.insert(Value::String("environment".into()), value.clone());
+            }
+            key if OPTION_KEYS.contains(&key) => {
+                parsed.insert(Value::String(key.into()), value.clone());
+            }
+            other => bail!(
+                "Executor '{name}' has unknown key '{other}' (expected one of {}, {}, architectures)",
+                TYPE_KEYS.join(", "),
+                OPTION_KEYS.join(", ")
+            ),
+        }
+        if TYPE_KEYS.contains(&key) {
+            types.push(key);
+        }
+    }
+
+    match types.as_slice() {
+        [] if require_type => {
+            bail!("Executor '{name}' must set one of image, docker, machine, or macos")
+        }
+        [] | [_] => Ok(parsed),
+        _ => bail!(
+            "Executor '{name}' sets {}; an executor has exactly one type",
+            types.join(" and ")
+        ),
+    }
+}
+
+/// Resolve resource class aliases and attach the default docker auth
+fn render_body(
+    mut body: Mapping,
+    architecture: &str,
+    resource_classes: &ResourceClassMap,
+    docker_auth: Option<&Value>,
+) -> Mapping {
+    if let Some(Value::String(class)) = body.get("resource_class") {
+        let resolved = resource_classes.resolve(class, architecture);
+        body.insert(
+            Value::String("resource_class".into()),
+            Value::String(resolved),
+        );
+    }
+    if let (Some(auth), Some(Value::Sequence(images))) = (docker_auth, body.get_mut("docker")) {
+        for image in images {
+            if let Value::Mapping(image) = image
+                && !image.contains_key("auth")
+            {
+                image.insert(Value::String("auth".into()), auth.clone());
+            }
+        }
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definitions(yaml: &str) -> Result<ExecutorDefinitions> {
+        ExecutorDefinitions::from_raw_config(&serde_yaml::from_str(yaml).unwrap())
+    }
+
+    #[test]
+    fn renders_definitions_with_architecture_overrides() {
+        let executors = definitions(
+            r#"
+executors:
+  ruby:
+    image: cimg/ruby:3.3
+    resource_class: large
+    env:
+      RAILS_ENV: test
+    architectures:
+      arm64:
+        resource_class: arm.large
+  vm:
+    machine: true
+"#,
+        )
+        .unwrap();
+        assert_eq!(executors.reference("ruby", "amd64"), "ruby");
+        assert_eq!(executors.reference("ruby", "arm64"), "ruby_arm64");
+        assert_eq!(executors.reference("vm", "arm64"), "vm");
+        assert_eq!(executors.kind("vm", "amd64"), "machine");
+
+        let rendered = executors.to_circleci(
+            ["ruby", "ruby_arm64", "vm"],
+            &ResourceClassMap::default(),
+            None,
+        );
+        let expected: Value = serde_yaml::from_str(
+            r#"
+ruby:
+  docker:
+    - image: cimg/ruby:3.3
+  resource_class: large
+  environment:
+    RAILS_ENV: test
+ruby_arm64:
+  docker:
+    - image: cimg/ruby:3.3
+  resource_class: arm.large
+  environment:
+    RAILS_ENV: test
+vm:
+  machine:
+    image: ubuntu-2204:current
+"#,
+        )
+        .unwrap();
+        assert_eq!(Value::Mapping(rendered), expected);
+    }
+
+    #[test]
+    fn rejects_invalid_definitions() {
+        let error = definitions("executors:\n  empty:\n    resource_class: large\n")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("must set one of image, docker, machine, or macos"),
+            "{error}"
+        );
+
+        let error =
+            definitions("executors:\n  both:\n    image: cimg/base:current\n    machine: true\n")
+                .unwrap_err()
+                .to_string();
+        assert!(error.contains("sets image and machine"), "{error}");
+
+        let error = definitions(
+            "executors:\n  typo:\n    image: cimg/base:current\n    resouce_class: large\n",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("unknown key 'resouce_class'"), "{error}");
+    }
+}
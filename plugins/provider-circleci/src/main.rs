@@ -1,36 +1,108 @@
 #![allow(clippy::needless_borrows_for_generic_args)]
 
 use anyhow::{Context, Result, anyhow, bail};
+use cigen::orbs::{CONTINUATION_ALIAS, setup_continuation_orb};
+use cigen::orchestrator::{CONTINUED_CONFIG, NO_JOB_STATUS_CACHE_FLAG, step_list_to_proto};
+use cigen::path_filter::ONLY_PROJECTS_FILE_ENV;
+use cigen::plugin::capabilities::ProviderCapabilities;
+use cigen::plugin::diagnostics::{error_location, located_error_in};
+use cigen::plugin::negotiation::{ProtocolRange, negotiate};
+use cigen::plugin::overrides::apply_provider_overrides;
 use cigen::plugin::protocol::{
-    CigenSchema, CommandDefinition, CommandParameter, CustomStep, Fragment, GenerateRequest,
-    GenerateResult, Hello, JobDefinition, PlanRequest, PlanResult, PluginInfo, RunStep, Step,
-    UsesStep, WorkflowCondition as ProtoWorkflowCondition,
+    CigenSchema, CommandDefinition, CommandParameter, CustomStep, Executor, Fragment,
+    GenerateRequest, GenerateResult, Hello, JobDefinition, PlanRequest, PlanResult, PluginInfo,
+    RemoteDocker, RunStep, SlackNotification, Step, UsesStep,
+    WorkflowCondition as ProtoWorkflowCondition,
     WorkflowConditionKind as ProtoWorkflowConditionKind,
 };
+use cigen::report::Phase;
+use cigen::schema::{
+    CIRCLECI_SCHEMA_URL, DEFAULT_WORKFLOW, Instrumentation, ProjectDetection, STEP_TIMINGS_LOG,
+    STEPS_PARAMETER_TYPE, SaveWhen, ServicePort, ServiceWait, default_step_name,
+    parse_service_ports, schema_comment, shell_quote, timed_command, unknown_reference_message,
+    versioned_cache_key, wait_for_service_command,
+};
 use serde_yaml::{Mapping, Value};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
 use std::io::Write;
 use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+mod cigen_install;
+mod cloud_auth;
+mod conditions;
+mod continuation;
+mod docker_auth;
+mod executors;
+mod notifications;
+mod output;
+mod resource_classes;
+mod shards;
+mod size_limits;
+mod validation;
+
+use cigen_install::CigenInstall;
+use cloud_auth::{cloud_auth_orbs, cloud_auth_steps, gcp_environment};
+use conditions::{
+    branch_guard, compile_step_condition, compile_workflow_condition, guard_command,
+    unless_halt_step, wrap_in_when,
+};
+use continuation::{ContinuationMode, build_continuation_steps, pipeline_parameter_definitions};
+use docker_auth::DockerAuthConfig;
+use executors::ExecutorDefinitions;
+use notifications::{DEFAULT_SLACK_ORB, SLACK_ALIAS, fixed_event_warnings, notify_step};
+use output::{ConfigFile, OutputOptions, prune_unused_definitions};
+use resource_classes::{DEFAULT_ARCHITECTURE, ResourceClassMap};
+use shards::{SHARD_PARAMETER, select_shard_step, shard_parameter, split_workflows};
+use size_limits::SizeLimits;
+use validation::validate_config;
 
 const PLUGIN_NAME: &str = "provider/circleci";
+/// Key for this provider in a job's `provider_overrides`
+const PROVIDER_NAME: &str = "circleci";
 const PLUGIN_VERSION: &str = "0.1.0";
-const PROTOCOL_VERSION: u32 = 1;
+/// Protocol 1 plugins never see `source_submodules`/`source_file`; both are optional here
+const PROTOCOLS: ProtocolRange = ProtocolRange::new(1, 2);
+
+/// Capabilities declared in `PluginInfo`. Job features come from CircleCI, so
+/// all of them are native.
+fn plugin_capabilities() -> Vec<String> {
+    let mut capabilities = vec!["provider:circleci".to_string()];
+    capabilities.extend(ProviderCapabilities::native().to_capabilities());
+    capabilities
+}
+
+/// CircleCI's current Ubuntu machine image, used when `executor.machine.image` is unset
+const DEFAULT_MACHINE_IMAGE: &str = "ubuntu-2204:current";
+
+/// Setup pipeline parameter that picks the workflow config to continue with
+/// when `output.per_workflow` is set
+const WORKFLOW_PARAMETER: &str = "workflow";
+
+/// Setup pipeline parameter that reruns every job from empty caches
+const SKIP_CACHE_PARAMETER: &str = "skip_cache";
+
+/// Where the setup job writes the projects `project_detection` finds affected
+const AFFECTED_PROJECTS_FILE: &str = "/tmp/cigen/affected_projects.txt";
+
+/// Plan flag that opts into validating generated configs with the `circleci` CLI
+const VALIDATE_WITH_CLI_FLAG: &str = "validate_with_cli";
 
 #[derive(Clone, Debug, Default)]
 struct ServiceDefinition {
     image: String,
     environment: Option<Mapping>,
+    ports: Vec<ServicePort>,
+    wait_for: Option<ServiceWait>,
 }
 
 #[derive(Clone, Debug, Default)]
 struct SetupOptions {
     image: Option<String>,
     resource_class: Option<String>,
-    compile_cigen: bool,
-    compile_repository: Option<String>,
-    compile_ref: Option<String>,
-    compile_path: Option<String>,
+    continuation: ContinuationMode,
+    install: CigenInstall,
     self_check: Option<SelfCheckOptions>,
 }
 
@@ -42,6 +114,12 @@ struct SelfCheckOptions {
 
 #[derive(Clone, Debug, Default)]
 struct CheckoutConfig {
+    /// `checkout: false` skips the checkout step (and its `post` steps)
+    disabled: bool,
+    /// User-defined command that replaces the built-in checkout
+    command: Option<String>,
+    /// Steps emitted right after whichever checkout variant is used
+    post: Vec<Value>,
     shallow: bool,
     fetch_options: Option<String>,
     tag_fetch_options: Option<String>,
@@ -57,6 +135,14 @@ struct WorkflowRunCondition {
     kind: WorkflowRunConditionKind,
     key: Option<String>,
     equals_yaml: Option<String>,
+    expression: Option<String>,
+}
+
+/// A workflow's `run_when` and `run_unless` conditions
+#[derive(Clone, Debug, Default)]
+struct WorkflowRunConditions {
+    run_when: Vec<WorkflowRunCondition>,
+    run_unless: Vec<WorkflowRunCondition>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -78,22 +164,28 @@ struct CircleciContext<'a> {
     setup_options: SetupOptions,
     checkout: CheckoutConfig,
     services: HashMap<String, ServiceDefinition>,
-    workflow_conditions: HashMap<String, Vec<WorkflowRunCondition>>,
+    resource_classes: ResourceClassMap,
+    executors: ExecutorDefinitions,
+    docker_auth: DockerAuthConfig,
+    workflow_conditions: HashMap<String, WorkflowRunConditions>,
+    output: OutputOptions,
+    instrumentation: Instrumentation,
+    /// Number of `.circleci/main_<n>.yml` shards, from `--shard-count`
+    shard_count: Option<u32>,
+    size_limits: SizeLimits,
+    /// `docker_build.verify_images`: the setup job regenerates with `--verify-images`
+    verify_images: bool,
+    /// Jobs with sources record their status for the setup job to skip them.
+    /// Off with `--no-job-status-cache`.
+    job_status_cache: bool,
+    project_detection: Option<ProjectDetection>,
     raw_config: Value,
 }
 
 fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_writer(std::io::stderr)
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("cigen_provider_circleci=info".parse()?),
-        )
-        .with_target(false)
-        .without_time()
-        .init();
+    cigen::plugin::logging::init_plugin_logging("cigen_provider_circleci")?;
 
-    tracing::info!("Starting {PLUGIN_NAME} v{PLUGIN_VERSION}");
+    tracing::debug!("Starting {PLUGIN_NAME} v{PLUGIN_VERSION}");
 
     use cigen::plugin::framing::{receive_message, send_message};
     use std::io::{stdin, stdout};
@@ -102,66 +194,62 @@ fn main() -> Result<()> {
     let mut stdout = stdout().lock();
 
     let hello: Hello = receive_message(&mut stdin).context("Failed to read Hello message")?;
-    if hello.core_protocol != PROTOCOL_VERSION {
-        anyhow::bail!(
-            "Protocol version mismatch: core={}, plugin={PROTOCOL_VERSION}",
-            hello.core_protocol
-        );
-    }
+    let protocol = negotiate(PROTOCOLS, ProtocolRange::from_hello(&hello))
+        .context("Protocol version mismatch")?;
 
     let info = PluginInfo {
         name: PLUGIN_NAME.to_string(),
         version: PLUGIN_VERSION.to_string(),
-        protocol: PROTOCOL_VERSION,
-        capabilities: vec!["provider:circleci".to_string()],
+        protocol,
+        capabilities: plugin_capabilities(),
         requires: vec![],
-        conflicts_with: vec!["provider:*".to_string()],
+        conflicts_with: vec!["provider:circleci".to_string()],
         metadata: HashMap::new(),
+        protocol_min: PROTOCOLS.min,
+        protocol_max: PROTOCOLS.max,
     };
 
     send_message(&info, &mut stdout).context("Failed to send PluginInfo")?;
-    tracing::info!("Handshake complete, entering message loop");
+    tracing::debug!("Handshake complete (protocol {protocol}), entering message loop");
 
     loop {
-        match receive_message::<PlanRequest, _>(&mut stdin) {
-            Ok(_plan_request) => {
+        let flags = match receive_message::<PlanRequest, _>(&mut stdin) {
+            Ok(plan_request) => {
                 let plan_result = PlanResult {
                     resources: vec![],
                     deps: vec![],
                     diagnostics: vec![],
                 };
                 send_message(&plan_result, &mut stdout).context("Failed to send PlanResult")?;
+                plan_request.flags
             }
             Err(_) => {
                 // Parent closed the pipe - normal termination
                 break;
             }
-        }
+        };
 
         match receive_message::<GenerateRequest, _>(&mut stdin) {
             Ok(generate_request) => {
-                tracing::info!(
+                tracing::debug!(
                     "Received GenerateRequest for target: {}",
                     generate_request.target
                 );
 
                 let result = match generate_request.schema.as_ref() {
-                    Some(schema) => match build_circleci_fragments(schema) {
-                        Ok(fragments) => GenerateResult {
-                            fragments,
-                            diagnostics: vec![],
-                        },
+                    Some(schema) => match build_circleci_fragments(schema, &flags) {
+                        Ok(result) => result,
                         Err(error) => GenerateResult {
-                            fragments: vec![],
                             diagnostics: vec![make_diagnostic("CIRCLECI_GENERATE_ERROR", error)],
+                            ..Default::default()
                         },
                     },
                     None => GenerateResult {
-                        fragments: vec![],
                         diagnostics: vec![make_diagnostic(
                             "CIRCLECI_GENERATE_ERROR",
                             anyhow!("GenerateRequest missing schema"),
                         )],
+                        ..Default::default()
                     },
                 };
 
@@ -177,56 +265,187 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn build_circleci_fragments(schema: &CigenSchema) -> Result<Vec<Fragment>> {
+fn build_circleci_fragments(
+    schema: &CigenSchema,
+    flags: &HashMap<String, String>,
+) -> Result<GenerateResult> {
     let raw_config: Value = serde_yaml::from_str(&schema.raw_config_yaml)
         .context("Failed to parse raw configuration from schema")?;
+    let validate_with_cli = flags
+        .get(VALIDATE_WITH_CLI_FLAG)
+        .is_some_and(|value| value == "true");
 
     let context = CircleciContext {
         schema,
         setup_options: extract_setup_options(&raw_config)?,
         checkout: extract_checkout_config(&raw_config),
-        services: extract_services(&raw_config),
+        services: extract_services(&raw_config)?,
+        resource_classes: ResourceClassMap::from_raw_config(&raw_config),
+        executors: ExecutorDefinitions::from_raw_config(&raw_config)?,
+        docker_auth: DockerAuthConfig::from_raw_config(&raw_config)?,
         workflow_conditions: extract_workflow_conditions(schema)?,
+        output: OutputOptions::from_raw_config(&raw_config, flags)?,
+        instrumentation: Instrumentation::from_raw_config(&raw_config)?,
+        shard_count: shards::shard_count(flags)?,
+        size_limits: SizeLimits::from_flags(flags)?,
+        verify_images: docker_build_verifies_images(&raw_config),
+        job_status_cache: flags
+            .get(NO_JOB_STATUS_CACHE_FLAG)
+            .is_none_or(|value| value != "true"),
+        project_detection: raw_config
+            .get("project_detection")
+            .map(|value| serde_yaml::from_value(value.clone()))
+            .transpose()
+            .context("Invalid project_detection")?,
         raw_config,
     };
 
-    let mut fragments = Vec::new();
+    let mut diagnostics = check_plaintext_docker_auth(&context)?;
+    diagnostics.extend(fixed_event_warnings(&schema.workflows));
 
-    // 1. Generate .circleci/config.yml (setup workflow)
-    let setup_config = generate_setup_config(&context)?;
-    let setup_yaml = serde_yaml::to_string(&setup_config)?;
+    // .circleci/config.yml (setup workflow), then the continued config: either
+    // .circleci/main.yml, one file per shard, or one standalone file per workflow
+    let workflows = collect_workflow_variants(&context)?;
+    if context.shard_count.is_some() && context.output.per_workflow {
+        bail!(
+            "--shard-count splits .circleci/main.yml, so it can't be used with output.per_workflow"
+        );
+    }
+    let setup_path = context.output.path(ConfigFile::Setup);
+    let mut configs = vec![(
+        setup_path.clone(),
+        generate_setup_config(&context, &workflows)?,
+    )];
+    if context.output.per_workflow {
+        for (workflow_id, variants) in &workflows {
+            let mut config =
+                generate_workflows_config(&context, [(workflow_id, variants)], &mut diagnostics)?;
+            if let Value::Mapping(root) = &mut config {
+                prune_unused_definitions(root);
+            }
+            configs.push((
+                context.output.path(ConfigFile::Workflow(workflow_id)),
+                config,
+            ));
+        }
+    } else if context.shard_count.is_some() {
+        for (shard, shard_workflows) in split_workflows(&workflows) {
+            let mut config =
+                generate_workflows_config(&context, &shard_workflows, &mut diagnostics)?;
+            if let Value::Mapping(root) = &mut config {
+                prune_unused_definitions(root);
+            }
+            configs.push((
+                context.output.path(ConfigFile::Shard(&shard.to_string())),
+                config,
+            ));
+        }
+    } else {
+        configs.push((
+            context.output.path(ConfigFile::Main),
+            generate_workflows_config(&context, &workflows, &mut diagnostics)?,
+        ));
+    }
 
-    if let Err(e) = validate_config_content(&setup_yaml) {
-        bail!("Validation failed for setup config\nError: {}", e);
+    let mut fragments = Vec::new();
+    // Reported to the core as the provider-validate phase
+    let mut validation_time = Duration::ZERO;
+    for (path, config) in configs {
+        let path = path.as_str();
+        let validation_start = Instant::now();
+        let invalid: Vec<_> = validate_config(path, &config)
+            .into_iter()
+            .map(|error| {
+                let mut diagnostic = make_diagnostic("CIRCLECI_INVALID_CONFIG", error.into());
+                diagnostic.title = "Generated CircleCI config is invalid".to_string();
+                diagnostic
+            })
+            .collect();
+        validation_time += validation_start.elapsed();
+        if !invalid.is_empty() {
+            diagnostics.extend(invalid);
+            continue;
+        }
+
+        let yaml = format!(
+            "{}{}",
+            schema_comment(CIRCLECI_SCHEMA_URL),
+            serde_yaml::to_string(&config)?
+        );
+        let validation_start = Instant::now();
+        // Everything but the setup config is continued
+        context
+            .size_limits
+            .check(path, &yaml, &config, path != setup_path)?;
+        if validate_with_cli {
+            validate_config_content(&yaml)
+                .with_context(|| format!("CircleCI CLI validation failed for {path}"))?;
+        }
+        validation_time += validation_start.elapsed();
+
+        fragments.push(Fragment {
+            path: path.to_string(),
+            content: yaml,
+            strategy: 0, // Replace
+            format: "yaml".to_string(),
+            order: 0,
+        });
     }
 
-    fragments.push(Fragment {
-        path: ".circleci/config.yml".to_string(),
-        content: setup_yaml,
-        strategy: 0, // Replace
-        format: "yaml".to_string(),
-        order: 0,
-    });
+    Ok(GenerateResult {
+        fragments,
+        diagnostics,
+        phase_millis: HashMap::from([(
+            Phase::ProviderValidate.as_str().to_string(),
+            validation_time.as_millis() as u64,
+        )]),
+    })
+}
+
+/// Docker credentials must reference environment variables, otherwise they end
+/// up committed to the generated config. Literal values are a warning, or an
+/// error when `strict_secrets: true` is set.
+fn check_plaintext_docker_auth(
+    context: &CircleciContext,
+) -> Result<Vec<cigen::plugin::protocol::Diagnostic>> {
+    let plaintext = context.docker_auth.plaintext_credentials();
+    if plaintext.is_empty() {
+        return Ok(Vec::new());
+    }
 
-    // 2. Generate .circleci/main.yml (main workflow)
-    let main_config = generate_main_config(&context)?;
-    let main_yaml = serde_yaml::to_string(&main_config)?;
-    validate_config_content(&main_yaml).context("Validation failed for main config")?;
+    let strict = context
+        .raw_config
+        .get("strict_secrets")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    let entries: Vec<String> = plaintext
+        .iter()
+        .map(|credential| format!("docker.auth.{}.{}", credential.auth_name, credential.field))
+        .collect();
+    let message = format!(
+        "Docker credentials would be written to the generated config in plaintext: {}",
+        entries.join(", ")
+    );
 
-    fragments.push(Fragment {
-        path: ".circleci/main.yml".to_string(),
-        content: main_yaml,
-        strategy: 0, // Replace
-        format: "yaml".to_string(),
-        order: 0,
-    });
+    if strict {
+        bail!("{message} (strict_secrets is enabled)");
+    }
 
-    Ok(fragments)
+    Ok(vec![cigen::plugin::protocol::Diagnostic {
+        level: cigen::plugin::protocol::diagnostic::Level::Warning as i32,
+        code: "CIRCLECI_PLAINTEXT_SECRET".to_string(),
+        title: "Plaintext docker credentials".to_string(),
+        message,
+        fix_hint: "Reference an environment variable instead, e.g. password: $DOCKERHUB_TOKEN"
+            .to_string(),
+        loc: None,
+    }])
 }
 
 fn validate_config_content(content: &str) -> Result<()> {
-    tracing::info!("Starting validation for content length: {}", content.len());
-    // Check for circleci CLI
+    tracing::debug!("Starting validation for content length: {}", content.len());
+    // Validation with the CLI was requested explicitly, so a missing CLI is an error
     if Command::new("circleci")
         .arg("version")
         .stdout(Stdio::null())
@@ -234,8 +453,7 @@ fn validate_config_content(content: &str) -> Result<()> {
         .status()
         .is_err()
     {
-        tracing::warn!("[DEBUG] circleci CLI not found, skipping validation");
-        return Ok(()); // Skip if not installed
+        bail!("circleci CLI not found; install it or drop --validate-with-cli");
     }
 
     let mut child = Command::new("circleci")
@@ -252,7 +470,7 @@ fn validate_config_content(content: &str) -> Result<()> {
     }
 
     let output = child.wait_with_output()?;
-    tracing::info!("[DEBUG] CircleCI validate status: {:?}", output.status);
+    tracing::debug!("CircleCI validate status: {:?}", output.status);
 
     if !output.stdout.is_empty() {
         tracing::warn!("{}", String::from_utf8_lossy(&output.stdout));
@@ -270,32 +488,37 @@ fn validate_config_content(content: &str) -> Result<()> {
 
 fn extract_workflow_conditions(
     schema: &CigenSchema,
-) -> Result<HashMap<String, Vec<WorkflowRunCondition>>> {
+) -> Result<HashMap<String, WorkflowRunConditions>> {
     let mut map = HashMap::new();
     for workflow in &schema.workflows {
-        let mut conditions = Vec::new();
-        for proto_cond in &workflow.run_when {
-            conditions.push(WorkflowRunCondition::from_proto(proto_cond)?);
-        }
+        let conditions = WorkflowRunConditions {
+            run_when: workflow
+                .run_when
+                .iter()
+                .map(WorkflowRunCondition::from_proto)
+                .collect::<Result<_>>()?,
+            run_unless: workflow
+                .run_unless
+                .iter()
+                .map(WorkflowRunCondition::from_proto)
+                .collect::<Result<_>>()?,
+        };
         map.insert(workflow.id.clone(), conditions);
     }
     Ok(map)
 }
 
-fn generate_setup_config(context: &CircleciContext) -> Result<Value> {
+fn generate_setup_config(
+    context: &CircleciContext,
+    workflows: &BTreeMap<String, Vec<JobVariant>>,
+) -> Result<Value> {
     let mut root = Mapping::new();
     root.insert(Value::String("version".into()), Value::String("2.1".into()));
     root.insert(Value::String("setup".into()), Value::Bool(true));
 
-    let mut parameters = if let Some(Value::Mapping(params)) =
-        context.raw_config.get(&Value::String("parameters".into()))
-    {
-        params.clone()
-    } else {
-        Mapping::new()
-    };
+    let mut parameters = pipeline_parameter_definitions(&context.raw_config).unwrap_or_default();
 
-    if !parameters.contains_key(&Value::String("skip_cache".into())) {
+    if !parameters.contains_key(SKIP_CACHE_PARAMETER) {
         let mut def = Mapping::new();
         def.insert(
             Value::String("type".into()),
@@ -304,39 +527,42 @@ fn generate_setup_config(context: &CircleciContext) -> Result<Value> {
         def.insert(Value::String("default".into()), Value::Bool(false));
         def.insert(
             Value::String("description".into()),
-            Value::String("Disable job-status cache and rerun all jobs".into()),
+            Value::String(
+                "Rerun every job, ignoring the job-status cache and starting from empty dependency caches"
+                    .into(),
+            ),
+        );
+        parameters.insert(
+            Value::String(SKIP_CACHE_PARAMETER.into()),
+            Value::Mapping(def),
+        );
+    }
+
+    if context.shard_count.is_some() && !parameters.contains_key(SHARD_PARAMETER) {
+        parameters.insert(Value::String(SHARD_PARAMETER.into()), shard_parameter());
+    }
+    if context.output.per_workflow && !parameters.contains_key(WORKFLOW_PARAMETER) {
+        parameters.insert(
+            Value::String(WORKFLOW_PARAMETER.into()),
+            build_workflow_parameter(workflows),
         );
-        parameters.insert(Value::String("skip_cache".into()), Value::Mapping(def));
     }
     root.insert(
         Value::String("parameters".into()),
         Value::Mapping(parameters),
     );
 
-    let orbs = build_orbs_map();
-    root.insert(Value::String("orbs".into()), Value::Mapping(orbs));
+    let orbs = build_orbs_map(&context.raw_config);
+    if !orbs.is_empty() {
+        root.insert(Value::String("orbs".into()), Value::Mapping(orbs));
+    }
 
     let commands = build_commands_map(context)?;
     if !commands.is_empty() {
         root.insert(Value::String("commands".into()), Value::Mapping(commands));
     }
 
-    let mut all_variants = Vec::new();
-    let mut grouped_jobs: HashMap<String, Vec<&JobDefinition>> = HashMap::new();
-
-    for job in &context.schema.jobs {
-        let wf = if job.workflow.is_empty() {
-            "ci"
-        } else {
-            &job.workflow
-        };
-        grouped_jobs.entry(wf.to_string()).or_default().push(job);
-    }
-
-    for wf_id in grouped_jobs.keys() {
-        let variants = collect_job_variants_for_workflow(context, wf_id)?;
-        all_variants.extend(variants);
-    }
+    let all_variants: Vec<JobVariant> = workflows.values().flatten().cloned().collect();
 
     let jobs = {
         let mut jobs_map = Mapping::new();
@@ -364,15 +590,59 @@ fn generate_setup_config(context: &CircleciContext) -> Result<Value> {
     Ok(Value::Mapping(root))
 }
 
-fn generate_main_config(context: &CircleciContext) -> Result<Value> {
+/// Workflow id → job variants, for every workflow that has jobs. Every job
+/// definition goes into the continued config's one `jobs:` map; the loader
+/// rejects a job name used by two workflows, so none replaces another.
+fn collect_workflow_variants<'a>(
+    context: &'a CircleciContext<'a>,
+) -> Result<BTreeMap<String, Vec<JobVariant<'a>>>> {
+    let mut workflows = BTreeMap::new();
+    for job in &context.schema.jobs {
+        let wf = if job.workflow.is_empty() {
+            DEFAULT_WORKFLOW
+        } else {
+            &job.workflow
+        };
+        if !workflows.contains_key(wf) {
+            workflows.insert(
+                wf.to_string(),
+                collect_job_variants_for_workflow(context, wf)?,
+            );
+        }
+    }
+    Ok(workflows)
+}
+
+/// Continued config containing the given workflows and their jobs
+fn generate_workflows_config<'a>(
+    context: &CircleciContext,
+    workflows: impl IntoIterator<Item = (&'a String, &'a Vec<JobVariant<'a>>)>,
+    diagnostics: &mut Vec<cigen::plugin::protocol::Diagnostic>,
+) -> Result<Value> {
     let mut root = Mapping::new();
     root.insert(Value::String("version".into()), Value::String("2.1".into()));
 
-    if let Some(params) = context.raw_config.get(&Value::String("parameters".into())) {
-        root.insert(Value::String("parameters".into()), params.clone());
+    if let Some(params) = pipeline_parameter_definitions(&context.raw_config) {
+        root.insert(Value::String("parameters".into()), Value::Mapping(params));
     }
 
-    let mut orbs = build_orbs_map();
+    let workflows: Vec<_> = workflows.into_iter().collect();
+    let mut orbs = build_orbs_map(&context.raw_config);
+    if workflows
+        .iter()
+        .any(|(wf_id, _)| workflow_slack(context, wf_id).is_some())
+    {
+        orbs.insert(
+            Value::String(SLACK_ALIAS.into()),
+            Value::String(DEFAULT_SLACK_ORB.into()),
+        );
+    }
+    let jobs = workflows
+        .iter()
+        .flat_map(|(_, variants)| variants.iter().map(|variant| variant.job));
+    for (alias, orb) in cloud_auth_orbs(jobs) {
+        orbs.insert(Value::String(alias.into()), Value::String(orb.into()));
+    }
     if let Some(Value::Mapping(user_orbs)) = context.raw_config.get(&Value::String("orbs".into())) {
         for (k, v) in user_orbs {
             orbs.insert(k.clone(), v.clone());
@@ -385,38 +655,38 @@ fn generate_main_config(context: &CircleciContext) -> Result<Value> {
         root.insert(Value::String("commands".into()), Value::Mapping(commands));
     }
 
-    let mut grouped_jobs: HashMap<String, Vec<&JobDefinition>> = HashMap::new();
-    for job in &context.schema.jobs {
-        let wf = if job.workflow.is_empty() {
-            "ci"
-        } else {
-            &job.workflow
-        };
-        grouped_jobs.entry(wf.to_string()).or_default().push(job);
-    }
-
-    let mut all_variants = Vec::new();
-    let mut workflow_variants_map: HashMap<String, Vec<JobVariant>> = HashMap::new();
-
-    for wf_id in grouped_jobs.keys() {
-        let variants = collect_job_variants_for_workflow(context, wf_id)?;
-        workflow_variants_map.insert(wf_id.clone(), variants.clone());
-        all_variants.extend(variants);
-    }
-
     let mut jobs_map = Mapping::new();
-    for variant in &all_variants {
-        if let Some(job_def) = convert_job(variant, context)? {
-            jobs_map.insert(Value::String(variant.variant_name.clone()), job_def);
+    let mut workflows_map = Mapping::new();
+    for (wf_id, variants) in workflows {
+        for variant in variants {
+            if let Some(mut job_def) = convert_job(variant, context)? {
+                if let Value::Mapping(job_map) = &mut job_def {
+                    diagnostics.extend(apply_provider_overrides(
+                        variant.job,
+                        PROVIDER_NAME,
+                        job_map,
+                    )?);
+                }
+                jobs_map.insert(Value::String(variant.variant_name.clone()), job_def);
+            }
         }
+        let wf_def = build_workflow_def(context, wf_id, variants, diagnostics)?;
+        workflows_map.insert(Value::String(wf_id.clone()), wf_def);
+    }
+    let referenced_executors: Vec<&str> = jobs_map
+        .values()
+        .filter_map(|job| job.get("executor").and_then(Value::as_str))
+        .collect();
+    let executors = context.executors.to_circleci(
+        referenced_executors,
+        &context.resource_classes,
+        context.docker_auth.default_auth_value().as_ref(),
+    );
+    if !executors.is_empty() {
+        root.insert(Value::String("executors".into()), Value::Mapping(executors));
     }
-    root.insert(Value::String("jobs".into()), Value::Mapping(jobs_map));
 
-    let mut workflows_map = Mapping::new();
-    for (wf_id, variants) in workflow_variants_map {
-        let wf_def = build_workflow_def(context, &wf_id, &variants)?;
-        workflows_map.insert(Value::String(wf_id), wf_def);
-    }
+    root.insert(Value::String("jobs".into()), Value::Mapping(jobs_map));
     root.insert(
         Value::String("workflows".into()),
         Value::Mapping(workflows_map),
@@ -425,11 +695,35 @@ fn generate_main_config(context: &CircleciContext) -> Result<Value> {
     Ok(Value::Mapping(root))
 }
 
-fn build_orbs_map() -> Mapping {
+/// Slack settings for a workflow, when it notifies
+fn workflow_slack<'a>(
+    context: &'a CircleciContext,
+    workflow_id: &str,
+) -> Option<&'a SlackNotification> {
+    context
+        .schema
+        .workflows
+        .iter()
+        .find(|workflow| workflow.id == workflow_id)
+        .and_then(|workflow| workflow.slack.as_ref())
+}
+
+/// The continuation orb, at the version from the config (or its lockfile) if
+/// set, else from `setup_options`. Empty when the setup job continues through
+/// the API.
+fn build_orbs_map(raw_config: &Value) -> Mapping {
     let mut orbs = Mapping::new();
+    let Some(default) = raw_config.as_mapping().and_then(setup_continuation_orb) else {
+        return orbs;
+    };
+    let continuation = raw_config
+        .get("orbs")
+        .and_then(|orbs| orbs.get(CONTINUATION_ALIAS))
+        .and_then(Value::as_str)
+        .map_or(default, str::to_string);
     orbs.insert(
-        Value::String("continuation".into()),
-        Value::String("circleci/continuation@1.0.0".into()),
+        Value::String(CONTINUATION_ALIAS.into()),
+        Value::String(continuation),
     );
     orbs
 }
@@ -441,7 +735,7 @@ fn collect_job_variants_for_workflow<'a>(
     let mut variants = Vec::new();
     for job in &context.schema.jobs {
         let job_workflow = if job.workflow.is_empty() {
-            "ci"
+            DEFAULT_WORKFLOW
         } else {
             &job.workflow
         };
@@ -461,40 +755,46 @@ fn build_workflow_def(
     context: &CircleciContext,
     workflow_id: &str,
     variants: &[JobVariant],
+    diagnostics: &mut Vec<cigen::plugin::protocol::Diagnostic>,
 ) -> Result<Value> {
     let mut workflow_map = Mapping::new();
 
     if let Some(conditions) = context.workflow_conditions.get(workflow_id)
-        && let Some(when_value) = build_circleci_when(conditions)?
+        && let Some((key, logic)) = build_workflow_when(conditions)?
     {
-        workflow_map.insert(Value::String("when".into()), when_value);
+        workflow_map.insert(Value::String(key.into()), logic);
     }
 
     workflow_map.insert(
         Value::String("jobs".into()),
-        Value::Sequence(build_workflow_jobs_sequence(variants)),
+        Value::Sequence(build_workflow_jobs_sequence(
+            variants,
+            workflow_slack(context, workflow_id),
+            &context.schema.commands,
+            diagnostics,
+        )?),
     );
 
     Ok(Value::Mapping(workflow_map))
 }
 
-fn build_workflow_jobs_sequence(variants: &[JobVariant]) -> Vec<Value> {
+fn build_workflow_jobs_sequence(
+    variants: &[JobVariant],
+    slack: Option<&SlackNotification>,
+    commands: &HashMap<String, CommandDefinition>,
+    diagnostics: &mut Vec<cigen::plugin::protocol::Diagnostic>,
+) -> Result<Vec<Value>> {
     let mut entries = Vec::new();
     for variant in variants {
         let job = variant.job;
 
-        // Check if job type is approval
-        let is_approval = if let Some(extra_type) = job.extra.get("type") {
-            if let Ok(val) = parse_yaml_value(extra_type) {
-                val.as_str() == Some("approval")
-            } else {
-                false
+        if job.is_approval() {
+            if !job.unless.is_empty() {
+                bail!(
+                    "approval job '{}' can't use `unless:` on CircleCI; use `branches:` or a workflow `run_unless`",
+                    job.id
+                );
             }
-        } else {
-            false
-        };
-
-        if is_approval {
             let mut job_config = Mapping::new();
             job_config.insert(
                 Value::String("type".into()),
@@ -508,6 +808,15 @@ fn build_workflow_jobs_sequence(variants: &[JobVariant]) -> Vec<Value> {
                 }
                 job_config.insert(Value::String("requires".into()), Value::Sequence(requires));
             }
+            if let Some(filters) = branch_filters(job) {
+                job_config.insert(Value::String("filters".into()), filters);
+            }
+            // Approval jobs only exist as workflow entries, so that's where their overrides go
+            diagnostics.extend(apply_provider_overrides(
+                job,
+                PROVIDER_NAME,
+                &mut job_config,
+            )?);
 
             let mut wrapper = Mapping::new();
             wrapper.insert(
@@ -518,15 +827,51 @@ fn build_workflow_jobs_sequence(variants: &[JobVariant]) -> Vec<Value> {
             continue;
         }
 
-        if job.needs.is_empty() {
-            entries.push(Value::String(variant.variant_name.clone()));
-        } else {
+        let mut job_config = Mapping::new();
+        if !job.needs.is_empty() {
             let mut requires = Vec::new();
             for need in &job.needs {
                 requires.push(Value::String(need.clone()));
             }
-            let mut job_config = Mapping::new();
             job_config.insert(Value::String("requires".into()), Value::Sequence(requires));
+        }
+        if let Some(filters) = branch_filters(job) {
+            job_config.insert(Value::String("filters".into()), filters);
+        }
+        let owner = format!("workflow job '{}'", job.id);
+        for (key, steps) in [
+            ("pre-steps", &job.pre_steps),
+            ("post-steps", &job.post_steps),
+        ] {
+            let mut converted = convert_steps_list(steps, &owner, commands)?;
+            if key == "pre-steps" && !job.unless.is_empty() {
+                converted.insert(
+                    0,
+                    unless_halt_step(&job.unless)
+                        .with_context(|| format!("Invalid `unless:` on job '{}'", job.id))?,
+                );
+            }
+            if key == "post-steps"
+                && let Some(slack) = slack
+            {
+                converted.push(notify_step(slack));
+            }
+            if !converted.is_empty() {
+                job_config.insert(Value::String(key.into()), Value::Sequence(converted));
+            }
+        }
+        if let Some(context) = slack.map(|slack| &slack.context)
+            && !context.is_empty()
+        {
+            job_config.insert(
+                Value::String("context".into()),
+                Value::Sequence(vec![Value::String(context.clone())]),
+            );
+        }
+
+        if job_config.is_empty() {
+            entries.push(Value::String(variant.variant_name.clone()));
+        } else {
             let mut wrapper = Mapping::new();
             wrapper.insert(
                 Value::String(variant.variant_name.clone()),
@@ -535,29 +880,86 @@ fn build_workflow_jobs_sequence(variants: &[JobVariant]) -> Vec<Value> {
             entries.push(Value::Mapping(wrapper));
         }
     }
-    entries
+    Ok(entries)
 }
 
 fn convert_job(variant: &JobVariant, context: &CircleciContext) -> Result<Option<Value>> {
     let job = variant.job;
 
     // Skip approval jobs in definition list (they only appear in workflows)
-    if let Some(extra_type) = job.extra.get("type")
-        && let Ok(val) = parse_yaml_value(extra_type)
-        && val.as_str() == Some("approval")
-    {
+    if job.is_approval() {
         return Ok(None);
     }
 
     let mut map = Mapping::new();
 
+    let mut executor_kind = "docker";
+    if let Some(executor) = &job.executor {
+        if executor.kind == "named" {
+            if !context.executors.contains(&executor.name) {
+                let message = unknown_reference_message(
+                    &format!(
+                        "Unknown CircleCI executor '{}' referenced by job '{}'",
+                        executor.name, job.id
+                    ),
+                    &executor.name,
+                    context.executors.names(),
+                );
+                return Err(located_error_in(
+                    message,
+                    &job.source_file,
+                    &job.source_section,
+                    &executor.name,
+                ));
+            }
+            if !job.services.is_empty() {
+                return Err(located_error_in(
+                    format!(
+                        "Job '{}' uses executor '{}', which can't add service containers; list them as extra docker images in the executor instead",
+                        job.id, executor.name
+                    ),
+                    &job.source_file,
+                    &job.source_section,
+                    "services",
+                ));
+            }
+            executor_kind = context.executors.kind(&executor.name, &job.architecture);
+            map.insert(
+                Value::String("executor".into()),
+                Value::String(
+                    context
+                        .executors
+                        .reference(&executor.name, &job.architecture),
+                ),
+            );
+        } else {
+            if !job.services.is_empty() {
+                return Err(located_error_in(
+                    format!(
+                        "Job '{}' uses the {} executor, which can't run service containers; services need the docker executor",
+                        job.id, executor.kind
+                    ),
+                    &job.source_file,
+                    &job.source_section,
+                    "services",
+                ));
+            }
+            let (key, value) = build_vm_executor(executor)?;
+            executor_kind = key;
+            map.insert(Value::String(key.into()), value);
+        }
+    }
+
     let mut docker_entries = Vec::new();
-    if !job.image.is_empty() {
+    if job.executor.is_none() && !job.image.is_empty() {
         let mut image_map = Mapping::new();
         image_map.insert(
             Value::String("image".into()),
             Value::String(job.image.clone()),
         );
+        if let Some(auth) = context.docker_auth.default_auth_value() {
+            image_map.insert(Value::String("auth".into()), auth);
+        }
         docker_entries.push(Value::Mapping(image_map));
     }
 
@@ -569,6 +971,9 @@ fn convert_job(variant: &JobVariant, context: &CircleciContext) -> Result<Option
                     Value::String("image".into()),
                     Value::String(definition.image.clone()),
                 );
+                if let Some(auth) = context.docker_auth.default_auth_value() {
+                    service_map.insert(Value::String("auth".into()), auth);
+                }
                 if let Some(env) = &definition.environment {
                     service_map.insert(
                         Value::String("environment".into()),
@@ -577,10 +982,20 @@ fn convert_job(variant: &JobVariant, context: &CircleciContext) -> Result<Option
                 }
                 docker_entries.push(Value::Mapping(service_map));
             } else {
-                bail!(
-                    "Unknown CircleCI service '{service}' referenced by job '{}'",
-                    job.id
+                let message = unknown_reference_message(
+                    &format!(
+                        "Unknown CircleCI service '{service}' referenced by job '{}'",
+                        job.id
+                    ),
+                    service,
+                    context.services.keys().map(String::as_str),
                 );
+                return Err(located_error_in(
+                    message,
+                    &job.source_file,
+                    &job.source_section,
+                    service,
+                ));
             }
         }
     }
@@ -593,68 +1008,483 @@ fn convert_job(variant: &JobVariant, context: &CircleciContext) -> Result<Option
     }
 
     let mut env_map = Mapping::new();
-    if !job.env.is_empty() {
-        for (key, value) in &job.env {
-            env_map.insert(Value::String(key.clone()), Value::String(value.clone()));
+    for (key, value) in merged_job_env(context.schema, job) {
+        env_map.insert(Value::String(key), Value::String(value));
+    }
+    if let Some(gcp) = job.cloud_auth.as_ref().and_then(|auth| auth.gcp.as_ref()) {
+        for (key, value) in gcp_environment(gcp) {
+            env_map
+                .entry(Value::String(key.into()))
+                .or_insert(Value::String(value));
+        }
+    }
+
+    if !env_map.is_empty() {
+        map.insert(Value::String("environment".into()), Value::Mapping(env_map));
+    }
+
+    if !job.runner.is_empty() {
+        map.insert(
+            Value::String("executor".into()),
+            Value::String(job.runner.clone()),
+        );
+    }
+
+    if let Some(resource_class_value) = job.extra.get("resource_class") {
+        let val = match parse_yaml_value(resource_class_value)? {
+            Value::String(name) => {
+                let resolved = context.resource_classes.resolve(&name, &job.architecture);
+                check_executor_resource_class(job, executor_kind, &resolved)?;
+                Value::String(resolved)
+            }
+            other => other,
+        };
+        map.insert(Value::String("resource_class".into()), val);
+    }
+
+    if let Some(parallelism_value) = job.extra.get("parallelism") {
+        map.insert(
+            Value::String("parallelism".into()),
+            parse_yaml_value(parallelism_value)?,
+        );
+    }
+
+    // CircleCI checks the repository out into the job's working directory, so
+    // only a `~/` or absolute one is set on the job. A relative one is a
+    // subdirectory of the checkout, which stays at the job root; the job's own
+    // run steps are pointed at it instead.
+    let subdirectory = checkout_subdirectory(job);
+    if subdirectory.is_none() && !job.working_directory.is_empty() {
+        map.insert(
+            Value::String("working_directory".into()),
+            Value::String(job.working_directory.clone()),
+        );
+    }
+    let test_results = match subdirectory {
+        Some(directory) if !job.test_results.starts_with(['/', '~', '$']) => {
+            format!("{directory}/{}", job.test_results)
+        }
+        _ => job.test_results.clone(),
+    };
+
+    let mut steps = build_checkout_steps(
+        context,
+        &resolve_job_checkout(context, job)?,
+        &job.source_submodules,
+    )
+    .with_context(|| format!("Invalid checkout for job '{}'", variant.variant_name))?;
+    let checkout_steps = steps.len();
+    steps.extend(build_wait_for_services_steps(job, context));
+    if let Some(remote_docker) = &job.remote_docker {
+        steps.push(build_setup_remote_docker_step(remote_docker));
+    }
+    let records_status = context.job_status_cache && job_has_hash_sources(job);
+    if records_status {
+        steps.extend(
+            job.source_submodules
+                .iter()
+                .map(|path| build_write_submodule_commit_step(path)),
+        );
+        steps.push(build_job_runtime_hash_step(job));
+    }
+    if needs_test_results_preparation(job) {
+        steps.push(build_prepare_test_results_step(&test_results));
+    }
+    if let Some(cloud_auth) = &job.cloud_auth {
+        steps.extend(cloud_auth_steps(cloud_auth));
+    }
+    let user_steps = steps.len();
+    steps.extend(convert_steps_list(
+        &job.steps,
+        &format!("job '{}'", variant.variant_name),
+        &context.schema.commands,
+    )?);
+    if !job.test_results.is_empty() {
+        steps.push(build_store_test_results_step(&test_results));
+    }
+    // Cleanup runs before the completion marker, which only records jobs
+    // whose every step passed
+    steps.extend(build_cleanup_steps(job, &context.schema.commands)?);
+    if let Some(directory) = subdirectory {
+        run_in_directory(&mut steps[user_steps..], directory);
+    }
+    if records_status {
+        steps.push(build_job_completion_marker_step(job));
+        steps.push(build_job_status_save_step(
+            job,
+            context.schema.cache_key_version(),
+        ));
+    }
+    if context.instrumentation.step_timing {
+        time_run_steps(&mut steps[checkout_steps..]);
+        steps.push(build_store_step_timings_step());
+    }
+    map.insert(Value::String("steps".into()), Value::Sequence(steps));
+
+    Ok(Some(Value::Mapping(map)))
+}
+
+/// The job's `working_directory` when it is a subdirectory of the checkout
+fn checkout_subdirectory(job: &JobDefinition) -> Option<&str> {
+    let directory = job.working_directory.trim_end_matches('/');
+    (!directory.is_empty() && directory != "." && !directory.starts_with(['/', '~', '$']))
+        .then_some(directory)
+}
+
+/// Run each `run` step in `directory`, including those under `when`/`unless`,
+/// unless the step sets its own `working_directory`. Cache keys read their
+/// `{{ checksum }}` files from the job root, so those are prefixed too.
+fn run_in_directory(steps: &mut [Value], directory: &str) {
+    for step in steps {
+        let Some(step) = step.as_mapping_mut() else {
+            continue;
+        };
+        if let Some(run) = step.get_mut("run") {
+            if let Value::String(command) = run {
+                let mut run_map = Mapping::new();
+                run_map.insert(
+                    Value::String("command".into()),
+                    Value::String(std::mem::take(command)),
+                );
+                *run = Value::Mapping(run_map);
+            }
+            if let Some(run) = run.as_mapping_mut() {
+                let key = Value::String("working_directory".into());
+                if !run.contains_key(&key) {
+                    run.insert(key, Value::String(directory.to_string()));
+                }
+            }
+            continue;
+        }
+        for cache_step in ["restore_cache", "save_cache"] {
+            let Some(cache) = step.get_mut(cache_step).and_then(Value::as_mapping_mut) else {
+                continue;
+            };
+            for (_, key) in cache.iter_mut() {
+                match key {
+                    Value::String(key) => *key = checksums_in_directory(key, directory),
+                    Value::Sequence(keys) => {
+                        for key in keys {
+                            if let Value::String(key) = key {
+                                *key = checksums_in_directory(key, directory);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for condition in ["when", "unless"] {
+            if let Some(Value::Sequence(nested)) = step
+                .get_mut(condition)
+                .and_then(|condition| condition.get_mut("steps"))
+            {
+                run_in_directory(nested, directory);
+            }
         }
     }
+}
+
+/// `key` with the relative files of its `{{ checksum "..." }}` templates in `directory`
+fn checksums_in_directory(key: &str, directory: &str) -> String {
+    const CHECKSUM: &str = "checksum \"";
+    let mut output = String::new();
+    let mut rest = key;
+    while let Some(start) = rest.find(CHECKSUM) {
+        let (before, after) = rest.split_at(start + CHECKSUM.len());
+        output.push_str(before);
+        if !after.starts_with(['/', '~', '$']) {
+            output.push_str(directory);
+            output.push('/');
+        }
+        rest = after;
+    }
+    output.push_str(rest);
+    output
+}
+
+/// `machine:` or `macos:` job key for a VM executor
+fn build_vm_executor(executor: &Executor) -> Result<(&'static str, Value)> {
+    let mut options = Mapping::new();
+    let key = match executor.kind.as_str() {
+        "machine" => {
+            let image = if executor.image.is_empty() {
+                DEFAULT_MACHINE_IMAGE
+            } else {
+                &executor.image
+            };
+            options.insert(
+                Value::String("image".into()),
+                Value::String(image.to_string()),
+            );
+            "machine"
+        }
+        "macos" => {
+            options.insert(
+                Value::String("xcode".into()),
+                Value::String(executor.xcode.clone()),
+            );
+            "macos"
+        }
+        other => bail!("Unsupported executor '{other}'"),
+    };
+    Ok((key, Value::Mapping(options)))
+}
+
+/// macOS jobs only run on `macos.*` resource classes, and only macOS jobs can use them
+fn check_executor_resource_class(
+    job: &JobDefinition,
+    executor_kind: &str,
+    resource_class: &str,
+) -> Result<()> {
+    let is_macos = executor_kind == "macos";
+    let macos_class = resource_class.starts_with("macos.");
+    if is_macos && !macos_class {
+        return Err(located_error_in(
+            format!(
+                "Job '{}' uses the macos executor, but resource_class '{resource_class}' is not a macOS resource class (e.g. macos.m1.medium.gen1)",
+                job.id
+            ),
+            &job.source_file,
+            &job.source_section,
+            resource_class,
+        ));
+    }
+    if !is_macos && macos_class {
+        return Err(located_error_in(
+            format!(
+                "Job '{}' uses resource_class '{resource_class}', which requires `executor: {{ macos: {{ xcode: ... }} }}`",
+                job.id
+            ),
+            &job.source_file,
+            &job.source_section,
+            resource_class,
+        ));
+    }
+    Ok(())
+}
+
+/// Job environment layered over workflow and global `env` (job > workflow > global)
+fn merged_job_env(schema: &CigenSchema, job: &JobDefinition) -> BTreeMap<String, String> {
+    let workflow_id = if job.workflow.is_empty() {
+        DEFAULT_WORKFLOW
+    } else {
+        &job.workflow
+    };
+
+    let mut env: BTreeMap<String, String> = schema.env.clone().into_iter().collect();
+    if let Some(workflow) = schema.workflows.iter().find(|wf| wf.id == workflow_id) {
+        env.extend(workflow.env.clone());
+    }
+    env.extend(job.env.clone());
+    env
+}
+
+fn job_parallelism(job: &JobDefinition) -> u64 {
+    job.extra
+        .get("parallelism")
+        .and_then(|value| parse_yaml_value(value).ok())
+        .and_then(|value| value.as_u64())
+        .unwrap_or(1)
+}
+
+fn job_has_hash_sources(job: &JobDefinition) -> bool {
+    !job.source_files.is_empty() || !job.source_submodules.is_empty()
+}
+
+fn build_write_submodule_commit_step(path: &str) -> Value {
+    let mut params = Mapping::new();
+    params.insert(
+        Value::String("path".into()),
+        Value::String(path.to_string()),
+    );
+
+    let mut wrapper = Mapping::new();
+    wrapper.insert(
+        Value::String("cigen_write_submodule_commit_hash".into()),
+        Value::Mapping(params),
+    );
+    Value::Mapping(wrapper)
+}
+
+fn needs_test_results_preparation(job: &JobDefinition) -> bool {
+    !job.test_results.is_empty() && job_parallelism(job) > 1
+}
+
+fn build_prepare_test_results_step(path: &str) -> Value {
+    let mut params = Mapping::new();
+    params.insert(
+        Value::String("path".into()),
+        Value::String(path.to_string()),
+    );
+    let mut wrapper = Mapping::new();
+    wrapper.insert(
+        Value::String("cigen_prepare_test_results".into()),
+        Value::Mapping(params),
+    );
+    Value::Mapping(wrapper)
+}
+
+/// Glob the test files, keep this container's share, and run them
+fn build_store_test_results_step(path: &str) -> Value {
+    let mut params = Mapping::new();
+    params.insert(
+        Value::String("path".into()),
+        Value::String(path.to_string()),
+    );
+    let mut wrapper = Mapping::new();
+    wrapper.insert(
+        Value::String("store_test_results".into()),
+        Value::Mapping(params),
+    );
+    Value::Mapping(wrapper)
+}
+
+/// `instrumentation.step_timing`: log how long each `run` step takes,
+/// including those inside `when`/`unless` blocks
+fn time_run_steps(steps: &mut [Value]) {
+    for step in steps {
+        let Value::Mapping(step) = step else {
+            continue;
+        };
+        for (key, body) in step.iter_mut() {
+            match (key.as_str(), body) {
+                (Some("run"), Value::String(command)) => {
+                    *command = timed_command(default_step_name(command), command);
+                }
+                (Some("run"), Value::Mapping(run)) => {
+                    let name = run.get("name").and_then(Value::as_str).map(str::to_string);
+                    if let Some(Value::String(command)) = run.get_mut("command") {
+                        let name = name.unwrap_or_else(|| default_step_name(command).to_string());
+                        *command = timed_command(&name, command);
+                    }
+                }
+                (Some("when" | "unless"), Value::Mapping(block)) => {
+                    if let Some(Value::Sequence(steps)) = block.get_mut("steps") {
+                        time_run_steps(steps);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn build_store_step_timings_step() -> Value {
+    let mut params = Mapping::new();
+    params.insert(
+        Value::String("path".into()),
+        Value::String(STEP_TIMINGS_LOG.into()),
+    );
+    params.insert(
+        Value::String("destination".into()),
+        Value::String("cigen-timings.log".into()),
+    );
+    // Stored when a step fails too, as that's when the timings are wanted
+    params.insert(Value::String("when".into()), Value::String("always".into()));
+    let mut wrapper = Mapping::new();
+    wrapper.insert(
+        Value::String("store_artifacts".into()),
+        Value::Mapping(params),
+    );
+    Value::Mapping(wrapper)
+}
 
-    if !env_map.is_empty() {
-        map.insert(Value::String("environment".into()), Value::Mapping(env_map));
+fn build_setup_remote_docker_step(remote_docker: &RemoteDocker) -> Value {
+    let mut options = Mapping::new();
+    if !remote_docker.version.is_empty() {
+        options.insert(
+            Value::String("version".into()),
+            Value::String(remote_docker.version.clone()),
+        );
     }
-
-    if !job.runner.is_empty() {
-        map.insert(
-            Value::String("executor".into()),
-            Value::String(job.runner.clone()),
+    if remote_docker.layer_caching {
+        options.insert(
+            Value::String("docker_layer_caching".into()),
+            Value::Bool(true),
         );
     }
 
-    if let Some(resource_class_value) = job.extra.get("resource_class") {
-        let val = parse_yaml_value(resource_class_value)?;
-        map.insert(Value::String("resource_class".into()), val);
-    }
+    let mut wrapper = Mapping::new();
+    wrapper.insert(
+        Value::String("setup_remote_docker".into()),
+        Value::Mapping(options),
+    );
+    Value::Mapping(wrapper)
+}
 
-    if let Some(parallelism_value) = job.extra.get("parallelism") {
-        map.insert(
-            Value::String("parallelism".into()),
-            parse_yaml_value(parallelism_value)?,
+/// Convert user steps, applying `if:` conditions. `owner` names the job or
+/// command in error messages.
+fn convert_steps_list(
+    steps: &[Step],
+    owner: &str,
+    commands: &HashMap<String, CommandDefinition>,
+) -> Result<Vec<Value>> {
+    let mut converted = Vec::new();
+    for (index, step) in steps.iter().enumerate() {
+        let value = convert_step(step, commands)?;
+        let condition = step_condition(step);
+        if condition.is_empty() {
+            converted.push(value);
+            continue;
+        }
+
+        let label = match &step.step_type {
+            Some(cigen::plugin::protocol::step::StepType::Run(run)) if !run.name.is_empty() => {
+                format!("step {} ('{}')", index + 1, run.name)
+            }
+            _ => format!("step {}", index + 1),
+        };
+        converted.push(
+            apply_step_condition(step, value, condition)
+                .with_context(|| format!("Unsupported condition on {label} of {owner}"))?,
         );
     }
+    Ok(converted)
+}
 
-    let mut steps = vec![build_checkout_invocation(&context.checkout)];
-    if !job.source_files.is_empty() {
-        steps.push(build_job_runtime_hash_step(job));
-    }
-    steps.extend(convert_steps_list(&job.steps)?);
-    if !job.source_files.is_empty() {
-        steps.push(build_job_completion_marker_step(job));
-        steps.push(build_job_status_save_step(job));
+fn step_condition(step: &Step) -> &str {
+    use cigen::plugin::protocol::step::StepType;
+    match &step.step_type {
+        Some(StepType::Run(run)) => &run.r#if,
+        Some(StepType::Uses(uses)) => &uses.r#if,
+        Some(StepType::RestoreCache(cache)) => &cache.r#if,
+        Some(StepType::SaveCache(cache)) => &cache.r#if,
+        Some(StepType::Custom(custom)) => &custom.r#if,
+        None => "",
     }
-    map.insert(Value::String("steps".into()), Value::Sequence(steps));
-
-    Ok(Some(Value::Mapping(map)))
 }
 
-fn convert_steps_list(steps: &[Step]) -> Result<Vec<Value>> {
-    let mut converted = Vec::new();
-    for step in steps {
-        converted.push(convert_step(step)?);
+/// Wrap a converted step in `when:` and/or guard its command with a shell test
+fn apply_step_condition(step: &Step, mut value: Value, condition: &str) -> Result<Value> {
+    let compiled = compile_step_condition(condition)?;
+
+    if let Some(guard) = &compiled.guard {
+        let Some(cigen::plugin::protocol::step::StepType::Run(run)) = &step.step_type else {
+            bail!(
+                "'{condition}' checks environment variables at runtime, which CircleCI only supports on run steps"
+            );
+        };
+        let command = guard_command(guard, &run.command);
+        if let Some(run_map) = value.get_mut("run").and_then(Value::as_mapping_mut) {
+            run_map.insert(Value::String("command".into()), Value::String(command));
+        }
     }
-    Ok(converted)
+
+    if let Some(when) = compiled.when {
+        value = wrap_in_when(when, value);
+    }
+    Ok(value)
 }
 
-fn convert_step(step: &Step) -> Result<Value> {
+fn convert_step(step: &Step, commands: &HashMap<String, CommandDefinition>) -> Result<Value> {
     match step
         .step_type
         .as_ref()
         .ok_or_else(|| anyhow!("missing step_type"))?
     {
         cigen::plugin::protocol::step::StepType::Run(RunStep {
-            name,
-            command,
-            env,
-            r#if,
+            name, command, env, ..
         }) => {
             let mut run_map = Mapping::new();
             if !name.is_empty() {
@@ -671,16 +1501,11 @@ fn convert_step(step: &Step) -> Result<Value> {
                 }
                 run_map.insert(Value::String("environment".into()), Value::Mapping(env_map));
             }
-            if !r#if.is_empty() {
-                run_map.insert(Value::String("if".into()), Value::String(r#if.clone()));
-            }
             let mut wrapper = Mapping::new();
             wrapper.insert(Value::String("run".into()), Value::Mapping(run_map));
             Ok(Value::Mapping(wrapper))
         }
-        cigen::plugin::protocol::step::StepType::Uses(UsesStep {
-            module, with, r#if, ..
-        }) => {
+        cigen::plugin::protocol::step::StepType::Uses(UsesStep { module, with, .. }) => {
             let mut uses_map = Mapping::new();
             uses_map.insert(Value::String("uses".into()), Value::String(module.clone()));
             if !with.is_empty() {
@@ -691,9 +1516,6 @@ fn convert_step(step: &Step) -> Result<Value> {
                 }
                 uses_map.insert(Value::String("with".into()), Value::Mapping(with_map));
             }
-            if !r#if.is_empty() {
-                uses_map.insert(Value::String("if".into()), Value::String(r#if.clone()));
-            }
             Ok(Value::Mapping(uses_map))
         }
         cigen::plugin::protocol::step::StepType::RestoreCache(step) => {
@@ -704,8 +1526,9 @@ fn convert_step(step: &Step) -> Result<Value> {
                     Value::String(step.name.clone()),
                 );
             }
-            restore_map.insert(Value::String("key".into()), Value::String(step.key.clone()));
-            if !step.keys.is_empty() {
+            if step.keys.is_empty() {
+                restore_map.insert(Value::String("key".into()), Value::String(step.key.clone()));
+            } else {
                 restore_map.insert(
                     Value::String("keys".into()),
                     Value::Sequence(step.keys.iter().map(|k| Value::String(k.clone())).collect()),
@@ -765,13 +1588,60 @@ fn convert_step(step: &Step) -> Result<Value> {
             wrapper.insert(Value::String("save_cache".into()), Value::Mapping(save_map));
             Ok(Value::Mapping(wrapper))
         }
-        cigen::plugin::protocol::step::StepType::Custom(CustomStep { yaml, .. }) => {
-            let val = parse_yaml_value(yaml)?;
+        cigen::plugin::protocol::step::StepType::Custom(CustomStep { kind, yaml, .. }) => {
+            let mut val = parse_yaml_value(yaml)?;
+            if let Some(command) = commands.get(kind)
+                && let Some(Value::Mapping(args)) = val.get_mut(kind.as_str())
+            {
+                for (parameter, definition) in &command.parameters {
+                    if definition.r#type != STEPS_PARAMETER_TYPE {
+                        continue;
+                    }
+                    if let Some(argument) = args.get_mut(parameter.as_str()) {
+                        *argument = convert_steps_argument(argument, kind, parameter, commands)?;
+                    }
+                }
+            }
             Ok(val)
         }
     }
 }
 
+/// Render steps passed to a `steps` parameter like the job's own steps
+///
+/// Anything but a list, such as `<< parameters.steps >>` forwarded from an
+/// enclosing command, is passed through for CircleCI to expand.
+fn convert_steps_argument(
+    argument: &Value,
+    command: &str,
+    parameter: &str,
+    commands: &HashMap<String, CommandDefinition>,
+) -> Result<Value> {
+    if !argument.is_sequence() {
+        return Ok(argument.clone());
+    }
+    let owner = format!("parameter '{parameter}' of command '{command}'");
+    let steps = step_list_to_proto(argument).with_context(|| format!("Invalid {owner}"))?;
+    Ok(Value::Sequence(convert_steps_list(
+        &steps, &owner, commands,
+    )?))
+}
+
+/// `filters:` for a workflow job entry that only runs on some `branches`
+fn branch_filters(job: &JobDefinition) -> Option<Value> {
+    if job.branches.is_empty() {
+        return None;
+    }
+    let mut only = Mapping::new();
+    only.insert(
+        Value::String("only".into()),
+        Value::Sequence(job.branches.iter().cloned().map(Value::String).collect()),
+    );
+    let mut filters = Mapping::new();
+    filters.insert(Value::String("branches".into()), Value::Mapping(only));
+    Some(Value::Mapping(filters))
+}
+
 fn build_setup_job(
     context: &CircleciContext,
     workflow_id: &str,
@@ -783,7 +1653,7 @@ fn build_setup_job(
         .setup_options
         .image
         .clone()
-        .unwrap_or_else(|| "cimg/rust:1.76".to_string());
+        .unwrap_or_else(|| context.setup_options.install.default_image().to_string());
 
     let mut docker_entries = Vec::new();
     let mut docker_map = Mapping::new();
@@ -797,17 +1667,27 @@ fn build_setup_job(
     if let Some(resource_class) = &context.setup_options.resource_class {
         job.insert(
             Value::String("resource_class".into()),
-            Value::String(resource_class.clone()),
+            Value::String(
+                context
+                    .resource_classes
+                    .resolve(resource_class, DEFAULT_ARCHITECTURE),
+            ),
         );
     }
 
-    let mut steps = Vec::new();
-    steps.push(build_checkout_invocation(&context.checkout));
-
-    if context.setup_options.compile_cigen {
-        steps.push(build_compile_cigen_step(&context.setup_options));
+    // The setup job always needs the repository, even when jobs disable checkout
+    let mut steps = build_checkout_steps(context, &context.checkout, &[])?;
+    if steps.is_empty() {
+        steps.push(Value::String("checkout".into()));
     }
 
+    steps.extend(
+        context
+            .setup_options
+            .install
+            .steps(context.schema.cache_key_version()),
+    );
+
     if let Some(self_check) = context
         .setup_options
         .self_check
@@ -817,82 +1697,93 @@ fn build_setup_job(
         steps.push(build_self_check_step(self_check));
     }
 
-    steps.push(build_skip_cache_parameter_step());
     steps.push(build_prepare_skip_list_step());
 
+    // With `skip_cache`, every job runs, so there is nothing to look up
+    let mut status_steps = Vec::new();
     for variant in job_variants {
         if variant.job.source_files.is_empty() {
             continue;
         }
-        steps.push(build_job_hash_step(variant));
-        steps.push(build_job_status_restore_step(variant));
-        steps.push(build_skip_list_append_step(variant, workflow_id));
+        status_steps.push(build_job_hash_step(variant));
+        status_steps.push(build_job_status_restore_step(
+            variant,
+            context.schema.cache_key_version(),
+        ));
+        status_steps.push(build_skip_list_append_step(variant, workflow_id));
+    }
+    if !status_steps.is_empty() {
+        steps.push(unless_skip_cache(status_steps));
     }
 
-    steps.push(build_generate_main_step(workflow_id));
-    steps.push(build_continuation_step(&context.raw_config));
+    // Per-workflow output regenerates and continues with the selected workflow's file
+    let (mut generate_target, configuration_path) = if context.output.per_workflow {
+        let workflow = format!("<< pipeline.parameters.{WORKFLOW_PARAMETER} >>");
+        let path = context.output.written_path(ConfigFile::Workflow(&workflow));
+        (workflow, path)
+    } else if let Some(shard_count) = context.shard_count {
+        (
+            format!("{CONTINUED_CONFIG} --shard-count {shard_count}"),
+            context.output.written_path(ConfigFile::Main),
+        )
+    } else {
+        (
+            CONTINUED_CONFIG.to_string(),
+            context.output.written_path(ConfigFile::Main),
+        )
+    };
+    if let Some(flag) = context.size_limits.override_flag() {
+        generate_target = format!("{generate_target} {flag}");
+    }
+    if context.verify_images {
+        generate_target = format!("{generate_target} --verify-images");
+    }
+    // Regenerate into the directory the setup config was generated into
+    if let Some(output_dir) = &context.output.output_dir {
+        generate_target = format!("{generate_target} --output {}", shell_quote(output_dir));
+    }
+    if let Some(detection) = &context.project_detection {
+        steps.push(build_detect_projects_step(detection));
+    }
+    steps.push(build_generate_main_step(
+        workflow_id,
+        &generate_target,
+        context.project_detection.is_some(),
+    ));
+    if context.shard_count.is_some() {
+        steps.push(select_shard_step(&context.output));
+    }
+    steps.extend(build_continuation_steps(
+        &context.raw_config,
+        &configuration_path,
+        context.setup_options.continuation,
+    )?);
 
     job.insert(Value::String("steps".into()), Value::Sequence(steps));
 
     Ok(Value::Mapping(job))
 }
 
-fn build_compile_cigen_step(options: &SetupOptions) -> Value {
-    let mut lines = Vec::new();
-    lines.push("set -euo pipefail".to_string());
-
-    if let Some(repo) = &options.compile_repository {
-        let path = options
-            .compile_path
-            .clone()
-            .unwrap_or_else(|| "/tmp/cigen".to_string());
-        lines.push(format!("rm -rf {path}"));
-        lines.push(format!("git clone {repo} {path}"));
-        lines.push(format!("cd {path}"));
-        if let Some(rev) = &options.compile_ref {
-            lines.push(format!("git checkout {rev}"));
-        }
-        lines.push("cargo build --release".to_string());
-        lines.push(format!(
-            "echo \"export PATH=\\\"{path}/target/release:$PATH\\\"\" >> $BASH_ENV"
-        ));
-    } else {
-        lines.push("cargo build --release".to_string());
-        lines.push(
-            "echo \"export PATH=\\\"$(pwd)/target/release:$PATH\\\"\" >> $BASH_ENV".to_string(),
-        );
-    }
-
-    lines.push(String::new());
-    let command = lines.join("\n");
-
-    let mut run_map = Mapping::new();
-    run_map.insert(
-        Value::String("name".into()),
-        Value::String("Compile cigen".into()),
-    );
-    run_map.insert(Value::String("command".into()), Value::String(command));
-
-    let mut wrapper = Mapping::new();
-    wrapper.insert(Value::String("run".into()), Value::Mapping(run_map));
-    Value::Mapping(wrapper)
+/// Whether `docker_build` is enabled with `verify_images: true`
+fn docker_build_verifies_images(raw_config: &Value) -> bool {
+    let Some(docker_build) = raw_config.get("docker_build") else {
+        return false;
+    };
+    let option = |key: &str| docker_build.get(key).and_then(Value::as_bool);
+    option("enabled").unwrap_or(true) && option("verify_images").unwrap_or(false)
 }
 
 fn build_self_check_step(options: &SelfCheckOptions) -> Value {
     let mut lines = vec![
         "set -euo pipefail".to_string(),
-        "cp -f .circleci/config.yml .circleci/config.yml.bak".to_string(),
-        "cigen generate".to_string(),
-        "if ! diff -q .circleci/config.yml .circleci/config.yml.bak > /dev/null 2>&1; then"
-            .to_string(),
+        "if ! cigen verify --provider circleci; then".to_string(),
     ];
     if options.commit_on_diff {
+        lines.push("  cigen verify --provider circleci --fix".to_string());
         lines.push("  git config user.email \"ci@cigen.dev\"".to_string());
         lines.push("  git config user.name \"CIGen\"".to_string());
-        lines.push("  git add .circleci/config.yml".to_string());
-        lines.push(
-            "  git commit -m \"ci: update .circleci/config.yml from cigen\" || true".to_string(),
-        );
+        lines.push("  git add .circleci".to_string());
+        lines.push("  git commit -m \"ci: update .circleci from cigen\" || true".to_string());
         lines.push("  git push || true".to_string());
     }
     lines.extend([
@@ -915,26 +1806,18 @@ fn build_self_check_step(options: &SelfCheckOptions) -> Value {
     Value::Mapping(wrapper)
 }
 
-fn build_skip_cache_parameter_step() -> Value {
-    let command = [
-        "set -euo pipefail".to_string(),
-        "if [ \"<< pipeline.parameters.skip_cache >>\" = \"true\" ]; then".to_string(),
-        "  cigen generate main".to_string(),
-        "  circleci step halt".to_string(),
-        "fi".to_string(),
-        String::new(),
-    ]
-    .join("\n");
-
-    let mut run_map = Mapping::new();
-    run_map.insert(
-        Value::String("name".into()),
-        Value::String("Handle skip_cache parameter".into()),
+/// `steps` in an `unless` block that leaves them out when the `skip_cache`
+/// pipeline parameter is true
+fn unless_skip_cache(steps: Vec<Value>) -> Value {
+    let mut block = Mapping::new();
+    block.insert(
+        Value::String("condition".into()),
+        Value::String(format!("<< pipeline.parameters.{SKIP_CACHE_PARAMETER} >>")),
     );
-    run_map.insert(Value::String("command".into()), Value::String(command));
+    block.insert(Value::String("steps".into()), Value::Sequence(steps));
 
     let mut wrapper = Mapping::new();
-    wrapper.insert(Value::String("run".into()), Value::Mapping(run_map));
+    wrapper.insert(Value::String("unless".into()), Value::Mapping(block));
     Value::Mapping(wrapper)
 }
 
@@ -954,19 +1837,31 @@ fn build_prepare_skip_list_step() -> Value {
     Value::Mapping(wrapper)
 }
 
+/// Hash of a job the current branch's filters leave out. No job saves a
+/// status under it, so its status probe always misses.
+const BRANCH_FILTERED_HASH: &str = "branch-filtered";
+
+/// Hash a job's sources. Jobs with `branches` only hash on a matching branch.
 fn build_job_hash_step(variant: &JobVariant) -> Value {
+    let hash = format!(
+        "JOB_HASH=$(cigen hash --job {} --config .cigen | tr -d '\\r')",
+        shell_quote(&variant.job.id)
+    );
+    let hash = match branch_guard(&variant.job.branches) {
+        Some(guard) => {
+            format!("if {guard}; then\n  {hash}\nelse\n  JOB_HASH={BRANCH_FILTERED_HASH}\nfi")
+        }
+        None => hash,
+    };
     let command = [
         "set -euo pipefail".to_string(),
         "mkdir -p /tmp/cigen".to_string(),
-        format!(
-            "JOB_HASH=$(cigen hash --job {} --config .cigen | tr -d '\\r')",
-            variant.job.id
-        ),
+        hash,
         "printf '%s' \"$JOB_HASH\" > /tmp/cigen/job_hash".to_string(),
-        "echo \"export JOB_HASH=$JOB_HASH\" >> $BASH_ENV".to_string(),
+        "echo \"export JOB_HASH=$JOB_HASH\" >> \"$BASH_ENV\"".to_string(),
         format!(
-            "echo 'Computed hash for {}: '$JOB_HASH",
-            variant.variant_name
+            "echo {}\"$JOB_HASH\"",
+            shell_quote(&format!("Computed hash for {}: ", variant.variant_name))
         ),
         String::new(),
     ]
@@ -984,19 +1879,24 @@ fn build_job_hash_step(variant: &JobVariant) -> Value {
     Value::Mapping(wrapper)
 }
 
-fn build_job_status_restore_step(variant: &JobVariant) -> Value {
+fn build_job_status_restore_step(variant: &JobVariant, cache_version: Option<u32>) -> Value {
     let mut restore_map = Mapping::new();
     restore_map.insert(
         Value::String("name".into()),
         Value::String(format!("Restore job status: {}", variant.variant_name)),
     );
-    restore_map.insert(
-        Value::String("keys".into()),
-        Value::Sequence(vec![
-            Value::String(job_status_cache_key(&variant.variant_name)),
-            Value::String("linux-{{ checksum \"/etc/os-release\" }}-job_status-exists-v1-".into()),
-        ]),
-    );
+    let mut keys = vec![Value::String(job_status_cache_key(
+        &variant.variant_name,
+        cache_version,
+    ))];
+    // Without the prefix fallback, a branch-filtered job's probe is a cheap miss
+    if variant.job.branches.is_empty() {
+        keys.push(Value::String(versioned_cache_key(
+            cache_version,
+            JOB_STATUS_KEY_PREFIX,
+        )));
+    }
+    restore_map.insert(Value::String("keys".into()), Value::Sequence(keys));
 
     let mut wrapper = Mapping::new();
     wrapper.insert(
@@ -1006,9 +1906,13 @@ fn build_job_status_restore_step(variant: &JobVariant) -> Value {
     Value::Mapping(wrapper)
 }
 
-fn job_status_cache_key(job_name: &str) -> String {
-    format!(
-        "linux-{{{{ checksum \"/etc/os-release\" }}}}-job_status-exists-v1-{job_name}-{{{{ checksum \"/tmp/cigen/job_hash\" }}}}"
+/// Job status keys start with this, then the job name and its source hash
+const JOB_STATUS_KEY_PREFIX: &str = "linux-{{ checksum \"/etc/os-release\" }}-job_status-exists-";
+
+fn job_status_cache_key(job_name: &str, cache_version: Option<u32>) -> String {
+    versioned_cache_key(
+        cache_version,
+        &format!("{JOB_STATUS_KEY_PREFIX}{job_name}-{{{{ checksum \"/tmp/cigen/job_hash\" }}}}"),
     )
 }
 
@@ -1018,10 +1922,10 @@ fn build_job_runtime_hash_step(job: &JobDefinition) -> Value {
         "mkdir -p /tmp/cigen /tmp/cigen_job_exists".to_string(),
         format!(
             "JOB_HASH=$(cigen hash --job {} --config .cigen | tr -d '\\r')",
-            job.id
+            shell_quote(&job.id)
         ),
         "printf '%s' \"$JOB_HASH\" > /tmp/cigen/job_hash".to_string(),
-        "echo \"export JOB_HASH=$JOB_HASH\" >> $BASH_ENV".to_string(),
+        "echo \"export JOB_HASH=$JOB_HASH\" >> \"$BASH_ENV\"".to_string(),
         "echo \"Computed job hash: $JOB_HASH\"".to_string(),
         String::new(),
     ]
@@ -1039,6 +1943,27 @@ fn build_job_runtime_hash_step(job: &JobDefinition) -> Value {
     Value::Mapping(wrapper)
 }
 
+/// The job's `cleanup` run steps, with `when:` set from `cleanup_on`
+fn build_cleanup_steps(
+    job: &JobDefinition,
+    commands: &HashMap<String, CommandDefinition>,
+) -> Result<Vec<Value>> {
+    let when = match job.cleanup_on.as_str() {
+        "failure" => "on_fail",
+        _ => "always",
+    };
+    job.cleanup
+        .iter()
+        .map(|step| {
+            let mut step = convert_step(step, commands)?;
+            if let Some(Value::Mapping(run)) = step.get_mut("run") {
+                run.insert(Value::String("when".into()), Value::String(when.into()));
+            }
+            Ok(step)
+        })
+        .collect()
+}
+
 fn build_job_completion_marker_step(job: &JobDefinition) -> Value {
     let command = [
         "set -euo pipefail".to_string(),
@@ -1046,7 +1971,7 @@ fn build_job_completion_marker_step(job: &JobDefinition) -> Value {
         "if [ -z \"${JOB_HASH:-}\" ]; then".to_string(),
         format!(
             "  JOB_HASH=$(cigen hash --job {} --config .cigen | tr -d '\\r')",
-            job.id
+            shell_quote(&job.id)
         ),
         "fi".to_string(),
         "printf '%s' \"$JOB_HASH\" > /tmp/cigen/job_hash".to_string(),
@@ -1064,7 +1989,7 @@ fn build_job_completion_marker_step(job: &JobDefinition) -> Value {
     run_map.insert(Value::String("command".into()), Value::String(command));
     run_map.insert(
         Value::String("when".into()),
-        Value::String("on_success".into()),
+        Value::String(SaveWhen::OnSuccess.as_str().into()),
     );
 
     let mut wrapper = Mapping::new();
@@ -1072,7 +1997,7 @@ fn build_job_completion_marker_step(job: &JobDefinition) -> Value {
     Value::Mapping(wrapper)
 }
 
-fn build_job_status_save_step(job: &JobDefinition) -> Value {
+fn build_job_status_save_step(job: &JobDefinition, cache_version: Option<u32>) -> Value {
     let mut save_map = Mapping::new();
     save_map.insert(
         Value::String("name".into()),
@@ -1080,7 +2005,7 @@ fn build_job_status_save_step(job: &JobDefinition) -> Value {
     );
     save_map.insert(
         Value::String("key".into()),
-        Value::String(job_status_cache_key(&job.id)),
+        Value::String(job_status_cache_key(&job.id, cache_version)),
     );
     save_map.insert(
         Value::String("paths".into()),
@@ -1098,12 +2023,17 @@ fn build_job_status_save_step(job: &JobDefinition) -> Value {
 
 fn build_skip_list_append_step(variant: &JobVariant, workflow_id: &str) -> Value {
     let skip_file = format!("/tmp/skip/{}.txt", workflow_id);
+    let probe = format!(
+        "if [ -f \"/tmp/cigen_job_exists/done_${{JOB_HASH}}\" ]; then echo {} >> \"{skip_file}\"; fi",
+        shell_quote(&variant.variant_name)
+    );
+    let probe = match branch_guard(&variant.job.branches) {
+        Some(guard) => guard_command(&guard, &probe).trim_end().to_string(),
+        None => probe,
+    };
     let command = [
         "set -euo pipefail".to_string(),
-        format!(
-            "if [ -f '/tmp/cigen_job_exists/done_${{JOB_HASH}}' ]; then echo '{}' >> {}; fi",
-            variant.variant_name, skip_file
-        ),
+        probe,
         "rm -rf /tmp/cigen_job_exists".to_string(),
         String::new(),
     ]
@@ -1121,17 +2051,18 @@ fn build_skip_list_append_step(variant: &JobVariant, workflow_id: &str) -> Value
     Value::Mapping(wrapper)
 }
 
-fn build_generate_main_step(workflow_id: &str) -> Value {
-    let skip_file = format!("/tmp/skip/{}.txt", workflow_id);
+/// List the affected projects for `cigen generate` to keep the jobs of
+fn build_detect_projects_step(detection: &ProjectDetection) -> Value {
     let command = format!(
-        "set -euo pipefail\nif [ -s \"{skip}\" ]; then\n  CIGEN_SKIP_JOBS_FILE=\"{skip}\" cigen generate main\nelse\n  cigen generate main\nfi\n",
-        skip = skip_file
+        "set -euo pipefail\nmkdir -p \"$(dirname {AFFECTED_PROJECTS_FILE})\"\nexport BASE=\"${{BASE:-{}}}\"\n{} > {AFFECTED_PROJECTS_FILE}\necho \"Affected projects:\"\ncat {AFFECTED_PROJECTS_FILE}\n",
+        detection.base(),
+        detection.command()
     );
 
     let mut run_map = Mapping::new();
     run_map.insert(
         Value::String("name".into()),
-        Value::String("Generate filtered main".into()),
+        Value::String("Detect affected projects".into()),
     );
     run_map.insert(Value::String("command".into()), Value::String(command));
 
@@ -1140,63 +2071,86 @@ fn build_generate_main_step(workflow_id: &str) -> Value {
     Value::Mapping(wrapper)
 }
 
-fn build_continuation_step(raw_config: &Value) -> Value {
-    let mut params = Mapping::new();
-    params.insert(
-        Value::String("configuration_path".into()),
-        Value::String(".circleci/main.yml".into()),
+fn build_generate_main_step(workflow_id: &str, target: &str, only_affected: bool) -> Value {
+    let skip_file = format!("/tmp/skip/{}.txt", workflow_id);
+    let projects = if only_affected {
+        format!("export {ONLY_PROJECTS_FILE_ENV}=\"{AFFECTED_PROJECTS_FILE}\"\n")
+    } else {
+        String::new()
+    };
+    // `skip_cache` reruns every job: no job-status steps, and a nonce so the
+    // dependency caches start empty
+    let command = format!(
+        "set -euo pipefail\n{projects}if [ \"<< pipeline.parameters.{SKIP_CACHE_PARAMETER} >>\" = \"true\" ]; then\n  cigen generate {target} --no-job-status-cache --cache-nonce \"pipeline-<< pipeline.number >>\"\nelif [ -s \"{skip}\" ]; then\n  CIGEN_SKIP_JOBS_FILE=\"{skip}\" cigen generate {target}\nelse\n  cigen generate {target}\nfi\n",
+        skip = skip_file
     );
 
-    let parameters = extract_parameters(raw_config);
-    if !parameters.is_empty() {
-        let mut json_parts = Vec::new();
-        for (name, type_) in parameters {
-            let val = if type_ == "string" || type_ == "enum" {
-                format!("\"<< pipeline.parameters.{name} >>\"")
-            } else {
-                format!("<< pipeline.parameters.{name} >>")
-            };
-            json_parts.push(format!("\"{}\": {}", name, val));
-        }
-        let json_str = format!("{{ {} }}", json_parts.join(", "));
-        params.insert(Value::String("parameters".into()), Value::String(json_str));
-    }
+    let mut run_map = Mapping::new();
+    run_map.insert(
+        Value::String("name".into()),
+        Value::String("Generate filtered main".into()),
+    );
+    run_map.insert(Value::String("command".into()), Value::String(command));
 
     let mut wrapper = Mapping::new();
-    wrapper.insert(
-        Value::String("continuation/continue".into()),
-        Value::Mapping(params),
-    );
+    wrapper.insert(Value::String("run".into()), Value::Mapping(run_map));
     Value::Mapping(wrapper)
 }
 
-fn extract_parameters(raw: &Value) -> Vec<(String, String)> {
-    raw.as_mapping()
-        .and_then(|map| map.get(&Value::String("parameters".into())))
-        .and_then(Value::as_mapping)
-        .map(|mapping| {
-            mapping
-                .iter()
-                .filter_map(|(k, v)| {
-                    let name = k.as_str()?.to_string();
-                    let type_ = v
-                        .as_mapping()
-                        .and_then(|m| m.get(&Value::String("type".into())))
-                        .and_then(|t| t.as_str())
-                        .unwrap_or("string")
-                        .to_string();
-                    Some((name, type_))
-                })
-                .collect()
-        })
-        .unwrap_or_default()
+/// Enum parameter listing the workflows; defaults to `ci` when there is one
+fn build_workflow_parameter(workflows: &BTreeMap<String, Vec<JobVariant>>) -> Value {
+    let default = if workflows.contains_key("ci") {
+        "ci"
+    } else {
+        workflows.keys().next().map(String::as_str).unwrap_or("ci")
+    };
+
+    let mut def = Mapping::new();
+    def.insert(Value::String("type".into()), Value::String("enum".into()));
+    def.insert(
+        Value::String("enum".into()),
+        Value::Sequence(workflows.keys().cloned().map(Value::String).collect()),
+    );
+    def.insert(
+        Value::String("default".into()),
+        Value::String(default.into()),
+    );
+    def.insert(
+        Value::String("description".into()),
+        Value::String("Workflow config the setup job continues with".into()),
+    );
+    Value::Mapping(def)
 }
 
 fn build_commands_map(context: &CircleciContext) -> Result<Mapping> {
     let mut commands = default_commands()?;
 
+    if context
+        .schema
+        .jobs
+        .iter()
+        .any(needs_test_results_preparation)
+    {
+        const PREPARE_TEST_RESULTS: &str = include_str!("prepare_test_results.yml");
+        let prepare: Mapping = serde_yaml::from_str(PREPARE_TEST_RESULTS)
+            .context("Failed to parse embedded test results command")?;
+        commands.extend(prepare);
+    }
+
+    if context
+        .schema
+        .jobs
+        .iter()
+        .any(|job| !job.source_submodules.is_empty())
+    {
+        const WRITE_SUBMODULE_COMMIT: &str = include_str!("write_submodule_commit_hash.yml");
+        let write: Mapping = serde_yaml::from_str(WRITE_SUBMODULE_COMMIT)
+            .context("Failed to parse embedded submodule commit command")?;
+        commands.extend(write);
+    }
+
     for (name, command) in &context.schema.commands {
-        let command_value = convert_command_definition(command)?;
+        let command_value = convert_command_definition(name, command, &context.schema.commands)?;
         commands.insert(Value::String(name.clone()), command_value);
     }
 
@@ -1210,7 +2164,11 @@ fn default_commands() -> Result<Mapping> {
     Ok(defaults)
 }
 
-fn convert_command_definition(command: &CommandDefinition) -> Result<Value> {
+fn convert_command_definition(
+    name: &str,
+    command: &CommandDefinition,
+    commands: &HashMap<String, CommandDefinition>,
+) -> Result<Value> {
     let mut map = Mapping::new();
 
     if !command.description.is_empty() {
@@ -1222,16 +2180,16 @@ fn convert_command_definition(command: &CommandDefinition) -> Result<Value> {
 
     if !command.parameters.is_empty() {
         let mut params = Mapping::new();
-        for (name, parameter) in &command.parameters {
+        for (parameter_name, parameter) in &command.parameters {
             params.insert(
-                Value::String(name.clone()),
-                convert_command_parameter(parameter)?,
+                Value::String(parameter_name.clone()),
+                convert_command_parameter(name, parameter_name, parameter, commands)?,
             );
         }
         map.insert(Value::String("parameters".into()), Value::Mapping(params));
     }
 
-    let steps = convert_steps_list(&command.steps)?;
+    let steps = convert_steps_list(&command.steps, &format!("command '{name}'"), commands)?;
     map.insert(Value::String("steps".into()), Value::Sequence(steps));
 
     if !command.extra.is_empty() {
@@ -1243,7 +2201,12 @@ fn convert_command_definition(command: &CommandDefinition) -> Result<Value> {
     Ok(Value::Mapping(map))
 }
 
-fn convert_command_parameter(parameter: &CommandParameter) -> Result<Value> {
+fn convert_command_parameter(
+    command: &str,
+    name: &str,
+    parameter: &CommandParameter,
+    commands: &HashMap<String, CommandDefinition>,
+) -> Result<Value> {
     let mut map = Mapping::new();
 
     if !parameter.r#type.is_empty() {
@@ -1261,7 +2224,10 @@ fn convert_command_parameter(parameter: &CommandParameter) -> Result<Value> {
     }
 
     if !parameter.default_yaml.is_empty() {
-        let default_value = parse_yaml_value(&parameter.default_yaml)?;
+        let mut default_value = parse_yaml_value(&parameter.default_yaml)?;
+        if parameter.r#type == STEPS_PARAMETER_TYPE {
+            default_value = convert_steps_argument(&default_value, command, name, commands)?;
+        }
         map.insert(Value::String("default".into()), default_value);
     }
 
@@ -1308,6 +2274,11 @@ impl WorkflowRunCondition {
             } else {
                 Some(proto.equals_yaml.clone())
             },
+            expression: if proto.expression.is_empty() {
+                None
+            } else {
+                Some(proto.expression.clone())
+            },
         })
     }
 }
@@ -1317,11 +2288,12 @@ fn make_diagnostic(code: &str, error: anyhow::Error) -> cigen::plugin::protocol:
         level: cigen::plugin::protocol::diagnostic::Level::Error as i32,
         code: code.to_string(),
         title: "CircleCI generation failed".to_string(),
-        message: error.to_string(),
+        message: format!("{error:#}"),
         fix_hint: String::new(),
-        loc: None,
+        loc: error_location(&error),
     }
 }
+
 fn build_checkout_invocation(config: &CheckoutConfig) -> Value {
     if !config.shallow
         && config.fetch_options.is_none()
@@ -1375,15 +2347,15 @@ fn build_checkout_invocation(config: &CheckoutConfig) -> Value {
     Value::Mapping(wrapper)
 }
 
-fn extract_services(raw_config: &Value) -> HashMap<String, ServiceDefinition> {
+fn extract_services(raw_config: &Value) -> Result<HashMap<String, ServiceDefinition>> {
     let mut services = HashMap::new();
 
     let Value::Mapping(root) = raw_config else {
-        return services;
+        return Ok(services);
     };
 
     let Some(Value::Mapping(service_map)) = root.get(&Value::String("services".into())) else {
-        return services;
+        return Ok(services);
     };
 
     for (key, value) in service_map {
@@ -1404,17 +2376,55 @@ fn extract_services(raw_config: &Value) -> HashMap<String, ServiceDefinition> {
             .get(&Value::String("environment".into()))
             .and_then(Value::as_mapping)
             .cloned();
+        let ports = parse_service_ports(name, definition.get("ports"))?;
+        let wait_for = ServiceWait::parse(name, definition.get("wait_for"))?;
+        if wait_for.is_some() && ports.is_empty() {
+            bail!("Service '{name}' sets wait_for but lists no ports to wait for");
+        }
 
         services.insert(
             name.to_string(),
             ServiceDefinition {
                 image: image.to_string(),
                 environment,
+                ports,
+                wait_for,
             },
         );
     }
 
-    services
+    Ok(services)
+}
+
+/// Steps that wait for the job's `wait_for` services to accept connections.
+/// Service containers share the primary container's network, so they listen
+/// on localhost.
+fn build_wait_for_services_steps(job: &JobDefinition, context: &CircleciContext) -> Vec<Value> {
+    job.services
+        .iter()
+        .filter_map(|service| {
+            let definition = context.services.get(service)?;
+            let wait = definition.wait_for?;
+            let ports: Vec<String> = definition
+                .ports
+                .iter()
+                .map(|port| port.container.to_string())
+                .collect();
+
+            let mut run_map = Mapping::new();
+            run_map.insert(
+                Value::String("name".into()),
+                Value::String(format!("Wait for {service}")),
+            );
+            run_map.insert(
+                Value::String("command".into()),
+                Value::String(wait_for_service_command(service, "localhost", &ports, wait)),
+            );
+            let mut wrapper = Mapping::new();
+            wrapper.insert(Value::String("run".into()), Value::Mapping(run_map));
+            Some(Value::Mapping(wrapper))
+        })
+        .collect()
 }
 
 fn extract_setup_options(raw_config: &Value) -> Result<SetupOptions> {
@@ -1445,33 +2455,28 @@ fn extract_setup_options(raw_config: &Value) -> Result<SetupOptions> {
         options.resource_class = Some(resource_class.to_string());
     }
 
-    if let Some(compile) = map
-        .get(&Value::String("compile_cigen".into()))
-        .and_then(Value::as_bool)
-    {
-        options.compile_cigen = compile;
-    }
-
-    if let Some(repo) = map
-        .get(&Value::String("compile_repository".into()))
-        .and_then(Value::as_str)
-    {
-        options.compile_repository = Some(repo.to_string());
+    let string = |key: &str| {
+        map.get(&Value::String(key.into()))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+    if let Some(mode) = string("continuation") {
+        options.continuation = ContinuationMode::parse(&mode)?;
     }
-
-    if let Some(rev) = map
-        .get(&Value::String("compile_ref".into()))
-        .and_then(Value::as_str)
-    {
-        options.compile_ref = Some(rev.to_string());
+    if let Some(version) = string("continuation_orb_version") {
+        if options.continuation != ContinuationMode::Orb {
+            bail!("setup_options.continuation_orb_version only applies to continuation: orb");
+        }
+        if version.is_empty()
+            || !version
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':'))
+        {
+            bail!("setup_options.continuation_orb_version '{version}' isn't an orb version");
+        }
     }
 
-    if let Some(path) = map
-        .get(&Value::String("compile_path".into()))
-        .and_then(Value::as_str)
-    {
-        options.compile_path = Some(path.to_string());
-    }
+    options.install = CigenInstall::from_setup_options(map)?;
 
     if let Some(Value::Mapping(self_map)) = map.get(&Value::String("self_check".into())) {
         let enabled = self_map
@@ -1488,32 +2493,50 @@ fn extract_setup_options(raw_config: &Value) -> Result<SetupOptions> {
         });
     }
 
-    if options.compile_cigen
-        && options.compile_repository.is_none()
-        && options.compile_path.is_none()
-        && options.compile_ref.is_none()
-    {
-        // Compiling from the current repository is acceptable, so no explicit repository is required.
-    }
-
     Ok(options)
 }
 
 fn extract_checkout_config(raw_config: &Value) -> CheckoutConfig {
     let mut config = CheckoutConfig::default();
 
-    let Some(value) = raw_config
+    if let Some(value) = raw_config
         .as_mapping()
         .and_then(|map| map.get(&Value::String("checkout".into())))
-    else {
-        return config;
-    };
+    {
+        apply_checkout_options(&mut config, value);
+    }
+
+    config
+}
 
+/// Layer a `checkout:` value (boolean or mapping) over `config`
+fn apply_checkout_options(config: &mut CheckoutConfig, value: &Value) {
     match value {
-        Value::Bool(false) => {
-            config.shallow = true;
+        Value::Bool(enabled) => {
+            config.disabled = !enabled;
         }
         Value::Mapping(map) => {
+            if let Some(enabled) = map
+                .get(&Value::String("enabled".into()))
+                .and_then(Value::as_bool)
+            {
+                config.disabled = !enabled;
+            }
+
+            if let Some(command) = map
+                .get(&Value::String("command".into()))
+                .and_then(Value::as_str)
+            {
+                config.command = Some(command.to_string());
+            }
+
+            if let Some(post) = map
+                .get(&Value::String("post".into()))
+                .and_then(Value::as_sequence)
+            {
+                config.post = post.clone();
+            }
+
             if let Some(shallow) = map
                 .get(&Value::String("shallow".into()))
                 .and_then(Value::as_bool)
@@ -1567,8 +2590,99 @@ fn extract_checkout_config(raw_config: &Value) -> CheckoutConfig {
         }
         _ => {}
     }
+}
 
-    config
+/// Global checkout settings with the job's `checkout:` overrides applied
+fn resolve_job_checkout(context: &CircleciContext, job: &JobDefinition) -> Result<CheckoutConfig> {
+    let mut config = context.checkout.clone();
+    if !job.checkout.is_empty() {
+        let mut overrides = Mapping::new();
+        for (key, value) in &job.checkout {
+            overrides.insert(Value::String(key.clone()), parse_yaml_value(value)?);
+        }
+        apply_checkout_options(&mut config, &Value::Mapping(overrides));
+    }
+    Ok(config)
+}
+
+/// Checkout step (built-in, shallow, or custom command), submodule initialization, then `post` steps
+fn build_checkout_steps(
+    context: &CircleciContext,
+    config: &CheckoutConfig,
+    submodules: &[String],
+) -> Result<Vec<Value>> {
+    if config.disabled {
+        return Ok(Vec::new());
+    }
+
+    let checkout = match &config.command {
+        Some(command) => {
+            if !context.schema.commands.contains_key(command) {
+                bail!(
+                    "{}",
+                    unknown_reference_message(
+                        &format!("Checkout command '{command}' is not defined"),
+                        command,
+                        context.schema.commands.keys().map(String::as_str),
+                    )
+                );
+            }
+            Value::String(command.clone())
+        }
+        None => build_checkout_invocation(config),
+    };
+
+    let mut steps = vec![checkout];
+    if !submodules.is_empty() {
+        steps.push(build_submodule_update_step(submodules));
+    }
+    steps.extend(config.post.iter().cloned());
+    Ok(steps)
+}
+
+fn build_submodule_update_step(submodules: &[String]) -> Value {
+    let mut run_map = Mapping::new();
+    run_map.insert(
+        Value::String("name".into()),
+        Value::String("Initialize submodules".into()),
+    );
+    run_map.insert(
+        Value::String("command".into()),
+        Value::String(format!(
+            "git submodule update --init -- {}",
+            submodules
+                .iter()
+                .map(|path| shell_quote(path))
+                .collect::<Vec<_>>()
+                .join(" ")
+        )),
+    );
+
+    let mut wrapper = Mapping::new();
+    wrapper.insert(Value::String("run".into()), Value::Mapping(run_map));
+    Value::Mapping(wrapper)
+}
+
+/// The workflow's `when:` or `unless:` key and its logic statement. With both
+/// `run_when` and `run_unless`, `when:` ANDs them with the unless clause negated.
+fn build_workflow_when(
+    conditions: &WorkflowRunConditions,
+) -> Result<Option<(&'static str, Value)>> {
+    let when = build_circleci_when(&conditions.run_when)?;
+    let unless = build_circleci_when(&conditions.run_unless)?;
+    Ok(match (when, unless) {
+        (None, None) => None,
+        (Some(when), None) => Some(("when", when)),
+        (None, Some(unless)) => Some(("unless", unless)),
+        (Some(when), Some(unless)) => {
+            let mut not_map = Mapping::new();
+            not_map.insert(Value::String("not".into()), unless);
+            let clauses = vec![when, Value::Mapping(not_map)];
+            let mut and_map = Mapping::new();
+            and_map.insert(Value::String("and".into()), Value::Sequence(clauses));
+            Some(("when", Value::Mapping(and_map)))
+        }
+    })
 }
 
 fn build_circleci_when(conditions: &[WorkflowRunCondition]) -> Result<Option<Value>> {
@@ -1595,12 +2709,19 @@ fn build_circleci_when(conditions: &[WorkflowRunCondition]) -> Result<Option<Val
                 equal_map.insert(Value::String("equal".into()), Value::Sequence(equal_values));
                 clauses.push(Value::Mapping(equal_map));
             }
-            WorkflowRunConditionKind::Variable
-            | WorkflowRunConditionKind::Env
-            | WorkflowRunConditionKind::Expression => {
+            WorkflowRunConditionKind::Expression => {
+                let expression = condition
+                    .expression
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("Workflow expression condition missing expression"))?;
+                clauses.push(compile_workflow_condition(expression)?);
+            }
+            WorkflowRunConditionKind::Env => {
+                bail!("env conditions are not supported on CircleCI; use a pipeline parameter");
+            }
+            WorkflowRunConditionKind::Variable => {
                 bail!(
-                    "Workflow condition type {:?} is not supported on CircleCI",
-                    condition.kind
+                    "variable conditions are not supported on CircleCI; use a pipeline parameter"
                 );
             }
         }
@@ -0,0 +1,90 @@
+//! Slack notifications through the circleci/slack orb
+//!
+//! Each job in a notifying workflow gets a `slack/notify` post-step. The orb
+//! reads `SLACK_ACCESS_TOKEN` from the environment, which normally comes from
+//! the context named in `notifications.slack.context`.
+
+use cigen::plugin::protocol::{Diagnostic, SlackNotification, WorkflowDefinition, diagnostic};
+use serde_yaml::{Mapping, Value};
+
+pub const SLACK_ALIAS: &str = "slack";
+pub const DEFAULT_SLACK_ORB: &str = "circleci/slack@4.13.3";
+
+/// The orb's `event` filter for the requested results. CircleCI can't tell a
+/// fixed build from any other passing one, so `fixed` notifies on every pass.
+fn slack_event(slack: &SlackNotification) -> &'static str {
+    let on_failure = slack.events.iter().any(|event| event == "failure");
+    let on_pass = slack
+        .events
+        .iter()
+        .any(|event| event == "success" || event == "fixed");
+    match (on_failure, on_pass) {
+        (true, true) => "always",
+        (false, true) => "pass",
+        _ => "fail",
+    }
+}
+
+/// `slack/notify` post-step for a workflow job
+pub fn notify_step(slack: &SlackNotification) -> Value {
+    let mut params = Mapping::new();
+    params.insert(
+        Value::String("channel".into()),
+        Value::String(slack.channel.clone()),
+    );
+    params.insert(
+        Value::String("event".into()),
+        Value::String(slack_event(slack).into()),
+    );
+    let mut step = Mapping::new();
+    step.insert(
+        Value::String(format!("{SLACK_ALIAS}/notify")),
+        Value::Mapping(params),
+    );
+    Value::Mapping(step)
+}
+
+/// Warn once per workflow that asks for `fixed`, which CircleCI can only
+/// approximate
+pub fn fixed_event_warnings(workflows: &[WorkflowDefinition]) -> Vec<Diagnostic> {
+    workflows
+        .iter()
+        .filter(|workflow| {
+            workflow
+                .slack
+                .as_ref()
+                .is_some_and(|slack| slack.events.iter().any(|event| event == "fixed"))
+        })
+        .map(|workflow| Diagnostic {
+            level: diagnostic::Level::Warning as i32,
+            code: "CIRCLECI_SLACK_FIXED".to_string(),
+            title: "Slack 'fixed' notifications".to_string(),
+            message: format!(
+                "Workflow '{}' notifies Slack on 'fixed', but CircleCI can't tell a fixed build from any other pass, so it will notify on every passing job",
+                workflow.id
+            ),
+            fix_hint: "Use 'success' to make this explicit, or drop 'fixed'".to_string(),
+            loc: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slack(events: &[&str]) -> SlackNotification {
+        SlackNotification {
+            channel: "#ci".to_string(),
+            events: events.iter().map(|event| event.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn events_map_to_orb_filters() {
+        assert_eq!(slack_event(&slack(&["failure"])), "fail");
+        assert_eq!(slack_event(&slack(&["fixed"])), "pass");
+        assert_eq!(slack_event(&slack(&["failure", "fixed"])), "always");
+    }
+}
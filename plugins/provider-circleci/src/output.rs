@@ -0,0 +1,350 @@
+use anyhow::{Result, bail};
+use cigen::schema::{
+    OUTPUT_DIR_FLAG, OutputConfig, STEPS_PARAMETER_TYPE, WORKFLOW_PLACEHOLDER,
+    check_output_filename, resolve_output_path,
+};
+use serde_yaml::{Mapping, Value};
+use std::collections::{HashMap, HashSet};
+
+/// Provider name the output paths are resolved for
+const PROVIDER: &str = "circleci";
+
+/// File name of each workflow's config in the output directory by default
+pub const DEFAULT_FILENAME_TEMPLATE: &str = "workflows/{workflow}.yml";
+
+/// File name of the continued config when all workflows share one file
+const MAIN_FILENAME: &str = "main.yml";
+
+/// A config file the provider generates
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFile<'a> {
+    /// `config.yml` (or `output_filename`), which CircleCI runs first
+    Setup,
+    /// `main.yml`, the continued config when all workflows share one file
+    Main,
+    /// `main_<n>.yml`, given the shard number or a shell variable holding it
+    Shard(&'a str),
+    /// A workflow's own config with `per_workflow`, given its id or a
+    /// pipeline parameter reference
+    Workflow(&'a str),
+}
+
+/// How the generated configs are laid out.
+///
+/// The setup config and `main.yml` go in `output_path` (`.circleci` by
+/// default), the setup config named by `output_filename`. The continued config
+/// is configured at the root of the config:
+///
+/// ```yaml
+/// output:
+///   per_workflow: true
+///   filename_template: "{workflow}_config.yml"
+/// ```
+///
+/// By default every workflow goes into `main.yml`. With `per_workflow`, each
+/// workflow gets its own standalone config and the setup job continues with
+/// the one selected by the `workflow` pipeline parameter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputOptions {
+    pub per_workflow: bool,
+    pub filename_template: String,
+    /// `output_path` and `output_filename`
+    pub config: OutputConfig,
+    /// `cigen generate --output`, where the files are written
+    pub output_dir: Option<String>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            per_workflow: false,
+            filename_template: DEFAULT_FILENAME_TEMPLATE.to_string(),
+            config: OutputConfig::default(),
+            output_dir: None,
+        }
+    }
+}
+
+impl OutputOptions {
+    pub fn from_raw_config(raw_config: &Value, flags: &HashMap<String, String>) -> Result<Self> {
+        let mut options = Self {
+            config: OutputConfig::from_raw_config(raw_config)?,
+            output_dir: flags.get(OUTPUT_DIR_FLAG).cloned(),
+            ..Self::default()
+        };
+        let Some(output) = raw_config.get("output") else {
+            return Ok(options);
+        };
+
+        if let Some(per_workflow) = output.get("per_workflow").and_then(Value::as_bool) {
+            options.per_workflow = per_workflow;
+        }
+        if let Some(template) = output.get("filename_template").and_then(Value::as_str) {
+            if !template.contains(WORKFLOW_PLACEHOLDER) {
+                bail!(
+                    "output.filename_template '{template}' must contain {WORKFLOW_PLACEHOLDER} so each workflow gets its own file"
+                );
+            }
+            check_output_filename("output.filename_template", template)?;
+            options.filename_template = template.to_string();
+        }
+        Ok(options)
+    }
+
+    /// Path of `file` as returned to the core, which writes it under the
+    /// output directory
+    pub fn path(&self, file: ConfigFile) -> String {
+        self.resolve(file, None)
+    }
+
+    /// Path `file` is written to, for paths embedded in the generated configs
+    pub fn written_path(&self, file: ConfigFile) -> String {
+        self.resolve(file, self.output_dir.as_deref())
+    }
+
+    fn resolve(&self, file: ConfigFile, output_dir: Option<&str>) -> String {
+        let (workflow, config) = match file {
+            ConfigFile::Setup => ("", self.config.clone()),
+            ConfigFile::Main => ("", self.config.with_filename(MAIN_FILENAME)),
+            ConfigFile::Shard(shard) => (shard, self.config.with_filename("main_{workflow}.yml")),
+            ConfigFile::Workflow(workflow) => {
+                (workflow, self.config.with_filename(&self.filename_template))
+            }
+        };
+        resolve_output_path(PROVIDER, workflow, &config, output_dir)
+    }
+}
+
+/// Drop `commands` and `orbs` that nothing in a standalone config references,
+/// so each per-workflow file only carries what its jobs use
+pub fn prune_unused_definitions(config: &mut Mapping) {
+    let commands = config
+        .get("commands")
+        .and_then(Value::as_mapping)
+        .cloned()
+        .unwrap_or_default();
+
+    let mut referenced = HashSet::new();
+    let mut pending = Vec::new();
+    if let Some(jobs) = config.get("jobs").and_then(Value::as_mapping) {
+        for job in jobs.values() {
+            if let Some(executor) = job.get("executor").and_then(Value::as_str) {
+                referenced.insert(executor.to_string());
+            }
+            collect_step_names(job.get("steps"), &commands, &mut pending);
+        }
+    }
+    if let Some(workflows) = config.get("workflows").and_then(Value::as_mapping) {
+        for workflow in workflows.values() {
+            let entries = workflow.get("jobs").and_then(Value::as_sequence);
+            for entry in entries.into_iter().flatten() {
+                let name = match entry {
+                    Value::Mapping(map) => {
+                        for options in map.values() {
+                            collect_step_names(options.get("pre-steps"), &commands, &mut pending);
+                            collect_step_names(options.get("post-steps"), &commands, &mut pending);
+                        }
+                        map.keys().next().and_then(Value::as_str)
+                    }
+                    other => other.as_str(),
+                };
+                pending.extend(name.map(String::from));
+            }
+        }
+    }
+
+    // Commands can call other commands, so follow them transitively
+    while let Some(name) = pending.pop() {
+        if !referenced.insert(name.clone()) {
+            continue;
+        }
+        if let Some(command) = commands.get(name.as_str()) {
+            collect_step_names(command.get("steps"), &commands, &mut pending);
+            for parameter in steps_parameters(command) {
+                let default = command["parameters"][parameter].get("default");
+                collect_step_names(default, &commands, &mut pending);
+            }
+        }
+    }
+
+    if let Some(Value::Mapping(commands)) = config.get_mut("commands") {
+        commands.retain(|name, _| name.as_str().is_some_and(|name| referenced.contains(name)));
+        if commands.is_empty() {
+            config.remove("commands");
+        }
+    }
+
+    let used_orbs: HashSet<&str> = referenced
+        .iter()
+        .filter_map(|name| name.split_once('/').map(|(orb, _)| orb))
+        .collect();
+    if let Some(Value::Mapping(orbs)) = config.get_mut("orbs") {
+        orbs.retain(|name, _| name.as_str().is_some_and(|name| used_orbs.contains(name)));
+        if orbs.is_empty() {
+            config.remove("orbs");
+        }
+    }
+}
+
+/// Names of the steps in `steps`, including those nested in `when`/`unless`
+fn collect_step_names(steps: Option<&Value>, commands: &Mapping, names: &mut Vec<String>) {
+    for step in steps.and_then(Value::as_sequence).into_iter().flatten() {
+        match step {
+            Value::String(name) => names.push(name.clone()),
+            Value::Mapping(map) => {
+                for (name, body) in map {
+                    let Some(name) = name.as_str() else { continue };
+                    if matches!(name, "when" | "unless") {
+                        collect_step_names(body.get("steps"), commands, names);
+                        continue;
+                    }
+                    names.push(name.to_string());
+                    // Steps passed to a command's `steps` parameters
+                    if let Some(command) = commands.get(name) {
+                        for parameter in steps_parameters(command) {
+                            collect_step_names(body.get(parameter), commands, names);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Names of a command's parameters that take steps
+fn steps_parameters(command: &Value) -> impl Iterator<Item = &str> {
+    command
+        .get("parameters")
+        .and_then(Value::as_mapping)
+        .into_iter()
+        .flatten()
+        .filter(|(_, parameter)| parameter["type"].as_str() == Some(STEPS_PARAMETER_TYPE))
+        .filter_map(|(name, _)| name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workflow_paths_follow_the_template() {
+        let raw: Value = serde_yaml::from_str(
+            "output:\n  per_workflow: true\n  filename_template: \"{workflow}_config.yml\"\n",
+        )
+        .unwrap();
+        let options = OutputOptions::from_raw_config(&raw, &HashMap::new()).unwrap();
+        assert!(options.per_workflow);
+        assert_eq!(
+            options.path(ConfigFile::Workflow("deploy")),
+            ".circleci/deploy_config.yml"
+        );
+
+        let default = OutputOptions::from_raw_config(&Value::Null, &HashMap::new()).unwrap();
+        assert!(!default.per_workflow);
+        assert_eq!(
+            default.path(ConfigFile::Workflow("deploy")),
+            ".circleci/workflows/deploy.yml"
+        );
+
+        let raw: Value =
+            serde_yaml::from_str("output:\n  filename_template: config.yml\n").unwrap();
+        let error = OutputOptions::from_raw_config(&raw, &HashMap::new())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("must contain {workflow}"), "{error}");
+    }
+
+    #[test]
+    fn output_path_and_cli_output_move_every_file() {
+        let raw: Value =
+            serde_yaml::from_str("output_path: ci\noutput_filename: setup.yml\n").unwrap();
+        let flags = HashMap::from([(OUTPUT_DIR_FLAG.to_string(), "out".to_string())]);
+        let options = OutputOptions::from_raw_config(&raw, &flags).unwrap();
+
+        assert_eq!(options.path(ConfigFile::Setup), "ci/setup.yml");
+        assert_eq!(options.path(ConfigFile::Main), "ci/main.yml");
+        assert_eq!(options.written_path(ConfigFile::Main), "out/ci/main.yml");
+        assert_eq!(
+            options.written_path(ConfigFile::Shard("$shard")),
+            "out/ci/main_$shard.yml"
+        );
+        assert_eq!(
+            options.written_path(ConfigFile::Workflow("deploy")),
+            "out/ci/workflows/deploy.yml"
+        );
+    }
+
+    #[test]
+    fn prunes_commands_and_orbs_nothing_references() {
+        let mut config: Mapping = serde_yaml::from_str(
+            r#"
+orbs:
+  continuation: circleci/continuation@1.0.0
+  node: circleci/node@5.0.0
+  slack: circleci/slack@4.12.5
+commands:
+  install:
+    steps:
+      - node/install-packages
+  nested:
+    steps:
+      - install
+  with_retry:
+    parameters:
+      steps:
+        type: steps
+        default:
+          - defaulted
+    steps:
+      - << parameters.steps >>
+  passed:
+    steps:
+      - run: echo passed
+  defaulted:
+    steps:
+      - run: echo defaulted
+  unused:
+    steps:
+      - run: echo unused
+jobs:
+  test:
+    docker:
+      - image: cimg/node:20.11
+    steps:
+      - checkout
+      - when:
+          condition: true
+          steps:
+            - nested
+      - with_retry:
+          steps:
+            - passed
+workflows:
+  ci:
+    jobs:
+      - test
+"#,
+        )
+        .unwrap();
+
+        prune_unused_definitions(&mut config);
+        let commands: Vec<_> = config["commands"]
+            .as_mapping()
+            .unwrap()
+            .keys()
+            .filter_map(Value::as_str)
+            .collect();
+        assert_eq!(
+            commands,
+            vec!["install", "nested", "with_retry", "passed", "defaulted"]
+        );
+        let orbs: Vec<_> = config["orbs"]
+            .as_mapping()
+            .unwrap()
+            .keys()
+            .filter_map(Value::as_str)
+            .collect();
+        assert_eq!(orbs, vec!["node"]);
+    }
+}
@@ -0,0 +1,92 @@
+use serde_yaml::Value;
+use std::collections::HashMap;
+
+/// Architecture used when a job does not declare one
+pub const DEFAULT_ARCHITECTURE: &str = "amd64";
+
+/// Logical resource class names mapped to concrete CircleCI classes per architecture.
+///
+/// Configured at the root of the config:
+///
+/// ```yaml
+/// resource_classes:
+///   large:
+///     amd64: large
+///     arm64: arm.large
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ResourceClassMap {
+    aliases: HashMap<String, HashMap<String, String>>,
+}
+
+impl ResourceClassMap {
+    pub fn from_raw_config(raw_config: &Value) -> Self {
+        let mut aliases = HashMap::new();
+
+        if let Some(Value::Mapping(map)) = raw_config.get("resource_classes") {
+            for (name, per_arch) in map {
+                let (Some(name), Some(per_arch)) = (name.as_str(), per_arch.as_mapping()) else {
+                    continue;
+                };
+                let classes = per_arch
+                    .iter()
+                    .filter_map(|(arch, class)| {
+                        Some((arch.as_str()?.to_string(), class.as_str()?.to_string()))
+                    })
+                    .collect();
+                aliases.insert(name.to_string(), classes);
+            }
+        }
+
+        Self { aliases }
+    }
+
+    /// Resolve a resource class for an architecture. Unknown names and
+    /// architectures without a mapping are returned unchanged so raw
+    /// CircleCI classes keep working.
+    pub fn resolve(&self, name: &str, architecture: &str) -> String {
+        let architecture = if architecture.is_empty() {
+            DEFAULT_ARCHITECTURE
+        } else {
+            architecture
+        };
+        self.aliases
+            .get(name)
+            .and_then(|classes| classes.get(architecture))
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map() -> ResourceClassMap {
+        let raw: Value = serde_yaml::from_str(
+            r#"
+resource_classes:
+  large:
+    amd64: large
+    arm64: arm.large
+"#,
+        )
+        .unwrap();
+        ResourceClassMap::from_raw_config(&raw)
+    }
+
+    #[test]
+    fn resolves_alias_per_architecture() {
+        let classes = map();
+        assert_eq!(classes.resolve("large", "amd64"), "large");
+        assert_eq!(classes.resolve("large", "arm64"), "arm.large");
+        assert_eq!(classes.resolve("large", ""), "large");
+    }
+
+    #[test]
+    fn passes_through_unknown_names() {
+        let classes = map();
+        assert_eq!(classes.resolve("2xlarge+", "amd64"), "2xlarge+");
+        assert_eq!(classes.resolve("arm.medium", "arm64"), "arm.medium");
+    }
+}
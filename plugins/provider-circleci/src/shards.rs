@@ -0,0 +1,139 @@
+//! Continued config split into shards
+//!
+//! With `cigen generate --shard-count N`, the jobs are spread over
+//! `.circleci/main_1.yml` ... `.circleci/main_N.yml` (in `output_path`). CircleCI continues a
+//! pipeline only once, so the setup job continues with one shard, picked by
+//! the `cigen_shard` pipeline parameter. A pipeline without it runs shard 1 and
+//! starts a pipeline for each other shard through the CircleCI API, which
+//! needs a `CIRCLE_TOKEN` in the setup job's environment.
+
+use anyhow::{Context, Result};
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::JobVariant;
+use crate::output::{ConfigFile, OutputOptions};
+
+/// Pipeline parameter naming the shard a pipeline runs (0 runs them all)
+pub const SHARD_PARAMETER: &str = "cigen_shard";
+
+/// Requested shard count from the plan flags, when sharding
+pub fn shard_count(flags: &HashMap<String, String>) -> Result<Option<u32>> {
+    flags
+        .get(cigen::orchestrator::SHARD_COUNT_FLAG)
+        .map(|count| {
+            count
+                .parse()
+                .with_context(|| format!("Invalid shard count '{count}'"))
+        })
+        .transpose()
+}
+
+/// Each shard's workflows, holding only the shard's jobs. Shards without jobs
+/// are left out.
+pub fn split_workflows<'a>(
+    workflows: &BTreeMap<String, Vec<JobVariant<'a>>>,
+) -> BTreeMap<u32, BTreeMap<String, Vec<JobVariant<'a>>>> {
+    let mut shards: BTreeMap<u32, BTreeMap<String, Vec<JobVariant<'a>>>> = BTreeMap::new();
+    for (workflow_id, variants) in workflows {
+        for variant in variants {
+            shards
+                .entry(variant.job.shard)
+                .or_default()
+                .entry(workflow_id.clone())
+                .or_default()
+                .push(variant.clone());
+        }
+    }
+    shards
+}
+
+/// Integer pipeline parameter selecting the shard
+pub fn shard_parameter() -> Value {
+    let mut def = Mapping::new();
+    def.insert(
+        Value::String("type".into()),
+        Value::String("integer".into()),
+    );
+    def.insert(Value::String("default".into()), Value::Number(0.into()));
+    def.insert(
+        Value::String("description".into()),
+        Value::String(
+            "Config shard to run; 0 runs shard 1 and starts a pipeline for each other shard".into(),
+        ),
+    );
+    Value::Mapping(def)
+}
+
+/// Setup step that starts the other shards' pipelines when needed and copies
+/// the selected shard to the main config path for the continuation step
+pub fn select_shard_step(output: &OutputOptions) -> Value {
+    let configuration_path = output.written_path(ConfigFile::Main);
+    let first = output.written_path(ConfigFile::Shard("1"));
+    let shards = output.written_path(ConfigFile::Shard("*"));
+    let selected = output.written_path(ConfigFile::Shard("$shard"));
+    let command = format!(
+        r#"set -euo pipefail
+shard=<< pipeline.parameters.{SHARD_PARAMETER} >>
+if [ "$shard" = "0" ]; then
+  shard=1
+  for file in {shards}; do
+    [ "$file" = "{first}" ] && continue
+    [ -e "$file" ] || continue
+    : "${{CIRCLE_TOKEN:?Set CIRCLE_TOKEN so the setup job can start the other config shards}}"
+    n="${{file##*_}}"
+    n="${{n%.yml}}"
+    if [ -n "${{CIRCLE_TAG:-}}" ]; then
+      ref="\"tag\": \"$CIRCLE_TAG\""
+    else
+      ref="\"branch\": \"$CIRCLE_BRANCH\""
+    fi
+    echo "Starting a pipeline for config shard $n"
+    curl -fsS -X POST \
+      -H "Circle-Token: $CIRCLE_TOKEN" \
+      -H "Content-Type: application/json" \
+      --data "{{$ref, \"parameters\": {{\"{SHARD_PARAMETER}\": $n}}}}" \
+      "https://circleci.com/api/v2/project/gh/$CIRCLE_PROJECT_USERNAME/$CIRCLE_PROJECT_REPONAME/pipeline"
+  done
+fi
+cp "{selected}" "{configuration_path}"
+"#
+    );
+
+    let mut run_map = Mapping::new();
+    run_map.insert(
+        Value::String("name".into()),
+        Value::String("Select config shard".into()),
+    );
+    run_map.insert(Value::String("command".into()), Value::String(command));
+    let mut wrapper = Mapping::new();
+    wrapper.insert(Value::String("run".into()), Value::Mapping(run_map));
+    Value::Mapping(wrapper)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_step_continues_with_the_chosen_shard() {
+        let step = select_shard_step(&OutputOptions::default());
+        let command = step["run"]["command"].as_str().unwrap();
+        assert!(command.contains("shard=<< pipeline.parameters.cigen_shard >>"));
+        assert!(command.contains("for file in .circleci/main_*.yml; do"));
+        assert!(command.contains(r#"[ "$file" = ".circleci/main_1.yml" ] && continue"#));
+        assert!(command.contains(r#"--data "{$ref, \"parameters\": {\"cigen_shard\": $n}}""#));
+        assert!(command.ends_with("cp \".circleci/main_$shard.yml\" \".circleci/main.yml\"\n"));
+
+        let output = OutputOptions {
+            output_dir: Some("out".to_string()),
+            ..Default::default()
+        };
+        let step = select_shard_step(&output);
+        let command = step["run"]["command"].as_str().unwrap();
+        assert!(command.contains("for file in out/.circleci/main_*.yml; do"));
+        assert!(
+            command.ends_with("cp \"out/.circleci/main_$shard.yml\" \"out/.circleci/main.yml\"\n")
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Size limits for generated configs
+//!
+//! CircleCI rejects a config over about 3 MB, and the continuation orb posts
+//! continued configs as a string inside a JSON payload, which has a limit of
+//! its own. The YAML grows when it is escaped into JSON, so a continued config
+//! is measured as that payload. Oversized files fail generation instead of the
+//! pipeline, naming the jobs that take the most space.
+
+use anyhow::{Context, Result, bail};
+use serde_yaml::Value;
+use std::collections::HashMap;
+
+/// Plan flag overriding both limits, from `--max-config-size`
+pub const MAX_CONFIG_SIZE_FLAG: &str = "max_config_size";
+
+/// Largest config CircleCI accepts
+pub const CONFIG_SIZE_LIMIT: u64 = 3 * 1024 * 1024;
+
+/// Largest continuation payload CircleCI accepts
+pub const CONTINUATION_SIZE_LIMIT: u64 = 3 * 1024 * 1024;
+
+/// How many jobs an oversized config error lists
+const LARGEST_JOBS: usize = 5;
+
+/// The limits a generation run checks against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeLimits {
+    pub config: u64,
+    pub continuation: u64,
+}
+
+impl SizeLimits {
+    /// CircleCI's limits, or `--max-config-size` for both
+    pub fn from_flags(flags: &HashMap<String, String>) -> Result<Self> {
+        match flags.get(MAX_CONFIG_SIZE_FLAG) {
+            Some(size) => {
+                let size = size
+                    .parse()
+                    .with_context(|| format!("Invalid max config size '{size}'"))?;
+                Ok(Self {
+                    config: size,
+                    continuation: size,
+                })
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// The `--max-config-size` to pass on when the limits were overridden
+    pub fn override_flag(&self) -> Option<String> {
+        (*self != Self::default()).then(|| format!("--max-config-size {}", self.config))
+    }
+
+    /// Fail when `yaml`, written to `path`, is over its limit. `continued`
+    /// configs are measured as the continuation payload.
+    pub fn check(&self, path: &str, yaml: &str, config: &Value, continued: bool) -> Result<()> {
+        let (size, limit, what) = if continued {
+            (
+                continuation_payload_size(yaml)?,
+                self.continuation,
+                "continuation payload",
+            )
+        } else {
+            (yaml.len() as u64, self.config, "config")
+        };
+        if size <= limit {
+            return Ok(());
+        }
+
+        let jobs = largest_jobs(config)?
+            .into_iter()
+            .map(|(name, size)| format!("  {name}: {}", format_size(size)))
+            .collect::<Vec<_>>()
+            .join("\n");
+        bail!(
+            "{path} is too large: its {what} is {} ({size} bytes), over the limit of {} ({limit} bytes). Split it with --shard-count or output.per_workflow, trim the largest jobs, or raise the limit with --max-config-size. Largest jobs:\n{jobs}",
+            format_size(size),
+            format_size(limit)
+        )
+    }
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        Self {
+            config: CONFIG_SIZE_LIMIT,
+            continuation: CONTINUATION_SIZE_LIMIT,
+        }
+    }
+}
+
+/// Bytes the continuation orb sends for `yaml`: the config as a JSON string
+/// plus the rest of the request body
+fn continuation_payload_size(yaml: &str) -> Result<u64> {
+    let payload = serde_json::json!({
+        "continuation-key": "",
+        "configuration": yaml,
+        "parameters": {},
+    });
+    Ok(serde_json::to_string(&payload)?.len() as u64)
+}
+
+/// Jobs by serialized size, largest first
+fn largest_jobs(config: &Value) -> Result<Vec<(String, u64)>> {
+    let mut sizes = Vec::new();
+    if let Some(jobs) = config.get("jobs").and_then(Value::as_mapping) {
+        for (name, job) in jobs {
+            let name = name.as_str().unwrap_or_default().to_string();
+            sizes.push((name, serde_yaml::to_string(job)?.len() as u64));
+        }
+    }
+    sizes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    sizes.truncate(LARGEST_JOBS);
+    Ok(sizes)
+}
+
+fn format_size(bytes: u64) -> String {
+    const KIB: f64 = 1024.0;
+    let bytes_f = bytes as f64;
+    if bytes_f >= KIB * KIB {
+        format!("{:.1} MiB", bytes_f / (KIB * KIB))
+    } else if bytes_f >= KIB {
+        format!("{:.1} KiB", bytes_f / KIB)
+    } else {
+        format!("{bytes} B")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_with_jobs(sizes: &[(&str, usize)]) -> (Value, String) {
+        let jobs: serde_yaml::Mapping = sizes
+            .iter()
+            .map(|(name, size)| {
+                let job: Value = serde_yaml::from_str(&format!(
+                    "docker: [{{image: cimg/base:stable}}]\nsteps:\n- run: echo {}\n",
+                    "x".repeat(*size)
+                ))
+                .unwrap();
+                (Value::String(name.to_string()), job)
+            })
+            .collect();
+        let mut root = serde_yaml::Mapping::new();
+        root.insert(Value::String("jobs".into()), Value::Mapping(jobs));
+        let config = Value::Mapping(root);
+        let yaml = serde_yaml::to_string(&config).unwrap();
+        (config, yaml)
+    }
+
+    #[test]
+    fn oversized_config_names_the_largest_jobs() {
+        let (config, yaml) = config_with_jobs(&[
+            ("small", 10),
+            ("huge", 4000),
+            ("medium", 1000),
+            ("large", 2000),
+            ("tiny", 1),
+            ("mini", 5),
+        ]);
+        let limits = SizeLimits {
+            config: 4096,
+            continuation: 4096,
+        };
+
+        let error = limits
+            .check(".circleci/config.yml", &yaml, &config, false)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with(&format!(
+                ".circleci/config.yml is too large: its config is 7.3 KiB ({} bytes), over the limit of 4.0 KiB (4096 bytes).",
+                yaml.len()
+            )),
+            "{error}"
+        );
+        let listed: Vec<&str> = error
+            .lines()
+            .skip(1)
+            .map(|line| line.trim().split(':').next().unwrap())
+            .collect();
+        assert_eq!(listed, ["huge", "large", "medium", "small", "mini"]);
+    }
+
+    #[test]
+    fn continued_configs_are_measured_as_json() {
+        // Quotes and newlines are escaped in the payload, so it outgrows the YAML
+        let (config, yaml) = config_with_jobs(&[("quoted", 0)]);
+        let yaml = yaml.replace("echo ", "echo \"\"\"\"\"\"");
+        let limits = SizeLimits {
+            config: yaml.len() as u64,
+            continuation: yaml.len() as u64,
+        };
+
+        limits
+            .check(".circleci/config.yml", &yaml, &config, false)
+            .unwrap();
+        let error = limits
+            .check(".circleci/main.yml", &yaml, &config, true)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("its continuation payload is"), "{error}");
+    }
+
+    #[test]
+    fn max_config_size_flag_overrides_both_limits() {
+        let flags = HashMap::from([(MAX_CONFIG_SIZE_FLAG.to_string(), "5000000".to_string())]);
+        let limits = SizeLimits::from_flags(&flags).unwrap();
+        assert_eq!(limits.config, 5_000_000);
+        assert_eq!(limits.continuation, 5_000_000);
+        assert_eq!(
+            limits.override_flag().as_deref(),
+            Some("--max-config-size 5000000")
+        );
+        assert_eq!(SizeLimits::default().override_flag(), None);
+    }
+}
@@ -0,0 +1,558 @@
+//! Structural validation of generated CircleCI 2.1 configs
+//!
+//! Catches configs that CircleCI would reject before they are written: missing
+//! required keys, malformed steps, `requires` entries that name jobs outside the
+//! workflow, and references to undeclared commands, executors, or orbs. Runs on
+//! every generation; the `circleci` CLI is only used with `--validate-with-cli`.
+
+use cigen::schema::steps_parameter_reference;
+use miette::Diagnostic;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashSet;
+
+/// Steps CircleCI provides without a `commands:` declaration
+const BUILTIN_STEPS: &[&str] = &[
+    "run",
+    "checkout",
+    "setup_remote_docker",
+    "save_cache",
+    "restore_cache",
+    "store_artifacts",
+    "store_test_results",
+    "persist_to_workspace",
+    "attach_workspace",
+    "add_ssh_keys",
+    "deploy",
+    "when",
+    "unless",
+];
+
+/// Values of a built-in step's `when:`
+const STEP_WHEN: &[&str] = &["always", "on_success", "on_fail"];
+
+/// Keys that select a job's executor
+const EXECUTOR_KEYS: &[&str] = &["docker", "machine", "macos", "executor"];
+
+/// A problem in a generated config, located by file and JSON pointer
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, Diagnostic)]
+#[error("{path} at {pointer}: {message}")]
+#[diagnostic(code(circleci::invalid_config))]
+pub struct ValidationError {
+    /// Generated file, e.g. `.circleci/main.yml`
+    pub path: String,
+    /// JSON pointer into the file, e.g. `/workflows/ci/jobs/1/test/requires/0`
+    pub pointer: String,
+    pub message: String,
+}
+
+/// Validate a generated CircleCI config, returning every problem found
+pub fn validate_config(path: &str, config: &Value) -> Vec<ValidationError> {
+    let mut validator = Validator {
+        path,
+        errors: Vec::new(),
+        commands: declared_names(config, "commands"),
+        executors: declared_names(config, "executors"),
+        orbs: declared_names(config, "orbs"),
+    };
+    validator.validate_root(config);
+    validator.errors
+}
+
+fn declared_names(config: &Value, section: &str) -> HashSet<String> {
+    config
+        .get(section)
+        .and_then(Value::as_mapping)
+        .map(|map| {
+            map.keys()
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+struct Validator<'a> {
+    path: &'a str,
+    errors: Vec<ValidationError>,
+    commands: HashSet<String>,
+    executors: HashSet<String>,
+    orbs: HashSet<String>,
+}
+
+impl Validator<'_> {
+    fn error(&mut self, pointer: &str, message: impl Into<String>) {
+        self.errors.push(ValidationError {
+            path: self.path.to_string(),
+            pointer: if pointer.is_empty() {
+                "/".to_string()
+            } else {
+                pointer.to_string()
+            },
+            message: message.into(),
+        });
+    }
+
+    fn validate_root(&mut self, config: &Value) {
+        let Some(root) = config.as_mapping() else {
+            self.error("", "config must be a mapping");
+            return;
+        };
+
+        match root.get("version") {
+            Some(Value::String(version)) if version == "2.1" => {}
+            Some(Value::Number(version)) if version.as_f64() == Some(2.1) => {}
+            Some(other) => self.error("/version", format!("expected 2.1, found {}", show(other))),
+            None => self.error("", "missing required key 'version'"),
+        }
+
+        if let Some(commands) = root.get("commands").and_then(Value::as_mapping) {
+            for (name, command) in commands {
+                let name = key(name);
+                let pointer = format!("/commands/{}", escape(&name));
+                match command.get("steps") {
+                    Some(steps) => self.validate_steps(&format!("{pointer}/steps"), steps),
+                    None => self.error(&pointer, "missing required key 'steps'"),
+                }
+            }
+        }
+
+        let jobs: HashSet<String> = declared_names(config, "jobs");
+        match root.get("jobs").and_then(Value::as_mapping) {
+            Some(job_map) => {
+                for (name, job) in job_map {
+                    self.validate_job(&format!("/jobs/{}", escape(&key(name))), job);
+                }
+            }
+            None => self.error("", "missing required key 'jobs'"),
+        }
+
+        match root.get("workflows").and_then(Value::as_mapping) {
+            Some(workflows) => {
+                for (name, workflow) in workflows {
+                    let name = key(name);
+                    // Legacy `workflows: { version: 2 }` marker
+                    if name == "version" {
+                        continue;
+                    }
+                    self.validate_workflow(
+                        &format!("/workflows/{}", escape(&name)),
+                        workflow,
+                        &jobs,
+                    );
+                }
+            }
+            None => self.error("", "missing required key 'workflows'"),
+        }
+    }
+
+    fn validate_job(&mut self, pointer: &str, job: &Value) {
+        let Some(job) = job.as_mapping() else {
+            self.error(pointer, "job must be a mapping");
+            return;
+        };
+
+        if !EXECUTOR_KEYS
+            .iter()
+            .any(|executor| job.contains_key(*executor))
+        {
+            self.error(
+                pointer,
+                "job has no executor; set one of docker, machine, macos, or executor",
+            );
+        }
+        if let Some(executor) = job.get("executor") {
+            let name = match executor {
+                Value::Mapping(map) => map.get("name").and_then(Value::as_str),
+                other => other.as_str(),
+            };
+            if let Some(name) = name
+                && !self.is_declared(name, &self.executors)
+            {
+                self.error(
+                    &format!("{pointer}/executor"),
+                    format!("executor '{name}' is not declared under executors"),
+                );
+            }
+        }
+
+        match job.get("steps") {
+            Some(steps) => self.validate_steps(&format!("{pointer}/steps"), steps),
+            None => self.error(pointer, "missing required key 'steps'"),
+        }
+    }
+
+    fn validate_steps(&mut self, pointer: &str, steps: &Value) {
+        let Some(steps) = steps.as_sequence() else {
+            self.error(pointer, "steps must be a list");
+            return;
+        };
+        for (index, step) in steps.iter().enumerate() {
+            self.validate_step(&format!("{pointer}/{index}"), step);
+        }
+    }
+
+    fn validate_step(&mut self, pointer: &str, step: &Value) {
+        let (name, body) = match step {
+            Value::String(name) => (name.as_str(), None),
+            Value::Mapping(map) if map.len() == 1 => {
+                let (name, body) = map.iter().next().expect("mapping has one entry");
+                let Some(name) = name.as_str() else {
+                    self.error(pointer, "step name must be a string");
+                    return;
+                };
+                (name, Some(body))
+            }
+            Value::Mapping(_) => {
+                self.error(pointer, "step must be a mapping with exactly one key");
+                return;
+            }
+            other => {
+                self.error(pointer, format!("invalid step {}", show(other)));
+                return;
+            }
+        };
+        // `- << parameters.name >>` splices a command's `steps` parameter in
+        if body.is_none() && steps_parameter_reference(name).is_some() {
+            return;
+        }
+        let body_pointer = format!("{pointer}/{}", escape(name));
+        if BUILTIN_STEPS.contains(&name)
+            && !matches!(name, "when" | "unless")
+            && let Some(when) = body
+                .and_then(|body| body.get("when"))
+                .and_then(Value::as_str)
+            && !STEP_WHEN.contains(&when)
+            && !when.trim_start().starts_with("<<")
+        {
+            self.error(
+                &format!("{body_pointer}/when"),
+                format!(
+                    "when must be one of {}, found '{when}'",
+                    STEP_WHEN.join(", ")
+                ),
+            );
+        }
+
+        match name {
+            "run" => match body {
+                Some(Value::String(_)) => {}
+                Some(Value::Mapping(run)) if run.contains_key("command") => {}
+                Some(Value::Mapping(_)) => {
+                    self.error(&body_pointer, "missing required key 'command'")
+                }
+                None => self.error(pointer, "run step needs a command"),
+                Some(other) => self.error(
+                    &body_pointer,
+                    format!("run must be a string or mapping, found {}", show(other)),
+                ),
+            },
+            "when" | "unless" => match body.and_then(Value::as_mapping) {
+                Some(condition) => {
+                    if !condition.contains_key("condition") {
+                        self.error(&body_pointer, "missing required key 'condition'");
+                    }
+                    match condition.get("steps") {
+                        Some(steps) => self.validate_steps(&format!("{body_pointer}/steps"), steps),
+                        None => self.error(&body_pointer, "missing required key 'steps'"),
+                    }
+                }
+                None => self.error(pointer, format!("{name} step needs condition and steps")),
+            },
+            _ if BUILTIN_STEPS.contains(&name) => {}
+            _ if !self.is_declared(name, &self.commands) => {
+                let message = if name.contains('/') {
+                    format!("orb '{}' is not declared under orbs", orb_name(name))
+                } else {
+                    format!("command '{name}' is not declared under commands")
+                };
+                self.error(pointer, message);
+            }
+            _ => {}
+        }
+    }
+
+    fn validate_workflow(&mut self, pointer: &str, workflow: &Value, jobs: &HashSet<String>) {
+        let Some(entries) = workflow.get("jobs").and_then(Value::as_sequence) else {
+            self.error(pointer, "missing required key 'jobs'");
+            return;
+        };
+
+        // Names other jobs can require: the job name, or its `name:` override
+        let mut members = HashSet::new();
+        let mut parsed = Vec::new();
+        for (index, entry) in entries.iter().enumerate() {
+            let entry_pointer = format!("{pointer}/jobs/{index}");
+            let (job, config) = match entry {
+                Value::String(job) => (job.as_str(), None),
+                Value::Mapping(map) if map.len() == 1 => {
+                    let (job, config) = map.iter().next().expect("mapping has one entry");
+                    match job.as_str() {
+                        Some(job) => (job, config.as_mapping()),
+                        None => {
+                            self.error(&entry_pointer, "workflow job name must be a string");
+                            continue;
+                        }
+                    }
+                }
+                _ => {
+                    self.error(
+                        &entry_pointer,
+                        "workflow job must be a job name or a mapping with exactly one key",
+                    );
+                    continue;
+                }
+            };
+
+            let is_approval = config
+                .and_then(|config| config.get("type"))
+                .and_then(Value::as_str)
+                == Some("approval");
+            if !is_approval && !self.is_declared(job, jobs) {
+                let message = if job.contains('/') {
+                    format!("orb '{}' is not declared under orbs", orb_name(job))
+                } else {
+                    format!("job '{job}' is not declared under jobs")
+                };
+                self.error(&entry_pointer, message);
+            }
+
+            let alias = config
+                .and_then(|config| config.get("name"))
+                .and_then(Value::as_str)
+                .unwrap_or(job);
+            members.insert(alias.to_string());
+            parsed.push((format!("{entry_pointer}/{}", escape(job)), config));
+        }
+
+        for (entry_pointer, config) in parsed {
+            let Some(requires) = config.and_then(|config: &Mapping| config.get("requires")) else {
+                continue;
+            };
+            let Some(requires) = requires.as_sequence() else {
+                self.error(
+                    &format!("{entry_pointer}/requires"),
+                    "requires must be a list",
+                );
+                continue;
+            };
+            for (index, required) in requires.iter().enumerate() {
+                // `- job: [success, failed]` status requirements
+                let name = match required {
+                    Value::Mapping(map) => map.keys().next().and_then(Value::as_str),
+                    other => other.as_str(),
+                };
+                if let Some(name) = name
+                    && !members.contains(name)
+                {
+                    self.error(
+                        &format!("{entry_pointer}/requires/{index}"),
+                        format!("requires '{name}', which is not a job in this workflow"),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Locally declared name, or an `orb/name` reference to a declared orb
+    fn is_declared(&self, name: &str, local: &HashSet<String>) -> bool {
+        local.contains(name) || (name.contains('/') && self.orbs.contains(orb_name(name)))
+    }
+}
+
+fn orb_name(reference: &str) -> &str {
+    reference.split('/').next().unwrap_or(reference)
+}
+
+fn key(value: &Value) -> String {
+    value
+        .as_str()
+        .map(String::from)
+        .unwrap_or_else(|| show(value))
+}
+
+fn show(value: &Value) -> String {
+    serde_yaml::to_string(value)
+        .map(|text| text.trim_end().to_string())
+        .unwrap_or_default()
+}
+
+/// Escape a JSON pointer segment (RFC 6901)
+fn escape(segment: &str) -> String {
+    segment.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(yaml: &str) -> Vec<String> {
+        let config: Value = serde_yaml::from_str(yaml).unwrap();
+        validate_config(".circleci/main.yml", &config)
+            .into_iter()
+            .map(|error| error.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_config_passes() {
+        let errors = validate(
+            r#"
+version: "2.1"
+orbs:
+  node: circleci/node@5.0.0
+commands:
+  setup:
+    parameters:
+      after:
+        type: steps
+        default: []
+    steps:
+      - checkout
+      - << parameters.after >>
+executors:
+  ruby:
+    docker:
+      - image: cimg/ruby:3.3
+jobs:
+  build:
+    docker:
+      - image: cimg/base:stable
+    steps:
+      - setup
+      - node/install-packages
+      - run: make
+      - run:
+          name: Test
+          command: make test
+      - when:
+          condition: true
+          steps:
+            - run: echo ok
+  test:
+    executor: ruby
+    steps:
+      - run: rspec
+workflows:
+  ci:
+    jobs:
+      - build
+      - hold:
+          type: approval
+          requires: [build]
+      - test:
+          name: test-ruby
+          requires:
+            - hold
+      - node/test:
+          requires:
+            - test-ruby
+"#,
+        );
+        assert!(errors.is_empty(), "{errors:#?}");
+    }
+
+    #[test]
+    fn test_step_when_must_be_a_circleci_value() {
+        let errors = validate(
+            r#"
+version: "2.1"
+jobs:
+  test:
+    docker:
+      - image: cimg/base:stable
+    steps:
+      - run:
+          command: make test
+          when: always
+      - save_cache:
+          key: gems
+          paths: [vendor/bundle]
+          when: on_failure
+workflows:
+  ci:
+    jobs: [test]
+"#,
+        );
+        assert_eq!(
+            errors,
+            vec![
+                ".circleci/main.yml at /jobs/test/steps/1/save_cache/when: when must be one of always, on_success, on_fail, found 'on_failure'"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_requires_unknown_job_is_rejected() {
+        let errors = validate(
+            r#"
+version: "2.1"
+jobs:
+  test:
+    docker:
+      - image: cimg/base:stable
+    steps:
+      - run: make test
+workflows:
+  ci:
+    jobs:
+      - test:
+          requires:
+            - build
+"#,
+        );
+        assert_eq!(
+            errors,
+            vec![
+                ".circleci/main.yml at /workflows/ci/jobs/0/test/requires/0: requires 'build', which is not a job in this workflow"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_undeclared_references_are_rejected() {
+        let errors = validate(
+            r#"
+version: "2.1"
+jobs:
+  test:
+    executor: ruby
+    steps:
+      - cigen_missing_command
+      - slack/notify
+      - run:
+          name: no command
+      - checkout: {}
+        run: make
+workflows:
+  ci:
+    jobs:
+      - test
+      - deploy
+"#,
+        );
+        assert_eq!(
+            errors,
+            vec![
+                ".circleci/main.yml at /jobs/test/executor: executor 'ruby' is not declared under executors",
+                ".circleci/main.yml at /jobs/test/steps/0: command 'cigen_missing_command' is not declared under commands",
+                ".circleci/main.yml at /jobs/test/steps/1: orb 'slack' is not declared under orbs",
+                ".circleci/main.yml at /jobs/test/steps/2/run: missing required key 'command'",
+                ".circleci/main.yml at /jobs/test/steps/3: step must be a mapping with exactly one key",
+                ".circleci/main.yml at /workflows/ci/jobs/1: job 'deploy' is not declared under jobs",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_required_keys_are_reported() {
+        let errors = validate("version: 2\njobs:\n  test:\n    steps: []\n");
+        assert_eq!(
+            errors,
+            vec![
+                ".circleci/main.yml at /version: expected 2.1, found 2",
+                ".circleci/main.yml at /jobs/test: job has no executor; set one of docker, machine, macos, or executor",
+                ".circleci/main.yml at /: missing required key 'workflows'",
+            ]
+        );
+    }
+}
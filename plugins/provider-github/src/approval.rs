@@ -0,0 +1,112 @@
+//! Approval jobs for GitHub Actions
+//!
+//! GitHub has no approval job type. An approval job becomes a no-op job that
+//! deploys to an environment named after it; giving that environment required
+//! reviewers (Settings → Environments) makes the job wait for an approval, and
+//! jobs that need it wait with it. Generated workflows explain this in a
+//! comment above each approval job.
+
+use cigen::plugin::protocol::JobDefinition;
+use serde_yaml::{Mapping, Value};
+
+/// Runner for the approval job, which only echoes a line
+const APPROVAL_RUNNER: &str = "ubuntu-latest";
+
+/// The job that stands in for an approval: `environment: <job id>` plus a
+/// single step. `extra` is the job's pass-through fields, minus `type`.
+pub fn render_approval_job(job: &JobDefinition, extra: Mapping) -> Mapping {
+    let mut job_map = extra;
+    job_map.remove(Value::String("type".into()));
+
+    let runs_on_key = Value::String("runs-on".into());
+    if !job_map.contains_key(&runs_on_key) {
+        job_map.insert(runs_on_key, Value::String(APPROVAL_RUNNER.into()));
+    }
+    let environment_key = Value::String("environment".into());
+    if !job_map.contains_key(&environment_key) {
+        job_map.insert(environment_key, Value::String(job.id.clone()));
+    }
+    if !job.needs.is_empty() {
+        job_map.insert(
+            Value::String("needs".into()),
+            Value::Sequence(job.needs.iter().cloned().map(Value::String).collect()),
+        );
+    }
+
+    let mut step = Mapping::new();
+    step.insert(
+        Value::String("name".into()),
+        Value::String("Approved".into()),
+    );
+    step.insert(
+        Value::String("run".into()),
+        Value::String(format!(
+            "echo \"Approved through the {} environment\"",
+            job.id
+        )),
+    );
+    job_map.insert(
+        Value::String("steps".into()),
+        Value::Sequence(vec![Value::Mapping(step)]),
+    );
+    job_map
+}
+
+/// Insert a comment above each approval job in a rendered workflow, saying
+/// which environment needs required reviewers
+pub fn annotate_approval_jobs(rendered: &str, jobs: &[JobDefinition]) -> String {
+    let approvals: Vec<&str> = jobs
+        .iter()
+        .filter(|job| job.is_approval())
+        .map(|job| job.id.as_str())
+        .collect();
+    if approvals.is_empty() {
+        return rendered.to_string();
+    }
+
+    let mut output = String::with_capacity(rendered.len());
+    let mut in_jobs = false;
+    for line in rendered.split_inclusive('\n') {
+        if !line.starts_with(' ') {
+            in_jobs = line.trim_end() == "jobs:";
+        } else if in_jobs
+            && let Some(id) = line
+                .strip_prefix("  ")
+                .and_then(|rest| rest.trim_end().strip_suffix(':'))
+            && approvals.contains(&id)
+        {
+            output.push_str(&format!(
+                "  # Approval job: waits for a reviewer to approve the '{id}' environment.\n  # Add required reviewers to it under Settings → Environments.\n"
+            ));
+        }
+        output.push_str(line);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn comment_lands_above_the_approval_job_only() {
+        let jobs = vec![
+            JobDefinition {
+                id: "hold".to_string(),
+                extra: HashMap::from([("type".to_string(), "approval".to_string())]),
+                ..Default::default()
+            },
+            JobDefinition {
+                id: "release".to_string(),
+                ..Default::default()
+            },
+        ];
+        let rendered = "name: DEPLOY\njobs:\n  hold:\n    environment: hold\n  release:\n    needs:\n    - hold\n";
+
+        assert_eq!(
+            annotate_approval_jobs(rendered, &jobs),
+            "name: DEPLOY\njobs:\n  # Approval job: waits for a reviewer to approve the 'hold' environment.\n  # Add required reviewers to it under Settings → Environments.\n  hold:\n    environment: hold\n  release:\n    needs:\n    - hold\n"
+        );
+    }
+}
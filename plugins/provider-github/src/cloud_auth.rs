@@ -0,0 +1,104 @@
+//! `cloud_auth` for GitHub Actions
+//!
+//! Each cloud's official action exchanges the job's OIDC token for
+//! short-lived credentials. Requesting that token needs `id-token: write`, so
+//! the job gets a `permissions:` block; as listing any permission revokes the
+//! unlisted ones, a generated block also keeps `contents: read` for checkout.
+
+use anyhow::{Result, bail};
+use cigen::plugin::protocol::{CloudAuth, JobDefinition};
+use serde_yaml::{Mapping, Value};
+
+const CONFIGURE_AWS_CREDENTIALS_ACTION: &str = "aws-actions/configure-aws-credentials@v4";
+const GOOGLE_AUTH_ACTION: &str = "google-github-actions/auth@v2";
+
+/// `aws-region` when `cloud_auth.aws.region` is unset
+const DEFAULT_AWS_REGION: &str = "${{ env.AWS_DEFAULT_REGION }}";
+
+/// Steps that sign the job in to each configured cloud
+pub fn cloud_auth_steps(cloud_auth: &CloudAuth) -> Vec<Mapping> {
+    let mut steps = Vec::new();
+    if let Some(aws) = &cloud_auth.aws {
+        let region = if aws.region.is_empty() {
+            DEFAULT_AWS_REGION
+        } else {
+            &aws.region
+        };
+        steps.push(action_step(
+            "Configure AWS credentials",
+            CONFIGURE_AWS_CREDENTIALS_ACTION,
+            [
+                ("role-to-assume", aws.role_arn.as_str()),
+                ("aws-region", region),
+            ],
+        ));
+    }
+    if let Some(gcp) = &cloud_auth.gcp {
+        let mut step = action_step(
+            "Authenticate to Google Cloud",
+            GOOGLE_AUTH_ACTION,
+            [
+                (
+                    "workload_identity_provider",
+                    gcp.workload_identity_provider.as_str(),
+                ),
+                ("service_account", gcp.service_account.as_str()),
+            ],
+        );
+        if !gcp.project_id.is_empty()
+            && let Some(Value::Mapping(with)) = step.get_mut("with")
+        {
+            with.insert(
+                Value::String("project_id".into()),
+                Value::String(gcp.project_id.clone()),
+            );
+        }
+        steps.push(step);
+    }
+    steps
+}
+
+fn action_step<'a>(
+    name: &str,
+    action: &str,
+    inputs: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Mapping {
+    let mut with = Mapping::new();
+    for (key, value) in inputs {
+        with.insert(Value::String(key.into()), Value::String(value.into()));
+    }
+    let mut step = Mapping::new();
+    step.insert(Value::String("name".into()), Value::String(name.into()));
+    step.insert(Value::String("uses".into()), Value::String(action.into()));
+    step.insert(Value::String("with".into()), Value::Mapping(with));
+    step
+}
+
+/// Let a job with `cloud_auth` request an OIDC token, keeping any
+/// permissions the job already lists
+pub fn grant_id_token(job: &JobDefinition, job_map: &mut Mapping) -> Result<()> {
+    if job.cloud_auth.is_none() {
+        return Ok(());
+    }
+    let permissions = job_map
+        .entry(Value::String("permissions".into()))
+        .or_insert_with(|| {
+            let mut permissions = Mapping::new();
+            permissions.insert(
+                Value::String("contents".into()),
+                Value::String("read".into()),
+            );
+            Value::Mapping(permissions)
+        });
+    let Value::Mapping(permissions) = permissions else {
+        bail!(
+            "Job '{}' uses cloud_auth, which needs `id-token: write`; list the job's permissions as a mapping",
+            job.id
+        );
+    };
+    permissions.insert(
+        Value::String("id-token".into()),
+        Value::String("write".into()),
+    );
+    Ok(())
+}
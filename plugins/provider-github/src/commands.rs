@@ -0,0 +1,534 @@
+//! Job steps that invoke cigen commands
+//!
+//! Commands are inlined into every job that uses them by default. With
+//! `github_actions: {commands_as: composite-actions}`, each used command that a
+//! composite action can express is generated once under
+//! `.github/actions/<name>/action.yml` and invoked with `uses:`; the rest are
+//! still inlined, with an info diagnostic explaining why.
+
+use anyhow::{Context, Result, bail};
+use cigen::orchestrator::step_list_to_proto;
+use cigen::plugin::protocol::{
+    CigenSchema, CommandDefinition, CustomStep, Diagnostic, Fragment, MergeStrategy, Step,
+    UsesStep, diagnostic, step::StepType,
+};
+use cigen::schema::{
+    GITHUB_ACTION_SCHEMA_URL, STEPS_PARAMETER_TYPE, schema_comment, steps_parameter_reference,
+};
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::conditions::github_step_condition;
+use crate::render_step;
+
+/// Commands calling commands deeper than this are assumed to be recursive
+const MAX_COMMAND_DEPTH: usize = 16;
+
+/// How command invocations are rendered (`github_actions.commands_as`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommandsAs {
+    #[default]
+    Inline,
+    CompositeActions,
+}
+
+impl CommandsAs {
+    pub fn from_raw_config(raw_config: &Value) -> Result<Self> {
+        let Some(value) = raw_config
+            .get("github_actions")
+            .and_then(|options| options.get("commands_as"))
+        else {
+            return Ok(Self::Inline);
+        };
+        match value.as_str() {
+            Some("inline") => Ok(Self::Inline),
+            Some("composite-actions") => Ok(Self::CompositeActions),
+            _ => bail!(
+                "Invalid github_actions.commands_as '{}': expected 'inline' or 'composite-actions'",
+                scalar_string(value)
+            ),
+        }
+    }
+}
+
+/// Renders the command invocations found in job steps
+pub struct CommandSteps<'a> {
+    commands: &'a HashMap<String, CommandDefinition>,
+    /// Commands generated as composite actions
+    composite: BTreeSet<String>,
+}
+
+impl<'a> CommandSteps<'a> {
+    pub fn new(
+        schema: &'a CigenSchema,
+        mode: CommandsAs,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Self {
+        let mut command_steps = Self {
+            commands: &schema.commands,
+            composite: BTreeSet::new(),
+        };
+        if mode == CommandsAs::Inline {
+            return command_steps;
+        }
+
+        let mut used = BTreeSet::new();
+        for job in &schema.jobs {
+            command_steps.collect_used(&job.steps, &mut used, 0);
+        }
+        for name in used {
+            match command_steps.composite_blocker(&name, &mut Vec::new()) {
+                None => {
+                    command_steps.composite.insert(name);
+                }
+                Some(reason) => diagnostics.push(Diagnostic {
+                    level: diagnostic::Level::Info as i32,
+                    code: "GITHUB_COMMAND_INLINED".to_string(),
+                    title: "Command inlined instead of generated as a composite action".to_string(),
+                    message: format!("Command '{name}' is inlined because {reason}"),
+                    fix_hint: String::new(),
+                    loc: None,
+                }),
+            }
+        }
+        command_steps
+    }
+
+    /// Replace command invocations with the steps GitHub Actions should run
+    pub fn expand(&self, steps: &[Step]) -> Result<Vec<Step>> {
+        let mut expanded = Vec::new();
+        for step in steps {
+            self.expand_step(step, &mut expanded, 0)?;
+        }
+        Ok(expanded)
+    }
+
+    /// `action.yml` files for the commands generated as composite actions
+    pub fn action_fragments(&self) -> (Vec<Fragment>, Vec<Diagnostic>) {
+        let mut fragments = Vec::new();
+        let mut diagnostics = Vec::new();
+        for name in &self.composite {
+            match self.render_action(name, &self.commands[name]) {
+                Ok(content) => fragments.push(Fragment {
+                    path: format!(".github/actions/{name}/action.yml"),
+                    content,
+                    strategy: MergeStrategy::Replace as i32,
+                    order: 0,
+                    format: "yaml".to_string(),
+                }),
+                Err(error) => diagnostics.push(Diagnostic {
+                    level: diagnostic::Level::Error as i32,
+                    code: "GITHUB_GENERATE_ERROR".to_string(),
+                    title: format!("Failed to generate composite action '{name}'"),
+                    message: format!("{error:#}"),
+                    fix_hint: String::new(),
+                    loc: None,
+                }),
+            }
+        }
+        (fragments, diagnostics)
+    }
+
+    fn expand_step(&self, step: &Step, expanded: &mut Vec<Step>, depth: usize) -> Result<()> {
+        let Some(StepType::Custom(custom)) = &step.step_type else {
+            expanded.push(step.clone());
+            return Ok(());
+        };
+        let Some((name, command)) = self.commands.get_key_value(&custom.kind) else {
+            expanded.push(step.clone());
+            return Ok(());
+        };
+        if depth >= MAX_COMMAND_DEPTH {
+            bail!(
+                "Command '{name}' is nested more than {MAX_COMMAND_DEPTH} levels deep; does it call itself?"
+            );
+        }
+
+        let args = invocation_args(custom)?;
+        if self.composite.contains(name) {
+            // `with:` values travel YAML-encoded; encode the string form so
+            // `"3.3"` or a block scalar arrives as the same plain string
+            let with = args
+                .iter()
+                .map(|(key, value)| {
+                    let text = Value::String(scalar_string(value));
+                    Ok((scalar_string(key), serde_yaml::to_string(&text)?))
+                })
+                .collect::<Result<_>>()?;
+            expanded.push(Step {
+                step_type: Some(StepType::Uses(UsesStep {
+                    name: String::new(),
+                    module: action_dir(name),
+                    with,
+                    r#if: custom.r#if.clone(),
+                })),
+            });
+            return Ok(());
+        }
+
+        let values = resolve_parameters(name, command, &args)?;
+        let mut inner = Vec::new();
+        for step in &command.steps {
+            if let Some(parameter) = spliced_parameter(step) {
+                let steps = values
+                    .get(parameter)
+                    .and_then(|value| value.steps.as_ref())
+                    .with_context(|| {
+                        format!(
+                            "Command '{name}' splices in '{parameter}', which is not a steps parameter"
+                        )
+                    })?;
+                for step in steps {
+                    self.expand_step(step, &mut inner, depth + 1)?;
+                }
+                continue;
+            }
+            let step = substitute_step(step, |parameter| {
+                let value = values.get(parameter).with_context(|| {
+                    format!("Command '{name}' uses undeclared parameter '{parameter}'")
+                })?;
+                if value.steps.is_some() {
+                    bail!(
+                        "Command '{name}' uses steps parameter '{parameter}' inside a step; it can only be a step of its own"
+                    );
+                }
+                Ok(value.text.clone())
+            })?;
+            self.expand_step(&step, &mut inner, depth + 1)?;
+        }
+        for mut step in inner {
+            if !custom.r#if.is_empty() {
+                add_condition(&mut step, &custom.r#if)?;
+            }
+            expanded.push(step);
+        }
+        Ok(())
+    }
+
+    fn collect_used(&self, steps: &[Step], used: &mut BTreeSet<String>, depth: usize) {
+        for name in self.invoked_commands(steps) {
+            if used.insert(name.to_string()) && depth < MAX_COMMAND_DEPTH {
+                self.collect_used(&self.commands[name].steps, used, depth + 1);
+            }
+        }
+    }
+
+    fn invoked_commands<'s>(&'s self, steps: &'s [Step]) -> impl Iterator<Item = &'s str> {
+        steps.iter().filter_map(|step| match &step.step_type {
+            Some(StepType::Custom(custom)) if self.commands.contains_key(&custom.kind) => {
+                Some(custom.kind.as_str())
+            }
+            _ => None,
+        })
+    }
+
+    /// Why `name` can't be generated as a composite action, if it can't
+    fn composite_blocker(&self, name: &str, stack: &mut Vec<String>) -> Option<String> {
+        if stack.iter().any(|caller| caller == name) {
+            return Some("it calls itself recursively".to_string());
+        }
+        let command = &self.commands[name];
+
+        let parameters: BTreeMap<_, _> = command.parameters.iter().collect();
+        for (parameter, definition) in parameters {
+            if !matches!(definition.r#type.as_str(), "" | "string" | "boolean") {
+                return Some(format!(
+                    "parameter '{parameter}' has type '{}' and action inputs can only be strings or booleans",
+                    definition.r#type
+                ));
+            }
+        }
+
+        if command
+            .steps
+            .iter()
+            .any(|step| step_text(step).iter().any(|text| text.contains("secrets.")))
+        {
+            return Some(
+                "its steps read the `secrets` context, which composite actions cannot access"
+                    .to_string(),
+            );
+        }
+
+        stack.push(name.to_string());
+        let nested = self.invoked_commands(&command.steps).find_map(|nested| {
+            self.composite_blocker(nested, stack).map(|reason| {
+                format!("it calls command '{nested}', which is inlined because {reason}")
+            })
+        });
+        stack.pop();
+        nested
+    }
+
+    fn render_action(&self, name: &str, command: &CommandDefinition) -> Result<String> {
+        let mut inputs = Mapping::new();
+        let parameters: BTreeMap<_, _> = command.parameters.iter().collect();
+        for (parameter, definition) in parameters {
+            let mut input = Mapping::new();
+            let description = if definition.description.is_empty() {
+                parameter.clone()
+            } else {
+                definition.description.clone()
+            };
+            input.insert("description".into(), Value::String(description));
+            input.insert(
+                "required".into(),
+                Value::Bool(definition.default_yaml.is_empty()),
+            );
+            if !definition.default_yaml.is_empty() {
+                let default: Value = serde_yaml::from_str(&definition.default_yaml)
+                    .with_context(|| format!("Invalid default for parameter '{parameter}'"))?;
+                input.insert("default".into(), Value::String(scalar_string(&default)));
+            }
+            inputs.insert(Value::String(parameter.clone()), Value::Mapping(input));
+        }
+
+        let owner = format!("command '{name}'");
+        let mut steps = Vec::new();
+        for step in &command.steps {
+            let step = substitute_step(step, |parameter| {
+                if command.parameters.contains_key(parameter) {
+                    Ok(format!("${{{{ inputs.{parameter} }}}}"))
+                } else {
+                    bail!("Command '{name}' uses undeclared parameter '{parameter}'")
+                }
+            })?;
+            let mut expanded = Vec::new();
+            self.expand_step(&step, &mut expanded, 1)?;
+            for step in expanded {
+                if let Some(mut rendered) = render_step(&step, steps.len(), &owner)? {
+                    if rendered.contains_key("run") {
+                        rendered.insert("shell".into(), Value::String("bash".into()));
+                    }
+                    steps.push(Value::Mapping(rendered));
+                }
+            }
+        }
+
+        let mut runs = Mapping::new();
+        runs.insert("using".into(), Value::String("composite".into()));
+        runs.insert("steps".into(), Value::Sequence(steps));
+
+        let description = if command.description.is_empty() {
+            format!("cigen command {name}")
+        } else {
+            command.description.clone()
+        };
+        let mut action = Mapping::new();
+        action.insert("name".into(), Value::String(name.to_string()));
+        action.insert("description".into(), Value::String(description));
+        if !inputs.is_empty() {
+            action.insert("inputs".into(), Value::Mapping(inputs));
+        }
+        action.insert("runs".into(), Value::Mapping(runs));
+
+        let mut yaml = schema_comment(GITHUB_ACTION_SCHEMA_URL);
+        yaml.push_str(&format!("# Source: .cigen/commands/{name}.yml\n"));
+        yaml.push_str("#\n");
+        yaml.push_str(
+            &serde_yaml::to_string(&action)
+                .with_context(|| format!("Failed to serialize composite action {name}"))?,
+        );
+        Ok(yaml)
+    }
+}
+
+fn action_dir(name: &str) -> String {
+    format!("./.github/actions/{name}")
+}
+
+/// Arguments passed to a command: `- name` or `- name: {param: value}`
+fn invocation_args(custom: &CustomStep) -> Result<Mapping> {
+    let value: Value = serde_yaml::from_str(&custom.yaml)
+        .with_context(|| format!("Failed to parse invocation of command '{}'", custom.kind))?;
+    match value {
+        Value::String(_) => Ok(Mapping::new()),
+        Value::Mapping(mut map) => match map.remove(custom.kind.as_str()) {
+            Some(Value::Mapping(args)) => Ok(args),
+            Some(Value::Null) | None => Ok(Mapping::new()),
+            Some(_) => bail!(
+                "Arguments for command '{}' must be a mapping of parameter names to values",
+                custom.kind
+            ),
+        },
+        _ => bail!("Unexpected invocation of command '{}'", custom.kind),
+    }
+}
+
+/// The value of a parameter in an inlined invocation
+struct ParameterValue {
+    text: String,
+    /// The steps passed to a `steps` parameter
+    steps: Option<Vec<Step>>,
+}
+
+/// Parameter values for an inlined invocation, falling back to defaults
+fn resolve_parameters(
+    name: &str,
+    command: &CommandDefinition,
+    args: &Mapping,
+) -> Result<HashMap<String, ParameterValue>> {
+    for key in args.keys() {
+        let key = scalar_string(key);
+        if !command.parameters.contains_key(&key) {
+            bail!("Command '{name}' has no parameter '{key}'");
+        }
+    }
+
+    let mut values = HashMap::new();
+    for (parameter, definition) in &command.parameters {
+        let value = match args.get(parameter.as_str()) {
+            Some(value) => value.clone(),
+            None if !definition.default_yaml.is_empty() => {
+                serde_yaml::from_str(&definition.default_yaml)
+                    .with_context(|| format!("Invalid default for parameter '{parameter}'"))?
+            }
+            None => bail!("Command '{name}' requires parameter '{parameter}'"),
+        };
+        let steps = if definition.r#type == STEPS_PARAMETER_TYPE {
+            Some(step_list_to_proto(&value).with_context(|| {
+                format!("Parameter '{parameter}' of command '{name}' must be a list of steps")
+            })?)
+        } else {
+            None
+        };
+        values.insert(
+            parameter.clone(),
+            ParameterValue {
+                text: scalar_string(&value),
+                steps,
+            },
+        );
+    }
+    Ok(values)
+}
+
+/// The parameter whose steps a `- << parameters.name >>` step stands for
+fn spliced_parameter(step: &Step) -> Option<&str> {
+    match &step.step_type {
+        Some(StepType::Custom(custom)) => steps_parameter_reference(&custom.kind),
+        _ => None,
+    }
+}
+
+/// Replace `<< parameters.name >>` in every string field of a step
+fn substitute_step(step: &Step, value: impl Fn(&str) -> Result<String>) -> Result<Step> {
+    let sub = |text: &str| substitute(text, &value);
+    let sub_map = |map: &HashMap<String, String>| -> Result<HashMap<String, String>> {
+        map.iter()
+            .map(|(key, text)| Ok((key.clone(), sub(text)?)))
+            .collect()
+    };
+
+    let mut step = step.clone();
+    match &mut step.step_type {
+        Some(StepType::Run(run)) => {
+            run.name = sub(&run.name)?;
+            run.command = sub(&run.command)?;
+            run.env = sub_map(&run.env)?;
+            run.r#if = sub(&run.r#if)?;
+        }
+        Some(StepType::Uses(uses)) => {
+            uses.name = sub(&uses.name)?;
+            uses.module = sub(&uses.module)?;
+            uses.with = sub_map(&uses.with)?;
+            uses.r#if = sub(&uses.r#if)?;
+        }
+        Some(StepType::Custom(custom)) => {
+            custom.yaml = sub(&custom.yaml)?;
+            custom.r#if = sub(&custom.r#if)?;
+        }
+        Some(StepType::RestoreCache(_) | StepType::SaveCache(_)) | None => {}
+    }
+    Ok(step)
+}
+
+fn substitute(text: &str, value: impl Fn(&str) -> Result<String>) -> Result<String> {
+    let mut output = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("<<") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let reference = after
+            .trim_start()
+            .strip_prefix("parameters.")
+            .and_then(|tail| {
+                tail.find(">>")
+                    .map(|end| (tail[..end].trim(), &tail[end + 2..]))
+            });
+        match reference {
+            Some((parameter, tail)) => {
+                output.push_str(&value(parameter)?);
+                rest = tail;
+            }
+            None => {
+                output.push_str("<<");
+                rest = after;
+            }
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Strings in a step that may reference contexts
+fn step_text(step: &Step) -> Vec<&str> {
+    match &step.step_type {
+        Some(StepType::Run(run)) => [run.command.as_str(), run.r#if.as_str()]
+            .into_iter()
+            .chain(run.env.values().map(String::as_str))
+            .collect(),
+        Some(StepType::Uses(uses)) => [uses.r#if.as_str()]
+            .into_iter()
+            .chain(uses.with.values().map(String::as_str))
+            .collect(),
+        Some(StepType::Custom(custom)) => vec![custom.yaml.as_str(), custom.r#if.as_str()],
+        Some(StepType::RestoreCache(_) | StepType::SaveCache(_)) | None => Vec::new(),
+    }
+}
+
+/// AND the invocation's condition onto an inlined step
+fn add_condition(step: &mut Step, condition: &str) -> Result<()> {
+    let existing = match &mut step.step_type {
+        Some(StepType::Run(run)) => &mut run.r#if,
+        Some(StepType::Uses(uses)) => &mut uses.r#if,
+        Some(StepType::Custom(custom)) => &mut custom.r#if,
+        Some(StepType::RestoreCache(restore)) => &mut restore.r#if,
+        Some(StepType::SaveCache(save)) => &mut save.r#if,
+        None => return Ok(()),
+    };
+    *existing = if existing.is_empty() {
+        condition.to_string()
+    } else {
+        format!(
+            "({}) && ({})",
+            github_step_condition(condition)?,
+            github_step_condition(existing)?
+        )
+    };
+    Ok(())
+}
+
+pub fn scalar_string(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Bool(flag) => flag.to_string(),
+        Value::Number(number) => number.to_string(),
+        Value::Null => String::new(),
+        other => serde_yaml::to_string(other)
+            .map(|text| text.trim_end().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitute_replaces_parameters_and_leaves_heredocs() {
+        let text = "cat <<EOF\nenv=<< parameters.env >> x=<<parameters.x>>\nEOF";
+        let result = substitute(text, |name| Ok(name.to_uppercase())).unwrap();
+        assert_eq!(result, "cat <<EOF\nenv=ENV x=X\nEOF");
+    }
+}
@@ -0,0 +1,97 @@
+//! Compile cigen step conditions into GitHub Actions `if:` expressions
+
+use anyhow::Result;
+use cigen::schema::{Comparison, Condition, Literal, is_condition_expression};
+
+/// Translate a step `if:` into a GitHub expression.
+///
+/// Expressions in the cigen condition language are compiled; anything else is
+/// treated as a native GitHub expression and passed through unchanged.
+pub fn github_step_condition(expression: &str) -> Result<String> {
+    match Condition::parse(expression) {
+        Ok(condition) => Ok(compile(&condition)),
+        Err(err) if is_condition_expression(expression) => Err(err),
+        Err(_) => Ok(expression.to_string()),
+    }
+}
+
+/// Job `if:` condition limiting it to its `branches` by name, or `None` when
+/// it runs on every branch. `/regex/` patterns are rejected before this.
+pub fn branch_condition(branches: &[String]) -> Option<String> {
+    let names: Vec<String> = branches
+        .iter()
+        .map(|name| format!("github.ref_name == {}", quote(name)))
+        .collect();
+    (!names.is_empty()).then(|| names.join(" || "))
+}
+
+fn compile(condition: &Condition) -> String {
+    match condition {
+        Condition::Branch { op, value } => {
+            format!("github.ref_name {} {}", operator(*op), quote(value))
+        }
+        Condition::Param { name, op, value } => {
+            let value = match value {
+                Literal::String(value) => quote(value),
+                Literal::Bool(value) => value.to_string(),
+                Literal::Number(value) => value.to_string(),
+            };
+            format!("inputs.{name} {} {value}", operator(*op))
+        }
+        Condition::EnvDefined { name } => format!("env.{name} != ''"),
+        Condition::Env { name, op, value } => {
+            format!("env.{name} {} {}", operator(*op), quote(value))
+        }
+        Condition::Not(inner) => format!("!({})", compile(inner)),
+        Condition::And(left, right) => format!("{} && {}", group(left), group(right)),
+        Condition::Or(left, right) => format!("{} || {}", group(left), group(right)),
+    }
+}
+
+fn group(condition: &Condition) -> String {
+    match condition {
+        Condition::And(..) | Condition::Or(..) => format!("({})", compile(condition)),
+        _ => compile(condition),
+    }
+}
+
+fn operator(op: Comparison) -> &'static str {
+    match op {
+        Comparison::Eq => "==",
+        Comparison::Ne => "!=",
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiles_condition_language() {
+        assert_eq!(
+            github_step_condition(r#"branch == "main" && !(env.SKIP defined)"#).unwrap(),
+            "github.ref_name == 'main' && !(env.SKIP != '')"
+        );
+        assert_eq!(
+            github_step_condition("param.run_e2e == true || param.suite == \"it's\"").unwrap(),
+            "inputs.run_e2e == true || inputs.suite == 'it''s'"
+        );
+    }
+
+    #[test]
+    fn passes_native_expressions_through() {
+        assert_eq!(
+            github_step_condition("runner.os == 'Linux'").unwrap(),
+            "runner.os == 'Linux'"
+        );
+        assert_eq!(
+            github_step_condition("${{ matrix.use-cross }}").unwrap(),
+            "${{ matrix.use-cross }}"
+        );
+        assert!(github_step_condition("branch = 'main'").is_err());
+    }
+}
@@ -1,14 +1,70 @@
 /// GitHub Actions Provider Plugin for CIGen
 use anyhow::{Context, Result};
+use cigen::orchestrator::{SPLIT_TESTS_STEP, WARMUP_WORKFLOW};
+use cigen::plugin::capabilities::{Feature, ProviderCapabilities, Support};
+use cigen::plugin::diagnostics::{error_location, located_error_in};
+use cigen::plugin::negotiation::{ProtocolRange, negotiate};
+use cigen::plugin::overrides::apply_provider_overrides;
 use cigen::plugin::protocol::{diagnostic, plugin_server::Plugin, *};
+use cigen::schema::{
+    DEFAULT_WORKFLOW, GITHUB_ACTIONS_SCHEMA_URL, Instrumentation, OutputConfig, STEP_TIMINGS_LOG,
+    branch_regex, default_step_name, normalize_cache_path, resolve_output_path, schema_comment,
+    timed_command, versioned_cache_key,
+};
 use serde_yaml::{Mapping, Value};
 use std::collections::{BTreeMap, HashMap};
 use tonic::{Request, Response, Status};
 
+mod approval;
+mod cloud_auth;
+mod commands;
+mod conditions;
+mod matrix;
+mod notifications;
+mod services;
+mod skip;
+mod workflow_conditions;
+
+use approval::{annotate_approval_jobs, render_approval_job};
+use cloud_auth::{cloud_auth_steps, grant_id_token};
+use commands::{CommandSteps, CommandsAs};
+use conditions::{branch_condition, github_step_condition};
+use matrix::{MatrixStyle, collapse_matrix_jobs};
+use notifications::{NOTIFY_JOB_ID, render_slack_job};
+use services::{ServiceDefinition, extract_services, job_services, wait_for_services_steps};
+use skip::{build_skip_flow, skipped_output};
+use workflow_conditions::{add_dispatch_inputs, add_job_guard, job_unless_guard, workflow_guard};
+
 /// Plugin version and metadata
 const PLUGIN_NAME: &str = "provider/github";
+/// Key for this provider in a job's `provider_overrides`
+const PROVIDER_NAME: &str = "github";
 const PLUGIN_VERSION: &str = "0.1.0";
-const PROTOCOL_VERSION: u32 = 1;
+/// Protocol 2 adds `source_file`, used to locate errors in `.cigen` files
+const PROTOCOLS: ProtocolRange = ProtocolRange::new(1, 2);
+
+/// Capabilities declared in `PluginInfo`, including how CircleCI-flavoured
+/// job features come out on GitHub Actions
+fn plugin_capabilities() -> Vec<String> {
+    let features = ProviderCapabilities::native()
+        // A job gated by a protected environment
+        .with(Feature::Approval, Support::Emulated)
+        // Written as-is; secrets come from environments and repository settings
+        .with(Feature::Contexts, Support::PassThrough)
+        .with(Feature::Parallelism, Support::Dropped)
+        // A shard matrix
+        .with(Feature::TestSplitting, Support::Emulated)
+        .with(Feature::Workspaces, Support::Dropped)
+        // Hosted runners already have Docker
+        .with(Feature::RemoteDocker, Support::Dropped);
+    let mut capabilities = vec![
+        "provider:github".to_string(),
+        "cache:native".to_string(),
+        "matrix:build".to_string(),
+    ];
+    capabilities.extend(features.to_capabilities());
+    capabilities
+}
 
 /// GitHub Actions provider plugin
 #[derive(Debug, Default)]
@@ -19,15 +75,10 @@ impl Plugin for GitHubProvider {
     async fn handshake(&self, request: Request<Hello>) -> Result<Response<PluginInfo>, Status> {
         let hello = request.into_inner();
 
-        // Verify protocol compatibility
-        if hello.core_protocol != PROTOCOL_VERSION {
-            return Err(Status::failed_precondition(format!(
-                "Protocol version mismatch: core={}, plugin={}",
-                hello.core_protocol, PROTOCOL_VERSION
-            )));
-        }
+        let protocol = negotiate(PROTOCOLS, ProtocolRange::from_hello(&hello))
+            .map_err(|error| Status::failed_precondition(format!("{error:#}")))?;
 
-        tracing::info!(
+        tracing::debug!(
             "Handshake from core version {} (protocol {})",
             hello.core_version,
             hello.core_protocol
@@ -36,14 +87,12 @@ impl Plugin for GitHubProvider {
         let info = PluginInfo {
             name: PLUGIN_NAME.to_string(),
             version: PLUGIN_VERSION.to_string(),
-            protocol: PROTOCOL_VERSION,
-            capabilities: vec![
-                "provider:github".to_string(),
-                "cache:native".to_string(),
-                "matrix:build".to_string(),
-            ],
+            protocol,
+            protocol_min: PROTOCOLS.min,
+            protocol_max: PROTOCOLS.max,
+            capabilities: plugin_capabilities(),
             requires: vec![],
-            conflicts_with: vec!["provider:*".to_string()],
+            conflicts_with: vec!["provider:github".to_string()],
             metadata: std::collections::HashMap::new(),
         };
 
@@ -88,7 +137,7 @@ impl Plugin for GitHubProvider {
     ) -> Result<Response<GenerateResult>, Status> {
         let req = request.into_inner();
 
-        tracing::info!(
+        tracing::debug!(
             "Generating GitHub Actions config for target: {}",
             req.target
         );
@@ -105,7 +154,7 @@ impl Plugin for GitHubProvider {
 
         let result = GenerateResult {
             fragments: vec![fragment],
-            diagnostics: vec![],
+            ..Default::default()
         };
 
         Ok(Response::new(result))
@@ -161,17 +210,9 @@ fn main() -> Result<()> {
     // Initialize logging to stderr (stdout is used for protobuf messages)
     // Note: Use underscores in environment variable (e.g., RUST_LOG=cigen_provider_github=debug)
     // even though the plugin name uses slashes (provider/github)
-    tracing_subscriber::fmt()
-        .with_writer(std::io::stderr)
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("cigen_provider_github=info".parse()?),
-        )
-        .with_target(false)
-        .without_time()
-        .init();
+    cigen::plugin::logging::init_plugin_logging("cigen_provider_github")?;
 
-    tracing::info!("Starting {} v{}", PLUGIN_NAME, PLUGIN_VERSION);
+    tracing::debug!("Starting {} v{}", PLUGIN_NAME, PLUGIN_VERSION);
 
     // Use simple stdio communication with length-prefixed framing
     // Phase 1: Just handle handshake, exit after
@@ -183,42 +224,34 @@ fn main() -> Result<()> {
     // Read Hello message from stdin
     let hello: Hello = receive_message(&mut stdin().lock())?;
 
-    tracing::info!(
+    tracing::debug!(
         "Received handshake from core version {} (protocol {})",
         hello.core_version,
         hello.core_protocol
     );
 
-    // Verify protocol compatibility
-    if hello.core_protocol != PROTOCOL_VERSION {
-        anyhow::bail!(
-            "Protocol version mismatch: core={}, plugin={}",
-            hello.core_protocol,
-            PROTOCOL_VERSION
-        );
-    }
+    let protocol = negotiate(PROTOCOLS, ProtocolRange::from_hello(&hello))
+        .context("Protocol version mismatch")?;
 
     // Send PluginInfo response
     let info = PluginInfo {
         name: PLUGIN_NAME.to_string(),
         version: PLUGIN_VERSION.to_string(),
-        protocol: PROTOCOL_VERSION,
-        capabilities: vec![
-            "provider:github".to_string(),
-            "cache:native".to_string(),
-            "matrix:build".to_string(),
-        ],
+        protocol,
+        protocol_min: PROTOCOLS.min,
+        protocol_max: PROTOCOLS.max,
+        capabilities: plugin_capabilities(),
         requires: vec![],
-        conflicts_with: vec!["provider:*".to_string()],
+        conflicts_with: vec!["provider:github".to_string()],
         metadata: std::collections::HashMap::new(),
     };
 
     send_message(&info, &mut stdout().lock())?;
 
-    tracing::info!("Handshake successful, plugin info sent");
+    tracing::debug!("Handshake successful, plugin info sent");
 
     // Message loop: handle Plan and Generate requests
-    tracing::info!("Entering message loop...");
+    tracing::debug!("Entering message loop...");
 
     let mut stdin = stdin().lock();
     let mut stdout = stdout().lock();
@@ -226,7 +259,7 @@ fn main() -> Result<()> {
     loop {
         match receive_message::<PlanRequest, _>(&mut stdin) {
             Ok(_plan_req) => {
-                tracing::info!("Received PlanRequest");
+                tracing::debug!("Received PlanRequest");
 
                 let plan_result = PlanResult {
                     resources: vec![],
@@ -235,7 +268,7 @@ fn main() -> Result<()> {
                 };
 
                 send_message(&plan_result, &mut stdout)?;
-                tracing::info!("Sent PlanResult");
+                tracing::debug!("Sent PlanResult");
             }
             Err(_) => {
                 // Parent closed the pipe - normal termination
@@ -245,11 +278,11 @@ fn main() -> Result<()> {
 
         match receive_message::<GenerateRequest, _>(&mut stdin) {
             Ok(gen_req) => {
-                tracing::info!("Received GenerateRequest for target: {}", gen_req.target);
+                tracing::debug!("Received GenerateRequest for target: {}", gen_req.target);
 
                 let gen_result = build_generate_result(&gen_req);
 
-                tracing::info!(
+                tracing::debug!(
                     "Sending GenerateResult with {} fragment(s)",
                     gen_result.fragments.len()
                 );
@@ -276,6 +309,7 @@ fn build_generate_result(req: &GenerateRequest) -> GenerateResult {
                     "unknown",
                     anyhow::anyhow!("GenerateRequest missing schema"),
                 )],
+                ..Default::default()
             };
         }
     };
@@ -283,34 +317,130 @@ fn build_generate_result(req: &GenerateRequest) -> GenerateResult {
     GenerateResult {
         fragments,
         diagnostics,
+        ..Default::default()
+    }
+}
+
+/// Provider settings parsed once per generate request
+struct GithubContext<'a> {
+    commands: CommandSteps<'a>,
+    services: HashMap<String, ServiceDefinition>,
+    cache_version: Option<u32>,
+    instrumentation: Instrumentation,
+    matrix_style: MatrixStyle,
+    /// Pipeline `parameters:`, which workflow conditions read as dispatch inputs
+    parameters: Mapping,
+    /// `output_path` and `output_filename` for the workflow files
+    output: OutputConfig,
+}
+
+impl<'a> GithubContext<'a> {
+    fn new(schema: &'a CigenSchema, diagnostics: &mut Vec<Diagnostic>) -> anyhow::Result<Self> {
+        let raw_config: Value = serde_yaml::from_str(&schema.raw_config_yaml)
+            .context("Failed to parse raw configuration from schema")?;
+        let commands_as = CommandsAs::from_raw_config(&raw_config)?;
+        Ok(Self {
+            commands: CommandSteps::new(schema, commands_as, diagnostics),
+            services: extract_services(&raw_config)?,
+            cache_version: schema.cache_key_version(),
+            instrumentation: Instrumentation::from_raw_config(&raw_config)?,
+            matrix_style: MatrixStyle::from_raw_config(&raw_config)?,
+            parameters: raw_config
+                .get("parameters")
+                .and_then(Value::as_mapping)
+                .cloned()
+                .unwrap_or_default(),
+            output: OutputConfig::from_raw_config(&raw_config)?,
+        })
     }
 }
 
 fn build_workflow_fragments(schema: &CigenSchema) -> (Vec<Fragment>, Vec<Diagnostic>) {
     let mut diagnostics = Vec::new();
 
+    let context = match GithubContext::new(schema, &mut diagnostics) {
+        Ok(context) => context,
+        Err(error) => return (Vec::new(), vec![make_diagnostic("config", error)]),
+    };
+
     let workflow_metadata = parse_workflow_metadata(schema, &mut diagnostics);
     let mut jobs_by_workflow: BTreeMap<String, Vec<JobDefinition>> = BTreeMap::new();
+    let mut skips_warmup = false;
     for job in &schema.jobs {
+        if job.remote_docker.is_some() {
+            diagnostics.push(Diagnostic {
+                level: diagnostic::Level::Info as i32,
+                code: "GITHUB_REMOTE_DOCKER_IGNORED".to_string(),
+                title: "remote_docker has no effect on GitHub Actions".to_string(),
+                message: format!(
+                    "Job '{}' sets remote_docker, which is ignored because GitHub-hosted runners already provide Docker",
+                    job.id
+                ),
+                fix_hint: String::new(),
+                loc: None,
+            });
+        }
         let workflow = if job.workflow.is_empty() {
-            "ci"
+            DEFAULT_WORKFLOW
         } else {
             &job.workflow
         };
+        // The warm-up jobs only restore and save caches, which GitHub
+        // Actions jobs don't run
+        if workflow == WARMUP_WORKFLOW {
+            skips_warmup = true;
+            continue;
+        }
         jobs_by_workflow
             .entry(workflow.to_string())
             .or_default()
             .push(job.clone());
     }
 
+    if skips_warmup {
+        diagnostics.push(Diagnostic {
+            level: diagnostic::Level::Warning as i32,
+            code: "GITHUB_CACHE_WARMUP_SKIPPED".to_string(),
+            title: "cache_warmup is not supported on GitHub Actions".to_string(),
+            message: format!(
+                "The '{WARMUP_WORKFLOW}' workflow is not generated, because GitHub Actions jobs don't run restore_cache or save_cache steps"
+            ),
+            fix_hint: "Remove cache_warmup, or generate it for CircleCI".to_string(),
+            loc: None,
+        });
+    }
+
+    let workflows: Vec<&str> = jobs_by_workflow.keys().map(String::as_str).collect();
+    if let Err(error) = context.output.check_per_workflow(&workflows) {
+        diagnostics.push(make_diagnostic("config", error));
+        return (Vec::new(), diagnostics);
+    }
+
     let mut fragments = Vec::new();
 
     for (workflow_name, mut jobs) in jobs_by_workflow {
+        if context.matrix_style == MatrixStyle::Native {
+            jobs =
+                collapse_matrix_jobs(jobs, context.instrumentation.step_timing, &mut diagnostics);
+        }
         jobs.sort_by(|a, b| a.id.cmp(&b.id));
         let metadata = workflow_metadata.get(&workflow_name);
-        match render_workflow_file(&workflow_name, &jobs, metadata) {
+        let env = workflow_env(schema, &workflow_name);
+        let workflow = schema
+            .workflows
+            .iter()
+            .find(|workflow| workflow.id == workflow_name);
+        match render_workflow_file(
+            &workflow_name,
+            &jobs,
+            metadata,
+            &env,
+            workflow,
+            &context,
+            &mut diagnostics,
+        ) {
             Ok(content) => fragments.push(Fragment {
-                path: format!(".github/workflows/{workflow_name}.yml"),
+                path: resolve_output_path(PROVIDER_NAME, &workflow_name, &context.output, None),
                 content,
                 strategy: MergeStrategy::Replace as i32,
                 order: 0,
@@ -320,6 +450,10 @@ fn build_workflow_fragments(schema: &CigenSchema) -> (Vec<Fragment>, Vec<Diagnos
         }
     }
 
+    let (action_fragments, action_diagnostics) = context.commands.action_fragments();
+    fragments.extend(action_fragments);
+    diagnostics.extend(action_diagnostics);
+
     (fragments, diagnostics)
 }
 
@@ -349,10 +483,24 @@ fn parse_workflow_metadata(
     result
 }
 
+/// Global `env` overridden by the workflow's own `env`. Job-level `env` is
+/// emitted on each job, which GitHub Actions already gives precedence.
+fn workflow_env(schema: &CigenSchema, workflow_name: &str) -> BTreeMap<String, String> {
+    let mut env: BTreeMap<String, String> = schema.env.clone().into_iter().collect();
+    if let Some(workflow) = schema.workflows.iter().find(|wf| wf.id == workflow_name) {
+        env.extend(workflow.env.clone());
+    }
+    env
+}
+
 fn render_workflow_file(
     workflow_name: &str,
     jobs: &[JobDefinition],
     metadata: Option<&Mapping>,
+    env: &BTreeMap<String, String>,
+    workflow: Option<&WorkflowDefinition>,
+    context: &GithubContext,
+    diagnostics: &mut Vec<Diagnostic>,
 ) -> anyhow::Result<String> {
     let mut workflow_map = metadata.cloned().unwrap_or_else(Mapping::new);
     let jobs_key = Value::String("jobs".into());
@@ -371,17 +519,51 @@ fn render_workflow_file(
         workflow_map.insert(on_key, default_on_value());
     }
 
-    let jobs_mapping = build_jobs_mapping(workflow_name, jobs)?;
+    let env_key = Value::String("env".into());
+    workflow_map.remove(&env_key);
+    if !env.is_empty() {
+        let env_map = env
+            .iter()
+            .map(|(key, value)| (Value::String(key.clone()), Value::String(value.clone())))
+            .collect();
+        workflow_map.insert(env_key, Value::Mapping(env_map));
+    }
+
+    let mut jobs_mapping = build_jobs_mapping(workflow_name, jobs, context, diagnostics)?;
+    if let Some(slack) = workflow.and_then(|workflow| workflow.slack.as_ref()) {
+        if jobs.iter().any(|job| job.id == NOTIFY_JOB_ID) {
+            anyhow::bail!(
+                "Workflow '{workflow_name}' has a job named '{NOTIFY_JOB_ID}', which Slack notifications need for their own job; rename it"
+            );
+        }
+        jobs_mapping.insert(
+            Value::String(NOTIFY_JOB_ID.into()),
+            Value::Mapping(render_slack_job(slack, jobs)),
+        );
+    }
+    if let Some(workflow) = workflow
+        && let Some(guard) = workflow_guard(workflow, &context.parameters).with_context(|| {
+            format!("Invalid run_when/run_unless for workflow '{workflow_name}'")
+        })?
+    {
+        for job in jobs_mapping.values_mut() {
+            if let Value::Mapping(job) = job {
+                add_job_guard(job, &guard.condition);
+            }
+        }
+        if !guard.inputs.is_empty() {
+            add_dispatch_inputs(&mut workflow_map, guard.inputs);
+        }
+    }
     workflow_map.insert(Value::String("jobs".into()), Value::Mapping(jobs_mapping));
 
-    let mut yaml = String::from("# DO NOT EDIT - This file is generated by cigen\n");
+    let mut yaml = schema_comment(GITHUB_ACTIONS_SCHEMA_URL);
     yaml.push_str("# Source: .cigen/workflows/\n");
-    yaml.push_str("# Regenerate with: cargo run -- --config .cigen generate\n");
     yaml.push_str("#\n");
 
     let rendered = serde_yaml::to_string(&workflow_map)
         .with_context(|| format!("Failed to serialize workflow {workflow_name}"))?;
-    yaml.push_str(&rendered);
+    yaml.push_str(&annotate_approval_jobs(&rendered, jobs));
     Ok(yaml)
 }
 
@@ -401,11 +583,52 @@ fn default_on_value() -> Value {
     Value::Mapping(on_mapping)
 }
 
-fn build_jobs_mapping(workflow_name: &str, jobs: &[JobDefinition]) -> anyhow::Result<Mapping> {
+fn build_jobs_mapping(
+    workflow_name: &str,
+    jobs: &[JobDefinition],
+    context: &GithubContext,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> anyhow::Result<Mapping> {
     let mut mapping = Mapping::new();
     let has_builder = jobs.iter().any(|job| job.id == "build_cigen");
     for job in jobs {
-        let rendered = render_job(job, workflow_name, has_builder)?;
+        let expand = |steps| {
+            context
+                .commands
+                .expand(steps)
+                .with_context(|| format!("Failed to expand commands in job '{}'", job.id))
+        };
+        let job = JobDefinition {
+            steps: expand(&job.steps)?,
+            pre_steps: expand(&job.pre_steps)?,
+            post_steps: expand(&job.post_steps)?,
+            ..job.clone()
+        };
+        let mut rendered = render_job(&job, workflow_name, has_builder, context)?;
+        if let Some(pattern) = job.branches.iter().find(|p| branch_regex(p).is_some()) {
+            return Err(located_error_in(
+                format!(
+                    "Job '{}' uses branches pattern '{pattern}', but GitHub Actions expressions can't match regexes; list the branch names instead",
+                    job.id
+                ),
+                &job.source_file,
+                &job.source_section,
+                pattern,
+            ));
+        }
+        if let Some(condition) = branch_condition(&job.branches) {
+            add_job_guard(&mut rendered, &condition);
+        }
+        if !job.unless.is_empty() {
+            let guard = job_unless_guard(&job.unless)
+                .with_context(|| format!("Invalid `unless:` on job '{}'", job.id))?;
+            add_job_guard(&mut rendered, &guard);
+        }
+        diagnostics.extend(apply_provider_overrides(
+            &job,
+            PROVIDER_NAME,
+            &mut rendered,
+        )?);
         mapping.insert(Value::String(job.id.clone()), Value::Mapping(rendered));
     }
     Ok(mapping)
@@ -415,16 +638,44 @@ fn render_job(
     job: &JobDefinition,
     _workflow_name: &str,
     has_builder: bool,
+    context: &GithubContext,
 ) -> anyhow::Result<Mapping> {
     let mut job_map = Mapping::new();
 
     for (key, value_yaml) in &job.extra {
         job_map.insert(Value::String(key.clone()), parse_yaml_value(value_yaml));
     }
+    if job.is_approval() {
+        return Ok(render_approval_job(job, job_map));
+    }
+    // GitHub has no `parallelism`; test splitting runs a shard matrix instead
+    let parallelism = job_map
+        .remove(Value::String("parallelism".into()))
+        .and_then(|value| value.as_u64())
+        .unwrap_or(1);
+    if job.test_splitting.is_some() {
+        add_shard_matrix(&mut job_map, parallelism)
+            .with_context(|| format!("Invalid strategy for job '{}'", job.id))?;
+    }
+
+    if let Some(executor) = job.executor.as_ref().filter(|e| e.kind == "named") {
+        return Err(located_error_in(
+            format!(
+                "Job '{}' uses executor '{}', but named executors are CircleCI-only; use image or a machine/macos executor for GitHub Actions",
+                job.id, executor.name
+            ),
+            &job.source_file,
+            &job.source_section,
+            &executor.name,
+        ));
+    }
 
     let runs_on_key = Value::String("runs-on".into());
     if !job_map.contains_key(&runs_on_key) {
-        let (runs_on, container) = determine_runner(&job.image);
+        let (runs_on, container) = match &job.executor {
+            Some(executor) => (Some(vm_runner(executor, &job.architecture)), None),
+            None => determine_runner(&job.image),
+        };
         if let Some(runs_on_value) = runs_on {
             job_map.insert(runs_on_key.clone(), runs_on_value);
         }
@@ -468,6 +719,43 @@ fn render_job(
         }
     }
 
+    if !job.services.is_empty()
+        && let Some(executor) = job.executor.as_ref().filter(|e| e.kind == "macos")
+    {
+        return Err(located_error_in(
+            format!(
+                "Job '{}' uses the {} executor, but GitHub Actions only runs service containers on Linux runners",
+                job.id, executor.kind
+            ),
+            &job.source_file,
+            &job.source_section,
+            "services",
+        ));
+    }
+
+    if let Some(services) = job_services(job, &context.services)? {
+        let services_key = Value::String("services".into());
+        if !job_map.contains_key(&services_key) {
+            job_map.insert(services_key, Value::Mapping(services));
+        }
+    }
+
+    if !job.working_directory.is_empty() {
+        let defaults_key = Value::String("defaults".into());
+        if !job_map.contains_key(&defaults_key) {
+            let mut run = Mapping::new();
+            run.insert(
+                Value::String("working-directory".into()),
+                Value::String(job.working_directory.clone()),
+            );
+            let mut defaults = Mapping::new();
+            defaults.insert(Value::String("run".into()), Value::Mapping(run));
+            job_map.insert(defaults_key, Value::Mapping(defaults));
+        }
+    }
+
+    grant_id_token(job, &mut job_map)?;
+
     // Determine if this job uses skip flow (has source_files and is not the builder)
     tracing::debug!("Job {} source_files: {:?}", job.id, job.source_files);
     let has_source_files = !job.source_files.is_empty();
@@ -475,11 +763,18 @@ fn render_job(
     let skip_flow = if is_builder_job || !has_source_files {
         None
     } else {
-        Some(build_skip_flow(&job.id))
+        Some(build_skip_flow(
+            &job.id,
+            job.test_splitting.is_some(),
+            context.cache_version,
+        ))
     };
+    // cigen's own run steps work on paths relative to the repository root, so
+    // they opt out of the job's working directory
+    let at_workspace = |step: Mapping| run_at_workspace(job, step);
 
     // Check what dependencies are actually needed
-    let package_cache_steps = build_package_cache_steps(job);
+    let package_cache_steps = build_package_cache_steps(job, context.cache_version)?;
     let needs_protobuf = job_needs_protobuf(job);
     let has_download_step = has_builder && !is_builder_job;
     let needs_node_runtime = job_needs_node_runtime(
@@ -493,20 +788,39 @@ fn render_job(
 
     let mut steps: Vec<Value> = Vec::new();
 
+    // The workflow's pre_steps come before everything, like CircleCI's pre-steps
+    let workflow_owner = format!("workflow steps of job '{}'", job.id);
+    for (index, step) in job.pre_steps.iter().enumerate() {
+        if let Some(rendered) = render_step(step, index, &workflow_owner)? {
+            steps.push(Value::Mapping(rendered));
+        }
+    }
+
     // PHASE 1: Minimal setup for skip check (checkout + cigen binary)
-    let checkout_step = build_checkout_step(job);
-    steps.push(Value::Mapping(checkout_step));
+    let checkout_start = steps.len();
+    steps.extend(build_checkout_steps(job)?);
+    let checkout_steps = checkout_start..steps.len();
+    let in_container = job_map.contains_key("container");
+    steps.extend(
+        wait_for_services_steps(job, &context.services, in_container)
+            .into_iter()
+            .map(Value::Mapping),
+    );
+
+    if let Some(executor) = job.executor.as_ref().filter(|e| !e.xcode.is_empty()) {
+        steps.push(Value::Mapping(select_xcode_step(&executor.xcode)));
+    }
 
     // Download cigen binary if there's a builder job and this isn't it
     // The binary is needed for skip flow hash computation and may be used by job steps
     if has_builder && !is_builder_job {
         steps.push(Value::Mapping(download_cigen_step()));
-        steps.push(Value::Mapping(make_cigen_executable_step()));
+        steps.push(Value::Mapping(at_workspace(make_cigen_executable_step())));
     }
 
     // PHASE 2: Skip check (before installing any heavy dependencies)
     if let Some(flow) = &skip_flow {
-        steps.push(Value::Mapping(flow.compute_step.clone()));
+        steps.push(Value::Mapping(at_workspace(flow.compute_step.clone())));
         steps.push(Value::Mapping(flow.restore_step.clone()));
         steps.push(Value::Mapping(flow.skip_step.clone()));
     }
@@ -530,6 +844,14 @@ fn render_job(
         steps.push(Value::Mapping(protobuf_step));
     }
 
+    // Cloud credentials (only if not skipped)
+    for mut auth_step in job.cloud_auth.iter().flat_map(cloud_auth_steps) {
+        if let Some(condition) = skip_condition {
+            apply_condition(&mut auth_step, condition);
+        }
+        steps.push(Value::Mapping(auth_step));
+    }
+
     // Package cache steps (only if not skipped)
     for mut cache_step in package_cache_steps.into_iter() {
         if let Some(condition) = skip_condition {
@@ -539,16 +861,48 @@ fn render_job(
     }
 
     // PHASE 4: User-defined steps (only if not skipped)
-    for step in &job.steps {
-        if let Some(step_type) = &step.step_type {
-            let mut rendered = match step_type {
-                step::StepType::Run(run) => convert_run_step(run),
-                step::StepType::Uses(uses) => convert_uses_step(uses),
-                step::StepType::RestoreCache(_) | step::StepType::SaveCache(_) => {
-                    continue;
-                }
-                step::StepType::Custom(_) => continue,
-            };
+    let owner = format!("job '{}'", job.id);
+    for (index, step) in job.steps.iter().enumerate() {
+        if let Some(mut rendered) = render_step(step, index, &owner)? {
+            // The file selection needs globstar, whatever the job's shell
+            if job.test_splitting.is_some()
+                && rendered.get("name").and_then(Value::as_str) == Some(SPLIT_TESTS_STEP)
+            {
+                rendered.insert(Value::String("shell".into()), Value::String("bash".into()));
+            }
+            if let Some(condition) = skip_condition {
+                apply_condition(&mut rendered, condition);
+            }
+            steps.push(Value::Mapping(rendered));
+        }
+    }
+
+    // Each shard uploads its own artifacts
+    let artifact = match job.test_splitting {
+        Some(_) => format!("{}-${{{{ matrix.shard }}}}", job.id),
+        None => job.id.clone(),
+    };
+
+    // Publish JUnit results even when tests fail (only if not skipped)
+    if !job.test_results.is_empty() {
+        let mut upload_step =
+            upload_test_results_step(&artifact, &job_path(job, &job.test_results));
+        if let Some(condition) = skip_condition {
+            apply_condition(&mut upload_step, condition);
+        }
+        steps.push(Value::Mapping(upload_step));
+    }
+
+    // Cleanup runs even after a failed step, and before the skip flow records
+    // completion, which only happens when every step passed
+    let cleanup_condition = match job.cleanup_on.as_str() {
+        "failure" => "failure()",
+        _ => "always()",
+    };
+    let cleanup_owner = format!("cleanup of job '{}'", job.id);
+    for (index, step) in job.cleanup.iter().enumerate() {
+        if let Some(mut rendered) = render_step(step, index, &cleanup_owner)? {
+            apply_condition(&mut rendered, cleanup_condition);
             if let Some(condition) = skip_condition {
                 apply_condition(&mut rendered, condition);
             }
@@ -556,9 +910,46 @@ fn render_job(
         }
     }
 
-    // PHASE 5: Record completion (only if not skipped)
+    let post_steps_condition = skip_condition.map(str::to_string);
+
+    // PHASE 5: Record completion and save the marker (only if not skipped)
     if let Some(flow) = skip_flow {
-        steps.push(Value::Mapping(flow.record_step));
+        steps.push(Value::Mapping(at_workspace(flow.record_step)));
+        steps.push(Value::Mapping(flow.save_step));
+
+        let (output_name, output_value) = skipped_output();
+        match job_map
+            .entry(Value::String("outputs".into()))
+            .or_insert_with(|| Value::Mapping(Mapping::new()))
+        {
+            Value::Mapping(outputs) => {
+                outputs.entry(output_name).or_insert(output_value);
+            }
+            _ => anyhow::bail!("Job '{}' has an `outputs` that is not a mapping", job.id),
+        }
+    }
+
+    // The workflow's post_steps come last; a skipped job ends before them,
+    // as CircleCI's post-steps don't run after `circleci step halt`
+    for (index, step) in job.post_steps.iter().enumerate() {
+        if let Some(mut rendered) = render_step(step, index, &workflow_owner)? {
+            if let Some(condition) = &post_steps_condition {
+                apply_condition(&mut rendered, condition);
+            }
+            steps.push(Value::Mapping(rendered));
+        }
+    }
+
+    if context.instrumentation.step_timing {
+        let default_shell = default_run_shell(job, &job_map);
+        for (index, step) in steps.iter_mut().enumerate() {
+            if let Value::Mapping(step) = step
+                && !checkout_steps.contains(&index)
+            {
+                time_run_step(step, default_shell.as_deref());
+            }
+        }
+        steps.push(Value::Mapping(upload_step_timings_step(&artifact)));
     }
 
     job_map.insert(Value::String("steps".into()), Value::Sequence(steps));
@@ -603,6 +994,11 @@ fn job_needs_node_runtime(
         return true;
     }
 
+    // The cloud_auth actions run on Node
+    if job.cloud_auth.is_some() {
+        return true;
+    }
+
     // Package cache steps use actions/cache
     if !package_cache_steps.is_empty() {
         return true;
@@ -644,176 +1040,246 @@ fn determine_runner(image: &str) -> (Option<Value>, Option<Value>) {
     )
 }
 
-struct SkipFlow {
-    compute_step: Mapping,
-    restore_step: Mapping,
-    skip_step: Mapping,
-    record_step: Mapping,
-    condition: String,
+/// `runs-on` for a job that runs directly on a VM rather than in a container
+fn vm_runner(executor: &Executor, architecture: &str) -> Value {
+    let runner = match (executor.kind.as_str(), architecture) {
+        ("macos", _) => "macos-latest",
+        (_, "arm64") => "ubuntu-24.04-arm",
+        _ => "ubuntu-latest",
+    };
+    Value::String(runner.into())
 }
 
-fn build_skip_flow(job_id: &str) -> SkipFlow {
-    let compute_script = format!(
-        concat!(
-            "set -euo pipefail\n",
-            "mkdir -p .cigen/skip-cache\n",
-            "mkdir -p .cigen/cache\n",
-            "./.cigen/bin/cigen hash \\\n",
-            "  --job {job_id} \\\n",
-            "  --config .cigen \\\n",
-            "  --base-dir . \\\n",
-            "  --output job_hash \\\n",
-            "  --cache .cigen/cache/file-hashes.json\n"
-        ),
-        job_id = job_id
-    );
-
-    let mut compute_step = Mapping::new();
-    compute_step.insert(
+fn select_xcode_step(xcode: &str) -> Mapping {
+    let mut step = Mapping::new();
+    step.insert(
         Value::String("name".into()),
-        Value::String("Compute source hash".into()),
-    );
-    compute_step.insert(
-        Value::String("id".into()),
-        Value::String("compute_hash".into()),
-    );
-    compute_step.insert(Value::String("run".into()), Value::String(compute_script));
-
-    let mut cache_with = Mapping::new();
-    cache_with.insert(
-        Value::String("path".into()),
-        Value::String(format!(".cigen/skip-cache/{job_id}")),
+        Value::String(format!("Select Xcode {xcode}")),
     );
-    cache_with.insert(
-        Value::String("key".into()),
+    step.insert(
+        Value::String("run".into()),
         Value::String(format!(
-            "job-skip-${{ runner.os }}-{job_id}-${{ steps.compute_hash.outputs.job_hash }}"
+            "sudo xcode-select -s /Applications/Xcode_{xcode}.app"
         )),
     );
-    cache_with.insert(
-        Value::String("restore-keys".into()),
-        Value::String(format!("job-skip-${{ runner.os }}-{job_id}-")),
-    );
+    step
+}
 
-    let mut restore_step = Mapping::new();
-    restore_step.insert(
+/// `actions/checkout` with the job's options as `with:`, followed by `post` steps.
+///
+/// `enabled: false` drops checkout entirely. Custom checkout commands rely on
+/// CircleCI commands and are rejected here.
+fn build_checkout_steps(job: &JobDefinition) -> anyhow::Result<Vec<Value>> {
+    let mut with_mapping = Mapping::new();
+    let mut post = Vec::new();
+    for (key, value) in &job.checkout {
+        match key.as_str() {
+            "enabled" if parse_yaml_value(value) == Value::Bool(false) => return Ok(Vec::new()),
+            "enabled" => {}
+            "command" => anyhow::bail!(
+                "Custom checkout command '{value}' in job '{}' is only supported by CircleCI",
+                job.id
+            ),
+            "post" => match parse_yaml_value(value) {
+                Value::Sequence(steps) => post = steps,
+                _ => anyhow::bail!("checkout.post in job '{}' must be a list of steps", job.id),
+            },
+            _ => {
+                with_mapping.insert(Value::String(key.clone()), parse_yaml_value(value));
+            }
+        }
+    }
+
+    let mut step = Mapping::new();
+    step.insert(
         Value::String("name".into()),
-        Value::String("Restore skip cache".into()),
-    );
-    restore_step.insert(
-        Value::String("id".into()),
-        Value::String("job_skip_cache".into()),
+        Value::String("Checkout repository".into()),
     );
-    restore_step.insert(
+    step.insert(
         Value::String("uses".into()),
-        Value::String("actions/cache@v4".into()),
-    );
-    restore_step.insert(Value::String("with".into()), Value::Mapping(cache_with));
-    restore_step.insert(
-        Value::String("if".into()),
-        Value::String("${{ env.ACT != 'true' }}".into()),
+        Value::String("actions/checkout@v4".into()),
     );
+    if !with_mapping.is_empty() {
+        step.insert(Value::String("with".into()), Value::Mapping(with_mapping));
+    }
 
-    let condition = "steps.job_skip_cache.outputs.cache-hit != 'true'".to_string();
+    let mut steps = vec![Value::Mapping(step)];
+    steps.extend(post);
+    Ok(steps)
+}
 
-    let mut record_step = Mapping::new();
-    record_step.insert(
+fn download_cigen_step() -> Mapping {
+    let mut step = Mapping::new();
+    step.insert(
         Value::String("name".into()),
-        Value::String("Record job completion".into()),
-    );
-    record_step.insert(
-        Value::String("if".into()),
-        Value::String(format!("success() && {condition}")),
-    );
-    record_step.insert(
-        Value::String("run".into()),
-        Value::String(format!(
-            "set -e
-HASH=\"${{JOB_HASH}}\"
-if [ -z \"$HASH\" ]; then
-  echo 'JOB_HASH missing' >&2
-  exit 1
-fi
-MARKER=.cigen/skip-cache/{job_id}/$HASH
-mkdir -p \"$(dirname \"$MARKER\")\"
-date > \"$MARKER\"
-"
-        )),
+        Value::String("Download cigen binary".into()),
     );
-
-    let mut record_env = Mapping::new();
-    record_env.insert(
-        Value::String("JOB_HASH".into()),
-        Value::String("${{ steps.compute_hash.outputs.job_hash }}".into()),
+    step.insert(
+        Value::String("uses".into()),
+        Value::String("actions/download-artifact@v4".into()),
     );
-    record_step.insert(Value::String("env".into()), Value::Mapping(record_env));
-
-    let mut skip_step = Mapping::new();
-    skip_step.insert(
+    let mut with = Mapping::new();
+    with.insert(
         Value::String("name".into()),
-        Value::String("Skip job (cached)".into()),
-    );
-    skip_step.insert(
-        Value::String("if".into()),
-        Value::String("steps.job_skip_cache.outputs.cache-hit == 'true'".into()),
+        Value::String("cigen-bin".into()),
     );
-    skip_step.insert(
-        Value::String("run".into()),
-        Value::String("echo 'Job cache hit; skipping remaining steps.' && exit 0".into()),
+    with.insert(
+        Value::String("path".into()),
+        Value::String(".cigen/bin".into()),
     );
+    step.insert(Value::String("with".into()), Value::Mapping(with));
+    step.insert(Value::String("continue-on-error".into()), Value::Bool(true));
+    step
+}
 
-    SkipFlow {
-        compute_step,
-        restore_step,
-        skip_step,
-        record_step,
-        condition,
-    }
+/// Run the job once per shard index, without cancelling the other shards
+fn add_shard_matrix(job_map: &mut Mapping, shards: u64) -> anyhow::Result<()> {
+    let strategy = job_map
+        .entry(Value::String("strategy".into()))
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    let Value::Mapping(strategy) = strategy else {
+        anyhow::bail!("`strategy` is not a mapping");
+    };
+    strategy
+        .entry(Value::String("fail-fast".into()))
+        .or_insert(Value::Bool(false));
+    let Value::Mapping(matrix) = strategy
+        .entry(Value::String("matrix".into()))
+        .or_insert_with(|| Value::Mapping(Mapping::new()))
+    else {
+        anyhow::bail!("`strategy.matrix` is not a mapping");
+    };
+    matrix.insert(
+        Value::String("shard".into()),
+        Value::Sequence(
+            (0..shards)
+                .map(|shard| Value::Number(shard.into()))
+                .collect(),
+        ),
+    );
+    Ok(())
 }
 
-fn build_checkout_step(job: &JobDefinition) -> Mapping {
+fn upload_test_results_step(job_id: &str, path: &str) -> Mapping {
     let mut step = Mapping::new();
     step.insert(
         Value::String("name".into()),
-        Value::String("Checkout repository".into()),
+        Value::String("Upload test results".into()),
     );
     step.insert(
         Value::String("uses".into()),
-        Value::String("actions/checkout@v4".into()),
+        Value::String("actions/upload-artifact@v4".into()),
+    );
+    step.insert(Value::String("if".into()), Value::String("always()".into()));
+    let mut with = Mapping::new();
+    with.insert(
+        Value::String("name".into()),
+        Value::String(format!("test-results-{job_id}")),
     );
+    with.insert(
+        Value::String("path".into()),
+        Value::String(path.to_string()),
+    );
+    step.insert(Value::String("with".into()), Value::Mapping(with));
+    step
+}
 
-    if !job.checkout.is_empty() {
-        let mut with_mapping = Mapping::new();
-        for (key, value) in &job.checkout {
-            with_mapping.insert(Value::String(key.clone()), parse_yaml_value(value));
-        }
-        step.insert(Value::String("with".into()), Value::Mapping(with_mapping));
+/// `instrumentation.step_timing`: log how long a `run` step takes. The timing
+/// is shell script, so steps run by another `shell:` (python, pwsh, ...) are
+/// left alone.
+fn time_run_step(step: &mut Mapping, default_shell: Option<&str>) {
+    let shell = step.get("shell").and_then(Value::as_str).or(default_shell);
+    if !shell.is_none_or(is_posix_shell) {
+        return;
     }
+    let name = step.get("name").and_then(Value::as_str).map(str::to_string);
+    if let Some(Value::String(command)) = step.get_mut("run") {
+        let name = name.unwrap_or_else(|| default_step_name(command).to_string());
+        *command = timed_command(&name, command);
+    }
+}
 
-    step
+/// The shell of the job's run steps without a `shell:`: its
+/// `defaults.run.shell`, from `provider_overrides` or the job itself, else the
+/// runner's default, which is `pwsh` on Windows
+fn default_run_shell(job: &JobDefinition, job_map: &Mapping) -> Option<String> {
+    let overrides = job
+        .provider_overrides
+        .get(PROVIDER_NAME)
+        .and_then(|overrides| serde_yaml::from_str::<Mapping>(overrides).ok());
+    let run_shell = |map: &Mapping| {
+        map.get("defaults")
+            .and_then(|defaults| defaults.get("run"))
+            .and_then(|run| run.get("shell"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+    if let Some(shell) = overrides.as_ref().and_then(run_shell) {
+        return Some(shell);
+    }
+    if let Some(shell) = run_shell(job_map) {
+        return Some(shell);
+    }
+    overrides
+        .as_ref()
+        .and_then(|overrides| overrides.get("runs-on"))
+        .or_else(|| job_map.get("runs-on"))
+        .and_then(Value::as_str)
+        .filter(|runner| runner.starts_with("windows"))
+        .map(|_| "pwsh".to_string())
 }
 
-fn download_cigen_step() -> Mapping {
+/// `bash`, `sh`, or a custom command running one of them (`bash -e {0}`)
+fn is_posix_shell(shell: &str) -> bool {
+    matches!(shell.split_whitespace().next(), Some("bash" | "sh"))
+}
+
+fn upload_step_timings_step(artifact: &str) -> Mapping {
     let mut step = Mapping::new();
     step.insert(
         Value::String("name".into()),
-        Value::String("Download cigen binary".into()),
+        Value::String("Upload step timings".into()),
     );
     step.insert(
         Value::String("uses".into()),
-        Value::String("actions/download-artifact@v4".into()),
+        Value::String("actions/upload-artifact@v4".into()),
     );
+    step.insert(Value::String("if".into()), Value::String("always()".into()));
     let mut with = Mapping::new();
     with.insert(
         Value::String("name".into()),
-        Value::String("cigen-bin".into()),
+        Value::String(format!("cigen-timings-{artifact}")),
     );
     with.insert(
         Value::String("path".into()),
-        Value::String(".cigen/bin".into()),
+        Value::String(STEP_TIMINGS_LOG.into()),
+    );
+    with.insert(
+        Value::String("if-no-files-found".into()),
+        Value::String("ignore".into()),
     );
     step.insert(Value::String("with".into()), Value::Mapping(with));
-    step.insert(Value::String("continue-on-error".into()), Value::Bool(true));
+    step
+}
+
+/// `path` as seen from the workspace root, where actions resolve their inputs.
+/// Relative paths in the job are relative to its `working_directory`.
+fn job_path(job: &JobDefinition, path: &str) -> String {
+    let directory = job.working_directory.trim_end_matches('/');
+    if directory.is_empty() || directory == "." || path.starts_with(['/', '~', '$']) {
+        return path.to_string();
+    }
+    format!("{directory}/{path}")
+}
+
+/// Pin a run step to the workspace root when the job sets `working_directory`
+fn run_at_workspace(job: &JobDefinition, mut step: Mapping) -> Mapping {
+    if !job.working_directory.is_empty() {
+        step.insert(
+            Value::String("working-directory".into()),
+            Value::String("${{ github.workspace }}".into()),
+        );
+    }
     step
 }
 
@@ -865,22 +1331,35 @@ fn install_protobuf_step() -> Mapping {
     step
 }
 
-fn build_package_cache_steps(job: &JobDefinition) -> Vec<Mapping> {
+fn build_package_cache_steps(
+    job: &JobDefinition,
+    cache_version: Option<u32>,
+) -> anyhow::Result<Vec<Mapping>> {
     let mut steps = Vec::new();
+    let cache_path = |path: &str| normalize_cache_path(path, &job.working_directory);
 
     if job.packages.iter().any(|pkg| pkg == "rust") {
         let mut with = Mapping::new();
         with.insert(
             Value::String("path".into()),
-            Value::String("~/.cargo/registry\n~/.cargo/git\ntarget".into()),
+            Value::String(format!(
+                "~/.cargo/registry\n~/.cargo/git\n{}",
+                cache_path("target")?
+            )),
         );
         with.insert(
             Value::String("key".into()),
-            Value::String("${{ runner.os }}-cargo-${{ hashFiles('**/Cargo.lock') }}".into()),
+            Value::String(versioned_cache_key(
+                cache_version,
+                "${{ runner.os }}-cargo-${{ hashFiles('**/Cargo.lock') }}",
+            )),
         );
         with.insert(
             Value::String("restore-keys".into()),
-            Value::String("${{ runner.os }}-cargo-".into()),
+            Value::String(versioned_cache_key(
+                cache_version,
+                "${{ runner.os }}-cargo-",
+            )),
         );
 
         let mut step = Mapping::new();
@@ -900,15 +1379,18 @@ fn build_package_cache_steps(job: &JobDefinition) -> Vec<Mapping> {
         let mut with = Mapping::new();
         with.insert(
             Value::String("path".into()),
-            Value::String("~/.pnpm-store\nnode_modules".into()),
+            Value::String(format!("~/.pnpm-store\n{}", cache_path("node_modules")?)),
         );
         with.insert(
             Value::String("key".into()),
-            Value::String("${{ runner.os }}-pnpm-${{ hashFiles('**/pnpm-lock.yaml') }}".into()),
+            Value::String(versioned_cache_key(
+                cache_version,
+                "${{ runner.os }}-pnpm-${{ hashFiles('**/pnpm-lock.yaml') }}",
+            )),
         );
         with.insert(
             Value::String("restore-keys".into()),
-            Value::String("${{ runner.os }}-pnpm-".into()),
+            Value::String(versioned_cache_key(cache_version, "${{ runner.os }}-pnpm-")),
         );
 
         let mut step = Mapping::new();
@@ -924,7 +1406,39 @@ fn build_package_cache_steps(job: &JobDefinition) -> Vec<Mapping> {
         steps.push(step);
     }
 
-    steps
+    Ok(steps)
+}
+
+/// Render a user step, compiling its `if:` condition. Cache steps and
+/// provider-specific custom steps have no GitHub Actions equivalent and are
+/// skipped.
+fn render_step(step: &Step, index: usize, owner: &str) -> anyhow::Result<Option<Mapping>> {
+    let mut rendered = match &step.step_type {
+        Some(step::StepType::Run(run)) => convert_run_step(run),
+        Some(step::StepType::Uses(uses)) => convert_uses_step(uses),
+        Some(
+            step::StepType::RestoreCache(_)
+            | step::StepType::SaveCache(_)
+            | step::StepType::Custom(_),
+        )
+        | None => return Ok(None),
+    };
+    let if_key = Value::String("if".into());
+    if let Some(expression) = rendered.get(&if_key).and_then(Value::as_str) {
+        let compiled = github_step_condition(expression).with_context(|| {
+            let label = rendered
+                .get("name")
+                .and_then(Value::as_str)
+                .map(|name| format!(" ('{name}')"))
+                .unwrap_or_default();
+            format!(
+                "Unsupported condition on step {}{label} of {owner}",
+                index + 1
+            )
+        })?;
+        rendered.insert(if_key, Value::String(compiled));
+    }
+    Ok(Some(rendered))
 }
 
 fn convert_run_step(run: &RunStep) -> Mapping {
@@ -1007,6 +1521,7 @@ fn step_requires_node(step_type: &step::StepType) -> bool {
 
 fn is_node_action(module: &str) -> bool {
     module.starts_with("actions/cache@")
+        || module.starts_with("actions/cache/")
         || module.starts_with("actions/download-artifact@")
         || module.starts_with("actions/upload-artifact@")
         || module.starts_with("actions/github-script@")
@@ -1028,9 +1543,9 @@ fn make_diagnostic(workflow: &str, error: anyhow::Error) -> Diagnostic {
         level: diagnostic::Level::Error as i32,
         code: "GITHUB_GENERATE_ERROR".to_string(),
         title: format!("Failed to generate workflow '{workflow}'"),
-        message: error.to_string(),
+        message: format!("{error:#}"),
         fix_hint: String::new(),
-        loc: None,
+        loc: error_location(&error),
     }
 }
 
@@ -1038,6 +1553,10 @@ fn make_diagnostic(workflow: &str, error: anyhow::Error) -> Diagnostic {
 mod tests {
     use super::*;
 
+    fn empty_context() -> GithubContext<'static> {
+        GithubContext::new(Box::leak(Box::default()), &mut Vec::new()).unwrap()
+    }
+
     fn job_with_sources(id: &str, sources: &[&str]) -> JobDefinition {
         JobDefinition {
             id: id.to_string(),
@@ -1050,7 +1569,7 @@ mod tests {
     #[test]
     fn builder_job_does_not_receive_download_step() {
         let job = job_with_sources("build_cigen", &[]);
-        let rendered = render_job(&job, "ci", true).unwrap();
+        let rendered = render_job(&job, "ci", true, &empty_context()).unwrap();
 
         let steps_key = Value::String("steps".into());
         let step_values: Vec<Value> = rendered
@@ -1075,4 +1594,845 @@ mod tests {
                 .any(|name| name == "Download cigen binary" || name == "Prepare cigen binary")
         );
     }
+
+    #[test]
+    fn test_results_are_uploaded_as_artifact() {
+        let mut job = job_with_sources("rspec", &[]);
+        job.test_results = "tmp/junit".to_string();
+        let rendered = render_job(&job, "ci", false, &empty_context()).unwrap();
+
+        let steps = rendered
+            .get(Value::String("steps".into()))
+            .and_then(Value::as_sequence)
+            .unwrap();
+        let upload = steps
+            .iter()
+            .filter_map(Value::as_mapping)
+            .find(|step| {
+                step.get(Value::String("uses".into()))
+                    .and_then(Value::as_str)
+                    == Some("actions/upload-artifact@v4")
+            })
+            .expect("upload step");
+
+        assert_eq!(
+            upload
+                .get(Value::String("if".into()))
+                .and_then(Value::as_str),
+            Some("always()")
+        );
+        let with = upload
+            .get(Value::String("with".into()))
+            .and_then(Value::as_mapping)
+            .unwrap();
+        assert_eq!(
+            with.get(Value::String("name".into()))
+                .and_then(Value::as_str),
+            Some("test-results-rspec")
+        );
+    }
+
+    #[test]
+    fn test_splitting_runs_a_shard_matrix() {
+        let mut job = job_with_sources("rspec", &["spec/**/*.rb"]);
+        job.extra.insert("parallelism".to_string(), "3".to_string());
+        job.test_results = "tmp/junit".to_string();
+        job.test_splitting = Some(TestSplitting {
+            glob: "spec/**/*_spec.rb".to_string(),
+            by: "timings".to_string(),
+            command_template: "bundle exec rspec {files}".to_string(),
+        });
+        job.steps = vec![Step {
+            step_type: Some(step::StepType::Run(RunStep {
+                name: SPLIT_TESTS_STEP.to_string(),
+                command: "bundle exec rspec $TEST_FILES".to_string(),
+                env: HashMap::new(),
+                r#if: String::new(),
+            })),
+        }];
+        let rendered = render_job(&job, "ci", false, &empty_context()).unwrap();
+        let rendered = Value::Mapping(rendered);
+
+        assert!(rendered.get("parallelism").is_none());
+        assert_eq!(rendered["strategy"]["fail-fast"], Value::Bool(false));
+        let shards: Vec<u64> = rendered["strategy"]["matrix"]["shard"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .filter_map(Value::as_u64)
+            .collect();
+        assert_eq!(shards, [0, 1, 2]);
+
+        let steps = rendered["steps"].as_sequence().unwrap();
+        let split = steps
+            .iter()
+            .find(|step| step["name"].as_str() == Some(SPLIT_TESTS_STEP))
+            .expect("split step");
+        assert_eq!(split["shell"].as_str(), Some("bash"));
+        let upload = steps
+            .iter()
+            .find(|step| step["name"].as_str() == Some("Upload test results"))
+            .expect("upload step");
+        assert_eq!(
+            upload["with"]["name"].as_str(),
+            Some("test-results-rspec-${{ matrix.shard }}")
+        );
+
+        // Each shard runs different tests, so one passing mustn't skip the others
+        for name in ["Restore skip cache", "Save skip cache"] {
+            let cache = steps
+                .iter()
+                .find(|step| step["name"].as_str() == Some(name))
+                .expect(name);
+            assert_eq!(
+                cache["with"]["key"].as_str(),
+                Some(
+                    "job-skip-${{ runner.os }}-rspec-shard-${{ matrix.shard }}-${{ steps.compute_hash.outputs.job_hash }}"
+                )
+            );
+            assert_eq!(
+                cache["with"]["path"].as_str(),
+                Some(".cigen/skip-cache/rspec-shard-${{ matrix.shard }}")
+            );
+        }
+        let record = steps
+            .iter()
+            .find(|step| step["name"].as_str() == Some("Record job completion"))
+            .expect("record step");
+        assert!(
+            record["run"]
+                .as_str()
+                .unwrap()
+                .contains("MARKER='.cigen/skip-cache/rspec-shard-${{ matrix.shard }}/'"),
+            "{record:?}"
+        );
+    }
+
+    #[test]
+    fn step_conditions_compile_to_github_expressions() {
+        let mut job = job_with_sources("deploy", &[]);
+        job.steps = vec![Step {
+            step_type: Some(step::StepType::Run(RunStep {
+                name: "Deploy".to_string(),
+                command: "./deploy.sh".to_string(),
+                env: HashMap::new(),
+                r#if: r#"branch == "main" && env.DEPLOY_TOKEN defined"#.to_string(),
+            })),
+        }];
+        let rendered = render_job(&job, "ci", false, &empty_context()).unwrap();
+        let steps = rendered
+            .get(Value::String("steps".into()))
+            .and_then(Value::as_sequence)
+            .unwrap();
+        let deploy = steps
+            .iter()
+            .find(|step| step.get("name").and_then(Value::as_str) == Some("Deploy"))
+            .expect("deploy step");
+        assert_eq!(
+            deploy.get("if").and_then(Value::as_str),
+            Some("github.ref_name == 'main' && env.DEPLOY_TOKEN != ''")
+        );
+
+        job.steps[0] = Step {
+            step_type: Some(step::StepType::Run(RunStep {
+                name: "Deploy".to_string(),
+                command: "./deploy.sh".to_string(),
+                env: HashMap::new(),
+                r#if: "branch = main".to_string(),
+            })),
+        };
+        let error = format!(
+            "{:#}",
+            render_job(&job, "ci", false, &empty_context()).unwrap_err()
+        );
+        assert!(
+            error.contains("step 1 ('Deploy') of job 'deploy'"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn remote_docker_is_ignored_with_info_diagnostic() {
+        let mut job = job_with_sources("image", &[]);
+        job.remote_docker = Some(RemoteDocker {
+            version: "24.0".to_string(),
+            layer_caching: true,
+        });
+        let schema = CigenSchema {
+            jobs: vec![job],
+            ..Default::default()
+        };
+
+        let (fragments, diagnostics) = build_workflow_fragments(&schema);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].level, diagnostic::Level::Info as i32);
+        assert_eq!(diagnostics[0].code, "GITHUB_REMOTE_DOCKER_IGNORED");
+        assert!(!fragments[0].content.contains("remote_docker"));
+    }
+
+    #[test]
+    fn checkout_can_be_disabled_or_followed_by_post_steps() {
+        let mut job = job_with_sources("lfs", &[]);
+        job.checkout = [
+            ("fetch-depth".to_string(), "0".to_string()),
+            ("post".to_string(), "- run: git lfs pull".to_string()),
+        ]
+        .into();
+        let steps = build_checkout_steps(&job).unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0]["with"]["fetch-depth"].as_u64(), Some(0));
+        assert!(steps[0]["with"].get("post").is_none());
+        assert_eq!(steps[1]["run"].as_str(), Some("git lfs pull"));
+
+        job.checkout = [("enabled".to_string(), "false".to_string())].into();
+        assert!(build_checkout_steps(&job).unwrap().is_empty());
+
+        job.checkout = [("command".to_string(), "mirror".to_string())].into();
+        assert!(build_checkout_steps(&job).is_err());
+    }
+
+    #[test]
+    fn workflow_env_overrides_global_env() {
+        let schema = CigenSchema {
+            env: [
+                ("LEVEL".to_string(), "global".to_string()),
+                ("GLOBAL_ONLY".to_string(), "global".to_string()),
+            ]
+            .into(),
+            workflows: vec![WorkflowDefinition {
+                id: "ci".to_string(),
+                env: [("LEVEL".to_string(), "workflow".to_string())].into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let env = workflow_env(&schema, "ci");
+        assert_eq!(env.get("LEVEL").map(String::as_str), Some("workflow"));
+        assert_eq!(env.get("GLOBAL_ONLY").map(String::as_str), Some("global"));
+
+        let mut job = job_with_sources("test", &[]);
+        job.env = [("LEVEL".to_string(), "job".to_string())].into();
+        let context = GithubContext::new(&schema, &mut Vec::new()).unwrap();
+        let rendered =
+            render_workflow_file("ci", &[job], None, &env, None, &context, &mut Vec::new())
+                .unwrap();
+        let document: Value = serde_yaml::from_str(&rendered).unwrap();
+        assert_eq!(document["env"]["LEVEL"].as_str(), Some("workflow"));
+        assert_eq!(
+            document["jobs"]["test"]["env"]["LEVEL"].as_str(),
+            Some("job")
+        );
+    }
+
+    #[test]
+    fn workflow_file_starts_with_schema_comment() {
+        let schema = CigenSchema::default();
+        let context = GithubContext::new(&schema, &mut Vec::new()).unwrap();
+        let rendered = render_workflow_file(
+            "ci",
+            &[job_with_sources("test", &[])],
+            None,
+            &BTreeMap::new(),
+            None,
+            &context,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert!(rendered.starts_with(&schema_comment(GITHUB_ACTIONS_SCHEMA_URL)));
+        assert!(rendered.contains("# Source: .cigen/workflows/"));
+        let document: Value = serde_yaml::from_str(&rendered).unwrap();
+        assert!(document["jobs"]["test"].is_mapping());
+    }
+
+    fn schema_with_command(commands_as: &str, parameter_type: &str) -> CigenSchema {
+        let command = CommandDefinition {
+            description: "Set the app environment".to_string(),
+            parameters: [(
+                "env".to_string(),
+                CommandParameter {
+                    r#type: parameter_type.to_string(),
+                    default_yaml: "test".to_string(),
+                    ..Default::default()
+                },
+            )]
+            .into(),
+            steps: vec![Step {
+                step_type: Some(step::StepType::Run(RunStep {
+                    name: "Set env".to_string(),
+                    command: "echo APP_ENV=<< parameters.env >> >> $GITHUB_ENV".to_string(),
+                    ..Default::default()
+                })),
+            }],
+            ..Default::default()
+        };
+        let mut job = job_with_sources("test", &[]);
+        job.steps = vec![Step {
+            step_type: Some(step::StepType::Custom(CustomStep {
+                kind: "set_env".to_string(),
+                yaml: "set_env:\n  env: production\n".to_string(),
+                r#if: String::new(),
+            })),
+        }];
+        CigenSchema {
+            jobs: vec![job],
+            commands: [("set_env".to_string(), command)].into(),
+            raw_config_yaml: format!("github_actions:\n  commands_as: {commands_as}\n"),
+            ..Default::default()
+        }
+    }
+
+    fn generated(schema: &CigenSchema) -> (BTreeMap<String, Value>, Vec<Diagnostic>) {
+        let (fragments, diagnostics) = build_workflow_fragments(schema);
+        let files = fragments
+            .into_iter()
+            .map(|fragment| {
+                (
+                    fragment.path,
+                    serde_yaml::from_str(&fragment.content).unwrap(),
+                )
+            })
+            .collect();
+        (files, diagnostics)
+    }
+
+    fn user_steps(workflow: &Value) -> Vec<Value> {
+        workflow["jobs"]["test"]["steps"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .filter(|step| step["uses"].as_str() != Some("actions/checkout@v4"))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn commands_are_inlined_or_generated_as_composite_actions() {
+        let (inline, diagnostics) = generated(&schema_with_command("inline", "string"));
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        assert_eq!(
+            inline.keys().collect::<Vec<_>>(),
+            [".github/workflows/ci.yml"]
+        );
+        let steps = user_steps(&inline[".github/workflows/ci.yml"]);
+        assert_eq!(steps.len(), 1);
+        assert_eq!(
+            steps[0]["run"].as_str(),
+            Some("echo APP_ENV=production >> $GITHUB_ENV")
+        );
+
+        let (composite, diagnostics) =
+            generated(&schema_with_command("composite-actions", "string"));
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        assert_eq!(
+            composite.keys().collect::<Vec<_>>(),
+            [
+                ".github/actions/set_env/action.yml",
+                ".github/workflows/ci.yml"
+            ]
+        );
+        let steps = user_steps(&composite[".github/workflows/ci.yml"]);
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0]["uses"].as_str(), Some("./.github/actions/set_env"));
+        assert_eq!(steps[0]["with"]["env"].as_str(), Some("production"));
+
+        let action = &composite[".github/actions/set_env/action.yml"];
+        assert_eq!(
+            action["description"].as_str(),
+            Some("Set the app environment")
+        );
+        assert_eq!(action["inputs"]["env"]["default"].as_str(), Some("test"));
+        assert_eq!(action["inputs"]["env"]["required"].as_bool(), Some(false));
+        assert_eq!(action["runs"]["using"].as_str(), Some("composite"));
+        let action_step = &action["runs"]["steps"][0];
+        assert_eq!(
+            action_step["run"].as_str(),
+            Some("echo APP_ENV=${{ inputs.env }} >> $GITHUB_ENV")
+        );
+        assert_eq!(action_step["shell"].as_str(), Some("bash"));
+    }
+
+    #[test]
+    fn composite_action_inputs_are_passed_as_plain_strings() {
+        for (argument, expected) in [
+            ("\"3.3\"", "3.3"),
+            ("\"3.10\"", "3.10"),
+            ("true", "true"),
+            ("|\n    line one\n    line two\n", "line one\nline two\n"),
+        ] {
+            let mut schema = schema_with_command("composite-actions", "string");
+            let Some(step::StepType::Custom(custom)) = &mut schema.jobs[0].steps[0].step_type
+            else {
+                unreachable!()
+            };
+            custom.yaml = format!("set_env:\n  env: {argument}\n");
+            let (files, diagnostics) = generated(&schema);
+            assert!(diagnostics.is_empty(), "{diagnostics:?}");
+            let steps = user_steps(&files[".github/workflows/ci.yml"]);
+            assert_eq!(
+                steps[0]["with"]["env"].as_str(),
+                Some(expected),
+                "{argument}"
+            );
+        }
+    }
+
+    #[test]
+    fn commands_composite_actions_cannot_express_are_inlined() {
+        let (files, diagnostics) = generated(&schema_with_command("composite-actions", "enum"));
+        assert_eq!(
+            files.keys().collect::<Vec<_>>(),
+            [".github/workflows/ci.yml"]
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].level(), diagnostic::Level::Info);
+        assert_eq!(diagnostics[0].code, "GITHUB_COMMAND_INLINED");
+        assert!(
+            diagnostics[0]
+                .message
+                .contains("parameter 'env' has type 'enum'"),
+            "{}",
+            diagnostics[0].message
+        );
+        let steps = user_steps(&files[".github/workflows/ci.yml"]);
+        assert_eq!(
+            steps[0]["run"].as_str(),
+            Some("echo APP_ENV=production >> $GITHUB_ENV")
+        );
+    }
+
+    #[test]
+    fn steps_parameters_are_spliced_into_inlined_commands() {
+        let run = |command: &str| Step {
+            step_type: Some(step::StepType::Run(RunStep {
+                command: command.to_string(),
+                ..Default::default()
+            })),
+        };
+        let custom = |kind: &str, yaml: &str| Step {
+            step_type: Some(step::StepType::Custom(CustomStep {
+                kind: kind.to_string(),
+                yaml: yaml.to_string(),
+                r#if: String::new(),
+            })),
+        };
+        let command = CommandDefinition {
+            parameters: [(
+                "steps".to_string(),
+                CommandParameter {
+                    r#type: "steps".to_string(),
+                    ..Default::default()
+                },
+            )]
+            .into(),
+            steps: vec![
+                run("echo before"),
+                custom("<< parameters.steps >>", "<< parameters.steps >>\n"),
+                run("echo after"),
+            ],
+            ..Default::default()
+        };
+        let mut job = job_with_sources("test", &[]);
+        job.steps = vec![custom(
+            "with_retry",
+            "with_retry:\n  steps:\n    - run: make test\n    - run:\n        name: Lint\n        command: make lint\n",
+        )];
+        let mut schema = CigenSchema {
+            jobs: vec![job],
+            commands: [("with_retry".to_string(), command)].into(),
+            ..Default::default()
+        };
+
+        let (files, diagnostics) = generated(&schema);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        let steps = user_steps(&files[".github/workflows/ci.yml"]);
+        let commands: Vec<_> = steps
+            .iter()
+            .map(|step| step["run"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            commands,
+            ["echo before", "make test", "make lint", "echo after"]
+        );
+        assert_eq!(steps[2]["name"].as_str(), Some("Lint"));
+
+        schema.jobs[0].steps = vec![custom("with_retry", "with_retry\n")];
+        let (_, diagnostics) = generated(&schema);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(
+            diagnostics[0]
+                .message
+                .contains("Command 'with_retry' requires parameter 'steps'"),
+            "{}",
+            diagnostics[0].message
+        );
+    }
+
+    #[test]
+    fn jobs_with_source_files_skip_when_their_hash_is_cached() {
+        let mut job = job_with_sources("test", &["src/**/*.rs"]);
+        job.steps = vec![Step {
+            step_type: Some(step::StepType::Run(RunStep {
+                command: "cargo test".to_string(),
+                ..Default::default()
+            })),
+        }];
+        let rendered = Value::Mapping(render_job(&job, "ci", false, &empty_context()).unwrap());
+        let steps = rendered["steps"].as_sequence().unwrap();
+        let step = |name: &str| {
+            steps
+                .iter()
+                .find(|step| step["name"].as_str() == Some(name))
+                .unwrap_or_else(|| panic!("missing step {name}"))
+        };
+        let key = "job-skip-${{ runner.os }}-test-${{ steps.compute_hash.outputs.job_hash }}";
+        let guard = "steps.job_skip_cache.outputs.cache-hit != 'true'";
+
+        assert!(
+            step("Compute source hash")["run"]
+                .as_str()
+                .unwrap()
+                .contains("hash \\\n  --job 'test'")
+        );
+
+        let restore = step("Restore skip cache");
+        assert_eq!(restore["uses"].as_str(), Some("actions/cache/restore@v4"));
+        assert_eq!(restore["with"]["key"].as_str(), Some(key));
+        assert_eq!(
+            restore["with"]["path"].as_str(),
+            Some(".cigen/skip-cache/test")
+        );
+
+        let skip = step("Skip job (cached)");
+        assert_eq!(
+            skip["if"].as_str(),
+            Some("steps.job_skip_cache.outputs.cache-hit == 'true'")
+        );
+        assert!(skip["run"].as_str().unwrap().contains("skipped=true"));
+        assert_eq!(
+            rendered["outputs"]["skipped"].as_str(),
+            Some("${{ steps.job_skip.outputs.skipped || 'false' }}")
+        );
+
+        let user_step = steps
+            .iter()
+            .find(|step| step["run"].as_str() == Some("cargo test"))
+            .unwrap();
+        assert_eq!(user_step["if"].as_str(), Some(guard));
+
+        assert_eq!(
+            step("Record job completion")["if"].as_str(),
+            Some(format!("success() && {guard}").as_str())
+        );
+        let save = step("Save skip cache");
+        assert_eq!(save["uses"].as_str(), Some("actions/cache/save@v4"));
+        assert_eq!(save["with"]["key"].as_str(), Some(key));
+        assert_eq!(
+            save["if"].as_str(),
+            Some(format!("success() && {guard} && env.ACT != 'true'").as_str())
+        );
+        assert_eq!(steps.last(), Some(save));
+    }
+
+    #[test]
+    fn working_directory_sets_run_defaults_but_not_for_injected_steps() {
+        let mut job = job_with_sources("test", &["src/**/*"]);
+        job.working_directory = "frontend".to_string();
+        job.packages = vec!["node".to_string()];
+        job.test_results = "reports/junit".to_string();
+        job.steps = vec![Step {
+            step_type: Some(step::StepType::Run(RunStep {
+                command: "pnpm test".to_string(),
+                ..Default::default()
+            })),
+        }];
+        let rendered = Value::Mapping(render_job(&job, "ci", true, &empty_context()).unwrap());
+
+        assert_eq!(
+            rendered["defaults"]["run"]["working-directory"].as_str(),
+            Some("frontend")
+        );
+
+        let steps = rendered["steps"].as_sequence().unwrap();
+        let step = |name: &str| {
+            steps
+                .iter()
+                .find(|step| step["name"].as_str() == Some(name))
+                .unwrap_or_else(|| panic!("missing step {name}"))
+        };
+        // cigen's own steps hash and record from the repository root
+        for name in [
+            "Prepare cigen binary",
+            "Compute source hash",
+            "Record job completion",
+        ] {
+            assert_eq!(
+                step(name)["working-directory"].as_str(),
+                Some("${{ github.workspace }}"),
+                "{name}"
+            );
+        }
+        assert!(
+            step("Compute source hash")["run"]
+                .as_str()
+                .unwrap()
+                .contains("--base-dir .")
+        );
+
+        // Action inputs resolve from the workspace root, so job paths are prefixed
+        assert_eq!(
+            step("Restore pnpm cache")["with"]["path"].as_str(),
+            Some("~/.pnpm-store\nfrontend/node_modules")
+        );
+        assert_eq!(
+            step("Upload test results")["with"]["path"].as_str(),
+            Some("frontend/reports/junit")
+        );
+        assert!(
+            steps
+                .iter()
+                .find(|step| step["run"].as_str() == Some("pnpm test"))
+                .unwrap()
+                .get("working-directory")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn vm_executors_run_directly_on_the_runner() {
+        let mut job = job_with_sources("test", &[]);
+        job.executor = Some(Executor {
+            kind: "machine".to_string(),
+            ..Default::default()
+        });
+        job.architecture = "arm64".to_string();
+        let rendered = Value::Mapping(render_job(&job, "ci", false, &empty_context()).unwrap());
+        assert_eq!(rendered["runs-on"].as_str(), Some("ubuntu-24.04-arm"));
+        assert!(rendered.get("container").is_none());
+
+        job.executor = Some(Executor {
+            kind: "macos".to_string(),
+            xcode: "15.3".to_string(),
+            ..Default::default()
+        });
+        let rendered = Value::Mapping(render_job(&job, "ci", false, &empty_context()).unwrap());
+        assert_eq!(rendered["runs-on"].as_str(), Some("macos-latest"));
+        assert!(rendered.get("container").is_none());
+        assert!(rendered["steps"].as_sequence().unwrap().iter().any(|step| {
+            step["run"].as_str() == Some("sudo xcode-select -s /Applications/Xcode_15.3.app")
+        }));
+
+        job.services = vec!["postgres".to_string()];
+        let error = render_job(&job, "ci", false, &empty_context())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("only runs service containers on Linux runners"),
+            "{error}"
+        );
+
+        job.services.clear();
+        job.executor = Some(Executor {
+            kind: "named".to_string(),
+            name: "ruby".to_string(),
+            ..Default::default()
+        });
+        let error = render_job(&job, "ci", false, &empty_context())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("named executors are CircleCI-only"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn cache_version_prefixes_skip_and_package_cache_keys() {
+        let schema: &'static CigenSchema = Box::leak(Box::new(CigenSchema {
+            cache_version: 3,
+            ..Default::default()
+        }));
+        let context = GithubContext::new(schema, &mut Vec::new()).unwrap();
+        let mut job = job_with_sources("test", &["src/**/*.rs"]);
+        job.packages = vec!["rust".to_string()];
+
+        let rendered = Value::Mapping(render_job(&job, "ci", false, &context).unwrap());
+        let steps = rendered["steps"].as_sequence().unwrap();
+        let with = |name: &str| {
+            &steps
+                .iter()
+                .find(|step| step["name"].as_str() == Some(name))
+                .unwrap_or_else(|| panic!("missing step {name}"))["with"]
+        };
+        let skip_key =
+            "v3-job-skip-${{ runner.os }}-test-${{ steps.compute_hash.outputs.job_hash }}";
+        assert_eq!(with("Restore skip cache")["key"].as_str(), Some(skip_key));
+        assert_eq!(with("Save skip cache")["key"].as_str(), Some(skip_key));
+        assert_eq!(
+            with("Restore cargo cache")["key"].as_str(),
+            Some("v3-${{ runner.os }}-cargo-${{ hashFiles('**/Cargo.lock') }}")
+        );
+        assert_eq!(
+            with("Restore cargo cache")["restore-keys"].as_str(),
+            Some("v3-${{ runner.os }}-cargo-")
+        );
+    }
+
+    #[test]
+    fn cleanup_runs_after_failures_but_before_completion_is_recorded() {
+        let mut job = job_with_sources("test", &["src/**/*.rs"]);
+        job.cleanup = vec![Step {
+            step_type: Some(step::StepType::Run(RunStep {
+                name: "Drop test database".to_string(),
+                command: "dropdb test".to_string(),
+                ..Default::default()
+            })),
+        }];
+        let rendered = Value::Mapping(render_job(&job, "ci", false, &empty_context()).unwrap());
+        let steps = rendered["steps"].as_sequence().unwrap();
+        let position = |name: &str| {
+            steps
+                .iter()
+                .position(|step| step["name"].as_str() == Some(name))
+                .unwrap_or_else(|| panic!("missing step {name}"))
+        };
+        assert!(position("Drop test database") < position("Record job completion"));
+        assert_eq!(
+            steps[position("Drop test database")]["if"].as_str(),
+            Some("(always()) && (steps.job_skip_cache.outputs.cache-hit != 'true')")
+        );
+
+        job.cleanup_on = "failure".to_string();
+        let rendered = Value::Mapping(render_job(&job, "ci", false, &empty_context()).unwrap());
+        let cleanup = rendered["steps"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .find(|step| step["name"].as_str() == Some("Drop test database"))
+            .unwrap();
+        assert!(
+            cleanup["if"]
+                .as_str()
+                .unwrap()
+                .starts_with("(failure()) && ")
+        );
+    }
+
+    #[test]
+    fn jobs_without_source_files_never_skip() {
+        let job = job_with_sources("test", &[]);
+        let rendered = Value::Mapping(render_job(&job, "ci", false, &empty_context()).unwrap());
+        assert!(rendered.get("outputs").is_none());
+        assert!(
+            !rendered["steps"]
+                .as_sequence()
+                .unwrap()
+                .iter()
+                .any(|step| step["name"].as_str() == Some("Restore skip cache"))
+        );
+    }
+
+    fn ruby_variant(ruby: &str) -> JobDefinition {
+        JobDefinition {
+            id: format!("test-{ruby}"),
+            image: format!("ruby:{ruby}"),
+            env: [("RUBY_VERSION".to_string(), ruby.to_string())].into(),
+            matrix_job: "test".to_string(),
+            matrix_values: [("ruby".to_string(), ruby.to_string())].into(),
+            steps: vec![Step {
+                step_type: Some(step::StepType::Run(RunStep {
+                    command: "bundle exec rspec".to_string(),
+                    ..Default::default()
+                })),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn matrix_schema(variants: Vec<JobDefinition>, matrix_style: &str) -> CigenSchema {
+        let mut deploy = job_with_sources("deploy", &[]);
+        deploy.needs = variants.iter().map(|job| job.id.clone()).collect();
+        let mut jobs = variants;
+        jobs.push(deploy);
+        CigenSchema {
+            jobs,
+            raw_config_yaml: format!("github_actions:\n  matrix_style: {matrix_style}\n"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn native_matrix_style_collapses_variants_into_one_job() {
+        let schema = matrix_schema(vec![ruby_variant("3.2"), ruby_variant("3.3")], "native");
+        let (files, diagnostics) = generated(&schema);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        let jobs = &files[".github/workflows/ci.yml"]["jobs"];
+        assert_eq!(
+            jobs.as_mapping().unwrap().keys().collect::<Vec<_>>(),
+            ["deploy", "test"]
+        );
+        let test = &jobs["test"];
+        assert_eq!(test["strategy"]["fail-fast"], Value::Bool(false));
+        assert_eq!(
+            test["strategy"]["matrix"]["ruby"],
+            serde_yaml::from_str::<Value>("['3.2', '3.3']").unwrap()
+        );
+        assert_eq!(
+            test["container"]["image"].as_str(),
+            Some("ruby:${{ matrix.ruby }}")
+        );
+        assert_eq!(
+            test["env"]["RUBY_VERSION"].as_str(),
+            Some("${{ matrix.ruby }}")
+        );
+        assert_eq!(
+            jobs["deploy"]["needs"],
+            Value::Sequence(vec![Value::String("test".into())])
+        );
+
+        let (files, diagnostics) = generated(&matrix_schema(
+            vec![ruby_variant("3.2"), ruby_variant("3.3")],
+            "expanded",
+        ));
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        let jobs = files[".github/workflows/ci.yml"]["jobs"]
+            .as_mapping()
+            .unwrap();
+        assert_eq!(
+            jobs.keys().collect::<Vec<_>>(),
+            ["deploy", "test-3.2", "test-3.3"]
+        );
+    }
+
+    #[test]
+    fn native_matrix_style_expands_variants_with_different_steps() {
+        let mut legacy = ruby_variant("3.2");
+        legacy.steps.push(Step {
+            step_type: Some(step::StepType::Run(RunStep {
+                command: "bin/legacy-checks".to_string(),
+                ..Default::default()
+            })),
+        });
+        let (files, diagnostics) =
+            generated(&matrix_schema(vec![legacy, ruby_variant("3.3")], "native"));
+        let jobs = &files[".github/workflows/ci.yml"]["jobs"];
+        assert_eq!(
+            jobs.as_mapping().unwrap().keys().collect::<Vec<_>>(),
+            ["deploy", "test-3.2", "test-3.3"]
+        );
+        assert!(jobs["test-3.2"].get("strategy").is_none());
+        assert_eq!(
+            jobs["deploy"]["needs"],
+            serde_yaml::from_str::<Value>("[test-3.2, test-3.3]").unwrap()
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "GITHUB_MATRIX_EXPANDED");
+        assert!(
+            diagnostics[0]
+                .message
+                .contains("differ in more than env and image"),
+            "{}",
+            diagnostics[0].message
+        );
+    }
 }
@@ -0,0 +1,424 @@
+//! Native matrix jobs (`github_actions.matrix_style: native`)
+//!
+//! cigen expands matrix jobs into one job per variant. With the native style,
+//! variants that differ only in their env and image collapse back into a
+//! single job with a `strategy.matrix`, named after the matrix job. Jobs that
+//! needed any variant need the whole matrix job instead.
+
+use anyhow::{Result, bail};
+use cigen::plugin::protocol::{Diagnostic, JobDefinition, diagnostic};
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::commands::scalar_string;
+use super::determine_runner;
+
+/// Matrix values that only name the expanded jobs
+const NAMING_KEYS: [&str; 3] = ["stage", "job_name", "job_name_suffix"];
+
+/// How matrix jobs are rendered (`github_actions.matrix_style`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatrixStyle {
+    #[default]
+    Expanded,
+    Native,
+}
+
+impl MatrixStyle {
+    pub fn from_raw_config(raw_config: &Value) -> Result<Self> {
+        let Some(value) = raw_config
+            .get("github_actions")
+            .and_then(|options| options.get("matrix_style"))
+        else {
+            return Ok(Self::Expanded);
+        };
+        match value.as_str() {
+            Some("expanded") => Ok(Self::Expanded),
+            Some("native") => Ok(Self::Native),
+            _ => bail!(
+                "Invalid github_actions.matrix_style '{}': expected 'expanded' or 'native'",
+                scalar_string(value)
+            ),
+        }
+    }
+}
+
+/// `jobs` with the variants of each matrix job collapsed into one job where
+/// that renders the same workflow. The others stay expanded, with an info
+/// diagnostic saying why.
+pub fn collapse_matrix_jobs(
+    jobs: Vec<JobDefinition>,
+    step_timing: bool,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<JobDefinition> {
+    let mut groups: BTreeMap<&str, Vec<&JobDefinition>> = BTreeMap::new();
+    for job in jobs.iter().filter(|job| !job.matrix_job.is_empty()) {
+        groups.entry(&job.matrix_job).or_default().push(job);
+    }
+
+    let mut expanded = |name: &str, reason: &str| {
+        diagnostics.push(Diagnostic {
+            level: diagnostic::Level::Info as i32,
+            code: "GITHUB_MATRIX_EXPANDED".to_string(),
+            title: "Matrix job generated as separate jobs".to_string(),
+            message: format!(
+                "Matrix job '{name}' is generated as one job per variant because {reason}"
+            ),
+            fix_hint: String::new(),
+            loc: None,
+        });
+    };
+
+    let mut collapsed: BTreeMap<String, JobDefinition> = BTreeMap::new();
+    for (name, variants) in &groups {
+        if variants.len() < 2 {
+            continue;
+        }
+        if jobs
+            .iter()
+            .any(|job| job.id == *name && job.matrix_job != *name)
+        {
+            expanded(name, &format!("another job is named '{name}'"));
+            continue;
+        }
+        match collapse(name, variants, step_timing) {
+            Ok(job) => {
+                collapsed.insert(name.to_string(), job);
+            }
+            Err(reason) => expanded(name, &reason),
+        }
+    }
+
+    // Variants of the same job must need the same jobs once collapsed. Their
+    // needs can differ by architecture, and agree only when the jobs they
+    // need collapse too, so drop groups until the rest agree.
+    loop {
+        let renamed = renamed_variants(&jobs, &collapsed);
+        let disagreeing: Vec<String> = collapsed
+            .keys()
+            .filter(|name| {
+                let needs: BTreeSet<Vec<String>> = groups[name.as_str()]
+                    .iter()
+                    .map(|variant| collapsed_needs(&variant.needs, &renamed))
+                    .collect();
+                needs.len() > 1
+            })
+            .cloned()
+            .collect();
+        if disagreeing.is_empty() {
+            break;
+        }
+        for name in disagreeing {
+            collapsed.remove(&name);
+            expanded(&name, "its variants need different jobs");
+        }
+    }
+
+    let renamed = renamed_variants(&jobs, &collapsed);
+    let mut result = Vec::new();
+    for job in &jobs {
+        if let Some(mut matrix_job) = collapsed.remove(&job.matrix_job) {
+            matrix_job.needs = collapsed_needs(&job.needs, &renamed);
+            result.push(matrix_job);
+        } else if !renamed.contains_key(&job.id) {
+            result.push(JobDefinition {
+                needs: collapsed_needs(&job.needs, &renamed),
+                ..job.clone()
+            });
+        }
+    }
+    result
+}
+
+/// Collapsed variant ids mapped to the name of their matrix job
+fn renamed_variants(
+    jobs: &[JobDefinition],
+    collapsed: &BTreeMap<String, JobDefinition>,
+) -> HashMap<String, String> {
+    jobs.iter()
+        .filter(|job| collapsed.contains_key(&job.matrix_job))
+        .map(|job| (job.id.clone(), job.matrix_job.clone()))
+        .collect()
+}
+
+fn collapsed_needs(needs: &[String], renamed: &HashMap<String, String>) -> Vec<String> {
+    let mut collapsed = Vec::new();
+    for need in needs {
+        let need = renamed.get(need).unwrap_or(need);
+        if !collapsed.contains(need) {
+            collapsed.push(need.clone());
+        }
+    }
+    collapsed
+}
+
+/// The single job the variants collapse into, or why they can't
+fn collapse(
+    name: &str,
+    variants: &[&JobDefinition],
+    step_timing: bool,
+) -> std::result::Result<JobDefinition, String> {
+    let first = variants[0];
+    if first.is_approval() {
+        return Err("it is an approval job".to_string());
+    }
+    if first.extra.contains_key("strategy") {
+        return Err("it sets its own strategy".to_string());
+    }
+    if first.test_splitting.is_some() {
+        return Err("test_splitting runs its own shard matrix".to_string());
+    }
+    if !first.source_files.is_empty() {
+        return Err("it skips on source_files, which records each variant separately".to_string());
+    }
+    if !first.test_results.is_empty() || step_timing {
+        return Err("its variants upload artifacts named after the job".to_string());
+    }
+
+    // Everything but the id, env, image, needs and matrix values must match
+    let shape = |job: &JobDefinition| JobDefinition {
+        id: String::new(),
+        needs: Vec::new(),
+        env: HashMap::new(),
+        image: String::new(),
+        matrix_values: HashMap::new(),
+        ..job.clone()
+    };
+    let first_shape = shape(first);
+    let env_keys: BTreeSet<&String> = first.env.keys().collect();
+    if variants.iter().any(|variant| {
+        shape(variant) != first_shape || variant.env.keys().collect::<BTreeSet<_>>() != env_keys
+    }) {
+        return Err("its variants differ in more than env and image".to_string());
+    }
+
+    let rows: Vec<BTreeMap<String, String>> = variants
+        .iter()
+        .map(|variant| {
+            variant
+                .matrix_values
+                .iter()
+                .filter(|(key, _)| !NAMING_KEYS.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect()
+        })
+        .collect();
+    if rows.iter().any(BTreeMap::is_empty) {
+        return Err("its variants differ only in their names".to_string());
+    }
+
+    let mut env = HashMap::new();
+    for key in env_keys {
+        let values: Vec<&str> = variants
+            .iter()
+            .map(|variant| variant.env[key].as_str())
+            .collect();
+        let Some(template) = matrix_template(&values, &rows) else {
+            return Err(format!(
+                "env {key} can't be written in terms of the matrix values"
+            ));
+        };
+        env.insert(key.clone(), template);
+    }
+
+    let images: Vec<&str> = variants
+        .iter()
+        .map(|variant| variant.image.as_str())
+        .collect();
+    let Some(image) = matrix_template(&images, &rows) else {
+        return Err("its image can't be written in terms of the matrix values".to_string());
+    };
+    // A template that starts with an expression is read as a runner label
+    let in_container = |image: &str| determine_runner(image).1.is_some();
+    if images
+        .iter()
+        .any(|other| in_container(other) != in_container(&image))
+    {
+        return Err("its variants don't all run in a container or all on the runner".to_string());
+    }
+
+    let mut strategy = Mapping::new();
+    strategy.insert(Value::String("fail-fast".into()), Value::Bool(false));
+    strategy.insert(
+        Value::String("matrix".into()),
+        Value::Mapping(strategy_matrix(&rows)),
+    );
+    let mut extra = first.extra.clone();
+    extra.insert(
+        "strategy".to_string(),
+        serde_yaml::to_string(&Value::Mapping(strategy)).unwrap_or_default(),
+    );
+
+    Ok(JobDefinition {
+        id: name.to_string(),
+        env,
+        image,
+        extra,
+        matrix_values: HashMap::new(),
+        ..first.clone()
+    })
+}
+
+/// `values` with each variant's matrix values replaced by `${{ matrix.KEY }}`,
+/// when one template renders every variant's value
+fn matrix_template(values: &[&str], rows: &[BTreeMap<String, String>]) -> Option<String> {
+    if values.iter().all(|value| *value == values[0]) {
+        return Some(values[0].to_string());
+    }
+
+    // Longest values first, so `3.10` isn't read as `3.1` followed by `0`
+    let mut dimensions: Vec<(&String, &String)> = rows[0]
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .collect();
+    dimensions.sort_by_key(|(_, value)| std::cmp::Reverse(value.len()));
+
+    let mut template = String::new();
+    let mut rest = values[0];
+    'scan: while let Some(next) = rest.chars().next() {
+        for (key, value) in &dimensions {
+            if let Some(after) = rest.strip_prefix(value.as_str()) {
+                template.push_str(&format!("${{{{ matrix.{key} }}}}"));
+                rest = after;
+                continue 'scan;
+            }
+        }
+        template.push(next);
+        rest = &rest[next.len_utf8()..];
+    }
+
+    let render = |row: &BTreeMap<String, String>| {
+        row.iter().fold(template.clone(), |text, (key, value)| {
+            text.replace(&format!("${{{{ matrix.{key} }}}}"), value)
+        })
+    };
+    values
+        .iter()
+        .zip(rows)
+        .all(|(value, row)| render(row) == *value)
+        .then_some(template)
+}
+
+/// `strategy.matrix` running exactly `rows`: the dimensions when the rows are
+/// all their combinations, the dimensions and an `exclude` list when a few are
+/// missing, or an `include` list of the rows otherwise
+fn strategy_matrix(rows: &[BTreeMap<String, String>]) -> Mapping {
+    let keys: Vec<&String> = rows[0].keys().collect();
+    let mut dimensions: Vec<(&String, Vec<&String>)> =
+        keys.iter().map(|key| (*key, Vec::new())).collect();
+    for row in rows {
+        for (key, values) in &mut dimensions {
+            if let Some(value) = row.get(*key)
+                && !values.contains(&value)
+            {
+                values.push(value);
+            }
+        }
+    }
+
+    let row_value = |row: &BTreeMap<String, String>| {
+        Value::Mapping(
+            row.iter()
+                .map(|(key, value)| (Value::String(key.clone()), Value::String(value.clone())))
+                .collect(),
+        )
+    };
+    let mut matrix = Mapping::new();
+    let same_keys = rows.iter().all(|row| row.keys().eq(keys.iter().copied()));
+    let distinct: BTreeSet<&BTreeMap<String, String>> = rows.iter().collect();
+    if same_keys && distinct.len() == rows.len() {
+        let mut combinations: Vec<BTreeMap<String, String>> = vec![BTreeMap::new()];
+        for (key, values) in &dimensions {
+            combinations = combinations
+                .into_iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |value| {
+                        let mut combination = combination.clone();
+                        combination.insert((*key).clone(), (*value).clone());
+                        combination
+                    })
+                })
+                .collect();
+        }
+        let missing: Vec<&BTreeMap<String, String>> = combinations
+            .iter()
+            .filter(|combination| !distinct.contains(combination))
+            .collect();
+        if missing.len() < rows.len() {
+            for (key, values) in &dimensions {
+                matrix.insert(
+                    Value::String((*key).clone()),
+                    Value::Sequence(
+                        values
+                            .iter()
+                            .map(|value| Value::String((*value).clone()))
+                            .collect(),
+                    ),
+                );
+            }
+            if !missing.is_empty() {
+                matrix.insert(
+                    Value::String("exclude".into()),
+                    Value::Sequence(missing.into_iter().map(row_value).collect()),
+                );
+            }
+            return matrix;
+        }
+    }
+    matrix.insert(
+        Value::String("include".into()),
+        Value::Sequence(rows.iter().map(row_value).collect()),
+    );
+    matrix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(values: &[(&str, &str)]) -> BTreeMap<String, String> {
+        values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn templates_recover_the_matrix_values() {
+        let rows = [row(&[("ruby", "3.2")]), row(&[("ruby", "3.10")])];
+        assert_eq!(
+            matrix_template(&["ruby:3.2-slim", "ruby:3.10-slim"], &rows).as_deref(),
+            Some("ruby:${{ matrix.ruby }}-slim")
+        );
+        assert_eq!(
+            matrix_template(&["same", "same"], &rows).as_deref(),
+            Some("same")
+        );
+        assert_eq!(matrix_template(&["a", "b"], &rows), None);
+    }
+
+    #[test]
+    fn matrices_use_dimensions_with_excludes_or_include_rows() {
+        let full = [
+            row(&[("db", "mysql"), ("ruby", "3.2")]),
+            row(&[("db", "mysql"), ("ruby", "3.3")]),
+            row(&[("db", "postgres"), ("ruby", "3.2")]),
+            row(&[("db", "postgres"), ("ruby", "3.3")]),
+        ];
+        let matrix = strategy_matrix(&full);
+        assert_eq!(
+            serde_yaml::to_string(&matrix).unwrap(),
+            "db:\n- mysql\n- postgres\nruby:\n- '3.2'\n- '3.3'\n"
+        );
+
+        let matrix = strategy_matrix(&full[..3]);
+        assert_eq!(
+            matrix["exclude"],
+            Value::Sequence(vec![serde_yaml::to_value(&full[3]).unwrap()])
+        );
+
+        let matrix = strategy_matrix(&[full[0].clone(), full[3].clone()]);
+        assert_eq!(matrix.keys().collect::<Vec<_>>(), ["include"]);
+        assert_eq!(matrix["include"].as_sequence().unwrap().len(), 2);
+    }
+}
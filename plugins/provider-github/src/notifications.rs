@@ -0,0 +1,148 @@
+//! Slack notifications for GitHub Actions
+//!
+//! A final job needs every other job in the workflow and posts to a Slack
+//! incoming webhook with curl. The webhook URL comes from a repository secret;
+//! the message lists the jobs that failed, read from `needs`.
+
+use cigen::plugin::protocol::{JobDefinition, SlackNotification};
+use serde_yaml::{Mapping, Value};
+
+/// Id of the generated notification job
+pub const NOTIFY_JOB_ID: &str = "notify_slack";
+
+const NOTIFY_RUNNER: &str = "ubuntu-latest";
+
+/// Shell that posts the message. `$NEEDS` is the `needs` context as JSON.
+fn notify_script(slack: &SlackNotification) -> String {
+    let wants = |event: &str| slack.events.iter().any(|wanted| wanted == event);
+    let mut script = String::from(
+        "failed=$(echo \"$NEEDS\" | jq -r '[to_entries[] | select(.value.result == \"failure\") | .key] | join(\", \")')\n\
+         run_url=\"$GITHUB_SERVER_URL/$GITHUB_REPOSITORY/actions/runs/$GITHUB_RUN_ID\"\n\
+         if [ -n \"$failed\" ]; then\n  \
+           text=\":x: $GITHUB_WORKFLOW failed on $GITHUB_REF_NAME: $failed\"\n\
+         else\n",
+    );
+    if wants("fixed") && !wants("success") {
+        // Only passes that follow a failed run are worth a message
+        script.push_str(
+            "  previous=$(gh run list --repo \"$GITHUB_REPOSITORY\" --workflow \"$GITHUB_WORKFLOW\" --branch \"$GITHUB_REF_NAME\" --status completed --limit 1 --json conclusion --jq '.[0].conclusion // \"\"')\n  \
+               if [ \"$previous\" != \"failure\" ]; then exit 0; fi\n  \
+               text=\":white_check_mark: $GITHUB_WORKFLOW is fixed on $GITHUB_REF_NAME\"\n",
+        );
+    } else {
+        script.push_str(
+            "  text=\":white_check_mark: $GITHUB_WORKFLOW passed on $GITHUB_REF_NAME\"\n",
+        );
+    }
+    script.push_str(
+        "fi\n\
+         jq -n --arg channel \"$CHANNEL\" --arg text \"$text (<$run_url|run>)\" '{channel: $channel, text: $text}' \\\n  \
+           | curl -fsS -X POST -H 'Content-Type: application/json' --data @- \"$SLACK_WEBHOOK_URL\"\n",
+    );
+    script
+}
+
+/// `if:` for the notification job: it runs after the others whatever their
+/// result, then filters on `failure()`/`success()`
+fn notify_condition(slack: &SlackNotification) -> &'static str {
+    let on_failure = slack.events.iter().any(|event| event == "failure");
+    let on_pass = slack
+        .events
+        .iter()
+        .any(|event| event == "success" || event == "fixed");
+    match (on_failure, on_pass) {
+        (true, true) => "${{ !cancelled() }}",
+        (false, true) => "${{ success() }}",
+        _ => "${{ failure() }}",
+    }
+}
+
+/// The job that reports the workflow's results to Slack
+pub fn render_slack_job(slack: &SlackNotification, jobs: &[JobDefinition]) -> Mapping {
+    let fixed = slack.events.iter().any(|event| event == "fixed");
+
+    let mut env = Mapping::new();
+    env.insert(
+        Value::String("SLACK_WEBHOOK_URL".into()),
+        Value::String(format!("${{{{ secrets.{} }}}}", slack.webhook_secret)),
+    );
+    env.insert(
+        Value::String("CHANNEL".into()),
+        Value::String(slack.channel.clone()),
+    );
+    env.insert(
+        Value::String("NEEDS".into()),
+        Value::String("${{ toJSON(needs) }}".into()),
+    );
+    if fixed {
+        env.insert(
+            Value::String("GH_TOKEN".into()),
+            Value::String("${{ github.token }}".into()),
+        );
+    }
+
+    let mut step = Mapping::new();
+    step.insert(
+        Value::String("name".into()),
+        Value::String("Notify Slack".into()),
+    );
+    step.insert(Value::String("env".into()), Value::Mapping(env));
+    step.insert(
+        Value::String("run".into()),
+        Value::String(notify_script(slack)),
+    );
+
+    let mut job_map = Mapping::new();
+    job_map.insert(
+        Value::String("runs-on".into()),
+        Value::String(NOTIFY_RUNNER.into()),
+    );
+    job_map.insert(
+        Value::String("needs".into()),
+        Value::Sequence(
+            jobs.iter()
+                .map(|job| Value::String(job.id.clone()))
+                .collect(),
+        ),
+    );
+    job_map.insert(
+        Value::String("if".into()),
+        Value::String(notify_condition(slack).into()),
+    );
+    if fixed {
+        let mut permissions = Mapping::new();
+        permissions.insert(
+            Value::String("actions".into()),
+            Value::String("read".into()),
+        );
+        job_map.insert(
+            Value::String("permissions".into()),
+            Value::Mapping(permissions),
+        );
+    }
+    job_map.insert(
+        Value::String("steps".into()),
+        Value::Sequence(vec![Value::Mapping(step)]),
+    );
+    job_map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_checks_the_previous_run() {
+        let slack = SlackNotification {
+            channel: "#ci".to_string(),
+            events: vec!["fixed".to_string()],
+            webhook_secret: "SLACK_WEBHOOK_URL".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(notify_condition(&slack), "${{ success() }}");
+        let script = notify_script(&slack);
+        assert!(script.contains("gh run list"), "{script}");
+        assert!(script.contains("if [ \"$previous\" != \"failure\" ]; then exit 0; fi"));
+    }
+}
@@ -0,0 +1,368 @@
+//! Service containers from the top-level `services` config
+
+use anyhow::{Result, bail};
+use cigen::plugin::diagnostics::located_error_in;
+use cigen::plugin::protocol::JobDefinition;
+use cigen::schema::{
+    ServicePort, ServiceWait, parse_service_ports, unknown_reference_message,
+    wait_for_service_command,
+};
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ServiceDefinition {
+    pub image: String,
+    pub env: Vec<(String, String)>,
+    pub ports: Vec<ServicePort>,
+    pub health_check: Option<HealthCheck>,
+    pub wait_for: Option<ServiceWait>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HealthCheck {
+    pub command: Option<String>,
+    pub interval: Option<String>,
+    pub timeout: Option<String>,
+    pub retries: Option<u64>,
+}
+
+pub fn extract_services(raw_config: &Value) -> Result<HashMap<String, ServiceDefinition>> {
+    let mut services = HashMap::new();
+    let Some(Value::Mapping(service_map)) = raw_config.get("services") else {
+        return Ok(services);
+    };
+
+    for (key, value) in service_map {
+        let Some(name) = key.as_str() else { continue };
+        let Some(image) = value.get("image").and_then(Value::as_str) else {
+            bail!("Service '{name}' must set an image");
+        };
+
+        let env = match value.get("environment") {
+            Some(Value::Mapping(map)) => map
+                .iter()
+                .map(|(key, value)| (scalar_string(key), scalar_string(value)))
+                .collect(),
+            Some(Value::Sequence(entries)) => entries
+                .iter()
+                .map(|entry| {
+                    let entry = scalar_string(entry);
+                    match entry.split_once('=') {
+                        Some((key, value)) => (key.to_string(), value.to_string()),
+                        None => (entry, String::new()),
+                    }
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let ports = parse_service_ports(name, value.get("ports"))?;
+
+        let health_check = value.get("health_check").map(|check| HealthCheck {
+            command: check.get("command").map(scalar_string),
+            interval: check.get("interval").map(scalar_string),
+            timeout: check.get("timeout").map(scalar_string),
+            retries: check.get("retries").and_then(Value::as_u64),
+        });
+        let wait_for = ServiceWait::parse(name, value.get("wait_for"))?;
+        if wait_for.is_some() && ports.is_empty() {
+            bail!("Service '{name}' sets wait_for but lists no ports to wait for");
+        }
+
+        services.insert(
+            name.to_string(),
+            ServiceDefinition {
+                image: image.to_string(),
+                env,
+                ports,
+                health_check,
+                wait_for,
+            },
+        );
+    }
+
+    Ok(services)
+}
+
+/// The job's `services:` block, or `None` when it references no services
+pub fn job_services(
+    job: &JobDefinition,
+    services: &HashMap<String, ServiceDefinition>,
+) -> Result<Option<Mapping>> {
+    if job.services.is_empty() {
+        return Ok(None);
+    }
+
+    let mut mapping = Mapping::new();
+    for service in &job.services {
+        let Some(definition) = services.get(service) else {
+            let message = unknown_reference_message(
+                &format!(
+                    "Unknown GitHub Actions service '{service}' referenced by job '{}'",
+                    job.id
+                ),
+                service,
+                services.keys().map(String::as_str),
+            );
+            return Err(located_error_in(
+                message,
+                &job.source_file,
+                &job.source_section,
+                service,
+            ));
+        };
+        mapping.insert(
+            Value::String(service.clone()),
+            Value::Mapping(definition.to_github()),
+        );
+    }
+    Ok(Some(mapping))
+}
+
+/// Steps that wait for the job's `wait_for` services to accept connections.
+/// Services with a health check command are skipped: GitHub Actions already
+/// waits for them to be healthy before the job starts. Jobs in a container
+/// reach services by name; jobs on the runner use the published ports.
+pub fn wait_for_services_steps(
+    job: &JobDefinition,
+    services: &HashMap<String, ServiceDefinition>,
+    in_container: bool,
+) -> Vec<Mapping> {
+    job.services
+        .iter()
+        .filter_map(|service| {
+            let definition = services.get(service)?;
+            let wait = definition.wait_for?;
+            if definition
+                .health_check
+                .as_ref()
+                .is_some_and(|check| check.command.is_some())
+            {
+                return None;
+            }
+            let (host, ports): (&str, Vec<String>) = if in_container {
+                (
+                    service,
+                    definition
+                        .ports
+                        .iter()
+                        .map(|port| port.container.to_string())
+                        .collect(),
+                )
+            } else {
+                (
+                    "localhost",
+                    definition
+                        .ports
+                        .iter()
+                        .map(|port| {
+                            format!(
+                                "${{{{ job.services.{service}.ports['{}'] }}}}",
+                                port.container
+                            )
+                        })
+                        .collect(),
+                )
+            };
+
+            let mut step = Mapping::new();
+            step.insert("name".into(), Value::String(format!("Wait for {service}")));
+            step.insert(
+                "run".into(),
+                Value::String(wait_for_service_command(service, host, &ports, wait)),
+            );
+            Some(step)
+        })
+        .collect()
+}
+
+impl ServiceDefinition {
+    fn to_github(&self) -> Mapping {
+        let mut container = Mapping::new();
+        container.insert("image".into(), Value::String(self.image.clone()));
+        if !self.env.is_empty() {
+            let env = self
+                .env
+                .iter()
+                .map(|(key, value)| (Value::String(key.clone()), Value::String(value.clone())))
+                .collect();
+            container.insert("env".into(), Value::Mapping(env));
+        }
+        if !self.ports.is_empty() {
+            let ports = self
+                .ports
+                .iter()
+                .map(|port| Value::String(port.to_string()))
+                .collect();
+            container.insert("ports".into(), Value::Sequence(ports));
+        }
+        if let Some(options) = self
+            .health_check
+            .as_ref()
+            .and_then(HealthCheck::docker_options)
+        {
+            container.insert("options".into(), Value::String(options));
+        }
+        container
+    }
+}
+
+impl HealthCheck {
+    /// `docker create` flags, which is how GitHub Actions configures health checks
+    fn docker_options(&self) -> Option<String> {
+        let mut options = Vec::new();
+        if let Some(command) = &self.command {
+            options.push(format!(
+                "--health-cmd \"{}\"",
+                command.replace('\\', "\\\\").replace('"', "\\\"")
+            ));
+        }
+        if let Some(interval) = &self.interval {
+            options.push(format!("--health-interval {interval}"));
+        }
+        if let Some(timeout) = &self.timeout {
+            options.push(format!("--health-timeout {timeout}"));
+        }
+        if let Some(retries) = self.retries {
+            options.push(format!("--health-retries {retries}"));
+        }
+        (!options.is_empty()).then(|| options.join(" "))
+    }
+}
+
+fn scalar_string(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Bool(flag) => flag.to_string(),
+        Value::Number(number) => number.to_string(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn services_map_to_github_service_containers() {
+        let raw: Value = serde_yaml::from_str(
+            r#"
+services:
+  postgres:
+    image: postgres:16
+    environment:
+      POSTGRES_PASSWORD: postgres
+    ports: ["5432:5432"]
+    health_check:
+      command: pg_isready -U "postgres"
+      interval: 10s
+      retries: 5
+  redis:
+    image: redis:7
+    environment: [REDIS_ARGS=--save ""]
+    ports: [6379]
+"#,
+        )
+        .unwrap();
+        let services = extract_services(&raw).unwrap();
+        let job = JobDefinition {
+            id: "test".to_string(),
+            services: vec!["postgres".to_string(), "redis".to_string()],
+            ..Default::default()
+        };
+
+        let rendered = Value::Mapping(job_services(&job, &services).unwrap().unwrap());
+        let expected: Value = serde_yaml::from_str(
+            r#"
+postgres:
+  image: postgres:16
+  env:
+    POSTGRES_PASSWORD: postgres
+  ports: ["5432:5432"]
+  options: --health-cmd "pg_isready -U \"postgres\"" --health-interval 10s --health-retries 5
+redis:
+  image: redis:7
+  env:
+    REDIS_ARGS: --save ""
+  ports: ["6379"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn wait_for_steps_skip_services_with_health_checks() {
+        let raw: Value = serde_yaml::from_str(
+            r#"
+services:
+  postgres:
+    image: postgres:16
+    ports: [5432]
+    wait_for: true
+    health_check:
+      command: pg_isready
+  redis:
+    image: redis:7
+    ports: ["6379:6379"]
+    wait_for: { timeout: 30s }
+  minio:
+    image: minio/minio
+    ports: [9000]
+"#,
+        )
+        .unwrap();
+        let services = extract_services(&raw).unwrap();
+        let job = JobDefinition {
+            id: "test".to_string(),
+            services: vec![
+                "postgres".to_string(),
+                "redis".to_string(),
+                "minio".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let on_runner = wait_for_services_steps(&job, &services, false);
+        assert_eq!(on_runner.len(), 1);
+        assert_eq!(on_runner[0]["name"].as_str(), Some("Wait for redis"));
+        let command = on_runner[0]["run"].as_str().unwrap();
+        assert!(
+            command.starts_with("deadline=$((SECONDS + 30))\n"),
+            "{command}"
+        );
+        assert!(
+            command.contains(
+                "until (echo > /dev/tcp/localhost/${{ job.services.redis.ports['6379'] }}) >/dev/null 2>&1; do"
+            ),
+            "{command}"
+        );
+
+        let in_container = wait_for_services_steps(&job, &services, true);
+        let command = in_container[0]["run"].as_str().unwrap();
+        assert!(command.contains("/dev/tcp/redis/6379)"), "{command}");
+    }
+
+    #[test]
+    fn unknown_service_suggests_a_defined_one() {
+        let services = HashMap::from([(
+            "postgres".to_string(),
+            ServiceDefinition {
+                image: "postgres:16".to_string(),
+                ..Default::default()
+            },
+        )]);
+        let job = JobDefinition {
+            id: "test".to_string(),
+            services: vec!["postgrse".to_string()],
+            ..Default::default()
+        };
+        let error = job_services(&job, &services).unwrap_err().to_string();
+        assert!(
+            error.starts_with("Unknown GitHub Actions service 'postgrse' referenced by job 'test'"),
+            "{error}"
+        );
+        assert!(error.contains("postgres"), "{error}");
+    }
+}
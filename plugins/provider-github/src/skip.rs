@@ -0,0 +1,189 @@
+//! Job-status skip cache for GitHub Actions
+//!
+//! Jobs with `source_files` compute their hash with `cigen hash --job`, then
+//! restore a marker from `actions/cache` keyed on that hash. A hit means the job
+//! already passed for these sources: the job reports `skipped=true` as an output
+//! and every later step is guarded by [`SkipFlow::condition`]. After a
+//! successful run the marker is written and saved under the same key.
+//!
+//! A test-splitting job runs once per `matrix.shard`, and each shard runs
+//! different tests, so each one keeps its own marker and key.
+
+use cigen::schema::{shell_quote, versioned_cache_key};
+use serde_yaml::{Mapping, Value};
+
+/// Step id of the hash computation; its `job_hash` output keys the cache
+const COMPUTE_STEP_ID: &str = "compute_hash";
+/// Step id of the cache restore; its `cache-hit` output drives the guards
+const RESTORE_STEP_ID: &str = "job_skip_cache";
+/// Step id of the early exit, which sets the job's `skipped` output
+const SKIP_STEP_ID: &str = "job_skip";
+
+/// The cache doesn't work under `act`, so local runs never skip
+const NOT_ACT: &str = "env.ACT != 'true'";
+
+pub struct SkipFlow {
+    pub compute_step: Mapping,
+    pub restore_step: Mapping,
+    pub skip_step: Mapping,
+    pub record_step: Mapping,
+    pub save_step: Mapping,
+    /// Guard for every step that should only run when the job isn't skipped
+    pub condition: String,
+}
+
+pub fn build_skip_flow(job_id: &str, sharded: bool, cache_version: Option<u32>) -> SkipFlow {
+    let scope = if sharded {
+        format!("{job_id}-shard-${{{{ matrix.shard }}}}")
+    } else {
+        job_id.to_string()
+    };
+    let key = cache_key(&scope, cache_version);
+    let condition = format!("steps.{RESTORE_STEP_ID}.outputs.cache-hit != 'true'");
+    let hash = format!("${{{{ steps.{COMPUTE_STEP_ID}.outputs.job_hash }}}}");
+
+    SkipFlow {
+        compute_step: compute_step(job_id),
+        restore_step: cache_step(
+            "Restore skip cache",
+            Some(RESTORE_STEP_ID),
+            "actions/cache/restore@v4",
+            &scope,
+            &key,
+            NOT_ACT.to_string(),
+        ),
+        skip_step: skip_step(),
+        record_step: record_step(&scope, &hash, &condition),
+        save_step: cache_step(
+            "Save skip cache",
+            None,
+            "actions/cache/save@v4",
+            &scope,
+            &key,
+            format!("success() && {condition} && {NOT_ACT}"),
+        ),
+        condition,
+    }
+}
+
+/// Job `outputs:` entry so dependent jobs can tell the job was skipped
+pub fn skipped_output() -> (Value, Value) {
+    (
+        Value::String("skipped".into()),
+        Value::String(format!(
+            "${{{{ steps.{SKIP_STEP_ID}.outputs.skipped || 'false' }}}}"
+        )),
+    )
+}
+
+/// `scope` is the job id, plus the shard for test-splitting jobs
+fn cache_key(scope: &str, cache_version: Option<u32>) -> String {
+    versioned_cache_key(
+        cache_version,
+        &format!(
+            "job-skip-${{{{ runner.os }}}}-{scope}-${{{{ steps.{COMPUTE_STEP_ID}.outputs.job_hash }}}}"
+        ),
+    )
+}
+
+fn compute_step(job_id: &str) -> Mapping {
+    // Prefer the binary from the build_cigen job, falling back to one on PATH
+    let script = format!(
+        concat!(
+            "set -euo pipefail\n",
+            "mkdir -p .cigen/skip-cache\n",
+            "mkdir -p .cigen/cache\n",
+            "CIGEN=./.cigen/bin/cigen\n",
+            "[ -x \"$CIGEN\" ] || CIGEN=cigen\n",
+            "\"$CIGEN\" hash \\\n",
+            "  --job {job_id} \\\n",
+            "  --config .cigen \\\n",
+            "  --base-dir . \\\n",
+            "  --output job_hash \\\n",
+            "  --cache .cigen/cache/file-hashes.json\n"
+        ),
+        job_id = shell_quote(job_id)
+    );
+
+    let mut step = Mapping::new();
+    step.insert("name".into(), Value::String("Compute source hash".into()));
+    step.insert("id".into(), Value::String(COMPUTE_STEP_ID.into()));
+    step.insert("run".into(), Value::String(script));
+    step
+}
+
+fn cache_step(
+    name: &str,
+    id: Option<&str>,
+    uses: &str,
+    scope: &str,
+    key: &str,
+    condition: String,
+) -> Mapping {
+    let mut with = Mapping::new();
+    with.insert(
+        "path".into(),
+        Value::String(format!(".cigen/skip-cache/{scope}")),
+    );
+    with.insert("key".into(), Value::String(key.into()));
+
+    let mut step = Mapping::new();
+    step.insert("name".into(), Value::String(name.into()));
+    if let Some(id) = id {
+        step.insert("id".into(), Value::String(id.into()));
+    }
+    step.insert("uses".into(), Value::String(uses.into()));
+    step.insert("with".into(), Value::Mapping(with));
+    step.insert("if".into(), Value::String(condition));
+    step
+}
+
+fn skip_step() -> Mapping {
+    let mut step = Mapping::new();
+    step.insert("name".into(), Value::String("Skip job (cached)".into()));
+    step.insert("id".into(), Value::String(SKIP_STEP_ID.into()));
+    step.insert(
+        "if".into(),
+        Value::String(format!(
+            "steps.{RESTORE_STEP_ID}.outputs.cache-hit == 'true'"
+        )),
+    );
+    step.insert(
+        "run".into(),
+        Value::String(
+            "echo 'skipped=true' >> \"$GITHUB_OUTPUT\"\necho 'Job cache hit; skipping remaining steps.'\n"
+                .into(),
+        ),
+    );
+    step
+}
+
+fn record_step(scope: &str, hash: &str, condition: &str) -> Mapping {
+    let marker_dir = shell_quote(&format!(".cigen/skip-cache/{scope}/"));
+    let mut env = Mapping::new();
+    env.insert("JOB_HASH".into(), Value::String(hash.into()));
+
+    let mut step = Mapping::new();
+    step.insert("name".into(), Value::String("Record job completion".into()));
+    step.insert(
+        "if".into(),
+        Value::String(format!("success() && {condition}")),
+    );
+    step.insert(
+        "run".into(),
+        Value::String(format!(
+            "set -e
+HASH=\"${{JOB_HASH}}\"
+if [ -z \"$HASH\" ]; then
+  echo 'JOB_HASH missing' >&2
+  exit 1
+fi
+MARKER={marker_dir}\"$HASH\"
+mkdir -p \"$(dirname \"$MARKER\")\"
+date > \"$MARKER\"
+"
+        )),
+    );
+    step.insert("env".into(), Value::Mapping(env));
+    step
+}
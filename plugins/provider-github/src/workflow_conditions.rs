@@ -0,0 +1,339 @@
+//! Workflow `run_when`/`run_unless` and job `unless:` conditions for GitHub
+//! Actions
+//!
+//! GitHub decides per job whether it runs, so a workflow's conditions become
+//! an `if:` guard on each of its jobs, with the `run_unless` clause negated. Parameter conditions read
+//! `workflow_dispatch` inputs, declared from the pipeline `parameters:` in
+//! `config.yml`. Pushes and pull requests have no inputs, so a parameter
+//! condition that its default satisfies also lets those events through.
+
+use anyhow::{Context, Result, anyhow, bail};
+use cigen::plugin::protocol::{WorkflowCondition, WorkflowConditionKind, WorkflowDefinition};
+use cigen::schema::Condition;
+use serde_yaml::{Mapping, Value};
+
+use crate::conditions::github_step_condition;
+
+/// What a workflow's conditions add to the generated file
+#[derive(Debug, Default, PartialEq)]
+pub struct WorkflowGuard {
+    /// Expression each job's `if:` must satisfy (without `${{ }}`)
+    pub condition: String,
+    /// `workflow_dispatch` inputs the condition reads
+    pub inputs: Mapping,
+}
+
+/// Compile the GitHub conditions of `workflow`. `parameters` is the pipeline
+/// `parameters:` mapping from `config.yml`.
+pub fn workflow_guard(
+    workflow: &WorkflowDefinition,
+    parameters: &Mapping,
+) -> Result<Option<WorkflowGuard>> {
+    let mut guard = WorkflowGuard::default();
+    let when = all_of(&workflow.run_when, parameters, &mut guard.inputs)?;
+    let unless = all_of(&workflow.run_unless, parameters, &mut guard.inputs)?;
+    guard.condition = match (when, unless) {
+        (None, None) => return Ok(None),
+        (Some(when), None) => when,
+        (None, Some(unless)) => format!("!({unless})"),
+        (Some(when), Some(unless)) => format!("({when}) && !({unless})"),
+    };
+    Ok(Some(guard))
+}
+
+/// `if:` guard for a job's `unless:` condition
+pub fn job_unless_guard(expression: &str) -> Result<String> {
+    Ok(format!("!({})", expression_clause(expression)?))
+}
+
+/// The GitHub conditions among `conditions` ANDed into one expression
+fn all_of(
+    conditions: &[WorkflowCondition],
+    parameters: &Mapping,
+    inputs: &mut Mapping,
+) -> Result<Option<String>> {
+    let mut clauses = Vec::new();
+    for condition in conditions {
+        if !condition.provider.is_empty() && condition.provider != "github" {
+            continue;
+        }
+        let clause = match condition.kind() {
+            WorkflowConditionKind::Parameter => parameter_clause(condition, parameters, inputs)?,
+            WorkflowConditionKind::Variable => {
+                let value = match equals_value(condition)? {
+                    Value::String(value) => value,
+                    other => serde_yaml::to_string(&other)?.trim_end().to_string(),
+                };
+                format!("vars.{} == {}", condition.key, quote(&value))
+            }
+            WorkflowConditionKind::Env => bail!(
+                "env conditions are not supported on GitHub Actions, where a job's `if:` can't read `env`; use a variable or a parameter"
+            ),
+            WorkflowConditionKind::Expression => expression_clause(&condition.expression)?,
+            WorkflowConditionKind::Unspecified => bail!("Workflow condition kind unspecified"),
+        };
+        clauses.push(clause);
+    }
+
+    Ok(match clauses.len() {
+        0 => None,
+        1 => clauses.pop(),
+        _ => Some(
+            clauses
+                .iter()
+                .map(|clause| format!("({clause})"))
+                .collect::<Vec<_>>()
+                .join(" && "),
+        ),
+    })
+}
+
+/// Add `guard` to a job's `if:`, keeping any condition it already has
+pub fn add_job_guard(job: &mut Mapping, guard: &str) {
+    let key = Value::String("if".into());
+    let condition = match job.get(&key).and_then(Value::as_str) {
+        Some(existing) => format!("({}) && ({guard})", unwrap_expression(existing)),
+        None => guard.to_string(),
+    };
+    job.insert(key, Value::String(format!("${{{{ {condition} }}}}")));
+}
+
+/// Declare `inputs` under `on.workflow_dispatch`, adding the trigger if needed
+pub fn add_dispatch_inputs(workflow: &mut Mapping, inputs: Mapping) {
+    let on = workflow
+        .entry(Value::String("on".into()))
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    // `on: push` and `on: [push, pull_request]` become the mapping form
+    let events = match &mut *on {
+        Value::Mapping(_) => None,
+        Value::Sequence(events) => Some(std::mem::take(events)),
+        other => Some(vec![std::mem::take(other)]),
+    };
+    if let Some(events) = events {
+        *on = Value::Mapping(
+            events
+                .into_iter()
+                .filter(|event| !event.is_null())
+                .map(|event| (event, Value::Null))
+                .collect(),
+        );
+    }
+    let Value::Mapping(on) = on else {
+        unreachable!("`on` was converted to a mapping");
+    };
+
+    let dispatch = on
+        .entry(Value::String("workflow_dispatch".into()))
+        .or_insert(Value::Null);
+    if !dispatch.is_mapping() {
+        *dispatch = Value::Mapping(Mapping::new());
+    }
+    let declared = dispatch
+        .as_mapping_mut()
+        .expect("workflow_dispatch is a mapping")
+        .entry(Value::String("inputs".into()))
+        .or_insert(Value::Null);
+    if !declared.is_mapping() {
+        *declared = Value::Mapping(Mapping::new());
+    }
+    let declared = declared.as_mapping_mut().expect("inputs is a mapping");
+    for (name, input) in inputs {
+        declared.entry(name).or_insert(input);
+    }
+}
+
+fn parameter_clause(
+    condition: &WorkflowCondition,
+    parameters: &Mapping,
+    inputs: &mut Mapping,
+) -> Result<String> {
+    let name = &condition.key;
+    let equals = equals_value(condition)?;
+    let definition = parameters.get(name.as_str());
+    let input = dispatch_input(name, definition, &equals)?;
+    let default = input.get("default").cloned();
+    inputs.insert(Value::String(name.clone()), Value::Mapping(input));
+
+    let check = format!("inputs.{name} == {}", literal(&equals)?);
+    // Other events have no inputs, so they run the workflow when the
+    // parameter's default would
+    Ok(if default.as_ref() == Some(&equals) {
+        format!("github.event_name != 'workflow_dispatch' || {check}")
+    } else {
+        check
+    })
+}
+
+/// A `workflow_dispatch` input for a pipeline parameter. Without a definition,
+/// the type follows the value the condition compares against.
+fn dispatch_input(name: &str, definition: Option<&Value>, equals: &Value) -> Result<Mapping> {
+    let kind = match definition.and_then(|definition| definition.get("type")) {
+        Some(kind) => kind
+            .as_str()
+            .ok_or_else(|| anyhow!("Parameter '{name}' has a non-string type"))?,
+        None => match equals {
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "integer",
+            _ => "string",
+        },
+    };
+    let mut input = Mapping::new();
+    if let Some(description) = definition.and_then(|definition| definition.get("description")) {
+        input.insert(Value::String("description".into()), description.clone());
+    }
+    let input_type = match kind {
+        "boolean" => "boolean",
+        "integer" | "number" => "number",
+        "string" => "string",
+        "enum" => {
+            let options = definition
+                .and_then(|definition| definition.get("enum"))
+                .cloned()
+                .with_context(|| format!("Enum parameter '{name}' has no `enum` values"))?;
+            input.insert(Value::String("options".into()), options);
+            "choice"
+        }
+        other => bail!(
+            "Parameter '{name}' has type '{other}', which workflow_dispatch inputs can't express"
+        ),
+    };
+    input.insert(
+        Value::String("type".into()),
+        Value::String(input_type.into()),
+    );
+    if let Some(default) = definition.and_then(|definition| definition.get("default")) {
+        input.insert(Value::String("default".into()), default.clone());
+    }
+    Ok(input)
+}
+
+fn expression_clause(expression: &str) -> Result<String> {
+    let expression = unwrap_expression(expression);
+    if let Ok(condition) = Condition::parse(expression)
+        && !condition.is_static()
+    {
+        bail!(
+            "env checks are not supported in GitHub Actions workflow conditions, where a job's `if:` can't read `env` (in '{expression}')"
+        );
+    }
+    github_step_condition(expression)
+}
+
+fn equals_value(condition: &WorkflowCondition) -> Result<Value> {
+    if condition.equals_yaml.is_empty() {
+        return Ok(Value::Bool(true));
+    }
+    serde_yaml::from_str(&condition.equals_yaml).with_context(|| {
+        format!(
+            "Failed to parse workflow condition value: {}",
+            condition.equals_yaml
+        )
+    })
+}
+
+fn literal(value: &Value) -> Result<String> {
+    Ok(match value {
+        Value::Bool(value) => value.to_string(),
+        Value::Number(value) => value.to_string(),
+        Value::String(value) => quote(value),
+        other => bail!("Workflow conditions can't compare against {other:?}"),
+    })
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Strip a surrounding `${{ }}`
+fn unwrap_expression(expression: &str) -> &str {
+    let trimmed = expression.trim();
+    trimmed
+        .strip_prefix("${{")
+        .and_then(|rest| rest.strip_suffix("}}"))
+        .map(str::trim)
+        .unwrap_or(trimmed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn condition(kind: WorkflowConditionKind, key: &str, equals: &str) -> WorkflowCondition {
+        WorkflowCondition {
+            kind: kind as i32,
+            key: key.to_string(),
+            equals_yaml: equals.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn conditions_combine_into_one_guard() {
+        let workflow = WorkflowDefinition {
+            id: "deploy".to_string(),
+            run_when: vec![
+                condition(WorkflowConditionKind::Parameter, "target", "production"),
+                condition(WorkflowConditionKind::Variable, "DEPLOYS_ENABLED", "true"),
+                WorkflowCondition {
+                    kind: WorkflowConditionKind::Expression as i32,
+                    expression: r#"branch == "main""#.to_string(),
+                    ..Default::default()
+                },
+                WorkflowCondition {
+                    provider: "circleci".to_string(),
+                    ..condition(WorkflowConditionKind::Env, "IGNORED", "")
+                },
+            ],
+            ..Default::default()
+        };
+        let parameters: Mapping = serde_yaml::from_str(
+            "target:\n  type: enum\n  enum: [staging, production]\n  default: staging\n",
+        )
+        .unwrap();
+
+        let guard = workflow_guard(&workflow, &parameters).unwrap().unwrap();
+        assert_eq!(
+            guard.condition,
+            "(inputs.target == 'production') && (vars.DEPLOYS_ENABLED == 'true') && (github.ref_name == 'main')"
+        );
+        assert_eq!(
+            serde_yaml::to_string(&guard.inputs).unwrap(),
+            "target:\n  options:\n  - staging\n  - production\n  type: choice\n  default: staging\n"
+        );
+    }
+
+    #[test]
+    fn existing_job_condition_is_kept() {
+        let mut job: Mapping = serde_yaml::from_str("if: ${{ github.actor != 'bot' }}").unwrap();
+        add_job_guard(&mut job, "inputs.deploy == true");
+        assert_eq!(
+            job["if"].as_str(),
+            Some("${{ (github.actor != 'bot') && (inputs.deploy == true) }}")
+        );
+    }
+
+    #[test]
+    fn dispatch_inputs_extend_the_existing_triggers() {
+        let mut workflow: Mapping =
+            serde_yaml::from_str("name: DEPLOY\non: [push, workflow_dispatch]\njobs: {}\n")
+                .unwrap();
+        let inputs: Mapping = serde_yaml::from_str("deploy:\n  type: boolean\n").unwrap();
+        add_dispatch_inputs(&mut workflow, inputs);
+        assert_eq!(
+            serde_yaml::to_string(&workflow).unwrap(),
+            "name: DEPLOY\non:\n  push: null\n  workflow_dispatch:\n    inputs:\n      deploy:\n        type: boolean\njobs: {}\n"
+        );
+    }
+
+    #[test]
+    fn env_conditions_are_rejected() {
+        let workflow = WorkflowDefinition {
+            run_when: vec![condition(WorkflowConditionKind::Env, "FEATURE", "")],
+            ..Default::default()
+        };
+        let error = workflow_guard(&workflow, &Mapping::new())
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("env conditions are not supported on GitHub Actions"));
+    }
+}
@@ -1,12 +1,15 @@
 /// Woodpecker CI Provider Plugin for CIGen
 use anyhow::{Context, Result};
 use cigen::plugin::protocol::{diagnostic, plugin_server::Plugin, *};
+use cigen::schema::{DEFAULT_WORKFLOW, OutputConfig, resolve_output_path};
 use serde_yaml::{Mapping, Value};
 use std::collections::{BTreeMap, HashMap};
 use tonic::{Request, Response, Status};
 
 /// Plugin version and metadata
 const PLUGIN_NAME: &str = "provider/woodpecker";
+/// Provider name the output paths are resolved for
+const PROVIDER_NAME: &str = "woodpecker";
 const PLUGIN_VERSION: &str = "0.1.0";
 const PROTOCOL_VERSION: u32 = 1;
 
@@ -27,7 +30,7 @@ impl Plugin for WoodpeckerProvider {
             )));
         }
 
-        tracing::info!(
+        tracing::debug!(
             "Handshake from core version {} (protocol {})",
             hello.core_version,
             hello.core_protocol
@@ -37,13 +40,15 @@ impl Plugin for WoodpeckerProvider {
             name: PLUGIN_NAME.to_string(),
             version: PLUGIN_VERSION.to_string(),
             protocol: PROTOCOL_VERSION,
+            protocol_min: PROTOCOL_VERSION,
+            protocol_max: PROTOCOL_VERSION,
             capabilities: vec![
                 "provider:woodpecker".to_string(),
                 "cache:native".to_string(),
                 "matrix:build".to_string(),
             ],
             requires: vec![],
-            conflicts_with: vec!["provider:*".to_string()],
+            conflicts_with: vec!["provider:woodpecker".to_string()],
             metadata: std::collections::HashMap::new(),
         };
 
@@ -85,7 +90,7 @@ impl Plugin for WoodpeckerProvider {
     ) -> Result<Response<GenerateResult>, Status> {
         let req = request.into_inner();
 
-        tracing::info!("Generating Woodpecker CI config for target: {}", req.target);
+        tracing::debug!("Generating Woodpecker CI config for target: {}", req.target);
 
         let fragment = Fragment {
             path: ".woodpecker/ci.yaml".to_string(),
@@ -97,7 +102,7 @@ impl Plugin for WoodpeckerProvider {
 
         let result = GenerateResult {
             fragments: vec![fragment],
-            diagnostics: vec![],
+            ..Default::default()
         };
 
         Ok(Response::new(result))
@@ -138,17 +143,9 @@ impl Plugin for WoodpeckerProvider {
 
 fn main() -> Result<()> {
     // Initialize logging to stderr (stdout is used for protobuf messages)
-    tracing_subscriber::fmt()
-        .with_writer(std::io::stderr)
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("cigen_provider_woodpecker=info".parse()?),
-        )
-        .with_target(false)
-        .without_time()
-        .init();
-
-    tracing::info!("Starting {} v{}", PLUGIN_NAME, PLUGIN_VERSION);
+    cigen::plugin::logging::init_plugin_logging("cigen_provider_woodpecker")?;
+
+    tracing::debug!("Starting {} v{}", PLUGIN_NAME, PLUGIN_VERSION);
 
     use cigen::plugin::framing::{receive_message, send_message};
     use std::io::{stdin, stdout};
@@ -156,7 +153,7 @@ fn main() -> Result<()> {
     // Read Hello message from stdin
     let hello: Hello = receive_message(&mut stdin().lock())?;
 
-    tracing::info!(
+    tracing::debug!(
         "Received handshake from core version {} (protocol {})",
         hello.core_version,
         hello.core_protocol
@@ -176,22 +173,24 @@ fn main() -> Result<()> {
         name: PLUGIN_NAME.to_string(),
         version: PLUGIN_VERSION.to_string(),
         protocol: PROTOCOL_VERSION,
+        protocol_min: PROTOCOL_VERSION,
+        protocol_max: PROTOCOL_VERSION,
         capabilities: vec![
             "provider:woodpecker".to_string(),
             "cache:native".to_string(),
             "matrix:build".to_string(),
         ],
         requires: vec![],
-        conflicts_with: vec!["provider:*".to_string()],
+        conflicts_with: vec!["provider:woodpecker".to_string()],
         metadata: std::collections::HashMap::new(),
     };
 
     send_message(&info, &mut stdout().lock())?;
 
-    tracing::info!("Handshake successful, plugin info sent");
+    tracing::debug!("Handshake successful, plugin info sent");
 
     // Message loop: handle Plan and Generate requests
-    tracing::info!("Entering message loop...");
+    tracing::debug!("Entering message loop...");
 
     let mut stdin = stdin().lock();
     let mut stdout = stdout().lock();
@@ -199,7 +198,7 @@ fn main() -> Result<()> {
     loop {
         match receive_message::<PlanRequest, _>(&mut stdin) {
             Ok(_plan_req) => {
-                tracing::info!("Received PlanRequest");
+                tracing::debug!("Received PlanRequest");
 
                 let plan_result = PlanResult {
                     resources: vec![],
@@ -208,7 +207,7 @@ fn main() -> Result<()> {
                 };
 
                 send_message(&plan_result, &mut stdout)?;
-                tracing::info!("Sent PlanResult");
+                tracing::debug!("Sent PlanResult");
             }
             Err(_) => {
                 break;
@@ -217,11 +216,11 @@ fn main() -> Result<()> {
 
         match receive_message::<GenerateRequest, _>(&mut stdin) {
             Ok(gen_req) => {
-                tracing::info!("Received GenerateRequest for target: {}", gen_req.target);
+                tracing::debug!("Received GenerateRequest for target: {}", gen_req.target);
 
                 let gen_result = build_generate_result(&gen_req);
 
-                tracing::info!(
+                tracing::debug!(
                     "Sending GenerateResult with {} fragment(s)",
                     gen_result.fragments.len()
                 );
@@ -247,6 +246,7 @@ fn build_generate_result(req: &GenerateRequest) -> GenerateResult {
                     "unknown",
                     anyhow::anyhow!("GenerateRequest missing schema"),
                 )],
+                ..Default::default()
             };
         }
     };
@@ -254,6 +254,7 @@ fn build_generate_result(req: &GenerateRequest) -> GenerateResult {
     GenerateResult {
         fragments,
         diagnostics,
+        ..Default::default()
     }
 }
 
@@ -264,7 +265,7 @@ fn build_workflow_fragments(schema: &CigenSchema) -> (Vec<Fragment>, Vec<Diagnos
     let mut jobs_by_workflow: BTreeMap<String, Vec<JobDefinition>> = BTreeMap::new();
     for job in &schema.jobs {
         let workflow = if job.workflow.is_empty() {
-            "ci"
+            DEFAULT_WORKFLOW
         } else {
             &job.workflow
         };
@@ -274,6 +275,14 @@ fn build_workflow_fragments(schema: &CigenSchema) -> (Vec<Fragment>, Vec<Diagnos
             .push(job.clone());
     }
 
+    let output = match output_config(schema, &jobs_by_workflow) {
+        Ok(output) => output,
+        Err(error) => {
+            diagnostics.push(make_diagnostic("config", error));
+            return (Vec::new(), diagnostics);
+        }
+    };
+
     let mut fragments = Vec::new();
 
     for (workflow_name, mut jobs) in jobs_by_workflow {
@@ -281,7 +290,7 @@ fn build_workflow_fragments(schema: &CigenSchema) -> (Vec<Fragment>, Vec<Diagnos
         let metadata = workflow_metadata.get(&workflow_name);
         match render_workflow_file(&workflow_name, &jobs, metadata) {
             Ok(content) => fragments.push(Fragment {
-                path: format!(".woodpecker/{workflow_name}.yaml"),
+                path: resolve_output_path(PROVIDER_NAME, &workflow_name, &output, None),
                 content,
                 strategy: MergeStrategy::Replace as i32,
                 order: 0,
@@ -294,6 +303,19 @@ fn build_workflow_fragments(schema: &CigenSchema) -> (Vec<Fragment>, Vec<Diagnos
     (fragments, diagnostics)
 }
 
+/// `output_path` and `output_filename`, checked against the workflows written
+fn output_config(
+    schema: &CigenSchema,
+    jobs_by_workflow: &BTreeMap<String, Vec<JobDefinition>>,
+) -> Result<OutputConfig> {
+    let raw_config: Value = serde_yaml::from_str(&schema.raw_config_yaml)
+        .context("Failed to parse raw configuration from schema")?;
+    let output = OutputConfig::from_raw_config(&raw_config)?;
+    let workflows: Vec<&str> = jobs_by_workflow.keys().map(String::as_str).collect();
+    output.check_per_workflow(&workflows)?;
+    Ok(output)
+}
+
 fn parse_workflow_metadata(
     schema: &CigenSchema,
     diagnostics: &mut Vec<Diagnostic>,
@@ -343,9 +365,7 @@ fn render_workflow_file(
     let steps = build_steps_sequence(jobs)?;
     workflow_map.insert(steps_key, Value::Sequence(steps));
 
-    let mut yaml = String::from("# DO NOT EDIT - This file is generated by cigen\n");
-    yaml.push_str("# Source: .cigen/workflows/\n");
-    yaml.push_str("# Regenerate with: cargo run -- --config .cigen generate\n");
+    let mut yaml = String::from("# Source: .cigen/workflows/\n");
     yaml.push_str("#\n");
 
     let rendered =
@@ -488,6 +508,7 @@ fn make_diagnostic(workflow: &str, error: anyhow::Error) -> Diagnostic {
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_borrows_for_generic_args)]
 mod tests {
     use super::*;
 
@@ -601,9 +622,8 @@ mod tests {
 
         let result = render_workflow_file("ci", &jobs, None).unwrap();
 
-        // Should contain header comments
-        assert!(result.contains("DO NOT EDIT"));
-        assert!(result.contains("generated by cigen"));
+        // Should say where it came from; cigen adds the rest of the header
+        assert!(result.starts_with("# Source: .cigen/workflows/\n"));
 
         // Should contain the step
         assert!(result.contains("alpine"));
@@ -654,6 +674,35 @@ mod tests {
         assert!(paths.contains(&".woodpecker/deploy.yaml"));
     }
 
+    #[test]
+    fn test_output_path_and_filename() {
+        let mut deploy = job_with_run_step("deploy", "alpine", "deploy.sh");
+        deploy.workflow = "deploy".to_string();
+        let mut schema = CigenSchema {
+            jobs: vec![
+                job_with_run_step("test", "rust:latest", "cargo test"),
+                deploy,
+            ],
+            raw_config_yaml: "output_path: ci\noutput_filename: \"{workflow}.yml\"\n".to_string(),
+            ..Default::default()
+        };
+
+        let (fragments, diagnostics) = build_workflow_fragments(&schema);
+        assert_eq!(diagnostics.len(), 0);
+        let paths: Vec<_> = fragments.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["ci/ci.yml", "ci/deploy.yml"]);
+
+        // Without {workflow}, both workflows would write the same file
+        schema.raw_config_yaml = "output_filename: pipeline.yml\n".to_string();
+        let (fragments, diagnostics) = build_workflow_fragments(&schema);
+        assert!(fragments.is_empty());
+        assert!(
+            diagnostics[0]
+                .message
+                .contains("would overwrite each other")
+        );
+    }
+
     #[test]
     fn test_services_collection() {
         let mut job1 = job_with_run_step("test", "rust:latest", "cargo test");
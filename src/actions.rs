@@ -0,0 +1,387 @@
+//! GitHub Actions SHA pinning
+//!
+//! With `github_actions: { pin_actions: true }`, every `uses: owner/repo@ref`
+//! in the generated GitHub Actions files is rewritten to the commit that ref
+//! points at, as `uses: owner/repo@<sha> # <ref>`. Resolved commits are kept
+//! in `.cigen/actions.lock.yml`, so generation only asks GitHub about actions
+//! the lockfile doesn't know yet. `cigen actions update` re-resolves them all.
+
+use anyhow::{Context, Result, bail};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::schema::CigenConfig;
+
+/// Lockfile name inside the `.cigen` directory
+pub const LOCKFILE_NAME: &str = "actions.lock.yml";
+
+/// GitHub's REST API, overridable with `CIGEN_GITHUB_API_URL`
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
+const LOCKFILE_HEADER: &str =
+    "# Generated by cigen; refresh it with `cigen actions update` instead of editing by hand.\n";
+
+/// Generated files under this directory are GitHub Actions workflows and actions
+const GITHUB_DIR: &str = ".github/";
+
+/// A `uses:` line, capturing the text before the reference and the reference
+static USES_LINE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"^(\s*(?:-\s+)?uses:\s*)['"]?([^'"\s#]+)['"]?\s*$"#).expect("valid regex")
+});
+
+/// A remote action reference such as `actions/cache/restore@v4`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ActionReference {
+    /// `owner/repo`
+    pub repo: String,
+    /// Directory of the action inside the repository (`/restore`), if any
+    pub path: String,
+    /// Tag, branch, or commit after the `@`
+    pub git_ref: String,
+}
+
+impl ActionReference {
+    /// The remote action in a `uses:` value. Local actions (`./...`), Docker
+    /// images, and references already pinned to a commit return `None`.
+    pub fn parse(uses: &str) -> Option<Self> {
+        if uses.starts_with("./") || uses.starts_with("docker://") {
+            return None;
+        }
+        let (action, git_ref) = uses.split_once('@')?;
+        let mut parts = action.splitn(3, '/');
+        let (owner, repo) = (parts.next()?, parts.next()?);
+        if owner.is_empty() || repo.is_empty() || git_ref.is_empty() || is_commit_sha(git_ref) {
+            return None;
+        }
+        Some(Self {
+            repo: format!("{owner}/{repo}"),
+            path: parts
+                .next()
+                .map(|path| format!("/{path}"))
+                .unwrap_or_default(),
+            git_ref: git_ref.to_string(),
+        })
+    }
+
+    /// Lockfile key, `owner/repo@ref`. Actions from the same repository and
+    /// ref share one commit.
+    pub fn key(&self) -> String {
+        format!("{}@{}", self.repo, self.git_ref)
+    }
+
+    /// The pinned `uses:` value, with the original ref kept as a comment
+    pub fn pinned(&self, sha: &str) -> String {
+        format!("{}{}@{sha} # {}", self.repo, self.path, self.git_ref)
+    }
+}
+
+fn is_commit_sha(git_ref: &str) -> bool {
+    git_ref.len() == 40 && git_ref.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Whether the config sets `github_actions.pin_actions: true`
+pub fn pin_actions_enabled(config: &CigenConfig) -> bool {
+    config
+        .raw
+        .get("github_actions")
+        .and_then(|options| options.get("pin_actions"))
+        .and_then(serde_yaml::Value::as_bool)
+        .unwrap_or(false)
+}
+
+/// Source of the commits action refs point at
+pub trait ActionRegistry {
+    /// Full commit SHA of `git_ref` in `repo` (`owner/repo`)
+    fn commit_sha(&self, repo: &str, git_ref: &str) -> Result<String>;
+}
+
+/// GitHub's REST API, authenticated with `$GITHUB_TOKEN` when it's set
+pub struct GithubApi {
+    url: String,
+    token: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl GithubApi {
+    pub fn new(url: impl Into<String>, token: Option<String>) -> Self {
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(30)))
+            .build()
+            .into();
+        Self {
+            url: url.into().trim_end_matches('/').to_string(),
+            token,
+            agent,
+        }
+    }
+
+    /// The API at `CIGEN_GITHUB_API_URL`, or GitHub's
+    pub fn from_env() -> Self {
+        let url = std::env::var("CIGEN_GITHUB_API_URL")
+            .ok()
+            .filter(|url| !url.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_API_URL.to_string());
+        let token = std::env::var("GITHUB_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+        Self::new(url, token)
+    }
+}
+
+impl ActionRegistry for GithubApi {
+    fn commit_sha(&self, repo: &str, git_ref: &str) -> Result<String> {
+        let url = format!("{}/repos/{repo}/commits/{git_ref}", self.url);
+        let mut request = self
+            .agent
+            .get(&url)
+            .header("Accept", "application/vnd.github.sha")
+            .header("User-Agent", "cigen");
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", &format!("Bearer {token}"));
+        }
+        let sha = request
+            .call()
+            .and_then(|mut response| response.body_mut().read_to_string())
+            .with_context(|| format!("Failed to resolve {repo}@{git_ref} with the GitHub API"))?;
+        let sha = sha.trim();
+        if !is_commit_sha(sha) {
+            bail!("GitHub returned '{sha}' for {repo}@{git_ref}, which isn't a commit SHA");
+        }
+        Ok(sha.to_string())
+    }
+}
+
+/// `.cigen/actions.lock.yml` for the project the config was loaded from
+pub fn lockfile_path(config: &CigenConfig) -> PathBuf {
+    config
+        .project_root
+        .clone()
+        .filter(|root| !root.as_os_str().is_empty())
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".cigen")
+        .join(LOCKFILE_NAME)
+}
+
+/// Locked `owner/repo@ref` → commit SHA; empty when there is no lockfile
+pub fn read_lockfile(path: &Path) -> Result<BTreeMap<String, String>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_yaml::from_str::<Option<BTreeMap<String, String>>>(&contents)
+        .map(Option::unwrap_or_default)
+        .with_context(|| format!("{} must map owner/repo@ref to commit SHAs", path.display()))
+}
+
+pub fn write_lockfile(path: &Path, locked: &BTreeMap<String, String>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let contents = format!("{LOCKFILE_HEADER}{}", serde_yaml::to_string(locked)?);
+    std::fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Pin the generated GitHub actions to commits from `lockfile`. Unless
+/// `offline`, actions it doesn't know yet are looked up on GitHub and added.
+pub fn pin_generated(
+    files: &mut HashMap<String, String>,
+    lockfile: &Path,
+    offline: bool,
+) -> Result<()> {
+    let mut locked = read_lockfile(lockfile)?;
+    let registry = (!offline).then(GithubApi::from_env);
+    let resolved = resolve_missing(
+        &used_actions(files),
+        &mut locked,
+        registry
+            .as_ref()
+            .map(|registry| registry as &dyn ActionRegistry),
+    )?;
+    if !resolved.is_empty() {
+        write_lockfile(lockfile, &locked)?;
+        tracing::info!(
+            "Pinned {} new action(s) in {}",
+            resolved.len(),
+            lockfile.display()
+        );
+    }
+    pin_files(files, &locked);
+    Ok(())
+}
+
+/// Every remote action the generated GitHub Actions files use
+pub fn used_actions(files: &HashMap<String, String>) -> BTreeMap<String, ActionReference> {
+    files
+        .iter()
+        .filter(|(path, _)| path.starts_with(GITHUB_DIR))
+        .flat_map(|(_, content)| content.lines())
+        .filter_map(|line| USES_LINE.captures(line))
+        .filter_map(|captures| ActionReference::parse(&captures[2]))
+        .map(|action| (action.key(), action))
+        .collect()
+}
+
+/// Add lockfile entries for the actions it doesn't know yet, and return their
+/// keys. Without a registry (offline), missing entries are an error.
+pub fn resolve_missing(
+    actions: &BTreeMap<String, ActionReference>,
+    locked: &mut BTreeMap<String, String>,
+    registry: Option<&dyn ActionRegistry>,
+) -> Result<Vec<String>> {
+    let missing: Vec<&ActionReference> = actions
+        .iter()
+        .filter(|(key, _)| !locked.contains_key(*key))
+        .map(|(_, action)| action)
+        .collect();
+    if missing.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Some(registry) = registry else {
+        let keys: Vec<String> = missing.iter().map(|action| action.key()).collect();
+        bail!(
+            "These actions aren't pinned in {LOCKFILE_NAME}: {}. Run `cigen actions update` to pin them",
+            keys.join(", ")
+        );
+    };
+
+    let mut resolved = Vec::new();
+    for action in missing {
+        let sha = registry.commit_sha(&action.repo, &action.git_ref)?;
+        locked.insert(action.key(), sha);
+        resolved.push(action.key());
+    }
+    Ok(resolved)
+}
+
+/// Rewrite the `uses:` lines of the generated GitHub Actions files to their
+/// locked commits. Every remote action must be in `locked`.
+pub fn pin_files(files: &mut HashMap<String, String>, locked: &BTreeMap<String, String>) {
+    for (path, content) in files.iter_mut() {
+        if !path.starts_with(GITHUB_DIR) {
+            continue;
+        }
+        let mut pinned = String::with_capacity(content.len());
+        for line in content.split_inclusive('\n') {
+            let text = line.trim_end_matches('\n');
+            let replacement = USES_LINE.captures(text).and_then(|captures| {
+                let action = ActionReference::parse(&captures[2])?;
+                let sha = locked.get(&action.key())?;
+                Some(format!("{}{}", &captures[1], action.pinned(sha)))
+            });
+            match replacement {
+                Some(replacement) => {
+                    pinned.push_str(&replacement);
+                    if line.ends_with('\n') {
+                        pinned.push('\n');
+                    }
+                }
+                None => pinned.push_str(line),
+            }
+        }
+        *content = pinned;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    const CHECKOUT_SHA: &str = "11bd71901bbe5b1630ceea73d27597364c9af683";
+    const CACHE_SHA: &str = "5a3ec84eff668545956fd18022155c47e93e2684";
+
+    /// Stands in for the GitHub API, recording the refs it resolves
+    #[derive(Default)]
+    struct FakeRegistry {
+        calls: RefCell<Vec<String>>,
+    }
+
+    impl ActionRegistry for FakeRegistry {
+        fn commit_sha(&self, repo: &str, git_ref: &str) -> Result<String> {
+            self.calls.borrow_mut().push(format!("{repo}@{git_ref}"));
+            match repo {
+                "actions/checkout" => Ok(CHECKOUT_SHA.to_string()),
+                "actions/cache" => Ok(CACHE_SHA.to_string()),
+                _ => bail!("unknown repository {repo}"),
+            }
+        }
+    }
+
+    fn files() -> HashMap<String, String> {
+        HashMap::from([
+            (
+                ".github/workflows/ci.yml".to_string(),
+                "jobs:\n  test:\n    steps:\n    - uses: actions/checkout@v4\n    - name: Restore\n      uses: actions/cache/restore@v4\n    - uses: ./.github/actions/setup\n".to_string(),
+            ),
+            (
+                ".circleci/config.yml".to_string(),
+                "uses: actions/checkout@v4\n".to_string(),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_parse_references() {
+        let action = ActionReference::parse("actions/cache/restore@v4").unwrap();
+        assert_eq!(action.repo, "actions/cache");
+        assert_eq!(action.path, "/restore");
+        assert_eq!(action.key(), "actions/cache@v4");
+        assert_eq!(
+            action.pinned(CACHE_SHA),
+            format!("actions/cache/restore@{CACHE_SHA} # v4")
+        );
+
+        assert_eq!(ActionReference::parse("./.github/actions/setup"), None);
+        assert_eq!(ActionReference::parse("docker://alpine:3.20"), None);
+        assert_eq!(
+            ActionReference::parse(&format!("actions/checkout@{CHECKOUT_SHA}")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_missing_actions_are_resolved_and_pinned() {
+        let mut files = files();
+        let mut locked = BTreeMap::from([("actions/checkout@v4".to_string(), CHECKOUT_SHA.into())]);
+        let registry = FakeRegistry::default();
+
+        let resolved =
+            resolve_missing(&used_actions(&files), &mut locked, Some(&registry)).unwrap();
+        assert_eq!(resolved, ["actions/cache@v4"]);
+        assert_eq!(*registry.calls.borrow(), ["actions/cache@v4"]);
+
+        pin_files(&mut files, &locked);
+        let workflow = &files[".github/workflows/ci.yml"];
+        assert!(
+            workflow.contains(&format!(
+                "    - uses: actions/checkout@{CHECKOUT_SHA} # v4\n"
+            )),
+            "{workflow}"
+        );
+        assert!(
+            workflow.contains(&format!(
+                "      uses: actions/cache/restore@{CACHE_SHA} # v4\n"
+            )),
+            "{workflow}"
+        );
+        assert!(workflow.contains("    - uses: ./.github/actions/setup\n"));
+        assert_eq!(files[".circleci/config.yml"], "uses: actions/checkout@v4\n");
+    }
+
+    #[test]
+    fn test_offline_lists_unpinned_actions() {
+        let mut locked = BTreeMap::new();
+        let error = resolve_missing(&used_actions(&files()), &mut locked, None)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            "These actions aren't pinned in actions.lock.yml: actions/cache@v4, actions/checkout@v4. Run `cigen actions update` to pin them"
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! The supported library API
+//!
+//! Everything reachable from this module follows semver: a release that
+//! removes or changes any of it bumps the major version (the minor version
+//! while cigen is 0.x). The other modules of the crate are public only so the
+//! `cigen` binary and the bundled provider plugins can use them; they're
+//! hidden from the docs and change in any release.
+//!
+//! The structs and enums here are `#[non_exhaustive]`: adding a field or a
+//! variant isn't a breaking change, so build them from `Default::default()`
+//! and match them with a wildcard arm.
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! let config = cigen::api::load_config(Path::new(".cigen"))?;
+//! for file in cigen::api::generate("github", &config, &Default::default())? {
+//!     println!("{}: {} bytes", file.path, file.content.len());
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use crate::actions::{self, pin_actions_enabled};
+use crate::header::{add_headers, config_hash};
+use crate::lint::{ConfigFiles, LintOptions, lint};
+use crate::loader::load_config_path;
+use crate::orchestrator::WorkflowOrchestrator;
+use crate::plugin::diagnostics::error_location;
+use crate::plugin::discovery::determine_plugin_dir;
+use crate::plugin::protocol::SourceLocation;
+use crate::schema::OUTPUT_DIR_FLAG;
+
+pub use crate::schema::{
+    CacheDefinition, CigenConfig, CommandDefinition, CommandParameter, Job, JobCache, JobMatrix,
+    RunStepOptions, Step, UsesStep, WorkflowConfig,
+};
+
+/// A config loaded from a `.cigen` directory or a single `cigen.yml`
+#[derive(Debug, Clone)]
+pub struct LoadedConfig {
+    path: PathBuf,
+    config: CigenConfig,
+}
+
+impl LoadedConfig {
+    /// The `.cigen` directory or `cigen.yml` the config was loaded from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn config(&self) -> &CigenConfig {
+        &self.config
+    }
+
+    pub fn into_config(self) -> CigenConfig {
+        self.config
+    }
+}
+
+/// Settings for [`generate`]
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct GenerateOptions {
+    /// Directory holding the provider plugin binaries. By default the one
+    /// `cigen generate` uses: `$CIGEN_PLUGIN_DIR`, the installed plugins, or
+    /// the directory of the running binary.
+    pub plugin_dir: Option<PathBuf>,
+    /// Only generate this workflow
+    pub workflow: Option<String>,
+    /// Directory the files will be written under, as `cigen generate
+    /// --output`, for paths the generated configs refer to
+    pub output_dir: Option<String>,
+}
+
+/// A generated file, as `cigen generate` would write it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct GeneratedFile {
+    /// Path relative to the output directory, e.g. `.circleci/config.yml`
+    pub path: String,
+    pub content: String,
+}
+
+/// A problem [`validate`] found
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Stable ID of the lint rule (`CIGEN-W001`, ...); `None` for errors
+    pub code: Option<String>,
+    pub message: String,
+    pub location: Option<Location>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Severity {
+    /// The config doesn't load, so nothing can be generated from it
+    Error,
+    /// A config smell that `cigen lint` reports
+    Warning,
+}
+
+/// 1-based position in a config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Location {
+    pub file: PathBuf,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl From<SourceLocation> for Location {
+    fn from(location: SourceLocation) -> Self {
+        Self {
+            file: PathBuf::from(location.file),
+            line: location.line,
+            column: location.column,
+        }
+    }
+}
+
+/// Load and validate a `.cigen` directory or a single `cigen.yml`
+pub fn load_config(path: &Path) -> Result<LoadedConfig> {
+    Ok(LoadedConfig {
+        path: path.to_path_buf(),
+        config: load_config_path(path, None)?,
+    })
+}
+
+/// Generate `provider`'s files (`circleci`, `github`, `woodpecker`) for
+/// `config`, sorted by path. GitHub actions are pinned from
+/// `.cigen/actions.lock.yml` alone, without asking GitHub about missing ones.
+/// Runs its own async runtime, so call it outside of one.
+pub fn generate(
+    provider: &str,
+    config: &LoadedConfig,
+    options: &GenerateOptions,
+) -> Result<Vec<GeneratedFile>> {
+    let mut cigen_config = config.config.clone();
+    cigen_config.providers = vec![provider.to_string()];
+    let actions_lockfile =
+        pin_actions_enabled(&cigen_config).then(|| actions::lockfile_path(&cigen_config));
+
+    let plugin_dir = options
+        .plugin_dir
+        .clone()
+        .unwrap_or_else(determine_plugin_dir);
+    let mut orchestrator = WorkflowOrchestrator::new(plugin_dir);
+    if let Some(output_dir) = &options.output_dir {
+        orchestrator.set_flag(OUTPUT_DIR_FLAG, output_dir);
+    }
+    if let Some(workflow) = &options.workflow {
+        orchestrator.set_workflow(workflow.clone());
+    }
+    let runtime = tokio::runtime::Runtime::new()?;
+    let mut files = runtime.block_on(orchestrator.execute(cigen_config))?.files;
+
+    if let Some(lockfile) = actions_lockfile {
+        actions::pin_generated(&mut files, &lockfile, true)?;
+    }
+    add_headers(&mut files, &config_hash(&config.path)?, None);
+
+    let mut files: Vec<GeneratedFile> = files
+        .into_iter()
+        .map(|(path, content)| GeneratedFile { path, content })
+        .collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// Check the config at `path`: an error when it doesn't load, otherwise the
+/// warnings `cigen lint` reports
+pub fn validate(path: &Path) -> Vec<Diagnostic> {
+    let config = match load_config_path(path, None) {
+        Ok(config) => config,
+        Err(error) => {
+            return vec![Diagnostic {
+                severity: Severity::Error,
+                code: None,
+                message: format!("{error:#}"),
+                location: error_location(&error).map(Location::from),
+            }];
+        }
+    };
+    lint(&config, &ConfigFiles::new(path), &LintOptions::default())
+        .into_iter()
+        .map(|finding| Diagnostic {
+            severity: Severity::Warning,
+            code: Some(finding.rule.id().to_string()),
+            message: finding.message,
+            location: finding.location.map(Location::from),
+        })
+        .collect()
+}
@@ -0,0 +1,395 @@
+//! Cache findings for `cigen audit caches`
+//!
+//! Works on the jobs as providers receive them (see
+//! [`WorkflowOrchestrator::expand_jobs`](crate::orchestrator::WorkflowOrchestrator::expand_jobs)),
+//! so the `restore_cache` and `save_cache` steps from job `cache:`, `packages:`,
+//! and hand-written steps are all covered. Keys are compared per job: the
+//! `{{ arch }}` component becomes the job's architecture and checksum files
+//! become paths from the project root, since both depend on where the job
+//! runs. A restore key matches every saved key it is a prefix of.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+
+use crate::schema::{
+    CACHE_KEY_ARCH, CigenConfig, Job, Step, cache_key_checksum_sources,
+    map_cache_key_checksum_sources,
+};
+
+/// Architecture `{{ arch }}` stands for in jobs that don't set one
+const DEFAULT_ARCH: &str = "amd64";
+
+/// Key components that differ in every pipeline, so each run saves a new key
+const PER_RUN_COMPONENTS: &[&str] = &["{{ epoch }}", "{{ .Revision }}", "{{ .BuildNum }}"];
+
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct CacheAudit {
+    /// Each key jobs save or restore, with the jobs doing so
+    pub keys: Vec<KeyUsage>,
+    /// Keys more than one job saves
+    pub write_races: Vec<WriteRace>,
+    /// Restores no job saves for earlier in the pipeline
+    pub unsaved_restores: Vec<UnsavedRestore>,
+    /// Checksum files that aren't in the repository where the job looks
+    pub missing_checksum_files: Vec<MissingChecksumFile>,
+    /// How many different keys the jobs save
+    pub distinct_keys: usize,
+    /// Saved keys that change in every pipeline
+    pub per_run_keys: Vec<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct KeyUsage {
+    pub key: String,
+    pub saved_by: Vec<String>,
+    pub restored_by: Vec<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct WriteRace {
+    pub key: String,
+    pub jobs: Vec<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct UnsavedRestore {
+    pub job: String,
+    pub key: String,
+    /// Jobs that save a matching key but don't run before `job`; empty when
+    /// none does
+    pub saved_by: Vec<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct MissingChecksumFile {
+    pub job: String,
+    pub key: String,
+    /// The checksummed file, from the project root
+    pub file: String,
+}
+
+/// The cache steps of one job, with keys as that job sees them
+struct JobCaches {
+    /// Each `restore_cache` step's keys, tried in order
+    restores: Vec<Vec<String>>,
+    saves: Vec<String>,
+}
+
+/// Audit the cache steps of `config`'s expanded jobs
+pub fn audit_caches(config: &CigenConfig) -> CacheAudit {
+    let root = config
+        .project_root
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("."));
+    let mut audit = CacheAudit::default();
+    let mut jobs: BTreeMap<&str, JobCaches> = BTreeMap::new();
+
+    for (name, job) in &config.jobs {
+        let mut caches = JobCaches {
+            restores: Vec::new(),
+            saves: Vec::new(),
+        };
+        let mut written_keys = Vec::new();
+        for step in &job.steps {
+            match step {
+                Step::RestoreCache { restore_cache, .. } => {
+                    let keys: Vec<&String> = restore_cache
+                        .key
+                        .iter()
+                        .chain(&restore_cache.keys)
+                        .chain(&restore_cache.restore_keys)
+                        .collect();
+                    written_keys.extend(keys.iter().map(|key| key.as_str()));
+                    caches
+                        .restores
+                        .push(keys.iter().map(|key| job_key(key, job)).collect());
+                }
+                Step::SaveCache { save_cache, .. } => {
+                    if let Some(key) = &save_cache.key {
+                        written_keys.push(key);
+                        caches.saves.push(job_key(key, job));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut reported = BTreeSet::new();
+        for key in written_keys {
+            for source in cache_key_checksum_sources(key) {
+                let file = project_path(job.working_directory.as_deref(), source);
+                let is_glob = file.contains(['*', '?', '[']);
+                if !is_glob && !root.join(&file).exists() && reported.insert(file.clone()) {
+                    audit.missing_checksum_files.push(MissingChecksumFile {
+                        job: name.clone(),
+                        key: key.to_string(),
+                        file,
+                    });
+                }
+            }
+        }
+        jobs.insert(name, caches);
+    }
+
+    let mut savers: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (name, caches) in &jobs {
+        for key in &caches.saves {
+            savers.entry(key).or_default().insert(name);
+        }
+    }
+    let mut restorers: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (name, caches) in &jobs {
+        for keys in &caches.restores {
+            let matched: Vec<&str> = savers
+                .keys()
+                .copied()
+                .filter(|saved| keys.iter().any(|key| saved.starts_with(key.as_str())))
+                .collect();
+            for key in &matched {
+                restorers.entry(key).or_default().insert(name);
+            }
+            if matched.is_empty()
+                && let Some(first) = keys.first()
+            {
+                restorers.entry(first).or_default().insert(name);
+            }
+
+            let saved_by: BTreeSet<&str> = matched
+                .iter()
+                .flat_map(|key| savers[key].iter().copied())
+                .collect();
+            let upstream = upstream_jobs(config, name);
+            let saved_before = saved_by
+                .iter()
+                .any(|saver| saver == name || upstream.contains(saver));
+            if !saved_before && let Some(first) = keys.first() {
+                audit.unsaved_restores.push(UnsavedRestore {
+                    job: name.to_string(),
+                    key: first.clone(),
+                    saved_by: saved_by.iter().map(|saver| saver.to_string()).collect(),
+                });
+            }
+        }
+    }
+
+    let keys: BTreeSet<&str> = savers.keys().chain(restorers.keys()).copied().collect();
+    let names = |jobs: Option<&BTreeSet<&str>>| -> Vec<String> {
+        jobs.into_iter()
+            .flatten()
+            .map(|job| job.to_string())
+            .collect()
+    };
+    for key in keys {
+        audit.keys.push(KeyUsage {
+            key: key.to_string(),
+            saved_by: names(savers.get(key)),
+            restored_by: names(restorers.get(key)),
+        });
+    }
+    for (key, jobs) in &savers {
+        if jobs.len() > 1 {
+            audit.write_races.push(WriteRace {
+                key: key.to_string(),
+                jobs: names(Some(jobs)),
+            });
+        }
+    }
+    audit.distinct_keys = savers.len();
+    audit.per_run_keys = savers
+        .keys()
+        .filter(|key| PER_RUN_COMPONENTS.iter().any(|part| key.contains(part)))
+        .map(|key| key.to_string())
+        .collect();
+    audit
+}
+
+/// `key` as `job` sees it: its architecture in place of `{{ arch }}` and
+/// checksum files as paths from the project root
+fn job_key(key: &str, job: &Job) -> String {
+    let arch = job.architecture.as_deref().unwrap_or(DEFAULT_ARCH);
+    let key = key.replace(CACHE_KEY_ARCH, arch);
+    map_cache_key_checksum_sources(&key, |source| {
+        project_path(job.working_directory.as_deref(), source)
+    })
+}
+
+/// `path` in `working_directory`, from the project root
+fn project_path(working_directory: Option<&str>, path: &str) -> String {
+    let joined = Path::new(working_directory.unwrap_or(".")).join(path);
+    let mut parts: Vec<&str> = Vec::new();
+    for component in joined.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str().unwrap_or_default()),
+            Component::ParentDir => {
+                parts.pop();
+            }
+            _ => {}
+        }
+    }
+    parts.join("/")
+}
+
+/// Every job `name` needs, directly or not
+fn upstream_jobs<'a>(config: &'a CigenConfig, name: &str) -> BTreeSet<&'a str> {
+    let mut upstream = BTreeSet::new();
+    let mut pending: Vec<&str> = config
+        .jobs
+        .get(name)
+        .map(|job| job.needs.iter().map(String::as_str).collect())
+        .unwrap_or_default();
+    while let Some(need) = pending.pop() {
+        let Some((need, job)) = config.jobs.get_key_value(need) else {
+            continue;
+        };
+        if upstream.insert(need.as_str()) {
+            pending.extend(job.needs.iter().map(String::as_str));
+        }
+    }
+    upstream
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(needs: &[&str], steps: &str) -> Job {
+        Job {
+            needs: needs.iter().map(|need| need.to_string()).collect(),
+            steps: serde_yaml::from_str(steps).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn config(root: &Path, jobs: Vec<(&str, Job)>) -> CigenConfig {
+        CigenConfig {
+            jobs: jobs
+                .into_iter()
+                .map(|(name, job)| (name.to_string(), job))
+                .collect(),
+            project_root: Some(root.to_path_buf()),
+            ..Default::default()
+        }
+    }
+
+    const SAVE_GEMS: &str = "- save_cache:\n    key: gems-{{ arch }}-{{ checksum \"Gemfile.lock\" }}\n    paths: [vendor/bundle]\n";
+    const RESTORE_GEMS: &str = "- restore_cache:\n    keys:\n      - gems-{{ arch }}-{{ checksum \"Gemfile.lock\" }}\n      - gems-{{ arch }}-\n";
+
+    #[test]
+    fn jobs_saving_one_key_race() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("Gemfile.lock"), "").unwrap();
+        let mut arm = job(&[], SAVE_GEMS);
+        arm.architecture = Some("arm64".to_string());
+        let config = config(
+            root.path(),
+            vec![
+                ("rspec-3_2", job(&[], SAVE_GEMS)),
+                ("rspec-3_3", job(&[], SAVE_GEMS)),
+                ("rspec-arm64", arm),
+            ],
+        );
+
+        let audit = audit_caches(&config);
+        assert_eq!(
+            audit.write_races,
+            [WriteRace {
+                key: r#"gems-amd64-{{ checksum "Gemfile.lock" }}"#.to_string(),
+                jobs: vec!["rspec-3_2".to_string(), "rspec-3_3".to_string()],
+            }]
+        );
+        assert_eq!(audit.distinct_keys, 2);
+        assert!(audit.missing_checksum_files.is_empty());
+    }
+
+    #[test]
+    fn restores_need_a_save_upstream() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("Gemfile.lock"), "").unwrap();
+        let config = config(
+            root.path(),
+            vec![
+                ("setup", job(&[], SAVE_GEMS)),
+                ("rspec", job(&["setup"], RESTORE_GEMS)),
+                ("lint", job(&[], RESTORE_GEMS)),
+                (
+                    "docs",
+                    job(
+                        &[],
+                        "- restore_cache:\n    key: npm-{{ arch }}\n- save_cache:\n    key: npm-{{ arch }}\n    paths: [node_modules]\n",
+                    ),
+                ),
+                (
+                    "deploy",
+                    job(&["setup"], "- restore_cache:\n    key: assets-{{ arch }}\n"),
+                ),
+            ],
+        );
+
+        let audit = audit_caches(&config);
+        assert_eq!(
+            audit.unsaved_restores,
+            [
+                UnsavedRestore {
+                    job: "deploy".to_string(),
+                    key: "assets-amd64".to_string(),
+                    saved_by: vec![],
+                },
+                UnsavedRestore {
+                    job: "lint".to_string(),
+                    key: r#"gems-amd64-{{ checksum "Gemfile.lock" }}"#.to_string(),
+                    saved_by: vec!["setup".to_string()],
+                },
+            ]
+        );
+        let gems = audit
+            .keys
+            .iter()
+            .find(|usage| usage.key.starts_with("gems"))
+            .unwrap();
+        assert_eq!(gems.saved_by, ["setup"]);
+        assert_eq!(gems.restored_by, ["lint", "rspec"]);
+    }
+
+    #[test]
+    fn checksum_files_are_found_from_the_working_directory() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("Gemfile.lock"), "").unwrap();
+        let mut docs = job(&[], SAVE_GEMS);
+        docs.working_directory = Some("docs".to_string());
+        let mut app = job(&[], SAVE_GEMS);
+        app.working_directory = Some("app/../".to_string());
+        let config = config(root.path(), vec![("docs", docs), ("app", app)]);
+
+        let audit = audit_caches(&config);
+        assert_eq!(
+            audit.missing_checksum_files,
+            [MissingChecksumFile {
+                job: "docs".to_string(),
+                key: r#"gems-{{ arch }}-{{ checksum "Gemfile.lock" }}"#.to_string(),
+                file: "docs/Gemfile.lock".to_string(),
+            }]
+        );
+        // The two jobs checksum different files, so their keys differ
+        assert!(audit.write_races.is_empty());
+    }
+
+    #[test]
+    fn per_run_keys_are_counted() {
+        let root = tempfile::tempdir().unwrap();
+        let config = config(
+            root.path(),
+            vec![(
+                "build",
+                job(
+                    &[],
+                    "- save_cache:\n    key: build-{{ .Revision }}\n    paths: [out]\n",
+                ),
+            )],
+        );
+        assert_eq!(
+            audit_caches(&config).per_run_keys,
+            ["build-{{ .Revision }}"]
+        );
+    }
+}
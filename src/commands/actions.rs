@@ -0,0 +1,61 @@
+use anyhow::Result;
+use cigen::actions::{
+    ActionRegistry, GithubApi, lockfile_path, read_lockfile, used_actions, write_lockfile,
+};
+use cigen::orchestrator::WorkflowOrchestrator;
+use clap::{Args, Subcommand};
+use std::collections::BTreeMap;
+
+use super::common::{determine_plugin_dir, find_cigen_yml, load_config};
+
+/// Arguments for the `cigen actions` subcommand.
+#[derive(Debug, Args)]
+pub struct ActionsArgs {
+    #[command(subcommand)]
+    pub target: ActionsTarget,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ActionsTarget {
+    /// Resolve every generated GitHub action to a commit and rewrite .cigen/actions.lock.yml
+    Update(ActionsUpdateArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ActionsUpdateArgs {
+    /// Path to .cigen directory or cigen.yml file
+    #[arg(short, long)]
+    pub config: Option<String>,
+}
+
+pub fn actions_command(args: ActionsArgs) -> Result<()> {
+    match args.target {
+        ActionsTarget::Update(args) => update_actions(&args),
+    }
+}
+
+fn update_actions(args: &ActionsUpdateArgs) -> Result<()> {
+    let config = load_config(&find_cigen_yml(args.config.clone())?)?;
+    let path = lockfile_path(&config);
+    let previous = read_lockfile(&path)?;
+
+    let mut orchestrator = WorkflowOrchestrator::new(determine_plugin_dir());
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(orchestrator.execute(config))?;
+
+    let registry = GithubApi::from_env();
+    let mut locked = BTreeMap::new();
+    for (key, action) in used_actions(&result.files) {
+        let sha = registry.commit_sha(&action.repo, &action.git_ref)?;
+        match previous.get(&key) {
+            Some(old) if *old == sha => tracing::info!("  {key}: {sha}"),
+            Some(old) => tracing::info!("  {key}: {old} -> {sha}"),
+            None => tracing::info!("  {key}: {sha} (new)"),
+        }
+        locked.insert(key, sha);
+    }
+
+    write_lockfile(&path, &locked)?;
+    tracing::info!("Pinned {} action(s) in {}", locked.len(), path.display());
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use anyhow::Result;
+use cigen::cache_audit::{CacheAudit, audit_caches};
+use cigen::orchestrator::WorkflowOrchestrator;
+use clap::{Args, Subcommand, ValueEnum};
+
+use super::common::{VarArgs, determine_plugin_dir, find_cigen_yml, load_config_with_vars};
+
+/// Arguments for the `cigen audit` subcommand.
+#[derive(Debug, Args)]
+pub struct AuditArgs {
+    #[command(subcommand)]
+    pub target: AuditTarget,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AuditTarget {
+    /// Report cache write races, restores nothing saves first, and missing
+    /// checksum files
+    Caches(AuditCachesArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct AuditCachesArgs {
+    /// Path to .cigen directory or cigen.yml file
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Merge the overlays for this profile over the base config
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = AuditFormat::Text)]
+    pub format: AuditFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AuditFormat {
+    Text,
+    Json,
+}
+
+pub fn audit_command(args: AuditArgs) -> Result<()> {
+    match args.target {
+        AuditTarget::Caches(args) => audit_caches_command(args),
+    }
+}
+
+fn audit_caches_command(args: AuditCachesArgs) -> Result<()> {
+    let config_path = find_cigen_yml(args.config)?;
+    let config = load_config_with_vars(&config_path, args.profile.as_deref(), &args.vars)?;
+    // The cache steps generation adds, with matrices expanded
+    let config = WorkflowOrchestrator::new(determine_plugin_dir()).expand_jobs(config)?;
+
+    let audit = audit_caches(&config);
+    match args.format {
+        AuditFormat::Json => println!("{}", serde_json::to_string_pretty(&audit)?),
+        AuditFormat::Text => print!("{}", render_text(&audit)),
+    }
+    Ok(())
+}
+
+fn render_text(audit: &CacheAudit) -> String {
+    let headers = ["KEY", "SAVED BY", "RESTORED BY"];
+    let rows: Vec<[String; 3]> = audit
+        .keys
+        .iter()
+        .map(|usage| {
+            [
+                usage.key.clone(),
+                usage.saved_by.join(", "),
+                usage.restored_by.join(", "),
+            ]
+        })
+        .collect();
+    let mut widths = headers.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let render = |cells: [&str; 3]| {
+        cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut lines = vec![render(headers)];
+    lines.extend(rows.iter().map(|row| render([&row[0], &row[1], &row[2]])));
+
+    if !audit.write_races.is_empty() {
+        lines.push(String::new());
+        lines.push(format!("Write races ({}):", audit.write_races.len()));
+        for race in &audit.write_races {
+            lines.push(format!(
+                "  {} is saved by {}",
+                race.key,
+                race.jobs.join(", ")
+            ));
+        }
+    }
+    if !audit.unsaved_restores.is_empty() {
+        lines.push(String::new());
+        lines.push(format!(
+            "Restored before saved ({}):",
+            audit.unsaved_restores.len()
+        ));
+        for restore in &audit.unsaved_restores {
+            lines.push(match restore.saved_by.as_slice() {
+                [] => format!(
+                    "  {} restores {}, which no job saves",
+                    restore.job, restore.key
+                ),
+                savers => format!(
+                    "  {} restores {}, which only {} save after it or alongside it",
+                    restore.job,
+                    restore.key,
+                    savers.join(", ")
+                ),
+            });
+        }
+    }
+    if !audit.missing_checksum_files.is_empty() {
+        lines.push(String::new());
+        lines.push(format!(
+            "Missing checksum files ({}):",
+            audit.missing_checksum_files.len()
+        ));
+        for missing in &audit.missing_checksum_files {
+            lines.push(format!(
+                "  {}: {} checksums {}, which doesn't exist",
+                missing.job, missing.key, missing.file
+            ));
+        }
+    }
+
+    lines.push(String::new());
+    let mut summary = format!("Distinct saved keys: {}", audit.distinct_keys);
+    if !audit.per_run_keys.is_empty() {
+        summary.push_str(&format!(
+            " ({} new in every pipeline)",
+            audit.per_run_keys.len()
+        ));
+    }
+    lines.push(summary);
+    lines.join("\n") + "\n"
+}
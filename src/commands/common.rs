@@ -0,0 +1,114 @@
+use anyhow::{Context, Result, bail};
+use cigen::schema::CigenConfig;
+use clap::Args;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub use cigen::plugin::discovery::determine_plugin_dir;
+
+/// Template variable overrides for commands that render templates
+#[derive(Debug, Default, Args)]
+pub struct VarArgs {
+    /// Override a template variable from `vars:` (repeatable)
+    #[arg(long = "var", value_name = "NAME=VALUE")]
+    pub vars: Vec<String>,
+
+    /// Load template variable overrides from a YAML map (repeatable)
+    #[arg(long = "var-file", value_name = "PATH")]
+    pub var_files: Vec<PathBuf>,
+}
+
+impl VarArgs {
+    /// The overrides to layer over `vars:` from the config: var files in
+    /// order, then `--var` flags
+    pub fn overrides(&self) -> Result<HashMap<String, serde_yaml::Value>> {
+        let mut overrides = HashMap::new();
+        for path in &self.var_files {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read var file: {}", path.display()))?;
+            let vars: HashMap<String, serde_yaml::Value> = serde_yaml::from_str(&contents)
+                .with_context(|| {
+                    format!(
+                        "Var file {} must be a YAML map of variable names to values",
+                        path.display()
+                    )
+                })?;
+            overrides.extend(vars);
+        }
+
+        for entry in &self.vars {
+            let (name, value) = parse_var(entry)?;
+            overrides.insert(
+                name.to_string(),
+                serde_yaml::Value::String(value.to_string()),
+            );
+        }
+        Ok(overrides)
+    }
+}
+
+/// Split `NAME=VALUE` on the first `=`
+fn parse_var(entry: &str) -> Result<(&str, &str)> {
+    match entry.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim(), value)),
+        _ => bail!("Invalid --var '{entry}': expected NAME=VALUE, e.g. --var ruby_version=3.3"),
+    }
+}
+
+/// Find cigen.yml in various locations
+pub fn find_cigen_yml(file: Option<String>) -> Result<PathBuf> {
+    if let Some(path) = file {
+        let p = PathBuf::from(path);
+        if p.exists() {
+            return Ok(p);
+        } else {
+            anyhow::bail!("Config file not found: {}", p.display());
+        }
+    }
+
+    // Try common locations
+    let candidates = vec![
+        PathBuf::from("cigen.yml"),
+        PathBuf::from(".cigen"), // directory with split config
+        PathBuf::from(".cigen/cigen.yml"),
+        PathBuf::from("cigen.yaml"),
+        PathBuf::from(".cigen/cigen.yaml"),
+    ];
+
+    for candidate in candidates {
+        if candidate.exists() {
+            return Ok(candidate);
+        }
+    }
+
+    anyhow::bail!(
+        "No cigen config found. Tried: cigen.yml, .cigen/, .cigen/cigen.yml, cigen.yaml, .cigen/cigen.yaml"
+    )
+}
+
+/// Load a config from a .cigen directory or a single cigen.yml file
+pub fn load_config(config_path: &Path) -> Result<CigenConfig> {
+    load_config_with_profile(config_path, None)
+}
+
+/// Load a config with a profile's overlays applied (split configs only)
+pub fn load_config_with_profile(config_path: &Path, profile: Option<&str>) -> Result<CigenConfig> {
+    cigen::loader::load_config_path(config_path, profile)
+}
+
+/// Load a config with a profile's overlays and template variable overrides
+/// applied. Split configs take the overrides while loading, so `.j2` job files
+/// see them.
+pub fn load_config_with_vars(
+    config_path: &Path,
+    profile: Option<&str>,
+    vars: &VarArgs,
+) -> Result<CigenConfig> {
+    let overrides = vars.overrides()?;
+    if config_path.is_dir() {
+        return cigen::loader::load_split_config_with_vars(config_path, profile, &overrides);
+    }
+    let mut config = load_config_with_profile(config_path, profile)?;
+    config.vars.extend(overrides);
+    Ok(config)
+}
@@ -0,0 +1,87 @@
+use anyhow::{Context, Result, bail};
+use cigen::format::{format_yaml, key_order_for};
+use clap::Args;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use super::common::find_cigen_yml;
+
+/// Arguments for the `cigen fmt` subcommand.
+#[derive(Debug, Args)]
+pub struct FmtArgs {
+    /// Path to .cigen directory or cigen.yml file
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// List files that need formatting and exit non-zero instead of rewriting them
+    #[arg(long)]
+    pub check: bool,
+}
+
+pub fn fmt_command(args: FmtArgs) -> Result<()> {
+    let config_path = find_cigen_yml(args.config)?;
+    let files = yaml_files(&config_path)?;
+
+    let mut unformatted = Vec::new();
+    let mut failed = 0;
+    for path in &files {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let formatted = match format_yaml(&source, key_order_for(path)) {
+            Ok(formatted) => formatted,
+            Err(error) => {
+                tracing::error!("Can't format {}: {error:#}", path.display());
+                failed += 1;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+
+        if args.check {
+            println!("{}", path.display());
+        } else {
+            fs::write(path, &formatted)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            tracing::info!("Formatted {}", path.display());
+        }
+        unformatted.push(path);
+    }
+
+    if failed > 0 {
+        bail!("{failed} file(s) couldn't be formatted");
+    }
+    if args.check && !unformatted.is_empty() {
+        bail!(
+            "{} of {} file(s) need formatting; run `cigen fmt` to fix them",
+            unformatted.len(),
+            files.len()
+        );
+    }
+    if unformatted.is_empty() {
+        tracing::info!("{} file(s) already formatted", files.len());
+    }
+    Ok(())
+}
+
+/// The config file itself, or every YAML file under a `.cigen` directory
+fn yaml_files(config_path: &Path) -> Result<Vec<PathBuf>> {
+    if config_path.is_file() {
+        return Ok(vec![config_path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    for entry in WalkDir::new(config_path).sort_by_file_name() {
+        let entry = entry?;
+        let is_yaml = matches!(
+            entry.path().extension().and_then(|ext| ext.to_str()),
+            Some("yml" | "yaml")
+        );
+        if entry.file_type().is_file() && is_yaml {
+            files.push(entry.into_path());
+        }
+    }
+    Ok(files)
+}
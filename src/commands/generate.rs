@@ -1,64 +1,304 @@
 use anyhow::{Context, Result};
-use std::path::PathBuf;
+use cigen::actions::{self, pin_actions_enabled};
+use cigen::header::{add_headers, config_hash};
+use cigen::hooks::{HookStage, render_hooks, run_hooks};
+use cigen::image_registry::RegistryApi;
+use cigen::path_filter::{
+    ChangedFiles, GitDiff, ONLY_PROJECTS_FILE_ENV, PathFilterSummary, SKIP_JOBS_FILE_ENV,
+    filter_affected_projects, filter_changed_jobs, read_projects_file, read_skip_jobs_file,
+};
+use cigen::plugin::capabilities::render_degradations;
+use cigen::report::{DiagnosticReport, GenerationReport, Phase, PhaseTiming};
+use cigen::schema::{OUTPUT_DIR_FLAG, output_file_path};
+use clap::Args;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+
+use super::common::{VarArgs, determine_plugin_dir, find_cigen_yml, load_config_with_vars};
+
+/// Arguments for the `cigen generate` subcommand.
+#[derive(Debug, Default, Args)]
+pub struct GenerateArgs {
+    /// Only generate this workflow
+    pub workflow: Option<String>,
+
+    /// Only generate this workflow (same as the positional argument)
+    #[arg(
+        long = "workflow",
+        value_name = "WORKFLOW",
+        conflicts_with = "workflow"
+    )]
+    pub workflow_flag: Option<String>,
+
+    /// Path to .cigen directory or cigen.yml file
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Output directory for generated files (default: .)
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Merge the overlays for this profile (.cigen/overlays/<profile>.yml and
+    /// jobs/<job>.<profile>.yml) over the base config
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Print generated files to stdout instead of writing them
+    #[arg(long, conflicts_with = "output")]
+    pub stdout: bool,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
+
+    /// Fail immediately when a plugin crashes instead of restarting it once
+    #[arg(long)]
+    pub no_plugin_retry: bool,
+
+    /// Also validate generated configs with the provider's CLI (e.g. `circleci`)
+    #[arg(long)]
+    pub validate_with_cli: bool,
+
+    /// Leave out jobs whose `source_files` have no changes since this git ref
+    #[arg(long, value_name = "REF")]
+    pub changed_since: Option<String>,
+
+    /// Leave these jobs out, by their final names (e.g. `test_arm64`); jobs
+    /// that needed them inherit their needs. Adds to `$CIGEN_SKIP_JOBS_FILE`.
+    #[arg(long, value_name = "JOBS", value_delimiter = ',')]
+    pub skip_jobs: Vec<String>,
+
+    /// Pin GitHub actions from .cigen/actions.lock.yml alone, without asking
+    /// the GitHub API about actions missing from it
+    #[arg(long)]
+    pub offline: bool,
+
+    /// List the files that would be written without writing them or running hooks
+    #[arg(long, conflicts_with = "stdout")]
+    pub dry_run: bool,
+
+    /// Split the CircleCI jobs across this many config files
+    /// (`.circleci/main_1.yml` ...), keeping dependent jobs together
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(2..))]
+    pub shard_count: Option<u16>,
+
+    /// Fail when a generated CircleCI config, or its continuation payload, is
+    /// larger than this many bytes (default: CircleCI's 3 MiB limits)
+    #[arg(long, value_name = "BYTES")]
+    pub max_config_size: Option<u64>,
+
+    /// Look up `docker_build` image tags in their registry and only build the
+    /// images that aren't published yet
+    #[arg(long)]
+    pub verify_images: bool,
+
+    /// Leave the job-status cache steps out of the CircleCI jobs, so every job
+    /// runs and no job records its status (the setup job passes this when the
+    /// `skip_cache` pipeline parameter is true)
+    #[arg(long)]
+    pub no_job_status_cache: bool,
+
+    /// Prefix every dependency cache key with this, so jobs start from empty
+    /// caches and save fresh ones under new keys
+    #[arg(long, value_name = "NONCE")]
+    pub cache_nonce: Option<String>,
+
+    /// Add the generation time to each file's header (off by default, so
+    /// unchanged configs generate identical files)
+    #[arg(long)]
+    pub timestamp: bool,
+
+    /// Write a JSON report of the run to this path: the files generated, jobs
+    /// per workflow, skipped jobs, diagnostics, degraded features, and time
+    /// spent per phase
+    #[arg(long, value_name = "PATH")]
+    pub report: Option<PathBuf>,
+
+    /// Fail when a provider would emulate, pass through, or drop a feature a
+    /// job uses, instead of listing it in the degradation report
+    #[arg(long)]
+    pub strict_capabilities: bool,
+}
 
-#[allow(clippy::collapsible_if)]
 /// Generate CI configs from cigen.yml
-pub fn generate_command(file: Option<String>, output: Option<String>) -> Result<()> {
+pub fn generate_command(args: GenerateArgs) -> Result<()> {
+    let GenerateArgs {
+        workflow,
+        workflow_flag,
+        config: file,
+        output,
+        profile,
+        stdout: to_stdout,
+        vars,
+        no_plugin_retry,
+        validate_with_cli,
+        changed_since,
+        skip_jobs,
+        offline,
+        dry_run,
+        shard_count,
+        max_config_size,
+        verify_images,
+        no_job_status_cache,
+        cache_nonce,
+        timestamp,
+        report: report_path,
+        strict_capabilities,
+    } = args;
+    let workflow = workflow.or(workflow_flag);
+
     // Find cigen.yml
     let config_path = find_cigen_yml(file)?;
 
-    println!("Loading config from: {}", config_path.display());
+    tracing::info!("Loading config from: {}", config_path.display());
 
     // Load and parse config (handle both single file and directory)
-    let config = if config_path.is_dir() {
-        cigen::loader::load_split_config(&config_path)?
-    } else {
-        let yaml = std::fs::read_to_string(&config_path)
-            .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
-        cigen::schema::CigenConfig::from_yaml(&yaml).context("Failed to parse cigen.yml")?
-    };
+    let load_start = Instant::now();
+    let mut config = load_config_with_vars(&config_path, profile.as_deref(), &vars)?;
+    let mut report = GenerationReport::default();
+    report
+        .phases
+        .push(PhaseTiming::new(Phase::Load, load_start.elapsed()));
+    if let Some(profile) = &profile {
+        tracing::info!("Applied profile: {profile}");
+    }
 
-    println!("Parsed config with {} job(s)", config.jobs.len());
+    tracing::info!("Parsed config with {} job(s)", config.jobs.len());
+
+    let project_root = config
+        .project_root
+        .clone()
+        .filter(|root| !root.as_os_str().is_empty())
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    if let Some(base) = &changed_since {
+        let changed = GitDiff::new(project_root.clone()).changed_since(base)?;
+        let summary = filter_changed_jobs(&mut config, &changed)?;
+        log_filter_summary(
+            &format!("{} file(s) changed since {base}", changed.len()),
+            &summary,
+        );
+        report.skip_jobs(&summary.excluded, &format!("no changes since {base}"));
+    }
+
+    if let Some(path) = std::env::var_os(ONLY_PROJECTS_FILE_ENV) {
+        let affected = read_projects_file(Path::new(&path))?;
+        let summary = filter_affected_projects(&mut config, &affected);
+        log_filter_summary(&format!("{} affected project(s)", affected.len()), &summary);
+        report.skip_jobs(&summary.excluded, "project not affected");
+    }
+
+    // Resolved before the config moves into the orchestrator
+    let actions_lockfile = pin_actions_enabled(&config).then(|| actions::lockfile_path(&config));
+    let output_dir = output
+        .as_deref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    // Hooks only run when files are written. They run from the project root,
+    // so they get the output directory as an absolute path.
+    let run_hooks_now = !dry_run && !to_stdout;
+    let hook_output_dir = std::path::absolute(&output_dir)?;
+    let post_generate = render_hooks(
+        &config.hooks.post_generate,
+        &hook_output_dir,
+        &config.providers,
+    );
+    if run_hooks_now {
+        let pre_generate = render_hooks(
+            &config.hooks.pre_generate,
+            &hook_output_dir,
+            &config.providers,
+        );
+        run_hooks(HookStage::PreGenerate, &pre_generate, &project_root)?;
+    }
 
     // Determine plugin directory (where provider binaries are)
     let plugin_dir = determine_plugin_dir();
-    println!("Using plugin directory: {}", plugin_dir.display());
+    tracing::info!("Using plugin directory: {}", plugin_dir.display());
 
     // Create orchestrator
     let mut orchestrator = cigen::orchestrator::WorkflowOrchestrator::new(plugin_dir);
+    orchestrator.set_plugin_retry(!no_plugin_retry);
+    orchestrator.set_strict_capabilities(strict_capabilities);
+    if validate_with_cli {
+        orchestrator.set_flag("validate_with_cli", "true");
+    }
+    if let Some(output) = &output {
+        orchestrator.set_flag(OUTPUT_DIR_FLAG, output);
+    }
+    if let Some(max_config_size) = max_config_size {
+        orchestrator.set_flag("max_config_size", &max_config_size.to_string());
+    }
+    if verify_images {
+        orchestrator.set_image_registry(Box::new(RegistryApi::from_env()));
+    }
+    if let Some(shard_count) = shard_count {
+        orchestrator.set_shard_count(shard_count.into());
+    }
+    if no_job_status_cache {
+        orchestrator.disable_job_status_cache();
+    }
+    if let Some(nonce) = cache_nonce {
+        orchestrator.set_cache_nonce(nonce)?;
+    }
+    if let Some(path) = std::env::var_os(SKIP_JOBS_FILE_ENV) {
+        orchestrator.skip_jobs(read_skip_jobs_file(Path::new(&path))?);
+    }
+    orchestrator.skip_jobs(skip_jobs);
+    if let Some(workflow) = workflow {
+        tracing::info!("Generating workflow: {workflow}");
+        orchestrator.set_workflow(workflow);
+    }
 
     // Execute workflow
-    println!("Executing workflow...");
+    tracing::info!("Executing workflow...");
     let runtime = tokio::runtime::Runtime::new()?;
-    let result = runtime.block_on(orchestrator.execute(config))?;
+    let mut result = runtime.block_on(orchestrator.execute(config))?;
+    finish_files(
+        &mut result.files,
+        &config_path,
+        actions_lockfile.as_deref(),
+        offline,
+        timestamp,
+    )?;
+
+    if let Some(path) = &report_path {
+        report.set_files(&result.files);
+        report.set_workflows(&result.jobs_by_workflow);
+        report.skip_jobs(&result.skipped_jobs, "listed to skip");
+        report.diagnostics = result
+            .diagnostics
+            .iter()
+            .map(DiagnosticReport::from)
+            .collect();
+        report.degradations = result.degradations.clone();
+        report.phases.extend(result.phases.iter().copied());
+        report.write(path)?;
+        tracing::info!("Wrote report to {}", path.display());
+    }
+
+    if !result.degradations.is_empty() {
+        tracing::warn!("{}", render_degradations(&result.degradations));
+    }
+
+    if to_stdout {
+        print!("{}", render_files(&result.files));
+        return Ok(());
+    }
 
     // Write output files
-    let output_dir = output
-        .map(PathBuf::from)
-        .unwrap_or_else(|| PathBuf::from("."));
+    tracing::info!("Generated {} file(s):", result.files.len());
+    let mut paths: Vec<&String> = result.files.keys().collect();
+    paths.sort();
+    for path in paths {
+        let content = &result.files[path];
+        let full_path = output_file_path(&output_dir, path);
 
-    println!("\nGenerated {} file(s):", result.files.len());
-    for (path, content) in &result.files {
-        let mut relative_path = PathBuf::from(path);
-
-        if output_dir.as_os_str() != "." && relative_path.is_relative() {
-            if let Some(output_name) = output_dir.file_name() {
-                if relative_path.starts_with(output_name) {
-                    if let Ok(stripped) = relative_path.strip_prefix(output_name) {
-                        relative_path = stripped.to_path_buf();
-                    }
-                }
-            }
+        if dry_run {
+            println!("Would write {}", full_path.display());
+            continue;
         }
 
-        let full_path = if output_dir.as_os_str() == "." {
-            relative_path.clone()
-        } else if relative_path.as_os_str().is_empty() {
-            output_dir.clone()
-        } else {
-            output_dir.join(&relative_path)
-        };
-
         // Create parent directories if needed
         if let Some(parent) = full_path.parent() {
             std::fs::create_dir_all(parent)?;
@@ -67,77 +307,69 @@ pub fn generate_command(file: Option<String>, output: Option<String>) -> Result<
         std::fs::write(&full_path, content)
             .with_context(|| format!("Failed to write file: {}", full_path.display()))?;
 
-        println!("  ✓ {}", path);
+        tracing::info!("  ✓ {path}");
     }
 
-    println!("\n✨ Done!");
+    if run_hooks_now {
+        run_hooks(HookStage::PostGenerate, &post_generate, &project_root)?;
+    }
+
+    tracing::info!("✨ Done!");
 
     Ok(())
 }
 
-/// Find cigen.yml in various locations
-fn find_cigen_yml(file: Option<String>) -> Result<PathBuf> {
-    if let Some(path) = file {
-        let p = PathBuf::from(path);
-        if p.exists() {
-            return Ok(p);
-        } else {
-            anyhow::bail!("Config file not found: {}", p.display());
-        }
-    }
-
-    // Try common locations
-    let candidates = vec![
-        PathBuf::from("cigen.yml"),
-        PathBuf::from(".cigen"), // directory with split config
-        PathBuf::from(".cigen/cigen.yml"),
-        PathBuf::from("cigen.yaml"),
-        PathBuf::from(".cigen/cigen.yaml"),
-    ];
-
-    for candidate in candidates {
-        if candidate.exists() {
-            return Ok(candidate);
-        }
+/// Finish generated files the way they are written: pin GitHub actions from
+/// `actions_lockfile` and add the header. `timestamp` adds the generation time.
+pub(super) fn finish_files(
+    files: &mut HashMap<String, String>,
+    config_path: &Path,
+    actions_lockfile: Option<&Path>,
+    offline: bool,
+    timestamp: bool,
+) -> Result<()> {
+    if let Some(lockfile) = actions_lockfile {
+        actions::pin_generated(files, lockfile, offline)?;
     }
-
-    anyhow::bail!(
-        "No cigen config found. Tried: cigen.yml, .cigen/, .cigen/cigen.yml, cigen.yaml, .cigen/cigen.yaml"
-    )
+    // Hashed after pinning, which can add to the actions lockfile
+    add_headers(
+        files,
+        &config_hash(config_path)?,
+        timestamp.then(SystemTime::now),
+    );
+    Ok(())
 }
 
-/// Determine where plugin binaries are located
-fn determine_plugin_dir() -> PathBuf {
-    // Respect explicit plugin directory override
-    if let Ok(dir) = std::env::var("CIGEN_PLUGIN_DIR")
-        && !dir.trim().is_empty()
-    {
-        return PathBuf::from(dir);
+fn log_filter_summary(reason: &str, summary: &PathFilterSummary) {
+    tracing::info!(
+        "{reason}: including {} job(s), excluding {}",
+        summary.included.len(),
+        summary.excluded.len()
+    );
+    for job in &summary.included {
+        tracing::info!("  + {job}");
     }
-
-    // Default installation path for packaged binaries
-    let installed_dir = PathBuf::from("/usr/local/lib/cigen/plugins");
-    if installed_dir.exists() {
-        return installed_dir;
+    for job in &summary.excluded {
+        tracing::info!("  - {job}");
     }
+}
 
-    // In development, use target/debug
-    // In production, use the same directory as the cigen binary
-
-    // Check if we're in development (target/debug or target/release exists)
-    let cargo_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    let debug_dir = cargo_dir.join("target/debug");
-    let release_dir = cargo_dir.join("target/release");
+/// Concatenate generated files for `--stdout`, sorted by path. Multiple files
+/// are separated by `--- # path: <path>` document markers.
+fn render_files(files: &HashMap<String, String>) -> String {
+    let mut paths: Vec<&String> = files.keys().collect();
+    paths.sort();
 
-    if debug_dir.exists() {
-        return debug_dir;
-    } else if release_dir.exists() {
-        return release_dir;
+    let mut output = String::new();
+    for path in paths {
+        let content = &files[path];
+        if files.len() > 1 {
+            output.push_str(&format!("--- # path: {path}\n"));
+        }
+        output.push_str(content);
+        if !content.ends_with('\n') {
+            output.push('\n');
+        }
     }
-
-    // Production: same directory as binary
-    std::env::current_exe()
-        .ok()
-        .and_then(|p| p.parent().map(|p| p.to_path_buf()))
-        .unwrap_or_else(|| PathBuf::from("."))
+    output
 }
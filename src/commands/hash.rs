@@ -1,4 +1,6 @@
 use anyhow::{Context, Result, bail};
+use cigen::docker_hash::image_hashes;
+use cigen::schema::{submodule_commit_file, unknown_reference_message};
 use clap::Args;
 use globwalk::{FileType, GlobWalkerBuilder};
 use serde::{Deserialize, Serialize};
@@ -37,15 +39,29 @@ pub struct HashArgs {
     /// Optional cache file path to persist per-file hashes
     #[arg(long = "cache")]
     pub cache_path: Option<PathBuf>,
+
+    /// Print the hashes docker_build tags its images with
+    #[arg(long = "docker-base", conflicts_with_all = ["patterns", "job"])]
+    pub docker_base: bool,
+
+    /// Only hash this docker_build image (implies --docker-base)
+    #[arg(long = "image", value_name = "NAME", conflicts_with_all = ["patterns", "job"])]
+    pub image: Option<String>,
+
+    /// Print docker_build hashes as JSON, with the files each hash_sources pattern matched
+    #[arg(long = "json")]
+    pub json: bool,
 }
 
 pub fn hash_command(args: HashArgs) -> Result<()> {
-    if let Some(job_id) = args.job.as_deref() {
+    if args.docker_base || args.image.is_some() {
+        hash_docker_images(&args)
+    } else if let Some(job_id) = args.job.as_deref() {
         hash_job(&args, job_id)
     } else {
         if args.patterns.is_empty() {
             bail!(
-                "No patterns provided. Use --pattern for file hashing or --job to hash a config job."
+                "No patterns provided. Use --pattern for file hashing, --job to hash a config job, or --docker-base to hash docker_build images."
             );
         }
         hash_patterns(&args)
@@ -163,7 +179,11 @@ fn hash_job(args: &HashArgs, job_id: &str) -> Result<()> {
             }
             SourceEntry::Group(name) => {
                 let patterns = source_groups.get(&name).with_context(|| {
-                    format!("Job '{job_id}' references unknown source file group '{name}'")
+                    unknown_reference_message(
+                        &format!("Job '{job_id}' references unknown source file group '{name}'"),
+                        &name,
+                        source_groups.keys().map(|group| group.as_str()),
+                    )
                 })?;
                 let digest = hash_group(
                     &name,
@@ -180,6 +200,14 @@ fn hash_job(args: &HashArgs, job_id: &str) -> Result<()> {
         }
     }
 
+    for path in &job.source_submodules {
+        let commit = submodule_commit(&base_dir, path)?;
+        final_hasher.update(b"submodule\0");
+        final_hasher.update(path.as_bytes());
+        final_hasher.update([0u8]);
+        final_hasher.update(commit.as_bytes());
+    }
+
     let digest = hex::encode(final_hasher.finalize());
 
     if let Some(name) = &args.output_name {
@@ -195,6 +223,83 @@ fn hash_job(args: &HashArgs, job_id: &str) -> Result<()> {
     Ok(())
 }
 
+fn hash_docker_images(args: &HashArgs) -> Result<()> {
+    let base_dir = canonicalize_path(&args.base_dir)?;
+    let config_path = resolve_path(&base_dir, &args.config);
+    let (config, _) = load_config(&config_path)?;
+    let docker = config
+        .docker_build
+        .as_ref()
+        .filter(|docker| !docker.images.is_empty())
+        .with_context(|| {
+            format!(
+                "No docker_build images are configured in {}",
+                config_path.display()
+            )
+        })?;
+
+    // Relative to the project root, as during generation
+    let root = config.project_root.clone().unwrap_or(base_dir);
+    let mut hashes = image_hashes(&root, docker)?;
+    if let Some(name) = &args.image {
+        hashes.retain(|image| &image.name == name);
+        if hashes.is_empty() {
+            bail!(
+                "{}",
+                unknown_reference_message(
+                    &format!("Unknown docker_build image '{name}'"),
+                    name,
+                    docker.images.iter().map(|image| image.name.as_str()),
+                )
+            );
+        }
+    }
+
+    if let Some(name) = &args.output_name {
+        let [image] = hashes.as_slice() else {
+            bail!("--output needs --image when docker_build has several images");
+        };
+        write_github_output(name, &image.hash)?;
+    }
+
+    if args.json {
+        let json = serde_json::json!({ "images": hashes });
+        println!("{}", serde_json::to_string_pretty(&json)?);
+    } else if args.image.is_some() {
+        println!("{}", hashes[0].hash);
+    } else {
+        for image in &hashes {
+            println!("{}={}", image.name, image.hash);
+        }
+    }
+    Ok(())
+}
+
+/// Pinned commit of a submodule, from the commit file written in CI or from git
+fn submodule_commit(base_dir: &Path, path: &str) -> Result<String> {
+    let commit_file = PathBuf::from(submodule_commit_file(path));
+    if commit_file.is_file() {
+        let commit = fs::read_to_string(&commit_file)
+            .with_context(|| format!("Failed to read {}", commit_file.display()))?;
+        return Ok(commit.trim().to_string());
+    }
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(base_dir)
+        .arg("rev-parse")
+        .arg(format!("HEAD:{}", path.trim_matches('/')))
+        .output()
+        .with_context(|| format!("Failed to run git for submodule '{path}'"))?;
+    if !output.status.success() {
+        bail!(
+            "Failed to resolve commit for submodule '{path}': {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
 fn hash_group(
     name: &str,
     patterns: &[String],
@@ -0,0 +1,389 @@
+use anyhow::{Context, Result, bail};
+use cigen::hooks::{HookStage, render_hooks};
+use cigen::orchestrator::{JobDAG, WorkflowOrchestrator};
+use cigen::schema::{CigenConfig, Job, unknown_reference_message};
+use cigen::templating::versions::{UnresolvedVersion, VERSION_SUFFIX, VersionResolver};
+use clap::{Args, Subcommand};
+use serde_yaml::{Mapping, Value};
+use std::path::{Path, PathBuf};
+
+use super::common::{
+    VarArgs, determine_plugin_dir, find_cigen_yml, load_config, load_config_with_vars,
+};
+
+/// Arguments for the `cigen inspect` subcommand.
+#[derive(Debug, Args)]
+pub struct InspectArgs {
+    #[command(subcommand)]
+    pub target: InspectTarget,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum InspectTarget {
+    /// Print the fully resolved provider-level job definition
+    Job(InspectJobArgs),
+    /// Print the pre- and post-generate hooks `cigen generate` would run
+    Hooks(InspectHooksArgs),
+    /// Print each `{{ <tool>_version }}` and the file it is read from
+    Versions(InspectVersionsArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct InspectJobArgs {
+    /// Job id (e.g. `test/rspec`) or generated job name
+    pub name: String,
+
+    /// Path to .cigen directory or cigen.yml file
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Provider to render the job for (defaults to the first configured provider)
+    #[arg(short, long)]
+    pub provider: Option<String>,
+
+    /// Select the matrix variant for this architecture
+    #[arg(long)]
+    pub arch: Option<String>,
+
+    /// Show which config layer contributed each job field
+    #[arg(long)]
+    pub explain: bool,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct InspectHooksArgs {
+    /// Path to .cigen directory or cigen.yml file
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Output directory substituted for `{output_dir}` (default: .)
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct InspectVersionsArgs {
+    /// Path to .cigen directory or cigen.yml file
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
+}
+
+pub fn inspect_command(args: InspectArgs) -> Result<()> {
+    match args.target {
+        InspectTarget::Job(args) => inspect_job(args),
+        InspectTarget::Hooks(args) => inspect_hooks(args),
+        InspectTarget::Versions(args) => inspect_versions(args),
+    }
+}
+
+fn inspect_hooks(args: InspectHooksArgs) -> Result<()> {
+    let config_path = find_cigen_yml(args.config)?;
+    let config = load_config(&config_path)?;
+    let output_dir = std::path::absolute(PathBuf::from(
+        args.output.unwrap_or_else(|| ".".to_string()),
+    ))?;
+
+    let mut hooks = Mapping::new();
+    for (stage, commands) in [
+        (HookStage::PreGenerate, &config.hooks.pre_generate),
+        (HookStage::PostGenerate, &config.hooks.post_generate),
+    ] {
+        let rendered = render_hooks(commands, &output_dir, &config.providers);
+        hooks.insert(
+            Value::String(stage.as_str().into()),
+            Value::Sequence(rendered.into_iter().map(Value::String).collect()),
+        );
+    }
+    print!("{}", serde_yaml::to_string(&Value::Mapping(hooks))?);
+    Ok(())
+}
+
+fn inspect_versions(args: InspectVersionsArgs) -> Result<()> {
+    let config_path = find_cigen_yml(args.config)?;
+    let config = load_config_with_vars(&config_path, None, &args.vars)?;
+    let root = config
+        .project_root
+        .clone()
+        .filter(|root| !root.as_os_str().is_empty())
+        .unwrap_or_else(|| PathBuf::from("."));
+    let resolver = VersionResolver::new(&config.version_sources, &root);
+
+    let mut rows = Vec::new();
+    for tool in resolver.tools() {
+        let variable = format!("{tool}{VERSION_SUFFIX}");
+        let (version, source) = match config.vars.get(&variable) {
+            Some(value) => (yaml_scalar(value)?, "vars".to_string()),
+            None => match resolver.resolve(tool) {
+                Ok(resolved) => (resolved.version, resolved.source),
+                Err(error) => match error.downcast_ref::<UnresolvedVersion>() {
+                    Some(unresolved) => (
+                        "-".to_string(),
+                        format!("not found: {}", unresolved.checked_summary()),
+                    ),
+                    None => return Err(error),
+                },
+            },
+        };
+        rows.push([variable, version, source]);
+    }
+
+    let widths: Vec<usize> = (0..2)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    for [variable, version, source] in rows {
+        println!(
+            "{variable:<width$}  {version:<version_width$}  {source}",
+            width = widths[0],
+            version_width = widths[1]
+        );
+    }
+    Ok(())
+}
+
+/// A `vars:` value as it renders in a template
+fn yaml_scalar(value: &Value) -> Result<String> {
+    Ok(match value {
+        Value::String(text) => text.clone(),
+        other => serde_yaml::to_string(other)?.trim_end().to_string(),
+    })
+}
+
+fn inspect_job(args: InspectJobArgs) -> Result<()> {
+    let config_path = find_cigen_yml(args.config.clone())?;
+    let mut config = load_config_with_vars(&config_path, None, &args.vars)?;
+
+    let (job_id, instance_id) = resolve_instance(&config, &args.name, args.arch.as_deref())?;
+
+    let provider = match &args.provider {
+        Some(provider) => provider.clone(),
+        None => config
+            .providers
+            .first()
+            .cloned()
+            .unwrap_or_else(|| "github".to_string()),
+    };
+    config.providers = vec![provider.clone()];
+
+    let mut orchestrator = WorkflowOrchestrator::new(determine_plugin_dir());
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(orchestrator.execute(config.clone()))?;
+
+    let mut paths: Vec<&String> = result.files.keys().collect();
+    paths.sort();
+
+    let rendered = paths
+        .into_iter()
+        .find_map(|path| {
+            let document: Value = serde_yaml::from_str(&result.files[path]).ok()?;
+            let jobs = document.get("jobs")?.as_mapping()?;
+            let candidates = [instance_id.clone(), instance_id.replace('/', "_")];
+            candidates
+                .iter()
+                .find_map(|name| jobs.get(name.as_str()))
+                .map(|job| (path.clone(), job.clone()))
+        })
+        .with_context(|| {
+            format!("Provider '{provider}' did not generate a job named '{instance_id}'")
+        })?;
+
+    let (path, job) = rendered;
+    println!("# {path} → jobs.{instance_id}");
+    let mut wrapper = Mapping::new();
+    wrapper.insert(Value::String(instance_id.clone()), job.clone());
+    print!("{}", serde_yaml::to_string(&Value::Mapping(wrapper))?);
+
+    if args.explain {
+        println!();
+        for line in explain_fields(&config, &config_path, &job_id, &job) {
+            println!("# {line}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Map a user-supplied name (+ optional architecture) to the source job id and concrete instance id
+fn resolve_instance(
+    config: &CigenConfig,
+    name: &str,
+    arch: Option<&str>,
+) -> Result<(String, String)> {
+    let dag = JobDAG::build(config).context("Failed to build dependency graph")?;
+
+    let mut matches: Vec<(&String, &str)> = dag
+        .jobs()
+        .iter()
+        .filter(|(instance_id, concrete)| concrete.job_id == name || *instance_id == name)
+        .filter(|(_, concrete)| arch.is_none() || concrete.architecture() == arch)
+        .map(|(instance_id, concrete)| (instance_id, concrete.job_id.as_str()))
+        .collect();
+    matches.sort();
+
+    match matches.as_slice() {
+        [] => {
+            let mut names: Vec<&str> = config.jobs.keys().map(String::as_str).collect();
+            names.extend(dag.jobs().keys().map(String::as_str));
+            let message = match arch {
+                Some(arch) => format!("Unknown job '{name}' for architecture '{arch}'"),
+                None => format!("Unknown job '{name}'"),
+            };
+            bail!("{}", unknown_reference_message(&message, name, names))
+        }
+        [(instance_id, job_id)] => Ok((job_id.to_string(), instance_id.to_string())),
+        many => {
+            let variants: Vec<&str> = many.iter().map(|(id, _)| id.as_str()).collect();
+            bail!(
+                "Job '{name}' has multiple variants ({}); select one with --arch or use the variant name",
+                variants.join(", ")
+            )
+        }
+    }
+}
+
+/// Top-level keys written in the job's own entry, or `None` when its file
+/// can't be read as YAML before rendering (e.g. a `.yml.j2` with blocks)
+fn job_file_keys(job: &Job) -> Option<Mapping> {
+    let contents = std::fs::read_to_string(job.source_file.as_ref()?).ok()?;
+    let document = cigen::schema::parse_yaml_value(&contents).ok()?;
+    let entry = match &job.source_section {
+        Some(section) => document
+            .get("jobs")
+            .and_then(|jobs| jobs.get(section.as_str()))
+            .or_else(|| document.get(section.as_str()))?,
+        None => &document,
+    };
+    entry.as_mapping().cloned()
+}
+
+/// Where the job's entry lives, relative to the config
+fn job_file_label(config_path: &Path, job: &Job) -> String {
+    let Some(file) = &job.source_file else {
+        return "job file".to_string();
+    };
+    let base = if config_path.is_dir() {
+        config_path
+    } else {
+        config_path.parent().unwrap_or(config_path)
+    };
+    let file = file.strip_prefix(base).unwrap_or(file).display();
+    match &job.source_section {
+        Some(section) => format!("job file ({file}, {section})"),
+        None => format!("job file ({file})"),
+    }
+}
+
+fn explain_fields(
+    config: &CigenConfig,
+    config_path: &Path,
+    job_id: &str,
+    rendered: &Value,
+) -> Vec<String> {
+    let mut lines = vec!["Field sources:".to_string()];
+    let Some(job) = config.jobs.get(job_id) else {
+        return lines;
+    };
+    let job_value = serde_yaml::to_value(job).unwrap_or(Value::Null);
+    let default_value = serde_yaml::to_value(Job::default()).unwrap_or(Value::Null);
+
+    let mut schema_keys: Vec<String> = job_value
+        .as_mapping()
+        .map(|map| {
+            map.keys()
+                .filter_map(|key| key.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+    schema_keys.sort();
+
+    let split = config_path.is_dir();
+    let config_file = if split { "config.yml" } else { "cigen.yml" };
+    let workflow_name = job.workflow.as_deref();
+    let workflow = workflow_name.and_then(|name| config.workflows.get(name));
+    let workflow_file = workflow_name.map(|name| {
+        if split {
+            format!("workflows/{name}/config.yml")
+        } else {
+            format!("cigen.yml workflows.{name}")
+        }
+    });
+    let job_file = job_file_label(config_path, job);
+    let written_keys = job_file_keys(job);
+
+    for key in &schema_keys {
+        let written = match &written_keys {
+            Some(keys) => keys.contains_key(key.as_str()),
+            None => job_value.get(key.as_str()) != default_value.get(key.as_str()),
+        };
+        let mut sources = Vec::new();
+        if written {
+            sources.push(job_file.clone());
+        }
+        match key.as_str() {
+            "environment" => {
+                if !config.env.is_empty() {
+                    sources.push(format!("{config_file} env"));
+                }
+                if let (Some(workflow), Some(file)) = (workflow, &workflow_file)
+                    && !workflow.env.is_empty()
+                {
+                    sources.push(format!("{file} env"));
+                }
+            }
+            "steps" => {
+                let inherits = job
+                    .inherit_global_steps
+                    .unwrap_or_else(|| workflow.is_none_or(|w| w.inherit_global_steps));
+                if inherits && !job.is_approval() && !config.global_steps.is_empty() {
+                    sources.push(format!("{config_file} global_steps"));
+                }
+                if job
+                    .cache
+                    .iter()
+                    .any(|cache| config.caches.contains_key(&cache.name))
+                {
+                    sources.push(format!("{config_file} caches"));
+                }
+                if let (Some(workflow), Some(file)) = (workflow, &workflow_file)
+                    && workflow.job_steps.contains_key(job_id)
+                {
+                    sources.push(format!("{file} job_steps"));
+                }
+            }
+            "checkout" if !written => {
+                if let (Some(workflow), Some(file)) = (workflow, &workflow_file)
+                    && workflow.checkout.is_some()
+                {
+                    sources.push(format!("{file} checkout"));
+                }
+            }
+            _ => {}
+        }
+        let source = if sources.is_empty() {
+            "default".to_string()
+        } else {
+            sources.join(", ")
+        };
+        lines.push(format!("  {key}: {source}"));
+    }
+    if let Some(rendered) = rendered.as_mapping() {
+        let mut injected: Vec<&str> = rendered
+            .keys()
+            .filter_map(Value::as_str)
+            .filter(|key| !schema_keys.iter().any(|schema_key| schema_key == key))
+            .collect();
+        injected.sort();
+        for key in injected {
+            lines.push(format!(
+                "  {key}: provider (derived from config.yml and job)"
+            ));
+        }
+    }
+
+    lines
+}
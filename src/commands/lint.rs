@@ -0,0 +1,57 @@
+use anyhow::{Result, bail};
+use cigen::lint::{ConfigFiles, DEFAULT_MAX_CHAIN_LENGTH, LintOptions, lint};
+use clap::{Args, ValueEnum};
+
+use super::common::{VarArgs, find_cigen_yml, load_config_with_vars};
+
+/// Arguments for the `cigen lint` subcommand.
+#[derive(Debug, Args)]
+pub struct LintArgs {
+    /// Path to .cigen directory or cigen.yml file
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Merge the overlays for this profile over the base config
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
+
+    /// Fail when there are findings of this kind
+    #[arg(long, value_enum)]
+    pub deny: Option<Deny>,
+
+    /// Longest chain of jobs that need one another before it's reported
+    #[arg(long, default_value_t = DEFAULT_MAX_CHAIN_LENGTH)]
+    pub max_chain_length: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Deny {
+    Warnings,
+}
+
+/// Report config smells; with `--deny warnings`, fail when there are any
+pub fn lint_command(args: LintArgs) -> Result<()> {
+    let config_path = find_cigen_yml(args.config)?;
+    let config = load_config_with_vars(&config_path, args.profile.as_deref(), &args.vars)?;
+    let options = LintOptions {
+        max_chain_length: args.max_chain_length,
+    };
+    let findings = lint(&config, &ConfigFiles::new(&config_path), &options);
+
+    for finding in &findings {
+        println!("{}\n", finding.render());
+    }
+    let summary = match findings.len() {
+        0 => "No lint warnings".to_string(),
+        1 => "1 lint warning".to_string(),
+        count => format!("{count} lint warnings"),
+    };
+    if args.deny == Some(Deny::Warnings) && !findings.is_empty() {
+        bail!("{summary} (denied by --deny warnings)");
+    }
+    println!("{summary}");
+    Ok(())
+}
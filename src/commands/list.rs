@@ -0,0 +1,503 @@
+use anyhow::{Result, bail};
+use cigen::loader::discover_profiles;
+use cigen::orchestrator::WorkflowOrchestrator;
+use cigen::plugin::PluginManager;
+use cigen::plugin::discovery::{discover_from_dir, plugin_search_dirs, resolve_plugin};
+use cigen::schema::{CigenConfig, DEFAULT_WORKFLOW, Job, Step};
+use clap::{Args, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::PathBuf;
+
+use super::common::{determine_plugin_dir, find_cigen_yml, load_config};
+
+/// Arguments for the `cigen list` subcommand.
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    #[command(subcommand)]
+    pub target: ListTarget,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ListTarget {
+    /// List jobs with their resolved settings
+    Jobs(ListJobsArgs),
+    /// List workflows and the jobs they contain
+    Workflows(ListOptions),
+    /// List available plugins with their handshake metadata
+    Plugins(ListOptions),
+    /// List profiles and the overlays they apply
+    Profiles(ListOptions),
+}
+
+#[derive(Debug, Args)]
+pub struct ListJobsArgs {
+    #[command(flatten)]
+    pub options: ListOptions,
+
+    /// Only list jobs belonging to this workflow
+    #[arg(short, long)]
+    pub workflow: Option<String>,
+
+    /// Filter predicates such as `image=ruby` (substring match, repeatable)
+    #[arg(long = "filter", value_name = "FIELD=VALUE")]
+    pub filters: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ListOptions {
+    /// Path to .cigen directory or cigen.yml file
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+    pub format: ListFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ListFormat {
+    Table,
+    Json,
+}
+
+/// Stable JSON representation of a job for `cigen list jobs --format json`
+#[derive(Debug, Default, Serialize)]
+pub struct JobSummary {
+    pub id: String,
+    pub workflow: String,
+    pub stage: String,
+    pub image: String,
+    pub architectures: Vec<String>,
+    pub needs: Vec<String>,
+    pub caches: Vec<String>,
+    pub parallelism: Option<u64>,
+    pub source_files: Vec<String>,
+    pub packages: Vec<String>,
+    pub services: Vec<String>,
+}
+
+/// Stable JSON representation of a plugin for `cigen list plugins --format json`
+#[derive(Debug, Serialize)]
+pub struct PluginSummary {
+    pub name: String,
+    pub version: String,
+    pub capabilities: Vec<String>,
+    pub path: String,
+}
+
+/// Stable JSON representation of a profile for `cigen list profiles --format json`
+#[derive(Debug, Serialize)]
+pub struct ProfileSummary {
+    pub name: String,
+    pub config_overlay: Option<String>,
+    pub job_overlays: Vec<String>,
+}
+
+/// Stable JSON representation of a workflow for `cigen list workflows --format json`
+#[derive(Debug, Serialize)]
+pub struct WorkflowSummary {
+    pub id: String,
+    pub jobs: Vec<String>,
+    pub stages: Vec<String>,
+    pub output_path: Option<String>,
+}
+
+pub fn list_command(args: ListArgs) -> Result<()> {
+    match args.target {
+        ListTarget::Jobs(args) => {
+            let filters = parse_filters(&args.filters)?;
+            let config = resolve(load_config(&find_cigen_yml(args.options.config.clone())?)?)?;
+            let jobs: Vec<JobSummary> = summarize_jobs(&config)
+                .into_iter()
+                .filter(|job| {
+                    args.workflow
+                        .as_deref()
+                        .is_none_or(|workflow| job.workflow == workflow)
+                })
+                .filter(|job| {
+                    filters
+                        .iter()
+                        .all(|(field, value)| job.matches(field, value))
+                })
+                .collect();
+
+            match args.options.format {
+                ListFormat::Json => println!("{}", serde_json::to_string_pretty(&jobs)?),
+                ListFormat::Table => print_table(
+                    &["ID", "WORKFLOW", "IMAGE", "ARCH", "NEEDS", "PARALLELISM"],
+                    jobs.iter()
+                        .map(|job| {
+                            vec![
+                                job.id.clone(),
+                                job.workflow.clone(),
+                                job.image.clone(),
+                                job.architectures.join(","),
+                                job.needs.join(","),
+                                job.parallelism.map(|p| p.to_string()).unwrap_or_default(),
+                            ]
+                        })
+                        .collect(),
+                ),
+            }
+        }
+        ListTarget::Workflows(options) => {
+            let config = resolve(load_config(&find_cigen_yml(options.config)?)?)?;
+            let workflows = summarize_workflows(&config);
+
+            match options.format {
+                ListFormat::Json => println!("{}", serde_json::to_string_pretty(&workflows)?),
+                ListFormat::Table => print_table(
+                    &["ID", "JOBS", "STAGES", "OUTPUT"],
+                    workflows
+                        .iter()
+                        .map(|workflow| {
+                            vec![
+                                workflow.id.clone(),
+                                workflow.jobs.len().to_string(),
+                                workflow.stages.join(","),
+                                workflow.output_path.clone().unwrap_or_default(),
+                            ]
+                        })
+                        .collect(),
+                ),
+            }
+        }
+        ListTarget::Plugins(options) => {
+            // The config is optional here: without one only discovered plugins are listed
+            let config = match options.config {
+                Some(path) => Some(load_config(&find_cigen_yml(Some(path))?)?),
+                None => find_cigen_yml(None)
+                    .ok()
+                    .map(|path| load_config(&path))
+                    .transpose()?,
+            };
+            let plugins = tokio::runtime::Runtime::new()?
+                .block_on(summarize_plugins(plugin_candidates(config.as_ref())?))?;
+
+            match options.format {
+                ListFormat::Json => println!("{}", serde_json::to_string_pretty(&plugins)?),
+                ListFormat::Table => print_table(
+                    &["NAME", "VERSION", "CAPABILITIES", "PATH"],
+                    plugins
+                        .iter()
+                        .map(|plugin| {
+                            vec![
+                                plugin.name.clone(),
+                                plugin.version.clone(),
+                                plugin.capabilities.join(","),
+                                plugin.path.clone(),
+                            ]
+                        })
+                        .collect(),
+                ),
+            }
+        }
+        ListTarget::Profiles(options) => {
+            let config_path = find_cigen_yml(options.config)?;
+            // Only split configs have overlays
+            let profiles = if config_path.is_dir() {
+                discover_profiles(&config_path)?
+            } else {
+                BTreeMap::new()
+            };
+            let profiles: Vec<ProfileSummary> = profiles
+                .into_iter()
+                .map(|(name, profile)| ProfileSummary {
+                    name,
+                    config_overlay: profile
+                        .config_overlay
+                        .map(|path| path.display().to_string()),
+                    job_overlays: profile.job_overlays,
+                })
+                .collect();
+
+            match options.format {
+                ListFormat::Json => println!("{}", serde_json::to_string_pretty(&profiles)?),
+                ListFormat::Table => print_table(
+                    &["PROFILE", "CONFIG OVERLAY", "JOB OVERLAYS"],
+                    profiles
+                        .iter()
+                        .map(|profile| {
+                            vec![
+                                profile.name.clone(),
+                                profile.config_overlay.clone().unwrap_or_default(),
+                                profile.job_overlays.join(","),
+                            ]
+                        })
+                        .collect(),
+                ),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Plugin binaries from `plugins:` in the config, the plugin search path, and
+/// the bundled plugin directory, without duplicates
+fn plugin_candidates(config: Option<&CigenConfig>) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    if let Some(config) = config {
+        let project_root = config
+            .project_root
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("."));
+        for entry in &config.plugins {
+            paths.push(resolve_plugin(entry, &project_root)?);
+        }
+    }
+    for dir in plugin_search_dirs()
+        .into_iter()
+        .chain(std::iter::once(determine_plugin_dir()))
+    {
+        paths.extend(discover_from_dir(&dir)?);
+    }
+
+    let mut seen = HashSet::new();
+    paths.retain(|path| seen.insert(path.canonicalize().unwrap_or_else(|_| path.clone())));
+    Ok(paths)
+}
+
+/// Handshake with each plugin binary and report what it declares.
+///
+/// Every plugin gets its own manager so that plugins sharing a name (such as a
+/// locally installed override of a bundled provider) are all listed.
+async fn summarize_plugins(paths: Vec<PathBuf>) -> Result<Vec<PluginSummary>> {
+    let mut summaries = Vec::new();
+    for path in paths {
+        let mut manager = PluginManager::new();
+        let name = manager.spawn(&path).await?;
+        if let Some(metadata) = manager.metadata(&name) {
+            summaries.push(PluginSummary {
+                name: metadata.name.clone(),
+                version: metadata.version.clone(),
+                capabilities: metadata.capabilities.clone(),
+                path: path.display().to_string(),
+            });
+        }
+        manager.shutdown().await?;
+    }
+    Ok(summaries)
+}
+
+/// The config as generation sees it: generated jobs and steps added, and
+/// matrices expanded into one job per variant
+fn resolve(config: CigenConfig) -> Result<CigenConfig> {
+    WorkflowOrchestrator::new(determine_plugin_dir()).expand_jobs(config)
+}
+
+/// The job a variant of a [`resolve`]d config was expanded from
+fn source_id<'a>(instance_id: &'a str, job: &'a Job) -> &'a str {
+    job.job_id.as_deref().unwrap_or(instance_id)
+}
+
+/// Summaries for every job of a [`resolve`]d config, with the variants of a
+/// matrix job merged into one, sorted by id
+pub fn summarize_jobs(config: &CigenConfig) -> Vec<JobSummary> {
+    let mut variants: BTreeMap<&str, Vec<(&String, &Job)>> = BTreeMap::new();
+    for (instance_id, job) in &config.jobs {
+        variants
+            .entry(source_id(instance_id, job))
+            .or_default()
+            .push((instance_id, job));
+    }
+    variants
+        .into_iter()
+        .map(|(id, mut jobs)| {
+            jobs.sort_by_key(|(instance_id, _)| *instance_id);
+            JobSummary::new(id, &jobs, config)
+        })
+        .collect()
+}
+
+/// Summaries for every workflow of a [`resolve`]d config with a job or a
+/// definition, sorted by id
+pub fn summarize_workflows(config: &CigenConfig) -> Vec<WorkflowSummary> {
+    let mut ids: BTreeSet<String> = config.workflows.keys().cloned().collect();
+    ids.extend(config.jobs.values().map(job_workflow));
+
+    ids.into_iter()
+        .map(|id| {
+            let jobs: BTreeSet<String> = config
+                .jobs
+                .iter()
+                .filter(|(_, job)| job_workflow(job) == id)
+                .map(|(instance_id, job)| source_id(instance_id, job).to_string())
+                .collect();
+            let stages: BTreeSet<String> = config
+                .jobs
+                .values()
+                .filter(|job| job_workflow(job) == id)
+                .filter_map(|job| job.stage.clone())
+                .collect();
+            let output_path = config
+                .workflows
+                .get(&id)
+                .and_then(|workflow| workflow.output_path.clone());
+            WorkflowSummary {
+                id,
+                jobs: jobs.into_iter().collect(),
+                stages: stages.into_iter().collect(),
+                output_path,
+            }
+        })
+        .collect()
+}
+
+impl JobSummary {
+    /// `variants` are the job's instances in `config`, sorted by instance id
+    fn new(id: &str, variants: &[(&String, &Job)], config: &CigenConfig) -> Self {
+        let job = variants[0].1;
+        // Variants of a matrix over images each have their own
+        let mut images: Vec<&str> = Vec::new();
+        for (_, variant) in variants {
+            if !images.contains(&variant.image.as_str()) {
+                images.push(&variant.image);
+            }
+        }
+        let architectures: BTreeSet<String> = variants
+            .iter()
+            .filter_map(|(_, job)| job.architecture.clone())
+            .collect();
+        // `needs` name variants; list the jobs they belong to
+        let needs: BTreeSet<String> = variants
+            .iter()
+            .flat_map(|(_, job)| &job.needs)
+            .map(|need| match config.jobs.get(need) {
+                Some(needed) => source_id(need, needed).to_string(),
+                None => need.clone(),
+            })
+            .collect();
+
+        let mut caches: Vec<String> = job.cache.iter().map(|cache| cache.name.clone()).collect();
+        let generated: Vec<String> = caches
+            .iter()
+            .map(|name| format!("Restore {name} cache"))
+            .collect();
+        caches.extend(job.steps.iter().filter_map(|step| {
+            match step {
+                Step::RestoreCache { restore_cache, .. } => match &restore_cache.name {
+                    Some(name) if generated.contains(name) => None,
+                    name => name
+                        .clone()
+                        .or_else(|| restore_cache.key.clone())
+                        .or_else(|| restore_cache.keys.first().cloned()),
+                },
+                _ => None,
+            }
+        }));
+
+        Self {
+            id: id.to_string(),
+            workflow: job_workflow(job),
+            stage: job.stage.clone().unwrap_or_else(|| "default".to_string()),
+            image: images.join(","),
+            architectures: architectures.into_iter().collect(),
+            needs: needs.into_iter().collect(),
+            caches,
+            parallelism: job
+                .extra
+                .get("parallelism")
+                .and_then(|value| value.as_u64()),
+            source_files: job.source_files.clone(),
+            packages: job.packages.iter().map(|pkg| pkg.name.clone()).collect(),
+            services: job.services.clone(),
+        }
+    }
+
+    fn field_values(&self, field: &str) -> Option<Vec<String>> {
+        Some(match field {
+            "id" => vec![self.id.clone()],
+            "workflow" => vec![self.workflow.clone()],
+            "stage" => vec![self.stage.clone()],
+            "image" => vec![self.image.clone()],
+            "arch" | "architecture" | "architectures" => self.architectures.clone(),
+            "needs" => self.needs.clone(),
+            "cache" | "caches" => self.caches.clone(),
+            "parallelism" => self
+                .parallelism
+                .map(|p| p.to_string())
+                .into_iter()
+                .collect(),
+            "source_files" => self.source_files.clone(),
+            "package" | "packages" => self.packages.clone(),
+            "service" | "services" => self.services.clone(),
+            _ => return None,
+        })
+    }
+
+    fn matches(&self, field: &str, value: &str) -> bool {
+        self.field_values(field)
+            .unwrap_or_default()
+            .iter()
+            .any(|candidate| candidate.contains(value))
+    }
+}
+
+const FILTER_FIELDS: &[&str] = &[
+    "id",
+    "workflow",
+    "stage",
+    "image",
+    "arch",
+    "needs",
+    "caches",
+    "parallelism",
+    "source_files",
+    "packages",
+    "services",
+];
+
+fn parse_filters(filters: &[String]) -> Result<Vec<(String, String)>> {
+    filters
+        .iter()
+        .map(|filter| {
+            let Some((field, value)) = filter.split_once('=') else {
+                bail!("Invalid filter '{filter}', expected FIELD=VALUE");
+            };
+            let field = field.trim();
+            if JobSummary::default().field_values(field).is_none() {
+                bail!(
+                    "Unknown filter field '{field}'. Available: {}",
+                    FILTER_FIELDS.join(", ")
+                );
+            }
+            Ok((field.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+fn job_workflow(job: &Job) -> String {
+    job.workflow
+        .clone()
+        .unwrap_or_else(|| DEFAULT_WORKFLOW.to_string())
+}
+
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let render = |cells: Vec<String>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!(
+        "{}",
+        render(headers.iter().map(|header| header.to_string()).collect())
+    );
+    for row in rows {
+        println!("{}", render(row));
+    }
+}
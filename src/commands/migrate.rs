@@ -0,0 +1,133 @@
+use anyhow::{Context, Result, bail};
+use cigen::migrate::{Migration, circleci, github};
+use clap::{Args, Subcommand};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Arguments for the `cigen migrate` subcommand.
+#[derive(Debug, Args)]
+pub struct MigrateArgs {
+    #[command(subcommand)]
+    pub target: MigrateTarget,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateTarget {
+    /// Import an existing .circleci/config.yml
+    Circleci(MigrateCircleciArgs),
+    /// Import GitHub Actions workflow files
+    Github(MigrateGithubArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct MigrateCircleciArgs {
+    /// CircleCI config to import
+    #[arg(short, long, default_value = ".circleci/config.yml")]
+    pub input: String,
+
+    /// Directory to write the cigen config to
+    #[arg(short, long, default_value = ".cigen")]
+    pub output: String,
+
+    /// Write into the output directory even if it already has files
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct MigrateGithubArgs {
+    /// Workflow file, or a directory of workflow files (one cigen workflow each)
+    #[arg(short, long, default_value = ".github/workflows")]
+    pub input: String,
+
+    /// Directory to write the cigen config to
+    #[arg(short, long, default_value = ".cigen")]
+    pub output: String,
+
+    /// Write into the output directory even if it already has files
+    #[arg(long)]
+    pub force: bool,
+}
+
+pub fn migrate_command(args: MigrateArgs) -> Result<()> {
+    match args.target {
+        MigrateTarget::Circleci(args) => migrate_circleci(&args),
+        MigrateTarget::Github(args) => migrate_github(&args),
+    }
+}
+
+fn migrate_circleci(args: &MigrateCircleciArgs) -> Result<()> {
+    let source = fs::read_to_string(&args.input)
+        .with_context(|| format!("Failed to read {}", args.input))?;
+    let migration =
+        circleci::migrate(&source).with_context(|| format!("Failed to migrate {}", args.input))?;
+
+    write_migration(&migration, Path::new(&args.output), args.force)
+}
+
+fn migrate_github(args: &MigrateGithubArgs) -> Result<()> {
+    let mut workflows = Vec::new();
+    for path in workflow_files(Path::new(&args.input))? {
+        let source = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        workflows.push((path, source));
+    }
+    if workflows.is_empty() {
+        bail!("No workflow files found in {}", args.input);
+    }
+
+    let migration = github::migrate(&workflows)?;
+    write_migration(&migration, Path::new(&args.output), args.force)
+}
+
+/// The file itself, or the YAML files directly inside a directory
+fn workflow_files(input: &Path) -> Result<Vec<PathBuf>> {
+    if input.is_file() {
+        return Ok(vec![input.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    for entry in WalkDir::new(input).max_depth(1).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("Failed to read {}", input.display()))?;
+        let is_yaml = matches!(
+            entry.path().extension().and_then(|ext| ext.to_str()),
+            Some("yml" | "yaml")
+        );
+        if entry.file_type().is_file() && is_yaml {
+            files.push(entry.into_path());
+        }
+    }
+    Ok(files)
+}
+
+fn write_migration(migration: &Migration, output: &Path, force: bool) -> Result<()> {
+    ensure_writable(output, force)?;
+    migration.write(output)?;
+
+    for warning in &migration.warnings {
+        tracing::warn!("{warning}");
+    }
+    print!("{}", migration.summary());
+    tracing::info!(
+        "Wrote {} file(s) to {}",
+        migration.files.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn ensure_writable(output: &Path, force: bool) -> Result<()> {
+    let has_files = output.is_dir()
+        && fs::read_dir(output)
+            .with_context(|| format!("Failed to read {}", output.display()))?
+            .next()
+            .is_some();
+    if has_files && !force {
+        bail!(
+            "{} already has files; pass --force to write into it anyway",
+            output.display()
+        );
+    }
+    Ok(())
+}
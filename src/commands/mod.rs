@@ -1,5 +1,32 @@
+mod actions;
+mod audit;
+mod common;
+mod fmt;
 mod generate;
 mod hash;
+mod inspect;
+mod lint;
+mod list;
+mod migrate;
+mod new;
+mod orbs;
+mod run;
+mod schema;
+mod stats;
+mod verify;
 
-pub use generate::generate_command;
+pub use actions::{ActionsArgs, actions_command};
+pub use audit::{AuditArgs, audit_command};
+pub use fmt::{FmtArgs, fmt_command};
+pub use generate::{GenerateArgs, generate_command};
 pub use hash::{HashArgs, hash_command};
+pub use inspect::{InspectArgs, inspect_command};
+pub use lint::{LintArgs, lint_command};
+pub use list::{ListArgs, list_command};
+pub use migrate::{MigrateArgs, migrate_command};
+pub use new::{NewArgs, new_command};
+pub use orbs::{OrbsArgs, orbs_command};
+pub use run::{RunArgs, run_command};
+pub use schema::{SchemaArgs, schema_command};
+pub use stats::{StatsArgs, stats_command};
+pub use verify::{VerifyArgs, verify_command};
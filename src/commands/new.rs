@@ -0,0 +1,256 @@
+use anyhow::{Context, Result, bail};
+use cigen::scaffold::{JobChoices, JobOptions, Prompter, prompt_job, render_job};
+use cigen::schema::{CigenConfig, unknown_reference_message};
+use clap::{Args, Subcommand};
+use serde_yaml::{Mapping, Value};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::common::{find_cigen_yml, load_config};
+
+/// Arguments for the `cigen new` subcommand.
+#[derive(Debug, Args)]
+pub struct NewArgs {
+    #[command(subcommand)]
+    pub target: NewTarget,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum NewTarget {
+    /// Create a job file, asking for anything not given as a flag
+    Job(NewJobArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct NewJobArgs {
+    /// Workflow the job belongs to
+    #[arg(short, long)]
+    pub workflow: Option<String>,
+
+    /// Job id, e.g. `rspec_system` or `test/rspec_system` for a stage directory
+    #[arg(short, long)]
+    pub name: Option<String>,
+
+    /// Start from an existing job's file, keeping its other fields
+    #[arg(long, value_name = "JOB")]
+    pub from: Option<String>,
+
+    /// Docker image, or a docker_build image name
+    #[arg(long)]
+    pub image: Option<String>,
+
+    /// Service from the config's `services:` (repeatable)
+    #[arg(long = "service", value_name = "NAME")]
+    pub services: Vec<String>,
+
+    /// Source file group whose changes run the job
+    #[arg(long, value_name = "GROUP")]
+    pub source_files: Option<String>,
+
+    /// Cache from the config's `caches:` (repeatable)
+    #[arg(long = "cache", value_name = "NAME")]
+    pub caches: Vec<String>,
+
+    /// Containers the job runs in at once
+    #[arg(long)]
+    pub parallelism: Option<u32>,
+
+    /// Don't ask; use the flags and defaults as they are
+    #[arg(long)]
+    pub no_input: bool,
+
+    /// Path to .cigen directory
+    #[arg(short, long)]
+    pub config: Option<String>,
+}
+
+pub fn new_command(args: NewArgs) -> Result<()> {
+    match args.target {
+        NewTarget::Job(args) => new_job(args),
+    }
+}
+
+fn new_job(args: NewJobArgs) -> Result<()> {
+    let config_path = find_cigen_yml(args.config.clone())?;
+    if !config_path.is_dir() {
+        bail!(
+            "cigen new job writes into a split .cigen/ config; {} is a single file",
+            config_path.display()
+        );
+    }
+    let config = load_config(&config_path)?;
+    let options = JobOptions::from_config(&config);
+
+    let stdin = io::stdin();
+    let mut prompter = Prompter::new(stdin.lock(), io::stderr());
+
+    let base = args
+        .from
+        .as_deref()
+        .map(|from| Ok::<_, anyhow::Error>((from, cloned_job(&config, from)?)))
+        .transpose()?;
+
+    let workflow = match (&args.workflow, &base) {
+        (Some(workflow), _) => workflow.clone(),
+        (None, Some((from, _))) if args.no_input => config.jobs[*from]
+            .workflow
+            .clone()
+            .unwrap_or_else(|| "main".to_string()),
+        (None, None) if args.no_input => bail!("--no-input needs --workflow"),
+        (None, base) => {
+            let mut workflows: Vec<&str> = config.workflows.keys().map(String::as_str).collect();
+            workflows.sort();
+            let default = match (base, workflows.as_slice()) {
+                (Some((from, _)), _) => config.jobs[*from].workflow.clone().unwrap_or_default(),
+                (None, [only]) => only.to_string(),
+                _ => String::new(),
+            };
+            prompter.ask(&format!("Workflow ({})", workflows.join(", ")), &default)?
+        }
+    };
+    let jobs_dir = jobs_dir(&config_path, &config, &workflow)?;
+
+    let name = match &args.name {
+        Some(name) => name.clone(),
+        None if args.no_input => bail!("--no-input needs --name"),
+        None => prompter.ask("Job name", "")?,
+    };
+    check_job_name(&name)?;
+    let path = jobs_dir.join(format!("{name}.yml"));
+    if let Some(existing) = config.jobs.get(&name) {
+        bail!(
+            "Job '{name}' already exists in workflow '{}'; pick another name",
+            existing.workflow.as_deref().unwrap_or_default()
+        );
+    }
+    if path.exists() {
+        bail!("{} already exists", path.display());
+    }
+
+    let mut defaults = base
+        .as_ref()
+        .map(|(_, job)| JobChoices::from_job(job))
+        .unwrap_or_default();
+    if let Some(image) = &args.image {
+        defaults.image = image.clone();
+    }
+    if !args.services.is_empty() {
+        defaults.services = args.services.clone();
+    }
+    if let Some(group) = &args.source_files {
+        defaults.source_file_group = Some(group.trim_start_matches('@').to_string());
+    }
+    if !args.caches.is_empty() {
+        defaults.cache = args.caches.clone();
+    }
+    if args.parallelism.is_some() {
+        defaults.parallelism = args.parallelism.filter(|n| *n > 1);
+    }
+    options.check(&defaults)?;
+    let choices = if args.no_input {
+        defaults
+    } else {
+        prompt_job(&mut prompter, &options, defaults)?
+    };
+
+    let yaml = render_job(
+        &name,
+        &workflow,
+        &choices,
+        base.as_ref().map(|(from, job)| (*from, job)),
+    )?;
+    write_job(&config_path, &path, &yaml)?;
+    println!("Created {}", path.display());
+    Ok(())
+}
+
+/// The file a job was loaded from, for `--from`
+fn cloned_job(config: &CigenConfig, from: &str) -> Result<Mapping> {
+    let job = config.jobs.get(from).with_context(|| {
+        unknown_reference_message(
+            &format!("Unknown job '{from}'"),
+            from,
+            config.jobs.keys().map(String::as_str),
+        )
+    })?;
+    let path = job
+        .source_file
+        .as_ref()
+        .with_context(|| format!("Job '{from}' wasn't loaded from a file"))?;
+    if path.extension().is_some_and(|ext| ext == "j2") {
+        bail!(
+            "Job '{from}' is a template ({}); copy it by hand",
+            path.display()
+        );
+    }
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let value: Value = serde_yaml::from_str(&contents)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    let value = match &job.source_section {
+        Some(section) => value.get(section.as_str()).cloned().unwrap_or_default(),
+        None => value,
+    };
+    match value {
+        Value::Mapping(job) => Ok(job),
+        _ => bail!("Job '{from}' in {} isn't a mapping", path.display()),
+    }
+}
+
+/// Where a workflow's job files go
+fn jobs_dir(config_path: &Path, config: &CigenConfig, workflow: &str) -> Result<PathBuf> {
+    if !config.workflows.contains_key(workflow) {
+        bail!(
+            "{}",
+            unknown_reference_message(
+                &format!("Unknown workflow '{workflow}'"),
+                workflow,
+                config.workflows.keys().map(String::as_str),
+            )
+        );
+    }
+    let workflow_dir = config_path.join("workflows").join(workflow);
+    if !workflow_dir.is_dir() {
+        bail!(
+            "Workflow '{workflow}' is defined in a single file, not {}; add the job there",
+            workflow_dir.display()
+        );
+    }
+    for jobs_file in ["jobs.yml", "jobs.yaml"] {
+        if workflow_dir.join(jobs_file).is_file() {
+            bail!("Workflow '{workflow}' defines its jobs in {jobs_file}; add the job there");
+        }
+    }
+    Ok(workflow_dir.join("jobs"))
+}
+
+fn check_job_name(name: &str) -> Result<()> {
+    let valid = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
+    };
+    if !name.split('/').all(valid) {
+        bail!(
+            "Invalid job name '{name}'; use letters, digits, '_' and '-', with '/' before a stage's jobs"
+        );
+    }
+    Ok(())
+}
+
+/// Write the job and load the config with it, removing it again if the
+/// config no longer loads
+fn write_job(config_path: &Path, path: &Path, yaml: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(path, yaml).with_context(|| format!("Failed to write {}", path.display()))?;
+    if let Err(error) = load_config(config_path) {
+        fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        return Err(error.context("The new job doesn't validate, so it wasn't kept"));
+    }
+    Ok(())
+}
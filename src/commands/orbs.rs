@@ -0,0 +1,102 @@
+use anyhow::{Context, Result};
+use cigen::orbs::{
+    HttpRegistry, OrbReference, OrbRegistry, OrbVersion, configured_orbs, lockfile_path,
+    newer_versions, read_lockfile, resolve, write_lockfile,
+};
+use clap::{Args, Subcommand};
+use std::collections::BTreeMap;
+
+use super::common::{find_cigen_yml, load_config};
+
+/// Arguments for the `cigen orbs` subcommand.
+#[derive(Debug, Args)]
+pub struct OrbsArgs {
+    #[command(subcommand)]
+    pub target: OrbsTarget,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum OrbsTarget {
+    /// Resolve every orb to an exact version and write .cigen/orbs.lock.yml
+    Lock(OrbsLockArgs),
+    /// List locked orbs that have newer published versions
+    Outdated(OrbsOutdatedArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct OrbsLockArgs {
+    /// Path to .cigen directory or cigen.yml file
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Don't query the orb registry; every orb must already pin an exact X.Y.Z version
+    #[arg(long)]
+    pub offline: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct OrbsOutdatedArgs {
+    /// Path to .cigen directory or cigen.yml file
+    #[arg(short, long)]
+    pub config: Option<String>,
+}
+
+pub fn orbs_command(args: OrbsArgs) -> Result<()> {
+    match args.target {
+        OrbsTarget::Lock(args) => lock_orbs(&args),
+        OrbsTarget::Outdated(args) => list_outdated(&args),
+    }
+}
+
+fn lock_orbs(args: &OrbsLockArgs) -> Result<()> {
+    let config = load_config(&find_cigen_yml(args.config.clone())?)?;
+    let registry = (!args.offline).then(HttpRegistry::from_env);
+    let registry = registry
+        .as_ref()
+        .map(|registry| registry as &dyn OrbRegistry);
+
+    let mut locked = BTreeMap::new();
+    for (alias, reference) in configured_orbs(&config) {
+        let parsed = OrbReference::parse(&reference)
+            .with_context(|| format!("Orb '{alias}' can't be locked"))?;
+        let exact = resolve(&parsed, registry)?;
+        tracing::info!("  {alias}: {}", parsed.at(exact));
+        locked.insert(alias, parsed.at(exact));
+    }
+
+    let path = lockfile_path(&config);
+    write_lockfile(&path, &locked)?;
+    tracing::info!("Locked {} orb(s) in {}", locked.len(), path.display());
+    Ok(())
+}
+
+fn list_outdated(args: &OrbsOutdatedArgs) -> Result<()> {
+    let config = load_config(&find_cigen_yml(args.config.clone())?)?;
+    let locked = read_lockfile(&lockfile_path(&config))?.unwrap_or_default();
+    let registry = HttpRegistry::from_env();
+
+    let mut outdated = 0;
+    for (alias, configured) in configured_orbs(&config) {
+        let current = locked.get(&alias).unwrap_or(&configured);
+        let reference = OrbReference::parse(current)
+            .with_context(|| format!("Orb '{alias}' can't be checked"))?;
+        let OrbVersion::Exact(version) = reference.version else {
+            tracing::warn!("Skipping orb '{alias}' ({current}): not locked to an exact version");
+            continue;
+        };
+        let newer = newer_versions(&reference, version, &registry)?;
+        if let Some(latest) = newer.last() {
+            println!(
+                "{alias}: {current} -> {} ({} newer)",
+                reference.at(*latest),
+                newer.len()
+            );
+            outdated += 1;
+        }
+    }
+
+    if outdated == 0 {
+        tracing::info!("All orbs are up to date");
+    }
+    Ok(())
+}
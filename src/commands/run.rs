@@ -0,0 +1,56 @@
+use anyhow::{Result, bail};
+use cigen::local::{JobStatus, LocalRunner, jobs_to_run};
+use cigen::orchestrator::WorkflowOrchestrator;
+use clap::Args;
+use std::path::PathBuf;
+
+use super::common::{VarArgs, determine_plugin_dir, find_cigen_yml, load_config_with_vars};
+
+/// Arguments for the `cigen run` subcommand.
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Job id (e.g. `test/rspec`) or generated job name; a job with a matrix
+    /// runs every variant
+    #[arg(long)]
+    pub job: String,
+
+    /// Path to .cigen directory or cigen.yml file
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Run each step in the job's docker image instead of a host shell
+    #[arg(long)]
+    pub docker: bool,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
+}
+
+pub fn run_command(args: RunArgs) -> Result<()> {
+    let config_path = find_cigen_yml(args.config)?;
+    let config = load_config_with_vars(&config_path, None, &args.vars)?;
+    let root = config
+        .project_root
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    // Expand jobs exactly as `cigen generate` does before handing them to providers
+    let config = WorkflowOrchestrator::new(determine_plugin_dir()).expand_jobs(config)?;
+    let jobs = jobs_to_run(&config, &args.job)?;
+
+    let runner = LocalRunner::new(root, args.docker)?;
+    let report = runner.run(&config, &jobs)?;
+
+    println!();
+    for (name, status) in &report {
+        println!("{status:>7}  {name}");
+    }
+    let failed = report
+        .iter()
+        .filter(|(_, status)| *status != JobStatus::Passed)
+        .count();
+    if failed > 0 {
+        bail!("{failed} of {} jobs did not pass", report.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use anyhow::{Context, Result, bail};
+use cigen::schema::{BUNDLED_PROVIDERS, BundledSchema, CIGEN_SCHEMAS, provider_schema};
+use clap::{Args, Subcommand};
+use std::fs;
+use std::path::PathBuf;
+
+/// Arguments for the `cigen schema` subcommand.
+#[derive(Debug, Args)]
+pub struct SchemaArgs {
+    #[command(subcommand)]
+    pub target: SchemaTarget,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SchemaTarget {
+    /// Write the bundled JSON Schemas to a directory for offline editor support
+    Export(SchemaExportArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct SchemaExportArgs {
+    /// Also export the schema for this provider's generated files (e.g. circleci)
+    #[arg(long)]
+    pub provider: Option<String>,
+
+    /// Directory to write the schemas to
+    #[arg(long, default_value = "schemas")]
+    pub out: PathBuf,
+}
+
+pub fn schema_command(args: SchemaArgs) -> Result<()> {
+    match args.target {
+        SchemaTarget::Export(args) => export_schemas(&args),
+    }
+}
+
+fn export_schemas(args: &SchemaExportArgs) -> Result<()> {
+    let mut schemas: Vec<BundledSchema> = CIGEN_SCHEMAS.to_vec();
+    if let Some(provider) = args.provider.as_deref() {
+        let Some(schema) = provider_schema(provider) else {
+            bail!(
+                "No bundled schema for provider '{provider}' (available: {})",
+                BUNDLED_PROVIDERS.join(", ")
+            );
+        };
+        schemas.push(schema);
+    }
+
+    fs::create_dir_all(&args.out)
+        .with_context(|| format!("Failed to create {}", args.out.display()))?;
+    for schema in &schemas {
+        let path = args.out.join(schema.file_name);
+        fs::write(&path, schema.contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        tracing::info!("Wrote {}", path.display());
+    }
+
+    Ok(())
+}
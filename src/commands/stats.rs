@@ -0,0 +1,108 @@
+use anyhow::Result;
+use cigen::orchestrator::WorkflowOrchestrator;
+use cigen::stats::{ConfigStats, collect_stats};
+use clap::{Args, ValueEnum};
+use std::collections::BTreeMap;
+
+use super::common::{VarArgs, determine_plugin_dir, find_cigen_yml, load_config_with_vars};
+
+/// Arguments for the `cigen stats` subcommand.
+#[derive(Debug, Args)]
+pub struct StatsArgs {
+    /// Path to .cigen directory or cigen.yml file
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Merge the overlays for this profile over the base config
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = StatsFormat::Text)]
+    pub format: StatsFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StatsFormat {
+    Text,
+    Json,
+}
+
+/// Generate every provider's files in memory and report on them
+pub fn stats_command(args: StatsArgs) -> Result<()> {
+    let config_path = find_cigen_yml(args.config)?;
+    let config = load_config_with_vars(&config_path, args.profile.as_deref(), &args.vars)?;
+
+    // One pass per provider, so each file is attributed to its provider
+    let runtime = tokio::runtime::Runtime::new()?;
+    let mut generated = BTreeMap::new();
+    for provider in &config.providers {
+        let mut provider_config = config.clone();
+        provider_config.providers = vec![provider.clone()];
+        let mut orchestrator = WorkflowOrchestrator::new(determine_plugin_dir());
+        let result = runtime.block_on(orchestrator.execute(provider_config))?;
+        generated.insert(provider.clone(), result.files);
+    }
+
+    let stats = collect_stats(&config, &generated)?;
+    match args.format {
+        StatsFormat::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+        StatsFormat::Text => print!("{}", render_text(&stats)),
+    }
+    Ok(())
+}
+
+fn render_text(stats: &ConfigStats) -> String {
+    let mut lines = vec![
+        format!("Workflows: {}", stats.workflows),
+        format!(
+            "Jobs: {} ({} after matrix expansion)",
+            stats.jobs, stats.expanded_jobs
+        ),
+    ];
+    for provider in &stats.providers {
+        lines.push(String::new());
+        lines.push(format!("{}:", provider.provider));
+        lines.push(format!(
+            "  Files: {} ({} bytes)",
+            provider.files.len(),
+            provider.total_bytes
+        ));
+        let width = provider
+            .files
+            .iter()
+            .map(|file| file.path.len())
+            .max()
+            .unwrap_or(0);
+        for file in &provider.files {
+            lines.push(format!("    {:<width$}  {} bytes", file.path, file.bytes));
+        }
+        lines.push(format!("  Jobs: {}", provider.jobs));
+        lines.push(format!(
+            "  Steps: {} ({} from job files, {} injected)",
+            provider.user_steps + provider.injected_steps,
+            provider.user_steps,
+            provider.injected_steps
+        ));
+        if !provider.largest_jobs.is_empty() {
+            lines.push("  Largest jobs:".to_string());
+            for job in &provider.largest_jobs {
+                lines.push(format!("    {} ({} steps)", job.name, job.steps));
+            }
+        }
+        lines.push(format!("  Cache keys: {}", provider.cache_keys));
+        if let Some(credits) = &provider.credits {
+            lines.push(format!("  Credits weight: {}", credits.weight));
+            if !credits.unknown_resource_classes.is_empty() {
+                lines.push(format!(
+                    "    (leaves out resource classes without a weight: {})",
+                    credits.unknown_resource_classes.join(", ")
+                ));
+            }
+        }
+    }
+    lines.join("\n") + "\n"
+}
@@ -0,0 +1,233 @@
+use anyhow::{Context, Result, bail};
+use cigen::actions::{self, pin_actions_enabled};
+use cigen::header::{config_hash, header_hash, without_timestamp};
+use cigen::orchestrator::WorkflowOrchestrator;
+use cigen::schema::{OUTPUT_DIR_FLAG, output_file_path, unknown_reference_message};
+use clap::Args;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use super::common::{VarArgs, determine_plugin_dir, find_cigen_yml, load_config_with_vars};
+use super::generate::finish_files;
+
+/// Directories that never hold generated files
+const SKIPPED_DIRS: &[&str] = &[".git", ".cigen", "target", "node_modules"];
+
+/// Arguments for the `cigen verify` subcommand.
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// Path to .cigen directory or cigen.yml file
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Directory the generated files were written to (default: .)
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Only check this provider's files
+    #[arg(long)]
+    pub provider: Option<String>,
+
+    /// Merge the overlays for this profile over the base config
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
+
+    /// Write the regenerated files over the ones that differ
+    #[arg(long, conflicts_with = "hash_only")]
+    pub fix: bool,
+
+    /// Only compare the config hash in each file's header with the current
+    /// config's, without regenerating
+    #[arg(long, conflicts_with_all = ["provider", "profile"])]
+    pub hash_only: bool,
+}
+
+/// Regenerate the configured providers' files in memory and compare them with
+/// the files on disk, failing with a summary of the differences
+pub fn verify_command(args: VerifyArgs) -> Result<()> {
+    let config_path = find_cigen_yml(args.config)?;
+    let output_dir = args
+        .output
+        .as_deref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    if args.hash_only {
+        return verify_hashes(&config_path, &output_dir);
+    }
+
+    let mut config = load_config_with_vars(&config_path, args.profile.as_deref(), &args.vars)?;
+    if let Some(provider) = &args.provider {
+        if !config.providers.contains(provider) {
+            bail!(
+                "{}",
+                unknown_reference_message(
+                    &format!("Provider '{provider}' isn't configured"),
+                    provider,
+                    config.providers.iter().map(String::as_str),
+                )
+            );
+        }
+        config.providers = vec![provider.clone()];
+    }
+
+    // Offline, so actions missing from the lockfile fail instead of being resolved
+    let actions_lockfile = pin_actions_enabled(&config).then(|| actions::lockfile_path(&config));
+    let runtime = tokio::runtime::Runtime::new()?;
+    let mut orchestrator = WorkflowOrchestrator::new(determine_plugin_dir());
+    if let Some(output) = &args.output {
+        orchestrator.set_flag(OUTPUT_DIR_FLAG, output);
+    }
+    let mut files = runtime.block_on(orchestrator.execute(config))?.files;
+    finish_files(
+        &mut files,
+        &config_path,
+        actions_lockfile.as_deref(),
+        true,
+        false,
+    )?;
+
+    let differences = compare_files(&files, &output_dir)?;
+    if differences.is_empty() {
+        tracing::info!("{} generated file(s) are up to date", files.len());
+        return Ok(());
+    }
+    if args.fix {
+        for (path, _) in &differences {
+            let full_path = output_file_path(&output_dir, path);
+            if let Some(parent) = full_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&full_path, &files[path])
+                .with_context(|| format!("Failed to write file: {}", full_path.display()))?;
+            tracing::info!("Updated {}", full_path.display());
+        }
+        return Ok(());
+    }
+
+    for (path, difference) in &differences {
+        println!(
+            "{}: {difference}",
+            output_file_path(&output_dir, path).display()
+        );
+    }
+    bail!(
+        "{} of {} generated file(s) differ from what the config generates; run `cigen verify --fix` or `cigen generate` to update them",
+        differences.len(),
+        files.len()
+    )
+}
+
+/// Generated files that are missing or different on disk, sorted by path,
+/// with a summary of each difference
+fn compare_files(
+    files: &HashMap<String, String>,
+    output_dir: &Path,
+) -> Result<Vec<(String, String)>> {
+    let mut paths: Vec<&String> = files.keys().collect();
+    paths.sort();
+
+    let mut differences = Vec::new();
+    for path in paths {
+        let full_path = output_file_path(output_dir, path);
+        let on_disk = match fs::read_to_string(&full_path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                differences.push((path.clone(), "missing".to_string()));
+                continue;
+            }
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Failed to read {}", full_path.display()));
+            }
+        };
+        let on_disk = without_timestamp(&on_disk);
+        if on_disk != files[path].as_str() {
+            differences.push((path.clone(), diff_summary(&files[path], &on_disk)));
+        }
+    }
+    Ok(differences)
+}
+
+/// Where the file on disk first differs from the regenerated one, and how
+/// many lines regenerating would add and remove
+fn diff_summary(regenerated: &str, on_disk: &str) -> String {
+    let first_difference = regenerated
+        .lines()
+        .zip(on_disk.lines())
+        .position(|(new, old)| new != old)
+        .unwrap_or_else(|| regenerated.lines().count().min(on_disk.lines().count()))
+        + 1;
+
+    // Line counts, positive for lines only the regenerated file has
+    let mut counts: HashMap<&str, i64> = HashMap::new();
+    for line in regenerated.lines() {
+        *counts.entry(line).or_default() += 1;
+    }
+    for line in on_disk.lines() {
+        *counts.entry(line).or_default() -= 1;
+    }
+    let added: i64 = counts.values().filter(|count| **count > 0).sum();
+    let removed: i64 = -counts.values().filter(|count| **count < 0).sum::<i64>();
+    format!("differs from line {first_difference} (+{added} -{removed} lines)")
+}
+
+/// Compare the config hash in each generated file's header with the current
+/// config's, listing the stale files
+fn verify_hashes(config_path: &Path, output_dir: &Path) -> Result<()> {
+    let expected = config_hash(config_path)?;
+
+    let mut generated = 0;
+    let mut stale = Vec::new();
+    let entries = WalkDir::new(output_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0
+                || !entry.file_type().is_dir()
+                || !SKIPPED_DIRS.contains(&entry.file_name().to_string_lossy().as_ref())
+        });
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read {}", output_dir.display()))?;
+        let path = entry.path();
+        let is_yaml = path
+            .extension()
+            .is_some_and(|extension| extension == "yml" || extension == "yaml");
+        if !entry.file_type().is_file() || !is_yaml {
+            continue;
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let Some(hash) = header_hash(&content) else {
+            continue;
+        };
+        generated += 1;
+        let relative = path.strip_prefix(output_dir).unwrap_or(path);
+        if hash == expected {
+            tracing::info!("  ✓ {}", relative.display());
+        } else {
+            println!("{}", relative.display());
+            stale.push(relative.to_path_buf());
+        }
+    }
+
+    if generated == 0 {
+        bail!(
+            "No files generated by cigen found in {}; run `cigen generate` first",
+            output_dir.display()
+        );
+    }
+    if !stale.is_empty() {
+        bail!(
+            "{} of {generated} generated file(s) are out of date with {}; run `cigen generate` to update them",
+            stale.len(),
+            config_path.display()
+        );
+    }
+    tracing::info!("{generated} generated file(s) are up to date");
+    Ok(())
+}
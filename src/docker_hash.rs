@@ -0,0 +1,270 @@
+//! Content hashes of `docker_build` images
+//!
+//! An image's hash covers its Dockerfile, the files matched by `hash_sources`,
+//! its build args, and the hashes of the images it depends on. Generation tags
+//! images with it, and `cigen hash --docker-base` prints it so scripts outside
+//! CI tag images the same way.
+
+use anyhow::{Context, Result, bail};
+use globwalk::{FileType, GlobWalkerBuilder};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::schema::{DockerBuildConfig, DockerImage, unknown_reference_message};
+
+/// Number of hex characters of the content hash used in image tags
+pub const TAG_HASH_LENGTH: usize = 16;
+
+/// The hash an image is tagged with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImageHash {
+    pub name: String,
+    pub hash: String,
+    /// Files each `hash_sources` pattern matched
+    pub patterns: Vec<PatternFiles>,
+}
+
+/// Number of files a `hash_sources` pattern matched
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PatternFiles {
+    pub pattern: String,
+    pub files: usize,
+}
+
+/// Hashes of every image under `root`, dependencies first
+pub fn image_hashes(root: &Path, docker: &DockerBuildConfig) -> Result<Vec<ImageHash>> {
+    // The parent of a relative `.cigen` is empty, where globs match nothing
+    let root = if root.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        root
+    };
+    let mut hashes: HashMap<String, String> = HashMap::new();
+    let mut results = Vec::new();
+    for image in ordered_images(docker)? {
+        let hash = compute_image_hash(root, &image, &hashes)
+            .with_context(|| format!("Failed to hash docker_build image '{}'", image.name))?;
+        hashes.insert(image.name.clone(), hash.hash.clone());
+        results.push(hash);
+    }
+    Ok(results)
+}
+
+/// Images ordered so dependencies come before the images built from them
+pub fn ordered_images(docker: &DockerBuildConfig) -> Result<Vec<DockerImage>> {
+    let by_name: BTreeMap<&str, &DockerImage> = docker
+        .images
+        .iter()
+        .map(|image| (image.name.as_str(), image))
+        .collect();
+    if by_name.len() != docker.images.len() {
+        bail!("docker_build.images contains duplicate image names");
+    }
+
+    for image in &docker.images {
+        if image.arch.is_empty() {
+            bail!(
+                "docker_build image '{}' must list at least one architecture",
+                image.name
+            );
+        }
+        for dependency in &image.depends_on {
+            if !by_name.contains_key(dependency.as_str()) {
+                bail!(
+                    "{}",
+                    unknown_reference_message(
+                        &format!(
+                            "docker_build image '{}' depends on unknown image '{dependency}'",
+                            image.name
+                        ),
+                        dependency,
+                        by_name.keys().copied(),
+                    )
+                );
+            }
+        }
+    }
+
+    fn visit<'a>(
+        name: &'a str,
+        by_name: &BTreeMap<&'a str, &'a DockerImage>,
+        visiting: &mut Vec<&'a str>,
+        done: &mut BTreeSet<&'a str>,
+        ordered: &mut Vec<DockerImage>,
+    ) -> Result<()> {
+        if done.contains(name) {
+            return Ok(());
+        }
+        if visiting.contains(&name) {
+            visiting.push(name);
+            bail!(
+                "docker_build images have a dependency cycle: {}",
+                visiting.join(" -> ")
+            );
+        }
+        visiting.push(name);
+        let image = by_name[name];
+        for dependency in &image.depends_on {
+            visit(dependency, by_name, visiting, done, ordered)?;
+        }
+        visiting.pop();
+        done.insert(name);
+        ordered.push(image.clone());
+        Ok(())
+    }
+
+    let mut ordered = Vec::new();
+    let mut done = BTreeSet::new();
+    for name in by_name.keys() {
+        visit(name, &by_name, &mut Vec::new(), &mut done, &mut ordered)?;
+    }
+    Ok(ordered)
+}
+
+fn compute_image_hash(
+    root: &Path,
+    image: &DockerImage,
+    dependency_hashes: &HashMap<String, String>,
+) -> Result<ImageHash> {
+    let mut hasher = Sha256::new();
+
+    let dockerfile = root.join(&image.dockerfile);
+    let contents = std::fs::read(&dockerfile)
+        .with_context(|| format!("Dockerfile not found at {}", dockerfile.display()))?;
+    hasher.update(b"dockerfile\0");
+    hasher.update(&contents);
+
+    let mut patterns = Vec::new();
+    if !image.hash_sources.is_empty() {
+        let mut files = source_files(root, &image.hash_sources)?;
+        files.sort();
+        for path in files {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            hasher.update(relative.to_string_lossy().as_bytes());
+            hasher.update([0u8]);
+            hasher.update(
+                std::fs::read(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?,
+            );
+        }
+
+        // `!pattern` entries exclude files from every other pattern
+        let (excludes, includes): (Vec<&String>, Vec<&String>) = image
+            .hash_sources
+            .iter()
+            .partition(|pattern| pattern.starts_with('!'));
+        for pattern in includes {
+            let mut alone = vec![pattern.clone()];
+            alone.extend(excludes.iter().map(|exclude| exclude.to_string()));
+            patterns.push(PatternFiles {
+                pattern: pattern.clone(),
+                files: source_files(root, &alone)?.len(),
+            });
+        }
+    }
+
+    for (key, value) in &image.build_args {
+        hasher.update(format!("arg\0{key}={value}\0").as_bytes());
+    }
+    for dependency in &image.depends_on {
+        hasher.update(format!("dep\0{dependency}={}\0", dependency_hashes[dependency]).as_bytes());
+    }
+
+    let digest = hex::encode(hasher.finalize());
+    Ok(ImageHash {
+        name: image.name.clone(),
+        hash: digest[..TAG_HASH_LENGTH].to_string(),
+        patterns,
+    })
+}
+
+fn source_files(root: &Path, patterns: &[String]) -> Result<Vec<PathBuf>> {
+    Ok(GlobWalkerBuilder::from_patterns(root, patterns)
+        .file_type(FileType::FILE)
+        .build()
+        .context("Invalid hash_sources pattern")?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().to_path_buf())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::DockerRegistry;
+    use std::fs;
+
+    fn image(name: &str, hash_sources: &[&str], depends_on: &[&str]) -> DockerImage {
+        DockerImage {
+            name: name.to_string(),
+            dockerfile: "Dockerfile".to_string(),
+            context: ".".to_string(),
+            arch: vec!["amd64".to_string()],
+            build_args: BTreeMap::new(),
+            hash_sources: hash_sources.iter().map(|s| s.to_string()).collect(),
+            depends_on: depends_on.iter().map(|s| s.to_string()).collect(),
+            cache_from: vec![],
+            cache_to: vec![],
+            registry_cache: false,
+        }
+    }
+
+    #[test]
+    fn hashes_follow_dependencies_and_count_pattern_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Dockerfile"), "FROM alpine:3.19\n").unwrap();
+        fs::create_dir_all(dir.path().join("config")).unwrap();
+        for file in [
+            "Gemfile.lock",
+            "config/a.yml",
+            "config/b.yml",
+            "config/secret.yml",
+        ] {
+            fs::write(dir.path().join(file), file).unwrap();
+        }
+        let mut docker = DockerBuildConfig {
+            enabled: true,
+            layer_caching: false,
+            manifest: false,
+            builder_image: None,
+            verify_images: false,
+            registry: DockerRegistry {
+                repo: "example/repo".to_string(),
+                push: true,
+                login: Default::default(),
+            },
+            images: vec![
+                image("app", &["config/*.yml", "!config/secret.yml"], &["base"]),
+                image("base", &["Gemfile.lock"], &[]),
+            ],
+        };
+
+        let hashes = image_hashes(dir.path(), &docker).unwrap();
+        let names: Vec<_> = hashes.iter().map(|hash| hash.name.as_str()).collect();
+        assert_eq!(names, ["base", "app"]);
+        assert!(hashes.iter().all(|hash| hash.hash.len() == TAG_HASH_LENGTH));
+        assert_eq!(
+            hashes[1].patterns,
+            [PatternFiles {
+                pattern: "config/*.yml".to_string(),
+                files: 2,
+            }]
+        );
+
+        // Changing a dependency's sources changes the images built from it
+        fs::write(dir.path().join("Gemfile.lock"), "GEM\n  rails\n").unwrap();
+        let changed = image_hashes(dir.path(), &docker).unwrap();
+        assert_ne!(changed[0].hash, hashes[0].hash);
+        assert_ne!(changed[1].hash, hashes[1].hash);
+
+        // Excluded files don't count
+        fs::write(dir.path().join("config/secret.yml"), "rotated").unwrap();
+        assert_eq!(image_hashes(dir.path(), &docker).unwrap(), changed);
+
+        docker.images[0].depends_on = vec!["bsae".to_string()];
+        let error = image_hashes(dir.path(), &docker).unwrap_err().to_string();
+        assert!(error.contains("unknown image 'bsae'"), "{error}");
+    }
+}
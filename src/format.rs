@@ -0,0 +1,541 @@
+//! Canonical formatting for `.cigen` YAML files, used by `cigen fmt`.
+//!
+//! serde_yaml drops comments, so this works on the block structure of the
+//! text instead: each mapping entry or list item is re-indented to 2 spaces
+//! with its comments attached, and the top-level keys of known files are put
+//! in a stable order. Block scalars are moved as-is. The result is parsed
+//! again and must equal the original, so a file the formatter misreads is
+//! reported instead of rewritten.
+
+use anyhow::{Result, bail};
+use serde_yaml::Value;
+use std::path::Path;
+
+const INDENT: usize = 2;
+
+/// Top-level key order for job files
+pub const JOB_KEY_ORDER: &[&str] = &[
+    "image",
+    "executor",
+    "runner",
+    "architectures",
+    "architecture",
+    "arch",
+    "resource_class",
+    "working_directory",
+    "needs",
+    "requires",
+    "matrix",
+    "parallelism",
+    "services",
+    "environment",
+    "env",
+    "packages",
+    "cache",
+    "checkout",
+    "source_files",
+    "source_submodules",
+    "skip_if",
+    "unless",
+    "trigger",
+    "inherit_global_steps",
+    "steps",
+    "artifacts",
+    "test_results",
+    "provider_overrides",
+];
+
+/// Top-level key order for config.yml, `config/` fragments, and overlays
+const CONFIG_KEY_ORDER: &[&str] = &[
+    "$schema",
+    "provider",
+    "providers",
+    "output",
+    "plugins",
+    "project",
+    "vars",
+    "env",
+    "source_file_groups",
+    "docker",
+    "docker_build",
+    "orbs",
+    "executors",
+    "resource_classes",
+    "runners",
+    "caches",
+    "services",
+    "packages",
+    "global_steps",
+    "commands",
+    "workflows",
+    "jobs",
+];
+
+/// Top-level key order for command files
+const COMMAND_KEY_ORDER: &[&str] = &["description", "parameters", "steps"];
+
+/// Key order for a file, based on where it sits under `.cigen/`
+pub fn key_order_for(path: &Path) -> &'static [&'static str] {
+    let parent = path
+        .parent()
+        .and_then(Path::file_name)
+        .and_then(|name| name.to_str());
+    let stem = path.file_stem().and_then(|stem| stem.to_str());
+    match (parent, stem) {
+        (Some("jobs"), _) => JOB_KEY_ORDER,
+        (Some("commands"), _) => COMMAND_KEY_ORDER,
+        (Some("config" | "overlays"), _) => CONFIG_KEY_ORDER,
+        (Some(".cigen"), Some("config" | "cigen")) | (_, Some("cigen")) => CONFIG_KEY_ORDER,
+        _ => &[],
+    }
+}
+
+/// Format YAML source, putting known top-level keys in `key_order`
+pub fn format_yaml(source: &str, key_order: &[&str]) -> Result<String> {
+    let original: Value = serde_yaml::from_str(source)?;
+
+    let mut header = Vec::new();
+    let mut lines = Vec::new();
+    for raw in source.lines() {
+        let line = SourceLine::new(raw);
+        let is_marker = raw == "---" || raw.starts_with("--- ") || raw == "...";
+        if is_marker && lines.iter().any(|line: &SourceLine| !line.is_comment()) {
+            bail!("Files with more than one YAML document can't be formatted");
+        }
+        if is_marker {
+            header.append(&mut lines);
+            header.push(SourceLine::new(raw));
+        } else {
+            lines.push(line);
+        }
+    }
+
+    let mut block = parse_block(&lines);
+    if let Some(first) = block.nodes.first_mut() {
+        // Comments above the first key describe the file, wherever that key ends up
+        header.extend(first.comments.drain(..).map(|text| SourceLine {
+            indent: 0,
+            text: text.clone(),
+            raw: text,
+        }));
+    }
+    reorder(&mut block, key_order);
+
+    let mut out = header.into_iter().map(|line| line.raw).collect::<Vec<_>>();
+    emit_block(&block, 0, &mut out);
+
+    let mut formatted = String::new();
+    let mut previous_blank = true;
+    for line in out {
+        let line = if line.trim().is_empty() {
+            String::new()
+        } else {
+            line
+        };
+        if line.is_empty() && previous_blank {
+            continue;
+        }
+        previous_blank = line.is_empty();
+        formatted.push_str(&line);
+        formatted.push('\n');
+    }
+    while formatted.ends_with("\n\n") {
+        formatted.pop();
+    }
+
+    let reparsed: Value = serde_yaml::from_str(&formatted)?;
+    if reparsed != original {
+        bail!("Formatting would change what this file means, so it was left alone");
+    }
+    Ok(formatted)
+}
+
+#[derive(Clone, Debug)]
+struct SourceLine {
+    indent: usize,
+    /// The line without indentation or trailing whitespace
+    text: String,
+    /// The line without its newline, for block scalar content
+    raw: String,
+}
+
+impl SourceLine {
+    fn new(raw: &str) -> Self {
+        let trimmed = raw.trim_start_matches(' ');
+        Self {
+            indent: raw.len() - trimmed.len(),
+            text: trimmed.trim_end().to_string(),
+            raw: raw.to_string(),
+        }
+    }
+
+    fn is_comment(&self) -> bool {
+        self.text.is_empty() || self.text.starts_with('#')
+    }
+}
+
+#[derive(Debug)]
+struct Block {
+    nodes: Vec<Node>,
+    /// Comments after the last node
+    trailing: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Node {
+    /// Comment and blank lines above the node; blank lines are empty strings
+    comments: Vec<String>,
+    header: Header,
+    body: Body,
+}
+
+#[derive(Debug)]
+enum Header {
+    Entry { key: String, value: String },
+    Item,
+    Text(String),
+}
+
+#[derive(Debug)]
+enum Body {
+    Block(Block),
+    /// Block scalar or continuation lines, kept verbatim apart from indentation
+    Raw(Vec<SourceLine>),
+}
+
+fn parse_block(lines: &[SourceLine]) -> Block {
+    let mut nodes = Vec::new();
+    let mut comments = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = &lines[i];
+        if line.is_comment() {
+            comments.push(line.text.clone());
+            i += 1;
+            continue;
+        }
+
+        // The node owns every following line indented past it, plus a list
+        // at its own indent when it's a key without a value
+        let owns_items =
+            !is_item(line) && split_entry(&line.text).is_some_and(|(_, value)| value.is_empty());
+        let mut last_child = i;
+        let mut j = i + 1;
+        while j < lines.len()
+            && (lines[j].is_comment()
+                || lines[j].indent > line.indent
+                || (owns_items && lines[j].indent == line.indent && is_item(&lines[j])))
+        {
+            if !lines[j].is_comment() {
+                last_child = j;
+            }
+            j += 1;
+        }
+        // Comments right after its last child stay with it when indented like children
+        let mut end = last_child + 1;
+        while end < j && !lines[end].text.is_empty() && lines[end].indent > line.indent {
+            end += 1;
+        }
+        let children = &lines[i + 1..end];
+
+        let (header, body) = if is_item(line) {
+            let rest = line.text[1..].trim_start();
+            let mut body_lines = Vec::new();
+            if !rest.is_empty() {
+                let column = line.indent + line.text.len() - rest.len();
+                body_lines.push(SourceLine {
+                    indent: column,
+                    text: rest.to_string(),
+                    raw: format!("{}{rest}", " ".repeat(column)),
+                });
+            }
+            body_lines.extend_from_slice(children);
+            (Header::Item, Body::Block(parse_block(&body_lines)))
+        } else if let Some((key, value)) = split_entry(&line.text) {
+            let body = if is_block_scalar(&value) {
+                Body::Raw(children.to_vec())
+            } else {
+                Body::Block(parse_block(children))
+            };
+            let value = normalize_quotes(&value);
+            (Header::Entry { key, value }, body)
+        } else {
+            let text = if children.is_empty() {
+                normalize_quotes(&line.text)
+            } else {
+                line.text.clone()
+            };
+            (Header::Text(text), Body::Raw(children.to_vec()))
+        };
+
+        nodes.push(Node {
+            comments: std::mem::take(&mut comments),
+            header,
+            body,
+        });
+        i = end;
+    }
+
+    Block {
+        nodes,
+        trailing: comments,
+    }
+}
+
+fn is_item(line: &SourceLine) -> bool {
+    line.text == "-" || line.text.starts_with("- ")
+}
+
+/// Split `key: value` into its key and (possibly empty) value
+fn split_entry(text: &str) -> Option<(String, String)> {
+    let key_end = match text.chars().next()? {
+        quote @ ('"' | '\'') => closing_quote(text, quote)? + 1,
+        '[' | '{' | '?' | '#' | '|' | '>' | '-' => return None,
+        _ => {
+            let mut colon = None;
+            for (index, ch) in text.char_indices() {
+                if ch == '#' && text[..index].ends_with(' ') {
+                    return None;
+                }
+                if ch == ':' && text[index + 1..].chars().next().is_none_or(|c| c == ' ') {
+                    colon = Some(index);
+                    break;
+                }
+            }
+            colon?
+        }
+    };
+
+    let rest = text[key_end..].trim_start();
+    let value = rest.strip_prefix(':')?;
+    if !(value.is_empty() || value.starts_with(' ')) {
+        return None;
+    }
+    Some((
+        text[..key_end].trim_end().to_string(),
+        value.trim().to_string(),
+    ))
+}
+
+/// Byte index of the quote closing the quoted scalar that starts `text`
+fn closing_quote(text: &str, quote: char) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut index = 1;
+    while index < bytes.len() {
+        match bytes[index] as char {
+            '\\' if quote == '"' => index += 2,
+            ch if ch == quote => {
+                if quote == '\'' && bytes.get(index + 1) == Some(&b'\'') {
+                    index += 2;
+                } else {
+                    return Some(index);
+                }
+            }
+            _ => index += 1,
+        }
+    }
+    None
+}
+
+/// Whether a value starts a block scalar (`|`, `>-`, `&anchor |2`, ...)
+fn is_block_scalar(value: &str) -> bool {
+    let value = match value.find(" #") {
+        Some(comment) => &value[..comment],
+        None => value,
+    };
+    value.split_whitespace().last().is_some_and(|token| {
+        token.starts_with(['|', '>'])
+            && token[1..]
+                .chars()
+                .all(|ch| matches!(ch, '+' | '-' | '1'..='9'))
+    })
+}
+
+/// Rewrite a single-quoted scalar with double quotes when that needs no escapes
+fn normalize_quotes(value: &str) -> String {
+    if value.starts_with('\'') && closing_quote(value, '\'') == Some(value.len() - 1) {
+        let inner = value[1..value.len() - 1].replace("''", "'");
+        if !inner.contains(['"', '\\']) {
+            return format!("\"{inner}\"");
+        }
+    }
+    value.to_string()
+}
+
+/// Sort known keys into `key_order`; other nodes keep their positions
+fn reorder(block: &mut Block, key_order: &[&str]) {
+    let rank = |node: &Node| match &node.header {
+        Header::Entry { key, .. } => key_order.iter().position(|known| known == key),
+        _ => None,
+    };
+
+    let slots: Vec<usize> = (0..block.nodes.len())
+        .filter(|&index| rank(&block.nodes[index]).is_some())
+        .collect();
+    let mut known: Vec<Node> = Vec::new();
+    let mut others: Vec<Option<Node>> = Vec::new();
+    for node in block.nodes.drain(..) {
+        if rank(&node).is_some() {
+            known.push(node);
+            others.push(None);
+        } else {
+            others.push(Some(node));
+        }
+    }
+    known.sort_by_key(|node| rank(node));
+
+    let mut known = known.into_iter();
+    for (index, slot) in others.into_iter().enumerate() {
+        let node = match slot {
+            Some(node) => node,
+            None => {
+                debug_assert!(slots.contains(&index));
+                known.next().expect("one known node per slot")
+            }
+        };
+        block.nodes.push(node);
+    }
+}
+
+fn emit_block(block: &Block, indent: usize, out: &mut Vec<String>) {
+    for node in &block.nodes {
+        emit_comments(&node.comments, indent, out);
+        emit_node(node, indent, out);
+    }
+    emit_comments(&block.trailing, indent, out);
+}
+
+fn emit_comments(comments: &[String], indent: usize, out: &mut Vec<String>) {
+    for comment in comments {
+        if comment.is_empty() {
+            out.push(String::new());
+        } else {
+            out.push(format!("{}{comment}", " ".repeat(indent)));
+        }
+    }
+}
+
+fn emit_node(node: &Node, indent: usize, out: &mut Vec<String>) {
+    let pad = " ".repeat(indent);
+    match &node.header {
+        Header::Entry { key, value } if value.is_empty() => out.push(format!("{pad}{key}:")),
+        Header::Entry { key, value } => out.push(format!("{pad}{key}: {value}")),
+        Header::Text(text) => out.push(format!("{pad}{text}")),
+        Header::Item => {
+            let Body::Block(body) = &node.body else {
+                unreachable!("list items always have a block body")
+            };
+            let mut lines = Vec::new();
+            emit_block(body, indent + INDENT, &mut lines);
+            match body.nodes.first() {
+                Some(first) if first.comments.is_empty() => {
+                    lines[0] = format!("{pad}- {}", &lines[0][indent + INDENT..]);
+                }
+                _ => out.push(format!("{pad}-")),
+            }
+            out.extend(lines);
+            return;
+        }
+    }
+
+    match &node.body {
+        Body::Block(block) => emit_block(block, indent + INDENT, out),
+        Body::Raw(lines) => {
+            let base = lines
+                .iter()
+                .filter(|line| !line.text.is_empty())
+                .map(|line| line.indent)
+                .min()
+                .unwrap_or(0);
+            for line in lines {
+                if line.text.is_empty() {
+                    out.push(String::new());
+                } else {
+                    out.push(format!(
+                        "{}{}",
+                        " ".repeat(indent + INDENT + line.indent - base),
+                        &line.raw[line.indent..]
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSY_JOB: &str = "\
+# Runs the test suite
+steps:
+    - run:   bundle exec rspec   # the main suite
+    -   name: 'Upload'
+        run: |
+            echo 'keep this'
+              indented
+services: ['postgres']
+
+image:    cimg/ruby:3.3   \n\
+# required before tests
+requires:
+- setup
+x-note: 'it''s fine'
+";
+
+    #[test]
+    fn formats_job_files() {
+        let formatted = format_yaml(MESSY_JOB, JOB_KEY_ORDER).unwrap();
+        assert_eq!(
+            formatted,
+            "\
+# Runs the test suite
+
+image: cimg/ruby:3.3
+# required before tests
+requires:
+  - setup
+services: ['postgres']
+steps:
+  - run: bundle exec rspec   # the main suite
+  - name: \"Upload\"
+    run: |
+      echo 'keep this'
+        indented
+x-note: \"it's fine\"
+"
+        );
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let once = format_yaml(MESSY_JOB, JOB_KEY_ORDER).unwrap();
+        assert_eq!(format_yaml(&once, JOB_KEY_ORDER).unwrap(), once);
+    }
+
+    #[test]
+    fn file_header_comments_stay_on_top() {
+        let source = "# yaml-language-server: $schema=../schema.json\noutput: out\n# Provider\nprovider: circleci\n";
+        assert_eq!(
+            format_yaml(source, CONFIG_KEY_ORDER).unwrap(),
+            "# yaml-language-server: $schema=../schema.json\n# Provider\nprovider: circleci\noutput: out\n"
+        );
+    }
+
+    #[test]
+    fn key_order_depends_on_the_file_location() {
+        assert_eq!(
+            key_order_for(Path::new(".cigen/workflows/test/jobs/rspec.yml")),
+            JOB_KEY_ORDER
+        );
+        assert_eq!(
+            key_order_for(Path::new(".cigen/config.yml")),
+            CONFIG_KEY_ORDER
+        );
+        assert_eq!(
+            key_order_for(Path::new(".cigen/commands/install.yml")),
+            COMMAND_KEY_ORDER
+        );
+        assert!(key_order_for(Path::new(".cigen/orbs.lock.yml")).is_empty());
+    }
+}
@@ -0,0 +1,271 @@
+//! Header comments for generated files
+//!
+//! `cigen generate` starts every generated YAML file with a comment block: a
+//! do-not-edit warning, the cigen version, a hash of the config it was
+//! generated from, and the workflows and jobs the file holds. The header is
+//! added to the serialized text, since comments don't survive serde_yaml.
+//! `cigen verify --hash-only` compares the hash in each header with the
+//! config's current hash to find stale files without generating them again.
+
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+/// First line of every header
+pub const HEADER_MARKER: &str = "# DO NOT EDIT — generated by cigen from .cigen/";
+
+const HASH_PREFIX: &str = "# config hash: ";
+
+const TIMESTAMP_PREFIX: &str = "# generated at: ";
+
+/// Editor schema comments stay on the first line, above the header
+const SCHEMA_COMMENT_PREFIX: &str = "# yaml-language-server:";
+
+/// Directory in `.cigen/` for `cigen hash --cache` files, which aren't config
+const CACHE_DIR: &str = "cache";
+
+/// Hash of every file in the config directory (or of a single config file),
+/// covering both their paths and contents
+pub fn config_hash(config_path: &Path) -> Result<String> {
+    let mut files = Vec::new();
+    if config_path.is_dir() {
+        let entries = WalkDir::new(config_path)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| !(entry.depth() == 1 && entry.file_name() == CACHE_DIR));
+        for entry in entries {
+            let entry =
+                entry.with_context(|| format!("Failed to read {}", config_path.display()))?;
+            if entry.file_type().is_file() {
+                let relative = entry
+                    .path()
+                    .strip_prefix(config_path)
+                    .unwrap_or(entry.path());
+                files.push((relative.to_path_buf(), entry.path().to_path_buf()));
+            }
+        }
+    } else {
+        let name = config_path.file_name().unwrap_or_default();
+        files.push((name.into(), config_path.to_path_buf()));
+    }
+
+    let mut hasher = Sha256::new();
+    for (relative, path) in files {
+        let contents =
+            std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        hasher.update(relative.to_string_lossy().replace('\\', "/").as_bytes());
+        hasher.update([0u8]);
+        hasher.update(Sha256::digest(&contents));
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Prepend the header to every generated YAML file. `generated_at` adds the
+/// generation time, which makes the output change on every run.
+pub fn add_headers(
+    files: &mut HashMap<String, String>,
+    config_hash: &str,
+    generated_at: Option<SystemTime>,
+) {
+    for (path, content) in files.iter_mut() {
+        if !(path.ends_with(".yml") || path.ends_with(".yaml")) {
+            continue;
+        }
+        let header = render_header(content, config_hash, generated_at);
+        *content = match content.split_once('\n') {
+            Some((first, rest)) if first.starts_with(SCHEMA_COMMENT_PREFIX) => {
+                format!("{first}\n{header}{rest}")
+            }
+            _ => format!("{header}{content}"),
+        };
+    }
+}
+
+/// The config hash recorded in a generated file's header
+pub fn header_hash(content: &str) -> Option<&str> {
+    let mut lines = content
+        .lines()
+        .skip_while(|line| line.starts_with(SCHEMA_COMMENT_PREFIX));
+    if lines.next()? != HEADER_MARKER {
+        return None;
+    }
+    lines
+        .take_while(|line| line.starts_with('#'))
+        .find_map(|line| line.strip_prefix(HASH_PREFIX))
+        .map(str::trim)
+}
+
+/// `content` without the generation time in its header, so files generated
+/// with `--timestamp` compare equal to ones generated without it
+pub fn without_timestamp(content: &str) -> Cow<'_, str> {
+    let header_end = content
+        .split_inclusive('\n')
+        .take_while(|line| line.starts_with('#'))
+        .map(str::len)
+        .sum::<usize>();
+    let Some(start) = content[..header_end]
+        .find(&format!("\n{TIMESTAMP_PREFIX}"))
+        .map(|start| start + 1)
+    else {
+        return Cow::Borrowed(content);
+    };
+    let end = content[start..]
+        .find('\n')
+        .map_or(content.len(), |end| start + end + 1);
+    Cow::Owned(format!("{}{}", &content[..start], &content[end..]))
+}
+
+fn render_header(content: &str, config_hash: &str, generated_at: Option<SystemTime>) -> String {
+    let mut lines = vec![
+        HEADER_MARKER.to_string(),
+        format!("# cigen version: {}", env!("CARGO_PKG_VERSION")),
+        format!("{HASH_PREFIX}{config_hash}"),
+    ];
+    if let Some(time) = generated_at {
+        lines.push(format!("{TIMESTAMP_PREFIX}{}", format_utc(time)));
+    }
+
+    let document: Value = serde_yaml::from_str(content).unwrap_or(Value::Null);
+    let workflows: Vec<&str> = match document.get("workflows").and_then(Value::as_mapping) {
+        // CircleCI lists its workflows; a GitHub workflow file is one named workflow
+        Some(workflows) => workflows
+            .iter()
+            .filter(|(_, workflow)| workflow.is_mapping())
+            .filter_map(|(name, _)| name.as_str())
+            .collect(),
+        None => document
+            .get("name")
+            .and_then(Value::as_str)
+            .into_iter()
+            .collect(),
+    };
+    if !workflows.is_empty() {
+        lines.push(format!("# workflows: {}", workflows.join(", ")));
+    }
+    if let Some(jobs) = document.get("jobs").and_then(Value::as_mapping) {
+        lines.push(format!("# jobs: {}", jobs.len()));
+    }
+
+    lines.push("#".to_string());
+    lines.join("\n") + "\n"
+}
+
+/// `time` as an RFC 3339 UTC timestamp, to the second
+fn format_utc(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds_of_day / 3_600,
+        seconds_of_day % 3_600 / 60,
+        seconds_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const CIRCLECI: &str = "version: 2.1\njobs:\n  test: {}\n  lint: {}\nworkflows:\n  version: 2\n  main:\n    jobs: [test, lint]\n  nightly:\n    jobs: [test]\n";
+
+    #[test]
+    fn header_lists_version_hash_workflows_and_jobs() {
+        let mut files = HashMap::from([
+            (".circleci/main.yml".to_string(), CIRCLECI.to_string()),
+            (
+                ".github/workflows/ci.yml".to_string(),
+                "# yaml-language-server: $schema=https://json.schemastore.org/github-workflow.json\nname: CI\njobs:\n  test: {}\n".to_string(),
+            ),
+            ("scripts/run.sh".to_string(), "echo hi\n".to_string()),
+        ]);
+        add_headers(&mut files, "abc123", None);
+
+        assert_eq!(
+            files[".circleci/main.yml"],
+            format!(
+                "{HEADER_MARKER}\n# cigen version: {}\n# config hash: abc123\n# workflows: main, nightly\n# jobs: 2\n#\n{CIRCLECI}",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        let github = &files[".github/workflows/ci.yml"];
+        assert!(
+            github.starts_with("# yaml-language-server: $schema="),
+            "{github}"
+        );
+        assert!(github.contains("# workflows: CI\n# jobs: 1\n"), "{github}");
+        assert_eq!(header_hash(github), Some("abc123"));
+        assert_eq!(files["scripts/run.sh"], "echo hi\n");
+        assert_eq!(header_hash(CIRCLECI), None);
+    }
+
+    #[test]
+    fn timestamps_are_opt_in() {
+        let mut files = HashMap::from([("main.yml".to_string(), "jobs: {}\n".to_string())]);
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        add_headers(&mut files, "abc123", Some(time));
+        assert!(
+            files["main.yml"].contains("# generated at: 2024-02-29T12:34:56Z\n"),
+            "{}",
+            files["main.yml"]
+        );
+
+        let mut untimed = HashMap::from([("main.yml".to_string(), "jobs: {}\n".to_string())]);
+        add_headers(&mut untimed, "abc123", None);
+        assert_eq!(without_timestamp(&files["main.yml"]), untimed["main.yml"]);
+        assert_eq!(without_timestamp(&untimed["main.yml"]), untimed["main.yml"]);
+    }
+
+    #[test]
+    fn config_hash_covers_paths_and_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("workflows/main/jobs")).unwrap();
+        std::fs::write(dir.path().join("config.yml"), "provider: circleci\n").unwrap();
+        std::fs::write(
+            dir.path().join("workflows/main/jobs/test.yml"),
+            "steps: []\n",
+        )
+        .unwrap();
+        let before = config_hash(dir.path()).unwrap();
+        assert_eq!(before.len(), 64);
+        // `cigen hash --cache` files aren't part of the config
+        std::fs::create_dir_all(dir.path().join("cache")).unwrap();
+        std::fs::write(dir.path().join("cache/file-hashes.json"), "{}").unwrap();
+        assert_eq!(config_hash(dir.path()).unwrap(), before);
+
+        std::fs::rename(
+            dir.path().join("workflows/main/jobs/test.yml"),
+            dir.path().join("workflows/main/jobs/spec.yml"),
+        )
+        .unwrap();
+        let renamed = config_hash(dir.path()).unwrap();
+        assert_ne!(renamed, before);
+
+        std::fs::write(dir.path().join("config.yml"), "provider: github\n").unwrap();
+        assert_ne!(config_hash(dir.path()).unwrap(), renamed);
+    }
+}
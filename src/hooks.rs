@@ -0,0 +1,105 @@
+//! `hooks:` commands that `cigen generate` runs around generation.
+
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+use std::process::Command;
+
+/// When a hook runs relative to generation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookStage {
+    PreGenerate,
+    PostGenerate,
+}
+
+impl HookStage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HookStage::PreGenerate => "pre_generate",
+            HookStage::PostGenerate => "post_generate",
+        }
+    }
+}
+
+/// Expand `{output_dir}` and `{provider}` in each hook. A hook that mentions
+/// `{provider}` runs once per provider; the others run once.
+pub fn render_hooks(hooks: &[String], output_dir: &Path, providers: &[String]) -> Vec<String> {
+    let output_dir = output_dir.to_string_lossy();
+    hooks
+        .iter()
+        .flat_map(|hook| {
+            let hook = hook.replace("{output_dir}", &output_dir);
+            if hook.contains("{provider}") {
+                providers
+                    .iter()
+                    .map(|provider| hook.replace("{provider}", provider))
+                    .collect()
+            } else {
+                vec![hook]
+            }
+        })
+        .collect()
+}
+
+/// Run rendered hooks in order with `sh -c` from `root`, stopping at the first failure
+pub fn run_hooks(stage: HookStage, commands: &[String], root: &Path) -> Result<()> {
+    for command in commands {
+        tracing::info!("Running {} hook: {command}", stage.as_str());
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .current_dir(root)
+            .output()
+            .with_context(|| format!("Failed to run {} hook `{command}`", stage.as_str()))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        for line in stdout.lines() {
+            tracing::info!("  {line}");
+        }
+        if !output.status.success() {
+            bail!(
+                "{} hook `{command}` failed ({}):\n{}",
+                stage.as_str(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provider_hooks_run_once_per_provider() {
+        let hooks = vec![
+            "prettier --write {output_dir}/config.yml".to_string(),
+            "echo {provider}".to_string(),
+        ];
+        let providers = vec!["circleci".to_string(), "github".to_string()];
+
+        assert_eq!(
+            render_hooks(&hooks, Path::new(".circleci"), &providers),
+            vec![
+                "prettier --write .circleci/config.yml",
+                "echo circleci",
+                "echo github",
+            ]
+        );
+    }
+
+    #[test]
+    fn failing_hook_reports_stderr() {
+        let root = tempfile::tempdir().unwrap();
+        let error = run_hooks(
+            HookStage::PostGenerate,
+            &["echo broken >&2; exit 3".to_string()],
+            root.path(),
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(error.starts_with("post_generate hook `echo broken >&2; exit 3` failed"));
+        assert!(error.ends_with("\nbroken"), "{error}");
+    }
+}
@@ -0,0 +1,309 @@
+//! Docker registry lookups for `docker_build` images
+//!
+//! `cigen generate --verify-images` asks the registry whether each image tag
+//! consuming jobs use is already published, and only schedules build jobs for
+//! the missing ones. Lookups use the registry HTTP API: a `HEAD` request for
+//! the tag's manifest, answering a `401` with the bearer token flow Docker Hub
+//! and GHCR use.
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Registry host for images without one
+pub const DOCKER_HUB: &str = "docker.io";
+
+/// Host Docker Hub's registry API is served from
+const DOCKER_HUB_API_HOST: &str = "registry-1.docker.io";
+
+/// Manifest types a tag can point at: single images and multi-arch indexes
+const MANIFEST_TYPES: &str = "application/vnd.docker.distribution.manifest.v2+json, \
+     application/vnd.docker.distribution.manifest.list.v2+json, \
+     application/vnd.oci.image.manifest.v1+json, \
+     application/vnd.oci.image.index.v1+json";
+
+/// `key="value"` parameters of a `WWW-Authenticate` challenge
+static CHALLENGE_PARAM: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"(\w+)="([^"]*)""#).expect("valid regex"));
+
+/// An image reference split into the parts the registry API needs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageReference {
+    /// Registry host (`docker.io`, `ghcr.io`, ...)
+    pub registry: String,
+    /// Repository within the registry (`acme/ci_base`)
+    pub repository: String,
+    pub tag: String,
+}
+
+impl ImageReference {
+    /// Parse `[registry/]repository[:tag]`. Images without a registry are on
+    /// Docker Hub, where single-name repositories live under `library/`.
+    pub fn parse(image: &str) -> Result<Self> {
+        let (name, tag) = match image.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => (name, tag),
+            _ => (image, "latest"),
+        };
+        let (registry, repository) = match name.split_once('/') {
+            Some((host, repository))
+                if host.contains('.') || host.contains(':') || host == "localhost" =>
+            {
+                (host.to_string(), repository.to_string())
+            }
+            _ => (DOCKER_HUB.to_string(), name.to_string()),
+        };
+        if repository.is_empty() || tag.is_empty() || image.contains('@') {
+            bail!("Invalid image reference '{image}'; expected [registry/]repository[:tag]");
+        }
+        let repository = if registry == DOCKER_HUB && !repository.contains('/') {
+            format!("library/{repository}")
+        } else {
+            repository
+        };
+        Ok(Self {
+            registry,
+            repository,
+            tag: tag.to_string(),
+        })
+    }
+
+    /// Host serving the registry API
+    fn api_host(&self) -> &str {
+        if self.registry == DOCKER_HUB {
+            DOCKER_HUB_API_HOST
+        } else {
+            &self.registry
+        }
+    }
+}
+
+/// Source of which image tags are published
+pub trait ImageRegistry {
+    /// Whether `image` (`registry/repository:tag`) exists in its registry
+    fn image_exists(&self, image: &str) -> Result<bool>;
+}
+
+/// The registry HTTP API (v2), authenticated with credentials from the environment
+pub struct RegistryApi {
+    /// Serve every registry from this URL instead of `https://<host>`
+    url: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl RegistryApi {
+    pub fn new(url: Option<String>) -> Self {
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(30)))
+            .http_status_as_error(false)
+            .build()
+            .into();
+        Self {
+            url: url.map(|url| url.trim_end_matches('/').to_string()),
+            agent,
+        }
+    }
+
+    /// Each image's own registry, or every registry at `CIGEN_DOCKER_REGISTRY_URL`
+    pub fn from_env() -> Self {
+        Self::new(
+            std::env::var("CIGEN_DOCKER_REGISTRY_URL")
+                .ok()
+                .filter(|url| !url.trim().is_empty()),
+        )
+    }
+
+    fn manifest_status(&self, url: &str, token: Option<&str>) -> Result<ManifestResponse> {
+        let mut request = self
+            .agent
+            .head(url)
+            .header("Accept", MANIFEST_TYPES)
+            .header("User-Agent", "cigen");
+        if let Some(token) = token {
+            request = request.header("Authorization", &format!("Bearer {token}"));
+        }
+        let response = request.call()?;
+        Ok(ManifestResponse {
+            status: response.status().as_u16(),
+            challenge: response
+                .headers()
+                .get("www-authenticate")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        })
+    }
+
+    /// A pull token from the realm in the registry's `WWW-Authenticate` challenge
+    fn token(&self, reference: &ImageReference, challenge: &str) -> Result<String> {
+        let Some(params) = bearer_challenge(challenge) else {
+            bail!(
+                "{} asked for unsupported authentication: {challenge}",
+                reference.registry
+            );
+        };
+        let Some(realm) = params.get("realm") else {
+            bail!(
+                "{} sent a token challenge without a realm",
+                reference.registry
+            );
+        };
+        let scope = params
+            .get("scope")
+            .cloned()
+            .unwrap_or_else(|| format!("repository:{}:pull", reference.repository));
+
+        let mut request = self
+            .agent
+            .get(realm)
+            .query("scope", &scope)
+            .header("User-Agent", "cigen");
+        if let Some(service) = params.get("service") {
+            request = request.query("service", service);
+        }
+        if let Some((username, password)) = registry_credentials(&reference.registry) {
+            let basic =
+                base64::engine::general_purpose::STANDARD.encode(format!("{username}:{password}"));
+            request = request.header("Authorization", &format!("Basic {basic}"));
+        }
+
+        let mut response = request.call()?;
+        if response.status().as_u16() != 200 {
+            bail!(
+                "{realm} refused a pull token for {} (HTTP {})",
+                reference.repository,
+                response.status().as_u16()
+            );
+        }
+        let body: serde_json::Value = response.body_mut().read_json()?;
+        body["token"]
+            .as_str()
+            .or_else(|| body["access_token"].as_str())
+            .map(str::to_string)
+            .with_context(|| format!("{realm} returned no token"))
+    }
+}
+
+struct ManifestResponse {
+    status: u16,
+    challenge: Option<String>,
+}
+
+impl ImageRegistry for RegistryApi {
+    fn image_exists(&self, image: &str) -> Result<bool> {
+        let reference = ImageReference::parse(image)?;
+        let base = self
+            .url
+            .clone()
+            .unwrap_or_else(|| format!("https://{}", reference.api_host()));
+        let url = format!(
+            "{base}/v2/{}/manifests/{}",
+            reference.repository, reference.tag
+        );
+
+        let check = || -> Result<bool> {
+            let mut response = self.manifest_status(&url, None)?;
+            if response.status == 401
+                && let Some(challenge) = &response.challenge
+            {
+                let token = self.token(&reference, challenge)?;
+                response = self.manifest_status(&url, Some(&token))?;
+            }
+            match response.status {
+                200 => Ok(true),
+                404 => Ok(false),
+                401 | 403 => bail!(
+                    "not authorized to read {}; set its registry credentials",
+                    reference.repository
+                ),
+                status => bail!("the registry answered HTTP {status}"),
+            }
+        };
+        check().with_context(|| format!("Failed to look up {image} in {}", reference.registry))
+    }
+}
+
+/// The parameters of a `Bearer` challenge; `None` for other schemes
+fn bearer_challenge(challenge: &str) -> Option<HashMap<String, String>> {
+    let (scheme, params) = challenge.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    Some(
+        CHALLENGE_PARAM
+            .captures_iter(params)
+            .map(|captures| (captures[1].to_lowercase(), captures[2].to_string()))
+            .collect(),
+    )
+}
+
+/// Username and password for `registry`: `CIGEN_REGISTRY_USERNAME` and
+/// `CIGEN_REGISTRY_PASSWORD`, else `DOCKERHUB_USERNAME`/`DOCKERHUB_TOKEN` for
+/// Docker Hub and `GITHUB_ACTOR`/`GITHUB_TOKEN` for GHCR. Without any, tokens
+/// are requested anonymously, which works for public images.
+fn registry_credentials(registry: &str) -> Option<(String, String)> {
+    let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+    if let Some(password) = env("CIGEN_REGISTRY_PASSWORD") {
+        return Some((env("CIGEN_REGISTRY_USERNAME").unwrap_or_default(), password));
+    }
+    match registry {
+        DOCKER_HUB => Some((env("DOCKERHUB_USERNAME")?, env("DOCKERHUB_TOKEN")?)),
+        "ghcr.io" => Some((
+            env("GITHUB_ACTOR").unwrap_or_else(|| "cigen".to_string()),
+            env("GITHUB_TOKEN")?,
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_image_references() {
+        let reference = |image: &str| ImageReference::parse(image).unwrap();
+        assert_eq!(
+            reference("docker.io/acme/ci_base:0123abcd-amd64"),
+            ImageReference {
+                registry: "docker.io".to_string(),
+                repository: "acme/ci_base".to_string(),
+                tag: "0123abcd-amd64".to_string(),
+            }
+        );
+        assert_eq!(reference("acme/ci_base:1").registry, "docker.io");
+        assert_eq!(reference("alpine").repository, "library/alpine");
+        assert_eq!(reference("alpine").tag, "latest");
+        assert_eq!(
+            reference("localhost:5000/ci_base:1"),
+            ImageReference {
+                registry: "localhost:5000".to_string(),
+                repository: "ci_base".to_string(),
+                tag: "1".to_string(),
+            }
+        );
+        assert_eq!(
+            reference("ghcr.io/acme/images/ci_base:1").repository,
+            "acme/images/ci_base"
+        );
+        assert_eq!(reference("ghcr.io/acme/ci_base:1").api_host(), "ghcr.io");
+        assert_eq!(
+            reference("acme/ci_base:1").api_host(),
+            "registry-1.docker.io"
+        );
+        assert!(ImageReference::parse("acme/ci_base:").is_err());
+    }
+
+    #[test]
+    fn parses_bearer_challenges() {
+        let params = bearer_challenge(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:acme/ci_base:pull""#,
+        )
+        .unwrap();
+        assert_eq!(params["realm"], "https://auth.docker.io/token");
+        assert_eq!(params["service"], "registry.docker.io");
+        assert_eq!(params["scope"], "repository:acme/ci_base:pull");
+        assert!(bearer_challenge(r#"Basic realm="registry""#).is_none());
+    }
+}
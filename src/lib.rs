@@ -1,4 +1,48 @@
+//! cigen generates CI pipeline configurations from a `.cigen` directory.
+//!
+//! [`api`] is the supported library interface and follows semver. The other
+//! modules are public for the `cigen` binary and the provider plugins, and
+//! may change in any release.
+
+pub mod api;
+
+#[doc(hidden)]
+pub mod actions;
+#[doc(hidden)]
+pub mod cache_audit;
+#[doc(hidden)]
+pub mod docker_hash;
+#[doc(hidden)]
+pub mod format;
+#[doc(hidden)]
+pub mod header;
+#[doc(hidden)]
+pub mod hooks;
+#[doc(hidden)]
+pub mod image_registry;
+#[doc(hidden)]
+pub mod lint;
+#[doc(hidden)]
 pub mod loader;
+#[doc(hidden)]
+pub mod local;
+#[doc(hidden)]
+pub mod migrate;
+#[doc(hidden)]
+pub mod orbs;
+#[doc(hidden)]
 pub mod orchestrator;
+#[doc(hidden)]
+pub mod path_filter;
+#[doc(hidden)]
 pub mod plugin;
+#[doc(hidden)]
+pub mod report;
+#[doc(hidden)]
+pub mod scaffold;
+#[doc(hidden)]
 pub mod schema;
+#[doc(hidden)]
+pub mod stats;
+#[doc(hidden)]
+pub mod templating;
@@ -0,0 +1,232 @@
+//! Warnings for config smells that validation allows, for `cigen lint`
+//!
+//! Each [`Rule`] has a stable ID (`CIGEN-W001`, ...) that CI can track or
+//! deny. Rules read the loaded config; findings point back into the `.cigen`
+//! files when the offending definition can be found in them.
+
+mod rules;
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::plugin::diagnostics::{locate, locate_in, render_located};
+use crate::plugin::protocol::{Diagnostic, SourceLocation, diagnostic::Level};
+use crate::schema::{CigenConfig, Job};
+
+/// Longest `needs` chain, in jobs, before [`Rule::LongNeedsChain`] warns
+pub const DEFAULT_MAX_CHAIN_LENGTH: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Rule {
+    JobWithoutSourceFiles,
+    UnusedCache,
+    UnusedService,
+    ParallelismWithoutSplitting,
+    LongNeedsChain,
+    DuplicateArchitectures,
+    UnusedCommand,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::JobWithoutSourceFiles,
+        Rule::UnusedCache,
+        Rule::UnusedService,
+        Rule::ParallelismWithoutSplitting,
+        Rule::LongNeedsChain,
+        Rule::DuplicateArchitectures,
+        Rule::UnusedCommand,
+    ];
+
+    /// Stable ID, never reused for a different rule
+    pub fn id(self) -> &'static str {
+        match self {
+            Rule::JobWithoutSourceFiles => "CIGEN-W001",
+            Rule::UnusedCache => "CIGEN-W002",
+            Rule::UnusedService => "CIGEN-W003",
+            Rule::ParallelismWithoutSplitting => "CIGEN-W004",
+            Rule::LongNeedsChain => "CIGEN-W005",
+            Rule::DuplicateArchitectures => "CIGEN-W006",
+            Rule::UnusedCommand => "CIGEN-W007",
+        }
+    }
+
+    /// What to do about a finding
+    pub fn hint(self) -> &'static str {
+        match self {
+            Rule::JobWithoutSourceFiles => {
+                "list the files the job depends on in `source_files` so it's skipped when they haven't changed"
+            }
+            Rule::UnusedCache => "add it to a job's `cache`, or remove the definition",
+            Rule::UnusedService => "add it to a job's `services`, or remove the definition",
+            Rule::ParallelismWithoutSplitting => {
+                "add `test_splitting` so each container runs part of the tests, or drop `parallelism`"
+            }
+            Rule::LongNeedsChain => {
+                "drop `needs` a job doesn't use the output of, so more of the chain runs in parallel"
+            }
+            Rule::DuplicateArchitectures => "list each architecture once",
+            Rule::UnusedCommand => "use it in a job's steps, or delete the command",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LintOptions {
+    /// Longest `needs` chain, in jobs, that doesn't get a warning
+    pub max_chain_length: usize,
+}
+
+impl Default for LintOptions {
+    fn default() -> Self {
+        Self {
+            max_chain_length: DEFAULT_MAX_CHAIN_LENGTH,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding {
+    pub rule: Rule,
+    pub message: String,
+    pub location: Option<SourceLocation>,
+}
+
+impl Finding {
+    /// A snippet of the file the finding points into, or a
+    /// `warning[ID]: message` line when it has no location
+    pub fn render(&self) -> String {
+        let diagnostic = Diagnostic {
+            level: Level::Warning as i32,
+            code: self.rule.id().to_string(),
+            message: self.message.clone(),
+            loc: self.location.clone(),
+            fix_hint: self.rule.hint().to_string(),
+            ..Default::default()
+        };
+        if let Some(rendered) = self
+            .location
+            .as_ref()
+            .and_then(|location| render_located(&diagnostic, location))
+        {
+            return rendered;
+        }
+
+        let mut output = format!("warning[{}]: {}", self.rule.id(), self.message);
+        if let Some(location) = &self.location {
+            let _ = write!(
+                output,
+                "\n  --> {}:{}:{}",
+                location.file, location.line, location.column
+            );
+        }
+        let _ = write!(output, "\n  help: {}", self.rule.hint());
+        output
+    }
+}
+
+/// The files a config was loaded from, to point findings into
+#[derive(Clone, Debug, Default)]
+pub struct ConfigFiles {
+    /// `config.yml` and the `config/` fragments, or the single config file
+    root_files: Vec<PathBuf>,
+    commands_dir: Option<PathBuf>,
+}
+
+impl ConfigFiles {
+    /// Files of a `.cigen` directory or a single config file
+    pub fn new(config_path: &Path) -> Self {
+        if !config_path.is_dir() {
+            return Self {
+                root_files: vec![config_path.to_path_buf()],
+                commands_dir: None,
+            };
+        }
+
+        let mut fragments: Vec<PathBuf> = fs::read_dir(config_path.join("config"))
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                matches!(
+                    path.extension().and_then(|ext| ext.to_str()),
+                    Some("yml" | "yaml")
+                )
+            })
+            .collect();
+        fragments.sort();
+        let mut root_files = vec![config_path.join("config.yml")];
+        root_files.append(&mut fragments);
+        Self {
+            root_files,
+            commands_dir: Some(config_path.join("commands")),
+        }
+    }
+
+    /// First line under the top-level `section:` that starts with `needle`,
+    /// ignoring indentation and a list item's `- `
+    fn in_section(&self, section: &str, needle: &str) -> Option<SourceLocation> {
+        self.root_files.iter().find_map(|file| {
+            let contents = fs::read_to_string(file).ok()?;
+            let mut in_section = false;
+            for (index, line) in contents.lines().enumerate() {
+                if !line.starts_with([' ', '\t', '#', '-']) && !line.is_empty() {
+                    in_section = line.trim_end() == format!("{section}:");
+                    continue;
+                }
+                let content = line.trim_start();
+                let content = content.strip_prefix("- ").unwrap_or(content);
+                if in_section && content.starts_with(needle) {
+                    let column = line.len() - content.len();
+                    return Some(SourceLocation {
+                        file: file.display().to_string(),
+                        line: index as u32 + 1,
+                        column: line[..column].chars().count() as u32 + 1,
+                        snippet: needle.to_string(),
+                    });
+                }
+            }
+            None
+        })
+    }
+
+    fn command(&self, name: &str) -> Option<SourceLocation> {
+        let in_commands_dir = self.commands_dir.as_ref().and_then(|dir| {
+            ["yml", "yaml"]
+                .iter()
+                .map(|ext| dir.join(format!("{name}.{ext}")))
+                .find(|path| path.is_file())
+                .map(|path| start_of(&path))
+        });
+        in_commands_dir.or_else(|| self.in_section("commands", &format!("{name}:")))
+    }
+}
+
+/// Where a job sets `key`, or where the job starts
+fn job_location(job: &Job, key: Option<&str>) -> Option<SourceLocation> {
+    let file = job.source_file.as_ref()?;
+    let path = file.display().to_string();
+    if let Some(section) = &job.source_section {
+        // A job in a file that defines several jobs
+        return locate_in(&path, section, &format!("{}:", key.unwrap_or(section)));
+    }
+    key.and_then(|key| locate(&path, &format!("{key}:")))
+        .or_else(|| file.is_file().then(|| start_of(file)))
+}
+
+fn start_of(file: &Path) -> SourceLocation {
+    SourceLocation {
+        file: file.display().to_string(),
+        line: 1,
+        column: 1,
+        snippet: String::new(),
+    }
+}
+
+/// Every finding for `config`, grouped by rule
+pub fn lint(config: &CigenConfig, files: &ConfigFiles, options: &LintOptions) -> Vec<Finding> {
+    let mut findings = rules::check_all(config, files, options);
+    findings.sort_by_key(|finding| finding.rule);
+    findings
+}
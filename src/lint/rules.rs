@@ -0,0 +1,464 @@
+//! The lint rules, one function each
+
+use serde_yaml::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::{ConfigFiles, Finding, LintOptions, Rule, job_location};
+use crate::schema::{CigenConfig, Job, JobMatrix, Step, split_need};
+
+pub(super) fn check_all(
+    config: &CigenConfig,
+    files: &ConfigFiles,
+    options: &LintOptions,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    findings.extend(jobs_without_source_files(config));
+    findings.extend(unused_caches(config, files));
+    findings.extend(unused_services(config, files));
+    findings.extend(parallelism_without_splitting(config));
+    findings.extend(long_needs_chains(config, options.max_chain_length));
+    findings.extend(duplicate_architectures(config, files));
+    findings.extend(unused_commands(config, files));
+    findings
+}
+
+fn sorted_jobs(config: &CigenConfig) -> Vec<(&String, &Job)> {
+    let mut jobs: Vec<_> = config.jobs.iter().collect();
+    jobs.sort_by_key(|(job_id, _)| *job_id);
+    jobs
+}
+
+/// CIGEN-W001: without `source_files` a job can't be skipped
+fn jobs_without_source_files(config: &CigenConfig) -> Vec<Finding> {
+    sorted_jobs(config)
+        .into_iter()
+        .filter(|(_, job)| job.source_files.is_empty() && !job.is_approval())
+        .map(|(job_id, job)| Finding {
+            rule: Rule::JobWithoutSourceFiles,
+            message: format!(
+                "Job '{job_id}' has no source_files, so it runs in every pipeline and is never skipped"
+            ),
+            location: job_location(job, None),
+        })
+        .collect()
+}
+
+/// CIGEN-W002: caches no job restores
+fn unused_caches(config: &CigenConfig, files: &ConfigFiles) -> Vec<Finding> {
+    let restored: BTreeSet<&str> = config
+        .jobs
+        .values()
+        .flat_map(|job| &job.cache)
+        .filter(|cache| cache.restore)
+        .map(|cache| cache.name.as_str())
+        .collect();
+    let defined: BTreeSet<&str> = config.caches.keys().map(String::as_str).collect();
+    defined
+        .difference(&restored)
+        .map(|name| Finding {
+            rule: Rule::UnusedCache,
+            message: format!("Cache '{name}' is defined, but no job restores it"),
+            location: files.in_section("caches", &format!("{name}:")),
+        })
+        .collect()
+}
+
+/// CIGEN-W003: services no job uses
+fn unused_services(config: &CigenConfig, files: &ConfigFiles) -> Vec<Finding> {
+    let Some(Value::Mapping(services)) = config.raw.get("services") else {
+        return Vec::new();
+    };
+    let used: BTreeSet<&str> = config
+        .jobs
+        .values()
+        .flat_map(|job| &job.services)
+        .map(String::as_str)
+        .collect();
+    let defined: BTreeSet<&str> = services.keys().filter_map(Value::as_str).collect();
+    defined
+        .difference(&used)
+        .map(|name| Finding {
+            rule: Rule::UnusedService,
+            message: format!("Service '{name}' is defined, but no job uses it"),
+            location: files.in_section("services", &format!("{name}:")),
+        })
+        .collect()
+}
+
+/// CIGEN-W004: `parallelism` without anything splitting the work runs the
+/// same steps in every container
+fn parallelism_without_splitting(config: &CigenConfig) -> Vec<Finding> {
+    sorted_jobs(config)
+        .into_iter()
+        .filter_map(|(job_id, job)| {
+            let parallelism = job.extra.get("parallelism").and_then(Value::as_u64)?;
+            let splits_itself = job.steps.iter().any(|step| {
+                run_command(step).is_some_and(|command| command.contains("circleci tests"))
+            });
+            (parallelism > 1 && job.test_splitting.is_none() && !splits_itself).then(|| Finding {
+                rule: Rule::ParallelismWithoutSplitting,
+                message: format!(
+                    "Job '{job_id}' sets parallelism: {parallelism}, but doesn't split its tests, so every container runs the same steps"
+                ),
+                location: job_location(job, Some("parallelism")),
+            })
+        })
+        .collect()
+}
+
+fn run_command(step: &Step) -> Option<&str> {
+    match step {
+        Step::SimpleRun { run, .. } => Some(run),
+        Step::RunWithOptions { run, .. } => Some(&run.command),
+        _ => None,
+    }
+}
+
+/// CIGEN-W005: jobs at the end of a `needs` chain longer than the maximum.
+/// Jobs in a chain run one after another.
+fn long_needs_chains(config: &CigenConfig, max_chain_length: usize) -> Vec<Finding> {
+    let mut chains = HashMap::new();
+    let needed: BTreeSet<&str> = config
+        .jobs
+        .values()
+        .flat_map(|job| &job.needs)
+        .map(|need| split_need(need).0)
+        .collect();
+    sorted_jobs(config)
+        .into_iter()
+        .filter(|(job_id, _)| !needed.contains(job_id.as_str()))
+        .filter_map(|(job_id, job)| {
+            let chain = longest_chain(config, job_id, &mut chains, &mut Vec::new());
+            (chain.len() > max_chain_length).then(|| Finding {
+                rule: Rule::LongNeedsChain,
+                message: format!(
+                    "Job '{job_id}' ends a chain of {} jobs that run one after another ({}), more than {max_chain_length}",
+                    chain.len(),
+                    chain.join(" -> ")
+                ),
+                location: job_location(job, Some("needs")),
+            })
+        })
+        .collect()
+}
+
+/// The longest chain of `needs` leading to `job_id`, ending with it. Ties go
+/// to the alphabetically first need.
+fn longest_chain<'a>(
+    config: &'a CigenConfig,
+    job_id: &'a str,
+    chains: &mut HashMap<&'a str, Vec<&'a str>>,
+    visiting: &mut Vec<&'a str>,
+) -> Vec<&'a str> {
+    if let Some(chain) = chains.get(job_id) {
+        return chain.clone();
+    }
+    let mut longest = Vec::new();
+    if let Some(job) = config.jobs.get(job_id)
+        && !visiting.contains(&job_id)
+    {
+        visiting.push(job_id);
+        let needs: BTreeSet<&str> = job.needs.iter().map(|need| split_need(need).0).collect();
+        for need in needs {
+            let chain = longest_chain(config, need, chains, visiting);
+            if chain.len() > longest.len() {
+                longest = chain;
+            }
+        }
+        visiting.pop();
+    }
+    longest.push(job_id);
+    chains.insert(job_id, longest.clone());
+    longest
+}
+
+/// CIGEN-W006: an architecture listed twice builds or runs the same thing twice
+fn duplicate_architectures(config: &CigenConfig, files: &ConfigFiles) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for (job_id, job) in sorted_jobs(config) {
+        let Some(JobMatrix::Dimensions(dimensions)) = &job.matrix else {
+            continue;
+        };
+        for key in ["arch", "architecture"] {
+            for arch in duplicates(dimensions.get(key).into_iter().flatten()) {
+                findings.push(Finding {
+                    rule: Rule::DuplicateArchitectures,
+                    message: format!(
+                        "Job '{job_id}' lists architecture '{arch}' more than once in its matrix"
+                    ),
+                    location: job_location(job, Some(key)),
+                });
+            }
+        }
+    }
+    let images = config.docker_build.iter().flat_map(|build| &build.images);
+    for image in images {
+        for arch in duplicates(&image.arch) {
+            findings.push(Finding {
+                rule: Rule::DuplicateArchitectures,
+                message: format!(
+                    "docker_build image '{}' lists architecture '{arch}' more than once",
+                    image.name
+                ),
+                location: files.in_section("docker_build", &format!("name: {}", image.name)),
+            });
+        }
+    }
+    findings
+}
+
+fn duplicates<'a>(values: impl IntoIterator<Item = &'a String>) -> Vec<&'a str> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(value, _)| value)
+        .collect()
+}
+
+/// CIGEN-W007: commands that no job's steps reach, directly or through
+/// other commands
+fn unused_commands(config: &CigenConfig, files: &ConfigFiles) -> Vec<Finding> {
+    let job_steps = config.jobs.values().flat_map(|job| &job.steps);
+    let workflow_steps = config
+        .workflows
+        .values()
+        .flat_map(|workflow| workflow.job_steps.values())
+        .flat_map(|steps| steps.pre_steps.iter().chain(&steps.post_steps));
+    let global_steps = config
+        .global_steps
+        .before
+        .iter()
+        .chain(&config.global_steps.after);
+    let mut pending: Vec<&str> = job_steps
+        .chain(workflow_steps)
+        .chain(global_steps)
+        .flat_map(invoked_commands)
+        .collect();
+    let mut used = BTreeSet::new();
+    while let Some(name) = pending.pop() {
+        let Some(command) = config.commands.get(name) else {
+            continue;
+        };
+        if used.insert(name) {
+            pending.extend(command.steps.iter().flat_map(invoked_commands));
+        }
+    }
+
+    let defined: BTreeSet<&str> = config.commands.keys().map(String::as_str).collect();
+    defined
+        .difference(&used)
+        .map(|name| Finding {
+            rule: Rule::UnusedCommand,
+            message: format!("Command '{name}' is defined, but no job uses it"),
+            location: files.command(name),
+        })
+        .collect()
+}
+
+/// Names a step could invoke a command by: `- name` or `- name: {...}`
+fn invoked_commands(step: &Step) -> Vec<&str> {
+    match step {
+        Step::Custom(Value::String(name)) => vec![name.as_str()],
+        Step::Custom(Value::Mapping(map)) => map
+            .keys()
+            .filter_map(Value::as_str)
+            .filter(|key| *key != "if")
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> CigenConfig {
+        CigenConfig::from_yaml(yaml).unwrap()
+    }
+
+    fn messages(findings: Vec<Finding>) -> Vec<String> {
+        findings
+            .into_iter()
+            .map(|finding| finding.message)
+            .collect()
+    }
+
+    #[test]
+    fn jobs_without_source_files_are_reported() {
+        let config = config(
+            r#"
+jobs:
+  test:
+    source_files: [src/**/*.rs]
+    steps: [{run: cargo test}]
+  deploy:
+    steps: [{run: ./deploy.sh}]
+  approve:
+    type: approval
+"#,
+        );
+        assert_eq!(
+            messages(jobs_without_source_files(&config)),
+            ["Job 'deploy' has no source_files, so it runs in every pipeline and is never skipped"]
+        );
+    }
+
+    #[test]
+    fn caches_no_job_restores_are_reported() {
+        let config = config(
+            r#"
+caches:
+  gems: {paths: [vendor/bundle]}
+  npm: {paths: [~/.npm]}
+  assets: {paths: [public/assets]}
+jobs:
+  test:
+    cache: [gems]
+    steps: [{run: bundle exec rspec}]
+  build:
+    cache:
+      assets: {restore: false}
+    steps: [{run: npm run build}]
+"#,
+        );
+        let findings = unused_caches(&config, &ConfigFiles::default());
+        assert_eq!(
+            messages(findings),
+            [
+                "Cache 'assets' is defined, but no job restores it",
+                "Cache 'npm' is defined, but no job restores it",
+            ]
+        );
+    }
+
+    #[test]
+    fn services_no_job_uses_are_reported() {
+        let config = config(
+            r#"
+services:
+  postgres: {image: postgres:16}
+  redis: {image: redis:7}
+jobs:
+  test:
+    services: [postgres]
+    steps: [{run: bundle exec rspec}]
+"#,
+        );
+        assert_eq!(
+            messages(unused_services(&config, &ConfigFiles::default())),
+            ["Service 'redis' is defined, but no job uses it"]
+        );
+    }
+
+    #[test]
+    fn parallelism_without_test_splitting_is_reported() {
+        let config = config(
+            r#"
+jobs:
+  rspec:
+    parallelism: 4
+    steps: [{run: bundle exec rspec}]
+  split:
+    parallelism: 4
+    test_splitting: {glob: "spec/**/*_spec.rb", command_template: "bundle exec rspec {files}"}
+  manual:
+    parallelism: 2
+    steps: [{run: "circleci tests glob 'test/*.js' | circleci tests run --command 'xargs npm test'"}]
+  single:
+    parallelism: 1
+    steps: [{run: make}]
+"#,
+        );
+        assert_eq!(
+            messages(parallelism_without_splitting(&config)),
+            [
+                "Job 'rspec' sets parallelism: 4, but doesn't split its tests, so every container runs the same steps"
+            ]
+        );
+    }
+
+    #[test]
+    fn needs_chains_longer_than_the_maximum_are_reported() {
+        let config = config(
+            r#"
+jobs:
+  a: {steps: [{run: a}]}
+  b: {needs: [a], steps: [{run: b}]}
+  c: {needs: [b, a], steps: [{run: c}]}
+  d: {needs: [c], steps: [{run: d}]}
+  lint: {needs: [a], steps: [{run: lint}]}
+"#,
+        );
+        assert!(long_needs_chains(&config, 4).is_empty());
+        assert_eq!(
+            messages(long_needs_chains(&config, 3)),
+            [
+                "Job 'd' ends a chain of 4 jobs that run one after another (a -> b -> c -> d), more than 3"
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_architectures_are_reported() {
+        let config = config(
+            r#"
+docker_build:
+  registry: {repo: docker.io/acme}
+  images:
+    - name: ci_base
+      arch: [amd64, arm64, amd64]
+jobs:
+  build:
+    matrix:
+      arch: [amd64, arm64, arm64]
+    steps: [{run: make}]
+  test:
+    matrix:
+      arch: [amd64, arm64]
+    steps: [{run: make test}]
+"#,
+        );
+        assert_eq!(
+            messages(duplicate_architectures(&config, &ConfigFiles::default())),
+            [
+                "Job 'build' lists architecture 'arm64' more than once in its matrix",
+                "docker_build image 'ci_base' lists architecture 'amd64' more than once",
+            ]
+        );
+    }
+
+    #[test]
+    fn commands_no_job_reaches_are_reported() {
+        let config = config(
+            r#"
+commands:
+  setup:
+    steps: [install_deps]
+  install_deps:
+    steps: [{run: bundle install}]
+  notify:
+    steps: [{run: ./notify.sh}]
+  orphan_helper:
+    steps: [{run: echo unused}]
+  unused_parent:
+    steps: [orphan_helper]
+jobs:
+  test:
+    steps:
+      - setup
+      - notify: {channel: ci}
+"#,
+        );
+        assert_eq!(
+            messages(unused_commands(&config, &ConfigFiles::default())),
+            [
+                "Command 'orphan_helper' is defined, but no job uses it",
+                "Command 'unused_parent' is defined, but no job uses it",
+            ]
+        );
+    }
+}
@@ -1,11 +1,29 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use serde::Deserialize;
 use serde_yaml::{Mapping, Value};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use crate::schema::{CigenConfig, CommandDefinition, Job, WorkflowConfig};
+mod merger;
+
+pub use merger::{ConfigMerger, ConfigSources, merge_values};
+
+use crate::orbs::configured_orbs;
+use crate::plugin::diagnostics::{
+    LocatedError, locate_in, locate_key, locate_list_item, located_error, located_error_in,
+    render_located_warning,
+};
+use crate::plugin::protocol::SourceLocation;
+use crate::schema::{
+    BUILTIN_STEPS, CacheDefinition, CacheWarmup, CigenConfig, CommandDefinition, DockerBuildConfig,
+    GlobalSteps, Hooks, Job, JobGroup, Notifications, PackageManagerDefinition, ProjectDetection,
+    RESERVED_CACHE_NAMES, StepShapeError, VersionSource, WorkflowConfig, check_branches,
+    check_cleanup, check_cloud_auth, check_executor_conflict, check_steps, check_test_splitting,
+    from_expanded_value, parse_yaml, parse_yaml_value, split_need, unknown_reference_message,
+    unknown_resource_class_message,
+};
+use crate::templating::{TEMPLATE_EXTENSION, TemplateEngine, is_template_file};
 
 /// Root config metadata fields used by the loader
 #[derive(Debug, Default, Deserialize)]
@@ -14,27 +32,133 @@ struct RootMetadata {
     providers: Option<Vec<String>>,
     #[serde(default)]
     source_file_groups: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    env: HashMap<String, String>,
+    #[serde(default)]
+    vars: HashMap<String, Value>,
+    #[serde(default)]
+    docker_build: Option<DockerBuildConfig>,
+    #[serde(default)]
+    plugins: Vec<String>,
+    #[serde(default)]
+    caches: HashMap<String, Value>,
+    #[serde(default)]
+    cache_version: Option<u32>,
+    #[serde(default)]
+    cache_warmup: Option<CacheWarmup>,
+    #[serde(default)]
+    package_managers: HashMap<String, PackageManagerDefinition>,
+    #[serde(default)]
+    version_sources: HashMap<String, Vec<VersionSource>>,
+    #[serde(default)]
+    projects: Vec<String>,
+    #[serde(default)]
+    project_detection: Option<ProjectDetection>,
+    #[serde(default)]
+    hooks: Hooks,
+    #[serde(default)]
+    notifications: Option<Notifications>,
+    #[serde(default)]
+    global_steps: GlobalSteps,
+    #[serde(default)]
+    groups: HashMap<String, JobGroup>,
+    #[serde(default)]
+    allow_custom_resource_classes: bool,
+}
+
+/// Directory under `.cigen/` holding one `<profile>.yml` overlay per profile
+pub const OVERLAYS_DIR: &str = "overlays";
+
+/// Directory under `.cigen/` that `.j2` job files can include from
+pub const TEMPLATES_DIR: &str = "templates";
+
+/// Overlays that make up a profile
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Profile {
+    /// `.cigen/overlays/<profile>.yml`, merged over the root config
+    pub config_overlay: Option<PathBuf>,
+    /// Jobs (`<workflow>/<job>`) with a `<job>.<profile>.yml` overlay
+    pub job_overlays: Vec<String>,
 }
 
 /// Load split config from .cigen/ directory
 pub fn load_split_config(config_dir: &Path) -> Result<CigenConfig> {
+    load_split_config_with_profile(config_dir, None)
+}
+
+/// Load split config with a profile's overlays deep-merged over it: maps are
+/// merged and everything else (lists included) is replaced
+pub fn load_split_config_with_profile(
+    config_dir: &Path,
+    profile: Option<&str>,
+) -> Result<CigenConfig> {
+    load_split_config_with_vars(config_dir, profile, &HashMap::new())
+}
+
+/// Load split config with a profile's overlays and `vars` overriding the
+/// config's `vars:`, before `.j2` job files are rendered
+pub fn load_split_config_with_vars(
+    config_dir: &Path,
+    profile: Option<&str>,
+    vars: &HashMap<String, Value>,
+) -> Result<CigenConfig> {
+    let profiles = find_profiles(config_dir, profile)?;
+    if let Some(profile) = profile
+        && !profiles.contains_key(profile)
+    {
+        bail!(
+            "{}",
+            unknown_reference_message(
+                &format!(
+                    "Unknown profile '{profile}': no .cigen/{OVERLAYS_DIR}/{profile}.yml or job overlays"
+                ),
+                profile,
+                profiles.keys().map(String::as_str),
+            )
+        );
+    }
+
     // Read main config
     let config_path = config_dir.join("config.yml");
     let config_yaml = fs::read_to_string(&config_path)
         .with_context(|| format!("Failed to read {}", config_path.display()))?;
 
-    let mut merged_config: Value = serde_yaml::from_str(&config_yaml)
-        .with_context(|| format!("Failed to parse {}", config_path.display()))?;
+    let mut merger = ConfigMerger::new(
+        parse_yaml_value(&config_yaml)
+            .with_context(|| format!("Failed to parse {}", config_path.display()))?,
+        Path::new("config.yml"),
+    );
 
     // Merge optional fragments from .cigen/config/
-    merge_config_fragments(config_dir, &mut merged_config)?;
+    merge_config_fragments(config_dir, &mut merger)?;
+
+    // Then the profile's overlay
+    if let Some(profile) = profile {
+        let overlay_path = config_dir.join(OVERLAYS_DIR).join(format!("{profile}.yml"));
+        if overlay_path.is_file() {
+            let overlay_yaml = fs::read_to_string(&overlay_path)
+                .with_context(|| format!("Failed to read {}", overlay_path.display()))?;
+            let overlay = parse_yaml_value(&overlay_yaml)
+                .with_context(|| format!("Failed to parse {}", overlay_path.display()))?;
+            let source = overlay_path
+                .strip_prefix(config_dir)
+                .unwrap_or(&overlay_path);
+            merger
+                .merge_overlay(overlay, source)
+                .with_context(|| format!("Failed to merge {}", overlay_path.display()))?;
+        }
+    }
+    let (merged_config, sources) = merger.finish();
 
     // Extract metadata for provider list + source file groups
     let raw_mapping = mapping_from_value(&merged_config);
     let metadata: RootMetadata = serde_yaml::from_value(Value::Mapping(raw_mapping.clone()))
+        .map_err(|error| locate_metadata_error(error, &raw_mapping, config_dir, &sources))
         .context("Failed to deserialize merged configuration metadata")?;
 
     let providers = derive_providers(&metadata);
+    let mut config_vars = metadata.vars;
+    config_vars.extend(vars.iter().map(|(key, value)| (key.clone(), value.clone())));
 
     let mut config = CigenConfig {
         project: None,
@@ -43,59 +167,416 @@ pub fn load_split_config(config_dir: &Path) -> Result<CigenConfig> {
         source_file_groups: metadata.source_file_groups,
         jobs: HashMap::new(),
         commands: HashMap::new(),
-        caches: HashMap::new(),
+        caches: cache_definitions(metadata.caches, config_dir, &sources)?,
+        cache_version: metadata.cache_version,
+        cache_warmup: metadata.cache_warmup,
+        package_managers: metadata.package_managers,
+        version_sources: metadata.version_sources,
+        projects: metadata.projects,
+        groups: metadata.groups,
+        project_detection: metadata.project_detection,
+        hooks: metadata.hooks,
+        notifications: metadata.notifications,
+        global_steps: metadata.global_steps,
+        allow_custom_resource_classes: metadata.allow_custom_resource_classes,
         runners: HashMap::new(),
         provider_config: HashMap::new(),
         workflows: HashMap::new(),
+        env: metadata.env,
+        vars: config_vars,
+        docker_build: metadata.docker_build,
+        plugins: metadata.plugins,
+        project_root: config_dir.parent().map(Path::to_path_buf),
         raw: raw_mapping,
     };
 
     collect_provider_specific_blocks(&merged_config, &mut config);
     load_commands(config_dir, &mut config)?;
-    load_jobs_and_workflows(config_dir, &mut config)?;
+    let overlay_profiles: BTreeSet<&str> = profiles.keys().map(String::as_str).collect();
+    load_jobs_and_workflows(config_dir, profile, &overlay_profiles, &mut config)?;
+    check_job_projects(&config)?;
+    config.check_groups()?;
+    check_workflow_job_steps(&config)?;
+    check_approval_jobs(&config)?;
+    check_resource_classes(&config)?;
+    check_step_invocations(&config)?;
 
     Ok(config)
 }
 
+/// Load a `.cigen` directory or a single `cigen.yml`, with a profile's
+/// overlays applied (split configs only)
+pub fn load_config_path(config_path: &Path, profile: Option<&str>) -> Result<CigenConfig> {
+    if config_path.is_dir() {
+        load_split_config_with_profile(config_path, profile)
+    } else if let Some(profile) = profile {
+        bail!(
+            "Profile '{profile}' needs a split .cigen/ config; {} is a single file",
+            config_path.display()
+        )
+    } else {
+        let yaml = fs::read_to_string(config_path)
+            .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
+        let mut config = CigenConfig::from_yaml(&yaml).context("Failed to parse cigen.yml")?;
+        config.project_root = config_path.parent().map(Path::to_path_buf);
+        for (job_id, job) in config.jobs.iter_mut() {
+            job.source_file = Some(config_path.to_path_buf());
+            job.source_section = Some(job_id.clone());
+        }
+        check_approval_jobs(&config)?;
+        check_resource_classes(&config)?;
+        check_step_invocations(&config)?;
+        Ok(config)
+    }
+}
+
+/// Jobs' `project:` must name one of the top-level `projects:`, when listed
+fn check_job_projects(config: &CigenConfig) -> Result<()> {
+    if config.projects.is_empty() {
+        return Ok(());
+    }
+    let mut job_ids: Vec<&String> = config.jobs.keys().collect();
+    job_ids.sort();
+    for job_id in job_ids {
+        if let Some(project) = &config.jobs[job_id].project
+            && !config.projects.contains(project)
+        {
+            bail!(
+                "{}",
+                unknown_reference_message(
+                    &format!("Job '{job_id}' belongs to unknown project '{project}'"),
+                    project,
+                    config.projects.iter().map(String::as_str),
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `job_steps` in a workflow's config.yml must name jobs of that workflow
+fn check_workflow_job_steps(config: &CigenConfig) -> Result<()> {
+    let mut workflow_ids: Vec<&String> = config.workflows.keys().collect();
+    workflow_ids.sort();
+    for workflow_id in workflow_ids {
+        let mut job_ids: Vec<&String> = config.workflows[workflow_id].job_steps.keys().collect();
+        job_ids.sort();
+        for job_id in job_ids {
+            let job = config
+                .jobs
+                .get(job_id)
+                .filter(|job| job.workflow.as_ref() == Some(workflow_id));
+            if job.is_some_and(Job::is_approval) {
+                bail!(
+                    "Workflow '{workflow_id}' adds job_steps to approval job '{job_id}', which runs no steps"
+                );
+            }
+            if job.is_none() {
+                bail!(
+                    "{}",
+                    unknown_reference_message(
+                        &format!(
+                            "Workflow '{workflow_id}' adds job_steps to unknown job '{job_id}'"
+                        ),
+                        job_id,
+                        config
+                            .jobs
+                            .iter()
+                            .filter(|(_, job)| job.workflow.as_ref() == Some(workflow_id))
+                            .map(|(id, _)| id.as_str()),
+                    )
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Approval jobs only wait for someone to approve them, so they can't declare
+/// steps or anything else that runs. Errors point at the offending key when
+/// the job has its own file.
+pub fn check_approval_jobs(config: &CigenConfig) -> Result<()> {
+    let mut job_ids: Vec<&String> = config.jobs.keys().collect();
+    job_ids.sort();
+    for job_id in job_ids {
+        let job = &config.jobs[job_id];
+        if !job.is_approval() {
+            continue;
+        }
+        let Some(field) = job.approval_conflict() else {
+            continue;
+        };
+        let message = format!(
+            "Approval job '{job_id}' can't declare `{field}`; approval jobs run nothing, so move it to a job that needs '{job_id}'"
+        );
+        return Err(match &job.source_file {
+            Some(path) => located_error_in(
+                message,
+                &path.to_string_lossy(),
+                job.source_section.as_deref().unwrap_or_default(),
+                field,
+            ),
+            None => anyhow::anyhow!(message),
+        });
+    }
+    Ok(())
+}
+
+/// Jobs' `resource_class` must be a CircleCI class, a self-hosted runner
+/// class, or an alias from `resource_classes:`, unless
+/// `allow_custom_resource_classes` is set. Errors point at the value when
+/// the job has its own file.
+pub fn check_resource_classes(config: &CigenConfig) -> Result<()> {
+    if config.allow_custom_resource_classes {
+        return Ok(());
+    }
+    let aliases: Vec<&str> = match config.raw.get("resource_classes") {
+        Some(Value::Mapping(aliases)) => aliases.keys().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    let mut job_ids: Vec<&String> = config.jobs.keys().collect();
+    job_ids.sort();
+    for job_id in job_ids {
+        let job = &config.jobs[job_id];
+        let Some(class) = job.extra.get("resource_class").and_then(Value::as_str) else {
+            continue;
+        };
+        let Some(message) = unknown_resource_class_message(class, aliases.iter().copied()) else {
+            continue;
+        };
+        let message = format!("Job '{job_id}': {message}");
+        return Err(match &job.source_file {
+            Some(path) => located_error_in(
+                message,
+                &path.to_string_lossy(),
+                job.source_section.as_deref().unwrap_or_default(),
+                class,
+            ),
+            None => anyhow::anyhow!(message),
+        });
+    }
+    Ok(())
+}
+
+/// Steps that invoke a command must pass only its parameters, and every one
+/// without a default. Other steps that aren't CircleCI's built-ins, a
+/// declared command, or `alias/command` from an orb in `orbs:` get a warning.
+pub fn check_step_invocations(config: &CigenConfig) -> Result<()> {
+    let orbs = configured_orbs(config);
+    let mut declared: Vec<&str> = config.commands.keys().map(String::as_str).collect();
+    if let Some(Value::Mapping(commands)) = config.raw.get("commands") {
+        declared.extend(commands.keys().filter_map(Value::as_str));
+    }
+    let is_known = |name: &str| {
+        BUILTIN_STEPS.contains(&name)
+            || declared.contains(&name)
+            || name
+                .split_once('/')
+                .is_some_and(|(alias, _)| orbs.contains_key(alias))
+    };
+
+    let mut command_names: Vec<&String> = config.commands.keys().collect();
+    command_names.sort();
+    for command_name in command_names {
+        for (name, arguments) in config.commands[command_name]
+            .steps
+            .iter()
+            .filter_map(|step| step.invocation())
+        {
+            if let Some(command) = config.commands.get(name) {
+                command
+                    .check_arguments(name, arguments)
+                    .with_context(|| format!("Invalid step in command '{command_name}'"))?;
+            } else if !is_known(name) {
+                tracing::warn!(
+                    "Command '{command_name}' has a step '{name}' that isn't a CircleCI step, a command, or from an orb in `orbs:`"
+                );
+            }
+        }
+    }
+
+    let mut job_ids: Vec<&String> = config.jobs.keys().collect();
+    job_ids.sort();
+    for job_id in job_ids {
+        let job = &config.jobs[job_id];
+        for (name, arguments) in job
+            .steps
+            .iter()
+            .chain(&job.cleanup)
+            .filter_map(|step| step.invocation())
+        {
+            if let Some(command) = config.commands.get(name) {
+                let Err(error) = command.check_arguments(name, arguments) else {
+                    continue;
+                };
+                let message = format!("Job '{job_id}': {error}");
+                return Err(match &job.source_file {
+                    Some(path) => located_error_in(
+                        message,
+                        &path.to_string_lossy(),
+                        job.source_section.as_deref().unwrap_or_default(),
+                        name,
+                    ),
+                    None => anyhow::anyhow!(message),
+                });
+            }
+            if !is_known(name) {
+                tracing::warn!(
+                    "{}",
+                    unknown_reference_message(
+                        &format!(
+                            "Job '{job_id}' has a step '{name}' that isn't a CircleCI step, a command, or from an orb in `orbs:`"
+                        ),
+                        name,
+                        declared.iter().copied(),
+                    )
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check the step shapes of a job or command defined in `path` (its
+/// `section:` block for files that define several jobs): warn about keys
+/// providers ignore, and point an error at the key in its step
+fn check_step_shapes(value: &Value, path: &Path, section: &str) -> Result<()> {
+    let file = path.to_string_lossy();
+    let locate = |issue: &StepShapeError| {
+        let needle = format!("{}:", issue.key);
+        issue
+            .step
+            .and_then(|(list, index)| locate_list_item(&file, section, list, index, &needle))
+            .or_else(|| locate_in(&file, section, &needle))
+    };
+    let warnings = check_steps(value).map_err(|error| match locate(&error) {
+        Some(location) => LocatedError {
+            message: error.message,
+            location,
+            help: None,
+        }
+        .into(),
+        None => anyhow::Error::from(error),
+    })?;
+    for warning in warnings {
+        match locate(&warning).and_then(|loc| render_located_warning(&warning.message, &loc)) {
+            Some(rendered) => tracing::warn!("{rendered}"),
+            None => tracing::warn!("{}: {}", path.display(), warning.message),
+        }
+    }
+    Ok(())
+}
+
+/// Cache definitions from the top-level `caches:`, skipping the reserved
+/// backend settings (`artifacts`, `job_status`)
+fn cache_definitions(
+    caches: HashMap<String, Value>,
+    config_dir: &Path,
+    sources: &ConfigSources,
+) -> Result<HashMap<String, CacheDefinition>> {
+    caches
+        .into_iter()
+        .filter(|(name, _)| !RESERVED_CACHE_NAMES.contains(&name.as_str()))
+        .map(|(name, definition)| {
+            let definition = serde_yaml::from_value(definition).map_err(|error| {
+                config_error(
+                    format!("Invalid definition for cache '{name}': {error}"),
+                    &format!("caches.{name}"),
+                    config_dir,
+                    sources,
+                )
+            })?;
+            Ok((name, definition))
+        })
+        .collect()
+}
+
+/// Error located at the key `path` in whichever file of the split config set it
+fn config_error(
+    message: String,
+    path: &str,
+    config_dir: &Path,
+    sources: &ConfigSources,
+) -> anyhow::Error {
+    let Some(file) = sources.file_for(path) else {
+        return anyhow::anyhow!(message);
+    };
+    let key = path.rsplit('.').next().unwrap_or(path);
+    located_error(
+        message,
+        &config_dir.join(file).to_string_lossy(),
+        &format!("{key}:"),
+    )
+}
+
+/// Point a metadata error at the top-level section it came from, found by
+/// deserializing the sections one at a time
+fn locate_metadata_error(
+    error: serde_yaml::Error,
+    raw: &Mapping,
+    config_dir: &Path,
+    sources: &ConfigSources,
+) -> anyhow::Error {
+    let failing_key = raw.iter().find_map(|(key, value)| {
+        let section = Mapping::from_iter([(key.clone(), value.clone())]);
+        serde_yaml::from_value::<RootMetadata>(Value::Mapping(section))
+            .is_err()
+            .then(|| key.as_str())
+            .flatten()
+    });
+    match failing_key {
+        Some(key) => config_error(format!("`{key}`: {error}"), key, config_dir, sources),
+        None => error.into(),
+    }
+}
+
 fn derive_providers(metadata: &RootMetadata) -> Vec<String> {
     if let Some(providers) = &metadata.providers {
-        return providers.clone();
+        return providers.iter().map(|name| provider_name(name)).collect();
     }
 
     if let Some(provider) = &metadata.provider {
-        return vec![match provider.as_str() {
-            "github-actions" => "github".to_string(),
-            "circleci" => "circleci".to_string(),
-            other => other.to_string(),
-        }];
+        return vec![provider_name(provider)];
     }
 
     Vec::new()
 }
 
-fn merge_config_fragments(config_dir: &Path, merged_config: &mut Value) -> Result<()> {
+/// Plugin name for a provider as written in the config
+fn provider_name(name: &str) -> String {
+    match name {
+        "github-actions" => "github".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn merge_config_fragments(config_dir: &Path, merger: &mut ConfigMerger) -> Result<()> {
     let fragments_dir = config_dir.join("config");
     if !fragments_dir.exists() {
         return Ok(());
     }
 
+    let mut paths = Vec::new();
     for entry in fs::read_dir(&fragments_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if !path.is_file() {
-            continue;
+        let path = entry?.path();
+        if path.is_file()
+            && matches!(
+                path.extension().and_then(|s| s.to_str()),
+                Some("yml" | "yaml")
+            )
+        {
+            paths.push(path);
         }
+    }
+    paths.sort();
 
-        if matches!(
-            path.extension().and_then(|s| s.to_str()),
-            Some("yml" | "yaml")
-        ) {
-            let fragment_yaml = fs::read_to_string(&path)
-                .with_context(|| format!("Failed to read {}", path.display()))?;
-            let fragment_value: Value = serde_yaml::from_str(&fragment_yaml)
-                .with_context(|| format!("Failed to parse {}", path.display()))?;
-            merge_value(merged_config, fragment_value);
-        }
+    for path in paths {
+        let fragment_yaml = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let fragment_value = parse_yaml_value(&fragment_yaml)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        let source = path.strip_prefix(config_dir).unwrap_or(&path);
+        merger.merge_fragment(fragment_value, source)?;
     }
 
     Ok(())
@@ -128,19 +609,156 @@ fn load_commands(config_dir: &Path, config: &mut CigenConfig) -> Result<()> {
 
         let yaml = fs::read_to_string(&path)
             .with_context(|| format!("Failed to read {}", path.display()))?;
-        let command: CommandDefinition = serde_yaml::from_str(&yaml)
+        let value = parse_yaml_value(&yaml)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        check_step_shapes(&value, &path, "")
+            .with_context(|| format!("Invalid command {}", path.display()))?;
+        let command: CommandDefinition = serde_yaml::from_value(value)
             .with_context(|| format!("Failed to parse {}", path.display()))?;
+        command
+            .validate()
+            .with_context(|| format!("Invalid command {}", path.display()))?;
         config.commands.insert(command_name, command);
     }
 
     Ok(())
 }
 
-fn load_jobs_and_workflows(config_dir: &Path, config: &mut CigenConfig) -> Result<()> {
+/// Profiles declared by `.cigen/overlays/*.yml`, with their job overlay files, by name
+pub fn discover_profiles(config_dir: &Path) -> Result<BTreeMap<String, Profile>> {
+    find_profiles(config_dir, None)
+}
+
+/// The declared profiles, and `selected` when it is declared or has job
+/// overlays, with their job overlay files
+fn find_profiles(config_dir: &Path, selected: Option<&str>) -> Result<BTreeMap<String, Profile>> {
+    let mut profiles: BTreeMap<String, Profile> = BTreeMap::new();
+
+    let overlays_dir = config_dir.join(OVERLAYS_DIR);
+    if overlays_dir.is_dir() {
+        for entry in fs::read_dir(&overlays_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|s| s.to_str()) == Some("yml")
+                && let Some(name) = path.file_stem().and_then(|s| s.to_str())
+            {
+                profiles.entry(name.to_string()).or_default().config_overlay = Some(path.clone());
+            }
+        }
+    }
+
+    let names: BTreeSet<&str> = profiles
+        .keys()
+        .map(String::as_str)
+        .chain(selected)
+        .collect();
+    let mut job_overlays: Vec<(String, String)> = Vec::new();
+    let workflows_dir = config_dir.join("workflows");
+    if workflows_dir.is_dir() {
+        for workflow_entry in fs::read_dir(&workflows_dir)? {
+            let workflow_path = workflow_entry?.path();
+            let jobs_dir = workflow_path.join("jobs");
+            let Some(workflow) = workflow_path.file_name().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            if !jobs_dir.is_dir() {
+                continue;
+            }
+
+            let mut stack = vec![jobs_dir.clone()];
+            while let Some(dir) = stack.pop() {
+                for entry in fs::read_dir(&dir)? {
+                    let path = entry?.path();
+                    if path.is_dir() {
+                        stack.push(path);
+                    } else if let Some((job, profile)) = job_overlay_parts(&path, &names) {
+                        let base = path.with_file_name(job);
+                        let job_id = base
+                            .strip_prefix(&jobs_dir)
+                            .unwrap_or(&base)
+                            .to_string_lossy()
+                            .replace('\\', "/");
+                        job_overlays.push((profile, format!("{workflow}/{job_id}")));
+                    }
+                }
+            }
+        }
+    }
+
+    for (profile, job) in job_overlays {
+        profiles.entry(profile).or_default().job_overlays.push(job);
+    }
+    for profile in profiles.values_mut() {
+        profile.job_overlays.sort();
+    }
+    Ok(profiles)
+}
+
+/// `(job, profile)` when `path` is a `<job>.<profile>.yml` overlay, i.e. one
+/// of `profiles` and a `<job>.yml` sits next to it. Otherwise the file is a
+/// job of its own, such as `build.arm.yml` next to `build.yml`.
+fn job_overlay_parts(path: &Path, profiles: &BTreeSet<&str>) -> Option<(String, String)> {
+    if !matches!(
+        path.extension().and_then(|s| s.to_str()),
+        Some("yml" | "yaml")
+    ) {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    let (job, profile) = stem.rsplit_once('.')?;
+    if !profiles.contains(profile) {
+        return None;
+    }
+    let has_base = ["yml", "yaml"].iter().any(|extension| {
+        [
+            format!("{job}.{extension}"),
+            format!("{job}.{extension}.{TEMPLATE_EXTENSION}"),
+        ]
+        .iter()
+        .any(|name| path.with_file_name(name).is_file())
+    });
+    has_base.then(|| (job.to_string(), profile.to_string()))
+}
+
+/// The `<job>.yml` a job file defines: the file itself, or `<job>.yml` for a
+/// `<job>.yml.j2` template. `None` when `path` isn't a job file.
+fn job_file_base(path: &Path) -> Option<PathBuf> {
+    let base = if is_template_file(path) {
+        path.with_extension("")
+    } else {
+        path.to_path_buf()
+    };
+    matches!(
+        base.extension().and_then(|s| s.to_str()),
+        Some("yml" | "yaml")
+    )
+    .then_some(base)
+}
+
+/// `<job>.<profile>.yml` (or `.yaml`) next to a job file, if it exists
+fn job_overlay_path(job_path: &Path, profile: &str) -> Option<PathBuf> {
+    let stem = job_path.file_stem()?.to_str()?;
+    ["yml", "yaml"]
+        .iter()
+        .map(|extension| job_path.with_file_name(format!("{stem}.{profile}.{extension}")))
+        .find(|path| path.is_file())
+}
+
+fn load_jobs_and_workflows(
+    config_dir: &Path,
+    profile: Option<&str>,
+    overlay_profiles: &BTreeSet<&str>,
+    config: &mut CigenConfig,
+) -> Result<()> {
     let workflows_dir = config_dir.join("workflows");
     if !workflows_dir.exists() {
         return Ok(());
     }
+    let mut templates = TemplateEngine::new(&config.vars)
+        .with_template_dir(config_dir.join(TEMPLATES_DIR))
+        .with_version_sources(&config.version_sources);
+    if let Some(root) = &config.project_root {
+        templates = templates.with_project_root(root);
+    }
 
     for workflow_entry in fs::read_dir(&workflows_dir)? {
         let workflow_entry = workflow_entry?;
@@ -159,6 +777,25 @@ fn load_jobs_and_workflows(config_dir: &Path, config: &mut CigenConfig) -> Resul
                 .insert(workflow_id.clone(), workflow_config.clone());
 
             let jobs_dir = workflow_path.join("jobs");
+            if let Some(jobs_file) = workflow_jobs_file(&workflow_path) {
+                if jobs_dir.exists() {
+                    bail!(
+                        "Workflow '{workflow_name}' has both {} and a jobs/ directory; define its jobs in one of them",
+                        jobs_file.display()
+                    );
+                }
+                for (job_id, job) in load_jobs_file(&jobs_file, profile)? {
+                    let stage = job_id
+                        .split_once('/')
+                        .map_or("default", |(stage, _)| stage)
+                        .to_string();
+                    let job = add_job(config, job_id.clone(), job, workflow_name, stage)?;
+                    job.source_file = Some(jobs_file.clone());
+                    job.source_section = Some(job_id);
+                }
+                resolve_job_dependencies(&mut config.jobs);
+                continue;
+            }
             if !jobs_dir.exists() {
                 continue;
             }
@@ -181,30 +818,52 @@ fn load_jobs_and_workflows(config_dir: &Path, config: &mut CigenConfig) -> Resul
 
                         let next_stage = current_stage.clone().or(Some(dir_name));
                         stack.push((path, next_stage));
-                    } else if matches!(
-                        path.extension().and_then(|s| s.to_str()),
-                        Some("yml" | "yaml")
-                    ) {
+                    } else if let Some(base) = job_file_base(&path)
+                        && job_overlay_parts(&path, overlay_profiles).is_none()
+                    {
                         let stage = current_stage
                             .clone()
                             .unwrap_or_else(|| "default".to_string());
 
                         // Use path relative to jobs_dir as job_id, strictly using forward slashes
-                        let relative_path = path.strip_prefix(&jobs_dir).unwrap_or(&path);
+                        let relative_path = base.strip_prefix(&jobs_dir).unwrap_or(&base);
                         let job_id = relative_path
                             .with_extension("")
                             .to_string_lossy()
                             .replace('\\', "/");
 
-                        let job_yaml = fs::read_to_string(&path)?;
-                        let mut job: Job = serde_yaml::from_str(&job_yaml)
-                            .with_context(|| format!("Failed to parse {}", path.display()))?;
-
-                        job.workflow = Some(workflow_name.to_string());
-                        job.stage = Some(stage.clone());
-                        migrate_requires_to_needs(&mut job);
+                        let mut job_yaml = fs::read_to_string(&path)?;
+                        if is_template_file(&path) {
+                            if base.is_file() {
+                                bail!(
+                                    "Job '{job_id}' is defined by both {} and {}",
+                                    base.display(),
+                                    path.display()
+                                );
+                            }
+                            job_yaml = templates
+                                .render_template_file(&path, &job_yaml, workflow_name)
+                                .with_context(|| format!("Failed to render {}", path.display()))?;
+                        }
+                        let overlay = profile.and_then(|profile| job_overlay_path(&base, profile));
+                        let job = match &overlay {
+                            Some(overlay_path) => {
+                                let overlay_yaml = fs::read_to_string(overlay_path)?;
+                                parse_job_with_overlay(&job_yaml, &overlay_yaml, &path)
+                                    .with_context(|| {
+                                        format!(
+                                            "Failed to parse {} with overlay {}",
+                                            path.display(),
+                                            overlay_path.display()
+                                        )
+                                    })?
+                            }
+                            None => parse_job(&job_yaml, &path)
+                                .with_context(|| format!("Failed to parse {}", path.display()))?,
+                        };
 
-                        config.jobs.insert(job_id, job);
+                        let job = add_job(config, job_id, job, workflow_name, stage)?;
+                        job.source_file = Some(path.clone());
                     }
                 }
             }
@@ -222,7 +881,7 @@ fn load_jobs_and_workflows(config_dir: &Path, config: &mut CigenConfig) -> Resul
                 .to_string();
 
             let contents = fs::read_to_string(&workflow_path)?;
-            let value: Value = serde_yaml::from_str(&contents)
+            let value = parse_yaml_value(&contents)
                 .with_context(|| format!("Failed to parse {}", workflow_path.display()))?;
             let workflow_config = WorkflowConfig::from_value(value)?;
             config.workflows.insert(workflow_name, workflow_config);
@@ -232,23 +891,202 @@ fn load_jobs_and_workflows(config_dir: &Path, config: &mut CigenConfig) -> Resul
     Ok(())
 }
 
+/// Add a job loaded from `workflow`, rejecting a name another workflow already
+/// uses
+fn add_job<'a>(
+    config: &'a mut CigenConfig,
+    job_id: String,
+    mut job: Job,
+    workflow: &str,
+    stage: String,
+) -> Result<&'a mut Job> {
+    if let Some(existing) = config.jobs.get(&job_id) {
+        bail!(
+            "Job '{job_id}' is defined in both workflow '{}' and workflow '{workflow}'; job names are global, so rename one of them",
+            existing.workflow.as_deref().unwrap_or_default()
+        );
+    }
+    job.workflow = Some(workflow.to_string());
+    job.stage = Some(stage);
+    migrate_requires_to_needs(&mut job);
+    Ok(config.jobs.entry(job_id).or_insert(job))
+}
+
+/// `jobs.yml` (or `.yaml`) defining every job of a workflow, if it exists
+fn workflow_jobs_file(workflow_path: &Path) -> Option<PathBuf> {
+    ["jobs.yml", "jobs.yaml"]
+        .iter()
+        .map(|name| workflow_path.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Jobs from a workflow's `jobs.yml`, a mapping of job name to definition,
+/// with the profile's `jobs.<profile>.yml` overlay merged over them. Top-level
+/// `x-` keys are anchor holders, as in job files.
+fn load_jobs_file(path: &Path, profile: Option<&str>) -> Result<Vec<(String, Job)>> {
+    let contents = fs::read_to_string(path)?;
+    let value = parse_yaml_value(&contents)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    let mut jobs = match value {
+        Value::Mapping(jobs) => jobs,
+        Value::Null => Mapping::new(),
+        _ => bail!(
+            "{} must be a mapping of job names to job definitions",
+            path.display()
+        ),
+    };
+    jobs.retain(|key, _| !is_anchor_key(key));
+
+    let overlay = profile.and_then(|profile| job_overlay_path(path, profile));
+    if let Some(overlay_path) = &overlay {
+        let overlay_yaml = fs::read_to_string(overlay_path)?;
+        let Value::Mapping(overlays) = parse_yaml_value(&overlay_yaml)
+            .with_context(|| format!("Failed to parse {}", overlay_path.display()))?
+        else {
+            bail!(
+                "{} must be a mapping of job names to overrides",
+                overlay_path.display()
+            );
+        };
+        for (name, overrides) in overlays {
+            if is_anchor_key(&name) {
+                continue;
+            }
+            let Some(job) = jobs.get_mut(&name) else {
+                bail!(
+                    "{} overrides job '{}', which {} doesn't define",
+                    overlay_path.display(),
+                    name.as_str().unwrap_or_default(),
+                    path.display()
+                );
+            };
+            merge_values(job, overrides)?;
+        }
+    }
+
+    jobs.into_iter()
+        .map(|(name, definition)| {
+            let Some(name) = name.as_str() else {
+                bail!("{} has a job name that isn't a string", path.display());
+            };
+            let job = job_from_value(definition, path, name).with_context(|| match &overlay {
+                Some(overlay_path) => format!(
+                    "Failed to parse job '{name}' in {} with overlay {}",
+                    path.display(),
+                    overlay_path.display()
+                ),
+                None => format!("Failed to parse job '{name}' in {}", path.display()),
+            })?;
+            Ok((name.to_string(), job))
+        })
+        .collect()
+}
+
+/// Parse a job file. Top-level `x-` keys are treated as anchor holders for
+/// `<<` merge keys and are dropped rather than passed through to providers.
+/// Files with neither are deserialized straight from the source, so serde_yaml
+/// errors keep their line and column.
+fn parse_job(job_yaml: &str, path: &Path) -> Result<Job> {
+    let value = parse_yaml_value(job_yaml)?;
+    let written: Value = serde_yaml::from_str(job_yaml)?;
+    if value == written && !has_anchor_keys(&written) {
+        check_job(&value, path, "")?;
+        return parse_yaml(job_yaml);
+    }
+    job_from_value(value, path, "")
+}
+
+/// Parse a job file with a profile overlay merged over it
+fn parse_job_with_overlay(job_yaml: &str, overlay_yaml: &str, path: &Path) -> Result<Job> {
+    let mut value = parse_yaml_value(job_yaml)?;
+    merge_values(&mut value, parse_yaml_value(overlay_yaml)?)?;
+    job_from_value(value, path, "")
+}
+
+/// Parse an already merged job definition from `path` (its `section:` block
+/// for files that define several jobs), dropping top-level `x-` keys
+fn job_from_value(mut value: Value, path: &Path, section: &str) -> Result<Job> {
+    if let Value::Mapping(map) = &mut value {
+        map.retain(|key, _| !is_anchor_key(key));
+    }
+    check_job(&value, path, section)?;
+    from_expanded_value(value).map_err(|error| {
+        let file = path.to_string_lossy();
+        match error
+            .path
+            .as_deref()
+            .and_then(|key_path| locate_key_path(&file, section, key_path))
+        {
+            Some(location) => LocatedError {
+                message: error.to_string(),
+                location,
+                help: None,
+            }
+            .into(),
+            None => error.into(),
+        }
+    })
+}
+
+/// Where the value at `key_path` (e.g. `image` or `steps[0].run`) is written
+/// in the job's entry
+fn locate_key_path(file: &str, section: &str, key_path: &str) -> Option<SourceLocation> {
+    let (top, rest) = key_path.split_once('.').unwrap_or((key_path, ""));
+    match top.strip_suffix(']').and_then(|top| top.split_once('[')) {
+        Some((list, index)) => {
+            let key = rest.split(['.', '[']).next().unwrap_or_default();
+            let needle = if key.is_empty() {
+                "-".to_string()
+            } else {
+                format!("{key}:")
+            };
+            locate_list_item(file, section, list, index.parse().ok()?, &needle)
+        }
+        None => locate_key(file, section, top),
+    }
+}
+
+/// The checks every job definition goes through before it's deserialized
+fn check_job(value: &Value, path: &Path, section: &str) -> Result<()> {
+    check_executor_conflict(value)?;
+    check_test_splitting(value)?;
+    check_cloud_auth(value)?;
+    check_branches(value)?;
+    check_cleanup(value)?;
+    check_step_shapes(value, path, section)
+}
+
+fn is_anchor_key(key: &Value) -> bool {
+    key.as_str().is_some_and(|key| key.starts_with("x-"))
+}
+
+fn has_anchor_keys(value: &Value) -> bool {
+    value
+        .as_mapping()
+        .is_some_and(|map| map.keys().any(is_anchor_key))
+}
+
 fn resolve_job_dependencies(jobs: &mut HashMap<String, Job>) {
     let job_keys: Vec<String> = jobs.keys().cloned().collect();
 
     for (job_id, job) in jobs.iter_mut() {
         for need in &mut job.needs {
+            let (needed_job, architecture) = split_need(need);
             // If need is already a valid key, skip
-            if job_keys.contains(need) {
+            if job_keys.iter().any(|key| key == needed_job) {
                 continue;
             }
 
             // Try to resolve as sibling
             let parent_dir = Path::new(job_id).parent().unwrap_or(Path::new(""));
-            let sibling_path = parent_dir.join(need.as_str());
+            let sibling_path = parent_dir.join(needed_job);
             let sibling_key = sibling_path.to_string_lossy().replace('\\', "/");
 
             if job_keys.contains(&sibling_key) {
-                *need = sibling_key;
+                *need = match architecture {
+                    Some(architecture) => format!("{sibling_key}@{architecture}"),
+                    None => sibling_key,
+                };
                 continue;
             }
         }
@@ -261,7 +1099,7 @@ fn load_workflow_config(workflow_path: &Path) -> Result<WorkflowConfig> {
         if candidate_path.exists() {
             let contents = fs::read_to_string(&candidate_path)
                 .with_context(|| format!("Failed to read {}", candidate_path.display()))?;
-            let value: Value = serde_yaml::from_str(&contents)
+            let value = parse_yaml_value(&contents)
                 .with_context(|| format!("Failed to parse {}", candidate_path.display()))?;
             return WorkflowConfig::from_value(value);
         }
@@ -307,24 +1145,6 @@ fn collect_provider_specific_blocks(source: &Value, config: &mut CigenConfig) {
     }
 }
 
-fn merge_value(dest: &mut Value, src: Value) {
-    match (dest, src) {
-        (Value::Mapping(dest_map), Value::Mapping(src_map)) => {
-            for (key, value) in src_map {
-                match dest_map.get_mut(&key) {
-                    Some(existing) => merge_value(existing, value),
-                    None => {
-                        dest_map.insert(key, value);
-                    }
-                }
-            }
-        }
-        (dest_value, src_value) => {
-            *dest_value = src_value;
-        }
-    }
-}
-
 fn mapping_from_value(value: &Value) -> Mapping {
     match value {
         Value::Mapping(map) => map.clone(),
@@ -0,0 +1,424 @@
+//! Deep merge for split configs: `.cigen/config/*.yml` fragments and profile
+//! overlays over `config.yml`, and job overlays over job files.
+//!
+//! Maps are merged key by key; lists and scalars are replaced. A key suffix
+//! changes that for one key:
+//!
+//! - `key!` replaces the value outright, even a map
+//! - `key+` appends a list to the existing list
+//!
+//! Two fragments setting the same scalar to different values is an error,
+//! since which one wins would depend on file order. Overlays and the base
+//! config may override anything.
+//!
+//! The merger also remembers which file set each key, so errors in the merged
+//! config can point at the fragment they came from rather than `config.yml`.
+
+use anyhow::{Result, bail};
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Key suffix that replaces instead of merging
+const REPLACE_SUFFIX: char = '!';
+/// Key suffix that appends to a list instead of replacing it
+const APPEND_SUFFIX: char = '+';
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Directive {
+    Merge,
+    Replace,
+    Append,
+}
+
+/// Merges fragments over a base config, remembering which fragment set each
+/// scalar so conflicting fragments can be reported by name
+#[derive(Debug)]
+pub struct ConfigMerger {
+    merged: Value,
+    /// Fragment that set each scalar, by dotted key path (base values aren't tracked)
+    origins: HashMap<String, PathBuf>,
+    sources: ConfigSources,
+}
+
+/// File that last set each key of a merged config, by dotted key path
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ConfigSources {
+    files: HashMap<String, PathBuf>,
+}
+
+impl ConfigSources {
+    /// File that set `path`, or the nearest enclosing key that has one
+    pub fn file_for(&self, path: &str) -> Option<&Path> {
+        let mut path = path;
+        loop {
+            if let Some(file) = self.files.get(path) {
+                return Some(file);
+            }
+            path = path.rsplit_once('.')?.0;
+        }
+    }
+
+    fn record(&mut self, value: &Value, path: &str, source: &Path) {
+        let Value::Mapping(map) = value else {
+            return;
+        };
+        for (key, value) in map {
+            let (key, directive) = parse_directive(key.clone());
+            let Some(name) = key.as_str() else {
+                continue;
+            };
+            let child_path = if path.is_empty() {
+                name.to_string()
+            } else {
+                format!("{path}.{name}")
+            };
+            if directive == Directive::Replace || !value.is_mapping() {
+                self.files.retain(|key, _| !is_within(key, &child_path));
+            }
+            self.files.insert(child_path.clone(), source.to_path_buf());
+            self.record(value, &child_path, source);
+        }
+    }
+}
+
+impl ConfigMerger {
+    /// Start from the base config read from `source`
+    pub fn new(base: Value, source: &Path) -> Self {
+        let mut sources = ConfigSources::default();
+        sources.record(&base, "", source);
+        Self {
+            merged: strip_directives(base),
+            origins: HashMap::new(),
+            sources,
+        }
+    }
+
+    /// Merge a `.cigen/config/` fragment
+    pub fn merge_fragment(&mut self, fragment: Value, source: &Path) -> Result<()> {
+        self.sources.record(&fragment, "", source);
+        merge_at(
+            &mut self.merged,
+            fragment,
+            "",
+            Some(source),
+            &mut self.origins,
+        )
+    }
+
+    /// Merge an overlay that deliberately overrides everything merged so far
+    pub fn merge_overlay(&mut self, overlay: Value, source: &Path) -> Result<()> {
+        self.sources.record(&overlay, "", source);
+        merge_at(&mut self.merged, overlay, "", None, &mut self.origins)
+    }
+
+    /// The merged config, and which file set each of its keys
+    pub fn finish(self) -> (Value, ConfigSources) {
+        (self.merged, self.sources)
+    }
+}
+
+/// Merge `src` over `dest`, honoring `!` and `+` key directives
+pub fn merge_values(dest: &mut Value, src: Value) -> Result<()> {
+    merge_at(dest, src, "", None, &mut HashMap::new())
+}
+
+fn merge_at(
+    dest: &mut Value,
+    src: Value,
+    path: &str,
+    source: Option<&Path>,
+    origins: &mut HashMap<String, PathBuf>,
+) -> Result<()> {
+    let (Value::Mapping(dest_map), Value::Mapping(src_map)) = (&mut *dest, &src) else {
+        return replace_at(dest, src, path, source, origins);
+    };
+    let src_map = src_map.clone();
+
+    for (key, value) in src_map {
+        let (key, directive) = parse_directive(key);
+        let child_path = match key.as_str() {
+            Some(name) if path.is_empty() => name.to_string(),
+            Some(name) => format!("{path}.{name}"),
+            None => format!("{path}.?"),
+        };
+
+        match directive {
+            Directive::Replace => {
+                origins.retain(|origin, _| !is_within(origin, &child_path));
+                let value = strip_directives(value);
+                if let Some(source) = source {
+                    record_origins(&value, &child_path, source, origins);
+                }
+                dest_map.insert(key, value);
+            }
+            Directive::Append => {
+                let Value::Sequence(items) = strip_directives(value) else {
+                    bail!(
+                        "'{child_path}{APPEND_SUFFIX}'{} appends to a list, but its value isn't a list",
+                        describe_source(source)
+                    );
+                };
+                match dest_map.get_mut(&key) {
+                    Some(Value::Sequence(existing)) => existing.extend(items),
+                    None => {
+                        dest_map.insert(key, Value::Sequence(items));
+                    }
+                    Some(_) => bail!(
+                        "'{child_path}{APPEND_SUFFIX}'{} appends to a list, but '{child_path}' isn't a list",
+                        describe_source(source)
+                    ),
+                }
+            }
+            Directive::Merge => match dest_map.get_mut(&key) {
+                Some(existing) => merge_at(existing, value, &child_path, source, origins)?,
+                None => {
+                    let value = strip_directives(value);
+                    if let Some(source) = source {
+                        record_origins(&value, &child_path, source, origins);
+                    }
+                    dest_map.insert(key, value);
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Replace a non-map value, rejecting a scalar that another fragment already set differently
+fn replace_at(
+    dest: &mut Value,
+    src: Value,
+    path: &str,
+    source: Option<&Path>,
+    origins: &mut HashMap<String, PathBuf>,
+) -> Result<()> {
+    let src = strip_directives(src);
+    if let Some(source) = source
+        && is_scalar(&src)
+        && *dest != src
+        && let Some(previous) = origins.get(path)
+        && previous != source
+    {
+        bail!(
+            "Conflicting values for '{path}': {} sets {}, {} sets {}. Set it in one file, or in config.yml",
+            previous.display(),
+            describe_value(dest),
+            source.display(),
+            describe_value(&src)
+        );
+    }
+
+    origins.retain(|origin, _| !is_within(origin, path));
+    if let Some(source) = source {
+        record_origins(&src, path, source, origins);
+    }
+    *dest = src;
+    Ok(())
+}
+
+fn parse_directive(key: Value) -> (Value, Directive) {
+    let Value::String(name) = &key else {
+        return (key, Directive::Merge);
+    };
+    if name.len() > 1 {
+        if let Some(stripped) = name.strip_suffix(REPLACE_SUFFIX) {
+            return (Value::String(stripped.to_string()), Directive::Replace);
+        }
+        if let Some(stripped) = name.strip_suffix(APPEND_SUFFIX) {
+            return (Value::String(stripped.to_string()), Directive::Append);
+        }
+    }
+    (key, Directive::Merge)
+}
+
+/// Drop directive suffixes from keys in values that aren't merged over anything
+fn strip_directives(value: Value) -> Value {
+    match value {
+        Value::Mapping(map) => Value::Mapping(
+            map.into_iter()
+                .map(|(key, value)| (parse_directive(key).0, strip_directives(value)))
+                .collect::<Mapping>(),
+        ),
+        Value::Sequence(items) => {
+            Value::Sequence(items.into_iter().map(strip_directives).collect())
+        }
+        other => other,
+    }
+}
+
+fn record_origins(
+    value: &Value,
+    path: &str,
+    source: &Path,
+    origins: &mut HashMap<String, PathBuf>,
+) {
+    match value {
+        Value::Mapping(map) => {
+            for (key, value) in map {
+                if let Some(key) = key.as_str() {
+                    record_origins(value, &format!("{path}.{key}"), source, origins);
+                }
+            }
+        }
+        value if is_scalar(value) => {
+            origins.insert(path.to_string(), source.to_path_buf());
+        }
+        _ => {}
+    }
+}
+
+fn is_scalar(value: &Value) -> bool {
+    !matches!(
+        value,
+        Value::Mapping(_) | Value::Sequence(_) | Value::Tagged(_)
+    )
+}
+
+fn is_within(path: &str, prefix: &str) -> bool {
+    path == prefix
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
+fn describe_source(source: Option<&Path>) -> String {
+    source
+        .map(|source| format!(" in {}", source.display()))
+        .unwrap_or_default()
+}
+
+fn describe_value(value: &Value) -> String {
+    serde_yaml::to_string(value)
+        .map(|yaml| yaml.trim_end().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(text: &str) -> Value {
+        serde_yaml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn maps_merge_and_lists_replace_by_default() {
+        let mut merger = ConfigMerger::new(
+            yaml("env:\n  A: base\n  B: base\narchitectures: [amd64, arm64]\n"),
+            Path::new("config.yml"),
+        );
+        merger
+            .merge_fragment(
+                yaml("env:\n  B: fragment\narchitectures: [amd64]\n"),
+                Path::new("config/a.yml"),
+            )
+            .unwrap();
+        assert_eq!(
+            merger.finish().0,
+            yaml("env:\n  A: base\n  B: fragment\narchitectures: [amd64]\n")
+        );
+    }
+
+    #[test]
+    fn replace_directive_swaps_out_a_map() {
+        let mut merged = yaml("env:\n  A: base\n  B: base\n");
+        merge_values(&mut merged, yaml("env!:\n  C: overlay\n")).unwrap();
+        assert_eq!(merged, yaml("env:\n  C: overlay\n"));
+    }
+
+    #[test]
+    fn append_directive_extends_lists() {
+        let mut merged = yaml("steps:\n  - run: a\nnested:\n  items: [1]\n");
+        merge_values(
+            &mut merged,
+            yaml("steps+:\n  - run: b\nnested:\n  items+: [2]\n  fresh+: [3]\n"),
+        )
+        .unwrap();
+        assert_eq!(
+            merged,
+            yaml("steps:\n  - run: a\n  - run: b\nnested:\n  items: [1, 2]\n  fresh: [3]\n")
+        );
+
+        let error = merge_values(&mut merged, yaml("nested+: [4]\n"))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("'nested' isn't a list"), "{error}");
+    }
+
+    #[test]
+    fn conflicting_fragments_name_both_files() {
+        let mut merger = ConfigMerger::new(
+            yaml("docker:\n  registry: base.example.com\n"),
+            Path::new("config.yml"),
+        );
+        merger
+            .merge_fragment(
+                yaml("docker:\n  registry: a.example.com\n"),
+                Path::new("config/a.yml"),
+            )
+            .unwrap();
+        let error = merger
+            .merge_fragment(
+                yaml("docker:\n  registry: b.example.com\n"),
+                Path::new("config/b.yml"),
+            )
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains(
+                "Conflicting values for 'docker.registry': config/a.yml sets a.example.com, config/b.yml sets b.example.com"
+            ),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn fragments_may_override_the_base_and_agree_with_each_other() {
+        let mut merger = ConfigMerger::new(yaml("provider: circleci\n"), Path::new("config.yml"));
+        merger
+            .merge_fragment(yaml("provider: github\n"), Path::new("config/a.yml"))
+            .unwrap();
+        merger
+            .merge_fragment(yaml("provider: github\n"), Path::new("config/b.yml"))
+            .unwrap();
+        merger
+            .merge_fragment(yaml("provider!: woodpecker\n"), Path::new("config/c.yml"))
+            .unwrap();
+        merger
+            .merge_overlay(yaml("provider: circleci\n"), Path::new("overlays/ci.yml"))
+            .unwrap();
+        assert_eq!(merger.finish().0, yaml("provider: circleci\n"));
+    }
+
+    #[test]
+    fn sources_name_the_file_that_set_each_key() {
+        let mut merger = ConfigMerger::new(
+            yaml("provider: circleci\ncaches:\n  gems:\n    paths: [vendor/bundle]\n"),
+            Path::new("config.yml"),
+        );
+        merger
+            .merge_fragment(
+                yaml("caches:\n  node:\n    paths: node_modules\n"),
+                Path::new("config/caches.yml"),
+            )
+            .unwrap();
+        merger
+            .merge_overlay(
+                yaml("caches:\n  gems!:\n    paths: [vendor/gems]\n"),
+                Path::new("overlays/ci.yml"),
+            )
+            .unwrap();
+        let (_, sources) = merger.finish();
+
+        let file = |path| sources.file_for(path).map(Path::to_path_buf);
+        assert_eq!(file("provider"), Some("config.yml".into()));
+        assert_eq!(file("caches.node.paths"), Some("config/caches.yml".into()));
+        assert_eq!(
+            file("caches.node.paths.0"),
+            Some("config/caches.yml".into())
+        );
+        assert_eq!(file("caches.gems.paths"), Some("overlays/ci.yml".into()));
+        assert_eq!(file("missing"), None);
+    }
+}
@@ -0,0 +1,432 @@
+//! The local provider behind `cigen run`
+//!
+//! Runs a job, and the jobs it needs first, on this machine. Jobs come from
+//! [`WorkflowOrchestrator::expand_jobs`](crate::orchestrator::WorkflowOrchestrator::expand_jobs),
+//! so they have the steps and matrix variants the CI providers generate.
+//! Only run steps execute: checkout, caches, services, and other provider
+//! steps are skipped, as are steps with an `if:` condition. Each run step gets
+//! the global, workflow, job, and step `env` (in that order of precedence) in
+//! the job's `working_directory`, under `bash -eo pipefail` either on the host
+//! or, with `--docker`, in the job's image with the project mounted.
+
+use anyhow::{Context, Result, bail};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::schema::{
+    CigenConfig, CleanupOn, DEFAULT_WORKFLOW, Job, Step, unknown_reference_message,
+};
+
+/// Where the project is mounted in the job's container with `--docker`
+const CONTAINER_WORKSPACE: &str = "/workspace";
+
+/// Options for the `bash` running each step, as CircleCI runs them
+const SHELL: [&str; 3] = ["-eo", "pipefail", "-c"];
+
+/// How a job ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Passed,
+    Failed,
+    /// Not run because a job it needs didn't pass
+    Blocked,
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Passed => "passed",
+            Self::Failed => "failed",
+            Self::Blocked => "blocked",
+        })
+    }
+}
+
+/// Runs expanded jobs on this machine
+pub struct LocalRunner {
+    /// Directory the jobs' commands and working directories are relative to
+    root: PathBuf,
+    /// Run each step in the job's image instead of a host shell
+    docker: bool,
+}
+
+impl LocalRunner {
+    pub fn new(root: PathBuf, docker: bool) -> Result<Self> {
+        if docker {
+            let available = Command::new("docker")
+                .arg("version")
+                .output()
+                .is_ok_and(|output| output.status.success());
+            if !available {
+                bail!("--docker needs a running docker daemon");
+            }
+        }
+        Ok(Self { root, docker })
+    }
+
+    /// Run `jobs` in order, skipping jobs whose needs didn't pass
+    pub fn run(&self, config: &CigenConfig, jobs: &[String]) -> Result<Vec<(String, JobStatus)>> {
+        let mut statuses: HashMap<&str, JobStatus> = HashMap::new();
+        let mut report = Vec::new();
+        for name in jobs {
+            let job = &config.jobs[name];
+            let blocked = job
+                .needs
+                .iter()
+                .any(|need| statuses.get(need.as_str()) != Some(&JobStatus::Passed));
+            let status = if blocked {
+                println!("==> {name}: blocked by a failed dependency");
+                JobStatus::Blocked
+            } else {
+                self.run_job(config, name, job)?
+            };
+            statuses.insert(name, status);
+            report.push((name.clone(), status));
+        }
+        Ok(report)
+    }
+
+    fn run_job(&self, config: &CigenConfig, name: &str, job: &Job) -> Result<JobStatus> {
+        println!("==> {name}");
+        if job.is_approval() {
+            println!("    approved (approval jobs run nothing locally)");
+            return Ok(JobStatus::Passed);
+        }
+
+        let env = job_env(config, job);
+        let mut passed = true;
+        for step in &job.steps {
+            if !passed {
+                break;
+            }
+            passed = self.run_step(name, job, &env, step)?;
+        }
+        let run_cleanup = match job.cleanup_on.unwrap_or_default() {
+            CleanupOn::Always => true,
+            CleanupOn::Failure => !passed,
+        };
+        if run_cleanup {
+            for step in &job.cleanup {
+                // Cleanup steps all run, whatever the earlier ones did
+                passed &= self.run_step(name, job, &env, step)?;
+            }
+        }
+
+        let status = if passed {
+            JobStatus::Passed
+        } else {
+            JobStatus::Failed
+        };
+        println!("<== {name}: {status}");
+        Ok(status)
+    }
+
+    /// Run one step, returning whether it passed. Steps that don't run
+    /// locally pass.
+    fn run_step(
+        &self,
+        job_name: &str,
+        job: &Job,
+        env: &HashMap<String, String>,
+        step: &Step,
+    ) -> Result<bool> {
+        let (label, command, step_env) = match step {
+            _ if step.condition().is_some() => {
+                println!("    skipped step with an if: condition");
+                return Ok(true);
+            }
+            Step::SimpleRun { run, .. } => (run.lines().next().unwrap_or_default(), run, None),
+            Step::RunWithOptions { run, .. } => (
+                run.name
+                    .as_deref()
+                    .unwrap_or_else(|| run.command.lines().next().unwrap_or_default()),
+                &run.command,
+                Some(&run.env),
+            ),
+            other => {
+                println!("    skipped {} step", step_kind(other));
+                return Ok(true);
+            }
+        };
+        println!("--> {label}");
+
+        let mut env = env.clone();
+        env.extend(
+            step_env
+                .into_iter()
+                .flatten()
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        env.insert("CIGEN_JOB".to_string(), job_name.to_string());
+        let status = self
+            .command(job, &env, command)
+            .status()
+            .with_context(|| format!("Failed to start step '{label}' of job '{job_name}'"))?;
+        if !status.success() {
+            println!("    step failed ({status})");
+        }
+        Ok(status.success())
+    }
+
+    fn command(&self, job: &Job, env: &HashMap<String, String>, script: &str) -> Command {
+        let working_directory = project_directory(job);
+        if !self.docker {
+            let mut command = Command::new("bash");
+            command
+                .args(SHELL)
+                .arg(script)
+                .current_dir(self.root.join(working_directory))
+                .envs(env);
+            return command;
+        }
+
+        let mut command = Command::new("docker");
+        command
+            .args(["run", "--rm", "-v"])
+            .arg(format!("{}:{CONTAINER_WORKSPACE}", self.root.display()))
+            .arg("-w")
+            .arg(Path::new(CONTAINER_WORKSPACE).join(working_directory));
+        for (key, value) in env {
+            command.arg("-e").arg(format!("{key}={value}"));
+        }
+        command.arg(&job.image).arg("bash").args(SHELL).arg(script);
+        command
+    }
+}
+
+/// The job's `working_directory` within the project. A `~/` or absolute one
+/// is where the provider checks the project out, so it is the project root.
+fn project_directory(job: &Job) -> &str {
+    match job.working_directory.as_deref() {
+        Some(directory) if !directory.starts_with(['/', '~', '$']) => directory,
+        _ => ".",
+    }
+}
+
+/// `target` and every job it needs, needs first. `target` is a job id or a
+/// generated job name; a job with matrix variants runs all of them.
+pub fn jobs_to_run(config: &CigenConfig, target: &str) -> Result<Vec<String>> {
+    let mut targets: Vec<&String> = config
+        .jobs
+        .iter()
+        .filter(|(name, job)| {
+            *name == target
+                || job.job_id.as_deref() == Some(target)
+                || job.matrix_job.as_deref() == Some(target)
+        })
+        .map(|(name, _)| name)
+        .collect();
+    if targets.is_empty() {
+        bail!(
+            "{}",
+            unknown_reference_message(
+                &format!("Unknown job '{target}'"),
+                target,
+                config.jobs.keys().map(String::as_str),
+            )
+        );
+    }
+    targets.sort();
+
+    let mut order = Vec::new();
+    let mut visited = BTreeSet::new();
+    for name in targets {
+        visit(config, name, &mut visited, &mut order)?;
+    }
+    Ok(order)
+}
+
+/// Depth-first, so each job comes after the jobs it needs
+fn visit(
+    config: &CigenConfig,
+    name: &str,
+    visited: &mut BTreeSet<String>,
+    order: &mut Vec<String>,
+) -> Result<()> {
+    if !visited.insert(name.to_string()) {
+        return Ok(());
+    }
+    let job = config
+        .jobs
+        .get(name)
+        .with_context(|| format!("No job named '{name}'"))?;
+    let mut needs: Vec<&String> = job.needs.iter().collect();
+    needs.sort();
+    for need in needs {
+        visit(config, need, visited, order)?;
+    }
+    order.push(name.to_string());
+    Ok(())
+}
+
+/// Global `env`, then the workflow's, then the job's, with `CI` set as on a
+/// CI runner
+fn job_env(config: &CigenConfig, job: &Job) -> HashMap<String, String> {
+    let mut env = HashMap::from([("CI".to_string(), "true".to_string())]);
+    env.extend(config.env.clone());
+    let workflow = job.workflow.as_deref().unwrap_or(DEFAULT_WORKFLOW);
+    if let Some(workflow) = config.workflows.get(workflow) {
+        env.extend(workflow.env.clone());
+    }
+    env.extend(job.environment.clone());
+    env
+}
+
+fn step_kind(step: &Step) -> String {
+    match step {
+        Step::Uses(uses) => format!("uses: {}", uses.uses),
+        Step::RestoreCache { .. } => "restore_cache".to_string(),
+        Step::SaveCache { .. } => "save_cache".to_string(),
+        Step::Custom(serde_yaml::Value::String(name)) => name.clone(),
+        Step::Custom(serde_yaml::Value::Mapping(map)) => map
+            .keys()
+            .find_map(|key| key.as_str().filter(|key| *key != "if"))
+            .unwrap_or("custom")
+            .to_string(),
+        _ => "custom".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::WorkflowConfig;
+
+    fn job(needs: &[&str], steps: &str) -> Job {
+        Job {
+            needs: needs.iter().map(|need| need.to_string()).collect(),
+            steps: serde_yaml::from_str(steps).unwrap(),
+            workflow: Some("ci".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn config(jobs: Vec<(&str, Job)>) -> CigenConfig {
+        CigenConfig {
+            jobs: jobs
+                .into_iter()
+                .map(|(name, job)| (name.to_string(), job))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn needs_run_first() {
+        let mut rspec_arm = job(&["build"], "[]");
+        rspec_arm.matrix_job = Some("rspec".to_string());
+        let mut rspec_amd = job(&["build", "lint"], "[]");
+        rspec_amd.matrix_job = Some("rspec".to_string());
+        let config = config(vec![
+            ("build", job(&[], "[]")),
+            ("lint", job(&["build"], "[]")),
+            ("rspec_amd64", rspec_amd),
+            ("rspec_arm64", rspec_arm),
+            ("deploy", job(&["rspec_amd64"], "[]")),
+        ]);
+
+        assert_eq!(
+            jobs_to_run(&config, "rspec").unwrap(),
+            ["build", "lint", "rspec_amd64", "rspec_arm64"]
+        );
+        assert_eq!(jobs_to_run(&config, "lint").unwrap(), ["build", "lint"]);
+        let error = jobs_to_run(&config, "deplyo").unwrap_err().to_string();
+        assert!(error.contains("Did you mean 'deploy'?"), "{error}");
+    }
+
+    #[test]
+    fn env_layers_global_workflow_and_job() {
+        let mut config = config(vec![]);
+        config.env = HashMap::from([
+            ("LEVEL".to_string(), "global".to_string()),
+            ("GLOBAL".to_string(), "1".to_string()),
+        ]);
+        config.workflows.insert(
+            "ci".to_string(),
+            WorkflowConfig {
+                env: HashMap::from([("LEVEL".to_string(), "workflow".to_string())]),
+                ..Default::default()
+            },
+        );
+        let mut job = job(&[], "[]");
+        let env = job_env(&config, &job);
+        assert_eq!(env["LEVEL"], "workflow");
+        assert_eq!(env["GLOBAL"], "1");
+        assert_eq!(env["CI"], "true");
+
+        job.environment = HashMap::from([("LEVEL".to_string(), "job".to_string())]);
+        assert_eq!(job_env(&config, &job)["LEVEL"], "job");
+    }
+
+    #[test]
+    fn home_and_absolute_working_directories_are_the_project_root() {
+        let root = tempfile::tempdir().unwrap();
+        let mut config = config(vec![]);
+        for (name, directory) in [("home", "~/project"), ("absolute", "/srv/app")] {
+            let mut job = job(&[], &format!("- run: touch {name}\n"));
+            job.working_directory = Some(directory.to_string());
+            config.jobs.insert(name.to_string(), job);
+        }
+
+        let runner = LocalRunner::new(root.path().to_path_buf(), false).unwrap();
+        let report = runner
+            .run(&config, &["home".to_string(), "absolute".to_string()])
+            .unwrap();
+        assert!(
+            report
+                .iter()
+                .all(|(_, status)| *status == JobStatus::Passed)
+        );
+        assert!(root.path().join("home").exists());
+        assert!(root.path().join("absolute").exists());
+    }
+
+    #[test]
+    fn steps_fail_when_any_command_in_a_pipeline_fails() {
+        let root = tempfile::tempdir().unwrap();
+        let config = config(vec![(
+            "lint",
+            job(&[], "- run: false | cat\n- run: touch unreachable\n"),
+        )]);
+
+        let runner = LocalRunner::new(root.path().to_path_buf(), false).unwrap();
+        let report = runner.run(&config, &["lint".to_string()]).unwrap();
+        assert_eq!(report, [("lint".to_string(), JobStatus::Failed)]);
+        assert!(!root.path().join("unreachable").exists());
+    }
+
+    #[test]
+    fn failures_block_dependents_and_still_run_cleanup() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("app")).unwrap();
+        let mut build = job(
+            &[],
+            "- checkout\n- run: pwd > built\n- run:\n    name: Fail\n    command: exit 3\n- run: touch unreachable\n",
+        );
+        build.working_directory = Some("app".to_string());
+        build.cleanup = serde_yaml::from_str("- run: touch cleaned\n").unwrap();
+        let config = config(vec![
+            ("build", build),
+            ("test", job(&["build"], "- run: touch tested\n")),
+        ]);
+
+        let runner = LocalRunner::new(root.path().to_path_buf(), false).unwrap();
+        let report = runner
+            .run(&config, &jobs_to_run(&config, "test").unwrap())
+            .unwrap();
+        assert_eq!(
+            report,
+            [
+                ("build".to_string(), JobStatus::Failed),
+                ("test".to_string(), JobStatus::Blocked),
+            ]
+        );
+        let app = root.path().join("app");
+        assert!(app.join("built").exists());
+        assert!(app.join("cleaned").exists());
+        assert!(!app.join("unreachable").exists());
+        assert!(!root.path().join("tested").exists());
+    }
+}
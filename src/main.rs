@@ -1,4 +1,6 @@
 use anyhow::Result;
+use cigen::plugin::logging::LogFormat;
+use clap::builder::{PossibleValuesParser, TypedValueParser};
 use clap::{Parser, Subcommand};
 
 mod commands;
@@ -14,62 +16,191 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
-    /// Enable verbose output (use -vv for debug output)
+    /// Enable verbose output (use -vv for trace output)
     #[arg(short, long, global = true, action = clap::ArgAction::Count)]
     verbose: u8,
+
+    /// Only print errors (stdout still carries command output)
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Format of the log output on stderr: `text`, or `json` for one JSON
+    /// object per line
+    #[arg(
+        long,
+        global = true,
+        value_name = "FORMAT",
+        default_value = "text",
+        value_parser = PossibleValuesParser::new(LogFormat::NAMES)
+            .try_map(|name| name.parse::<LogFormat>())
+    )]
+    log_format: LogFormat,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Generate CI configuration (default command)
     Generate {
-        /// Path to .cigen directory or cigen.yml file
-        #[arg(short, long)]
-        config: Option<String>,
-
-        /// Output directory for generated files (default: .)
-        #[arg(short, long)]
-        output: Option<String>,
+        #[command(flatten)]
+        args: commands::GenerateArgs,
+    },
+    /// Pin GitHub actions to commit SHAs
+    Actions {
+        #[command(flatten)]
+        args: commands::ActionsArgs,
+    },
+    /// Check the generated config for problems, such as cache write races
+    Audit {
+        #[command(flatten)]
+        args: commands::AuditArgs,
+    },
+    /// Rewrite .cigen YAML files with canonical formatting
+    Fmt {
+        #[command(flatten)]
+        args: commands::FmtArgs,
     },
     /// Compute hashes for file patterns or jobs
     Hash {
         #[command(flatten)]
         args: commands::HashArgs,
     },
+    /// Inspect the fully resolved output for a job
+    Inspect {
+        #[command(flatten)]
+        args: commands::InspectArgs,
+    },
+    /// Warn about config smells, such as unused definitions
+    Lint {
+        #[command(flatten)]
+        args: commands::LintArgs,
+    },
+    /// List jobs, workflows, or plugins
+    List {
+        #[command(flatten)]
+        args: commands::ListArgs,
+    },
+    /// Import an existing CI config into a .cigen directory
+    Migrate {
+        #[command(flatten)]
+        args: commands::MigrateArgs,
+    },
+    /// Create a job file from prompts or flags
+    New {
+        #[command(flatten)]
+        args: commands::NewArgs,
+    },
+    /// Lock CircleCI orb versions or check them for updates
+    Orbs {
+        #[command(flatten)]
+        args: commands::OrbsArgs,
+    },
+    /// Run a job, and the jobs it needs, on this machine
+    Run {
+        #[command(flatten)]
+        args: commands::RunArgs,
+    },
+    /// Work with the bundled JSON Schemas
+    Schema {
+        #[command(flatten)]
+        args: commands::SchemaArgs,
+    },
+    /// Report the size and complexity of the generated config
+    Stats {
+        #[command(flatten)]
+        args: commands::StatsArgs,
+    },
+    /// Check that the generated files match the current config
+    Verify {
+        #[command(flatten)]
+        args: commands::VerifyArgs,
+    },
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    init_logging(cli.verbose);
+    init_logging(cli.verbose, cli.quiet, cli.log_format);
+
+    if let Err(error) = run(cli.command) {
+        if cli.log_format == LogFormat::Json {
+            tracing::error!("{error:#}");
+            std::process::exit(1);
+        }
+        // Errors pointing into a .cigen file are shown with a snippet of it
+        if let Some(rendered) = cigen::plugin::diagnostics::render_located_error(&error) {
+            eprintln!("{rendered}");
+            std::process::exit(1);
+        }
+        return Err(error);
+    }
+    Ok(())
+}
 
-    match cli.command {
-        Some(Commands::Generate { config, output }) => {
-            commands::generate_command(config, output)?;
+fn run(command: Option<Commands>) -> Result<()> {
+    match command {
+        Some(Commands::Generate { args }) => {
+            commands::generate_command(args)?;
+        }
+        Some(Commands::Actions { args }) => {
+            commands::actions_command(args)?;
+        }
+        Some(Commands::Audit { args }) => {
+            commands::audit_command(args)?;
+        }
+        Some(Commands::Fmt { args }) => {
+            commands::fmt_command(args)?;
         }
         Some(Commands::Hash { args }) => {
             commands::hash_command(args)?;
         }
+        Some(Commands::Inspect { args }) => {
+            commands::inspect_command(args)?;
+        }
+        Some(Commands::Lint { args }) => {
+            commands::lint_command(args)?;
+        }
+        Some(Commands::List { args }) => {
+            commands::list_command(args)?;
+        }
+        Some(Commands::Migrate { args }) => {
+            commands::migrate_command(args)?;
+        }
+        Some(Commands::New { args }) => {
+            commands::new_command(args)?;
+        }
+        Some(Commands::Orbs { args }) => {
+            commands::orbs_command(args)?;
+        }
+        Some(Commands::Run { args }) => {
+            commands::run_command(args)?;
+        }
+        Some(Commands::Schema { args }) => {
+            commands::schema_command(args)?;
+        }
+        Some(Commands::Stats { args }) => {
+            commands::stats_command(args)?;
+        }
+        Some(Commands::Verify { args }) => {
+            commands::verify_command(args)?;
+        }
         None => {
             // Default to generate command
-            commands::generate_command(None, None)?;
+            commands::generate_command(commands::GenerateArgs::default())?;
         }
     }
 
     Ok(())
 }
 
-fn init_logging(verbose: u8) {
+/// Progress goes to stderr at `info`; stdout is reserved for command output
+fn init_logging(verbose: u8, quiet: bool, format: LogFormat) {
     use tracing_subscriber::EnvFilter;
 
-    let filter = match verbose {
-        0 => EnvFilter::new("cigen=warn"),
-        1 => EnvFilter::new("cigen=info"),
-        _ => EnvFilter::new("cigen=debug"),
+    let filter = match (quiet, verbose) {
+        (true, _) => EnvFilter::new("cigen=error"),
+        (false, 0) => EnvFilter::new("cigen=info"),
+        (false, 1) => EnvFilter::new("cigen=debug"),
+        (false, _) => EnvFilter::new("cigen=trace"),
     };
 
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .without_time()
-        .init();
+    cigen::plugin::logging::init_logging(filter, format);
 }
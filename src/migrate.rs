@@ -0,0 +1,192 @@
+//! Import an existing CI config into a `.cigen/` tree (`cigen migrate`).
+//!
+//! Importers translate what cigen can model and keep the rest as raw YAML,
+//! marked with a [`TODO_MARKER`] comment so it's easy to find afterwards.
+
+pub mod circleci;
+pub mod github;
+
+use anyhow::{Context, Result};
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::format::{format_yaml, key_order_for};
+
+/// Marks anything the importer couldn't translate
+pub const TODO_MARKER: &str = "TODO(cigen-migrate)";
+
+/// Files for a `.cigen/` tree, plus what was translated and what was kept raw
+#[derive(Debug, Default)]
+pub struct Migration {
+    /// File contents by path relative to the `.cigen` directory
+    pub files: BTreeMap<PathBuf, String>,
+    /// Things translated into cigen config, one line each
+    pub translated: Vec<String>,
+    /// Things passed through as raw provider config, one line each
+    pub raw: Vec<String>,
+    /// Constructs that couldn't be migrated, as `file:line: message`
+    pub warnings: Vec<String>,
+}
+
+impl Migration {
+    /// Write every file under `cigen_dir`
+    pub fn write(&self, cigen_dir: &Path) -> Result<()> {
+        for (relative, contents) in &self.files {
+            let path = cigen_dir.join(relative);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            fs::write(&path, contents)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Human-readable report of what was translated and what was kept raw
+    pub fn summary(&self) -> String {
+        let mut summary = format!("Translated ({}):\n", self.translated.len());
+        for line in &self.translated {
+            summary.push_str(&format!("  {line}\n"));
+        }
+        summary.push_str(&format!("Passed through raw ({}):\n", self.raw.len()));
+        for line in &self.raw {
+            summary.push_str(&format!("  {line}\n"));
+        }
+        if !self.raw.is_empty() {
+            summary.push_str(&format!(
+                "Search the generated files for `{TODO_MARKER}` to review them.\n"
+            ));
+        }
+        summary
+    }
+}
+
+/// A YAML file assembled from a mapping, with TODO comments above the file,
+/// above top-level keys, and above individual `steps:` items
+#[derive(Debug, Default)]
+pub(crate) struct YamlFile {
+    pub map: Mapping,
+    pub file_todos: Vec<String>,
+    pub key_todos: BTreeMap<String, Vec<String>>,
+    pub step_todos: BTreeMap<usize, Vec<String>>,
+}
+
+impl YamlFile {
+    pub fn insert(&mut self, key: &str, value: Value) {
+        self.map.insert(Value::String(key.into()), value);
+    }
+
+    pub fn todo_for_key(&mut self, key: &str, todo: String) {
+        self.key_todos
+            .entry(key.to_string())
+            .or_default()
+            .push(todo);
+    }
+
+    /// Render the file, formatted the way `cigen fmt` would for `path`
+    pub fn render(&self, path: &Path) -> Result<String> {
+        let mut text = String::new();
+        for todo in &self.file_todos {
+            text.push_str(&format!("# {TODO_MARKER}: {todo}\n"));
+        }
+        for (key, value) in &self.map {
+            let name = key.as_str().unwrap_or_default();
+            for todo in self.key_todos.get(name).into_iter().flatten() {
+                text.push_str(&format!("# {TODO_MARKER}: {todo}\n"));
+            }
+            match value {
+                Value::Sequence(steps) if name == "steps" => {
+                    text.push_str("steps:\n");
+                    for (index, step) in steps.iter().enumerate() {
+                        for todo in self.step_todos.get(&index).into_iter().flatten() {
+                            text.push_str(&format!("  # {TODO_MARKER}: {todo}\n"));
+                        }
+                        let item = serde_yaml::to_string(&Value::Sequence(vec![step.clone()]))?;
+                        for line in item.lines() {
+                            text.push_str(&format!("  {line}\n"));
+                        }
+                    }
+                }
+                _ => {
+                    let mut entry = Mapping::new();
+                    entry.insert(key.clone(), value.clone());
+                    text.push_str(&serde_yaml::to_string(&Value::Mapping(entry))?);
+                }
+            }
+        }
+        Ok(format_yaml(&text, key_order_for(path)).unwrap_or(text))
+    }
+}
+
+/// Coerce a scalar to the string cigen's string maps (like `environment:`) expect
+pub(crate) fn scalar_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Bool(flag) => Some(flag.to_string()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+/// cigen environment maps hold strings
+pub(crate) fn stringify_values(map: Mapping) -> Mapping {
+    map.into_iter()
+        .map(|(key, value)| {
+            let value = scalar_string(&value).map(Value::String).unwrap_or(value);
+            (key, value)
+        })
+        .collect()
+}
+
+/// Register a service definition under `name`, or under `name_2`, `name_3`, ...
+/// when a different definition already uses the name. Returns the name used.
+pub(crate) fn add_service(
+    services: &mut BTreeMap<String, Mapping>,
+    name: &str,
+    definition: Mapping,
+) -> String {
+    let mut candidate = name.to_string();
+    let mut suffix = 1;
+    loop {
+        match services.get(&candidate) {
+            Some(existing) if *existing == definition => return candidate,
+            Some(_) => {
+                suffix += 1;
+                candidate = format!("{name}_{suffix}");
+            }
+            None => {
+                services.insert(candidate.clone(), definition);
+                return candidate;
+            }
+        }
+    }
+}
+
+/// Job names that more than one workflow uses, which need a workflow prefix
+/// because cigen job names are global
+pub(crate) fn shared_job_names<'a>(
+    names: impl IntoIterator<Item = (&'a str, String)>,
+) -> BTreeSet<String> {
+    let mut workflows: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+    for (workflow, name) in names {
+        workflows.entry(name).or_default().insert(workflow);
+    }
+    workflows
+        .into_iter()
+        .filter(|(_, workflows)| workflows.len() > 1)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// A file or directory name made from an arbitrary CI name
+pub(crate) fn file_stem(name: &str) -> String {
+    name.chars()
+        .map(|ch| match ch {
+            '/' | '\\' | ':' | ' ' => '_',
+            ch => ch,
+        })
+        .collect()
+}
@@ -0,0 +1,1063 @@
+//! `cigen migrate circleci`: import a hand-written `.circleci/config.yml`.
+//!
+//! Each job a workflow runs becomes `workflows/<workflow>/jobs/<job>.yml`,
+//! with job parameters filled in from the workflow entry. Commands become
+//! `commands/<name>.yml`. Orbs, pipeline parameters, executors, and the
+//! services that jobs list as extra docker images go into `config.yml`.
+
+use anyhow::{Context, Result, bail};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use super::{
+    Migration, YamlFile, add_service, file_stem, scalar_string, shared_job_names, stringify_values,
+};
+
+/// Keys of a workflow job entry that configure the run instead of passing parameters
+const INVOCATION_KEYS: [&str; 9] = [
+    "requires",
+    "name",
+    "context",
+    "filters",
+    "matrix",
+    "type",
+    "pre-steps",
+    "post-steps",
+    "serial-group",
+];
+
+/// Executor keys a job or a named executor can set. The CircleCI plugin
+/// accepts all of them in `executors:`
+const EXECUTOR_KEYS: [&str; 7] = [
+    "docker",
+    "machine",
+    "macos",
+    "resource_class",
+    "environment",
+    "working_directory",
+    "shell",
+];
+
+const RUN_OPTION_KEYS: [&str; 3] = ["name", "command", "environment"];
+
+/// Built-in steps cigen passes through verbatim, with the cigen alternative if there is one
+const BUILTIN_STEPS: [(&str, Option<&str>); 10] = [
+    ("store_test_results", Some("the `test_results:` job key")),
+    ("store_artifacts", Some("the `artifacts:` job key")),
+    ("setup_remote_docker", Some("the `remote_docker:` job key")),
+    ("when", Some("an `if:` condition on the step")),
+    ("unless", Some("an `if:` condition on the step")),
+    ("checkout", None),
+    ("persist_to_workspace", None),
+    ("attach_workspace", None),
+    ("add_ssh_keys", None),
+    ("deploy", None),
+];
+
+/// Name of the docker auth imported from `auth:` on images
+const IMPORTED_AUTH: &str = "circleci";
+
+static PARAMETER_REFERENCE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"<<\s*parameters\.([A-Za-z0-9_-]+)\s*>>").expect("valid regex"));
+
+/// Translate a CircleCI config into the files of a `.cigen/` tree
+pub fn migrate(source: &str) -> Result<Migration> {
+    let mut root: Value =
+        serde_yaml::from_str(source).context("Failed to parse the CircleCI config")?;
+    root.apply_merge()
+        .context("Failed to expand `<<` merge keys in the CircleCI config")?;
+    let Value::Mapping(root) = root else {
+        bail!("The CircleCI config must be a mapping");
+    };
+
+    let mut importer = Importer::default();
+    importer
+        .config
+        .insert("provider", Value::String("circleci".into()));
+
+    let section = |key: &str| root.get(key).and_then(Value::as_mapping).cloned();
+    let jobs = section("jobs").unwrap_or_default();
+    let workflows = match section("workflows") {
+        Some(workflows) => workflows,
+        None if jobs.contains_key("build") => default_workflow(),
+        None => bail!("The CircleCI config has no workflows: and no `build` job to run"),
+    };
+
+    for (key, value) in &root {
+        let Some(key) = key.as_str() else { continue };
+        match key {
+            "version" | "jobs" | "workflows" | "commands" | "executors" => {}
+            "orbs" | "parameters" => {
+                let label = if key == "orbs" {
+                    "orb"
+                } else {
+                    "pipeline parameter"
+                };
+                for name in value.as_mapping().into_iter().flat_map(Mapping::keys) {
+                    let name = name.as_str().unwrap_or_default();
+                    importer
+                        .migration
+                        .translated
+                        .push(format!("{label} '{name}' -> config.yml"));
+                }
+                importer.config.insert(key, value.clone());
+            }
+            "setup" if value.as_bool() == Some(true) => {
+                importer.config.file_todos.push(
+                    "this was a setup (dynamic config) pipeline; cigen writes its own setup config, so check how the continued config was chosen".into(),
+                );
+                importer.migration.raw.push("`setup: true`".into());
+            }
+            other => {
+                importer
+                    .unsupported_config
+                    .insert(Value::String(other.into()), value.clone());
+                importer
+                    .migration
+                    .raw
+                    .push(format!("top-level `{other}` -> config.yml x-circleci"));
+            }
+        }
+    }
+
+    importer.import_executors(section("executors").unwrap_or_default());
+    importer.import_commands(section("commands").unwrap_or_default())?;
+    importer.import_workflows(&jobs, &workflows)?;
+    importer.finish()
+}
+
+/// CircleCI runs the `build` job when a config has no workflows
+fn default_workflow() -> Mapping {
+    let mut workflow = Mapping::new();
+    workflow.insert(
+        Value::String("jobs".into()),
+        Value::Sequence(vec![Value::String("build".into())]),
+    );
+    let mut workflows = Mapping::new();
+    workflows.insert(Value::String("ci".into()), Value::Mapping(workflow));
+    workflows
+}
+
+#[derive(Default)]
+struct Importer {
+    migration: Migration,
+    config: YamlFile,
+    /// Top-level keys kept under `x-circleci` in config.yml
+    unsupported_config: Mapping,
+    /// Every executor definition, by name
+    executors: Mapping,
+    /// Executors written to config.yml; the rest are inlined into jobs
+    shared_executors: Mapping,
+    commands: BTreeSet<String>,
+    services: BTreeMap<String, Mapping>,
+    /// `auth:` from docker images, if they all agree
+    docker_auth: Option<Value>,
+    conflicting_auth: bool,
+}
+
+/// A step kept as raw CircleCI, with the comment left above it
+struct RawStep {
+    todo: String,
+    summary: String,
+}
+
+impl Importer {
+    fn import_executors(&mut self, executors: Mapping) {
+        for (name, definition) in &executors {
+            let (Some(name), Some(definition)) = (name.as_str(), definition.as_mapping()) else {
+                continue;
+            };
+            let types = definition
+                .keys()
+                .filter(|key| matches!(key.as_str(), Some("docker" | "machine" | "macos")))
+                .count();
+            let supported = types == 1
+                && definition
+                    .keys()
+                    .all(|key| key.as_str().is_some_and(|key| EXECUTOR_KEYS.contains(&key)));
+            if supported {
+                self.shared_executors.insert(
+                    Value::String(name.into()),
+                    Value::Mapping(definition.clone()),
+                );
+                self.migration
+                    .translated
+                    .push(format!("executor '{name}' -> config.yml"));
+            } else {
+                self.migration.translated.push(format!(
+                    "executor '{name}' -> inlined into the jobs that use it"
+                ));
+            }
+        }
+        self.executors = executors;
+    }
+
+    fn import_commands(&mut self, commands: Mapping) -> Result<()> {
+        self.commands = commands
+            .keys()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect();
+
+        for (name, definition) in commands {
+            let Some(name) = name.as_str() else { continue };
+            let Value::Mapping(mut definition) = definition else {
+                bail!("Command '{name}' must be a mapping");
+            };
+            let path = PathBuf::from("commands").join(format!("{}.yml", file_stem(name)));
+            let label = path.display().to_string();
+
+            let mut file = YamlFile::default();
+            let steps = take_steps(&mut definition, "steps");
+            for (key, value) in definition {
+                file.map.insert(key, value);
+            }
+            let steps = self.translate_steps(steps, &mut file, &label);
+            file.insert("steps", Value::Sequence(steps));
+
+            self.migration
+                .translated
+                .push(format!("command '{name}' -> {label}"));
+            self.migration
+                .files
+                .insert(path.clone(), file.render(&path)?);
+        }
+        Ok(())
+    }
+
+    fn import_workflows(&mut self, jobs: &Mapping, workflows: &Mapping) -> Result<()> {
+        let entries = workflow_entries(workflows);
+        let shared = shared_job_names(entries.iter().flat_map(|(workflow, entries)| {
+            entries
+                .iter()
+                .map(|(job, invocation)| (workflow.as_str(), invocation_name(job, invocation)))
+        }));
+
+        let mut used_jobs = BTreeSet::new();
+        for (workflow, definition) in workflows {
+            let Some(workflow) = workflow.as_str() else {
+                continue;
+            };
+            let Some(definition) = definition.as_mapping() else {
+                continue;
+            };
+            for key in definition.keys().filter_map(Value::as_str) {
+                if key != "jobs" {
+                    self.config.file_todos.push(format!(
+                        "workflow '{workflow}' has `{key}:`, which wasn't migrated"
+                    ));
+                    self.migration
+                        .raw
+                        .push(format!("workflow '{workflow}' `{key}:` (not migrated)"));
+                }
+            }
+        }
+
+        for (workflow, entries) in entries {
+            let rename = |name: &str| {
+                if shared.contains(name) {
+                    format!("{workflow}_{name}")
+                } else {
+                    name.to_string()
+                }
+            };
+            for (job, invocation) in entries {
+                let name = invocation_name(&job, &invocation);
+                let id = rename(&name);
+                let path = PathBuf::from("workflows")
+                    .join(file_stem(&workflow))
+                    .join("jobs")
+                    .join(format!("{}.yml", file_stem(&id)));
+                let label = path.display().to_string();
+
+                let file = self
+                    .import_job(&job, &invocation, jobs, &label, &rename)
+                    .with_context(|| {
+                        format!("Failed to migrate job '{name}' in workflow '{workflow}'")
+                    })?;
+                used_jobs.insert(job);
+
+                let renamed = if id == name {
+                    String::new()
+                } else {
+                    format!(" (renamed from '{name}', which several workflows run)")
+                };
+                self.migration
+                    .translated
+                    .push(format!("job '{id}' -> {label}{renamed}"));
+                self.migration
+                    .files
+                    .insert(path.clone(), file.render(&path)?);
+            }
+        }
+
+        for job in jobs.keys().filter_map(Value::as_str) {
+            if !used_jobs.contains(job) {
+                self.migration
+                    .raw
+                    .push(format!("job '{job}' isn't run by any workflow (skipped)"));
+            }
+        }
+        Ok(())
+    }
+
+    fn import_job(
+        &mut self,
+        job: &str,
+        invocation: &Mapping,
+        jobs: &Mapping,
+        label: &str,
+        rename: &dyn Fn(&str) -> String,
+    ) -> Result<YamlFile> {
+        let mut file = YamlFile::default();
+        let mut unsupported = Mapping::new();
+
+        let mut requires = Vec::new();
+        for required in invocation
+            .get("requires")
+            .and_then(Value::as_sequence)
+            .into_iter()
+            .flatten()
+        {
+            match required.as_str() {
+                Some(name) => requires.push(Value::String(rename(name))),
+                None => {
+                    unsupported
+                        .entry(Value::String("requires".into()))
+                        .or_insert_with(|| Value::Sequence(Vec::new()))
+                        .as_sequence_mut()
+                        .expect("requires is a list")
+                        .push(required.clone());
+                }
+            }
+        }
+        if !requires.is_empty() {
+            file.insert("requires", Value::Sequence(requires));
+        }
+
+        if invocation.get("type").and_then(Value::as_str) == Some("approval") {
+            file.insert("type", Value::String("approval".into()));
+            return Ok(file);
+        }
+
+        let Some(Value::Mapping(definition)) = jobs.get(job) else {
+            bail!("The workflow runs job '{job}', which isn't defined under jobs:");
+        };
+        let mut definition = definition.clone();
+
+        // Fill in parameters from the workflow entry, falling back to defaults
+        let declared = definition
+            .remove("parameters")
+            .and_then(|parameters| parameters.as_mapping().cloned())
+            .unwrap_or_default();
+        let mut values = BTreeMap::new();
+        for (name, spec) in &declared {
+            let Some(name) = name.as_str() else { continue };
+            if let Some(value) = invocation.get(name).or_else(|| spec.get("default")) {
+                values.insert(name.to_string(), value.clone());
+            }
+        }
+        let mut body = Value::Mapping(definition);
+        substitute_parameters(&mut body, &values);
+        if contains_parameter_reference(&body) {
+            file.file_todos.push(
+                "some `<< parameters.* >>` references had no value or default and were left as-is"
+                    .into(),
+            );
+            self.migration
+                .raw
+                .push(format!("{label}: unresolved job parameters"));
+        }
+        let Value::Mapping(mut definition) = body else {
+            unreachable!("substitution keeps mappings");
+        };
+
+        let mut environment = Mapping::new();
+        self.import_job_executor(
+            &mut definition,
+            &mut file,
+            &mut environment,
+            &mut unsupported,
+            label,
+        );
+        if let Some(Value::Mapping(job_environment)) = definition.remove("environment") {
+            environment.extend(job_environment);
+        }
+        if !environment.is_empty() {
+            file.insert("environment", Value::Mapping(stringify_values(environment)));
+        }
+        if let Some(parallelism) = definition.remove("parallelism") {
+            file.insert("parallelism", parallelism);
+        }
+
+        let mut steps = take_steps(&mut invocation.clone(), "pre-steps");
+        steps.extend(take_steps(&mut definition, "steps"));
+        steps.extend(take_steps(&mut invocation.clone(), "post-steps"));
+        let steps = self.import_checkout(steps, &mut file, label);
+        let steps = self.translate_steps(steps, &mut file, label);
+        file.insert("steps", Value::Sequence(steps));
+
+        for (key, value) in definition {
+            unsupported.insert(key, value);
+        }
+        for (key, value) in invocation {
+            if let Some(name) = key.as_str()
+                && matches!(name, "context" | "filters" | "matrix" | "serial-group")
+            {
+                unsupported.insert(key.clone(), value.clone());
+            } else if let Some(name) = key.as_str()
+                && !INVOCATION_KEYS.contains(&name)
+                && !declared.contains_key(name)
+            {
+                unsupported.insert(key.clone(), value.clone());
+            }
+        }
+        if !unsupported.is_empty() {
+            let keys: Vec<String> = unsupported
+                .keys()
+                .filter_map(Value::as_str)
+                .map(|key| format!("`{key}`"))
+                .collect();
+            file.todo_for_key(
+                "x-circleci",
+                format!(
+                    "cigen can't express {} for this job yet; kept here for reference but ignored",
+                    keys.join(", ")
+                ),
+            );
+            file.insert("x-circleci", Value::Mapping(unsupported));
+            self.migration
+                .raw
+                .push(format!("{label}: {} (under x-circleci)", keys.join(", ")));
+        }
+        Ok(file)
+    }
+
+    /// Translate the job's executor: a shared named executor, or the docker
+    /// image, services, machine, or macOS settings it resolves to
+    fn import_job_executor(
+        &mut self,
+        definition: &mut Mapping,
+        file: &mut YamlFile,
+        environment: &mut Mapping,
+        unsupported: &mut Mapping,
+        label: &str,
+    ) {
+        let mut spec = Mapping::new();
+        if let Some(reference) = definition.remove("executor") {
+            let (name, arguments) = match &reference {
+                Value::Mapping(map) => (
+                    map.get("name").and_then(Value::as_str).unwrap_or_default(),
+                    map.iter()
+                        .filter(|(key, _)| key.as_str() != Some("name"))
+                        .map(|(key, value)| (key.clone(), value.clone()))
+                        .collect::<Mapping>(),
+                ),
+                other => (other.as_str().unwrap_or_default(), Mapping::new()),
+            };
+            match self.executors.get(name).and_then(Value::as_mapping) {
+                Some(_) if self.shared_executors.contains_key(name) && arguments.is_empty() => {
+                    file.insert("executor", Value::String(name.to_string()));
+                }
+                Some(executor) => {
+                    let mut executor = executor.clone();
+                    let declared = executor.remove("parameters");
+                    let mut values = BTreeMap::new();
+                    for (parameter, spec) in declared.iter().filter_map(Value::as_mapping).flatten()
+                    {
+                        let Some(parameter) = parameter.as_str() else {
+                            continue;
+                        };
+                        if let Some(value) =
+                            arguments.get(parameter).or_else(|| spec.get("default"))
+                        {
+                            values.insert(parameter.to_string(), value.clone());
+                        }
+                    }
+                    let mut executor = Value::Mapping(executor);
+                    substitute_parameters(&mut executor, &values);
+                    if let Value::Mapping(executor) = executor {
+                        spec.extend(executor);
+                    }
+                }
+                None => {
+                    unsupported.insert(Value::String("executor".into()), reference.clone());
+                    self.migration.raw.push(format!(
+                        "{label}: executor '{name}' isn't defined in the config (orb executors aren't supported)"
+                    ));
+                }
+            }
+        }
+        for key in EXECUTOR_KEYS {
+            if let Some(value) = definition.remove(key) {
+                spec.insert(Value::String(key.into()), value);
+            }
+        }
+
+        if let Some(Value::Sequence(images)) = spec.remove("docker") {
+            let mut images = images.into_iter();
+            if let Some(Value::Mapping(mut primary)) = images.next() {
+                if let Some(image) = primary.remove("image") {
+                    file.insert("image", image);
+                }
+                if let Some(Value::Mapping(image_environment)) = primary.remove("environment") {
+                    environment.extend(image_environment);
+                }
+                if let Some(auth) = primary.remove("auth") {
+                    self.record_auth(auth);
+                }
+                for (key, value) in primary {
+                    let key = format!("docker.{}", key.as_str().unwrap_or_default());
+                    unsupported.insert(Value::String(key), value);
+                }
+            }
+            let services: Vec<Value> = images
+                .filter_map(|image| match image {
+                    Value::Mapping(image) => Some(self.import_service(image, unsupported)),
+                    _ => None,
+                })
+                .map(Value::String)
+                .collect();
+            if !services.is_empty() {
+                file.insert("services", Value::Sequence(services));
+            }
+        }
+
+        match spec.remove("machine") {
+            Some(Value::Bool(true)) => file.insert("executor", Value::String("machine".into())),
+            Some(Value::Mapping(mut machine)) => {
+                let mut options = Mapping::new();
+                if let Some(image) = machine.remove("image") {
+                    options.insert(Value::String("image".into()), image);
+                }
+                for (key, value) in machine {
+                    let key = format!("machine.{}", key.as_str().unwrap_or_default());
+                    unsupported.insert(Value::String(key), value);
+                }
+                let mut executor = Mapping::new();
+                executor.insert(Value::String("machine".into()), Value::Mapping(options));
+                file.insert("executor", Value::Mapping(executor));
+            }
+            Some(other) => {
+                unsupported.insert(Value::String("machine".into()), other);
+            }
+            None => {}
+        }
+        if let Some(macos) = spec.remove("macos") {
+            let mut executor = Mapping::new();
+            executor.insert(Value::String("macos".into()), macos);
+            file.insert("executor", Value::Mapping(executor));
+        }
+
+        if let Some(Value::Mapping(executor_environment)) = spec.remove("environment") {
+            let mut merged = executor_environment;
+            merged.extend(std::mem::take(environment));
+            *environment = merged;
+        }
+        for key in ["resource_class", "working_directory"] {
+            if let Some(value) = spec.remove(key) {
+                file.insert(key, value);
+            }
+        }
+        for (key, value) in spec {
+            unsupported.insert(key, value);
+        }
+    }
+
+    /// Name of the cigen service for an extra docker image, defining it if needed
+    fn import_service(&mut self, mut image: Mapping, unsupported: &mut Mapping) -> String {
+        let name = image
+            .remove("name")
+            .and_then(|name| name.as_str().map(str::to_string))
+            .unwrap_or_else(|| {
+                service_name(
+                    image
+                        .get("image")
+                        .and_then(Value::as_str)
+                        .unwrap_or("service"),
+                )
+            });
+        if let Some(auth) = image.remove("auth") {
+            self.record_auth(auth);
+        }
+
+        let mut definition = Mapping::new();
+        for (key, value) in image {
+            match key.as_str() {
+                Some("image") => {
+                    definition.insert(key, value);
+                }
+                Some("environment") => {
+                    let environment = value.as_mapping().cloned().unwrap_or_default();
+                    definition.insert(key, Value::Mapping(stringify_values(environment)));
+                }
+                Some(other) => {
+                    unsupported.insert(Value::String(format!("services.{name}.{other}")), value);
+                }
+                None => {}
+            }
+        }
+
+        add_service(&mut self.services, &name, definition)
+    }
+
+    fn record_auth(&mut self, auth: Value) {
+        match &self.docker_auth {
+            None => self.docker_auth = Some(auth),
+            Some(existing) if *existing != auth => self.conflicting_auth = true,
+            Some(_) => {}
+        }
+    }
+
+    /// cigen checks the repository out itself, so drop the job's `checkout` step
+    fn import_checkout(
+        &mut self,
+        mut steps: Vec<Value>,
+        file: &mut YamlFile,
+        label: &str,
+    ) -> Vec<Value> {
+        if steps.iter().any(|step| step.get("checkout").is_some()) {
+            // Checkout options (like `path:`) stay on the raw step
+            file.insert("checkout", Value::Bool(false));
+            return steps;
+        }
+        match steps
+            .iter()
+            .position(|step| step.as_str() == Some("checkout"))
+        {
+            None => file.insert("checkout", Value::Bool(false)),
+            Some(index) => {
+                steps.remove(index);
+                if index > 0 {
+                    file.todo_for_key(
+                        "steps",
+                        format!(
+                            "cigen checks out the repository before the first step; this job ran {index} step(s) before checking out"
+                        ),
+                    );
+                    self.migration
+                        .raw
+                        .push(format!("{label}: checkout moved before step {}", index + 1));
+                }
+            }
+        }
+        steps
+    }
+
+    fn translate_steps(
+        &mut self,
+        steps: Vec<Value>,
+        file: &mut YamlFile,
+        label: &str,
+    ) -> Vec<Value> {
+        let mut translated = Vec::new();
+        for step in flatten_steps(steps) {
+            let (step, raw) = self.translate_step(step);
+            if let Some(raw) = raw {
+                file.step_todos
+                    .entry(translated.len())
+                    .or_default()
+                    .push(raw.todo);
+                self.migration.raw.push(format!("{label}: {}", raw.summary));
+            }
+            translated.push(step);
+        }
+        translated
+    }
+
+    fn translate_step(&self, step: Value) -> (Value, Option<RawStep>) {
+        let (kind, body) = match &step {
+            Value::String(name) => (name.clone(), None),
+            Value::Mapping(map) if map.len() == 1 => {
+                let (kind, body) = map.iter().next().expect("one entry");
+                (
+                    kind.as_str().unwrap_or_default().to_string(),
+                    Some(body.clone()),
+                )
+            }
+            _ => {
+                return (
+                    step,
+                    Some(RawStep {
+                        todo: "unrecognized step, passed through as raw CircleCI".into(),
+                        summary: "unrecognized step".into(),
+                    }),
+                );
+            }
+        };
+
+        match kind.as_str() {
+            "run" => match body {
+                Some(Value::Mapping(options)) => translate_run(options),
+                _ => (step, None),
+            },
+            "restore_cache" | "save_cache" => (step, None),
+            kind if self.commands.contains(kind) || kind.contains('/') => (step, None),
+            kind => {
+                let hint = BUILTIN_STEPS
+                    .iter()
+                    .find(|(builtin, _)| *builtin == kind)
+                    .map(|(_, hint)| *hint);
+                let todo = match hint {
+                    Some(Some(hint)) => {
+                        format!("`{kind}` passed through as raw CircleCI; consider {hint}")
+                    }
+                    Some(None) => format!("`{kind}` passed through as raw CircleCI"),
+                    None => format!(
+                        "`{kind}` isn't a command in this config; passed through as raw CircleCI"
+                    ),
+                };
+                (
+                    step,
+                    Some(RawStep {
+                        todo,
+                        summary: format!("`{kind}` step"),
+                    }),
+                )
+            }
+        }
+    }
+
+    fn finish(mut self) -> Result<Migration> {
+        if !self.shared_executors.is_empty() {
+            self.config
+                .insert("executors", Value::Mapping(self.shared_executors));
+        }
+        if !self.services.is_empty() {
+            let services = self
+                .services
+                .into_iter()
+                .map(|(name, definition)| (Value::String(name), Value::Mapping(definition)))
+                .collect();
+            self.config.insert("services", Value::Mapping(services));
+        }
+        if let Some(auth) = self.docker_auth {
+            let mut auths = Mapping::new();
+            auths.insert(Value::String(IMPORTED_AUTH.into()), auth);
+            let mut docker = Mapping::new();
+            docker.insert(
+                Value::String("default_auth".into()),
+                Value::String(IMPORTED_AUTH.into()),
+            );
+            docker.insert(Value::String("auth".into()), Value::Mapping(auths));
+            self.config.insert("docker", Value::Mapping(docker));
+            self.config.todo_for_key(
+                "docker",
+                "cigen applies the default auth to every image, including ones that had no `auth:`"
+                    .into(),
+            );
+            if self.conflicting_auth {
+                self.config.todo_for_key(
+                    "docker",
+                    "images used different `auth:` settings; only the first one was kept".into(),
+                );
+                self.migration
+                    .raw
+                    .push("docker auth (images used different credentials)".into());
+            }
+            self.migration
+                .translated
+                .push("docker auth -> config.yml docker.auth".into());
+        }
+        if !self.unsupported_config.is_empty() {
+            self.config.todo_for_key(
+                "x-circleci",
+                "cigen doesn't understand these top-level keys; they're kept here for reference but ignored".into(),
+            );
+            self.config
+                .insert("x-circleci", Value::Mapping(self.unsupported_config));
+        }
+
+        let path = PathBuf::from("config.yml");
+        self.migration
+            .files
+            .insert(path.clone(), self.config.render(&path)?);
+        Ok(self.migration)
+    }
+}
+
+/// `(job, invocation)` pairs for each workflow, in order
+fn workflow_entries(workflows: &Mapping) -> Vec<(String, Vec<(String, Mapping)>)> {
+    let mut result = Vec::new();
+    for (workflow, definition) in workflows {
+        let Some(workflow) = workflow.as_str() else {
+            continue;
+        };
+        let Some(entries) = definition.get("jobs").and_then(Value::as_sequence) else {
+            continue;
+        };
+        let entries = entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Value::String(job) => Some((job.clone(), Mapping::new())),
+                Value::Mapping(map) if map.len() == 1 => {
+                    let (job, invocation) = map.iter().next()?;
+                    Some((
+                        job.as_str()?.to_string(),
+                        invocation.as_mapping().cloned().unwrap_or_default(),
+                    ))
+                }
+                _ => None,
+            })
+            .collect();
+        result.push((workflow.to_string(), entries));
+    }
+    result
+}
+
+fn invocation_name(job: &str, invocation: &Mapping) -> String {
+    invocation
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or(job)
+        .to_string()
+}
+
+fn take_steps(map: &mut Mapping, key: &str) -> Vec<Value> {
+    match map.remove(key) {
+        Some(Value::Sequence(steps)) => steps,
+        _ => Vec::new(),
+    }
+}
+
+/// Splice in `- steps: [...]` items left by `steps` parameters
+fn flatten_steps(steps: Vec<Value>) -> Vec<Value> {
+    let mut flattened = Vec::new();
+    for step in steps {
+        match step.get("steps") {
+            Some(Value::Sequence(inner)) if step.as_mapping().is_some_and(|map| map.len() == 1) => {
+                flattened.extend(flatten_steps(inner.clone()));
+            }
+            _ => flattened.push(step),
+        }
+    }
+    flattened
+}
+
+/// cigen's run step takes `name`, `command`, and `env`; anything else is
+/// kept on the step but flagged, since the generated step leaves it out
+fn translate_run(mut options: Mapping) -> (Value, Option<RawStep>) {
+    let unsupported: Vec<String> = options
+        .keys()
+        .filter_map(Value::as_str)
+        .filter(|key| !RUN_OPTION_KEYS.contains(key))
+        .map(|key| format!("`{key}`"))
+        .collect();
+    if let Some(Value::Mapping(environment)) = options.remove("environment") {
+        options.insert(
+            Value::String("env".into()),
+            Value::Mapping(stringify_values(environment)),
+        );
+    }
+
+    let mut step = Mapping::new();
+    step.insert(Value::String("run".into()), Value::Mapping(options));
+    let raw = (!unsupported.is_empty()).then(|| RawStep {
+        todo: format!(
+            "the generated step won't have {}; cigen run steps don't support that yet",
+            unsupported.join(", ")
+        ),
+        summary: format!("run step option(s) {}", unsupported.join(", ")),
+    });
+    (Value::Mapping(step), raw)
+}
+
+/// Replace `<< parameters.name >>` references that have a value
+fn substitute_parameters(value: &mut Value, values: &BTreeMap<String, Value>) {
+    match value {
+        Value::String(text) => {
+            let trimmed = text.trim();
+            if let Some(captures) = PARAMETER_REFERENCE.captures(trimmed)
+                && captures[0].len() == trimmed.len()
+                && let Some(replacement) = values.get(&captures[1])
+            {
+                *value = replacement.clone();
+                return;
+            }
+            let replaced = PARAMETER_REFERENCE.replace_all(text, |captures: &Captures| {
+                values
+                    .get(&captures[1])
+                    .and_then(scalar_string)
+                    .unwrap_or_else(|| captures[0].to_string())
+            });
+            *text = replaced.into_owned();
+        }
+        Value::Sequence(items) => {
+            for item in items {
+                substitute_parameters(item, values);
+            }
+        }
+        Value::Mapping(map) => {
+            for (_, item) in map.iter_mut() {
+                substitute_parameters(item, values);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn contains_parameter_reference(value: &Value) -> bool {
+    match value {
+        Value::String(text) => PARAMETER_REFERENCE.is_match(text),
+        Value::Sequence(items) => items.iter().any(contains_parameter_reference),
+        Value::Mapping(map) => map.values().any(contains_parameter_reference),
+        _ => false,
+    }
+}
+
+/// `cimg/postgres:14.1` -> `postgres`
+fn service_name(image: &str) -> String {
+    let image = image.split('@').next().unwrap_or(image);
+    let name = image.rsplit('/').next().unwrap_or(image);
+    let name = name.split(':').next().unwrap_or(name);
+    file_stem(name).replace(['.', '-'], "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file<'a>(migration: &'a Migration, path: &str) -> &'a str {
+        migration
+            .files
+            .get(&PathBuf::from(path))
+            .unwrap_or_else(|| panic!("{path} wasn't written: {:?}", migration.files.keys()))
+    }
+
+    #[test]
+    fn jobs_are_expanded_per_workflow_entry() {
+        let migration = migrate(
+            r#"
+version: 2.1
+executors:
+  ruby:
+    docker:
+      - image: cimg/ruby:3.3
+    resource_class: large
+jobs:
+  test:
+    parameters:
+      suite:
+        type: string
+        default: unit
+    executor: ruby
+    steps:
+      - checkout
+      - run: bin/test << parameters.suite >>
+workflows:
+  ci:
+    jobs:
+      - test
+      - test:
+          name: test_system
+          suite: system
+          requires: [test]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            file(&migration, "workflows/ci/jobs/test.yml"),
+            "executor: ruby\nsteps:\n  - run: bin/test unit\n"
+        );
+        assert_eq!(
+            file(&migration, "workflows/ci/jobs/test_system.yml"),
+            "executor: ruby\nrequires:\n  - test\nsteps:\n  - run: bin/test system\n"
+        );
+        assert!(
+            file(&migration, "config.yml").contains("executors:\n  ruby:\n"),
+            "{}",
+            file(&migration, "config.yml")
+        );
+    }
+
+    #[test]
+    fn docker_images_become_an_image_and_services() {
+        let migration = migrate(
+            r#"
+version: 2.1
+jobs:
+  build:
+    docker:
+      - image: cimg/ruby:3.3
+        environment:
+          RAILS_ENV: test
+      - image: cimg/postgres:16.1
+        environment:
+          POSTGRES_PASSWORD: secret
+    parallelism: 4
+    steps:
+      - checkout
+      - run:
+          name: Tests
+          command: bundle exec rspec
+          environment:
+            COVERAGE: 1
+"#,
+        )
+        .unwrap();
+
+        let job: Value =
+            serde_yaml::from_str(file(&migration, "workflows/ci/jobs/build.yml")).unwrap();
+        assert_eq!(job["image"], "cimg/ruby:3.3");
+        assert_eq!(job["services"][0], "postgres");
+        assert_eq!(job["environment"]["RAILS_ENV"], "test");
+        assert_eq!(job["parallelism"], 4);
+        assert_eq!(job["steps"][0]["run"]["env"]["COVERAGE"], "1");
+
+        let config: Value = serde_yaml::from_str(file(&migration, "config.yml")).unwrap();
+        assert_eq!(
+            config["services"]["postgres"]["image"],
+            "cimg/postgres:16.1"
+        );
+    }
+
+    #[test]
+    fn unsupported_constructs_are_kept_with_todo_comments() {
+        let migration = migrate(
+            r#"
+version: 2.1
+jobs:
+  deploy:
+    docker:
+      - image: cimg/base:current
+    shell: /bin/bash -eo pipefail
+    steps:
+      - attach_workspace:
+          at: .
+      - checkout
+      - run:
+          command: ./deploy.sh
+          no_output_timeout: 30m
+workflows:
+  release:
+    jobs:
+      - deploy:
+          context: production
+"#,
+        )
+        .unwrap();
+
+        let job = file(&migration, "workflows/release/jobs/deploy.yml");
+        assert!(
+            job.contains("# TODO(cigen-migrate): `attach_workspace` passed through as raw CircleCI\n  - attach_workspace:"),
+            "{job}"
+        );
+        assert!(
+            job.contains("the generated step won't have `no_output_timeout`"),
+            "{job}"
+        );
+        assert!(job.contains("ran 1 step(s) before checking out"), "{job}");
+        let parsed: Value = serde_yaml::from_str(job).unwrap();
+        assert_eq!(parsed["x-circleci"]["shell"], "/bin/bash -eo pipefail");
+        assert_eq!(parsed["x-circleci"]["context"], "production");
+        assert!(
+            migration.summary().contains("Passed through raw (4):"),
+            "{}",
+            migration.summary()
+        );
+    }
+}
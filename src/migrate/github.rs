@@ -0,0 +1,973 @@
+//! `cigen migrate github`: import GitHub Actions workflow files.
+//!
+//! Each workflow file becomes a cigen workflow named after the file. Its
+//! `on:`, `permissions:`, and other workflow settings go into
+//! `workflows/<workflow>/config.yml`, each job into
+//! `workflows/<workflow>/jobs/<job>.yml`, and service containers into
+//! `config.yml`.
+
+use anyhow::{Context, Result, bail};
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use super::{
+    Migration, YamlFile, add_service, file_stem, scalar_string, shared_job_names, stringify_values,
+};
+
+/// Workflow keys the GitHub provider writes back out unchanged
+const WORKFLOW_KEYS: [&str; 6] = [
+    "name",
+    "run-name",
+    "on",
+    "permissions",
+    "concurrency",
+    "defaults",
+];
+
+/// Job keys the GitHub provider copies onto the generated job unchanged
+const PASSTHROUGH_JOB_KEYS: [&str; 8] = [
+    "name",
+    "if",
+    "permissions",
+    "timeout-minutes",
+    "continue-on-error",
+    "concurrency",
+    "defaults",
+    "outputs",
+];
+
+/// Runner labels cigen treats as an `image:` (see the GitHub provider)
+const RUNNER_PREFIXES: [&str; 4] = ["ubuntu", "macos", "windows", "${{"];
+
+const RUN_STEP_KEYS: [&str; 4] = ["name", "run", "env", "if"];
+const USES_STEP_KEYS: [&str; 4] = ["name", "uses", "with", "if"];
+
+/// Translate GitHub Actions workflow files, given as `(path, contents)`,
+/// into the files of a `.cigen/` tree
+pub fn migrate(workflows: &[(PathBuf, String)]) -> Result<Migration> {
+    let mut importer = Importer::default();
+    let mut parsed = Vec::new();
+    for (path, source) in workflows {
+        let mut root: Value = serde_yaml::from_str(source)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        root.apply_merge()
+            .with_context(|| format!("Failed to expand `<<` merge keys in {}", path.display()))?;
+        let Value::Mapping(root) = root else {
+            bail!("{} must be a mapping", path.display());
+        };
+        let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            bail!("Can't name a workflow after {}", path.display());
+        };
+        parsed.push((file_stem(stem), path.as_path(), source.as_str(), root));
+    }
+
+    let shared = shared_job_names(parsed.iter().flat_map(|(workflow, _, _, root)| {
+        root.get("jobs")
+            .and_then(Value::as_mapping)
+            .into_iter()
+            .flat_map(Mapping::keys)
+            .filter_map(Value::as_str)
+            .map(|job| (workflow.as_str(), job.to_string()))
+    }));
+
+    for (workflow, path, source, root) in parsed {
+        let locator = Locator { path, source };
+        importer
+            .import_workflow(&workflow, root, &locator, &shared)
+            .with_context(|| format!("Failed to migrate {}", path.display()))?;
+    }
+    importer.finish()
+}
+
+#[derive(Default)]
+struct Importer {
+    migration: Migration,
+    services: BTreeMap<String, Mapping>,
+}
+
+/// Finds lines in a workflow file, so warnings can point at them
+struct Locator<'a> {
+    path: &'a Path,
+    source: &'a str,
+}
+
+impl Locator<'_> {
+    /// 1-based line of the job's key under `jobs:`
+    fn job_line(&self, job: &str) -> usize {
+        let lines: Vec<&str> = self.source.lines().collect();
+        let jobs = lines
+            .iter()
+            .position(|line| line.trim_end() == "jobs:")
+            .unwrap_or(0);
+        lines
+            .iter()
+            .enumerate()
+            .skip(jobs)
+            .find(|(_, line)| {
+                let key = line.trim_start().trim_matches(|ch| ch == '"' || ch == '\'');
+                key.strip_prefix(job)
+                    .is_some_and(|rest| rest.trim_start_matches(['"', '\'']).starts_with(':'))
+            })
+            .map_or(1, |(index, _)| index + 1)
+    }
+
+    /// 1-based line of the first `needle` at or after line `start`
+    fn line_after(&self, start: usize, needle: &str) -> usize {
+        self.source
+            .lines()
+            .enumerate()
+            .skip(start.saturating_sub(1))
+            .find(|(_, line)| line.contains(needle))
+            .map_or(start, |(index, _)| index + 1)
+    }
+
+    fn warning(&self, line: usize, message: String) -> String {
+        format!("{}:{line}: {message}", self.path.display())
+    }
+}
+
+impl Importer {
+    fn import_workflow(
+        &mut self,
+        workflow: &str,
+        root: Mapping,
+        locator: &Locator,
+        shared: &BTreeSet<String>,
+    ) -> Result<()> {
+        let mut config = YamlFile::default();
+        let mut unsupported = Mapping::new();
+        let mut jobs = None;
+        for (key, value) in root {
+            match key.as_str() {
+                Some("jobs") => jobs = value.as_mapping().cloned(),
+                Some("env") => match value {
+                    Value::Mapping(env) => {
+                        config.insert("env", Value::Mapping(stringify_values(env)))
+                    }
+                    other => {
+                        unsupported.insert(key, other);
+                    }
+                },
+                Some(name) if WORKFLOW_KEYS.contains(&name) => config.insert(name, value),
+                _ => {
+                    unsupported.insert(key, value);
+                }
+            }
+        }
+        let Some(jobs) = jobs else {
+            bail!("The workflow has no jobs:");
+        };
+
+        let config_path = PathBuf::from("workflows").join(workflow).join("config.yml");
+        if !unsupported.is_empty() {
+            let keys = key_list(&unsupported);
+            config.todo_for_key(
+                "x-github",
+                format!("cigen doesn't understand {keys} here; kept for reference but ignored"),
+            );
+            config.insert("x-github", Value::Mapping(unsupported));
+            self.migration.raw.push(format!(
+                "{}: {keys} (under x-github)",
+                config_path.display()
+            ));
+        }
+        if !config.map.is_empty() {
+            self.migration.translated.push(format!(
+                "workflow '{workflow}' settings -> {}",
+                config_path.display()
+            ));
+            self.migration
+                .files
+                .insert(config_path.clone(), config.render(&config_path)?);
+        }
+
+        let rename = |name: &str| {
+            if shared.contains(name) {
+                format!("{workflow}_{name}")
+            } else {
+                name.to_string()
+            }
+        };
+        for (job, definition) in jobs {
+            let Some(job) = job.as_str() else { continue };
+            let Value::Mapping(definition) = definition else {
+                bail!("Job '{job}' must be a mapping");
+            };
+            let id = rename(job);
+            let path = PathBuf::from("workflows")
+                .join(workflow)
+                .join("jobs")
+                .join(format!("{}.yml", file_stem(&id)));
+            let label = path.display().to_string();
+
+            let file = self
+                .import_job(job, definition, &label, locator, &rename)
+                .with_context(|| format!("Failed to migrate job '{job}'"))?;
+            let renamed = if id == job {
+                String::new()
+            } else {
+                format!(" (renamed from '{job}', which several workflows use)")
+            };
+            self.migration
+                .translated
+                .push(format!("job '{id}' -> {label}{renamed}"));
+            self.migration
+                .files
+                .insert(path.clone(), file.render(&path)?);
+        }
+        Ok(())
+    }
+
+    fn import_job(
+        &mut self,
+        job: &str,
+        definition: Mapping,
+        label: &str,
+        locator: &Locator,
+        rename: &dyn Fn(&str) -> String,
+    ) -> Result<YamlFile> {
+        let mut file = YamlFile::default();
+        let mut unsupported = Mapping::new();
+        let mut environment = Mapping::new();
+        let mut runs_on = None;
+        let mut container = None;
+        let mut steps = Vec::new();
+
+        for (key, value) in definition {
+            let Some(name) = key.as_str() else { continue };
+            match name {
+                "needs" => {
+                    let needs = match value {
+                        Value::String(need) => vec![need],
+                        Value::Sequence(needs) => needs
+                            .iter()
+                            .filter_map(|need| need.as_str().map(str::to_string))
+                            .collect(),
+                        _ => Vec::new(),
+                    };
+                    let needs = needs
+                        .iter()
+                        .map(|need| Value::String(rename(need)))
+                        .collect();
+                    file.insert("needs", Value::Sequence(needs));
+                }
+                "runs-on" => runs_on = Some(value),
+                "container" => container = Some(value),
+                "env" => match value {
+                    Value::Mapping(env) => environment.extend(env),
+                    other => {
+                        unsupported.insert(key, other);
+                    }
+                },
+                "services" => {
+                    let services = self.import_services(value, &mut unsupported);
+                    if !services.is_empty() {
+                        file.insert("services", Value::Sequence(services));
+                    }
+                }
+                "strategy" => self.import_strategy(value, &mut file, label),
+                "steps" => steps = value.as_sequence().cloned().unwrap_or_default(),
+                "uses" => {
+                    let workflow = value.as_str().unwrap_or_default();
+                    let line = locator.line_after(locator.job_line(job), "uses:");
+                    self.migration.warnings.push(locator.warning(
+                        line,
+                        format!(
+                            "job '{job}' calls the reusable workflow `{workflow}`, which cigen can't generate yet"
+                        ),
+                    ));
+                    self.migration.raw.push(format!(
+                        "{label}: reusable workflow `{workflow}` (under x-github)"
+                    ));
+                    unsupported.insert(key, value);
+                }
+                name if PASSTHROUGH_JOB_KEYS.contains(&name) => file.insert(name, value),
+                _ => {
+                    unsupported.insert(key, value);
+                }
+            }
+        }
+
+        self.import_runner(
+            runs_on,
+            container,
+            &mut file,
+            &mut environment,
+            &mut unsupported,
+        );
+        if !environment.is_empty() {
+            file.insert("environment", Value::Mapping(stringify_values(environment)));
+        }
+
+        if unsupported.contains_key("uses") {
+            // A job that calls a reusable workflow has no steps of its own. Keep
+            // a failing placeholder so jobs that need it still line up.
+            file.insert("checkout", Value::Bool(false));
+            file.todo_for_key(
+                "steps",
+                "replace this placeholder with the reusable workflow's jobs".into(),
+            );
+            file.insert(
+                "steps",
+                serde_yaml::from_str(
+                    "- run:\n    name: Reusable workflow not migrated\n    command: echo \"Reusable workflows aren't supported by cigen yet\" >&2 && exit 1\n",
+                )?,
+            );
+        } else {
+            let steps = self.import_checkout(steps, &mut file, label);
+            let steps = self.import_caches(steps, &mut file, label);
+            let steps = self.translate_steps(steps, &mut file, label, job, locator);
+            file.insert("steps", Value::Sequence(steps));
+        }
+
+        if file.map.contains_key("matrix") && references_matrix(&file.map) {
+            file.todo_for_key(
+                "matrix",
+                "cigen runs one job per combination but doesn't fill in `${{ matrix.* }}` yet; check the references in this job".into(),
+            );
+            self.migration
+                .raw
+                .push(format!("{label}: `${{{{ matrix.* }}}}` references"));
+        }
+
+        if !unsupported.is_empty() {
+            let keys = key_list(&unsupported);
+            file.todo_for_key(
+                "x-github",
+                format!("cigen can't express {keys} for this job yet; kept here for reference but ignored"),
+            );
+            file.insert("x-github", Value::Mapping(unsupported));
+            self.migration
+                .raw
+                .push(format!("{label}: {keys} (under x-github)"));
+        }
+        Ok(file)
+    }
+
+    /// `runs-on` and `container:` become `image:` where cigen's GitHub
+    /// provider would produce the same runner; other runners stay `runs-on`
+    fn import_runner(
+        &mut self,
+        runs_on: Option<Value>,
+        container: Option<Value>,
+        file: &mut YamlFile,
+        environment: &mut Mapping,
+        unsupported: &mut Mapping,
+    ) {
+        let image = match container {
+            Some(Value::String(image)) => Some(image),
+            Some(Value::Mapping(mut container)) => {
+                if let Some(Value::Mapping(env)) = container.remove("env") {
+                    let mut merged = env;
+                    merged.extend(std::mem::take(environment));
+                    *environment = merged;
+                }
+                let image = container
+                    .remove("image")
+                    .and_then(|image| image.as_str().map(str::to_string));
+                for (key, value) in container {
+                    let key = format!("container.{}", key.as_str().unwrap_or_default());
+                    unsupported.insert(Value::String(key), value);
+                }
+                image
+            }
+            Some(other) => {
+                unsupported.insert(Value::String("container".into()), other);
+                None
+            }
+            None => None,
+        };
+
+        match (image, runs_on) {
+            (Some(image), runs_on) => {
+                file.insert("image", Value::String(image));
+                if let Some(runs_on) =
+                    runs_on.filter(|runs_on| runs_on.as_str() != Some("ubuntu-latest"))
+                {
+                    file.insert("runs-on", runs_on);
+                }
+            }
+            (None, Some(Value::String(runner)))
+                if RUNNER_PREFIXES
+                    .iter()
+                    .any(|prefix| runner.starts_with(prefix)) =>
+            {
+                file.insert("image", Value::String(runner));
+            }
+            (None, Some(runs_on)) => file.insert("runs-on", runs_on),
+            (None, None) => {}
+        }
+    }
+
+    /// Service containers move to `config.yml`; the job lists them by name
+    fn import_services(&mut self, services: Value, unsupported: &mut Mapping) -> Vec<Value> {
+        let mut names = Vec::new();
+        for (name, container) in services.as_mapping().into_iter().flatten() {
+            let Some(name) = name.as_str() else { continue };
+            let Some(container) = container.as_mapping() else {
+                unsupported.insert(Value::String(format!("services.{name}")), container.clone());
+                continue;
+            };
+
+            let mut definition = Mapping::new();
+            for (key, value) in container {
+                match key.as_str() {
+                    Some("image") => {
+                        definition.insert(key.clone(), value.clone());
+                    }
+                    Some("env") => {
+                        let env = value.as_mapping().cloned().unwrap_or_default();
+                        definition.insert(
+                            Value::String("environment".into()),
+                            Value::Mapping(stringify_values(env)),
+                        );
+                    }
+                    Some("ports") => {
+                        let ports = value
+                            .as_sequence()
+                            .into_iter()
+                            .flatten()
+                            .filter_map(scalar_string)
+                            .map(Value::String)
+                            .collect();
+                        definition.insert(key.clone(), Value::Sequence(ports));
+                    }
+                    Some("options") => {
+                        let options = value.as_str().unwrap_or_default();
+                        let (health_check, rest) = parse_health_options(options);
+                        if !health_check.is_empty() {
+                            definition.insert(
+                                Value::String("health_check".into()),
+                                Value::Mapping(health_check),
+                            );
+                        }
+                        if !rest.is_empty() {
+                            unsupported.insert(
+                                Value::String(format!("services.{name}.options")),
+                                Value::String(rest.join(" ")),
+                            );
+                        }
+                    }
+                    Some(other) => {
+                        unsupported.insert(
+                            Value::String(format!("services.{name}.{other}")),
+                            value.clone(),
+                        );
+                    }
+                    None => {}
+                }
+            }
+
+            let service = add_service(&mut self.services, name, definition);
+            names.push(Value::String(service));
+        }
+        names
+    }
+
+    /// Plain lists of values become a cigen `matrix:`. Anything else stays a
+    /// GitHub `strategy:`, which the provider copies onto the job.
+    fn import_strategy(&mut self, strategy: Value, file: &mut YamlFile, label: &str) {
+        let Value::Mapping(mut strategy) = strategy else {
+            file.insert("strategy", strategy);
+            return;
+        };
+        if let Some(matrix) = strategy.remove("matrix") {
+            match matrix_dimensions(&matrix) {
+                Some(dimensions) => file.insert("matrix", Value::Mapping(dimensions)),
+                None => {
+                    strategy.insert(Value::String("matrix".into()), matrix);
+                    file.todo_for_key(
+                        "strategy",
+                        "cigen's `matrix:` only takes lists of values, so this matrix stays a GitHub strategy".into(),
+                    );
+                    self.migration.raw.push(format!(
+                        "{label}: `strategy.matrix` (kept as a GitHub strategy)"
+                    ));
+                }
+            }
+        }
+        if !strategy.is_empty() {
+            file.insert("strategy", Value::Mapping(strategy));
+        }
+    }
+
+    /// cigen checks the repository out itself, so drop the job's
+    /// `actions/checkout` step and keep its `with:` as `checkout:` options
+    fn import_checkout(
+        &mut self,
+        mut steps: Vec<Value>,
+        file: &mut YamlFile,
+        label: &str,
+    ) -> Vec<Value> {
+        let Some(index) = steps.iter().position(|step| {
+            step.get("uses")
+                .and_then(Value::as_str)
+                .is_some_and(is_checkout_action)
+        }) else {
+            file.insert("checkout", Value::Bool(false));
+            return steps;
+        };
+        let simple = steps[index].as_mapping().is_some_and(|step| {
+            step.keys()
+                .all(|key| matches!(key.as_str(), Some("uses" | "with" | "name")))
+        });
+        if !simple {
+            // A conditional or otherwise customized checkout stays as written
+            file.insert("checkout", Value::Bool(false));
+            return steps;
+        }
+
+        let step = steps.remove(index);
+        if let Some(Value::Mapping(with)) = step.get("with") {
+            file.insert("checkout", Value::Mapping(with.clone()));
+        }
+        if index > 0 {
+            file.todo_for_key(
+                "steps",
+                format!(
+                    "cigen checks out the repository before the first step; this job ran {index} step(s) before checking out"
+                ),
+            );
+            self.migration
+                .raw
+                .push(format!("{label}: checkout moved before step {}", index + 1));
+        }
+        steps
+    }
+
+    /// `actions/cache` steps for lockfiles cigen knows become `packages:`
+    fn import_caches(&mut self, steps: Vec<Value>, file: &mut YamlFile, label: &str) -> Vec<Value> {
+        let mut packages = Vec::new();
+        let mut kept = Vec::new();
+        for step in steps {
+            match cache_package(&step) {
+                Some(package) => {
+                    if !packages.contains(&package) {
+                        packages.push(package);
+                    }
+                    self.migration.translated.push(format!(
+                        "{label}: `actions/cache` step -> packages: [{package}]"
+                    ));
+                }
+                None => kept.push(step),
+            }
+        }
+        if !packages.is_empty() {
+            let packages = packages
+                .into_iter()
+                .map(|package| Value::String(package.into()))
+                .collect();
+            file.insert("packages", Value::Sequence(packages));
+        }
+        kept
+    }
+
+    fn translate_steps(
+        &mut self,
+        steps: Vec<Value>,
+        file: &mut YamlFile,
+        label: &str,
+        job: &str,
+        locator: &Locator,
+    ) -> Vec<Value> {
+        let mut translated = Vec::new();
+        for step in steps {
+            let Value::Mapping(step) = step else {
+                file.step_todos
+                    .entry(translated.len())
+                    .or_default()
+                    .push("unrecognized step, passed through as-is".into());
+                self.migration
+                    .raw
+                    .push(format!("{label}: unrecognized step"));
+                translated.push(step);
+                continue;
+            };
+
+            let (step, todo) = if step.contains_key("run") {
+                translate_run(step)
+            } else if let Some(action) = step.get("uses").and_then(Value::as_str) {
+                let action = action.to_string();
+                if action.starts_with("./") {
+                    let line = locator.line_after(locator.job_line(job), &action);
+                    self.migration.warnings.push(locator.warning(
+                        line,
+                        format!(
+                            "job '{job}' uses the local action `{action}`; composite actions aren't migrated, so it still runs from its action.yml"
+                        ),
+                    ));
+                }
+                self.migration
+                    .raw
+                    .push(format!("{label}: `uses: {action}` step (kept as-is)"));
+                translate_uses(step)
+            } else {
+                (
+                    Value::Mapping(step),
+                    Some("unrecognized step, passed through as-is".to_string()),
+                )
+            };
+            if let Some(todo) = todo {
+                self.migration.raw.push(format!("{label}: {todo}"));
+                file.step_todos
+                    .entry(translated.len())
+                    .or_default()
+                    .push(todo);
+            }
+            translated.push(step);
+        }
+        translated
+    }
+
+    fn finish(mut self) -> Result<Migration> {
+        let mut config = YamlFile::default();
+        config.insert("provider", Value::String("github".into()));
+        if !self.services.is_empty() {
+            for name in self.services.keys() {
+                self.migration
+                    .translated
+                    .push(format!("service '{name}' -> config.yml"));
+            }
+            let services = self
+                .services
+                .into_iter()
+                .map(|(name, definition)| (Value::String(name), Value::Mapping(definition)))
+                .collect();
+            config.insert("services", Value::Mapping(services));
+        }
+        let path = PathBuf::from("config.yml");
+        self.migration
+            .files
+            .insert(path.clone(), config.render(&path)?);
+        Ok(self.migration)
+    }
+}
+
+fn is_checkout_action(action: &str) -> bool {
+    action.starts_with("actions/checkout@")
+}
+
+/// The package whose built-in cache an `actions/cache` step matches, if any
+fn cache_package(step: &Value) -> Option<&'static str> {
+    let action = step.get("uses")?.as_str()?;
+    if !action.starts_with("actions/cache@") || step.get("if").is_some() {
+        return None;
+    }
+    let with = step.get("with")?;
+    let key = with.get("key")?.as_str()?;
+    let path = with.get("path").and_then(Value::as_str).unwrap_or_default();
+    if key.contains("Cargo.lock") && path.contains(".cargo") {
+        Some("rust")
+    } else if key.contains("pnpm-lock.yaml") {
+        Some("node")
+    } else {
+        None
+    }
+}
+
+/// `{ name, run, env, if }` becomes a cigen run step. Other keys are kept
+/// but flagged, since the generated step leaves them out.
+fn translate_run(mut step: Mapping) -> (Value, Option<String>) {
+    let unsupported = unknown_keys(&step, &RUN_STEP_KEYS);
+    let name = step.remove("name");
+    let env = step.remove("env");
+    let command = step.remove("run").unwrap_or_default();
+
+    let run = if name.is_none() && env.is_none() {
+        command
+    } else {
+        let mut options = Mapping::new();
+        if let Some(name) = name {
+            options.insert(Value::String("name".into()), name);
+        }
+        options.insert(Value::String("command".into()), command);
+        if let Some(env) = env {
+            let env = match env {
+                Value::Mapping(env) => Value::Mapping(stringify_values(env)),
+                other => other,
+            };
+            options.insert(Value::String("env".into()), env);
+        }
+        Value::Mapping(options)
+    };
+
+    let mut translated = Mapping::new();
+    translated.insert(Value::String("run".into()), run);
+    translated.extend(step);
+    let todo = (!unsupported.is_empty()).then(|| {
+        format!(
+            "the generated step won't have {}; cigen run steps don't support that yet",
+            unsupported.join(", ")
+        )
+    });
+    (Value::Mapping(translated), todo)
+}
+
+/// Action steps are kept as they are; cigen's GitHub provider emits them
+/// with `uses`, `with`, and `if`
+fn translate_uses(step: Mapping) -> (Value, Option<String>) {
+    let unsupported = unknown_keys(&step, &USES_STEP_KEYS);
+    let todo = (!unsupported.is_empty()).then(|| {
+        format!(
+            "the generated step won't have {}; cigen action steps don't support that yet",
+            unsupported.join(", ")
+        )
+    });
+    (Value::Mapping(step), todo)
+}
+
+fn unknown_keys(step: &Mapping, known: &[&str]) -> Vec<String> {
+    step.keys()
+        .filter_map(Value::as_str)
+        .filter(|key| !known.contains(key))
+        .map(|key| format!("`{key}`"))
+        .collect()
+}
+
+/// `{ ruby: ["3.2", "3.3"] }` as string lists, or `None` if the matrix uses
+/// `include`/`exclude`, expressions, or nested values
+fn matrix_dimensions(matrix: &Value) -> Option<Mapping> {
+    let mut dimensions = Mapping::new();
+    for (key, values) in matrix.as_mapping()? {
+        if matches!(key.as_str(), Some("include" | "exclude")) {
+            return None;
+        }
+        let values = values
+            .as_sequence()?
+            .iter()
+            .map(|value| scalar_string(value).map(Value::String))
+            .collect::<Option<Vec<_>>>()?;
+        dimensions.insert(key.clone(), Value::Sequence(values));
+    }
+    Some(dimensions)
+}
+
+fn references_matrix(map: &Mapping) -> bool {
+    serde_yaml::to_string(map).is_ok_and(|text| text.contains("${{ matrix."))
+}
+
+/// Split docker `--health-*` options into a cigen `health_check:` and the
+/// options it can't express
+fn parse_health_options(options: &str) -> (Mapping, Vec<String>) {
+    let mut health_check = Mapping::new();
+    let mut rest = Vec::new();
+    let mut words = shell_words(options).into_iter();
+    while let Some(word) = words.next() {
+        let (flag, inline) = match word.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (word.clone(), None),
+        };
+        let key = match flag.as_str() {
+            "--health-cmd" => "command",
+            "--health-interval" => "interval",
+            "--health-timeout" => "timeout",
+            "--health-retries" => "retries",
+            _ => {
+                rest.push(word);
+                continue;
+            }
+        };
+        let Some(value) = inline.or_else(|| words.next()) else {
+            rest.push(word);
+            continue;
+        };
+        let value = match key {
+            "retries" => value
+                .parse::<u64>()
+                .map(Value::from)
+                .unwrap_or(Value::String(value)),
+            _ => Value::String(value),
+        };
+        health_check.insert(Value::String(key.into()), value);
+    }
+    (health_check, rest)
+}
+
+/// Split on whitespace, keeping quoted runs together and dropping the quotes
+fn shell_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        match (quote, ch) {
+            (Some(open), ch) if ch == open => quote = None,
+            (Some('"'), '\\') => {
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            (Some(_), ch) => current.push(ch),
+            (None, '"' | '\'') => {
+                quote = Some(ch);
+                in_word = true;
+            }
+            (None, ch) if ch.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            (None, ch) => {
+                current.push(ch);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+    words
+}
+
+fn key_list(map: &Mapping) -> String {
+    map.keys()
+        .filter_map(Value::as_str)
+        .map(|key| format!("`{key}`"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migrate_one(source: &str) -> Migration {
+        migrate(&[(
+            PathBuf::from(".github/workflows/ci.yml"),
+            source.to_string(),
+        )])
+        .unwrap()
+    }
+
+    fn job(migration: &Migration, path: &str) -> Value {
+        let text = migration
+            .files
+            .get(&PathBuf::from(path))
+            .unwrap_or_else(|| panic!("{path} wasn't written: {:?}", migration.files.keys()));
+        serde_yaml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn jobs_translate_into_cigen_keys() {
+        let migration = migrate_one(
+            r#"
+name: CI
+on: [push]
+jobs:
+  test:
+    runs-on: ubuntu-latest
+    needs: lint
+    timeout-minutes: 15
+    env:
+      RAILS_ENV: test
+      RETRIES: 3
+    strategy:
+      fail-fast: false
+      matrix:
+        ruby: ["3.2", "3.3"]
+    services:
+      postgres:
+        image: postgres:16
+        env:
+          POSTGRES_PASSWORD: postgres
+        ports: [5432]
+        options: --health-cmd "pg_isready -U postgres" --health-interval 10s --health-retries 5
+    steps:
+      - uses: actions/checkout@v4
+        with:
+          fetch-depth: 0
+      - uses: actions/cache@v4
+        with:
+          path: |
+            ~/.cargo/registry
+            target
+          key: ${{ runner.os }}-cargo-${{ hashFiles('**/Cargo.lock') }}
+      - name: Test
+        run: cargo test
+        env:
+          RUST_LOG: debug
+      - run: echo done
+"#,
+        );
+
+        let test = job(&migration, "workflows/ci/jobs/test.yml");
+        assert_eq!(test["image"], "ubuntu-latest");
+        assert_eq!(test["needs"][0], "lint");
+        assert_eq!(test["timeout-minutes"], 15);
+        assert_eq!(test["environment"]["RETRIES"], "3");
+        assert_eq!(test["matrix"]["ruby"][1], "3.3");
+        assert_eq!(test["strategy"]["fail-fast"], false);
+        assert_eq!(test["services"][0], "postgres");
+        assert_eq!(test["checkout"]["fetch-depth"], 0);
+        assert_eq!(test["packages"][0], "rust");
+        assert_eq!(test["steps"][0]["run"]["name"], "Test");
+        assert_eq!(test["steps"][0]["run"]["env"]["RUST_LOG"], "debug");
+        assert_eq!(test["steps"][1]["run"], "echo done");
+
+        let config = job(&migration, "config.yml");
+        assert_eq!(config["provider"], "github");
+        let postgres = &config["services"]["postgres"];
+        assert_eq!(postgres["environment"]["POSTGRES_PASSWORD"], "postgres");
+        assert_eq!(postgres["ports"][0], "5432");
+        assert_eq!(
+            postgres["health_check"]["command"],
+            "pg_isready -U postgres"
+        );
+        assert_eq!(postgres["health_check"]["retries"], 5);
+
+        let workflow = job(&migration, "workflows/ci/config.yml");
+        assert_eq!(workflow["name"], "CI");
+        assert_eq!(workflow["on"][0], "push");
+    }
+
+    #[test]
+    fn reusable_workflows_and_local_actions_warn_with_locations() {
+        let migration = migrate_one(
+            "on: push
+jobs:
+  build:
+    runs-on: ubuntu-latest
+    steps:
+      - uses: ./.github/actions/setup
+      - run: make
+        shell: bash
+  deploy:
+    needs: build
+    uses: octo/deploy/.github/workflows/deploy.yml@v1
+    secrets: inherit
+",
+        );
+
+        assert_eq!(
+            migration.warnings,
+            vec![
+                ".github/workflows/ci.yml:6: job 'build' uses the local action `./.github/actions/setup`; composite actions aren't migrated, so it still runs from its action.yml",
+                ".github/workflows/ci.yml:11: job 'deploy' calls the reusable workflow `octo/deploy/.github/workflows/deploy.yml@v1`, which cigen can't generate yet",
+            ]
+        );
+
+        let build = &migration.files[&PathBuf::from("workflows/ci/jobs/build.yml")];
+        assert!(
+            build.contains("# TODO(cigen-migrate): the generated step won't have `shell`"),
+            "{build}"
+        );
+        assert!(build.contains("checkout: false"), "{build}");
+
+        let deploy = job(&migration, "workflows/ci/jobs/deploy.yml");
+        assert_eq!(deploy["x-github"]["secrets"], "inherit");
+        assert_eq!(deploy["needs"][0], "build");
+    }
+
+    #[test]
+    fn health_options_split_from_other_docker_flags() {
+        let (health_check, rest) = parse_health_options(
+            "--health-cmd=\"redis-cli ping\" --health-timeout 5s --entrypoint redis-server",
+        );
+        assert_eq!(health_check["command"], "redis-cli ping");
+        assert_eq!(health_check["timeout"], "5s");
+        assert_eq!(rest, vec!["--entrypoint", "redis-server"]);
+    }
+}
@@ -0,0 +1,517 @@
+//! CircleCI orb version locking
+//!
+//! `cigen orbs lock` resolves every orb in the config's `orbs:` passthrough
+//! (plus the `continuation` orb the CircleCI provider always uses) to an exact
+//! version and records them in `.cigen/orbs.lock.yml`. Generation then prefers
+//! the locked versions via [`apply_lock`].
+
+use anyhow::{Context, Result, bail};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::schema::CigenConfig;
+
+/// Lockfile name inside the `.cigen` directory
+pub const LOCKFILE_NAME: &str = "orbs.lock.yml";
+
+/// Alias of the orb the CircleCI setup config continues with
+pub const CONTINUATION_ALIAS: &str = "continuation";
+
+/// Continuation orb used when neither the config nor the lockfile picks one
+pub const DEFAULT_CONTINUATION_ORB: &str = "circleci/continuation@1.0.0";
+
+/// The continuation orb without a version
+const CONTINUATION_ORB: &str = "circleci/continuation";
+
+/// CircleCI's GraphQL endpoint, overridable with `CIGEN_ORB_REGISTRY_URL`
+pub const DEFAULT_REGISTRY_URL: &str = "https://circleci.com/graphql-unstable";
+
+const LOCKFILE_HEADER: &str =
+    "# Generated by `cigen orbs lock`; regenerate it instead of editing by hand.\n";
+
+/// An orb reference such as `circleci/slack@4.12.5`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OrbReference {
+    pub namespace: String,
+    pub name: String,
+    pub version: OrbVersion,
+}
+
+/// The version part of an orb reference
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OrbVersion {
+    /// `@4.12.5`
+    Exact(Semver),
+    /// `@4` or `@4.12`: the newest matching release
+    Partial { major: u64, minor: Option<u64> },
+    /// `@volatile`: the newest release
+    Volatile,
+    /// `@dev:<label>`: a development version, which can't be locked
+    Dev(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Semver {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl Semver {
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut parts = text.split('.');
+        let version = Self {
+            major: parts.next()?.parse().ok()?,
+            minor: parts.next()?.parse().ok()?,
+            patch: parts.next()?.parse().ok()?,
+        };
+        parts.next().is_none().then_some(version)
+    }
+}
+
+impl fmt::Display for Semver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl OrbReference {
+    pub fn parse(reference: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow::anyhow!(
+                "Invalid orb reference '{reference}': expected namespace/name@version, e.g. circleci/slack@4.12.5"
+            )
+        };
+        let (orb, version) = reference.split_once('@').ok_or_else(invalid)?;
+        let (namespace, name) = orb.split_once('/').ok_or_else(invalid)?;
+        if namespace.is_empty() || name.is_empty() || name.contains('/') {
+            return Err(invalid());
+        }
+
+        let version = if version == "volatile" {
+            OrbVersion::Volatile
+        } else if let Some(label) = version.strip_prefix("dev:") {
+            OrbVersion::Dev(label.to_string())
+        } else if let Some(exact) = Semver::parse(version) {
+            OrbVersion::Exact(exact)
+        } else {
+            let mut parts = version.split('.');
+            let major = parts.next().and_then(|part| part.parse().ok());
+            let minor = parts.next().map(str::parse::<u64>).transpose().ok();
+            match (major, minor, parts.next()) {
+                (Some(major), Some(minor), None) => OrbVersion::Partial { major, minor },
+                _ => return Err(invalid()),
+            }
+        };
+
+        Ok(Self {
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            version,
+        })
+    }
+
+    /// `namespace/name`, as the registry knows the orb
+    pub fn orb(&self) -> String {
+        format!("{}/{}", self.namespace, self.name)
+    }
+
+    /// Whether `exact` is a version this reference would pick up
+    pub fn accepts(&self, exact: Semver) -> bool {
+        match &self.version {
+            OrbVersion::Exact(version) => *version == exact,
+            OrbVersion::Partial { major, minor } => {
+                exact.major == *major && minor.is_none_or(|minor| exact.minor == minor)
+            }
+            OrbVersion::Volatile => true,
+            OrbVersion::Dev(_) => false,
+        }
+    }
+
+    /// The same orb at an exact version
+    pub fn at(&self, exact: Semver) -> String {
+        format!("{}@{exact}", self.orb())
+    }
+}
+
+/// Source of published orb versions
+pub trait OrbRegistry {
+    /// Every published version of `orb` (`namespace/name`)
+    fn versions(&self, orb: &str) -> Result<Vec<Semver>>;
+}
+
+/// The CircleCI orb registry, queried over GraphQL
+pub struct HttpRegistry {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl HttpRegistry {
+    pub fn new(url: impl Into<String>) -> Self {
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(30)))
+            .build()
+            .into();
+        Self {
+            url: url.into(),
+            agent,
+        }
+    }
+
+    /// The registry at `CIGEN_ORB_REGISTRY_URL`, or CircleCI's
+    pub fn from_env() -> Self {
+        match std::env::var("CIGEN_ORB_REGISTRY_URL") {
+            Ok(url) if !url.trim().is_empty() => Self::new(url),
+            _ => Self::new(DEFAULT_REGISTRY_URL),
+        }
+    }
+}
+
+impl OrbRegistry for HttpRegistry {
+    fn versions(&self, orb: &str) -> Result<Vec<Semver>> {
+        let query = serde_json::json!({
+            "query": "query($name: String!) { orb(name: $name) { versions(count: 200) { version } } }",
+            "variables": { "name": orb },
+        });
+        let response: serde_json::Value = self
+            .agent
+            .post(&self.url)
+            .send_json(&query)
+            .and_then(|mut response| response.body_mut().read_json())
+            .with_context(|| format!("Failed to query the orb registry at {}", self.url))?;
+
+        if let Some(message) = response["errors"][0]["message"].as_str() {
+            bail!("Orb registry error for '{orb}': {message}");
+        }
+        let Some(versions) = response["data"]["orb"]["versions"].as_array() else {
+            bail!("Orb '{orb}' was not found in the orb registry");
+        };
+        Ok(versions
+            .iter()
+            .filter_map(|entry| entry["version"].as_str().and_then(Semver::parse))
+            .collect())
+    }
+}
+
+/// Exact version of `reference`: the newest published version it accepts, or
+/// (offline) the version it already pins
+pub fn resolve(reference: &OrbReference, registry: Option<&dyn OrbRegistry>) -> Result<Semver> {
+    if let OrbVersion::Dev(label) = &reference.version {
+        bail!(
+            "Orb '{}@dev:{label}' is a development version and can't be locked",
+            reference.orb()
+        );
+    }
+    let Some(registry) = registry else {
+        return match reference.version {
+            OrbVersion::Exact(exact) => Ok(exact),
+            _ => bail!(
+                "Orb '{}' isn't pinned to an exact version; --offline needs X.Y.Z pins",
+                reference.orb()
+            ),
+        };
+    };
+
+    registry
+        .versions(&reference.orb())?
+        .into_iter()
+        .filter(|version| reference.accepts(*version))
+        .max()
+        .with_context(|| {
+            format!(
+                "No published version of '{}' matches the pinned version",
+                reference.orb()
+            )
+        })
+}
+
+/// Published versions newer than `locked`, oldest first
+pub fn newer_versions(
+    reference: &OrbReference,
+    locked: Semver,
+    registry: &dyn OrbRegistry,
+) -> Result<Vec<Semver>> {
+    let mut newer: Vec<Semver> = registry
+        .versions(&reference.orb())?
+        .into_iter()
+        .filter(|version| *version > locked)
+        .collect();
+    newer.sort();
+    Ok(newer)
+}
+
+/// The continuation orb the setup job uses: `setup_options.continuation_orb_version`
+/// of it, or [`DEFAULT_CONTINUATION_ORB`]. `None` with `setup_options.continuation: api`,
+/// which continues without the orb.
+pub fn setup_continuation_orb(raw: &Mapping) -> Option<String> {
+    if setup_option(raw, "continuation") == Some("api") {
+        return None;
+    }
+    Some(match setup_option(raw, "continuation_orb_version") {
+        Some(version) => format!("{CONTINUATION_ORB}@{version}"),
+        None => DEFAULT_CONTINUATION_ORB.to_string(),
+    })
+}
+
+fn setup_option<'a>(raw: &'a Mapping, key: &str) -> Option<&'a str> {
+    raw.get("setup_options")
+        .and_then(|options| options.get(key))
+        .and_then(Value::as_str)
+}
+
+/// Alias → orb reference for every orb the generated CircleCI config uses.
+/// Inline orb definitions aren't versioned and are left out.
+pub fn configured_orbs(config: &CigenConfig) -> BTreeMap<String, String> {
+    let mut orbs: BTreeMap<String, String> = setup_continuation_orb(&config.raw)
+        .map(|orb| (CONTINUATION_ALIAS.to_string(), orb))
+        .into_iter()
+        .collect();
+    if let Some(Value::Mapping(configured)) = config.raw.get("orbs") {
+        for (alias, reference) in configured {
+            if let (Some(alias), Some(reference)) = (alias.as_str(), reference.as_str()) {
+                orbs.insert(alias.to_string(), reference.to_string());
+            }
+        }
+    }
+    orbs
+}
+
+/// `.cigen/orbs.lock.yml` for the project the config was loaded from
+pub fn lockfile_path(config: &CigenConfig) -> PathBuf {
+    config
+        .project_root
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".cigen")
+        .join(LOCKFILE_NAME)
+}
+
+/// Locked alias → exact orb reference, or `None` when there is no lockfile
+pub fn read_lockfile(path: &Path) -> Result<Option<BTreeMap<String, String>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let locked: BTreeMap<String, String> = serde_yaml::from_str(&contents)
+        .with_context(|| format!("{} must map orb aliases to orb references", path.display()))?;
+    Ok(Some(locked))
+}
+
+pub fn write_lockfile(path: &Path, locked: &BTreeMap<String, String>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let contents = format!("{LOCKFILE_HEADER}{}", serde_yaml::to_string(locked)?);
+    std::fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Apply the project's lockfile, if it has one, warning where it disagrees
+/// with the config
+pub fn apply_lockfile(config: &mut CigenConfig) -> Result<()> {
+    let path = lockfile_path(config);
+    let Some(locked) = read_lockfile(&path)? else {
+        return Ok(());
+    };
+    for warning in apply_lock(config, &locked) {
+        tracing::warn!("{warning}");
+    }
+    Ok(())
+}
+
+/// Swap the config's orb references for their locked versions.
+///
+/// A locked version is only used when the config's pin accepts it; otherwise
+/// the config wins. Returns a warning for each orb where the two disagree.
+pub fn apply_lock(config: &mut CigenConfig, locked: &BTreeMap<String, String>) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut orbs = match config.raw.get("orbs") {
+        Some(Value::Mapping(orbs)) => orbs.clone(),
+        _ => Mapping::new(),
+    };
+
+    for (alias, reference) in orbs.iter_mut() {
+        let (Some(alias), Some(configured)) = (alias.as_str(), reference.as_str()) else {
+            continue;
+        };
+        let Some(lock) = locked.get(alias) else {
+            warnings.push(format!(
+                "Orb '{alias}' ({configured}) is not in {LOCKFILE_NAME}; run `cigen orbs lock`"
+            ));
+            continue;
+        };
+        if lock_satisfies(configured, lock) {
+            *reference = Value::String(lock.clone());
+        } else {
+            warnings.push(format!(
+                "Orb '{alias}' is {configured} in the config but {lock} in {LOCKFILE_NAME}; using {configured}. Run `cigen orbs lock` to update the lockfile"
+            ));
+        }
+    }
+
+    if let Some(continuation) = locked.get(CONTINUATION_ALIAS)
+        && !orbs.contains_key(CONTINUATION_ALIAS)
+        && let Some(configured) = setup_continuation_orb(&config.raw)
+    {
+        // The default version is only a fallback, so any locked version replaces it
+        let pinned = setup_option(&config.raw, "continuation_orb_version").is_some();
+        if !pinned || lock_satisfies(&configured, continuation) {
+            orbs.insert(
+                Value::String(CONTINUATION_ALIAS.into()),
+                Value::String(continuation.clone()),
+            );
+        } else {
+            warnings.push(format!(
+                "Orb '{CONTINUATION_ALIAS}' is {configured} in setup_options but {continuation} in {LOCKFILE_NAME}; using {configured}. Run `cigen orbs lock` to update the lockfile"
+            ));
+        }
+    }
+
+    if !orbs.is_empty() {
+        config
+            .raw
+            .insert(Value::String("orbs".into()), Value::Mapping(orbs));
+    }
+    warnings
+}
+
+fn lock_satisfies(configured: &str, lock: &str) -> bool {
+    let (Ok(configured), Ok(lock)) = (OrbReference::parse(configured), OrbReference::parse(lock))
+    else {
+        return false;
+    };
+    match lock.version {
+        OrbVersion::Exact(exact) => configured.orb() == lock.orb() && configured.accepts(exact),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeRegistry;
+
+    impl OrbRegistry for FakeRegistry {
+        fn versions(&self, orb: &str) -> Result<Vec<Semver>> {
+            let versions = match orb {
+                "circleci/slack" => vec!["3.4.2", "4.10.1", "4.12.5", "4.9.0"],
+                "circleci/continuation" => vec!["0.5.0", "1.0.0", "1.1.0"],
+                _ => bail!("Orb '{orb}' was not found in the orb registry"),
+            };
+            Ok(versions.into_iter().filter_map(Semver::parse).collect())
+        }
+    }
+
+    fn resolve_str(reference: &str, registry: Option<&dyn OrbRegistry>) -> Result<String> {
+        let reference = OrbReference::parse(reference)?;
+        Ok(reference.at(resolve(&reference, registry)?))
+    }
+
+    #[test]
+    fn resolves_pins_to_the_newest_matching_version() {
+        let registry: &dyn OrbRegistry = &FakeRegistry;
+        assert_eq!(
+            resolve_str("circleci/slack@4", Some(registry)).unwrap(),
+            "circleci/slack@4.12.5"
+        );
+        assert_eq!(
+            resolve_str("circleci/slack@4.9", Some(registry)).unwrap(),
+            "circleci/slack@4.9.0"
+        );
+        assert_eq!(
+            resolve_str("circleci/slack@volatile", Some(registry)).unwrap(),
+            "circleci/slack@4.12.5"
+        );
+        let error = resolve_str("circleci/slack@5", Some(registry))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("No published version"), "{error}");
+    }
+
+    #[test]
+    fn offline_resolution_only_accepts_exact_pins() {
+        assert_eq!(
+            resolve_str("circleci/slack@4.12.5", None).unwrap(),
+            "circleci/slack@4.12.5"
+        );
+        let error = resolve_str("circleci/slack@4", None)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("--offline needs X.Y.Z pins"), "{error}");
+        for invalid in ["slack@4.12.5", "circleci/slack", "circleci/slack@4.x"] {
+            assert!(OrbReference::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn lock_applies_when_the_config_pin_accepts_it() {
+        let mut config = CigenConfig::from_yaml(
+            r#"
+orbs:
+  slack: circleci/slack@4
+  node: circleci/node@5.0.0
+  aws: circleci/aws-cli@4.1
+jobs:
+  notify:
+    steps:
+      - slack/notify
+"#,
+        )
+        .unwrap();
+        let locked = BTreeMap::from([
+            ("slack".to_string(), "circleci/slack@4.12.5".to_string()),
+            ("node".to_string(), "circleci/node@5.1.0".to_string()),
+            (
+                "continuation".to_string(),
+                "circleci/continuation@1.1.0".to_string(),
+            ),
+        ]);
+
+        let warnings = apply_lock(&mut config, &locked);
+        let orbs = &config.raw["orbs"];
+        assert_eq!(orbs["slack"].as_str(), Some("circleci/slack@4.12.5"));
+        assert_eq!(orbs["node"].as_str(), Some("circleci/node@5.0.0"));
+        assert_eq!(orbs["aws"].as_str(), Some("circleci/aws-cli@4.1"));
+        assert_eq!(
+            orbs["continuation"].as_str(),
+            Some("circleci/continuation@1.1.0")
+        );
+        assert_eq!(warnings.len(), 2, "{warnings:?}");
+        assert!(warnings[0].contains("circleci/node@5.0.0 in the config but circleci/node@5.1.0"));
+        assert!(warnings[1].contains("'aws' (circleci/aws-cli@4.1) is not in orbs.lock.yml"));
+    }
+
+    #[test]
+    fn setup_options_pick_the_continuation_orb() {
+        let config = |setup_options: &str| {
+            CigenConfig::from_yaml(&format!(
+                "setup_options:\n{setup_options}\njobs:\n  test:\n    steps:\n      - run: make test\n"
+            ))
+            .unwrap()
+        };
+        let locked = BTreeMap::from([(
+            "continuation".to_string(),
+            "circleci/continuation@1.0.0".to_string(),
+        )]);
+
+        let mut pinned = config("  continuation_orb_version: 1.1.0");
+        assert_eq!(
+            configured_orbs(&pinned)["continuation"],
+            "circleci/continuation@1.1.0"
+        );
+        let warnings = apply_lock(&mut pinned, &locked);
+        assert!(pinned.raw.get("orbs").is_none());
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+        assert!(warnings[0].contains("circleci/continuation@1.1.0 in setup_options"));
+
+        let mut api = config("  continuation: api");
+        assert!(configured_orbs(&api).is_empty());
+        assert!(apply_lock(&mut api, &locked).is_empty());
+        assert!(api.raw.get("orbs").is_none());
+    }
+}
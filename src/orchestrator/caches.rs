@@ -0,0 +1,332 @@
+//! Job `cache:` augmentation
+//!
+//! Each cache a job lists becomes a `restore_cache` step before its steps and
+//! a `save_cache` step after them. Keys come from the cache definition in the
+//! top-level `caches:` (see [`CacheDefinition::key`]), so CircleCI's
+//! `{{ arch }}` and `{{ checksum "..." }}` templates reach the provider as-is.
+//!
+//! Before a provider's plugin sees the jobs, their `save_cache` paths are put
+//! in the form that provider expects (see [`normalize_cache_path`]).
+//!
+//! `cigen generate --cache-nonce <NONCE>` prefixes every cache key in the jobs,
+//! so a run starts from empty dependency caches.
+
+use anyhow::{Context, Result, bail};
+use serde_yaml::Value;
+use std::collections::HashMap;
+
+use crate::schema::{
+    CacheDefinition, CigenConfig, RestoreCacheDefinition, SaveCacheDefinition, SaveWhen, Step,
+    normalize_cache_path, unknown_reference_message,
+};
+
+/// Wrap every job that lists caches in their restore and save steps
+pub fn augment_with_caches(config: &mut CigenConfig) -> Result<()> {
+    let definitions = &config.caches;
+    let version = config.cache_version;
+    for (job_id, job) in config.jobs.iter_mut() {
+        let mut restores = Vec::new();
+        let mut saves = Vec::new();
+        for cache in &job.cache {
+            let definition = match definitions.get(&cache.name) {
+                Some(definition) if cache.paths.is_empty() => definition.clone(),
+                Some(definition) => CacheDefinition {
+                    paths: cache.paths.clone(),
+                    ..definition.clone()
+                },
+                None if !cache.paths.is_empty() => CacheDefinition {
+                    paths: cache.paths.clone(),
+                    ..Default::default()
+                },
+                None => bail!(unknown_reference_message(
+                    &format!("Job '{job_id}' uses unknown cache '{}'", cache.name),
+                    &cache.name,
+                    definitions.keys().map(String::as_str),
+                )),
+            };
+            if definition.paths.is_empty() {
+                bail!("Cache '{}' used by job '{job_id}' has no paths", cache.name);
+            }
+
+            if cache.restore {
+                restores.push(restore_step(&cache.name, &definition, version));
+            }
+            if cache.save {
+                saves.push(save_step(
+                    &cache.name,
+                    &definition,
+                    version,
+                    cache.save_when,
+                ));
+            }
+        }
+
+        if restores.is_empty() && saves.is_empty() {
+            continue;
+        }
+        restores.append(&mut job.steps);
+        restores.append(&mut saves);
+        job.steps = restores;
+    }
+    Ok(())
+}
+
+/// Plan flag telling providers to leave out the job-status cache steps, from
+/// `cigen generate --no-job-status-cache`
+pub const NO_JOB_STATUS_CACHE_FLAG: &str = "no_job_status_cache";
+
+/// `nonce`, when it can start a cache key
+pub fn check_cache_nonce(nonce: &str) -> Result<()> {
+    if nonce.is_empty()
+        || !nonce
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        bail!(
+            "Invalid cache nonce '{nonce}': use letters, digits, '.', '_' and '-' (e.g. pipeline-123)"
+        );
+    }
+    Ok(())
+}
+
+/// Prefix the keys of every `restore_cache` and `save_cache` step in the jobs
+/// with `<nonce>-`. Restores miss every cache saved without the nonce, and
+/// saves don't collide with them.
+pub fn bust_caches(config: &mut CigenConfig, nonce: &str) {
+    let prefixed = |key: &mut String| *key = format!("{nonce}-{key}");
+    for job in config.jobs.values_mut() {
+        for step in &mut job.steps {
+            match step {
+                Step::RestoreCache { restore_cache, .. } => {
+                    restore_cache.key.iter_mut().for_each(prefixed);
+                    restore_cache.keys.iter_mut().for_each(prefixed);
+                    restore_cache.restore_keys.iter_mut().for_each(prefixed);
+                }
+                Step::SaveCache { save_cache, .. } => {
+                    save_cache.key.iter_mut().for_each(prefixed);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Providers whose cache steps cigen knows how to resolve paths for
+const CACHE_PATH_PROVIDERS: &[&str] = &["circleci", "github"];
+
+/// Put the paths of every `save_cache` step in the jobs in the form
+/// `provider`'s cache steps expect. Unknown providers get them as written.
+pub fn normalize_cache_paths(config: &mut CigenConfig, provider: &str) -> Result<()> {
+    if !CACHE_PATH_PROVIDERS.contains(&provider) {
+        return Ok(());
+    }
+    for (job_id, job) in config.jobs.iter_mut() {
+        let working_directory = job.working_directory.clone().unwrap_or_default();
+        for step in &mut job.steps {
+            let Step::SaveCache { save_cache, .. } = step else {
+                continue;
+            };
+            for path in &mut save_cache.paths {
+                *path = normalize_cache_path(path, &working_directory)
+                    .with_context(|| format!("Invalid cache path in job '{job_id}'"))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `restore_cache` step trying the exact key, then each restore key in turn
+pub(super) fn restore_step(name: &str, definition: &CacheDefinition, version: Option<u32>) -> Step {
+    let mut keys = vec![definition.key(name, version)];
+    keys.extend(definition.restore_keys(name, version));
+    Step::RestoreCache {
+        restore_cache: RestoreCacheDefinition {
+            name: Some(format!("Restore {name} cache")),
+            key: None,
+            keys,
+            restore_keys: Vec::new(),
+            extra: HashMap::new(),
+        },
+        condition: None,
+    }
+}
+
+/// `save_cache` step under the exact key
+pub(super) fn save_step(
+    name: &str,
+    definition: &CacheDefinition,
+    version: Option<u32>,
+    save_when: Option<SaveWhen>,
+) -> Step {
+    let mut extra = HashMap::new();
+    if let Some(when) = save_when {
+        extra.insert("when".to_string(), Value::String(when.as_str().into()));
+    }
+    Step::SaveCache {
+        save_cache: SaveCacheDefinition {
+            name: Some(format!("Save {name} cache")),
+            key: Some(definition.key(name, version)),
+            paths: definition.paths.clone(),
+            extra,
+        },
+        condition: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{Job, JobCache};
+
+    fn config(cache: Vec<JobCache>) -> CigenConfig {
+        let mut caches = HashMap::new();
+        caches.insert(
+            "gems".to_string(),
+            CacheDefinition {
+                paths: vec!["vendor/bundle".to_string()],
+                checksum_sources: vec!["Gemfile.lock".to_string(), ".ruby-version".to_string()],
+                restore_key_levels: 2,
+                ..Default::default()
+            },
+        );
+        let mut jobs = HashMap::new();
+        jobs.insert(
+            "rspec".to_string(),
+            Job {
+                cache,
+                steps: vec![Step::SimpleRun {
+                    run: "bundle exec rspec".to_string(),
+                    condition: None,
+                }],
+                ..Default::default()
+            },
+        );
+        CigenConfig {
+            jobs,
+            caches,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_restore_and_save_wrap_job_steps() {
+        let mut config = config(vec![JobCache::named("gems")]);
+        augment_with_caches(&mut config).unwrap();
+
+        let steps = &config.jobs["rspec"].steps;
+        assert_eq!(steps.len(), 3);
+        let Step::RestoreCache { restore_cache, .. } = &steps[0] else {
+            panic!("expected restore_cache first, got {:?}", steps[0]);
+        };
+        assert_eq!(
+            restore_cache.keys,
+            vec![
+                r#"gems-{{ arch }}-{{ checksum "Gemfile.lock" }}-{{ checksum ".ruby-version" }}"#,
+                r#"gems-{{ arch }}-{{ checksum "Gemfile.lock" }}-"#,
+                "gems-{{ arch }}-",
+            ]
+        );
+        let Step::SaveCache { save_cache, .. } = &steps[2] else {
+            panic!("expected save_cache last, got {:?}", steps[2]);
+        };
+        assert_eq!(
+            save_cache.key.as_deref(),
+            Some(restore_cache.keys[0].as_str())
+        );
+        assert_eq!(save_cache.paths, vec!["vendor/bundle"]);
+    }
+
+    #[test]
+    fn test_save_when_sets_save_step_when() {
+        for when in [SaveWhen::Always, SaveWhen::OnSuccess, SaveWhen::OnFailure] {
+            let mut config = config(vec![JobCache {
+                save_when: Some(when),
+                ..JobCache::named("gems")
+            }]);
+            augment_with_caches(&mut config).unwrap();
+
+            let Some(Step::SaveCache { save_cache, .. }) = config.jobs["rspec"].steps.last() else {
+                panic!("expected save_cache last");
+            };
+            assert_eq!(
+                save_cache.extra.get("when"),
+                Some(&Value::String(when.as_str().into()))
+            );
+        }
+
+        let mut config = config(vec![JobCache::named("gems")]);
+        augment_with_caches(&mut config).unwrap();
+        let Some(Step::SaveCache { save_cache, .. }) = config.jobs["rspec"].steps.last() else {
+            panic!("expected save_cache last");
+        };
+        assert!(!save_cache.extra.contains_key("when"));
+    }
+
+    #[test]
+    fn test_cache_nonce_prefixes_every_key() {
+        let mut config = config(vec![JobCache::named("gems")]);
+        augment_with_caches(&mut config).unwrap();
+        bust_caches(&mut config, "pipeline-42");
+
+        let steps = &config.jobs["rspec"].steps;
+        let Step::RestoreCache { restore_cache, .. } = &steps[0] else {
+            panic!("expected restore_cache first, got {:?}", steps[0]);
+        };
+        assert!(
+            restore_cache
+                .keys
+                .iter()
+                .all(|key| key.starts_with("pipeline-42-gems-{{ arch }}-")),
+            "{:?}",
+            restore_cache.keys
+        );
+        let Step::SaveCache { save_cache, .. } = &steps[2] else {
+            panic!("expected save_cache last, got {:?}", steps[2]);
+        };
+        assert_eq!(
+            save_cache.key.as_deref(),
+            Some(restore_cache.keys[0].as_str())
+        );
+
+        assert!(check_cache_nonce("pipeline-42").is_ok());
+        for invalid in ["", "has space", "{{ epoch }}"] {
+            assert!(check_cache_nonce(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_cache_paths_follow_the_provider() {
+        let mut circleci = config(vec![JobCache {
+            paths: vec!["vendor/bundle".to_string(), "$HOME/.bundle".to_string()],
+            ..JobCache::named("gems")
+        }]);
+        circleci.jobs.get_mut("rspec").unwrap().working_directory = Some("~/app".to_string());
+        augment_with_caches(&mut circleci).unwrap();
+        normalize_cache_paths(&mut circleci, "circleci").unwrap();
+
+        let Some(Step::SaveCache { save_cache, .. }) = circleci.jobs["rspec"].steps.last() else {
+            panic!("expected save_cache last");
+        };
+        assert_eq!(save_cache.paths, ["~/app/vendor/bundle", "~/.bundle"]);
+
+        let mut github = config(vec![JobCache {
+            paths: vec!["../vendor".to_string()],
+            ..JobCache::named("gems")
+        }]);
+        augment_with_caches(&mut github).unwrap();
+        let error = normalize_cache_paths(&mut github, "github").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid cache path in job 'rspec'");
+        assert!(
+            format!("{error:#}").contains("'../vendor' leaves"),
+            "{error:#}"
+        );
+    }
+
+    #[test]
+    fn test_unknown_cache_without_paths_is_an_error() {
+        let mut config = config(vec![JobCache::named("gemz")]);
+        let error = augment_with_caches(&mut config).unwrap_err().to_string();
+        assert!(error.contains("unknown cache 'gemz'"), "{error}");
+        assert!(error.contains("gems"), "{error}");
+    }
+}
@@ -1,25 +1,33 @@
+use anyhow::{Context, Result};
 use std::collections::HashMap;
 
 use crate::plugin::protocol::{
-    self, CacheDefinition, CigenSchema, CommandDefinition as ProtoCommandDefinition,
-    CommandParameter as ProtoCommandParameter, CustomStep, JobDefinition, MatrixRow, MatrixValue,
-    PackageSpec as ProtoPackageSpec, ProjectConfig, RestoreCacheStep, RunStep, RunnerDefinition,
-    SaveCacheStep, SkipConfig, Step, StringList, UsesStep,
-    WorkflowConditionKind as ProtoWorkflowConditionKind, WorkflowDefinition,
+    self, AwsAuth, CacheDefinition, CigenSchema, CloudAuth,
+    CommandDefinition as ProtoCommandDefinition, CommandParameter as ProtoCommandParameter,
+    CustomStep, Executor, GcpAuth, JobDefinition, MatrixRow, MatrixValue,
+    PackageSpec as ProtoPackageSpec, ProjectConfig, RemoteDocker, RestoreCacheStep, RunStep,
+    RunnerDefinition, SaveCacheStep, SkipConfig, SlackNotification, Step, StringList,
+    TestSplitting, UsesStep, WorkflowConditionKind as ProtoWorkflowConditionKind,
+    WorkflowDefinition,
 };
-use crate::schema::{self, JobMatrix};
+use crate::schema::{self, JobExecutor, JobMatrix};
 use serde_yaml::Value;
 
-/// Convert schema::CigenConfig to protobuf CigenSchema
+/// Convert schema::CigenConfig to protobuf CigenSchema. Jobs and workflows are
+/// sorted by id so plugins see them, and generate output, in a stable order.
 pub fn config_to_proto(config: &schema::CigenConfig) -> CigenSchema {
+    let mut jobs: Vec<_> = config.jobs.iter().collect();
+    jobs.sort_by_key(|(id, _)| *id);
+    let mut workflows: Vec<_> = config.workflows.iter().collect();
+    workflows.sort_by_key(|(id, _)| *id);
+
     CigenSchema {
         version: "1".to_string(),
         project: config.project.as_ref().map(project_to_proto),
         variables: HashMap::new(), // TODO: Add variable support
-        jobs: config
-            .jobs
-            .iter()
-            .map(|(id, job)| job_to_proto(id, job))
+        jobs: jobs
+            .into_iter()
+            .map(|(id, job)| job_to_proto(id, job, workflow_job_steps(config, id, job)))
             .collect(),
         caches: config
             .caches
@@ -32,10 +40,9 @@ pub fn config_to_proto(config: &schema::CigenConfig) -> CigenSchema {
             .map(|(id, runner)| (id.clone(), runner_to_proto(runner)))
             .collect(),
         outputs: vec![], // Outputs are generated by plugins
-        workflows: config
-            .workflows
-            .iter()
-            .map(|(id, workflow)| workflow_to_proto(id, workflow))
+        workflows: workflows
+            .into_iter()
+            .map(|(id, workflow)| workflow_to_proto(id, workflow, config.notifications.as_ref()))
             .collect(),
         source_file_groups: config
             .source_file_groups
@@ -60,9 +67,24 @@ pub fn config_to_proto(config: &schema::CigenConfig) -> CigenSchema {
             .map(|(id, value)| (id.clone(), serialize_value(value)))
             .collect(),
         raw_config_yaml: serialize_value(&Value::Mapping(config.raw.clone())),
+        env: config.env.clone(),
+        cache_version: config.cache_version.unwrap_or_default(),
     }
 }
 
+/// `pre_steps`/`post_steps` the job's workflow adds around it
+fn workflow_job_steps<'a>(
+    config: &'a schema::CigenConfig,
+    id: &str,
+    job: &schema::Job,
+) -> Option<&'a schema::WorkflowJobSteps> {
+    config
+        .workflows
+        .get(job.workflow.as_deref()?)?
+        .job_steps
+        .get(id)
+}
+
 fn project_to_proto(project: &schema::ProjectConfig) -> ProjectConfig {
     ProjectConfig {
         name: project.name.clone(),
@@ -100,7 +122,11 @@ fn command_parameter_to_proto(parameter: &schema::CommandParameter) -> ProtoComm
     }
 }
 
-fn job_to_proto(id: &str, job: &schema::Job) -> JobDefinition {
+fn job_to_proto(
+    id: &str,
+    job: &schema::Job,
+    workflow_steps: Option<&schema::WorkflowJobSteps>,
+) -> JobDefinition {
     let (matrix_dimensions_map, matrix_rows_vec) = match &job.matrix {
         Some(JobMatrix::Dimensions(dims)) => (
             dims.iter()
@@ -133,6 +159,11 @@ fn job_to_proto(id: &str, job: &schema::Job) -> JobDefinition {
         matrix_rows: matrix_rows_vec,
         packages: job.packages.iter().map(|pkg| pkg.name.clone()).collect(),
         steps: job.steps.iter().map(step_to_proto).collect(),
+        cleanup: job.cleanup.iter().map(step_to_proto).collect(),
+        cleanup_on: match job.cleanup_on.unwrap_or_default() {
+            schema::CleanupOn::Always => "always".to_string(),
+            schema::CleanupOn::Failure => "failure".to_string(),
+        },
         skip_if: job.skip_if.as_ref().map(skip_config_to_proto),
         runner: job.runner.clone().unwrap_or_default(),
         env: job.environment.clone(),
@@ -153,22 +184,125 @@ fn job_to_proto(id: &str, job: &schema::Job) -> JobDefinition {
             .iter()
             .map(|(key, value)| (key.clone(), serialize_value(value)))
             .collect(),
+        provider_overrides: job
+            .provider_overrides
+            .iter()
+            .map(|(provider, overrides)| {
+                (
+                    provider.clone(),
+                    serialize_value(&Value::Mapping(overrides.clone())),
+                )
+            })
+            .collect(),
         source_files: job.source_files.clone(),
+        source_submodules: job.source_submodules.clone(),
+        shard: job.shard.unwrap_or_default(),
+        matrix_job: job.matrix_job.clone().unwrap_or_default(),
+        matrix_values: job.matrix_values.clone(),
+        source_file: job
+            .source_file
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default(),
+        source_section: job.source_section.clone().unwrap_or_default(),
         package_specs: job.packages.iter().map(package_to_proto).collect(),
         services: job.services.clone(),
         stage: job.stage.clone().unwrap_or_default(),
+        test_results: job.test_results.clone().unwrap_or_default(),
+        architecture: job.architecture.clone().unwrap_or_default(),
+        remote_docker: job.remote_docker.as_ref().map(|remote| RemoteDocker {
+            version: remote.version.clone().unwrap_or_default(),
+            layer_caching: remote.layer_caching,
+        }),
+        working_directory: job.working_directory.clone().unwrap_or_default(),
+        executor: job.executor.as_ref().and_then(executor_to_proto),
+        test_splitting: job.test_splitting.as_ref().map(|splitting| TestSplitting {
+            glob: splitting.glob.clone(),
+            by: splitting.by.as_str().to_string(),
+            command_template: splitting.command_template.clone(),
+        }),
+        cloud_auth: job.cloud_auth.as_ref().map(cloud_auth_to_proto),
+        branches: job.branches.clone(),
+        unless: job.unless.clone().unwrap_or_default(),
+        pre_steps: workflow_steps
+            .map(|steps| steps.pre_steps.iter().map(step_to_proto).collect())
+            .unwrap_or_default(),
+        post_steps: workflow_steps
+            .map(|steps| steps.post_steps.iter().map(step_to_proto).collect())
+            .unwrap_or_default(),
+    }
+}
+
+fn cloud_auth_to_proto(cloud_auth: &schema::CloudAuth) -> CloudAuth {
+    CloudAuth {
+        aws: cloud_auth.aws.as_ref().map(|aws| AwsAuth {
+            role_arn: aws.role_arn.clone(),
+            region: aws.region.clone().unwrap_or_default(),
+        }),
+        gcp: cloud_auth.gcp.as_ref().map(|gcp| GcpAuth {
+            workload_identity_provider: gcp.workload_identity_provider.clone(),
+            service_account: gcp.service_account.clone(),
+            project_id: gcp.project_id.clone().unwrap_or_default(),
+        }),
+    }
+}
+
+fn executor_to_proto(executor: &JobExecutor) -> Option<Executor> {
+    match executor {
+        JobExecutor::Docker => None,
+        JobExecutor::Machine(machine) => Some(Executor {
+            kind: executor.kind().to_string(),
+            image: machine.image.clone().unwrap_or_default(),
+            ..Default::default()
+        }),
+        JobExecutor::Macos(macos) => Some(Executor {
+            kind: executor.kind().to_string(),
+            xcode: macos.xcode.clone(),
+            ..Default::default()
+        }),
+        JobExecutor::Named(name) => Some(Executor {
+            kind: executor.kind().to_string(),
+            name: name.clone(),
+            ..Default::default()
+        }),
     }
 }
 
-fn workflow_to_proto(id: &str, workflow: &schema::WorkflowConfig) -> WorkflowDefinition {
+/// `notifications` is the top-level setting, which the workflow's own replaces
+fn workflow_to_proto(
+    id: &str,
+    workflow: &schema::WorkflowConfig,
+    notifications: Option<&schema::Notifications>,
+) -> WorkflowDefinition {
+    let slack = workflow
+        .notifications
+        .as_ref()
+        .or(notifications)
+        .and_then(|notifications| notifications.slack.as_ref());
     WorkflowDefinition {
         id: id.to_string(),
-        yaml: serialize_value(&workflow.raw),
+        yaml: serialize_value(&workflow.provider_metadata()),
         run_when: workflow
             .run_when
             .iter()
             .map(workflow_condition_to_proto)
             .collect(),
+        run_unless: workflow
+            .run_unless
+            .iter()
+            .map(workflow_condition_to_proto)
+            .collect(),
+        env: workflow.env.clone(),
+        slack: slack.map(|slack| SlackNotification {
+            channel: slack.channel.clone(),
+            events: slack
+                .events
+                .iter()
+                .map(|event| event.as_str().to_string())
+                .collect(),
+            context: slack.context.clone().unwrap_or_default(),
+            webhook_secret: slack.webhook_secret.clone(),
+        }),
     }
 }
 
@@ -209,21 +343,22 @@ fn package_to_proto(package: &schema::PackageSpec) -> ProtoPackageSpec {
 }
 
 fn step_to_proto(step: &schema::Step) -> Step {
+    let condition = step.condition().unwrap_or_default().to_string();
     match step {
-        schema::Step::SimpleRun { run } => Step {
+        schema::Step::SimpleRun { run, .. } => Step {
             step_type: Some(protocol::step::StepType::Run(RunStep {
                 name: String::new(),
                 command: run.clone(),
                 env: HashMap::new(),
-                r#if: String::new(),
+                r#if: condition,
             })),
         },
-        schema::Step::RunWithOptions { run } => Step {
+        schema::Step::RunWithOptions { run, .. } => Step {
             step_type: Some(protocol::step::StepType::Run(RunStep {
                 name: run.name.clone().unwrap_or_default(),
                 command: run.command.clone(),
                 env: run.env.clone(),
-                r#if: run.condition.clone().unwrap_or_default(),
+                r#if: condition,
             })),
         },
         schema::Step::Uses(uses) => Step {
@@ -235,10 +370,10 @@ fn step_to_proto(step: &schema::Step) -> Step {
                     .iter()
                     .map(|(k, v)| (k.clone(), serialize_value(v)))
                     .collect(),
-                r#if: uses.condition.clone().unwrap_or_default(),
+                r#if: condition,
             })),
         },
-        schema::Step::RestoreCache { restore_cache } => {
+        schema::Step::RestoreCache { restore_cache, .. } => {
             let key = restore_cache
                 .key
                 .clone()
@@ -255,10 +390,11 @@ fn step_to_proto(step: &schema::Step) -> Step {
                         .iter()
                         .map(|(k, v)| (k.clone(), serialize_value(v)))
                         .collect(),
+                    r#if: condition,
                 })),
             }
         }
-        schema::Step::SaveCache { save_cache } => Step {
+        schema::Step::SaveCache { save_cache, .. } => Step {
             step_type: Some(protocol::step::StepType::SaveCache(SaveCacheStep {
                 name: save_cache.name.clone().unwrap_or_default(),
                 key: save_cache.key.clone().unwrap_or_default(),
@@ -268,20 +404,35 @@ fn step_to_proto(step: &schema::Step) -> Step {
                     .iter()
                     .map(|(k, v)| (k.clone(), serialize_value(v)))
                     .collect(),
+                r#if: condition,
             })),
         },
         schema::Step::Custom(value) => {
-            let kind = step_kind(value);
+            let mut value = value.clone();
+            if !condition.is_empty()
+                && let Value::Mapping(map) = &mut value
+            {
+                map.remove("if");
+            }
+            let kind = step_kind(&value);
             Step {
                 step_type: Some(protocol::step::StepType::Custom(CustomStep {
                     kind,
-                    yaml: serialize_value(value),
+                    yaml: serialize_value(&value),
+                    r#if: condition,
                 })),
             }
         }
     }
 }
 
+/// Steps passed as the argument to a command's `steps` parameter
+pub fn step_list_to_proto(value: &Value) -> Result<Vec<Step>> {
+    let steps: Vec<schema::Step> =
+        serde_yaml::from_value(value.clone()).context("Expected a list of steps")?;
+    Ok(steps.iter().map(step_to_proto).collect())
+}
+
 fn step_kind(value: &Value) -> String {
     match value {
         Value::Mapping(map) => map
@@ -308,6 +459,8 @@ fn cache_to_proto(cache: &schema::CacheDefinition) -> CacheDefinition {
         paths: cache.paths.clone(),
         key_parts: cache.key_parts.clone(),
         backend: format!("{:?}", cache.backend).to_lowercase(),
+        checksum_sources: cache.checksum_sources.clone(),
+        restore_key_levels: cache.restore_key_levels as u32,
     }
 }
 
@@ -356,39 +509,18 @@ mod tests {
         jobs.insert(
             "test".to_string(),
             schema::Job {
-                needs: vec![],
-                matrix: None, // Updated for Option<JobMatrix>
                 packages: vec![schema::PackageSpec::from_name("ruby".to_string())],
-                services: vec![],
-                environment: HashMap::new(),
-                checkout: None,
                 steps: vec![schema::Step::SimpleRun {
                     run: "bundle exec rspec".to_string(),
+                    condition: None,
                 }],
-                source_files: vec![],
-                skip_if: None,
-                trigger: None,
-                image: "ubuntu-latest".to_string(),
-                runner: None,
-                artifacts: vec![],
-                extra: HashMap::new(),
-                workflow: None,
-                stage: None,
+                ..Default::default()
             },
         );
 
         schema::CigenConfig {
-            project: None,
-            providers: vec![],
-            packages: vec![],
-            source_file_groups: HashMap::new(),
             jobs,
-            commands: HashMap::new(),
-            caches: HashMap::new(),
-            runners: HashMap::new(),
-            provider_config: HashMap::new(),
-            workflows: HashMap::new(),
-            raw: Default::default(),
+            ..Default::default()
         }
     }
 
@@ -409,6 +541,7 @@ mod tests {
     fn test_step_conversion() {
         let simple_run = schema::Step::SimpleRun {
             run: "echo hello".to_string(),
+            condition: None,
         };
 
         let proto = step_to_proto(&simple_run);
@@ -3,7 +3,13 @@ use petgraph::algo::{is_cyclic_directed, toposort};
 use petgraph::graph::{DiGraph, NodeIndex};
 use std::collections::{HashMap, HashSet};
 
-use crate::schema::{CigenConfig, Job, JobMatrix, WorkflowConfig};
+use crate::plugin::diagnostics::{LocatedError, locate_in};
+use crate::schema::{
+    CigenConfig, DEFAULT_WORKFLOW, Job, JobMatrix, WorkflowConfig, split_need,
+    unknown_reference_help,
+};
+
+use super::job_names::{check_collisions, provider_job_name};
 
 /// A concrete job instance after matrix expansion
 #[derive(Debug, Clone, PartialEq)]
@@ -16,10 +22,37 @@ pub struct ConcreteJob {
     pub stage: String,
     /// Matrix variable values for this instance (empty for non-matrix jobs)
     pub matrix_values: HashMap<String, String>,
+    /// Name the job would have without a matrix (None for non-matrix jobs)
+    pub matrix_job: Option<String>,
     /// The job definition
     pub job: Job,
 }
 
+impl ConcreteJob {
+    /// Architecture for this instance, from an `arch`/`architecture` matrix value or the job itself
+    pub fn architecture(&self) -> Option<&str> {
+        self.matrix_values
+            .get("arch")
+            .or_else(|| self.matrix_values.get("architecture"))
+            .map(String::as_str)
+            .or(self.job.architecture.as_deref())
+    }
+
+    /// The job this instance was expanded from, with its matrix values
+    fn source(&self) -> String {
+        if self.matrix_values.is_empty() {
+            return self.job_id.clone();
+        }
+        let mut values: Vec<String> = self
+            .matrix_values
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        values.sort();
+        format!("{}[{}]", self.job_id, values.join(", "))
+    }
+}
+
 /// DAG builder and manager for cigen jobs
 #[derive(Debug)]
 pub struct JobDAG {
@@ -39,21 +72,27 @@ impl JobDAG {
         let mut jobs = HashMap::new();
 
         // 1. Expand matrix jobs into concrete instances
+        let mut instances = Vec::new();
         for (job_id, job) in &config.jobs {
             // Find workflow config
-            let workflow_name = job.workflow.as_deref().unwrap_or("main");
+            let workflow_name = job.workflow.as_deref().unwrap_or(DEFAULT_WORKFLOW);
             let workflow_config = config.workflows.get(workflow_name);
             let default_config = WorkflowConfig::default();
             let wf_config = workflow_config.unwrap_or(&default_config);
 
-            let instances = expand_matrix_job(job_id, job, wf_config)?;
-
-            for instance in instances {
-                let instance_id = instance.instance_id.clone();
-                let node = graph.add_node(instance_id.clone());
-                node_map.insert(instance_id.clone(), node);
-                jobs.insert(instance_id, instance);
-            }
+            instances.extend(expand_matrix_job(job_id, job, wf_config)?);
+        }
+        instances.sort_by(|a, b| a.instance_id.cmp(&b.instance_id));
+        check_collisions(
+            instances
+                .iter()
+                .map(|instance| (instance.instance_id.as_str(), instance.source())),
+        )?;
+        for instance in instances {
+            let instance_id = instance.instance_id.clone();
+            let node = graph.add_node(instance_id.clone());
+            node_map.insert(instance_id.clone(), node);
+            jobs.insert(instance_id, instance);
         }
 
         // 2. Resolve Dependencies and Update Jobs
@@ -66,7 +105,11 @@ impl JobDAG {
             // We need to clone the job to read it, then we'll update it back
             let mut concrete_job = jobs.get(&instance_id).unwrap().clone();
 
-            let workflow_name = concrete_job.job.workflow.as_deref().unwrap_or("main");
+            let workflow_name = concrete_job
+                .job
+                .workflow
+                .as_deref()
+                .unwrap_or(DEFAULT_WORKFLOW);
             let default_config = WorkflowConfig::default();
             let wf_config = config
                 .workflows
@@ -76,41 +119,78 @@ impl JobDAG {
             let mut new_needs = HashSet::new();
 
             // 2a. Explicit Dependencies
-            for needed_job_id in &concrete_job.job.needs {
-                let mut found_match = false;
+            for need in &concrete_job.job.needs {
+                let (needed_job_id, selected_arch) = split_need(need);
+                let mut matches = Vec::new();
                 for (candidate_id, candidate) in &jobs {
-                    // Match exact instance ID
-                    if candidate_id == needed_job_id {
-                        new_needs.insert(candidate_id.clone());
-                        graph.update_edge(node_map[candidate_id], dependent_node, ());
-                        found_match = true;
+                    // Match exact instance ID, as written or as generated
+                    if candidate_id == needed_job_id
+                        || *candidate_id == provider_job_name(needed_job_id)
+                    {
+                        matches.push(candidate);
                         continue;
                     }
 
                     // Match base job ID (scoped to same stage if prefixing active)
-                    if &candidate.job_id == needed_job_id {
+                    if candidate.job_id == needed_job_id {
                         if should_prefix(&concrete_job.stage, wf_config)
                             && should_prefix(&candidate.stage, wf_config)
                         {
                             if concrete_job.stage == candidate.stage {
-                                new_needs.insert(candidate_id.clone());
-                                graph.update_edge(node_map[candidate_id], dependent_node, ());
-                                found_match = true;
+                                matches.push(candidate);
                             }
                         } else {
                             // Global match (e.g. no stage prefixing)
-                            new_needs.insert(candidate_id.clone());
-                            graph.update_edge(node_map[candidate_id], dependent_node, ());
-                            found_match = true;
+                            matches.push(candidate);
                         }
                     }
                 }
-                if !found_match {
-                    bail!(
-                        "Job '{}' depends on '{}', but no matching job instance exists",
-                        instance_id,
-                        needed_job_id
-                    );
+                if matches.is_empty() {
+                    let names = jobs
+                        .values()
+                        .filter(|candidate| candidate.job_id != concrete_job.job_id)
+                        .flat_map(|candidate| [&candidate.instance_id, &candidate.job_id])
+                        .map(String::as_str);
+                    return Err(unknown_need_error(
+                        &concrete_job.job,
+                        format!(
+                            "Job '{instance_id}' depends on '{needed_job_id}', but no matching job instance exists"
+                        ),
+                        need,
+                        unknown_reference_help(needed_job_id, names),
+                    ));
+                }
+
+                let matches = match selected_arch {
+                    Some(arch) => {
+                        let selected: Vec<_> = matches
+                            .iter()
+                            .copied()
+                            .filter(|candidate| candidate.architecture() == Some(arch))
+                            .collect();
+                        if selected.is_empty() {
+                            let mut available: Vec<&str> = matches
+                                .iter()
+                                .filter_map(|candidate| candidate.architecture())
+                                .collect();
+                            available.sort();
+                            available.dedup();
+                            bail!(
+                                "Job '{instance_id}' depends on '{need}', but '{needed_job_id}' has no {arch} variant{}",
+                                if available.is_empty() {
+                                    String::new()
+                                } else {
+                                    format!(" (it has {})", available.join(", "))
+                                }
+                            );
+                        }
+                        selected
+                    }
+                    None => same_architecture(matches, concrete_job.architecture()),
+                };
+                for candidate in matches {
+                    new_needs.insert(candidate.instance_id.clone());
+                    graph.update_edge(node_map[&candidate.instance_id], dependent_node, ());
                 }
             }
 
@@ -255,26 +335,24 @@ fn expand_matrix_job(
     // Sanitize base name (replace / with _ just in case, though split should handle it)
     let base_clean_name = base_short_name.replace('/', "_");
 
+    let unexpanded_id = provider_job_name(&if should_prefix(&default_stage, wf_config) {
+        format!(
+            "{}{}{}",
+            default_stage, wf_config.stage_prefix_separator, base_clean_name
+        )
+    } else {
+        base_clean_name.clone()
+    });
+
     match &job.matrix {
-        None => {
-            let stage = default_stage;
-            let instance_id = if should_prefix(&stage, wf_config) {
-                format!(
-                    "{}{}{}",
-                    stage, wf_config.stage_prefix_separator, base_clean_name
-                )
-            } else {
-                base_clean_name.clone()
-            };
-
-            Ok(vec![ConcreteJob {
-                job_id: job_id.to_string(),
-                instance_id,
-                stage,
-                matrix_values: HashMap::new(),
-                job: job.clone(),
-            }])
-        }
+        None => Ok(vec![ConcreteJob {
+            job_id: job_id.to_string(),
+            instance_id: unexpanded_id,
+            stage: default_stage,
+            matrix_values: HashMap::new(),
+            matrix_job: None,
+            job: job.clone(),
+        }]),
         Some(JobMatrix::Explicit(rows)) => {
             let mut instances = Vec::new();
             for row in rows {
@@ -320,14 +398,14 @@ fn expand_matrix_job(
                     }
                 };
 
-                let instance_id = if should_prefix(&stage, wf_config) {
+                let instance_id = provider_job_name(&if should_prefix(&stage, wf_config) {
                     format!(
                         "{}{}{}{}",
                         stage, wf_config.stage_prefix_separator, base_name_for_instance, suffix
                     )
                 } else {
                     format!("{}{}", base_name_for_instance, suffix)
-                };
+                });
 
                 let mut substituted_job = job.clone();
                 for need in substituted_job.needs.iter_mut() {
@@ -339,6 +417,7 @@ fn expand_matrix_job(
                     instance_id,
                     stage,
                     matrix_values: row.clone(),
+                    matrix_job: Some(unexpanded_id.clone()),
                     job: substituted_job,
                 });
             }
@@ -392,14 +471,14 @@ fn expand_matrix_job(
                     }
                 };
 
-                let instance_id = if should_prefix(&stage, wf_config) {
+                let instance_id = provider_job_name(&if should_prefix(&stage, wf_config) {
                     format!(
                         "{}{}{}{}",
                         stage, wf_config.stage_prefix_separator, base_name_for_instance, suffix
                     )
                 } else {
                     format!("{}{}", base_name_for_instance, suffix)
-                };
+                });
 
                 let mut substituted_job = job.clone();
                 for need in substituted_job.needs.iter_mut() {
@@ -411,6 +490,7 @@ fn expand_matrix_job(
                     instance_id,
                     stage,
                     matrix_values,
+                    matrix_job: Some(unexpanded_id.clone()),
                     job: substituted_job,
                 });
             }
@@ -420,6 +500,59 @@ fn expand_matrix_job(
     }
 }
 
+/// `message` about the unknown `need` of `job`, pointing at it in the job's
+/// file when it has one, with `help` below
+fn unknown_need_error(
+    job: &Job,
+    message: String,
+    need: &str,
+    help: Option<String>,
+) -> anyhow::Error {
+    let location = job.source_file.as_ref().and_then(|path| {
+        locate_in(
+            &path.to_string_lossy(),
+            job.source_section.as_deref().unwrap_or_default(),
+            need,
+        )
+    });
+    match location {
+        Some(location) => LocatedError {
+            message,
+            location,
+            help,
+        }
+        .into(),
+        None => match help {
+            Some(help) => anyhow::anyhow!("{message}\n{help}"),
+            None => anyhow::anyhow!(message),
+        },
+    }
+}
+
+/// The variants of a needed job that a dependent on `arch` requires: the ones
+/// on the same architecture, when there are any. Dependents without an
+/// architecture, or on one the needed job doesn't build for, require every
+/// variant.
+fn same_architecture<'a>(
+    candidates: Vec<&'a ConcreteJob>,
+    arch: Option<&str>,
+) -> Vec<&'a ConcreteJob> {
+    let Some(arch) = arch else {
+        return candidates;
+    };
+    if candidates
+        .iter()
+        .any(|candidate| candidate.architecture() == Some(arch))
+    {
+        candidates
+            .into_iter()
+            .filter(|candidate| candidate.architecture() == Some(arch))
+            .collect()
+    } else {
+        candidates
+    }
+}
+
 fn should_prefix(stage: &str, config: &WorkflowConfig) -> bool {
     if stage == "default" {
         config.default_stage_prefix
@@ -464,24 +597,7 @@ mod tests {
     use super::*;
 
     fn create_simple_job() -> Job {
-        Job {
-            needs: vec![],
-            matrix: None, // Updated for Option<JobMatrix>
-            packages: vec![],
-            services: vec![],
-            environment: HashMap::new(),
-            checkout: None,
-            steps: vec![],
-            source_files: vec![],
-            skip_if: None,
-            trigger: None,
-            image: "ubuntu-latest".to_string(),
-            runner: None,
-            artifacts: vec![],
-            extra: HashMap::new(),
-            workflow: None,
-            stage: None,
-        }
+        Job::default()
     }
 
     #[test]
@@ -490,17 +606,8 @@ mod tests {
         jobs.insert("test".to_string(), create_simple_job());
 
         let config = CigenConfig {
-            project: None,
-            providers: vec![],
-            packages: vec![],
-            source_file_groups: HashMap::new(),
             jobs,
-            commands: HashMap::new(),
-            caches: HashMap::new(),
-            runners: HashMap::new(),
-            provider_config: HashMap::new(),
-            workflows: HashMap::new(),
-            raw: Default::default(),
+            ..Default::default()
         };
 
         let dag = JobDAG::build(&config).unwrap();
@@ -523,17 +630,8 @@ mod tests {
         jobs.insert("deploy".to_string(), deploy);
 
         let config = CigenConfig {
-            project: None,
-            providers: vec![],
-            packages: vec![],
-            source_file_groups: HashMap::new(),
             jobs,
-            commands: HashMap::new(),
-            caches: HashMap::new(),
-            runners: HashMap::new(),
-            provider_config: HashMap::new(),
-            workflows: HashMap::new(),
-            raw: Default::default(),
+            ..Default::default()
         };
 
         let dag = JobDAG::build(&config).unwrap();
@@ -565,13 +663,13 @@ mod tests {
         let instances = expand_matrix_job("test", &job, &WorkflowConfig::default()).unwrap();
         assert_eq!(instances.len(), 4);
 
-        // Check instance IDs are unique and follow pattern
+        // Check instance IDs are unique and follow pattern, with dots made safe
         // Note: dimensions are sorted alphabetically (arch before ruby)
         let ids: Vec<_> = instances.iter().map(|i| &i.instance_id).collect();
-        assert!(ids.contains(&&"test-amd64-3.2".to_string()));
-        assert!(ids.contains(&&"test-amd64-3.3".to_string()));
-        assert!(ids.contains(&&"test-arm64-3.2".to_string()));
-        assert!(ids.contains(&&"test-arm64-3.3".to_string()));
+        assert!(ids.contains(&&"test-amd64-3_2".to_string()));
+        assert!(ids.contains(&&"test-amd64-3_3".to_string()));
+        assert!(ids.contains(&&"test-arm64-3_2".to_string()));
+        assert!(ids.contains(&&"test-arm64-3_3".to_string()));
     }
 
     #[test]
@@ -605,32 +703,153 @@ mod tests {
         jobs.insert("test".to_string(), test);
 
         let config = CigenConfig {
-            project: None,
-            providers: vec![],
-            packages: vec![],
-            source_file_groups: HashMap::new(),
             jobs,
-            commands: HashMap::new(),
-            caches: HashMap::new(),
-            runners: HashMap::new(),
-            provider_config: HashMap::new(),
-            workflows: HashMap::new(),
-            raw: Default::default(),
+            ..Default::default()
         };
 
         let dag = JobDAG::build(&config).unwrap();
 
-        // Should have 3 instances: setup, test-3.2, test-3.3
+        // Should have 3 instances: setup, test-3_2, test-3_3
         assert_eq!(dag.jobs().len(), 3);
 
         // Both test instances should depend on setup
-        let test_32_deps = dag.get_dependencies("test-3.2");
-        let test_33_deps = dag.get_dependencies("test-3.3");
+        let test_32_deps = dag.get_dependencies("test-3_2");
+        let test_33_deps = dag.get_dependencies("test-3_3");
 
         assert_eq!(test_32_deps, vec!["setup"]);
         assert_eq!(test_33_deps, vec!["setup"]);
     }
 
+    #[test]
+    fn test_job_names_are_made_provider_safe() {
+        let mut lint = create_simple_job();
+        lint.matrix = Some(JobMatrix::Dimensions(HashMap::from([(
+            "node".to_string(),
+            vec!["20.11".to_string()],
+        )])));
+        let mut deploy = create_simple_job();
+        deploy.needs = vec!["lint-20.11".to_string(), "Build.Assets".to_string()];
+
+        let config = CigenConfig {
+            jobs: HashMap::from([
+                ("lint".to_string(), lint),
+                ("release/Build.Assets".to_string(), create_simple_job()),
+                ("deploy".to_string(), deploy),
+            ]),
+            ..Default::default()
+        };
+        let dag = JobDAG::build(&config).unwrap();
+        let mut ids: Vec<_> = dag.jobs().keys().cloned().collect();
+        ids.sort();
+        assert_eq!(ids, ["Build_Assets", "deploy", "lint-20_11"]);
+        assert_eq!(
+            dag.get_job("deploy").unwrap().job.needs,
+            ["Build_Assets", "lint-20_11"]
+        );
+    }
+
+    #[test]
+    fn test_colliding_job_names_are_rejected() {
+        let config = CigenConfig {
+            jobs: HashMap::from([
+                ("ci/test".to_string(), create_simple_job()),
+                ("nightly/test".to_string(), create_simple_job()),
+                ("lint.js".to_string(), create_simple_job()),
+                ("Lint_js".to_string(), create_simple_job()),
+            ]),
+            ..Default::default()
+        };
+        let error = JobDAG::build(&config).unwrap_err().to_string();
+        assert!(
+            error.contains(
+                "'Lint_js' (Lint_js), 'lint.js' (lint_js); 'ci/test' (test), 'nightly/test' (test)"
+            ),
+            "{error}"
+        );
+    }
+
+    fn multi_arch_job() -> Job {
+        let mut job = create_simple_job();
+        job.matrix = Some(JobMatrix::Dimensions(HashMap::from([(
+            "arch".to_string(),
+            vec!["amd64".to_string(), "arm64".to_string()],
+        )])));
+        job
+    }
+
+    fn arch_dag(build: Job, test: Job) -> Result<JobDAG> {
+        let config = CigenConfig {
+            jobs: HashMap::from([("build".to_string(), build), ("test".to_string(), test)]),
+            ..Default::default()
+        };
+        JobDAG::build(&config)
+    }
+
+    fn needs(dag: &JobDAG, instance_id: &str) -> Vec<String> {
+        dag.get_job(instance_id).unwrap().job.needs.clone()
+    }
+
+    #[test]
+    fn test_single_arch_dependent_requires_every_architecture() {
+        let mut test = create_simple_job();
+        test.needs = vec!["build".to_string()];
+        let dag = arch_dag(multi_arch_job(), test).unwrap();
+        assert_eq!(needs(&dag, "test"), ["build-amd64", "build-arm64"]);
+    }
+
+    #[test]
+    fn test_multi_arch_dependent_requires_its_own_architecture() {
+        let mut test = multi_arch_job();
+        test.needs = vec!["build".to_string()];
+        let dag = arch_dag(multi_arch_job(), test).unwrap();
+        assert_eq!(needs(&dag, "test-amd64"), ["build-amd64"]);
+        assert_eq!(needs(&dag, "test-arm64"), ["build-arm64"]);
+    }
+
+    #[test]
+    fn test_multi_arch_dependent_on_single_arch_job() {
+        let mut build = create_simple_job();
+        build.architecture = Some("amd64".to_string());
+        let mut test = multi_arch_job();
+        test.needs = vec!["build".to_string()];
+        let dag = arch_dag(build, test).unwrap();
+        assert_eq!(needs(&dag, "test-amd64"), ["build"]);
+        assert_eq!(needs(&dag, "test-arm64"), ["build"]);
+    }
+
+    #[test]
+    fn test_single_arch_dependent_on_single_arch_job() {
+        let mut build = create_simple_job();
+        build.architecture = Some("arm64".to_string());
+        let mut test = create_simple_job();
+        test.architecture = Some("amd64".to_string());
+        test.needs = vec!["build".to_string()];
+        let dag = arch_dag(build, test).unwrap();
+        assert_eq!(needs(&dag, "test"), ["build"]);
+    }
+
+    #[test]
+    fn test_needs_can_select_an_architecture() {
+        let mut test = create_simple_job();
+        test.needs = vec!["build@arm64".to_string()];
+        let dag = arch_dag(multi_arch_job(), test).unwrap();
+        assert_eq!(needs(&dag, "test"), ["build-arm64"]);
+
+        // The selection overrides the dependent's own architecture
+        let mut test = multi_arch_job();
+        test.needs = vec!["build@amd64".to_string()];
+        let dag = arch_dag(multi_arch_job(), test).unwrap();
+        assert_eq!(needs(&dag, "test-arm64"), ["build-amd64"]);
+
+        let mut test = create_simple_job();
+        test.needs = vec!["build@riscv64".to_string()];
+        let error = arch_dag(multi_arch_job(), test).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "Job 'test' depends on 'build@riscv64', but 'build' has no riscv64 variant (it has amd64, arm64)"
+        );
+    }
+
     #[test]
     fn test_circular_dependency() {
         let mut job_a = create_simple_job();
@@ -648,17 +867,8 @@ mod tests {
         jobs.insert("c".to_string(), job_c);
 
         let config = CigenConfig {
-            project: None,
-            providers: vec![],
-            packages: vec![],
-            source_file_groups: HashMap::new(),
             jobs,
-            commands: HashMap::new(),
-            caches: HashMap::new(),
-            runners: HashMap::new(),
-            provider_config: HashMap::new(),
-            workflows: HashMap::new(),
-            raw: Default::default(),
+            ..Default::default()
         };
 
         let result = JobDAG::build(&config);
@@ -680,17 +890,8 @@ mod tests {
         jobs.insert("test".to_string(), job);
 
         let config = CigenConfig {
-            project: None,
-            providers: vec![],
-            packages: vec![],
-            source_file_groups: HashMap::new(),
             jobs,
-            commands: HashMap::new(),
-            caches: HashMap::new(),
-            runners: HashMap::new(),
-            provider_config: HashMap::new(),
-            workflows: HashMap::new(),
-            raw: Default::default(),
+            ..Default::default()
         };
 
         let result = JobDAG::build(&config);
@@ -703,6 +904,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_unknown_dependency_suggests_the_closest_job() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deploy.yml");
+        std::fs::write(&path, "needs: [tset]\nsteps:\n  - run: ./deploy\n").unwrap();
+        let mut deploy = create_simple_job();
+        deploy.needs = vec!["tset".to_string()];
+        deploy.source_file = Some(path.clone());
+
+        let mut jobs = HashMap::new();
+        jobs.insert("test".to_string(), create_simple_job());
+        jobs.insert("deploy".to_string(), deploy);
+        let config = CigenConfig {
+            jobs,
+            ..Default::default()
+        };
+
+        let error = JobDAG::build(&config).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Job 'deploy' depends on 'tset', but no matching job instance exists\n\
+             Did you mean 'test'?\nAvailable: test"
+        );
+        let located = error.downcast_ref::<LocatedError>().unwrap();
+        assert_eq!((located.location.line, located.location.column), (1, 9));
+        assert_eq!(
+            located.help.as_deref(),
+            Some("Did you mean 'test'?\nAvailable: test")
+        );
+    }
+
     #[test]
     fn test_cartesian_product() {
         let dimensions = vec![
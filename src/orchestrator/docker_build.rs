@@ -0,0 +1,661 @@
+//! `docker_build` augmentation
+//!
+//! Adds a `build_<image>` job for every image in `docker_build.images` and
+//! points consuming jobs (`image: <name>`) at the built tag. Tags are derived
+//! from a content hash of the Dockerfile, `hash_sources`, build args, and the
+//! hashes of any images the image depends on, so unchanged images keep their tag.
+//!
+//! Per-architecture tags use the `{{ architecture }}` template variable, which
+//! is rendered for each matrix variant after expansion.
+//!
+//! Given an image registry (`--verify-images`), images whose tags are already
+//! published get no build jobs, and their consumers use the published tags.
+
+use anyhow::{Result, bail};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+
+use crate::docker_hash::{image_hashes, ordered_images};
+use crate::image_registry::{DOCKER_HUB, ImageReference, ImageRegistry};
+use crate::schema::{
+    CigenConfig, DEFAULT_WORKFLOW, DockerBuildConfig, DockerImage, Job, JobMatrix, RemoteDocker,
+    Step,
+};
+
+/// Image used for build and manifest jobs unless `docker_build.builder_image` is set
+pub const DEFAULT_BUILDER_IMAGE: &str = "cimg/base:current";
+
+/// Add build (and manifest) jobs for `docker_build` images and resolve consuming
+/// jobs' images. With a `registry`, images it already has aren't built.
+pub fn augment_with_docker_build(
+    config: &mut CigenConfig,
+    registry: Option<&dyn ImageRegistry>,
+) -> Result<()> {
+    let Some(docker) = config.docker_build.clone().filter(|docker| docker.enabled) else {
+        return Ok(());
+    };
+
+    let images = ordered_images(&docker)?;
+    let root = config
+        .project_root
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("."));
+    let hashes: HashMap<String, String> = image_hashes(&root, &docker)?
+        .into_iter()
+        .map(|image| (image.name, image.hash))
+        .collect();
+
+    let workflow = build_workflow(config, &docker)?;
+    let builder_image = docker
+        .builder_image
+        .clone()
+        .unwrap_or_else(|| DEFAULT_BUILDER_IMAGE.to_string());
+
+    if docker.manifest && !docker.registry.push {
+        bail!("docker_build.manifest requires docker_build.registry.push to be enabled");
+    }
+
+    // Image name -> the job consumers need (none when already published) and the tag they use
+    let mut consumer_targets: HashMap<String, (Option<String>, String)> = HashMap::new();
+    let mut built = BTreeSet::new();
+    for image in &images {
+        let hash = &hashes[&image.name];
+        let manifest = docker.manifest && image.arch.len() > 1;
+        if let Some(registry) = registry
+            && image_published(registry, &docker, image, hash, manifest)
+        {
+            let arch = (!manifest).then_some("{{ architecture }}");
+            consumer_targets.insert(
+                image.name.clone(),
+                (None, image_tag(&docker, image, hash, arch)),
+            );
+            continue;
+        }
+
+        let build_job_id = format!("build_{}", image.name);
+        insert_generated_job(
+            config,
+            &build_job_id,
+            build_job(
+                &docker,
+                image,
+                hash,
+                &builder_image,
+                workflow.clone(),
+                &built,
+            ),
+        )?;
+        built.insert(image.name.clone());
+
+        let target = if manifest {
+            let manifest_job_id = format!("manifest_{}", image.name);
+            insert_generated_job(
+                config,
+                &manifest_job_id,
+                manifest_job(&docker, image, hash, &builder_image, workflow.clone()),
+            )?;
+            (Some(manifest_job_id), image_tag(&docker, image, hash, None))
+        } else {
+            (
+                Some(build_job_id),
+                image_tag(&docker, image, hash, Some("{{ architecture }}")),
+            )
+        };
+        consumer_targets.insert(image.name.clone(), target);
+    }
+
+    for job in config.jobs.values_mut() {
+        let Some((needed_job, image)) = consumer_targets.get(&job.image) else {
+            continue;
+        };
+        job.image = image.clone();
+        if let Some(needed_job) = needed_job
+            && !job.needs.contains(needed_job)
+        {
+            job.needs.push(needed_job.clone());
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether every tag consumers of `image` use is in the registry. Images whose
+/// tags can't be looked up are built.
+fn image_published(
+    registry: &dyn ImageRegistry,
+    docker: &DockerBuildConfig,
+    image: &DockerImage,
+    hash: &str,
+    manifest: bool,
+) -> bool {
+    let tags: Vec<String> = if manifest {
+        vec![image_tag(docker, image, hash, None)]
+    } else {
+        image
+            .arch
+            .iter()
+            .map(|arch| image_tag(docker, image, hash, Some(arch)))
+            .collect()
+    };
+    for tag in &tags {
+        match registry.image_exists(tag) {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::info!("{tag} isn't published; building image '{}'", image.name);
+                return false;
+            }
+            Err(error) => {
+                tracing::warn!("{error:#}; building image '{}'", image.name);
+                return false;
+            }
+        }
+    }
+    tracing::info!(
+        "Image '{}' is already published; skipping its build",
+        image.name
+    );
+    true
+}
+
+/// Fully qualified tag for an image; `arch` appends the per-architecture suffix
+pub fn image_tag(
+    docker: &DockerBuildConfig,
+    image: &DockerImage,
+    hash: &str,
+    arch: Option<&str>,
+) -> String {
+    let repo = docker.registry.repo.trim_end_matches('/');
+    match arch {
+        Some(arch) => format!("{repo}/{}:{hash}-{arch}", image.name),
+        None => format!("{repo}/{}:{hash}", image.name),
+    }
+}
+
+/// The workflow build jobs are added to: the one shared by all consuming jobs
+fn build_workflow(config: &CigenConfig, docker: &DockerBuildConfig) -> Result<Option<String>> {
+    let image_names: BTreeSet<&str> = docker
+        .images
+        .iter()
+        .map(|image| image.name.as_str())
+        .collect();
+    let workflows: BTreeSet<Option<String>> = config
+        .jobs
+        .values()
+        .filter(|job| image_names.contains(job.image.as_str()))
+        .map(|job| job.workflow.clone())
+        .collect();
+
+    match workflows.len() {
+        0 => Ok(None),
+        1 => Ok(workflows.into_iter().next().flatten()),
+        _ => bail!(
+            "docker_build images are used by jobs in several workflows ({}); build jobs can only be added to one",
+            workflows
+                .iter()
+                .map(|workflow| workflow.as_deref().unwrap_or(DEFAULT_WORKFLOW))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn insert_generated_job(config: &mut CigenConfig, job_id: &str, job: Job) -> Result<()> {
+    if config.jobs.contains_key(job_id) {
+        bail!("Job '{job_id}' conflicts with the job generated by docker_build");
+    }
+    config.jobs.insert(job_id.to_string(), job);
+    Ok(())
+}
+
+fn build_job(
+    docker: &DockerBuildConfig,
+    image: &DockerImage,
+    hash: &str,
+    builder_image: &str,
+    workflow: Option<String>,
+    built: &BTreeSet<String>,
+) -> Job {
+    let tag = image_tag(docker, image, hash, Some("{{ architecture }}"));
+    let steps = build_commands(docker, image, &tag)
+        .iter()
+        .map(|command| run_step(command))
+        .collect();
+
+    let (architecture, matrix) = architecture_variants(image);
+    Job {
+        image: builder_image.to_string(),
+        // Dependencies that are already published have no build job to wait for
+        needs: image
+            .depends_on
+            .iter()
+            .filter(|dependency| built.contains(*dependency))
+            .map(|dependency| format!("build_{dependency}"))
+            .collect(),
+        architecture,
+        matrix,
+        remote_docker: Some(RemoteDocker {
+            version: None,
+            layer_caching: docker.layer_caching,
+        }),
+        steps,
+        workflow,
+        ..Default::default()
+    }
+}
+
+fn manifest_job(
+    docker: &DockerBuildConfig,
+    image: &DockerImage,
+    hash: &str,
+    builder_image: &str,
+    workflow: Option<String>,
+) -> Job {
+    let sources: Vec<String> = image
+        .arch
+        .iter()
+        .map(|arch| image_tag(docker, image, hash, Some(arch)))
+        .collect();
+    let command = format!(
+        "docker buildx imagetools create -t {} {}",
+        image_tag(docker, image, hash, None),
+        sources.join(" ")
+    );
+
+    Job {
+        image: builder_image.to_string(),
+        needs: vec![format!("build_{}", image.name)],
+        steps: vec![run_step(&login_command(docker)), run_step(&command)],
+        workflow,
+        ..Default::default()
+    }
+}
+
+/// Commands that build (and push) one architecture of an image.
+///
+/// Images with build cache settings are built with `docker buildx build` on a
+/// `docker-container` builder, which the default `docker` driver can't export
+/// caches without; the image is pushed with `--push`, or loaded into the local
+/// daemon with `--load`. Other images use `docker build` followed by `docker
+/// push`. Jobs that push or use a registry cache log in to the registry first.
+fn build_commands(docker: &DockerBuildConfig, image: &DockerImage, tag: &str) -> Vec<String> {
+    let (cache_from, cache_to) = cache_options(docker, image);
+    let buildx = !cache_from.is_empty() || !cache_to.is_empty();
+    let registry_cache = cache_from
+        .iter()
+        .chain(&cache_to)
+        .any(|option| option.contains("type=registry"));
+
+    let mut commands = Vec::new();
+    if docker.registry.push || registry_cache {
+        commands.push(login_command(docker));
+    }
+    if buildx {
+        commands.push("docker buildx create --use".to_string());
+    }
+
+    let mut parts = vec![
+        if buildx {
+            "docker buildx build"
+        } else {
+            "docker build"
+        }
+        .to_string(),
+        "--platform linux/{{ architecture }}".to_string(),
+        format!("-f {}", image.dockerfile),
+        format!("-t {tag}"),
+    ];
+    for (key, value) in &image.build_args {
+        parts.push(format!("--build-arg {key}={value}"));
+    }
+    parts.extend(
+        cache_from
+            .iter()
+            .map(|source| format!("--cache-from {source}")),
+    );
+    parts.extend(
+        cache_to
+            .iter()
+            .map(|destination| format!("--cache-to {destination}")),
+    );
+    if buildx {
+        let output = if docker.registry.push {
+            "--push"
+        } else {
+            "--load"
+        };
+        parts.push(output.to_string());
+    }
+    parts.push(image.context.clone());
+
+    commands.push(parts.join(" "));
+    if !buildx && docker.registry.push {
+        commands.push(format!("docker push {tag}"));
+    }
+    commands
+}
+
+/// `docker login` to the registry of `registry.repo`, with the password piped
+/// from `registry.login.password_env` so it isn't in the command line
+fn login_command(docker: &DockerBuildConfig) -> String {
+    let login = &docker.registry.login;
+    let host = ImageReference::parse(&docker.registry.repo)
+        .map(|reference| reference.registry)
+        .unwrap_or_else(|_| DOCKER_HUB.to_string());
+    format!(
+        "printf '%s' \"${}\" | docker login --username \"${}\" --password-stdin {host}",
+        login.password_env, login.username_env
+    )
+}
+
+/// `--cache-from`/`--cache-to` values, including the `registry_cache` shorthand
+fn cache_options(docker: &DockerBuildConfig, image: &DockerImage) -> (Vec<String>, Vec<String>) {
+    let mut cache_from = image.cache_from.clone();
+    let mut cache_to = image.cache_to.clone();
+    if image.registry_cache {
+        let reference = format!(
+            "type=registry,ref={}",
+            image_tag(docker, image, "buildcache", Some("{{ architecture }}"))
+        );
+        cache_from.push(reference.clone());
+        cache_to.push(format!("{reference},mode=max"));
+    }
+    (cache_from, cache_to)
+}
+
+/// Single-architecture images set `architecture`; others expand via an `arch` matrix
+fn architecture_variants(image: &DockerImage) -> (Option<String>, Option<JobMatrix>) {
+    match image.arch.as_slice() {
+        [arch] => (Some(arch.clone()), None),
+        arches => {
+            let mut dimensions = HashMap::new();
+            dimensions.insert("arch".to_string(), arches.to_vec());
+            (None, Some(JobMatrix::Dimensions(dimensions)))
+        }
+    }
+}
+
+fn run_step(command: &str) -> Step {
+    Step::SimpleRun {
+        run: command.to_string(),
+        condition: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker_hash::TAG_HASH_LENGTH;
+    use crate::schema::{DockerRegistry, RegistryLogin};
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    const LOGIN: &str = "printf '%s' \"$CIGEN_REGISTRY_PASSWORD\" | docker login \
+        --username \"$CIGEN_REGISTRY_USERNAME\" --password-stdin docker.io";
+
+    fn docker_config(manifest: bool, arch: &[&str]) -> DockerBuildConfig {
+        DockerBuildConfig {
+            enabled: true,
+            layer_caching: true,
+            manifest,
+            builder_image: None,
+            verify_images: false,
+            registry: DockerRegistry {
+                repo: "example/repo".to_string(),
+                push: true,
+                login: RegistryLogin::default(),
+            },
+            images: vec![DockerImage {
+                name: "ci_base".to_string(),
+                dockerfile: "Dockerfile".to_string(),
+                context: ".".to_string(),
+                arch: arch.iter().map(|arch| arch.to_string()).collect(),
+                build_args: BTreeMap::new(),
+                hash_sources: vec!["Gemfile.lock".to_string()],
+                depends_on: vec![],
+                cache_from: vec![],
+                cache_to: vec![],
+                registry_cache: false,
+            }],
+        }
+    }
+
+    fn project(docker: DockerBuildConfig) -> (tempfile::TempDir, CigenConfig) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Dockerfile"), "FROM alpine:3.19\n").unwrap();
+        fs::write(dir.path().join("Gemfile.lock"), "GEM\n").unwrap();
+
+        let mut jobs = HashMap::new();
+        jobs.insert(
+            "test".to_string(),
+            Job {
+                image: "ci_base".to_string(),
+                ..Default::default()
+            },
+        );
+        let config = CigenConfig {
+            jobs,
+            docker_build: Some(docker),
+            project_root: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        (dir, config)
+    }
+
+    #[test]
+    fn test_build_job_and_consumer_image() {
+        let (_dir, mut config) = project(docker_config(false, &["amd64", "arm64"]));
+        augment_with_docker_build(&mut config, None).unwrap();
+
+        let build = &config.jobs["build_ci_base"];
+        assert!(matches!(build.matrix, Some(JobMatrix::Dimensions(_))));
+        assert_eq!(
+            build
+                .remote_docker
+                .as_ref()
+                .map(|remote| remote.layer_caching),
+            Some(true)
+        );
+
+        let consumer = &config.jobs["test"];
+        assert_eq!(consumer.needs, vec!["build_ci_base"]);
+        assert!(consumer.image.starts_with("example/repo/ci_base:"));
+        assert!(consumer.image.ends_with("-{{ architecture }}"));
+        assert!(!config.jobs.contains_key("manifest_ci_base"));
+    }
+
+    #[test]
+    fn test_manifest_job_requires_arch_builds() {
+        let (_dir, mut config) = project(docker_config(true, &["amd64", "arm64"]));
+        augment_with_docker_build(&mut config, None).unwrap();
+
+        let manifest = &config.jobs["manifest_ci_base"];
+        assert_eq!(manifest.needs, vec!["build_ci_base"]);
+        let Step::SimpleRun { run, .. } = &manifest.steps[1] else {
+            panic!("expected run step");
+        };
+        let consumer = &config.jobs["test"];
+        assert_eq!(consumer.needs, vec!["manifest_ci_base"]);
+        let hash = consumer.image.rsplit(':').next().unwrap();
+        assert_eq!(hash.len(), TAG_HASH_LENGTH);
+        assert_eq!(
+            run,
+            &format!(
+                "docker buildx imagetools create -t example/repo/ci_base:{hash} \
+                 example/repo/ci_base:{hash}-amd64 example/repo/ci_base:{hash}-arm64"
+            )
+        );
+    }
+
+    #[test]
+    fn test_manifest_skipped_for_single_architecture() {
+        let (_dir, mut config) = project(docker_config(true, &["amd64"]));
+        augment_with_docker_build(&mut config, None).unwrap();
+
+        assert!(!config.jobs.contains_key("manifest_ci_base"));
+        assert_eq!(
+            config.jobs["build_ci_base"].architecture.as_deref(),
+            Some("amd64")
+        );
+        assert_eq!(config.jobs["test"].needs, vec!["build_ci_base"]);
+    }
+
+    #[test]
+    fn test_hash_changes_with_sources() {
+        let (dir, mut config) = project(docker_config(false, &["amd64"]));
+        let mut before = config.clone();
+        augment_with_docker_build(&mut before, None).unwrap();
+
+        fs::write(dir.path().join("Gemfile.lock"), "GEM\n  rails\n").unwrap();
+        augment_with_docker_build(&mut config, None).unwrap();
+        assert_ne!(before.jobs["test"].image, config.jobs["test"].image);
+    }
+
+    /// Tags starting with any of the prefixes exist; tags with `error` fail
+    struct MockRegistry(Vec<String>);
+
+    impl ImageRegistry for MockRegistry {
+        fn image_exists(&self, image: &str) -> Result<bool> {
+            if image.contains("error") {
+                bail!("registry unavailable");
+            }
+            Ok(self.0.iter().any(|prefix| image.starts_with(prefix)))
+        }
+    }
+
+    #[test]
+    fn test_published_images_are_not_built() {
+        let mut docker = docker_config(false, &["amd64", "arm64"]);
+        let mut app = docker.images[0].clone();
+        app.name = "app".to_string();
+        app.depends_on = vec!["ci_base".to_string()];
+        docker.images.push(app);
+        let (_dir, mut config) = project(docker);
+        config.jobs.insert(
+            "deploy".to_string(),
+            Job {
+                image: "app".to_string(),
+                ..Default::default()
+            },
+        );
+
+        // ci_base has both architectures; app is missing arm64
+        let registry = MockRegistry(vec![
+            "example/repo/ci_base:".to_string(),
+            "example/repo/app:".to_string(),
+        ]);
+        let mut published = config.clone();
+        augment_with_docker_build(&mut published, Some(&registry)).unwrap();
+        assert!(!published.jobs.contains_key("build_ci_base"));
+        assert!(!published.jobs.contains_key("build_app"));
+        assert!(published.jobs["test"].needs.is_empty());
+        assert!(
+            published.jobs["test"]
+                .image
+                .ends_with("-{{ architecture }}")
+        );
+
+        let registry = MockRegistry(vec!["example/repo/ci_base:".to_string()]);
+        augment_with_docker_build(&mut config, Some(&registry)).unwrap();
+        assert!(!config.jobs.contains_key("build_ci_base"));
+        assert!(config.jobs["build_app"].needs.is_empty());
+        assert!(config.jobs["test"].needs.is_empty());
+        assert_eq!(config.jobs["deploy"].needs, vec!["build_app"]);
+    }
+
+    #[test]
+    fn test_failed_lookups_build_the_image() {
+        let mut docker = docker_config(true, &["amd64", "arm64"]);
+        docker.registry.repo = "example/error".to_string();
+        let (_dir, mut config) = project(docker);
+        augment_with_docker_build(&mut config, Some(&MockRegistry(vec![]))).unwrap();
+        assert!(config.jobs.contains_key("build_ci_base"));
+        assert_eq!(config.jobs["test"].needs, vec!["manifest_ci_base"]);
+    }
+
+    #[test]
+    fn test_build_commands_without_cache() {
+        let mut docker = docker_config(false, &["amd64"]);
+        assert_eq!(
+            build_commands(&docker, &docker.images[0], "example/repo/ci_base:abc-amd64"),
+            vec![
+                LOGIN,
+                "docker build --platform linux/{{ architecture }} -f Dockerfile \
+                 -t example/repo/ci_base:abc-amd64 .",
+                "docker push example/repo/ci_base:abc-amd64",
+            ]
+        );
+
+        docker.registry.push = false;
+        assert_eq!(
+            build_commands(&docker, &docker.images[0], "example/repo/ci_base:abc-amd64"),
+            vec![
+                "docker build --platform linux/{{ architecture }} -f Dockerfile \
+                 -t example/repo/ci_base:abc-amd64 ."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_login_reads_configured_env_vars() {
+        let mut docker = docker_config(true, &["amd64"]);
+        docker.registry.repo = "ghcr.io/acme".to_string();
+        docker.registry.login = RegistryLogin {
+            username_env: "GHCR_USER".to_string(),
+            password_env: "GHCR_TOKEN".to_string(),
+        };
+        assert_eq!(
+            login_command(&docker),
+            "printf '%s' \"$GHCR_TOKEN\" | docker login --username \"$GHCR_USER\" --password-stdin ghcr.io"
+        );
+
+        let (_dir, mut config) = project(docker_config(true, &["amd64", "arm64"]));
+        augment_with_docker_build(&mut config, None).unwrap();
+        for job in ["build_ci_base", "manifest_ci_base"] {
+            let Step::SimpleRun { run, .. } = &config.jobs[job].steps[0] else {
+                panic!("expected run step");
+            };
+            assert_eq!(run, LOGIN, "{job}");
+        }
+    }
+
+    #[test]
+    fn test_build_commands_with_cache_use_a_buildx_builder() {
+        let mut docker = docker_config(false, &["amd64"]);
+        docker.images[0].cache_from = vec!["type=gha".to_string()];
+        docker.images[0].registry_cache = true;
+        assert_eq!(
+            build_commands(&docker, &docker.images[0], "example/repo/ci_base:abc-amd64"),
+            vec![
+                LOGIN,
+                "docker buildx create --use",
+                "docker buildx build --platform linux/{{ architecture }} -f Dockerfile \
+                 -t example/repo/ci_base:abc-amd64 --cache-from type=gha \
+                 --cache-from type=registry,ref=example/repo/ci_base:buildcache-{{ architecture }} \
+                 --cache-to type=registry,ref=example/repo/ci_base:buildcache-{{ architecture }},mode=max \
+                 --push ."
+            ]
+        );
+
+        docker.registry.push = false;
+        docker.images[0].registry_cache = false;
+        assert_eq!(
+            build_commands(&docker, &docker.images[0], "example/repo/ci_base:abc-amd64"),
+            vec![
+                "docker buildx create --use",
+                "docker buildx build --platform linux/{{ architecture }} -f Dockerfile \
+                 -t example/repo/ci_base:abc-amd64 --cache-from type=gha --load ."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_dependency() {
+        let mut docker = docker_config(false, &["amd64"]);
+        docker.images[0].depends_on = vec!["ci_bse".to_string()];
+        let (_dir, mut config) = project(docker);
+        let error = augment_with_docker_build(&mut config, None)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("unknown image 'ci_bse'"), "{error}");
+    }
+}
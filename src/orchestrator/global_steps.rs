@@ -0,0 +1,113 @@
+//! `global_steps:` augmentation
+//!
+//! Every job runs the top-level `global_steps.before` ahead of its own steps
+//! and `global_steps.after` behind them, unless it sets
+//! `inherit_global_steps: false`, or leaves it unset in a workflow that does.
+//! This runs before package installs and caches wrap the steps, so `before`
+//! follows the checkout, the provider's setup steps, cache restores, and
+//! package installs, and `after` comes ahead of the saves of the job's
+//! `cache:` entries, cleanup, and the job-status completion marker. Workflow `job_steps` stay outside
+//! all of it.
+
+use crate::schema::CigenConfig;
+
+/// Put the global steps around the steps of every job that inherits them
+pub fn augment_with_global_steps(config: &mut CigenConfig) {
+    let global = &config.global_steps;
+    if global.is_empty() {
+        return;
+    }
+    for job in config.jobs.values_mut() {
+        let inherits = job.inherit_global_steps.unwrap_or_else(|| {
+            job.workflow
+                .as_deref()
+                .and_then(|workflow| config.workflows.get(workflow))
+                .is_none_or(|workflow| workflow.inherit_global_steps)
+        });
+        if job.is_approval() || !inherits {
+            continue;
+        }
+        let mut steps = global.before.clone();
+        steps.append(&mut job.steps);
+        steps.extend(global.after.iter().cloned());
+        job.steps = steps;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{GlobalSteps, Job, Step, WorkflowConfig};
+    use serde_yaml::Value;
+    use std::collections::HashMap;
+
+    fn run(command: &str) -> Step {
+        Step::SimpleRun {
+            run: command.to_string(),
+            condition: None,
+        }
+    }
+
+    fn commands(job: &Job) -> Vec<&str> {
+        job.steps
+            .iter()
+            .map(|step| match step {
+                Step::SimpleRun { run, .. } => run.as_str(),
+                other => panic!("unexpected step {other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn global_steps_wrap_jobs_that_inherit_them() {
+        let job = |workflow: &str, inherit: Option<bool>| Job {
+            workflow: Some(workflow.to_string()),
+            inherit_global_steps: inherit,
+            steps: vec![run("make test")],
+            ..Default::default()
+        };
+        let mut approval = job("ci", None);
+        approval.steps.clear();
+        approval
+            .extra
+            .insert("type".to_string(), Value::String("approval".into()));
+        let mut config = CigenConfig {
+            global_steps: GlobalSteps {
+                before: vec![run("scan-secrets")],
+                after: vec![run("attest")],
+            },
+            jobs: HashMap::from([
+                ("test".to_string(), job("ci", None)),
+                ("opted_out".to_string(), job("ci", Some(false))),
+                ("release".to_string(), job("release", None)),
+                ("opted_in".to_string(), job("release", Some(true))),
+                ("hold".to_string(), approval),
+            ]),
+            workflows: HashMap::from([
+                ("ci".to_string(), WorkflowConfig::default()),
+                (
+                    "release".to_string(),
+                    WorkflowConfig {
+                        inherit_global_steps: false,
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+        augment_with_global_steps(&mut config);
+
+        assert_eq!(
+            commands(&config.jobs["test"]),
+            ["scan-secrets", "make test", "attest"]
+        );
+        assert_eq!(commands(&config.jobs["opted_out"]), ["make test"]);
+        assert_eq!(commands(&config.jobs["release"]), ["make test"]);
+        // A job's own setting wins over its workflow's
+        assert_eq!(
+            commands(&config.jobs["opted_in"]),
+            ["scan-secrets", "make test", "attest"]
+        );
+        assert!(config.jobs["hold"].steps.is_empty());
+    }
+}
@@ -0,0 +1,187 @@
+//! `group:<name>` needs
+//!
+//! Jobs join a group with `group: <name>`, and a job that needs
+//! `group:<name>` waits for every job in the group from its own workflow. By
+//! default the need is replaced by the group's jobs. A group configured with
+//! `gate: true` gets a `<name>_gate` job that needs the group's jobs instead,
+//! and dependents need only that job.
+
+use anyhow::{Result, bail};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::schema::{CigenConfig, Job, Step, group_need};
+
+/// Image of gate jobs, which only report that the group passed
+const GATE_IMAGE: &str = "cimg/base:current";
+
+/// Name of the gate job for `group`
+fn gate_job_id(group: &str) -> String {
+    format!("{group}_gate")
+}
+
+/// Replace `group:<name>` needs with the group's jobs, or with its gate job
+pub fn augment_with_groups(config: &mut CigenConfig) -> Result<()> {
+    // Group members by group and workflow
+    let mut members: BTreeMap<&str, BTreeMap<&str, Vec<String>>> = BTreeMap::new();
+    for (job_id, job) in &config.jobs {
+        if let Some(group) = &job.group {
+            members
+                .entry(group)
+                .or_default()
+                .entry(job.workflow.as_deref().unwrap_or_default())
+                .or_default()
+                .push(job_id.clone());
+        }
+    }
+    for workflows in members.values_mut() {
+        for jobs in workflows.values_mut() {
+            jobs.sort();
+        }
+    }
+
+    let mut gates: BTreeMap<String, (String, Vec<String>)> = BTreeMap::new();
+    let mut needs: HashMap<String, Vec<String>> = HashMap::new();
+    let mut job_ids: Vec<&String> = config.jobs.keys().collect();
+    job_ids.sort();
+    for job_id in job_ids {
+        let job = &config.jobs[job_id];
+        if !job.needs.iter().any(|need| group_need(need).is_some()) {
+            continue;
+        }
+        let workflow = job.workflow.as_deref().unwrap_or_default();
+        let mut resolved = Vec::new();
+        for need in &job.needs {
+            let Some(group) = group_need(need) else {
+                resolved.push(need.clone());
+                continue;
+            };
+            let Some(workflows) = members.get(group) else {
+                bail!("Job '{job_id}' needs unknown group '{group}'");
+            };
+            let Some(group_jobs) = workflows.get(workflow) else {
+                bail!(
+                    "Job '{job_id}' needs group '{group}', which has no jobs in workflow '{workflow}'"
+                );
+            };
+            let gated = config.groups.get(group).is_some_and(|group| group.gate);
+            if gated {
+                if workflows.len() > 1 {
+                    let names: Vec<&str> = workflows.keys().copied().collect();
+                    bail!(
+                        "Group '{group}' has a gate job, so its jobs must be in one workflow, but they are in {}",
+                        names.join(", ")
+                    );
+                }
+                if group_jobs.contains(job_id) {
+                    bail!("Job '{job_id}' is in group '{group}', so it can't need its gate job");
+                }
+                let gate = gate_job_id(group);
+                gates
+                    .entry(gate.clone())
+                    .or_insert_with(|| (workflow.to_string(), group_jobs.clone()));
+                resolved.push(gate);
+            } else {
+                resolved.extend(
+                    group_jobs
+                        .iter()
+                        .filter(|member| *member != job_id)
+                        .cloned(),
+                );
+            }
+        }
+        let mut seen = BTreeSet::new();
+        resolved.retain(|need| seen.insert(need.clone()));
+        needs.insert(job_id.clone(), resolved);
+    }
+
+    for (job_id, resolved) in needs {
+        if let Some(job) = config.jobs.get_mut(&job_id) {
+            job.needs = resolved;
+        }
+    }
+    for (gate, (workflow, group_jobs)) in gates {
+        if config.jobs.contains_key(&gate) {
+            bail!("Job '{gate}' conflicts with the gate job generated for its group");
+        }
+        config.jobs.insert(gate, gate_job(workflow, group_jobs));
+    }
+    Ok(())
+}
+
+fn gate_job(workflow: String, needs: Vec<String>) -> Job {
+    Job {
+        image: GATE_IMAGE.to_string(),
+        checkout: Some(HashMap::from([(
+            "enabled".to_string(),
+            serde_yaml::Value::Bool(false),
+        )])),
+        steps: vec![Step::SimpleRun {
+            run: format!("echo \"{} jobs passed\"", needs.len()),
+            condition: None,
+        }],
+        needs,
+        workflow: Some(workflow),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::JobGroup;
+
+    fn job(workflow: &str, group: Option<&str>, needs: &[&str]) -> Job {
+        Job {
+            group: group.map(str::to_string),
+            needs: needs.iter().map(|need| need.to_string()).collect(),
+            workflow: Some(workflow.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn config(gate: bool) -> CigenConfig {
+        let mut config = CigenConfig {
+            jobs: HashMap::from([
+                ("rspec".to_string(), job("ci", Some("tests"), &[])),
+                ("jest".to_string(), job("ci", Some("tests"), &[])),
+                ("lint".to_string(), job("ci", None, &[])),
+                (
+                    "deploy".to_string(),
+                    job("ci", None, &["group:tests", "lint"]),
+                ),
+            ]),
+            ..Default::default()
+        };
+        if gate {
+            config
+                .groups
+                .insert("tests".to_string(), JobGroup { gate: true });
+        }
+        config
+    }
+
+    #[test]
+    fn group_needs_expand_to_the_groups_jobs() {
+        let mut config = config(false);
+        augment_with_groups(&mut config).unwrap();
+        assert_eq!(config.jobs["deploy"].needs, ["jest", "rspec", "lint"]);
+        assert!(!config.jobs.contains_key("tests_gate"));
+    }
+
+    #[test]
+    fn gated_groups_are_needed_through_their_gate_job() {
+        let mut config = config(true);
+        augment_with_groups(&mut config).unwrap();
+        assert_eq!(config.jobs["deploy"].needs, ["tests_gate", "lint"]);
+        let gate = &config.jobs["tests_gate"];
+        assert_eq!(gate.needs, ["jest", "rspec"]);
+        assert_eq!(gate.workflow.as_deref(), Some("ci"));
+
+        let mut config = self::config(true);
+        config
+            .jobs
+            .insert("e2e".to_string(), job("nightly", Some("tests"), &[]));
+        let error = augment_with_groups(&mut config).unwrap_err().to_string();
+        assert!(error.contains("must be in one workflow"), "{error}");
+    }
+}
@@ -0,0 +1,102 @@
+//! Provider-safe job names
+//!
+//! Every provider gets the same name for a job instance, so workflow entries,
+//! `requires`/`needs` edges, `{{ job_name }}` in cache keys, and job status
+//! keys agree. Names keep ASCII letters, digits, `_`, and `-`; anything else
+//! (`/`, `.`, spaces, ...) becomes `_`, and a name that doesn't start with a
+//! letter or `_` gets a leading `_`, as GitHub Actions job IDs require. Case
+//! is kept, but GitHub compares job IDs case-insensitively, so names that
+//! differ only in case collide.
+
+use anyhow::{Result, bail};
+use std::collections::BTreeMap;
+
+/// `name` with the characters providers reject replaced
+pub fn provider_job_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if !sanitized.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+/// Fail when two jobs get the same provider name (ignoring case). `sources`
+/// pairs each provider name with the job it was generated from.
+pub fn check_collisions<'a>(sources: impl IntoIterator<Item = (&'a str, String)>) -> Result<()> {
+    let mut by_name: BTreeMap<String, Vec<(&str, String)>> = BTreeMap::new();
+    for (name, source) in sources {
+        by_name
+            .entry(name.to_ascii_lowercase())
+            .or_default()
+            .push((name, source));
+    }
+    let collisions: Vec<String> = by_name
+        .into_values()
+        .filter(|jobs| jobs.len() > 1)
+        .map(|mut jobs| {
+            jobs.sort();
+            let sources: Vec<String> = jobs
+                .iter()
+                .map(|(name, source)| format!("'{source}' ({name})"))
+                .collect();
+            sources.join(", ")
+        })
+        .collect();
+    if !collisions.is_empty() {
+        bail!(
+            "Jobs would get the same name in the generated config: {}. Rename one of each, or set `job_name` in its matrix",
+            collisions.join("; ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_keep_safe_characters() {
+        assert_eq!(provider_job_name("rspec-3.2"), "rspec-3_2");
+        assert_eq!(provider_job_name("deploy/us east"), "deploy_us_east");
+        assert_eq!(provider_job_name("Lint_JS"), "Lint_JS");
+        assert_eq!(provider_job_name("3.3"), "_3_3");
+        assert_eq!(provider_job_name("-x"), "_-x");
+        // Already safe names are unchanged
+        assert_eq!(
+            provider_job_name("build_ci_base-amd64"),
+            "build_ci_base-amd64"
+        );
+    }
+
+    #[test]
+    fn collisions_list_every_source() {
+        check_collisions([
+            ("test", "ci/test".to_string()),
+            ("rspec-3_2", "rspec".to_string()),
+        ])
+        .unwrap();
+
+        let error = check_collisions([
+            ("ruby_rb", "lint/ruby.rb".to_string()),
+            ("build", "build".to_string()),
+            ("ruby_rb", "lint/ruby_rb".to_string()),
+            ("Build", "release/Build".to_string()),
+        ])
+        .unwrap_err()
+        .to_string();
+        assert_eq!(
+            error,
+            "Jobs would get the same name in the generated config: 'release/Build' (Build), 'build' (build); 'lint/ruby.rb' (ruby_rb), 'lint/ruby_rb' (ruby_rb). Rename one of each, or set `job_name` in its matrix"
+        );
+    }
+}
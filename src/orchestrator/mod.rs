@@ -1,7 +1,26 @@
 /// Job dependency graph and orchestration
+mod caches;
 mod convert;
 mod dag;
+mod docker_build;
+mod global_steps;
+mod groups;
+mod job_names;
+mod packages;
+mod providers;
+mod retries;
+mod sharding;
+mod test_splitting;
+mod warmup;
 mod workflow;
 
+pub use caches::NO_JOB_STATUS_CACHE_FLAG;
+pub use convert::step_list_to_proto;
 pub use dag::{ConcreteJob, JobDAG};
-pub use workflow::{FileFragment, GenerationResult, MergeStrategy, WorkflowOrchestrator};
+pub use job_names::provider_job_name;
+pub use sharding::SHARD_COUNT_FLAG;
+pub use test_splitting::SPLIT_TESTS_STEP;
+pub use warmup::WARMUP_WORKFLOW;
+pub use workflow::{
+    CONTINUED_CONFIG, FileFragment, GenerationResult, MergeStrategy, WorkflowOrchestrator,
+};
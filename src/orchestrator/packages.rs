@@ -0,0 +1,353 @@
+//! Job `packages:` augmentation
+//!
+//! A package whose name (or `manager:`) is a known package manager gets its
+//! dependencies installed before the job's own steps: restore the manager's
+//! cache, run the install command, then save the cache. Built-in managers can
+//! be overridden, and new ones added, under the top-level `package_managers:`.
+//! Other packages (`ruby`, `node`, `rust`, ...) are left to the providers.
+
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::caches::{restore_step, save_step};
+use crate::schema::{
+    CacheDefinition, CigenConfig, PackageManagerDefinition, PackageSpec, RunStepOptions, Step,
+};
+
+struct BuiltinManager {
+    name: &'static str,
+    lockfile: &'static str,
+    install: &'static str,
+    cache: &'static str,
+    cache_paths: &'static [&'static str],
+}
+
+const BUILTIN_MANAGERS: &[BuiltinManager] = &[
+    BuiltinManager {
+        name: "bundler",
+        lockfile: "Gemfile.lock",
+        install: "bundle config set --local path vendor/bundle\nbundle check || bundle install",
+        cache: "gems",
+        cache_paths: &["vendor/bundle"],
+    },
+    BuiltinManager {
+        name: "npm",
+        lockfile: "package-lock.json",
+        install: "npm ci",
+        cache: "npm",
+        cache_paths: &["~/.npm"],
+    },
+    BuiltinManager {
+        name: "yarn",
+        lockfile: "yarn.lock",
+        install: "yarn install --frozen-lockfile",
+        cache: "yarn",
+        cache_paths: &["~/.cache/yarn"],
+    },
+    BuiltinManager {
+        name: "pnpm",
+        lockfile: "pnpm-lock.yaml",
+        install: "pnpm install --frozen-lockfile",
+        cache: "pnpm",
+        cache_paths: &["~/.local/share/pnpm/store"],
+    },
+    BuiltinManager {
+        name: "pip",
+        lockfile: "requirements.txt",
+        install: "pip install -r requirements.txt",
+        cache: "pip",
+        cache_paths: &["~/.cache/pip"],
+    },
+    BuiltinManager {
+        name: "poetry",
+        lockfile: "poetry.lock",
+        install: "poetry install --no-interaction",
+        cache: "poetry",
+        cache_paths: &["~/.cache/pypoetry"],
+    },
+];
+
+/// A package manager with the config's overrides applied over the built-in
+struct PackageManager {
+    lockfile: String,
+    install: String,
+    cache: String,
+    cache_paths: Vec<String>,
+}
+
+impl PackageManager {
+    fn resolve(
+        name: &str,
+        overrides: &HashMap<String, PackageManagerDefinition>,
+    ) -> Result<Option<Self>> {
+        let builtin = BUILTIN_MANAGERS.iter().find(|manager| manager.name == name);
+        let overrides = overrides.get(name);
+        let (Some(builtin), overrides) = (builtin, overrides) else {
+            let Some(overrides) = overrides else {
+                return Ok(None);
+            };
+            let (Some(lockfile), Some(install)) = (&overrides.lockfile, &overrides.install) else {
+                bail!("Package manager '{name}' needs a lockfile and an install command");
+            };
+            return Ok(Some(Self {
+                lockfile: lockfile.clone(),
+                install: install.clone(),
+                cache: overrides.cache.clone().unwrap_or_else(|| name.to_string()),
+                cache_paths: overrides.cache_paths.clone().unwrap_or_default(),
+            }));
+        };
+
+        let overrides = overrides.cloned().unwrap_or_default();
+        Ok(Some(Self {
+            lockfile: overrides
+                .lockfile
+                .unwrap_or_else(|| builtin.lockfile.to_string()),
+            install: overrides
+                .install
+                .unwrap_or_else(|| builtin.install.to_string()),
+            cache: overrides.cache.unwrap_or_else(|| builtin.cache.to_string()),
+            cache_paths: overrides.cache_paths.unwrap_or_else(|| {
+                builtin
+                    .cache_paths
+                    .iter()
+                    .map(|path| path.to_string())
+                    .collect()
+            }),
+        }))
+    }
+
+    /// Name of the cache for packages installed in `dir`
+    fn cache_for(&self, dir: Option<&str>) -> String {
+        match dir {
+            Some(dir) => format!("{}-{}", self.cache, dir.replace('/', "-")),
+            None => self.cache.clone(),
+        }
+    }
+}
+
+fn manager_name(package: &PackageSpec) -> &str {
+    package.manager.as_deref().unwrap_or(&package.name)
+}
+
+/// Cache `package` installs into, when it uses a package manager
+pub(super) fn package_cache(
+    package: &PackageSpec,
+    overrides: &HashMap<String, PackageManagerDefinition>,
+) -> Result<Option<String>> {
+    Ok(PackageManager::resolve(manager_name(package), overrides)?
+        .map(|manager| manager.cache_for(package_dir(package))))
+}
+
+/// Install every job's package manager dependencies ahead of its steps
+pub fn augment_with_packages(config: &mut CigenConfig) -> Result<()> {
+    let version = config.cache_version;
+    for (job_id, job) in config.jobs.iter_mut() {
+        let mut setup = Vec::new();
+        for package in &job.packages {
+            let name = manager_name(package);
+            let Some(manager) = PackageManager::resolve(name, &config.package_managers)? else {
+                continue;
+            };
+
+            let dir = package_dir(package);
+            let lockfile = in_dir(dir, &manager.lockfile);
+            if let Some(root) = &config.project_root
+                && !root.join(&lockfile).is_file()
+            {
+                bail!("Job '{job_id}' uses package '{name}', but {lockfile} doesn't exist");
+            }
+
+            let cache = manager.cache_for(dir);
+            let definition = CacheDefinition {
+                paths: manager
+                    .cache_paths
+                    .iter()
+                    .map(|path| in_dir(dir, path))
+                    .collect(),
+                checksum_sources: vec![lockfile],
+                restore_key_levels: 1,
+                ..Default::default()
+            };
+
+            let cached = !definition.paths.is_empty();
+            if cached {
+                setup.push(restore_step(&cache, &definition, version));
+            }
+            setup.push(install_step(name, dir, &manager.install));
+            if cached {
+                setup.push(save_step(&cache, &definition, version, None));
+            }
+        }
+
+        if !setup.is_empty() {
+            setup.append(&mut job.steps);
+            job.steps = setup;
+        }
+    }
+    Ok(())
+}
+
+/// The package's `path:`, unless it's the repository root
+fn package_dir(package: &PackageSpec) -> Option<&str> {
+    package
+        .path
+        .as_deref()
+        .map(|path| path.trim_end_matches('/'))
+        .filter(|path| !path.is_empty() && *path != ".")
+}
+
+/// `path` relative to the package directory; home and absolute paths stay as-is
+fn in_dir(dir: Option<&str>, path: &str) -> String {
+    match dir {
+        Some(dir) if !path.starts_with('~') && !Path::new(path).is_absolute() => {
+            format!("{dir}/{path}")
+        }
+        _ => path.to_string(),
+    }
+}
+
+fn install_step(manager: &str, dir: Option<&str>, install: &str) -> Step {
+    let (name, command) = match dir {
+        Some(dir) => (
+            format!("Install {manager} packages in {dir}"),
+            format!("cd {dir}\n{install}"),
+        ),
+        None => (format!("Install {manager} packages"), install.to_string()),
+    };
+    Step::RunWithOptions {
+        run: RunStepOptions {
+            name: Some(name),
+            command,
+            env: HashMap::new(),
+            condition: None,
+            retry: None,
+        },
+        condition: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::Job;
+
+    fn config(packages: Vec<PackageSpec>) -> CigenConfig {
+        let mut jobs = HashMap::new();
+        jobs.insert(
+            "test".to_string(),
+            Job {
+                packages,
+                steps: vec![Step::SimpleRun {
+                    run: "make test".to_string(),
+                    condition: None,
+                }],
+                ..Default::default()
+            },
+        );
+        CigenConfig {
+            jobs,
+            ..Default::default()
+        }
+    }
+
+    fn step_names(config: &CigenConfig) -> Vec<String> {
+        config.jobs["test"]
+            .steps
+            .iter()
+            .map(|step| match step {
+                Step::RestoreCache { restore_cache, .. } => restore_cache.name.clone().unwrap(),
+                Step::SaveCache { save_cache, .. } => save_cache.name.clone().unwrap(),
+                Step::RunWithOptions { run, .. } => run.name.clone().unwrap(),
+                Step::SimpleRun { run, .. } => run.clone(),
+                other => panic!("unexpected step {other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_install_runs_between_restore_and_save_before_user_steps() {
+        let mut config = config(vec![
+            PackageSpec::from_name("bundler".to_string()),
+            PackageSpec::from_name("ruby".to_string()),
+        ]);
+        augment_with_packages(&mut config).unwrap();
+
+        assert_eq!(
+            step_names(&config),
+            [
+                "Restore gems cache",
+                "Install bundler packages",
+                "Save gems cache",
+                "make test",
+            ]
+        );
+        let Step::SaveCache { save_cache, .. } = &config.jobs["test"].steps[2] else {
+            unreachable!();
+        };
+        assert_eq!(
+            save_cache.key.as_deref(),
+            Some(r#"gems-{{ arch }}-{{ checksum "Gemfile.lock" }}"#)
+        );
+        assert_eq!(save_cache.paths, ["vendor/bundle"]);
+    }
+
+    #[test]
+    fn test_package_path_and_overrides() {
+        let mut package = PackageSpec::from_name("node".to_string());
+        package.manager = Some("npm".to_string());
+        package.path = Some("docs/".to_string());
+        let mut config = config(vec![package]);
+        config.package_managers.insert(
+            "npm".to_string(),
+            PackageManagerDefinition {
+                install: Some("npm ci --ignore-scripts".to_string()),
+                cache_paths: Some(vec!["node_modules".to_string()]),
+                ..Default::default()
+            },
+        );
+        augment_with_packages(&mut config).unwrap();
+
+        let steps = &config.jobs["test"].steps;
+        let Step::RunWithOptions { run, .. } = &steps[1] else {
+            panic!("expected install step, got {:?}", steps[1]);
+        };
+        assert_eq!(run.command, "cd docs\nnpm ci --ignore-scripts");
+        let Step::SaveCache { save_cache, .. } = &steps[2] else {
+            panic!("expected save step, got {:?}", steps[2]);
+        };
+        assert_eq!(
+            save_cache.key.as_deref(),
+            Some(r#"npm-docs-{{ arch }}-{{ checksum "docs/package-lock.json" }}"#)
+        );
+        assert_eq!(save_cache.paths, ["docs/node_modules"]);
+    }
+
+    #[test]
+    fn test_missing_lockfile_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config(vec![PackageSpec::from_name("npm".to_string())]);
+        config.project_root = Some(dir.path().to_path_buf());
+
+        let error = augment_with_packages(&mut config).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "Job 'test' uses package 'npm', but package-lock.json doesn't exist"
+        );
+    }
+
+    #[test]
+    fn test_custom_manager_needs_lockfile_and_install() {
+        let mut config = config(vec![PackageSpec::from_name("mix".to_string())]);
+        config.package_managers.insert(
+            "mix".to_string(),
+            PackageManagerDefinition {
+                install: Some("mix deps.get".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let error = augment_with_packages(&mut config).unwrap_err().to_string();
+        assert!(error.contains("needs a lockfile"), "{error}");
+    }
+}
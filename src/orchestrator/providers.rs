@@ -0,0 +1,79 @@
+//! Per-provider settings in a multi-provider config
+//!
+//! A provider's own section (`circleci:`, `github:`) is merged over the shared
+//! top-level settings before the config goes to that provider's plugin, with
+//! the same `!` and `+` key directives as config fragments.
+
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+use std::collections::HashMap;
+
+use crate::loader::merge_values;
+use crate::schema::CigenConfig;
+
+/// `config` as `provider` sees it, with its section merged over the shared settings
+pub fn config_for_provider(config: &CigenConfig, provider: &str) -> Result<CigenConfig> {
+    let Some(section) = config.provider_config.get(provider) else {
+        return Ok(config.clone());
+    };
+    let Value::Mapping(settings) = section else {
+        anyhow::bail!("`{provider}:` must be a mapping of settings for the {provider} provider");
+    };
+
+    let mut provider_config = config.clone();
+    let mut raw = Value::Mapping(provider_config.raw);
+    merge_values(&mut raw, section.clone())
+        .with_context(|| format!("Failed to apply the `{provider}:` settings"))?;
+    let Value::Mapping(raw) = raw else {
+        unreachable!("merging a mapping into a mapping yields a mapping");
+    };
+    provider_config.raw = raw;
+
+    // Settings the core reads itself rather than leaving to the plugin
+    if let Some(env) = settings.get("env") {
+        let env: HashMap<String, String> = serde_yaml::from_value(env.clone())
+            .with_context(|| format!("Invalid `{provider}.env`"))?;
+        provider_config.env.extend(env);
+    }
+    if let Some(version) = settings.get("cache_version") {
+        provider_config.cache_version = serde_yaml::from_value(version.clone())
+            .with_context(|| format!("Invalid `{provider}.cache_version`"))?;
+    }
+    Ok(provider_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provider_sections_override_shared_settings() {
+        let raw: Value = serde_yaml::from_str(
+            "setup: {image: cimg/rust:1.88, resource_class: small}\ncache_version: 1\n",
+        )
+        .unwrap();
+        let config = CigenConfig {
+            raw: raw.as_mapping().unwrap().clone(),
+            cache_version: Some(1),
+            env: HashMap::from([("CI".to_string(), "true".to_string())]),
+            provider_config: HashMap::from([(
+                "circleci".to_string(),
+                serde_yaml::from_str(
+                    "setup: {resource_class: large}\ncache_version: 2\nenv: {TZ: UTC}\n",
+                )
+                .unwrap(),
+            )]),
+            ..Default::default()
+        };
+
+        let circleci = config_for_provider(&config, "circleci").unwrap();
+        assert_eq!(circleci.raw["setup"]["image"], "cimg/rust:1.88");
+        assert_eq!(circleci.raw["setup"]["resource_class"], "large");
+        assert_eq!(circleci.cache_version, Some(2));
+        assert_eq!(circleci.env["CI"], "true");
+        assert_eq!(circleci.env["TZ"], "UTC");
+
+        let github = config_for_provider(&config, "github").unwrap();
+        assert_eq!(github, config);
+    }
+}
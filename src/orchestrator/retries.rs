@@ -0,0 +1,219 @@
+//! `retry:` augmentation
+//!
+//! Neither provider can retry a single run step, so a step with a retry
+//! policy (its own, or its job's) has its command replaced by a bash loop.
+//! The command is written verbatim to a temporary script through a quoted
+//! heredoc, so quotes, `$` and its own heredocs reach bash untouched, and each
+//! attempt runs it with `bash -eo pipefail` in the step's environment.
+
+use crate::schema::{CigenConfig, RetryKind, RetryPolicy, RetryWhen, Step};
+
+const HEREDOC_DELIMITER: &str = "CIGEN_RETRY_EOF";
+
+/// Wrap the run steps of jobs, commands, and workflow `job_steps` that have a
+/// retry policy in a retry loop
+pub fn augment_with_retries(config: &mut CigenConfig) {
+    for job in config.jobs.values_mut() {
+        let job_retry = job.retry.take();
+        retry_steps(&mut job.steps, job_retry.as_ref());
+    }
+    for command in config.commands.values_mut() {
+        retry_steps(&mut command.steps, None);
+    }
+    for workflow in config.workflows.values_mut() {
+        for steps in workflow.job_steps.values_mut() {
+            retry_steps(&mut steps.pre_steps, None);
+            retry_steps(&mut steps.post_steps, None);
+        }
+    }
+}
+
+fn retry_steps(steps: &mut [Step], job_retry: Option<&RetryPolicy>) {
+    for step in steps {
+        match step {
+            Step::SimpleRun { run, .. } => {
+                if let Some(policy) = job_retry {
+                    *run = retry_command(run, policy);
+                }
+            }
+            Step::RunWithOptions { run, .. } => {
+                if let Some(policy) = run.retry.take().as_ref().or(job_retry) {
+                    run.command = retry_command(&run.command, policy);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `command` run up to `policy.max` more times while it fails in a way
+/// `policy.when` allows, exiting with the last attempt's status
+pub fn retry_command(command: &str, policy: &RetryPolicy) -> String {
+    let attempts = policy.max + 1;
+    let mut delimiter = HEREDOC_DELIMITER.to_string();
+    while command.lines().any(|line| line.trim() == delimiter) {
+        delimiter.push('_');
+    }
+
+    let mut lines = vec![
+        "cigen_retry_script=$(mktemp)".to_string(),
+        format!("cat > \"$cigen_retry_script\" <<'{delimiter}'"),
+        command.trim_end_matches('\n').to_string(),
+        delimiter,
+        format!("for cigen_retry_attempt in $(seq 1 {attempts}); do"),
+        "  cigen_retry_status=0".to_string(),
+        "  bash -eo pipefail \"$cigen_retry_script\" || cigen_retry_status=$?".to_string(),
+        format!(
+            "  if [ \"$cigen_retry_status\" -eq 0 ] || [ \"$cigen_retry_attempt\" -eq {attempts} ]; then"
+        ),
+        "    break".to_string(),
+        "  fi".to_string(),
+    ];
+    let any_failure =
+        policy.when.is_empty() || policy.when.contains(&RetryWhen::Kind(RetryKind::Failure));
+    if !any_failure {
+        let codes: Vec<String> = policy
+            .when
+            .iter()
+            .filter_map(|when| match when {
+                RetryWhen::ExitCode(code) => Some(code.to_string()),
+                RetryWhen::Kind(_) => None,
+            })
+            .collect();
+        lines.push(format!(
+            "  case \"$cigen_retry_status\" in {}) ;; *) break ;; esac",
+            codes.join("|")
+        ));
+    }
+    let backoff = match policy.backoff_seconds {
+        0 => String::new(),
+        seconds => format!(" in {seconds}s"),
+    };
+    lines.push(format!(
+        "  echo \"Attempt $cigen_retry_attempt of {attempts} failed with exit code $cigen_retry_status; retrying{backoff}\" >&2"
+    ));
+    if policy.backoff_seconds > 0 {
+        lines.push(format!("  sleep {}", policy.backoff_seconds));
+    }
+    lines.extend([
+        "done".to_string(),
+        "rm -f \"$cigen_retry_script\"".to_string(),
+        "exit \"$cigen_retry_status\"".to_string(),
+    ]);
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn policy(yaml: &str) -> RetryPolicy {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn run(script: &str) -> (i32, String) {
+        let output = Command::new("bash")
+            .args(["-eo", "pipefail", "-c", script])
+            .output()
+            .unwrap();
+        (
+            output.status.code().unwrap(),
+            String::from_utf8(output.stdout).unwrap(),
+        )
+    }
+
+    #[test]
+    fn multi_line_commands_with_quotes_and_heredocs_run_verbatim() {
+        let command = r#"name="it's here"
+echo "quoted: $name" 'single $x' "${name%% *}"
+cat <<EOF
+heredoc $((1 + 1))
+EOF
+"#;
+        let script = retry_command(command, &policy("max: 2"));
+        assert!(
+            script.starts_with(&format!(
+                "cigen_retry_script=$(mktemp)\ncat > \"$cigen_retry_script\" <<'CIGEN_RETRY_EOF'\n{command}CIGEN_RETRY_EOF\nfor cigen_retry_attempt in $(seq 1 3); do\n"
+            )),
+            "{script}"
+        );
+        assert_eq!(
+            run(&script),
+            (
+                0,
+                "quoted: it's here single $x it's\nheredoc 2\n".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn failures_are_retried_until_the_last_attempt() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("attempts");
+        let command = format!(
+            "echo attempt >> {path}\n[ \"$(wc -l < {path})\" -ge 3 ]\necho done\n",
+            path = counter.display()
+        );
+
+        let (status, stdout) = run(&retry_command(&command, &policy("max: 2")));
+        assert_eq!((status, stdout.as_str()), (0, "done\n"));
+
+        std::fs::remove_file(&counter).unwrap();
+        let (status, _) = run(&retry_command(&command, &policy("max: 1")));
+        assert_eq!(status, 1);
+        assert_eq!(
+            std::fs::read_to_string(&counter).unwrap().lines().count(),
+            2
+        );
+    }
+
+    #[test]
+    fn only_listed_exit_codes_are_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("attempts");
+        let command = format!("echo attempt >> {}\nexit 3\n", counter.display());
+
+        let (status, _) = run(&retry_command(&command, &policy("{max: 3, when: [75]}")));
+        assert_eq!(status, 3);
+        assert_eq!(
+            std::fs::read_to_string(&counter).unwrap().lines().count(),
+            1
+        );
+
+        let script = retry_command(&command, &policy("{max: 3, when: [3], backoff_seconds: 1}"));
+        assert!(script.contains("  case \"$cigen_retry_status\" in 3) ;; *) break ;; esac\n"));
+        assert!(script.contains("  sleep 1\n"));
+    }
+
+    #[test]
+    fn step_retry_overrides_the_job_retry() {
+        let mut config = CigenConfig::from_yaml(
+            r#"
+jobs:
+  test:
+    retry: {max: 1}
+    steps:
+      - run: bundle install
+      - run:
+          name: Flaky
+          command: bin/flaky
+          retry: {max: 4}
+      - restore_cache: {key: gems}
+"#,
+        )
+        .unwrap();
+        augment_with_retries(&mut config);
+        let job = &config.jobs["test"];
+        assert_eq!(job.retry, None);
+        let Step::SimpleRun { run, .. } = &job.steps[0] else {
+            panic!("expected a run step");
+        };
+        assert!(run.contains("$(seq 1 2)"), "{run}");
+        let Step::RunWithOptions { run, .. } = &job.steps[1] else {
+            panic!("expected a run step");
+        };
+        assert!(run.command.contains("$(seq 1 5)"), "{}", run.command);
+        assert_eq!(run.retry, None);
+    }
+}
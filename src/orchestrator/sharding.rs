@@ -0,0 +1,200 @@
+//! Splitting the generated jobs across several config files
+//!
+//! `cigen generate --shard-count N` keeps each file under the provider's
+//! config size limit. Jobs that depend on each other, directly or through
+//! other jobs, must share a file, so shards are made of whole connected
+//! components of the job DAG, balanced by job count.
+
+use anyhow::{Result, bail};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Plugin flag carrying the requested number of shards
+pub const SHARD_COUNT_FLAG: &str = "shard_count";
+
+/// Assign each job to a shard, numbered from 1. `dependencies` maps every job
+/// to the jobs it needs; needs that aren't jobs are ignored. There may be
+/// fewer shards than `shard_count` when there are fewer components.
+pub fn partition_jobs(
+    dependencies: &BTreeMap<String, Vec<String>>,
+    shard_count: usize,
+) -> Result<BTreeMap<String, usize>> {
+    if shard_count == 0 {
+        bail!("Shard count must be at least 1");
+    }
+
+    let mut components = connected_components(dependencies);
+    let total: usize = components.iter().map(Vec::len).sum();
+    let capacity = total.div_ceil(shard_count);
+    // Largest first, so the greedy fill below stays balanced
+    components.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+
+    if let Some(largest) = components.first()
+        && largest.len() > capacity
+    {
+        bail!(
+            "Jobs {} depend on each other, so they must be generated into one shard, but that is {} jobs and each of the {shard_count} shards holds about {capacity} ({total} jobs in all); use fewer shards",
+            sample(largest),
+            largest.len()
+        );
+    }
+
+    let mut sizes = vec![0; shard_count];
+    let mut shards = BTreeMap::new();
+    for component in components {
+        let (index, _) = sizes
+            .iter()
+            .enumerate()
+            .min_by_key(|(index, size)| (**size, *index))
+            .expect("at least one shard");
+        sizes[index] += component.len();
+        for job in component {
+            shards.insert(job, index + 1);
+        }
+    }
+    Ok(shards)
+}
+
+/// Groups of jobs linked by dependencies in either direction, each sorted
+fn connected_components(dependencies: &BTreeMap<String, Vec<String>>) -> Vec<Vec<String>> {
+    let mut neighbours: BTreeMap<&str, BTreeSet<&str>> = dependencies
+        .keys()
+        .map(|job| (job.as_str(), BTreeSet::new()))
+        .collect();
+    for (job, needs) in dependencies {
+        for need in needs.iter().filter(|need| dependencies.contains_key(*need)) {
+            neighbours.entry(job).or_default().insert(need);
+            neighbours.entry(need).or_default().insert(job);
+        }
+    }
+
+    let mut seen = BTreeSet::new();
+    let mut components = Vec::new();
+    for start in neighbours.keys() {
+        if !seen.insert(*start) {
+            continue;
+        }
+        let mut component = vec![start.to_string()];
+        let mut stack = vec![*start];
+        while let Some(job) = stack.pop() {
+            for next in &neighbours[job] {
+                if seen.insert(*next) {
+                    component.push(next.to_string());
+                    stack.push(next);
+                }
+            }
+        }
+        component.sort();
+        components.push(component);
+    }
+    components
+}
+
+/// The first few job names, for error messages
+fn sample(jobs: &[String]) -> String {
+    const SHOWN: usize = 3;
+    let names: Vec<String> = jobs
+        .iter()
+        .take(SHOWN)
+        .map(|job| format!("'{job}'"))
+        .collect();
+    match jobs.len().saturating_sub(SHOWN) {
+        0 => names.join(", "),
+        rest => format!("{} and {rest} more", names.join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dag(edges: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+        edges
+            .iter()
+            .map(|(job, needs)| {
+                (
+                    job.to_string(),
+                    needs.iter().map(|need| need.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn connected_jobs_share_a_shard() {
+        // Two chains of three, two chains of two and two lone jobs
+        let jobs = dag(&[
+            ("build", &[]),
+            ("test", &["build"]),
+            ("deploy", &["test"]),
+            ("lint", &[]),
+            ("docs", &["lint"]),
+            ("assets", &[]),
+            ("precompile", &[]),
+            ("cdn", &["assets", "precompile"]),
+            ("audit", &[]),
+            ("typecheck", &[]),
+            ("spell", &[]),
+        ]);
+
+        let shards = partition_jobs(&jobs, 3).unwrap();
+        assert_eq!(shards.len(), jobs.len());
+        for (job, needs) in &jobs {
+            for need in needs {
+                assert_eq!(shards[job], shards[need], "{job}");
+            }
+        }
+
+        let mut sizes = BTreeMap::new();
+        for shard in shards.values() {
+            *sizes.entry(*shard).or_insert(0) += 1;
+        }
+        assert_eq!(sizes, BTreeMap::from([(1, 4), (2, 4), (3, 3)]));
+    }
+
+    #[test]
+    fn partitions_are_deterministic() {
+        let jobs = dag(&[("a", &[]), ("b", &[]), ("c", &["b"]), ("d", &[])]);
+        assert_eq!(
+            partition_jobs(&jobs, 2).unwrap(),
+            BTreeMap::from([
+                ("a".to_string(), 2),
+                ("b".to_string(), 1),
+                ("c".to_string(), 1),
+                ("d".to_string(), 2),
+            ])
+        );
+    }
+
+    #[test]
+    fn fewer_components_than_shards_leaves_shards_unused() {
+        let jobs = dag(&[("lint", &[]), ("test", &[])]);
+        let shards = partition_jobs(&jobs, 4).unwrap();
+        assert_eq!(shards["lint"], 1);
+        assert_eq!(shards["test"], 2);
+    }
+
+    #[test]
+    fn needs_outside_the_jobs_are_ignored() {
+        let jobs = dag(&[("test", &["skipped_build"]), ("lint", &[])]);
+        let shards = partition_jobs(&jobs, 2).unwrap();
+        assert_ne!(shards["test"], shards["lint"]);
+    }
+
+    #[test]
+    fn oversized_component_is_an_error() {
+        let jobs = dag(&[
+            ("build", &[]),
+            ("rspec", &["build"]),
+            ("jest", &["build"]),
+            ("deploy", &["rspec", "jest"]),
+            ("e2e", &["build"]),
+            ("lint", &[]),
+        ]);
+
+        let error = partition_jobs(&jobs, 3).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "Jobs 'build', 'deploy', 'e2e' and 2 more depend on each other, so they must be generated into one shard, but that is 5 jobs and each of the 3 shards holds about 2 (6 jobs in all); use fewer shards"
+        );
+    }
+}
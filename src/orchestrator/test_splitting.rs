@@ -0,0 +1,226 @@
+//! `test_splitting:` augmentation
+//!
+//! A job that splits its tests gets a "Run split tests" step after its own
+//! steps, running `command_template` with `{files}` replaced by its
+//! container's share of the files matching `glob`. It's added before the
+//! global `after` steps, cache saves and retry wrapping, so it sits with the
+//! job's own steps and a job `retry:` covers it.
+//!
+//! How the files are shared out is up to the provider, so the step starts with
+//! a placeholder line that [`select_test_files`] replaces for each provider.
+
+use crate::schema::{CigenConfig, RunStepOptions, Step, shell_quote};
+
+/// Name of the step running the job's share of the tests
+pub const SPLIT_TESTS_STEP: &str = "Run split tests";
+
+/// Stands in for the provider's file selection until the provider is known
+const SELECT_TEST_FILES: &str = "# cigen: select this container's test files";
+
+/// Append the split tests step to every job with `test_splitting`
+pub fn augment_with_test_splitting(config: &mut CigenConfig) {
+    for job in config.jobs.values_mut() {
+        let Some(splitting) = &job.test_splitting else {
+            continue;
+        };
+        let command = format!(
+            "{SELECT_TEST_FILES}\n# shellcheck disable=SC2086\n{}\n",
+            splitting.command_template.replace("{files}", "$TEST_FILES")
+        );
+        job.steps.push(Step::RunWithOptions {
+            run: RunStepOptions {
+                name: Some(SPLIT_TESTS_STEP.to_string()),
+                command,
+                env: Default::default(),
+                condition: None,
+                retry: None,
+            },
+            condition: None,
+        });
+    }
+}
+
+/// Put `provider`'s file selection in the split tests steps. CircleCI splits
+/// with `circleci tests split`; GitHub deals the sorted files out round-robin
+/// by `matrix.shard`, since it keeps no timings. Other providers can't split
+/// tests, so the step is left out.
+pub fn select_test_files(config: &mut CigenConfig, provider: &str) {
+    for job in config.jobs.values_mut() {
+        let Some(splitting) = &job.test_splitting else {
+            continue;
+        };
+        let shards = job
+            .extra
+            .get("parallelism")
+            .and_then(|value| value.as_u64())
+            .unwrap_or(1);
+        let selection = match provider {
+            "circleci" => format!(
+                "TEST_FILES=$(circleci tests glob {} | circleci tests split --split-by={})",
+                shell_quote(&splitting.glob),
+                splitting.by.as_str()
+            ),
+            "github" => format!(
+                "shopt -s globstar nullglob\n\
+                 files=({})\n\
+                 TEST_FILES=$(printf '%s\\n' \"${{files[@]}}\" | sort | awk -v shard=\"${{{{ matrix.shard }}}}\" -v total={shards} 'NF && (NR - 1) % total == shard' | tr '\\n' ' ')",
+                splitting.glob
+            ),
+            _ => {
+                job.steps.retain(|step| !is_split_tests_step(step));
+                continue;
+            }
+        };
+        for step in job
+            .steps
+            .iter_mut()
+            .filter(|step| is_split_tests_step(step))
+        {
+            if let Step::RunWithOptions { run, .. } = step {
+                run.command = run.command.replace(SELECT_TEST_FILES, &selection);
+            }
+        }
+    }
+}
+
+fn is_split_tests_step(step: &Step) -> bool {
+    matches!(step, Step::RunWithOptions { run, .. } if run.command.contains(SELECT_TEST_FILES))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::caches::augment_with_caches;
+    use crate::orchestrator::retries::augment_with_retries;
+    use crate::schema::{CacheDefinition, Job, JobCache, RetryPolicy, SplitBy, TestSplitting};
+    use serde_yaml::Value;
+    use std::collections::HashMap;
+
+    fn rspec() -> CigenConfig {
+        let job = Job {
+            steps: vec![Step::SimpleRun {
+                run: "bundle install".to_string(),
+                condition: None,
+            }],
+            cache: vec![JobCache {
+                name: "gems".to_string(),
+                paths: Vec::new(),
+                restore: true,
+                save: true,
+                save_when: None,
+            }],
+            test_splitting: Some(TestSplitting {
+                glob: "spec/**/*_spec.rb".to_string(),
+                by: SplitBy::Timings,
+                command_template: "bundle exec rspec {files}".to_string(),
+            }),
+            extra: HashMap::from([("parallelism".to_string(), Value::from(3))]),
+            ..Default::default()
+        };
+        CigenConfig {
+            caches: HashMap::from([(
+                "gems".to_string(),
+                CacheDefinition {
+                    paths: vec!["vendor/bundle".to_string()],
+                    ..Default::default()
+                },
+            )]),
+            jobs: HashMap::from([("rspec".to_string(), job)]),
+            ..Default::default()
+        }
+    }
+
+    fn step_names(config: &CigenConfig) -> Vec<String> {
+        config.jobs["rspec"]
+            .steps
+            .iter()
+            .map(|step| match step {
+                Step::SimpleRun { run, .. } => run.clone(),
+                Step::RunWithOptions { run, .. } => run.name.clone().unwrap(),
+                Step::RestoreCache { .. } => "restore".to_string(),
+                Step::SaveCache { .. } => "save".to_string(),
+                other => panic!("unexpected step {other:?}"),
+            })
+            .collect()
+    }
+
+    fn split_command(config: &CigenConfig) -> &str {
+        config.jobs["rspec"]
+            .steps
+            .iter()
+            .find_map(|step| match step {
+                Step::RunWithOptions { run, .. } => Some(run.command.as_str()),
+                _ => None,
+            })
+            .expect("split tests step")
+    }
+
+    #[test]
+    fn split_tests_run_before_cache_saves() {
+        let mut config = rspec();
+        augment_with_test_splitting(&mut config);
+        augment_with_caches(&mut config).unwrap();
+
+        assert_eq!(
+            step_names(&config),
+            ["restore", "bundle install", SPLIT_TESTS_STEP, "save"]
+        );
+    }
+
+    #[test]
+    fn job_retry_covers_the_split_tests() {
+        let mut config = rspec();
+        config.jobs.get_mut("rspec").unwrap().retry = Some(RetryPolicy {
+            max: 2,
+            when: Vec::new(),
+            backoff_seconds: 0,
+        });
+        augment_with_test_splitting(&mut config);
+        augment_with_retries(&mut config);
+        select_test_files(&mut config, "circleci");
+
+        let command = split_command(&config);
+        assert!(
+            command.starts_with("cigen_retry_script=$(mktemp)"),
+            "{command}"
+        );
+        assert!(
+            command.contains("circleci tests split --split-by=timings"),
+            "{command}"
+        );
+    }
+
+    #[test]
+    fn each_provider_selects_its_own_files() {
+        let selected = |provider: &str| {
+            let mut config = rspec();
+            augment_with_test_splitting(&mut config);
+            select_test_files(&mut config, provider);
+            split_command(&config).to_string()
+        };
+
+        assert_eq!(
+            selected("circleci"),
+            "TEST_FILES=$(circleci tests glob 'spec/**/*_spec.rb' | circleci tests split --split-by=timings)\n\
+             # shellcheck disable=SC2086\n\
+             bundle exec rspec $TEST_FILES\n"
+        );
+        assert_eq!(
+            selected("github"),
+            "shopt -s globstar nullglob\n\
+             files=(spec/**/*_spec.rb)\n\
+             TEST_FILES=$(printf '%s\\n' \"${files[@]}\" | sort | awk -v shard=\"${{ matrix.shard }}\" -v total=3 'NF && (NR - 1) % total == shard' | tr '\\n' ' ')\n\
+             # shellcheck disable=SC2086\n\
+             bundle exec rspec $TEST_FILES\n"
+        );
+    }
+
+    #[test]
+    fn providers_that_cant_split_leave_the_step_out() {
+        let mut config = rspec();
+        augment_with_test_splitting(&mut config);
+        select_test_files(&mut config, "woodpecker");
+
+        assert_eq!(step_names(&config), ["bundle install"]);
+    }
+}
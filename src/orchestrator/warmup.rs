@@ -0,0 +1,353 @@
+//! `cache_warmup:` workflow
+//!
+//! A scheduled workflow with one `warm_<cache>` job per listed cache. Each
+//! job installs the way the cache's consumers do: a cache with a
+//! `warmup_command` is listed in the job's `cache:` around that command, and
+//! a package manager's cache comes from the same entry in `packages:`. The
+//! jobs are added before packages and caches are expanded, so their restore
+//! and save keys come from the same code as every other job's.
+//!
+//! CircleCI runs the workflow when the `cache_warmup` pipeline parameter is
+//! true, which the project's scheduled pipeline sets, and every other
+//! workflow unless it is. GitHub Actions has no equivalent of the jobs'
+//! `restore_cache`/`save_cache` steps, so its plugin leaves the workflow out.
+
+use anyhow::{Result, bail};
+use serde_yaml::{Mapping, Value};
+
+use super::packages::package_cache;
+use crate::schema::{
+    CigenConfig, DEFAULT_WORKFLOW, Job, JobCache, PackageSpec, RunStepOptions, Step,
+    WorkflowCondition, WorkflowConfig,
+};
+
+/// Workflow the warm-up jobs run in
+pub const WARMUP_WORKFLOW: &str = "cache_warmup";
+
+/// CircleCI pipeline parameter that runs the warm-up workflow
+pub const WARMUP_PARAMETER: &str = "cache_warmup";
+
+/// Add the warm-up workflow and its jobs for `cache_warmup:`
+pub fn augment_with_cache_warmup(config: &mut CigenConfig) -> Result<()> {
+    let Some(warmup) = config.cache_warmup.clone() else {
+        return Ok(());
+    };
+    if warmup.caches.is_empty() {
+        bail!("cache_warmup lists no caches");
+    }
+    if config.workflows.contains_key(WARMUP_WORKFLOW)
+        || config
+            .jobs
+            .values()
+            .any(|job| job.workflow.as_deref() == Some(WARMUP_WORKFLOW))
+    {
+        bail!(
+            "Workflow '{WARMUP_WORKFLOW}' conflicts with the workflow generated for cache_warmup"
+        );
+    }
+
+    // The scheduled pipeline only warms caches
+    let workflows: Vec<String> = config
+        .jobs
+        .values()
+        .map(|job| {
+            job.workflow
+                .as_deref()
+                .unwrap_or(DEFAULT_WORKFLOW)
+                .to_string()
+        })
+        .collect();
+    for workflow in workflows {
+        config.workflows.entry(workflow).or_default();
+    }
+    for workflow in config.workflows.values_mut() {
+        workflow.run_unless.push(warmup_condition());
+    }
+
+    for cache in &warmup.caches {
+        let job_id = format!("warm_{cache}");
+        if config.jobs.contains_key(&job_id) {
+            bail!("Job '{job_id}' conflicts with the job generated to warm cache '{cache}'");
+        }
+        let job = warmup_job(config, cache)?;
+        config.jobs.insert(job_id, job);
+    }
+    config.workflows.insert(
+        WARMUP_WORKFLOW.to_string(),
+        warmup_workflow(&warmup.schedule),
+    );
+    declare_parameter(&mut config.raw);
+    Ok(())
+}
+
+/// Where a cache's contents come from
+enum Install {
+    /// The cache definition's `warmup_command`, around the consumer's entry
+    Command(String, JobCache),
+    /// The consumer's package manager entry
+    Package(PackageSpec),
+}
+
+fn warmup_job(config: &CigenConfig, cache: &str) -> Result<Job> {
+    let command = config
+        .caches
+        .get(cache)
+        .and_then(|definition| definition.warmup_command.clone());
+
+    // The first job, by name, that uses the cache
+    let mut job_ids: Vec<&String> = config.jobs.keys().collect();
+    job_ids.sort();
+    let mut consumer = None;
+    for job_id in job_ids {
+        let job = &config.jobs[job_id];
+        let install = match &command {
+            Some(command) => job
+                .cache
+                .iter()
+                .find(|entry| entry.name == cache)
+                .map(|entry| Install::Command(command.clone(), entry.clone())),
+            None => {
+                let mut found = None;
+                for package in &job.packages {
+                    if package_cache(package, &config.package_managers)?.as_deref() == Some(cache) {
+                        found = Some(Install::Package(package.clone()));
+                        break;
+                    }
+                }
+                found
+            }
+        };
+        if let Some(install) = install {
+            consumer = Some((job, install));
+            break;
+        }
+    }
+    let Some((consumer, install)) = consumer else {
+        if command.is_some() {
+            bail!("Cache '{cache}' in cache_warmup isn't used by any job");
+        }
+        bail!(
+            "Cache '{cache}' in cache_warmup isn't installed by any job's packages; \
+             set warmup_command on its definition in caches:"
+        );
+    };
+
+    // Other packages set up the toolchain (e.g. `ruby` before `bundler`)
+    let mut packages = Vec::new();
+    for package in &consumer.packages {
+        if package_cache(package, &config.package_managers)?.is_none() {
+            packages.push(package.clone());
+        }
+    }
+    let mut job = Job {
+        image: consumer.image.clone(),
+        runner: consumer.runner.clone(),
+        architecture: consumer.architecture.clone(),
+        executor: consumer.executor.clone(),
+        working_directory: consumer.working_directory.clone(),
+        environment: consumer.environment.clone(),
+        workflow: Some(WARMUP_WORKFLOW.to_string()),
+        ..Default::default()
+    };
+    match install {
+        Install::Command(command, entry) => {
+            job.packages = packages;
+            job.cache = vec![JobCache {
+                restore: true,
+                save: true,
+                save_when: None,
+                ..entry
+            }];
+            job.steps = vec![Step::RunWithOptions {
+                run: RunStepOptions {
+                    name: Some(format!("Warm {cache} cache")),
+                    command,
+                    env: Default::default(),
+                    condition: None,
+                    retry: None,
+                },
+                condition: None,
+            }];
+        }
+        Install::Package(package) => {
+            packages.push(package);
+            job.packages = packages;
+        }
+    }
+    Ok(job)
+}
+
+fn warmup_workflow(schedule: &str) -> WorkflowConfig {
+    let mut trigger = Mapping::new();
+    let mut cron = Mapping::new();
+    cron.insert("cron".into(), schedule.into());
+    trigger.insert(
+        "schedule".into(),
+        Value::Sequence(vec![Value::Mapping(cron)]),
+    );
+    trigger.insert("workflow_dispatch".into(), Value::Mapping(Mapping::new()));
+    let mut raw = Mapping::new();
+    raw.insert("on".into(), Value::Mapping(trigger));
+
+    WorkflowConfig {
+        run_when: vec![warmup_condition()],
+        raw: Value::Mapping(raw),
+        ..Default::default()
+    }
+}
+
+/// The `cache_warmup` pipeline parameter is true, on CircleCI
+fn warmup_condition() -> WorkflowCondition {
+    WorkflowCondition {
+        provider: Some("circleci".to_string()),
+        parameter: Some(WARMUP_PARAMETER.to_string()),
+        equals: Some(Value::Bool(true)),
+        ..Default::default()
+    }
+}
+
+/// Declare the boolean pipeline parameter, unless the config already does
+fn declare_parameter(raw: &mut Mapping) {
+    let parameters = raw
+        .entry("parameters".into())
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    if let Value::Mapping(parameters) = parameters
+        && !parameters.contains_key(WARMUP_PARAMETER)
+    {
+        let mut parameter = Mapping::new();
+        parameter.insert("type".into(), "boolean".into());
+        parameter.insert("default".into(), false.into());
+        parameters.insert(WARMUP_PARAMETER.into(), Value::Mapping(parameter));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::caches::augment_with_caches;
+    use crate::orchestrator::packages::augment_with_packages;
+    use crate::schema::{CacheDefinition, CacheWarmup};
+    use std::collections::HashMap;
+
+    fn consumer(cache: Vec<JobCache>, packages: &[&str]) -> Job {
+        Job {
+            image: "cimg/ruby:3.3".to_string(),
+            cache,
+            packages: packages
+                .iter()
+                .map(|name| PackageSpec::from_name(name.to_string()))
+                .collect(),
+            workflow: Some("ci".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn config(jobs: Vec<(&str, Job)>, caches: &[&str]) -> CigenConfig {
+        CigenConfig {
+            jobs: jobs
+                .into_iter()
+                .map(|(id, job)| (id.to_string(), job))
+                .collect(),
+            cache_warmup: Some(CacheWarmup {
+                schedule: "0 4 * * *".to_string(),
+                caches: caches.iter().map(|cache| cache.to_string()).collect(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Keys of every restore_cache and save_cache step, in order
+    fn cache_keys(job: &Job) -> Vec<String> {
+        job.steps
+            .iter()
+            .filter_map(|step| match step {
+                Step::RestoreCache { restore_cache, .. } => {
+                    Some(format!("{:?} {:?}", restore_cache.key, restore_cache.keys))
+                }
+                Step::SaveCache { save_cache, .. } => Some(format!("{:?}", save_cache.key)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn warmup_jobs_use_the_consumers_keys() {
+        let mut config = config(
+            vec![
+                ("rspec", consumer(vec![], &["ruby", "bundler"])),
+                ("jest", consumer(vec![JobCache::named("node_modules")], &[])),
+            ],
+            &["gems", "node_modules"],
+        );
+        config.caches = HashMap::from([(
+            "node_modules".to_string(),
+            CacheDefinition {
+                paths: vec!["node_modules".to_string()],
+                checksum_sources: vec!["package-lock.json".to_string()],
+                warmup_command: Some("npm ci".to_string()),
+                ..Default::default()
+            },
+        )]);
+        let project = tempfile::tempdir().unwrap();
+        std::fs::write(project.path().join("Gemfile.lock"), "").unwrap();
+        std::fs::write(project.path().join("package-lock.json"), "").unwrap();
+        config.project_root = Some(project.path().to_path_buf());
+
+        augment_with_cache_warmup(&mut config).unwrap();
+        augment_with_packages(&mut config).unwrap();
+        augment_with_caches(&mut config).unwrap();
+
+        let gems = &config.jobs["warm_gems"];
+        assert_eq!(gems.workflow.as_deref(), Some(WARMUP_WORKFLOW));
+        assert_eq!(gems.image, "cimg/ruby:3.3");
+        assert!(!cache_keys(gems).is_empty());
+        assert_eq!(cache_keys(gems), cache_keys(&config.jobs["rspec"]));
+
+        let node_modules = &config.jobs["warm_node_modules"];
+        assert!(!cache_keys(node_modules).is_empty());
+        assert_eq!(cache_keys(node_modules), cache_keys(&config.jobs["jest"]));
+        assert!(node_modules.steps.iter().any(|step| matches!(
+            step,
+            Step::RunWithOptions { run, .. } if run.command == "npm ci"
+        )));
+
+        let workflow = &config.workflows[WARMUP_WORKFLOW];
+        assert_eq!(
+            serde_yaml::to_string(&workflow.raw).unwrap(),
+            "on:\n  schedule:\n  - cron: 0 4 * * *\n  workflow_dispatch: {}\n"
+        );
+        assert_eq!(
+            config.raw["parameters"][WARMUP_PARAMETER]["type"],
+            Value::from("boolean")
+        );
+
+        // The jobs' own workflow is skipped when the parameter is set
+        let ci = &config.workflows["ci"];
+        assert_eq!(ci.run_unless.len(), 1);
+        assert_eq!(
+            ci.run_unless[0].parameter.as_deref(),
+            Some(WARMUP_PARAMETER)
+        );
+        assert!(workflow.run_unless.is_empty());
+    }
+
+    #[test]
+    fn caches_without_an_install_are_rejected() {
+        let mut unused = config(vec![("lint", consumer(vec![], &[]))], &["gems"]);
+        let error = augment_with_cache_warmup(&mut unused).unwrap_err();
+        assert!(error.to_string().contains("set warmup_command"), "{error}");
+
+        let mut conflict = config(
+            vec![
+                ("rspec", consumer(vec![], &["bundler"])),
+                ("warm_gems", consumer(vec![], &[])),
+            ],
+            &["gems"],
+        );
+        let error = augment_with_cache_warmup(&mut conflict).unwrap_err();
+        assert!(
+            error.to_string().contains("Job 'warm_gems' conflicts"),
+            "{error}"
+        );
+    }
+}
@@ -1,13 +1,40 @@
-use anyhow::{Context, Result, bail};
-use std::collections::HashMap;
+use anyhow::{Context, Result, anyhow, bail};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::path::PathBuf;
-
+use std::time::{Duration, Instant};
+
+use crate::image_registry::ImageRegistry;
+use crate::orbs::apply_lockfile;
+use crate::path_filter::skip_jobs;
+use crate::plugin::capabilities::{
+    Degradation, ProviderCapabilities, find_degradations, render_degradations,
+};
+use crate::plugin::diagnostics::render_diagnostic;
+use crate::plugin::discovery::resolve_plugin;
+use crate::plugin::logging::LogFormat;
 use crate::plugin::manager::PluginManager;
-use crate::plugin::protocol::{GenerateRequest, PlanRequest};
-use crate::schema::CigenConfig;
-
+use crate::plugin::protocol::{
+    CigenSchema, Diagnostic, GenerateRequest, GenerateResult, PlanRequest, diagnostic::Level,
+};
+use crate::report::{Phase, PhaseTiming};
+use crate::schema::{CigenConfig, DEFAULT_WORKFLOW, unknown_reference_message};
+use crate::templating::{JobMetadata, TemplateEngine};
+
+use super::caches::{
+    NO_JOB_STATUS_CACHE_FLAG, augment_with_caches, bust_caches, check_cache_nonce,
+    normalize_cache_paths,
+};
 use super::convert::config_to_proto;
 use super::dag::JobDAG;
+use super::docker_build::augment_with_docker_build;
+use super::global_steps::augment_with_global_steps;
+use super::groups::augment_with_groups;
+use super::packages::augment_with_packages;
+use super::providers::config_for_provider;
+use super::retries::augment_with_retries;
+use super::sharding::{SHARD_COUNT_FLAG, partition_jobs};
+use super::test_splitting::{augment_with_test_splitting, select_test_files};
+use super::warmup::augment_with_cache_warmup;
 
 /// Main orchestrator for the cigen workflow
 pub struct WorkflowOrchestrator {
@@ -15,6 +42,20 @@ pub struct WorkflowOrchestrator {
     plugin_manager: PluginManager,
     /// Base directory for plugin binaries
     plugin_dir: PathBuf,
+    /// Only generate this workflow
+    workflow: Option<String>,
+    /// Flags forwarded to plugins in each PlanRequest
+    flags: HashMap<String, String>,
+    /// Split the jobs across this many config files
+    shard_count: Option<usize>,
+    /// Registry checked for published `docker_build` images
+    image_registry: Option<Box<dyn ImageRegistry>>,
+    /// Prefix for every cache key, so the run starts from empty caches
+    cache_nonce: Option<String>,
+    /// Final job names to leave out of the generated workflows
+    skipped_jobs: BTreeSet<String>,
+    /// Fail when a provider doesn't support a feature a job uses natively
+    strict_capabilities: bool,
 }
 
 impl WorkflowOrchestrator {
@@ -23,16 +64,124 @@ impl WorkflowOrchestrator {
         Self {
             plugin_manager: PluginManager::new(),
             plugin_dir,
+            workflow: None,
+            flags: HashMap::new(),
+            shard_count: None,
+            image_registry: None,
+            cache_nonce: None,
+            skipped_jobs: BTreeSet::new(),
+            strict_capabilities: false,
         }
     }
 
-    /// Execute the full workflow: detect → plan → generate → merge
-    pub async fn execute(&mut self, mut config: CigenConfig) -> Result<GenerationResult> {
-        // 1. Build DAG from job definitions (expands matrix and resolves dependencies)
+    /// Forward a flag (e.g. `validate_with_cli`) to every plugin
+    pub fn set_flag(&mut self, name: &str, value: &str) {
+        self.flags.insert(name.to_string(), value.to_string());
+    }
+
+    /// Restrict generation to a single workflow
+    pub fn set_workflow(&mut self, workflow: String) {
+        self.workflow = Some(workflow);
+    }
+
+    /// Split the generated jobs across `count` config files, keeping jobs that
+    /// depend on each other in the same one
+    pub fn set_shard_count(&mut self, count: usize) {
+        self.shard_count = Some(count);
+        self.set_flag(SHARD_COUNT_FLAG, &count.to_string());
+    }
+
+    /// Only add `docker_build` jobs for images `registry` doesn't have yet
+    pub fn set_image_registry(&mut self, registry: Box<dyn ImageRegistry>) {
+        self.image_registry = Some(registry);
+    }
+
+    /// Leave the job-status cache steps out of the generated jobs, so every
+    /// job runs and nothing records its status
+    pub fn disable_job_status_cache(&mut self) {
+        self.set_flag(NO_JOB_STATUS_CACHE_FLAG, "true");
+    }
+
+    /// Prefix every cache key in the jobs with `nonce`
+    pub fn set_cache_nonce(&mut self, nonce: String) -> Result<()> {
+        check_cache_nonce(&nonce)?;
+        self.cache_nonce = Some(nonce);
+        Ok(())
+    }
+
+    /// Leave these jobs out, by their final names after matrix expansion. Jobs
+    /// that needed them inherit their needs instead.
+    pub fn skip_jobs(&mut self, jobs: impl IntoIterator<Item = String>) {
+        self.skipped_jobs.extend(jobs);
+    }
+
+    /// Fail instead of reporting when a provider would emulate, pass through,
+    /// or drop a feature a job uses
+    pub fn set_strict_capabilities(&mut self, strict: bool) {
+        self.strict_capabilities = strict;
+    }
+
+    /// Restart a crashed plugin once and replay the request (enabled by default)
+    pub fn set_plugin_retry(&mut self, retry: bool) {
+        self.plugin_manager.set_retry_crashed(retry);
+    }
+
+    /// The config as providers receive it: generated jobs and steps added,
+    /// matrices expanded into one job per variant with templates rendered for
+    /// it, and `needs` resolved to those variants
+    pub fn expand_jobs(&self, mut config: CigenConfig) -> Result<CigenConfig> {
+        // Orb versions from .cigen/orbs.lock.yml take precedence over floating pins
+        apply_lockfile(&mut config).context("Failed to apply the orb lockfile")?;
+
+        // 1. Add docker_build jobs and point consumers at the built images
+        augment_with_docker_build(&mut config, self.image_registry.as_deref())
+            .context("Failed to generate docker_build jobs")?;
+        augment_with_cache_warmup(&mut config)?;
+        augment_with_test_splitting(&mut config);
+        augment_with_global_steps(&mut config);
+        augment_with_packages(&mut config)?;
+        augment_with_caches(&mut config)?;
+        if let Some(nonce) = &self.cache_nonce {
+            bust_caches(&mut config, nonce);
+        }
+        augment_with_retries(&mut config);
+        augment_with_groups(&mut config)?;
+        if let Some(workflow) = &self.workflow {
+            restrict_to_workflow(&mut config, workflow)?;
+        }
+
+        // Build DAG from job definitions (expands matrix and resolves dependencies)
         let dag = JobDAG::build(&config)
             .context("Failed to build dependency graph from job definitions")?;
 
         // 2. Reconstruct config with expanded jobs for the plugin
+        let mut templates =
+            TemplateEngine::new(&config.vars).with_version_sources(&config.version_sources);
+        if let Some(root) = &config.project_root {
+            templates = templates.with_project_root(root);
+        }
+        templates
+            .render_env(&mut config.env, None)
+            .context("Invalid `env` in config.yml")?;
+        for (provider, section) in config.provider_config.iter_mut() {
+            let Some(env) = section.get_mut("env") else {
+                continue;
+            };
+            let written: HashMap<String, String> = serde_yaml::from_value(env.clone())
+                .with_context(|| format!("Invalid `{provider}.env`"))?;
+            let mut rendered = written.clone();
+            templates
+                .render_env(&mut rendered, None)
+                .with_context(|| format!("Invalid `{provider}.env`"))?;
+            if rendered != written {
+                *env = serde_yaml::to_value(rendered)?;
+            }
+        }
+        for (workflow_name, workflow) in config.workflows.iter_mut() {
+            templates
+                .render_env(&mut workflow.env, Some(workflow_name))
+                .with_context(|| format!("Invalid `env` in workflow '{workflow_name}'"))?;
+        }
         let mut expanded_jobs = HashMap::new();
         for (instance_id, concrete_job) in dag.jobs() {
             let mut job = concrete_job.job.clone();
@@ -40,79 +189,185 @@ impl WorkflowOrchestrator {
             job.matrix = None;
             // Ensure stage is set to the concrete stage
             job.stage = Some(concrete_job.stage.clone());
+            // Matrix `arch` dimensions select the job's architecture
+            if let Some(arch) = concrete_job.architecture() {
+                job.architecture = Some(arch.to_string());
+            }
+            // Render templates per variant so job metadata reflects this instance
+            let metadata = JobMetadata {
+                job_name: instance_id.clone(),
+                workflow_name: job
+                    .workflow
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_WORKFLOW.to_string()),
+                architecture: job.architecture.clone(),
+                matrix: concrete_job.matrix_values.clone(),
+            };
+            let mut job = templates.render_job(&job, &metadata)?;
+            job.job_id = Some(concrete_job.job_id.clone());
+            job.matrix_job = concrete_job.matrix_job.clone();
+            job.matrix_values = concrete_job.matrix_values.clone();
 
             expanded_jobs.insert(instance_id.clone(), job);
         }
+        if let Some(shard_count) = self.shard_count {
+            let dependencies = dag
+                .jobs()
+                .keys()
+                .map(|instance_id| (instance_id.clone(), dag.get_dependencies(instance_id)))
+                .collect();
+            for (instance_id, shard) in partition_jobs(&dependencies, shard_count)? {
+                if let Some(job) = expanded_jobs.get_mut(&instance_id) {
+                    job.shard = Some(shard as u32);
+                }
+            }
+        }
         config.jobs = expanded_jobs;
+        Ok(config)
+    }
+
+    /// Execute the full workflow: detect → plan → generate → merge
+    pub async fn execute(&mut self, config: CigenConfig) -> Result<GenerationResult> {
+        let validate_start = Instant::now();
+        let mut config = self.expand_jobs(config)?;
+        let mut skipped_jobs = Vec::new();
+        if !self.skipped_jobs.is_empty() {
+            let (summary, unknown) = skip_jobs(&mut config, &self.skipped_jobs);
+            for name in unknown {
+                tracing::warn!("Not skipping '{name}': no job has that name");
+            }
+            tracing::info!(
+                "Skipping {} job(s): {}",
+                summary.excluded.len(),
+                summary.excluded.join(", ")
+            );
+            skipped_jobs = summary.excluded;
+        }
 
         // 3. Convert config to protobuf
         let proto_schema = config_to_proto(&config);
+        let mut jobs_by_workflow: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for job in &proto_schema.jobs {
+            jobs_by_workflow
+                .entry(job.workflow.clone())
+                .or_default()
+                .push(job.id.clone());
+        }
+        for jobs in jobs_by_workflow.values_mut() {
+            jobs.sort();
+        }
+        let validate_time = validate_start.elapsed();
+        let generate_start = Instant::now();
+        let mut provider_validate_time = Duration::ZERO;
+        let mut diagnostics = Vec::new();
 
         // 4. Detect which plugins are needed
         let providers = self.detect_providers(&config);
 
         // 5. Spawn plugins
-        let plugin_ids = self.spawn_plugins(&providers).await?;
-
-        // 6. For each plugin, execute plan → generate workflow
-        let mut all_fragments = Vec::new();
-        for plugin_id in &plugin_ids {
-            // Send PlanRequest
-            let plan_request = PlanRequest {
-                capabilities: vec![],  // TODO: Collect from all plugins
-                facts: HashMap::new(), // TODO: Implement detect phase
-                schema: Some(proto_schema.clone()),
-                flags: HashMap::new(),
-                repo: None, // TODO: Add repository snapshot
-            };
-
-            let plan_result = self
-                .plugin_manager
-                .send_plan(plugin_id, plan_request)
-                .await
-                .with_context(|| format!("Failed to send plan request to plugin '{plugin_id}'"))?;
-
-            tracing::info!(
-                "Plugin '{}' returned {} resources",
-                plugin_id,
-                plan_result.resources.len()
+        let plugin_ids = self.spawn_plugins(&providers, &config).await?;
+
+        // 6. Providers are independent, so each plugin plans and generates on
+        // its own task. A plugin offering several providers handles them in turn.
+        let mut requests: BTreeMap<String, Vec<ProviderRequest>> = BTreeMap::new();
+        let mut degradations = Vec::new();
+        for (index, (provider, plugin_id)) in plugin_ids.into_iter().enumerate() {
+            let mut provider_config = config_for_provider(&config, &provider)?;
+            normalize_cache_paths(&mut provider_config, &provider)?;
+            select_test_files(&mut provider_config, &provider);
+            let schema = config_to_proto(&provider_config);
+            if let Some(capabilities) =
+                self.plugin_manager
+                    .metadata(&plugin_id)
+                    .and_then(|metadata| {
+                        ProviderCapabilities::from_capabilities(&metadata.capabilities)
+                    })
+            {
+                degradations.extend(find_degradations(&provider, &capabilities, &schema));
+            }
+            requests
+                .entry(plugin_id.clone())
+                .or_default()
+                .push(ProviderRequest {
+                    index,
+                    provider,
+                    plugin_id,
+                    schema,
+                });
+        }
+        if self.strict_capabilities && !degradations.is_empty() {
+            bail!(
+                "{}\n(--strict-capabilities turns these into errors)",
+                render_degradations(&degradations)
             );
+        }
+        let mut tasks = Vec::new();
+        for (plugin_id, requests) in requests {
+            let mut manager = self.plugin_manager.detach(&plugin_id)?;
+            let flags = self.flags.clone();
+            tasks.push(tokio::spawn(async move {
+                let mut outcomes = Vec::new();
+                for request in requests {
+                    let outcome = generate_with_provider(&mut manager, &request, &flags).await;
+                    outcomes.push((request.index, request.plugin_id, outcome));
+                }
+                (manager, outcomes)
+            }));
+        }
+        let mut outcomes = Vec::new();
+        for task in tasks {
+            let (manager, plugin_outcomes) = task.await.context("Provider generation panicked")?;
+            self.plugin_manager.absorb(manager);
+            outcomes.extend(plugin_outcomes);
+        }
+        outcomes.sort_by_key(|(index, ..)| *index);
 
-            // Send GenerateRequest
-            let generate_request = GenerateRequest {
-                target: extract_provider_name(plugin_id),
-                graph: plan_result.resources,
-                work_signatures: HashMap::new(), // TODO: Compute work signatures
-                schema: Some(proto_schema.clone()),
-                facts: HashMap::new(),
+        // One provider failing doesn't stop the others; every failure is reported
+        let mut failures = Vec::new();
+        let mut all_fragments = Vec::new();
+        for (_, plugin_id, outcome) in outcomes {
+            let generate_result = match outcome {
+                Ok(generate_result) => generate_result,
+                Err(error) => {
+                    failures.push(error);
+                    continue;
+                }
             };
 
-            let generate_result = self
-                .plugin_manager
-                .send_generate(plugin_id, generate_request)
-                .await
-                .with_context(|| {
-                    format!("Failed to send generate request to plugin '{plugin_id}'")
-                })?;
-
-            tracing::info!(
+            tracing::debug!(
                 "Plugin '{}' generated {} fragments",
                 plugin_id,
                 generate_result.fragments.len()
             );
 
-            if !generate_result.diagnostics.is_empty() {
-                let mut has_errors = false;
-                for diag in generate_result.diagnostics {
-                    eprintln!("Plugin diagnostic: [{}] {}", diag.code, diag.message);
-                    if diag.level == 1 {
-                        // Error
+            // Providers validate at the same time, so the slowest one counts
+            if let Some(millis) = generate_result
+                .phase_millis
+                .get(Phase::ProviderValidate.as_str())
+            {
+                provider_validate_time = provider_validate_time.max(Duration::from_millis(*millis));
+            }
+
+            let mut has_errors = false;
+            for diag in generate_result.diagnostics {
+                let rendered = render_diagnostic(&diag);
+                match diag.level() {
+                    // Errors are printed even with --quiet
+                    Level::Error => {
+                        match LogFormat::current() {
+                            LogFormat::Text => eprintln!("{rendered}"),
+                            LogFormat::Json => tracing::error!("{rendered}"),
+                        }
                         has_errors = true;
                     }
+                    Level::Warning => tracing::warn!("{rendered}"),
+                    Level::Info | Level::Unspecified => tracing::info!("{rendered}"),
                 }
-                if has_errors {
-                    bail!("Plugin '{}' reported errors", plugin_id);
-                }
+                diagnostics.push(diag);
+            }
+            if has_errors {
+                failures.push(anyhow!("Plugin '{plugin_id}' reported errors"));
+                continue;
             }
 
             // Collect fragments
@@ -133,15 +388,30 @@ impl WorkflowOrchestrator {
         }
 
         // 7. Shutdown all plugins
-        self.plugin_manager
-            .shutdown()
-            .await
-            .context("Failed to shutdown plugins")?;
+        let shutdown = self.plugin_manager.shutdown().await;
+        combine_failures(failures)?;
+        shutdown.context("Failed to shutdown plugins")?;
 
         // 8. Merge fragments and write files
         let files = merge_fragments(all_fragments)?;
 
-        Ok(GenerationResult { files })
+        // Plugins validate as they generate, so their validation time is
+        // taken out of the generate phase
+        let generate_time = generate_start
+            .elapsed()
+            .saturating_sub(provider_validate_time);
+        Ok(GenerationResult {
+            files,
+            jobs_by_workflow,
+            skipped_jobs,
+            diagnostics,
+            degradations,
+            phases: vec![
+                PhaseTiming::new(Phase::Validate, validate_time),
+                PhaseTiming::new(Phase::Generate, generate_time),
+                PhaseTiming::new(Phase::ProviderValidate, provider_validate_time),
+            ],
+        })
     }
 
     /// Detect which providers are needed from the configuration
@@ -158,11 +428,38 @@ impl WorkflowOrchestrator {
         providers
     }
 
-    /// Spawn all provider plugins
-    async fn spawn_plugins(&mut self, providers: &[String]) -> Result<Vec<String>> {
+    /// Spawn configured plugins plus a provider plugin for each provider.
+    ///
+    /// Providers offered by a plugin from `plugins:` take precedence over the
+    /// bundled `cigen-provider-*` binaries. Returns `(provider, plugin id)` pairs.
+    async fn spawn_plugins(
+        &mut self,
+        providers: &[String],
+        config: &CigenConfig,
+    ) -> Result<Vec<(String, String)>> {
+        let project_root = config
+            .project_root
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("."));
+        for entry in &config.plugins {
+            let plugin_path = resolve_plugin(entry, &project_root)?;
+            self.plugin_manager
+                .spawn(&plugin_path)
+                .await
+                .with_context(|| format!("Failed to load plugin '{entry}'"))?;
+        }
+
         let mut plugin_ids = Vec::new();
 
         for provider in providers {
+            if let Some(plugin_id) = self
+                .plugin_manager
+                .find_capability(&format!("provider:{provider}"))
+            {
+                plugin_ids.push((provider.clone(), plugin_id));
+                continue;
+            }
+
             let plugin_path = self.plugin_dir.join(format!("cigen-provider-{provider}"));
 
             if !plugin_path.exists() {
@@ -175,9 +472,11 @@ impl WorkflowOrchestrator {
                 .await
                 .with_context(|| format!("Failed to spawn plugin for provider '{provider}'"))?;
 
-            plugin_ids.push(plugin_id);
+            plugin_ids.push((provider.clone(), plugin_id));
         }
 
+        self.plugin_manager.check_compatibility()?;
+
         Ok(plugin_ids)
     }
 }
@@ -199,11 +498,85 @@ impl WorkflowOrchestrator {
     }
 }
 
+/// A provider to generate for, with the config as that provider sees it
+struct ProviderRequest {
+    /// Position in the provider list, which orders the results
+    index: usize,
+    provider: String,
+    plugin_id: String,
+    schema: CigenSchema,
+}
+
+/// Send the plan and generate requests for one provider
+async fn generate_with_provider(
+    manager: &mut PluginManager,
+    request: &ProviderRequest,
+    flags: &HashMap<String, String>,
+) -> Result<GenerateResult> {
+    let plugin_id = &request.plugin_id;
+    let plan_request = PlanRequest {
+        capabilities: vec![],  // TODO: Collect from all plugins
+        facts: HashMap::new(), // TODO: Implement detect phase
+        schema: Some(request.schema.clone()),
+        flags: flags.clone(),
+        repo: None, // TODO: Add repository snapshot
+    };
+
+    let plan_result = manager
+        .send_plan(plugin_id, plan_request)
+        .await
+        .with_context(|| format!("Failed to send plan request to plugin '{plugin_id}'"))?;
+
+    tracing::debug!(
+        "Plugin '{}' returned {} resources",
+        plugin_id,
+        plan_result.resources.len()
+    );
+
+    let generate_request = GenerateRequest {
+        target: request.provider.clone(),
+        graph: plan_result.resources,
+        work_signatures: HashMap::new(), // TODO: Compute work signatures
+        schema: Some(request.schema.clone()),
+        facts: HashMap::new(),
+    };
+
+    manager
+        .send_generate(plugin_id, generate_request)
+        .await
+        .with_context(|| format!("Failed to send generate request to plugin '{plugin_id}'"))
+}
+
+/// A single provider's error as it is, or every error when several failed
+fn combine_failures(mut failures: Vec<anyhow::Error>) -> Result<()> {
+    match failures.len() {
+        0 => Ok(()),
+        1 => Err(failures.remove(0)),
+        count => {
+            let messages: Vec<String> = failures
+                .iter()
+                .map(|error| format!("- {error:#}"))
+                .collect();
+            bail!("{count} providers failed:\n{}", messages.join("\n"))
+        }
+    }
+}
+
 /// Result of configuration generation
 #[derive(Debug)]
 pub struct GenerationResult {
     /// Generated files (path -> content)
     pub files: HashMap<String, String>,
+    /// Job ids generated for each workflow, sorted
+    pub jobs_by_workflow: BTreeMap<String, Vec<String>>,
+    /// Jobs left out by [`WorkflowOrchestrator::skip_jobs`], sorted
+    pub skipped_jobs: Vec<String>,
+    /// Warnings and notes the plugins reported
+    pub diagnostics: Vec<Diagnostic>,
+    /// Features the providers emulate, pass through, or drop, by job
+    pub degradations: Vec<Degradation>,
+    /// Time spent in each phase after the config was loaded
+    pub phases: Vec<PhaseTiming>,
 }
 
 /// Fragment merge strategy
@@ -228,13 +601,37 @@ pub struct FileFragment {
     pub merge_strategy: MergeStrategy,
 }
 
-/// Extract provider name from plugin ID (e.g., "provider/github" -> "github")
-fn extract_provider_name(plugin_id: &str) -> String {
-    plugin_id
-        .split('/')
-        .next_back()
-        .unwrap_or(plugin_id)
-        .to_string()
+/// Workflow argument of the `cigen generate` CircleCI's setup job runs: the
+/// continued config with every workflow, unless a workflow is named `main`
+pub const CONTINUED_CONFIG: &str = "main";
+
+fn job_workflow(job: &crate::schema::Job) -> &str {
+    job.workflow.as_deref().unwrap_or(DEFAULT_WORKFLOW)
+}
+
+/// Drop every job and workflow that doesn't belong to `workflow`.
+/// [`CONTINUED_CONFIG`] keeps them all unless a workflow has that name.
+fn restrict_to_workflow(config: &mut CigenConfig, workflow: &str) -> Result<()> {
+    let known: BTreeSet<&str> = config
+        .workflows
+        .keys()
+        .map(String::as_str)
+        .chain(config.jobs.values().map(job_workflow))
+        .collect();
+    if workflow == CONTINUED_CONFIG && !known.contains(workflow) {
+        return Ok(());
+    }
+    if !known.contains(workflow) {
+        bail!(unknown_reference_message(
+            &format!("Unknown workflow '{workflow}'"),
+            workflow,
+            known
+        ));
+    }
+
+    config.jobs.retain(|_, job| job_workflow(job) == workflow);
+    config.workflows.retain(|id, _| id == workflow);
+    Ok(())
 }
 
 /// Merge fragments into final files
@@ -305,20 +702,40 @@ mod tests {
         assert_eq!(files.get("output.txt").unwrap(), "line 1\nline 2\n");
     }
 
+    #[test]
+    fn test_restrict_to_workflow() {
+        let mut config = CigenConfig::from_yaml(
+            r#"
+jobs:
+  test:
+    image: rust:latest
+  deploy:
+    image: rust:latest
+    workflow: release
+"#,
+        )
+        .unwrap();
+
+        let error = restrict_to_workflow(&mut config.clone(), "relase").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unknown workflow 'relase'. Did you mean 'release'?\nAvailable: ci, release"
+        );
+
+        // `main` is the continued config when no workflow has that name
+        let mut continued = config.clone();
+        restrict_to_workflow(&mut continued, CONTINUED_CONFIG).unwrap();
+        assert_eq!(continued.jobs.len(), 2);
+
+        restrict_to_workflow(&mut config, "release").unwrap();
+        assert_eq!(config.jobs.keys().collect::<Vec<_>>(), vec!["deploy"]);
+    }
+
     #[test]
     fn test_detect_providers() {
         let config = CigenConfig {
-            project: None,
             providers: vec!["github".to_string(), "circleci".to_string()],
-            packages: vec![],
-            source_file_groups: HashMap::new(),
-            jobs: HashMap::new(),
-            commands: HashMap::new(),
-            caches: HashMap::new(),
-            runners: HashMap::new(),
-            provider_config: HashMap::new(),
-            workflows: HashMap::new(),
-            raw: Default::default(),
+            ..Default::default()
         };
 
         let orchestrator = WorkflowOrchestrator::new(PathBuf::from("plugins"));
@@ -330,17 +747,8 @@ mod tests {
     #[test]
     fn test_detect_providers_defaults() {
         let config = CigenConfig {
-            project: None,
             providers: vec![], // Empty - should use defaults
-            packages: vec![],
-            source_file_groups: HashMap::new(),
-            jobs: HashMap::new(),
-            commands: HashMap::new(),
-            caches: HashMap::new(),
-            runners: HashMap::new(),
-            provider_config: HashMap::new(),
-            workflows: HashMap::new(),
-            raw: Default::default(),
+            ..Default::default()
         };
 
         let orchestrator = WorkflowOrchestrator::new(PathBuf::from("plugins"));
@@ -0,0 +1,374 @@
+//! Generation-time job filtering
+//!
+//! With `cigen generate --changed-since <ref>`, jobs whose `source_files`
+//! match none of the changed paths are left out of the generated config. Jobs
+//! without `source_files` always run. With `$CIGEN_ONLY_PROJECTS_FILE` set,
+//! jobs whose `project` isn't listed in that file are left out as well.
+//!
+//! Jobs named in `$CIGEN_SKIP_JOBS_FILE` (the setup job's list of jobs whose
+//! status cache says they already passed) or in `--skip-jobs` are left out by
+//! their final names, after matrix expansion.
+//!
+//! A job that needed an excluded job inherits that job's own dependencies
+//! instead, so ordering between the remaining jobs is kept.
+
+use anyhow::{Context, Result, bail};
+use globset::Glob;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::orchestrator::provider_job_name;
+use crate::schema::{CigenConfig, Job, split_need, unknown_reference_message};
+
+/// File of affected project names, one per line, written by the setup job
+pub const ONLY_PROJECTS_FILE_ENV: &str = "CIGEN_ONLY_PROJECTS_FILE";
+
+/// File of job names to leave out, one per line, written by the setup job
+pub const SKIP_JOBS_FILE_ENV: &str = "CIGEN_SKIP_JOBS_FILE";
+
+/// Source of the paths changed since a git ref
+pub trait ChangedFiles {
+    /// Changed paths, relative to the project root
+    fn changed_since(&self, base: &str) -> Result<Vec<String>>;
+}
+
+/// Changed paths from `git diff --name-only <base>...HEAD`
+pub struct GitDiff {
+    root: PathBuf,
+}
+
+impl GitDiff {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl ChangedFiles for GitDiff {
+    fn changed_since(&self, base: &str) -> Result<Vec<String>> {
+        let output = Command::new("git")
+            .args(["diff", "--name-only", "--relative"])
+            .arg(format!("{base}...HEAD"))
+            .current_dir(&self.root)
+            .output()
+            .context("Failed to run git diff")?;
+        if !output.status.success() {
+            bail!(
+                "git diff against '{base}' failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+/// Jobs kept and left out by a filter, sorted by id
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PathFilterSummary {
+    pub included: Vec<String>,
+    pub excluded: Vec<String>,
+}
+
+/// Remove jobs none of whose `source_files` (or job file) changed
+pub fn filter_changed_jobs(
+    config: &mut CigenConfig,
+    changed: &[String],
+) -> Result<PathFilterSummary> {
+    let mut excluded = BTreeSet::new();
+    for (job_id, job) in &config.jobs {
+        if job.source_files.is_empty() {
+            continue;
+        }
+        let patterns = source_patterns(job_id, job, config)?;
+        let job_file = job_file(job, config);
+        let touched = changed.iter().any(|path| {
+            job_file.as_deref() == Some(path.as_str())
+                || patterns
+                    .iter()
+                    .any(|pattern| pattern_matches(pattern, path))
+        });
+        if !touched {
+            excluded.insert(job_id.clone());
+        }
+    }
+    Ok(exclude_jobs(config, excluded))
+}
+
+/// Affected project names from `path`: one per line, blank lines ignored
+pub fn read_projects_file(path: &Path) -> Result<Vec<String>> {
+    read_names(path, "projects")
+}
+
+/// Job names to skip from `path`: one per line, blank lines ignored
+pub fn read_skip_jobs_file(path: &Path) -> Result<Vec<String>> {
+    read_names(path, "skip jobs")
+}
+
+fn read_names(path: &Path, kind: &str) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {kind} file {}", path.display()))?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Remove jobs that belong to a project missing from `affected`. Jobs without
+/// a `project` always run.
+pub fn filter_affected_projects(
+    config: &mut CigenConfig,
+    affected: &[String],
+) -> PathFilterSummary {
+    let excluded = config
+        .jobs
+        .iter()
+        .filter(|(_, job)| {
+            job.project
+                .as_ref()
+                .is_some_and(|project| !affected.contains(project))
+        })
+        .map(|(job_id, _)| job_id.clone())
+        .collect();
+    exclude_jobs(config, excluded)
+}
+
+/// Remove the jobs named in `skipped`, by their final (expanded) names. Names
+/// that match no job are returned alongside the summary.
+pub fn skip_jobs(
+    config: &mut CigenConfig,
+    skipped: &BTreeSet<String>,
+) -> (PathFilterSummary, Vec<String>) {
+    let mut excluded = BTreeSet::new();
+    let mut unknown = Vec::new();
+    for name in skipped {
+        let provider_name = provider_job_name(name);
+        if config.jobs.contains_key(name) {
+            excluded.insert(name.clone());
+        } else if config.jobs.contains_key(&provider_name) {
+            excluded.insert(provider_name);
+        } else {
+            unknown.push(name.clone());
+        }
+    }
+    let summary = exclude_jobs(config, excluded);
+    // Expanded jobs keep their resolved needs sorted
+    for job in config.jobs.values_mut() {
+        job.needs.sort();
+    }
+    (summary, unknown)
+}
+
+/// Drop the excluded jobs, rewiring the needs of the jobs that remain
+fn exclude_jobs(config: &mut CigenConfig, excluded: BTreeSet<String>) -> PathFilterSummary {
+    let excluded_needs: HashMap<String, Vec<String>> = excluded
+        .iter()
+        .map(|id| (id.clone(), config.jobs[id].needs.clone()))
+        .collect();
+    config.jobs.retain(|id, _| !excluded.contains(id));
+    for job in config.jobs.values_mut() {
+        let mut needs = Vec::new();
+        for need in &job.needs {
+            inherit_needs(need, &excluded_needs, &mut BTreeSet::new(), &mut needs);
+        }
+        job.needs = needs;
+    }
+
+    let mut included: Vec<String> = config.jobs.keys().cloned().collect();
+    included.sort();
+    PathFilterSummary {
+        included,
+        excluded: excluded.into_iter().collect(),
+    }
+}
+
+/// Add `need`, or the needs of an excluded job in its place
+fn inherit_needs(
+    need: &str,
+    excluded: &HashMap<String, Vec<String>>,
+    seen: &mut BTreeSet<String>,
+    needs: &mut Vec<String>,
+) {
+    let (job, _) = split_need(need);
+    match excluded.get(job) {
+        Some(inherited) if seen.insert(job.to_string()) => {
+            for need in inherited {
+                inherit_needs(need, excluded, seen, needs);
+            }
+        }
+        Some(_) => {}
+        None if !needs.iter().any(|existing| existing == need) => needs.push(need.to_string()),
+        None => {}
+    }
+}
+
+/// The job's patterns with `@group` references expanded
+fn source_patterns(job_id: &str, job: &Job, config: &CigenConfig) -> Result<Vec<String>> {
+    let mut patterns = Vec::new();
+    for entry in &job.source_files {
+        let Some(group) = entry.strip_prefix('@') else {
+            patterns.push(entry.clone());
+            continue;
+        };
+        let members = config.source_file_groups.get(group).with_context(|| {
+            unknown_reference_message(
+                &format!("Job '{job_id}' references unknown source file group '{group}'"),
+                group,
+                config.source_file_groups.keys().map(String::as_str),
+            )
+        })?;
+        patterns.extend(members.iter().cloned());
+    }
+    Ok(patterns)
+}
+
+/// The file the job was loaded from, relative to the project root
+fn job_file(job: &Job, config: &CigenConfig) -> Option<String> {
+    let source = job.source_file.as_ref()?;
+    let relative = match &config.project_root {
+        Some(root) => source.strip_prefix(root).ok()?,
+        None => source.as_path(),
+    };
+    Some(relative.to_string_lossy().replace('\\', "/"))
+}
+
+/// Whether a changed path falls under a `source_files` pattern. Globs follow
+/// git's pathspecs, where `*` also matches `/`; other patterns name a file or
+/// a directory.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+    if pattern.contains(['*', '?', '[', ']']) {
+        return Glob::new(pattern)
+            .map(|glob| glob.compile_matcher().is_match(path))
+            .unwrap_or(false);
+    }
+    let pattern = pattern.trim_end_matches('/');
+    path == pattern
+        || path
+            .strip_prefix(pattern)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for git in tests
+    struct FakeDiff(Vec<&'static str>);
+
+    impl ChangedFiles for FakeDiff {
+        fn changed_since(&self, _base: &str) -> Result<Vec<String>> {
+            Ok(self.0.iter().map(|path| path.to_string()).collect())
+        }
+    }
+
+    fn job(source_files: &[&str], needs: &[&str]) -> Job {
+        Job {
+            source_files: source_files.iter().map(|s| s.to_string()).collect(),
+            needs: needs.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn config() -> CigenConfig {
+        let mut config = CigenConfig::default();
+        config.source_file_groups.insert(
+            "ruby".to_string(),
+            vec!["app/".to_string(), "Gemfile.lock".to_string()],
+        );
+        config.jobs.insert("setup".to_string(), job(&[], &[]));
+        config
+            .jobs
+            .insert("frontend".to_string(), job(&["web/**/*.ts"], &["setup"]));
+        config
+            .jobs
+            .insert("backend".to_string(), job(&["@ruby"], &["setup"]));
+        config.jobs.insert(
+            "deploy".to_string(),
+            job(&["deploy/"], &["frontend", "backend"]),
+        );
+        config
+    }
+
+    #[test]
+    fn test_unchanged_jobs_are_excluded_and_needs_inherited() {
+        let mut config = config();
+        let changed = FakeDiff(vec!["app/models/user.rb", "deploy/run.sh"])
+            .changed_since("origin/main")
+            .unwrap();
+        let summary = filter_changed_jobs(&mut config, &changed).unwrap();
+
+        assert_eq!(summary.included, ["backend", "deploy", "setup"]);
+        assert_eq!(summary.excluded, ["frontend"]);
+        assert_eq!(config.jobs["deploy"].needs, ["setup", "backend"]);
+    }
+
+    #[test]
+    fn test_jobs_without_source_files_always_run() {
+        let mut config = config();
+        let summary = filter_changed_jobs(&mut config, &[]).unwrap();
+
+        assert_eq!(summary.included, ["setup"]);
+        assert_eq!(summary.excluded, ["backend", "deploy", "frontend"]);
+    }
+
+    #[test]
+    fn test_skipped_jobs_are_excluded_by_final_name() {
+        let mut config = config();
+        let skipped = ["backend", "frontend", "docs/site"]
+            .map(String::from)
+            .into_iter()
+            .collect();
+        let (summary, unknown) = skip_jobs(&mut config, &skipped);
+
+        assert_eq!(summary.included, ["deploy", "setup"]);
+        assert_eq!(summary.excluded, ["backend", "frontend"]);
+        assert_eq!(unknown, ["docs/site"]);
+        assert_eq!(config.jobs["deploy"].needs, ["setup"]);
+    }
+
+    #[test]
+    fn test_jobs_of_unaffected_projects_are_excluded() {
+        let mut config = config();
+        config.jobs.get_mut("frontend").unwrap().project = Some("web".to_string());
+        config.jobs.get_mut("backend").unwrap().project = Some("api".to_string());
+        let summary = filter_affected_projects(&mut config, &["api".to_string()]);
+
+        assert_eq!(summary.included, ["backend", "deploy", "setup"]);
+        assert_eq!(summary.excluded, ["frontend"]);
+        assert_eq!(config.jobs["deploy"].needs, ["setup", "backend"]);
+    }
+
+    #[test]
+    fn test_pattern_matching() {
+        assert!(pattern_matches("web/**/*.ts", "web/src/app.ts"));
+        assert!(pattern_matches("web/*", "web/src/app.ts"));
+        assert!(!pattern_matches("web/**/*.ts", "web/src/app.css"));
+        assert!(pattern_matches("app/", "app/models/user.rb"));
+        assert!(pattern_matches("./Gemfile.lock", "Gemfile.lock"));
+        assert!(!pattern_matches("app", "application.rb"));
+    }
+
+    #[test]
+    fn test_unknown_group_is_an_error() {
+        let mut config = config();
+        config
+            .jobs
+            .insert("lint".to_string(), job(&["@rubby"], &[]));
+
+        let error = filter_changed_jobs(&mut config, &[])
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("unknown source file group 'rubby'"),
+            "{error}"
+        );
+        assert!(error.contains("Did you mean 'ruby'?"), "{error}");
+    }
+}
@@ -0,0 +1,332 @@
+//! Provider feature support and the degradation report
+//!
+//! Provider plugins declare how they handle features that don't map to every
+//! CI system as `feature:<name>` capabilities in `PluginInfo`, with an
+//! optional `=emulated`, `=passthrough`, or `=dropped` suffix (no suffix means
+//! native support). Before generating, the core checks the features each job
+//! uses against its provider's declarations and reports every job that loses
+//! fidelity. Plugins that declare no features are not checked.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+
+use super::protocol::{CigenSchema, JobDefinition, Step, step};
+
+/// Prefix of feature capabilities, e.g. `feature:approval=emulated`
+pub const FEATURE_PREFIX: &str = "feature:";
+
+/// A job feature that not every provider supports natively
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    /// `type: approval` jobs that wait for a person
+    Approval,
+    /// `context:` secrets shared between projects
+    Contexts,
+    /// `parallelism:` without `test_splitting`
+    Parallelism,
+    /// `test_splitting:` across parallel containers
+    TestSplitting,
+    /// `persist_to_workspace` and `attach_workspace` steps
+    Workspaces,
+    /// `remote_docker:` engines
+    RemoteDocker,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 6] = [
+        Feature::Approval,
+        Feature::Contexts,
+        Feature::Parallelism,
+        Feature::TestSplitting,
+        Feature::Workspaces,
+        Feature::RemoteDocker,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Feature::Approval => "approval",
+            Feature::Contexts => "contexts",
+            Feature::Parallelism => "parallelism",
+            Feature::TestSplitting => "test_splitting",
+            Feature::Workspaces => "workspaces",
+            Feature::RemoteDocker => "remote_docker",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|feature| feature.as_str() == name)
+    }
+
+    /// Whether `job` uses the feature
+    pub fn is_used_by(self, job: &JobDefinition) -> bool {
+        match self {
+            Feature::Approval => job.is_approval(),
+            Feature::Contexts => job.extra.contains_key("context"),
+            Feature::Parallelism => {
+                job.test_splitting.is_none() && job.extra.contains_key("parallelism")
+            }
+            Feature::TestSplitting => job.test_splitting.is_some(),
+            Feature::Workspaces => [&job.pre_steps, &job.steps, &job.post_steps]
+                .into_iter()
+                .flatten()
+                .any(is_workspace_step),
+            Feature::RemoteDocker => job.remote_docker.is_some(),
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+fn is_workspace_step(step: &Step) -> bool {
+    matches!(
+        &step.step_type,
+        Some(step::StepType::Custom(custom))
+            if matches!(custom.kind.as_str(), "persist_to_workspace" | "attach_workspace")
+    )
+}
+
+/// How a provider handles a feature
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Support {
+    /// Generated with the provider's own equivalent
+    Native,
+    /// Approximated with something else, e.g. a matrix for test splitting
+    Emulated,
+    /// Copied into the generated job as written, for the provider to interpret
+    PassThrough,
+    /// Left out of the generated config
+    Dropped,
+}
+
+impl Support {
+    fn suffix(self) -> Option<&'static str> {
+        match self {
+            Support::Native => None,
+            Support::Emulated => Some("emulated"),
+            Support::PassThrough => Some("passthrough"),
+            Support::Dropped => Some("dropped"),
+        }
+    }
+
+    fn from_suffix(suffix: &str) -> Option<Self> {
+        [Support::Emulated, Support::PassThrough, Support::Dropped]
+            .into_iter()
+            .find(|support| support.suffix() == Some(suffix))
+    }
+}
+
+impl fmt::Display for Support {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.suffix().unwrap_or("native"))
+    }
+}
+
+/// The features a provider declares, and how it handles each
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProviderCapabilities {
+    features: BTreeMap<Feature, Support>,
+}
+
+impl ProviderCapabilities {
+    /// Every feature supported natively
+    pub fn native() -> Self {
+        Self {
+            features: Feature::ALL
+                .into_iter()
+                .map(|feature| (feature, Support::Native))
+                .collect(),
+        }
+    }
+
+    pub fn with(mut self, feature: Feature, support: Support) -> Self {
+        self.features.insert(feature, support);
+        self
+    }
+
+    /// Read the `feature:` capabilities a plugin declared; `None` when it
+    /// declared none. Unknown features and levels are ignored, so newer
+    /// plugins work with older cores.
+    pub fn from_capabilities(capabilities: &[String]) -> Option<Self> {
+        let declared: Vec<&str> = capabilities
+            .iter()
+            .filter_map(|capability| capability.strip_prefix(FEATURE_PREFIX))
+            .collect();
+        if declared.is_empty() {
+            return None;
+        }
+        let features = declared
+            .into_iter()
+            .filter_map(|declaration| {
+                let (name, support) = match declaration.split_once('=') {
+                    Some((name, suffix)) => (name, Support::from_suffix(suffix)?),
+                    None => (declaration, Support::Native),
+                };
+                Some((Feature::from_name(name)?, support))
+            })
+            .collect();
+        Some(Self { features })
+    }
+
+    /// Capabilities for a plugin to declare in `PluginInfo`
+    pub fn to_capabilities(&self) -> Vec<String> {
+        self.features
+            .iter()
+            .map(|(feature, support)| match support.suffix() {
+                Some(suffix) => format!("{FEATURE_PREFIX}{feature}={suffix}"),
+                None => format!("{FEATURE_PREFIX}{feature}"),
+            })
+            .collect()
+    }
+
+    /// How the provider handles `feature`. Features it doesn't declare are
+    /// passed through.
+    pub fn support(&self, feature: Feature) -> Support {
+        self.features
+            .get(&feature)
+            .copied()
+            .unwrap_or(Support::PassThrough)
+    }
+}
+
+/// A job feature a provider doesn't support natively
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Degradation {
+    pub provider: String,
+    pub job: String,
+    pub feature: Feature,
+    pub support: Support,
+}
+
+/// Every feature the jobs in `schema` use that `provider` doesn't support
+/// natively, sorted by job
+pub fn find_degradations(
+    provider: &str,
+    capabilities: &ProviderCapabilities,
+    schema: &CigenSchema,
+) -> Vec<Degradation> {
+    let mut degradations: Vec<Degradation> = schema
+        .jobs
+        .iter()
+        .flat_map(|job| {
+            Feature::ALL
+                .into_iter()
+                .filter(|feature| feature.is_used_by(job))
+                .map(move |feature| (job, feature, capabilities.support(feature)))
+        })
+        .filter(|(_, _, support)| *support != Support::Native)
+        .map(|(job, feature, support)| Degradation {
+            provider: provider.to_string(),
+            job: job.id.clone(),
+            feature,
+            support,
+        })
+        .collect();
+    degradations.sort_by(|a, b| (&a.job, a.feature).cmp(&(&b.job, b.feature)));
+    degradations
+}
+
+/// The degradation report: one line per job, grouped by provider
+pub fn render_degradations(degradations: &[Degradation]) -> String {
+    let mut by_job: BTreeMap<(&str, &str), Vec<&Degradation>> = BTreeMap::new();
+    for degradation in degradations {
+        by_job
+            .entry((&degradation.provider, &degradation.job))
+            .or_default()
+            .push(degradation);
+    }
+
+    let mut output = String::new();
+    let mut provider = None;
+    for ((job_provider, job), features) in by_job {
+        if provider != Some(job_provider) {
+            let _ = writeln!(output, "Features {job_provider} doesn't support natively:");
+            provider = Some(job_provider);
+        }
+        let features: Vec<String> = features
+            .iter()
+            .map(|degradation| format!("{} ({})", degradation.feature, degradation.support))
+            .collect();
+        let _ = writeln!(output, "  {job}: {}", features.join(", "));
+    }
+    output.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::protocol::{CustomStep, TestSplitting};
+
+    #[test]
+    fn capabilities_round_trip_through_plugin_info() {
+        let capabilities = ProviderCapabilities::native()
+            .with(Feature::Approval, Support::Emulated)
+            .with(Feature::Workspaces, Support::Dropped);
+        let declared = capabilities.to_capabilities();
+        assert!(declared.contains(&"feature:approval=emulated".to_string()));
+        assert!(declared.contains(&"feature:contexts".to_string()));
+
+        let mut info = vec!["provider:example".to_string(), "feature:future".to_string()];
+        info.extend(declared);
+        assert_eq!(
+            ProviderCapabilities::from_capabilities(&info),
+            Some(capabilities)
+        );
+        assert_eq!(
+            ProviderCapabilities::from_capabilities(&["provider:example".to_string()]),
+            None
+        );
+    }
+
+    #[test]
+    fn jobs_are_checked_against_the_providers_support() {
+        let mut job = JobDefinition {
+            id: "rspec".to_string(),
+            test_splitting: Some(TestSplitting::default()),
+            steps: vec![Step {
+                step_type: Some(step::StepType::Custom(CustomStep {
+                    kind: "persist_to_workspace".to_string(),
+                    ..Default::default()
+                })),
+            }],
+            ..Default::default()
+        };
+        job.extra.insert("parallelism".to_string(), "4".to_string());
+        job.extra
+            .insert("context".to_string(), "org-global".to_string());
+        let schema = CigenSchema {
+            jobs: vec![job],
+            ..Default::default()
+        };
+        let capabilities = ProviderCapabilities::native()
+            .with(Feature::TestSplitting, Support::Emulated)
+            .with(Feature::Workspaces, Support::Dropped);
+
+        let degradations = find_degradations("github", &capabilities, &schema);
+        let found: Vec<(Feature, Support)> = degradations
+            .iter()
+            .map(|degradation| (degradation.feature, degradation.support))
+            .collect();
+        // parallelism is part of test splitting, and contexts are native here
+        assert_eq!(
+            found,
+            [
+                (Feature::TestSplitting, Support::Emulated),
+                (Feature::Workspaces, Support::Dropped),
+            ]
+        );
+        assert_eq!(
+            render_degradations(&degradations),
+            "Features github doesn't support natively:\n  rspec: test_splitting (emulated), workspaces (dropped)"
+        );
+        assert!(find_degradations("circleci", &ProviderCapabilities::native(), &schema).is_empty());
+    }
+}
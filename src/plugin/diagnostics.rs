@@ -0,0 +1,422 @@
+//! Source locations for plugin diagnostics
+//!
+//! Plugins attach a [`SourceLocation`] pointing into the user's `.cigen` files by
+//! returning a [`LocatedError`]; the core renders located diagnostics as a
+//! miette snippet of that file.
+
+use miette::{
+    GraphicalReportHandler, GraphicalTheme, LabeledSpan, MietteDiagnostic, NamedSource, Report,
+    Severity,
+};
+use std::fmt::Write as _;
+
+use super::protocol::{Diagnostic, SourceLocation, diagnostic::Level};
+
+/// Error that should be reported at a location in a `.cigen` source file
+#[derive(Debug, thiserror::Error)]
+#[error("{message}{}", help.as_ref().map(|help| format!("\n{help}")).unwrap_or_default())]
+pub struct LocatedError {
+    pub message: String,
+    pub location: SourceLocation,
+    /// Shown below the snippet, e.g. a "did you mean"
+    pub help: Option<String>,
+}
+
+/// Build an error located at the first occurrence of `needle` in `file`.
+/// Falls back to a plain error when the file or needle can't be found.
+pub fn located_error(message: String, file: &str, needle: &str) -> anyhow::Error {
+    match locate(file, needle) {
+        Some(location) => LocatedError {
+            message,
+            location,
+            help: None,
+        }
+        .into(),
+        None => anyhow::anyhow!(message),
+    }
+}
+
+/// Like [`located_error`], but only searches the `section:` block of `file`
+/// when `section` is set: the job's own entry in a file that defines several
+/// jobs. Falls back to the section's key when the needle isn't in it.
+pub fn located_error_in(message: String, file: &str, section: &str, needle: &str) -> anyhow::Error {
+    match locate_in(file, section, needle) {
+        Some(location) => LocatedError {
+            message,
+            location,
+            help: None,
+        }
+        .into(),
+        None => anyhow::anyhow!(message),
+    }
+}
+
+/// 1-based location of the first occurrence of `needle` in `file`
+pub fn locate(file: &str, needle: &str) -> Option<SourceLocation> {
+    locate_in(file, "", needle)
+}
+
+/// 1-based location of the first occurrence of `needle` in the `section:`
+/// block of `file` (the whole file when `section` is empty). The block ends at
+/// the next line indented no deeper than its key.
+pub fn locate_in(file: &str, section: &str, needle: &str) -> Option<SourceLocation> {
+    if file.is_empty() || needle.is_empty() {
+        return None;
+    }
+    let contents = std::fs::read_to_string(file).ok()?;
+    let location = |index: usize, line: &str, offset: usize, snippet: &str| SourceLocation {
+        file: file.to_string(),
+        line: index as u32 + 1,
+        column: line[..offset].chars().count() as u32 + 1,
+        snippet: snippet.to_string(),
+    };
+    if section.is_empty() {
+        return contents.lines().enumerate().find_map(|(index, line)| {
+            line.find(needle)
+                .map(|offset| location(index, line, offset, needle))
+        });
+    }
+
+    let key = format!("{section}:");
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let mut lines = contents.lines().enumerate();
+    let (start, key_line) = lines.find(|(_, line)| line.trim_start().starts_with(&key))?;
+    let key_indent = indent(key_line);
+    let key_location = location(start, key_line, key_indent, section);
+    if let Some(offset) = key_line[key_indent + key.len()..].find(needle) {
+        let offset = key_indent + key.len() + offset;
+        return Some(location(start, key_line, offset, needle));
+    }
+    lines
+        .take_while(|(_, line)| {
+            let trimmed = line.trim_start();
+            trimmed.is_empty() || trimmed.starts_with('#') || indent(line) > key_indent
+        })
+        .find_map(|(index, line)| {
+            line.find(needle)
+                .map(|offset| location(index, line, offset, needle))
+        })
+        .or(Some(key_location))
+}
+
+/// 1-based location of `key:` where it's written least indented in the
+/// `section:` block of `file` (the whole file when `section` is empty). A key
+/// a job sets itself is found ahead of the copy in an anchor it merges.
+pub fn locate_key(file: &str, section: &str, key: &str) -> Option<SourceLocation> {
+    if file.is_empty() || key.is_empty() {
+        return None;
+    }
+    let contents = std::fs::read_to_string(file).ok()?;
+    let lines: Vec<&str> = contents.lines().collect();
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let (start, end) = if section.is_empty() {
+        (0, lines.len())
+    } else {
+        let section_key = format!("{section}:");
+        let start = lines
+            .iter()
+            .position(|line| line.trim_start().starts_with(&section_key))?;
+        let key_indent = indent(lines[start]);
+        let end = (start + 1..lines.len())
+            .find(|&i| {
+                let trimmed = lines[i].trim_start();
+                !trimmed.is_empty() && !trimmed.starts_with('#') && indent(lines[i]) <= key_indent
+            })
+            .unwrap_or(lines.len());
+        (start + 1, end)
+    };
+    let needle = format!("{key}:");
+    let line = (start..end)
+        .filter(|&i| lines[i].trim_start().starts_with(&needle))
+        .min_by_key(|&i| indent(lines[i]))?;
+    Some(SourceLocation {
+        file: file.to_string(),
+        line: line as u32 + 1,
+        column: indent(lines[line]) as u32 + 1,
+        snippet: key.to_string(),
+    })
+}
+
+/// 1-based location of the first occurrence of `needle` in the `index`th item
+/// of the shallowest `list:` sequence in the `section:` block of `file` (the
+/// whole file when `section` is empty). Falls back to the item's `-` when the
+/// needle isn't in it.
+pub fn locate_list_item(
+    file: &str,
+    section: &str,
+    list: &str,
+    index: usize,
+    needle: &str,
+) -> Option<SourceLocation> {
+    if file.is_empty() || needle.is_empty() {
+        return None;
+    }
+    let contents = std::fs::read_to_string(file).ok()?;
+    let lines: Vec<&str> = contents.lines().collect();
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let is_blank = |line: &str| {
+        let trimmed = line.trim_start();
+        trimmed.is_empty() || trimmed.starts_with('#')
+    };
+    let location = |index: usize, offset: usize, snippet: &str| SourceLocation {
+        file: file.to_string(),
+        line: index as u32 + 1,
+        column: lines[index][..offset].chars().count() as u32 + 1,
+        snippet: snippet.to_string(),
+    };
+
+    let (start, end) = if section.is_empty() {
+        (0, lines.len())
+    } else {
+        let key = format!("{section}:");
+        let start = lines
+            .iter()
+            .position(|line| line.trim_start().starts_with(&key))?;
+        let key_indent = indent(lines[start]);
+        let end = (start + 1..lines.len())
+            .find(|&i| !is_blank(lines[i]) && indent(lines[i]) <= key_indent)
+            .unwrap_or(lines.len());
+        (start + 1, end)
+    };
+    let key = format!("{list}:");
+    let list_line = (start..end)
+        .filter(|&i| lines[i].trim_start().starts_with(&key))
+        .min_by_key(|&i| indent(lines[i]))?;
+    let list_indent = indent(lines[list_line]);
+
+    let mut items = Vec::new();
+    let mut item_indent = None;
+    let mut list_end = end;
+    for (i, line) in lines.iter().enumerate().take(end).skip(list_line + 1) {
+        if is_blank(line) {
+            continue;
+        }
+        let is_item = line.trim_start().starts_with('-');
+        if indent(line) < list_indent || (indent(line) == list_indent && !is_item) {
+            list_end = i;
+            break;
+        }
+        if is_item && *item_indent.get_or_insert(indent(line)) == indent(line) {
+            items.push(i);
+        }
+    }
+    let item = *items.get(index)?;
+    let item_end = items.get(index + 1).copied().unwrap_or(list_end);
+    (item..item_end)
+        .find_map(|i| {
+            lines[i]
+                .find(needle)
+                .map(|offset| location(i, offset, needle))
+        })
+        .or_else(|| Some(location(item, indent(lines[item]), "-")))
+}
+
+/// Location carried by `error` or any error in its chain
+pub fn error_location(error: &anyhow::Error) -> Option<SourceLocation> {
+    located(error).map(|located| located.location.clone())
+}
+
+fn located(error: &anyhow::Error) -> Option<&LocatedError> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<LocatedError>())
+}
+
+/// Render a plugin diagnostic for the terminal.
+///
+/// Diagnostics with a readable source location get a labelled snippet of the
+/// file; the rest are a single `Plugin <level>: [code] message` line.
+pub fn render_diagnostic(diagnostic: &Diagnostic) -> String {
+    let label = match diagnostic.level() {
+        Level::Error => "error",
+        Level::Warning => "warning",
+        Level::Info => "info",
+        Level::Unspecified => "diagnostic",
+    };
+
+    if let Some(rendered) = diagnostic
+        .loc
+        .as_ref()
+        .and_then(|loc| render_located(diagnostic, loc))
+    {
+        return rendered;
+    }
+
+    let mut output = format!(
+        "Plugin {label}: [{}] {}",
+        diagnostic.code, diagnostic.message
+    );
+    if let Some(loc) = diagnostic.loc.as_ref().filter(|loc| !loc.file.is_empty()) {
+        let _ = write!(output, "\n  --> {}:{}:{}", loc.file, loc.line, loc.column);
+    }
+    if !diagnostic.fix_hint.is_empty() {
+        let _ = write!(output, "\n  hint: {}", diagnostic.fix_hint);
+    }
+    output
+}
+
+/// Render an error carrying a [`LocatedError`] as a snippet of its file, the
+/// same way as a located plugin diagnostic. `None` when it has no location.
+pub fn render_located_error(error: &anyhow::Error) -> Option<String> {
+    let located = located(error)?;
+    let mut message = format!("{error:#}");
+    let mut fix_hint = String::new();
+    if let Some(help) = &located.help {
+        // The help goes below the snippet rather than in the message
+        if let Some(stripped) = message.strip_suffix(&format!("\n{help}")) {
+            message = stripped.to_string();
+        }
+        fix_hint = help.clone();
+    }
+    let diagnostic = Diagnostic {
+        level: Level::Error as i32,
+        message,
+        fix_hint,
+        loc: Some(located.location.clone()),
+        ..Default::default()
+    };
+    render_located(&diagnostic, &located.location)
+}
+
+/// Render a warning about a `.cigen` file as a snippet of it; `None` when the
+/// file or line can't be read
+pub fn render_located_warning(message: &str, loc: &SourceLocation) -> Option<String> {
+    let diagnostic = Diagnostic {
+        level: Level::Warning as i32,
+        message: message.to_string(),
+        loc: Some(loc.clone()),
+        ..Default::default()
+    };
+    render_located(&diagnostic, loc)
+}
+
+/// `diagnostic` as a labelled snippet of the file `loc` points into; `None`
+/// when the file or line can't be read
+pub(crate) fn render_located(diagnostic: &Diagnostic, loc: &SourceLocation) -> Option<String> {
+    if loc.line == 0 {
+        return None;
+    }
+    let contents = std::fs::read_to_string(&loc.file).ok()?;
+    let line_start: usize = contents
+        .split_inclusive('\n')
+        .take(loc.line as usize - 1)
+        .map(str::len)
+        .sum();
+    let line = contents[line_start..].lines().next()?;
+    let column_offset: usize = line
+        .chars()
+        .take(loc.column.saturating_sub(1) as usize)
+        .map(char::len_utf8)
+        .sum();
+    let start = line_start + column_offset;
+    let length = if !loc.snippet.is_empty() && contents[start..].starts_with(&loc.snippet) {
+        loc.snippet.len()
+    } else {
+        1
+    };
+
+    let severity = match diagnostic.level() {
+        Level::Warning => Severity::Warning,
+        Level::Info => Severity::Advice,
+        Level::Error | Level::Unspecified => Severity::Error,
+    };
+    let mut report = MietteDiagnostic::new(diagnostic.message.clone())
+        .with_severity(severity)
+        .with_label(LabeledSpan::at(start..start + length, "here"));
+    if !diagnostic.code.is_empty() {
+        report = report.with_code(diagnostic.code.clone());
+    }
+    if !diagnostic.fix_hint.is_empty() {
+        report = report.with_help(diagnostic.fix_hint.clone());
+    }
+    let report = Report::new(report).with_source_code(NamedSource::new(&loc.file, contents));
+
+    let mut output = String::new();
+    GraphicalReportHandler::new_themed(GraphicalTheme::unicode_nocolor())
+        .render_report(&mut output, report.as_ref())
+        .ok()?;
+    Some(output.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_located_error_renders_snippet() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("rspec.yml");
+        std::fs::write(&file, "image: cimg/ruby:3.3\nservices:\n  - postgrse\n").unwrap();
+        let file = file.display().to_string();
+
+        let error = located_error("Unknown service 'postgrse'".to_string(), &file, "postgrse")
+            .context("Failed to build job");
+        let loc = error_location(&error).expect("location should survive context");
+        assert_eq!((loc.line, loc.column), (3, 5));
+
+        let rendered = render_diagnostic(&Diagnostic {
+            level: Level::Error as i32,
+            code: "CIRCLECI_GENERATE_ERROR".to_string(),
+            message: format!("{error:#}"),
+            loc: Some(loc),
+            ..Default::default()
+        });
+        assert!(rendered.contains(&format!("{file}:3:5")), "{rendered}");
+        assert!(rendered.contains("- postgrse"), "{rendered}");
+    }
+
+    #[test]
+    fn test_locate_in_searches_only_the_section() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("jobs.yml");
+        std::fs::write(
+            &file,
+            "lint:\n  image: node\n  services: [redis]\n\nrspec:\n  # runs specs\n  services: [redis]\ndeploy:\n  image: ruby\n",
+        )
+        .unwrap();
+        let file = file.display().to_string();
+
+        let loc = locate_in(&file, "rspec", "redis").unwrap();
+        assert_eq!((loc.line, loc.column), (7, 14));
+        // Outside the section: point at the job's key
+        let loc = locate_in(&file, "rspec", "ruby").unwrap();
+        assert_eq!(
+            (loc.line, loc.column, loc.snippet.as_str()),
+            (5, 1, "rspec")
+        );
+        assert!(locate_in(&file, "missing", "redis").is_none());
+        assert_eq!(locate_in(&file, "", "redis").unwrap().line, 3);
+    }
+
+    #[test]
+    fn test_locate_list_item_finds_the_item() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("jobs.yml");
+        std::fs::write(
+            &file,
+            "lint:\n  steps:\n    - name: Lint\n      run: make lint\n\n    # the tests\n    - name: Test\n      run:\n        when: always\n      retry: 2\n  cleanup:\n    - run: stop\n",
+        )
+        .unwrap();
+        let file = file.display().to_string();
+
+        let loc = locate_list_item(&file, "lint", "steps", 1, "name:").unwrap();
+        assert_eq!((loc.line, loc.column), (7, 7));
+        let loc = locate_list_item(&file, "lint", "steps", 1, "retry:").unwrap();
+        assert_eq!((loc.line, loc.column), (10, 7));
+        // Not in the item: point at its `-`
+        let loc = locate_list_item(&file, "", "cleanup", 0, "name:").unwrap();
+        assert_eq!((loc.line, loc.column), (12, 5));
+        assert!(locate_list_item(&file, "", "steps", 2, "run:").is_none());
+    }
+
+    #[test]
+    fn test_unlocated_diagnostic_is_single_line() {
+        let rendered = render_diagnostic(&Diagnostic {
+            level: Level::Warning as i32,
+            code: "CODE".to_string(),
+            message: "careful".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(rendered, "Plugin warning: [CODE] careful");
+    }
+}
@@ -5,9 +5,101 @@
 /// - .cigen/plugins/ directory
 /// - Configuration file
 /// - Registry (future)
-use anyhow::Result;
+use anyhow::{Result, bail};
 use std::path::{Path, PathBuf};
 
+/// Environment variable with extra plugin directories (`:`-separated, searched first)
+pub const PLUGIN_PATH_ENV: &str = "CIGEN_PLUGIN_PATH";
+
+/// Directories searched for plugins referenced by name:
+/// `$CIGEN_PLUGIN_PATH` entries, then `~/.cigen/plugins`
+pub fn plugin_search_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = std::env::var_os(PLUGIN_PATH_ENV)
+        .map(|value| {
+            std::env::split_paths(&value)
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(home) = std::env::var_os("HOME") {
+        dirs.push(PathBuf::from(home).join(".cigen/plugins"));
+    }
+    dirs
+}
+
+/// Directory of the bundled provider plugins: `$CIGEN_PLUGIN_DIR`, the
+/// installed plugins, the cargo target directory in development, or the
+/// directory of the running binary
+pub fn determine_plugin_dir() -> PathBuf {
+    // Respect explicit plugin directory override
+    if let Ok(dir) = std::env::var("CIGEN_PLUGIN_DIR")
+        && !dir.trim().is_empty()
+    {
+        return PathBuf::from(dir);
+    }
+
+    // Default installation path for packaged binaries
+    let installed_dir = PathBuf::from("/usr/local/lib/cigen/plugins");
+    if installed_dir.exists() {
+        return installed_dir;
+    }
+
+    // In development, use target/debug
+    // In production, use the same directory as the cigen binary
+
+    // Check if we're in development (target/debug or target/release exists)
+    let cargo_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let debug_dir = cargo_dir.join("target/debug");
+    let release_dir = cargo_dir.join("target/release");
+
+    if debug_dir.exists() {
+        return debug_dir;
+    } else if release_dir.exists() {
+        return release_dir;
+    }
+
+    // Production: same directory as binary
+    std::env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(|p| p.to_path_buf()))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Resolve a `plugins:` entry to a binary.
+///
+/// Entries containing a path separator are paths (relative ones resolve against
+/// `project_root`); anything else is a binary name looked up in [`plugin_search_dirs`].
+pub fn resolve_plugin(entry: &str, project_root: &Path) -> Result<PathBuf> {
+    if entry.contains('/') || entry.contains(std::path::MAIN_SEPARATOR) {
+        let path = match entry.strip_prefix("~/") {
+            Some(rest) => std::env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join(rest))
+                .unwrap_or_else(|| PathBuf::from(entry)),
+            None => project_root.join(entry),
+        };
+        if !path.is_file() {
+            bail!("Plugin '{entry}' not found at {}", path.display());
+        }
+        return Ok(path);
+    }
+
+    let dirs = plugin_search_dirs();
+    if let Some(path) = dirs
+        .iter()
+        .map(|dir| dir.join(entry))
+        .find(|path| path.is_file())
+    {
+        return Ok(path);
+    }
+    bail!(
+        "Plugin '{entry}' not found; searched {}",
+        dirs.iter()
+            .map(|dir| dir.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
 /// Discover plugins in the system PATH
 pub fn discover_from_path() -> Result<Vec<PathBuf>> {
     let plugins = Vec::new();
@@ -21,15 +113,24 @@ pub fn discover_from_path() -> Result<Vec<PathBuf>> {
     Ok(plugins)
 }
 
-/// Discover plugins in a local directory
-pub fn discover_from_dir(_dir: &Path) -> Result<Vec<PathBuf>> {
-    let plugins = Vec::new();
+/// Discover plugins in a local directory: `cigen-*` files, sorted by name
+pub fn discover_from_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
 
-    // TODO: Implement directory-based discovery
-    // 1. Check if directory exists
-    // 2. List all files
-    // 3. Filter for plugin binaries
-    // 4. Return paths
+    let mut plugins = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_plugin = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("cigen-") && !name.ends_with(".d"));
+        if is_plugin && validate_plugin(&path)? {
+            plugins.push(path);
+        }
+    }
+    plugins.sort();
 
     Ok(plugins)
 }
@@ -47,12 +148,38 @@ pub fn discover_stdlib() -> Result<Vec<PathBuf>> {
     Ok(plugins)
 }
 
-/// Validate that a plugin binary is valid
+/// Validate that a plugin binary is valid (an executable file)
 pub fn validate_plugin(path: &Path) -> Result<bool> {
-    // TODO: Implement validation
-    // 1. Check it's executable
-    // 2. Maybe run with --version or --info flag
-    // 3. Verify it responds to handshake
+    // TODO: Verify it responds to handshake
+
+    if !path.is_file() {
+        return Ok(false);
+    }
+    #[cfg(unix)]
+    let executable = {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()?.permissions().mode() & 0o111 != 0
+    };
+    #[cfg(not(unix))]
+    let executable = true;
+    Ok(executable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_plugin_paths_relative_to_project() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("bin")).unwrap();
+        std::fs::write(dir.path().join("bin/cigen-provider-acme"), "").unwrap();
 
-    Ok(path.exists())
+        assert_eq!(
+            resolve_plugin("bin/cigen-provider-acme", dir.path()).unwrap(),
+            dir.path().join("bin/cigen-provider-acme")
+        );
+        let error = resolve_plugin("bin/missing", dir.path()).unwrap_err();
+        assert!(error.to_string().contains("Plugin 'bin/missing' not found"));
+    }
 }
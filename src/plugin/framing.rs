@@ -81,7 +81,7 @@ pub fn receive_message<M: Message + Default, R: Read>(reader: &mut R) -> Result<
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::plugin::protocol::Hello;
+    use crate::plugin::protocol::{Hello, JobDefinition};
     use std::collections::HashMap;
 
     #[test]
@@ -90,6 +90,7 @@ mod tests {
             core_protocol: 1,
             core_version: "0.2.0".to_string(),
             env: HashMap::new(),
+            ..Default::default()
         };
 
         // Encode to buffer
@@ -113,6 +114,7 @@ mod tests {
             core_protocol: 1,
             core_version: "0.2.0".to_string(),
             env: env.clone(),
+            ..Default::default()
         };
 
         let mut buf = Vec::new();
@@ -122,6 +124,22 @@ mod tests {
         assert_eq!(decoded.env, env);
     }
 
+    #[test]
+    fn test_job_definition_round_trip_keeps_submodules() {
+        let original = JobDefinition {
+            id: "test".to_string(),
+            source_files: vec!["src/**/*".to_string()],
+            source_submodules: vec!["vendor/engine".to_string(), "docs".to_string()],
+            ..Default::default()
+        };
+
+        let mut buf = Vec::new();
+        send_message(&original, &mut buf).unwrap();
+        let decoded: JobDefinition = receive_message(&mut &buf[..]).unwrap();
+
+        assert_eq!(decoded, original);
+    }
+
     #[test]
     fn test_message_size_limit() {
         // Create a message that's too large
@@ -134,6 +152,7 @@ mod tests {
             core_protocol: 1,
             core_version: "0.2.0".to_string(),
             env: huge_env,
+            ..Default::default()
         };
 
         let mut buf = Vec::new();
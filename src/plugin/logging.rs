@@ -0,0 +1,99 @@
+//! Logging setup shared by the core and plugin binaries
+//!
+//! Plugins log to stderr, which the core forwards to its own stderr. The core
+//! passes its verbosity in [`PLUGIN_LOG_ENV`] and its `--log-format` in
+//! [`PLUGIN_LOG_FORMAT_ENV`], so `-v`, `--quiet`, and JSON output apply to
+//! plugin output as well.
+
+use std::io::IsTerminal;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use tracing_subscriber::EnvFilter;
+
+/// Environment variable carrying the core's log level to plugin processes
+pub const PLUGIN_LOG_ENV: &str = "CIGEN_PLUGIN_LOG";
+
+/// Environment variable carrying the core's log format to plugin processes
+pub const PLUGIN_LOG_FORMAT_ENV: &str = "CIGEN_PLUGIN_LOG_FORMAT";
+
+static LOG_FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+/// How log events are written to stderr
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per event and line (NDJSON)
+    Json,
+}
+
+impl LogFormat {
+    pub const NAMES: [&str; 2] = ["text", "json"];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        }
+    }
+
+    /// The format installed by [`init_logging`], which spawned plugins use too
+    pub fn current() -> Self {
+        LOG_FORMAT.get().copied().unwrap_or_default()
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!(
+                "unknown log format '{other}'; expected one of: {}",
+                LogFormat::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// Install the stderr tracing subscriber. Text output leaves out the time
+/// and target; JSON events carry a timestamp, level, and `fields.message`.
+pub fn init_logging(filter: EnvFilter, format: LogFormat) {
+    let _ = LOG_FORMAT.set(format);
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(filter)
+        .with_target(false);
+    match format {
+        LogFormat::Text => subscriber
+            .with_ansi(std::io::stderr().is_terminal())
+            .without_time()
+            .init(),
+        LogFormat::Json => subscriber
+            .json()
+            .with_current_span(false)
+            .with_span_list(false)
+            .init(),
+    }
+}
+
+/// Install a stderr tracing subscriber for the plugin crate `target`.
+///
+/// Uses the level from [`PLUGIN_LOG_ENV`] when spawned by cigen; when run by
+/// hand, `RUST_LOG` applies with `target` defaulting to `info`.
+pub fn init_plugin_logging(target: &str) -> anyhow::Result<()> {
+    let filter = match std::env::var(PLUGIN_LOG_ENV) {
+        Ok(level) if !level.trim().is_empty() => EnvFilter::try_new(format!("{target}={level}"))?,
+        _ => EnvFilter::from_default_env().add_directive(format!("{target}=info").parse()?),
+    };
+    let format = std::env::var(PLUGIN_LOG_FORMAT_ENV)
+        .ok()
+        .and_then(|name| name.parse().ok())
+        .unwrap_or_default();
+
+    init_logging(filter, format);
+    Ok(())
+}
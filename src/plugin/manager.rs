@@ -7,12 +7,20 @@
 /// - Hook invocation (detect, plan, generate, validate)
 /// - Error handling and crash recovery
 use crate::plugin::framing::{receive_message, send_message};
-use crate::plugin::protocol::{Hello, PluginInfo};
+use crate::plugin::logging::{LogFormat, PLUGIN_LOG_ENV, PLUGIN_LOG_FORMAT_ENV};
+use crate::plugin::negotiation::{ProtocolRange, downgrade_schema, negotiate};
+use crate::plugin::protocol::{
+    CigenSchema, GenerateRequest, GenerateResult, Hello, PlanRequest, PlanResult, PluginInfo,
+};
 use anyhow::{Context, Result, bail};
-use std::collections::HashMap;
+use prost::Message;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
-use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
-use std::time::Duration;
+use std::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 /// Plugin manager coordinates all plugin operations
 pub struct PluginManager {
@@ -20,12 +28,14 @@ pub struct PluginManager {
     pub plugins: HashMap<String, PluginMetadata>,
 
     /// Active plugin processes
-    #[allow(dead_code)]
     active: HashMap<String, PluginProcess>,
-}
 
-/// Protocol version that this core supports
-const CORE_PROTOCOL_VERSION: u32 = 1;
+    /// Last plan sent to each plugin, replayed when a restarted plugin needs to generate
+    last_plans: HashMap<String, PlanRequest>,
+
+    /// Restart a crashed plugin once and replay the request
+    retry_crashed: bool,
+}
 
 /// Core version string
 const CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -34,6 +44,12 @@ const CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
 #[allow(dead_code)]
 const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// How long to wait for a plugin that broke off a request to exit
+const EXIT_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Number of plugin stderr lines included in crash reports
+const STDERR_TAIL_LINES: usize = 20;
+
 /// Metadata about a discovered plugin
 #[derive(Debug, Clone)]
 pub struct PluginMetadata {
@@ -42,15 +58,101 @@ pub struct PluginMetadata {
     pub version: String,
     pub protocol: u32,
     pub capabilities: Vec<String>,
+    /// Capability patterns that must be provided by another loaded plugin
+    pub requires: Vec<String>,
+    /// Capability patterns that must not be provided by any other loaded plugin
+    pub conflicts_with: Vec<String>,
 }
 
 /// An active plugin process with stdio handles
 pub struct PluginProcess {
     pub metadata: PluginMetadata,
-    #[allow(dead_code)]
     process: Child,
     stdin: ChildStdin,
     stdout: ChildStdout,
+    /// Most recent stderr lines, kept for crash reports
+    stderr_tail: Arc<Mutex<VecDeque<String>>>,
+    /// Thread forwarding plugin stderr to ours
+    stderr_forwarder: Option<JoinHandle<()>>,
+}
+
+/// A request that failed, and whether it failed because the plugin process died
+struct ExchangeFailure {
+    error: anyhow::Error,
+    crashed: bool,
+}
+
+impl PluginProcess {
+    /// Send `request` and read the response. On failure the process is reaped
+    /// and the error describes how it exited, including the tail of its stderr.
+    fn exchange<Req: Message, Resp: Message + Default>(
+        mut self,
+        request: &Req,
+        kind: &str,
+    ) -> std::result::Result<(Self, Resp), ExchangeFailure> {
+        let outcome =
+            send_message(request, &mut self.stdin).and_then(|()| receive_message(&mut self.stdout));
+        match outcome {
+            Ok(response) => Ok((self, response)),
+            Err(error) => Err(self.into_failure(error, kind)),
+        }
+    }
+
+    fn into_failure(mut self, error: anyhow::Error, kind: &str) -> ExchangeFailure {
+        let status = wait_for_exit(&mut self.process, EXIT_GRACE_PERIOD);
+        if status.is_none() {
+            let _ = self.process.kill();
+            let _ = self.process.wait();
+        }
+        if let Some(forwarder) = self.stderr_forwarder.take() {
+            let _ = forwarder.join();
+        }
+
+        let name = &self.metadata.name;
+        let mut message = match status {
+            Some(status) => format!("Plugin '{name}' exited ({status}) during {kind} request"),
+            None => format!("Plugin '{name}' sent an invalid {kind} response"),
+        };
+        let tail = self.stderr_tail.lock().unwrap_or_else(|e| e.into_inner());
+        if !tail.is_empty() {
+            message.push_str("\nLast plugin stderr output:");
+            for line in tail.iter() {
+                message.push_str("\n  ");
+                message.push_str(line);
+            }
+        }
+
+        ExchangeFailure {
+            error: error.context(message),
+            crashed: status.is_some(),
+        }
+    }
+}
+
+fn wait_for_exit(process: &mut Child, timeout: Duration) -> Option<ExitStatus> {
+    let start = Instant::now();
+    loop {
+        match process.try_wait() {
+            Ok(Some(status)) => return Some(status),
+            Ok(None) if start.elapsed() < timeout => std::thread::sleep(Duration::from_millis(20)),
+            _ => return None,
+        }
+    }
+}
+
+/// Forward plugin stderr to ours, keeping the last lines for crash reports
+fn forward_stderr(stderr: ChildStderr, tail: Arc<Mutex<VecDeque<String>>>) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        for line in BufReader::new(stderr).lines() {
+            let Ok(line) = line else { break };
+            eprintln!("{line}");
+            let mut tail = tail.lock().unwrap_or_else(|e| e.into_inner());
+            if tail.len() == STDERR_TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+    })
 }
 
 impl PluginManager {
@@ -59,9 +161,16 @@ impl PluginManager {
         Self {
             plugins: HashMap::new(),
             active: HashMap::new(),
+            last_plans: HashMap::new(),
+            retry_crashed: true,
         }
     }
 
+    /// Whether a plugin that crashes mid-request is restarted once and the request replayed
+    pub fn set_retry_crashed(&mut self, retry: bool) {
+        self.retry_crashed = retry;
+    }
+
     /// Discover plugins from PATH and config
     pub async fn discover(&mut self) -> Result<()> {
         // TODO: Implement plugin discovery
@@ -81,52 +190,84 @@ impl PluginManager {
     pub async fn spawn<P: AsRef<Path>>(&mut self, plugin_path: P) -> Result<String> {
         let path = plugin_path.as_ref();
 
+        // Plugins log at the same level and in the same format as the core
+        let log_level = tracing::level_filters::LevelFilter::current().to_string();
+        let log_format = LogFormat::current().as_str();
+
         // Spawn the plugin process and perform handshake in a blocking context
-        let (process, stdin, stdout, plugin_info) = tokio::task::spawn_blocking({
+        let (mut plugin, plugin_info) = tokio::task::spawn_blocking({
             let path = path.to_path_buf();
-            move || -> Result<(Child, ChildStdin, ChildStdout, PluginInfo)> {
+            move || -> Result<(PluginProcess, PluginInfo)> {
                 // Spawn the plugin process
                 let mut child = Command::new(&path)
                     .stdin(Stdio::piped())
                     .stdout(Stdio::piped())
-                    .stderr(Stdio::inherit()) // Plugin logs go to our stderr
+                    .stderr(Stdio::piped()) // Forwarded to our stderr
+                    .env(PLUGIN_LOG_ENV, log_level)
+                    .env(PLUGIN_LOG_FORMAT_ENV, log_format)
                     .spawn()
                     .with_context(|| format!("Failed to spawn plugin: {}", path.display()))?;
 
-                let mut stdin = child
+                let stdin = child
                     .stdin
                     .take()
                     .context("Failed to capture plugin stdin")?;
-                let mut stdout = child
+                let stdout = child
                     .stdout
                     .take()
                     .context("Failed to capture plugin stdout")?;
+                let stderr = child
+                    .stderr
+                    .take()
+                    .context("Failed to capture plugin stderr")?;
+                let stderr_tail = Arc::new(Mutex::new(VecDeque::new()));
+
+                let plugin = PluginProcess {
+                    metadata: PluginMetadata {
+                        name: path.display().to_string(),
+                        path: path.clone(),
+                        version: String::new(),
+                        protocol: 0,
+                        capabilities: Vec::new(),
+                        requires: Vec::new(),
+                        conflicts_with: Vec::new(),
+                    },
+                    process: child,
+                    stdin,
+                    stdout,
+                    stderr_forwarder: Some(forward_stderr(stderr, stderr_tail.clone())),
+                    stderr_tail,
+                };
 
-                // Send Hello message
+                // Send Hello and receive PluginInfo
+                // TODO: Add timeout handling here
                 let hello = Hello {
-                    core_protocol: CORE_PROTOCOL_VERSION,
+                    core_protocol: ProtocolRange::CORE.min,
                     core_version: CORE_VERSION.to_string(),
                     env: std::env::vars().collect(),
+                    protocol_min: ProtocolRange::CORE.min,
+                    protocol_max: ProtocolRange::CORE.max,
                 };
-
-                send_message(&hello, &mut stdin)
-                    .context("Failed to send Hello message to plugin")?;
-
-                // Receive PluginInfo response
-                // TODO: Add timeout handling here
-                let info: PluginInfo = receive_message(&mut stdout)
-                    .context("Failed to receive PluginInfo from plugin")?;
-
-                Ok((child, stdin, stdout, info))
+                plugin
+                    .exchange(&hello, "handshake")
+                    .map_err(|failure| failure.error)
             }
         })
         .await??;
 
-        // Validate protocol version
-        if plugin_info.protocol != CORE_PROTOCOL_VERSION {
+        // Both sides must settle on the highest version they have in common
+        let plugin_range = ProtocolRange::from_plugin_info(&plugin_info);
+        let protocol = negotiate(ProtocolRange::CORE, plugin_range).with_context(|| {
+            format!(
+                "Plugin '{}' ({}) is incompatible with this version of cigen",
+                plugin_info.name,
+                path.display()
+            )
+        })?;
+        if plugin_info.protocol != protocol {
             bail!(
-                "Plugin protocol mismatch: core expects {}, plugin has {}",
-                CORE_PROTOCOL_VERSION,
+                "Plugin '{}' chose protocol {}, but the highest version supported by both sides is {protocol}",
+                plugin_info.name,
                 plugin_info.protocol
             );
         }
@@ -136,11 +277,13 @@ impl PluginManager {
             name: plugin_info.name.clone(),
             path: path.to_path_buf(),
             version: plugin_info.version.clone(),
-            protocol: plugin_info.protocol,
+            protocol,
             capabilities: plugin_info.capabilities.clone(),
+            requires: plugin_info.requires.clone(),
+            conflicts_with: plugin_info.conflicts_with.clone(),
         };
 
-        tracing::info!(
+        tracing::debug!(
             "Plugin handshake successful: {} v{} (protocol {})",
             metadata.name,
             metadata.version,
@@ -150,19 +293,81 @@ impl PluginManager {
 
         // Store the active plugin process
         let plugin_name = metadata.name.clone();
-        let plugin_process = PluginProcess {
-            metadata: metadata.clone(),
-            process,
-            stdin,
-            stdout,
-        };
+        if let Some(existing) = self.active.get(&plugin_name) {
+            bail!(
+                "Plugin '{plugin_name}' from {} is already loaded from {}",
+                path.display(),
+                existing.metadata.path.display()
+            );
+        }
+        plugin.metadata = metadata.clone();
 
-        self.active.insert(plugin_name.clone(), plugin_process);
+        self.active.insert(plugin_name.clone(), plugin);
         self.plugins.insert(plugin_name.clone(), metadata);
 
         Ok(plugin_name)
     }
 
+    /// Move an active plugin into a manager of its own, so requests to it can
+    /// run alongside requests to other plugins. [`PluginManager::absorb`]
+    /// takes it back.
+    pub fn detach(&mut self, plugin_id: &str) -> Result<PluginManager> {
+        let plugin = self.active.remove(plugin_id).context("Plugin not found")?;
+        let mut detached = PluginManager::new();
+        detached.retry_crashed = self.retry_crashed;
+        if let Some(metadata) = self.plugins.get(plugin_id) {
+            detached
+                .plugins
+                .insert(plugin_id.to_string(), metadata.clone());
+        }
+        if let Some(plan) = self.last_plans.remove(plugin_id) {
+            detached.last_plans.insert(plugin_id.to_string(), plan);
+        }
+        detached.active.insert(plugin_id.to_string(), plugin);
+        Ok(detached)
+    }
+
+    /// Take back the plugins of a manager made by [`PluginManager::detach`],
+    /// including any restarted while it was detached
+    pub fn absorb(&mut self, other: PluginManager) {
+        self.plugins.extend(other.plugins);
+        self.active.extend(other.active);
+        self.last_plans.extend(other.last_plans);
+    }
+
+    /// Handshake metadata of a loaded plugin
+    pub fn metadata(&self, name: &str) -> Option<&PluginMetadata> {
+        self.plugins.get(name)
+    }
+
+    /// Name of the active plugin providing `capability` (e.g. `provider:circleci`)
+    pub fn find_capability(&self, capability: &str) -> Option<String> {
+        let mut names: Vec<&String> = self
+            .active
+            .iter()
+            .filter(|(_, plugin)| {
+                plugin
+                    .metadata
+                    .capabilities
+                    .iter()
+                    .any(|provided| provided == capability)
+            })
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        names.first().map(|name| name.to_string())
+    }
+
+    /// Enforce the `requires` and `conflicts_with` declarations of all active plugins
+    pub fn check_compatibility(&self) -> Result<()> {
+        let plugins: Vec<&PluginMetadata> = self
+            .active
+            .values()
+            .map(|plugin| &plugin.metadata)
+            .collect();
+        check_declarations(plugins)
+    }
+
     /// Invoke a hook on all plugins with a capability
     pub async fn invoke_hook(&self, _capability: &str, _hook: &str) -> Result<()> {
         // TODO: Implement hook invocation
@@ -177,48 +382,103 @@ impl PluginManager {
     pub async fn send_plan(
         &mut self,
         plugin_id: &str,
-        request: crate::plugin::protocol::PlanRequest,
-    ) -> Result<crate::plugin::protocol::PlanResult> {
-        // Take the plugin out of the map temporarily
-        let mut plugin = self.active.remove(plugin_id).context("Plugin not found")?;
-
-        let result = tokio::task::spawn_blocking(
-            move || -> Result<(PluginProcess, crate::plugin::protocol::PlanResult)> {
-                send_message(&request, &mut plugin.stdin)?;
-                let response: crate::plugin::protocol::PlanResult =
-                    receive_message(&mut plugin.stdout)?;
-                Ok((plugin, response))
-            },
-        )
-        .await??;
-
-        // Put the plugin back
-        self.active.insert(plugin_id.to_string(), result.0);
-        Ok(result.1)
+        mut request: PlanRequest,
+    ) -> Result<PlanResult> {
+        if let Some(schema) = request.schema.as_mut() {
+            self.downgrade_for(plugin_id, schema);
+        }
+        self.last_plans
+            .insert(plugin_id.to_string(), request.clone());
+        self.request(plugin_id, request, "plan").await
     }
 
     /// Send a Generate request to a plugin
     pub async fn send_generate(
         &mut self,
         plugin_id: &str,
-        request: crate::plugin::protocol::GenerateRequest,
-    ) -> Result<crate::plugin::protocol::GenerateResult> {
-        // Take the plugin out of the map temporarily
-        let mut plugin = self.active.remove(plugin_id).context("Plugin not found")?;
-
-        let result = tokio::task::spawn_blocking(
-            move || -> Result<(PluginProcess, crate::plugin::protocol::GenerateResult)> {
-                send_message(&request, &mut plugin.stdin)?;
-                let response: crate::plugin::protocol::GenerateResult =
-                    receive_message(&mut plugin.stdout)?;
-                Ok((plugin, response))
-            },
-        )
-        .await??;
+        mut request: GenerateRequest,
+    ) -> Result<GenerateResult> {
+        if let Some(schema) = request.schema.as_mut() {
+            self.downgrade_for(plugin_id, schema);
+        }
+        self.request(plugin_id, request, "generate").await
+    }
+
+    /// Drop schema fields newer than the protocol version negotiated with the plugin
+    fn downgrade_for(&self, plugin_id: &str, schema: &mut CigenSchema) {
+        if let Some(metadata) = self.plugins.get(plugin_id) {
+            downgrade_schema(schema, metadata.protocol);
+        }
+    }
+
+    /// Send a request, restarting the plugin once if it crashed while handling it
+    async fn request<Req, Resp>(
+        &mut self,
+        plugin_id: &str,
+        request: Req,
+        kind: &str,
+    ) -> Result<Resp>
+    where
+        Req: Message + Clone + Send + 'static,
+        Resp: Message + Default + Send + 'static,
+    {
+        let failure = match self.round_trip(plugin_id, request.clone(), kind).await? {
+            Ok(response) => return Ok(response),
+            Err(failure) if failure.crashed && self.retry_crashed => failure,
+            Err(failure) => return Err(failure.error),
+        };
 
-        // Put the plugin back
-        self.active.insert(plugin_id.to_string(), result.0);
-        Ok(result.1)
+        tracing::warn!("{:#}", failure.error);
+        tracing::warn!("Restarting plugin '{plugin_id}' and retrying the {kind} request");
+
+        let path = self
+            .plugins
+            .get(plugin_id)
+            .map(|metadata| metadata.path.clone())
+            .context("Plugin not found")?;
+        self.spawn(&path)
+            .await
+            .with_context(|| format!("Failed to restart plugin '{plugin_id}' after it crashed"))?;
+
+        // Plugins expect a plan before each generate request
+        if kind == "generate"
+            && let Some(plan) = self.last_plans.get(plugin_id).cloned()
+        {
+            self.round_trip::<_, PlanResult>(plugin_id, plan, "plan")
+                .await?
+                .map_err(|failure| failure.error)?;
+        }
+
+        self.round_trip(plugin_id, request, kind)
+            .await?
+            .map_err(|failure| {
+                failure.error.context(format!(
+                    "Plugin '{plugin_id}' crashed again after restarting"
+                ))
+            })
+    }
+
+    /// One request/response exchange with an active plugin. A failed plugin is
+    /// removed from the active set.
+    async fn round_trip<Req, Resp>(
+        &mut self,
+        plugin_id: &str,
+        request: Req,
+        kind: &str,
+    ) -> Result<std::result::Result<Resp, ExchangeFailure>>
+    where
+        Req: Message + Send + 'static,
+        Resp: Message + Default + Send + 'static,
+    {
+        // Take the plugin out of the map while the blocking exchange runs
+        let plugin = self.active.remove(plugin_id).context("Plugin not found")?;
+        let kind = kind.to_string();
+        let outcome = tokio::task::spawn_blocking(move || plugin.exchange(&request, &kind)).await?;
+
+        Ok(outcome.map(|(plugin, response)| {
+            self.active.insert(plugin_id.to_string(), plugin);
+            response
+        }))
     }
 
     /// Shutdown all active plugins
@@ -230,7 +490,7 @@ impl PluginManager {
 
         for name in plugin_names {
             if let Some(plugin) = self.active.remove(&name) {
-                tracing::info!("Shutting down plugin: {}", name);
+                tracing::debug!("Shutting down plugin: {}", name);
 
                 // Clone name for error message since it's moved into closure
                 let name_for_error = name.clone();
@@ -256,6 +516,9 @@ impl PluginManager {
                                     plugin_name,
                                     status
                                 );
+                                if let Some(forwarder) = plugin.stderr_forwarder.take() {
+                                    let _ = forwarder.join();
+                                }
                                 return Ok(());
                             }
                             None => {
@@ -285,8 +548,122 @@ impl PluginManager {
     }
 }
 
+/// Check `requires`/`conflicts_with` declarations across a set of plugins
+fn check_declarations(mut plugins: Vec<&PluginMetadata>) -> Result<()> {
+    plugins.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for plugin in &plugins {
+        let others = plugins.iter().filter(|other| other.name != plugin.name);
+
+        for pattern in &plugin.conflicts_with {
+            for other in others.clone() {
+                if let Some(capability) = other
+                    .capabilities
+                    .iter()
+                    .find(|capability| capability_matches(pattern, capability))
+                {
+                    bail!(
+                        "Plugins '{}' ({}) and '{}' ({}) conflict: '{}' declares \
+                         conflicts_with '{pattern}', which matches '{capability}'",
+                        plugin.name,
+                        plugin.path.display(),
+                        other.name,
+                        other.path.display(),
+                        plugin.name
+                    );
+                }
+            }
+        }
+
+        for pattern in &plugin.requires {
+            let satisfied = others.clone().any(|other| {
+                other
+                    .capabilities
+                    .iter()
+                    .any(|capability| capability_matches(pattern, capability))
+            });
+            if !satisfied {
+                bail!(
+                    "Plugin '{}' requires '{pattern}', which no loaded plugin provides",
+                    plugin.name
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Match a capability against a pattern; a trailing `*` matches any suffix
+fn capability_matches(pattern: &str, capability: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => capability.starts_with(prefix),
+        None => pattern == capability,
+    }
+}
+
 impl Default for PluginManager {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plugin(name: &str, capability: &str, conflicts_with: &[&str]) -> PluginMetadata {
+        PluginMetadata {
+            name: name.to_string(),
+            path: PathBuf::from(format!("/plugins/{name}")),
+            version: "1.0.0".to_string(),
+            protocol: ProtocolRange::CORE.max,
+            capabilities: vec![capability.to_string()],
+            requires: Vec::new(),
+            conflicts_with: conflicts_with.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_wildcard_conflict_is_rejected() {
+        let circleci = plugin(
+            "provider/circleci",
+            "provider:circleci",
+            &["provider:circleci"],
+        );
+        let acme = plugin("acme/provider", "provider:acme", &["provider:*"]);
+
+        let error = check_declarations(vec![&circleci, &acme]).unwrap_err();
+        assert!(
+            error.to_string().contains(
+                "declares conflicts_with 'provider:*', which matches 'provider:circleci'"
+            ),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_distinct_providers_are_compatible() {
+        let circleci = plugin(
+            "provider/circleci",
+            "provider:circleci",
+            &["provider:circleci"],
+        );
+        let github = plugin("provider/github", "provider:github", &["provider:github"]);
+        check_declarations(vec![&circleci, &github]).unwrap();
+    }
+
+    #[test]
+    fn test_missing_requirement_is_rejected() {
+        let mut rails = plugin("lang/rails", "lang:rails", &[]);
+        rails.requires = vec!["lang:ruby".to_string()];
+        let error = check_declarations(vec![&rails]).unwrap_err();
+        assert!(
+            error.to_string().contains("requires 'lang:ruby'"),
+            "{error}"
+        );
+
+        let ruby = plugin("lang/ruby", "lang:ruby", &[]);
+        check_declarations(vec![&rails, &ruby]).unwrap();
+    }
+}
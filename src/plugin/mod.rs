@@ -3,9 +3,14 @@
 /// This module implements the plugin architecture that allows CIGen to be extended
 /// with providers (CircleCI, GitHub Actions, Buildkite) and modules (language support,
 /// caching, etc.) as separate processes communicating via gRPC.
+pub mod capabilities;
+pub mod diagnostics;
 pub mod discovery;
 pub mod framing;
+pub mod logging;
 pub mod manager;
+pub mod negotiation;
+pub mod overrides;
 pub mod protocol;
 pub mod stdio_transport;
 
@@ -0,0 +1,182 @@
+//! Plugin protocol version negotiation
+//!
+//! The core advertises the range of protocol versions it supports in `Hello`
+//! and the plugin answers in `PluginInfo` with its own range and the version it
+//! will speak: the highest version both sides support. Requests sent to a
+//! plugin that negotiated an older version omit fields it doesn't know about.
+
+use anyhow::{Result, bail};
+use std::fmt;
+
+use super::protocol::{CigenSchema, Hello, PluginInfo};
+
+/// First protocol version with `JobDefinition.source_submodules` and `source_file`
+pub const PROTOCOL_JOB_SOURCES: u32 = 2;
+
+/// Inclusive range of protocol versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl ProtocolRange {
+    /// Versions this core can speak
+    pub const CORE: Self = Self::new(1, PROTOCOL_JOB_SOURCES);
+
+    pub const fn new(min: u32, max: u32) -> Self {
+        Self { min, max }
+    }
+
+    /// Range advertised by the core. Cores that predate negotiation only send
+    /// `core_protocol`.
+    pub fn from_hello(hello: &Hello) -> Self {
+        if hello.protocol_max == 0 {
+            Self::new(hello.core_protocol, hello.core_protocol)
+        } else {
+            Self::new(hello.protocol_min, hello.protocol_max)
+        }
+    }
+
+    /// Range supported by a plugin. Plugins that predate negotiation only send
+    /// `protocol`.
+    pub fn from_plugin_info(info: &PluginInfo) -> Self {
+        if info.protocol_max == 0 {
+            Self::new(info.protocol, info.protocol)
+        } else {
+            Self::new(info.protocol_min, info.protocol_max)
+        }
+    }
+
+    pub fn contains(&self, version: u32) -> bool {
+        (self.min..=self.max).contains(&version)
+    }
+}
+
+impl fmt::Display for ProtocolRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else {
+            write!(f, "{}-{}", self.min, self.max)
+        }
+    }
+}
+
+/// Highest protocol version supported by both sides
+pub fn negotiate(ours: ProtocolRange, theirs: ProtocolRange) -> Result<u32> {
+    let min = ours.min.max(theirs.min);
+    let max = ours.max.min(theirs.max);
+    if min > max {
+        bail!("No common protocol version: we support {ours}, the other side supports {theirs}");
+    }
+    Ok(max)
+}
+
+/// Clear schema fields that `protocol` doesn't define
+pub fn downgrade_schema(schema: &mut CigenSchema, protocol: u32) {
+    if protocol < PROTOCOL_JOB_SOURCES {
+        for job in &mut schema.jobs {
+            job.source_submodules.clear();
+            job.source_file.clear();
+            job.source_section.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::protocol::JobDefinition;
+
+    #[test]
+    fn test_negotiate_picks_highest_common_version() {
+        let cases = [
+            // identical ranges
+            ((1, 2), (1, 2), 2),
+            // plugin is older
+            ((1, 2), (1, 1), 1),
+            // plugin is newer
+            ((1, 2), (2, 3), 2),
+            // one range contains the other
+            ((1, 5), (2, 3), 3),
+            ((2, 3), (1, 5), 3),
+            // ranges touch at a single version
+            ((1, 2), (2, 2), 2),
+        ];
+        for ((our_min, our_max), (their_min, their_max), expected) in cases {
+            let ours = ProtocolRange::new(our_min, our_max);
+            let theirs = ProtocolRange::new(their_min, their_max);
+            assert_eq!(
+                negotiate(ours, theirs).unwrap(),
+                expected,
+                "{ours} vs {theirs}"
+            );
+            assert_eq!(
+                negotiate(theirs, ours).unwrap(),
+                expected,
+                "{theirs} vs {ours}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_negotiate_rejects_disjoint_ranges() {
+        for (ours, theirs) in [
+            (ProtocolRange::new(1, 2), ProtocolRange::new(3, 4)),
+            (ProtocolRange::new(3, 4), ProtocolRange::new(1, 2)),
+        ] {
+            let error = negotiate(ours, theirs).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "No common protocol version: we support {ours}, the other side supports {theirs}"
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_legacy_handshakes_advertise_a_single_version() {
+        let hello = Hello {
+            core_protocol: 1,
+            ..Default::default()
+        };
+        assert_eq!(ProtocolRange::from_hello(&hello), ProtocolRange::new(1, 1));
+
+        let info = PluginInfo {
+            protocol: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            ProtocolRange::from_plugin_info(&info),
+            ProtocolRange::new(1, 1)
+        );
+        assert_eq!(
+            negotiate(ProtocolRange::CORE, ProtocolRange::new(1, 1)).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_downgrade_schema_drops_newer_fields() {
+        let job = JobDefinition {
+            id: "test".to_string(),
+            source_submodules: vec!["vendor/lib".to_string()],
+            source_file: ".cigen/workflows/ci/jobs/test.yml".to_string(),
+            ..Default::default()
+        };
+        let mut schema = CigenSchema {
+            jobs: vec![job],
+            ..Default::default()
+        };
+
+        downgrade_schema(&mut schema, PROTOCOL_JOB_SOURCES);
+        assert_eq!(schema.jobs[0].source_submodules, vec!["vendor/lib"]);
+
+        downgrade_schema(&mut schema, 1);
+        assert!(schema.jobs[0].source_submodules.is_empty());
+        assert!(schema.jobs[0].source_file.is_empty());
+        assert_eq!(schema.jobs[0].id, "test");
+    }
+}
@@ -0,0 +1,102 @@
+//! `provider_overrides` on jobs
+//!
+//! A job's overrides for a provider are deep-merged into the job mapping the
+//! provider generated, after everything else, so they can add keys cigen
+//! doesn't model or replace ones it produced.
+
+use anyhow::{Context, Result};
+use serde_yaml::{Mapping, Value};
+
+use super::protocol::{Diagnostic, JobDefinition, diagnostic::Level};
+
+/// Merge `overrides` into `target`. Mappings merge key by key; anything else
+/// replaces what was there. Returns the dotted paths of generated values that
+/// were replaced.
+pub fn deep_merge(target: &mut Mapping, overrides: &Mapping) -> Vec<String> {
+    let mut replaced = Vec::new();
+    merge_into(target, overrides, "", &mut replaced);
+    replaced
+}
+
+fn merge_into(target: &mut Mapping, overrides: &Mapping, prefix: &str, replaced: &mut Vec<String>) {
+    for (key, value) in overrides {
+        let path = match key.as_str() {
+            Some(key) if prefix.is_empty() => key.to_string(),
+            Some(key) => format!("{prefix}.{key}"),
+            None => format!(
+                "{prefix}.{}",
+                serde_yaml::to_string(key).unwrap_or_default().trim()
+            ),
+        };
+        match (target.get_mut(key), value) {
+            (Some(Value::Mapping(existing)), Value::Mapping(nested)) => {
+                merge_into(existing, nested, &path, replaced);
+            }
+            (Some(existing), _) => {
+                if existing != value {
+                    replaced.push(path);
+                }
+                *existing = value.clone();
+            }
+            (None, _) => {
+                target.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Apply `job`'s overrides for `provider` to its generated mapping. Each
+/// generated value an override replaced is reported as an info diagnostic.
+pub fn apply_provider_overrides(
+    job: &JobDefinition,
+    provider: &str,
+    job_map: &mut Mapping,
+) -> Result<Vec<Diagnostic>> {
+    let Some(overrides) = job.provider_overrides.get(provider) else {
+        return Ok(Vec::new());
+    };
+    let overrides: Mapping = serde_yaml::from_str(overrides)
+        .with_context(|| format!("Invalid provider_overrides.{provider} for job '{}'", job.id))?;
+    Ok(deep_merge(job_map, &overrides)
+        .into_iter()
+        .map(|path| Diagnostic {
+            level: Level::Info as i32,
+            code: "PROVIDER_OVERRIDE".to_string(),
+            title: "Generated value overridden".to_string(),
+            message: format!(
+                "Job '{}': provider_overrides.{provider}.{path} replaces the value cigen generated",
+                job.id
+            ),
+            fix_hint: String::new(),
+            loc: None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(yaml: &str) -> Mapping {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn nested_mappings_merge_and_scalars_replace() {
+        let mut job = mapping(
+            "resource_class: medium\nenvironment:\n  RAILS_ENV: test\n  CI: 'true'\nsteps:\n- checkout\n",
+        );
+        let replaced = deep_merge(
+            &mut job,
+            &mapping(
+                "resource_class: large\nenvironment:\n  CI: 'true'\n  TZ: UTC\ncircleci_ip_ranges: true\n",
+            ),
+        );
+
+        assert_eq!(replaced, vec!["resource_class"]);
+        assert_eq!(
+            serde_yaml::to_string(&job).unwrap(),
+            "resource_class: large\nenvironment:\n  RAILS_ENV: test\n  CI: 'true'\n  TZ: UTC\nsteps:\n- checkout\ncircleci_ip_ranges: true\n"
+        );
+    }
+}
@@ -5,3 +5,20 @@
 
 // Include the generated code from tonic-prost-build
 tonic::include_proto!("cigen.plugin.v1");
+
+impl CigenSchema {
+    /// `cache_version` from config.yml, for [`crate::schema::versioned_cache_key`]
+    pub fn cache_key_version(&self) -> Option<u32> {
+        (self.cache_version != 0).then_some(self.cache_version)
+    }
+}
+
+impl JobDefinition {
+    /// Whether the job is an approval gate (`type: approval`) rather than work to run
+    pub fn is_approval(&self) -> bool {
+        self.extra.get("type").is_some_and(|value| {
+            serde_yaml::from_str::<serde_yaml::Value>(value)
+                .is_ok_and(|value| value.as_str() == Some("approval"))
+        })
+    }
+}
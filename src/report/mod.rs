@@ -0,0 +1,255 @@
+//! Machine-readable summary of a `cigen generate` run, for `--report`
+//!
+//! The JSON layout is versioned by [`REPORT_SCHEMA_VERSION`]: fields are only
+//! added within a version, and renaming or removing one bumps it.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::plugin::capabilities::Degradation;
+use crate::plugin::protocol::{Diagnostic, diagnostic::Level};
+
+/// Version of the report layout, written as `schema_version`
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenerationReport {
+    pub schema_version: u32,
+    /// Generated files, sorted by path
+    pub files: Vec<FileReport>,
+    /// Jobs generated for each workflow, sorted by workflow
+    pub workflows: Vec<WorkflowReport>,
+    /// Jobs left out of the generated config
+    pub skipped_jobs: Vec<SkippedJob>,
+    /// Warnings and notes from the providers
+    pub diagnostics: Vec<DiagnosticReport>,
+    /// Features the providers emulate, pass through, or drop, by job
+    pub degradations: Vec<Degradation>,
+    /// Wall time of each phase, in the order they ran
+    pub phases: Vec<PhaseTiming>,
+}
+
+impl Default for GenerationReport {
+    fn default() -> Self {
+        Self {
+            schema_version: REPORT_SCHEMA_VERSION,
+            files: Vec::new(),
+            workflows: Vec::new(),
+            skipped_jobs: Vec::new(),
+            diagnostics: Vec::new(),
+            degradations: Vec::new(),
+            phases: Vec::new(),
+        }
+    }
+}
+
+impl GenerationReport {
+    /// Record the generated files' sizes and SHA-256 hashes
+    pub fn set_files(&mut self, files: &HashMap<String, String>) {
+        let mut paths: Vec<&String> = files.keys().collect();
+        paths.sort();
+        self.files = paths
+            .into_iter()
+            .map(|path| FileReport {
+                path: path.clone(),
+                bytes: files[path].len() as u64,
+                sha256: hex::encode(Sha256::digest(files[path].as_bytes())),
+            })
+            .collect();
+    }
+
+    pub fn set_workflows(&mut self, jobs_by_workflow: &BTreeMap<String, Vec<String>>) {
+        self.workflows = jobs_by_workflow
+            .iter()
+            .map(|(name, jobs)| WorkflowReport {
+                name: name.clone(),
+                jobs: jobs.clone(),
+            })
+            .collect();
+    }
+
+    pub fn skip_jobs<'a>(&mut self, jobs: impl IntoIterator<Item = &'a String>, reason: &str) {
+        self.skipped_jobs
+            .extend(jobs.into_iter().map(|job| SkippedJob {
+                job: job.clone(),
+                reason: reason.to_string(),
+            }));
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json + "\n")
+            .with_context(|| format!("Failed to write report to {}", path.display()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileReport {
+    /// Path relative to the output directory
+    pub path: String,
+    pub bytes: u64,
+    /// Hex SHA-256 of the contents
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkflowReport {
+    pub name: String,
+    /// Job ids, with matrix variants expanded, sorted
+    pub jobs: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedJob {
+    pub job: String,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiagnosticReport {
+    /// `error`, `warning`, or `info`
+    pub level: String,
+    pub code: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<u32>,
+}
+
+impl From<&Diagnostic> for DiagnosticReport {
+    fn from(diagnostic: &Diagnostic) -> Self {
+        let level = match diagnostic.level() {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Info | Level::Unspecified => "info",
+        };
+        let location = diagnostic.loc.as_ref().filter(|loc| !loc.file.is_empty());
+        Self {
+            level: level.to_string(),
+            code: diagnostic.code.clone(),
+            message: diagnostic.message.clone(),
+            file: location.map(|loc| loc.file.clone()),
+            line: location.map(|loc| loc.line).filter(|line| *line > 0),
+        }
+    }
+}
+
+/// A phase of generation
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    /// Reading and merging the `.cigen` files
+    Load,
+    /// Expanding the config and checking the job graph
+    Validate,
+    /// Providers generating their files
+    Generate,
+    /// Providers checking the files they generated
+    ProviderValidate,
+}
+
+impl Phase {
+    /// Name a plugin uses for the phase in `GenerateResult.phase_millis`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Phase::Load => "load",
+            Phase::Validate => "validate",
+            Phase::Generate => "generate",
+            Phase::ProviderValidate => "provider-validate",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub phase: Phase,
+    pub millis: u64,
+}
+
+impl PhaseTiming {
+    pub fn new(phase: Phase, duration: Duration) -> Self {
+        Self {
+            phase,
+            millis: duration.as_millis() as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::capabilities::{Feature, Support};
+    use crate::plugin::protocol::SourceLocation;
+    use serde_json::json;
+
+    #[test]
+    fn reports_serialize_with_a_stable_layout() {
+        let mut report = GenerationReport::default();
+        report.set_files(&HashMap::from([(
+            ".circleci/config.yml".to_string(),
+            "version: 2.1\n".to_string(),
+        )]));
+        report.set_workflows(&BTreeMap::from([(
+            "ci".to_string(),
+            vec!["lint".to_string(), "test".to_string()],
+        )]));
+        report.skip_jobs(&["docs".to_string()], "no changes since main");
+        report.diagnostics.push(DiagnosticReport::from(&Diagnostic {
+            level: Level::Warning as i32,
+            code: "CIRCLECI_SLACK_FIXED".to_string(),
+            message: "fixed notifies on every pass".to_string(),
+            loc: Some(SourceLocation {
+                file: ".cigen/config.yml".to_string(),
+                line: 3,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        report.degradations.push(Degradation {
+            provider: "github".to_string(),
+            job: "test".to_string(),
+            feature: Feature::Workspaces,
+            support: Support::Dropped,
+        });
+        report.phases = vec![
+            PhaseTiming::new(Phase::Load, Duration::from_millis(12)),
+            PhaseTiming::new(Phase::ProviderValidate, Duration::from_micros(2500)),
+        ];
+
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "schema_version": 1,
+                "files": [{
+                    "path": ".circleci/config.yml",
+                    "bytes": 13,
+                    "sha256": "a3868ce58a9603fd8d660e184c9c5a9c2f2820a69652193de9ee112d7c0dc97f"
+                }],
+                "workflows": [{ "name": "ci", "jobs": ["lint", "test"] }],
+                "skipped_jobs": [{ "job": "docs", "reason": "no changes since main" }],
+                "diagnostics": [{
+                    "level": "warning",
+                    "code": "CIRCLECI_SLACK_FIXED",
+                    "message": "fixed notifies on every pass",
+                    "file": ".cigen/config.yml",
+                    "line": 3
+                }],
+                "degradations": [{
+                    "provider": "github",
+                    "job": "test",
+                    "feature": "workspaces",
+                    "support": "dropped"
+                }],
+                "phases": [
+                    { "phase": "load", "millis": 12 },
+                    { "phase": "provider-validate", "millis": 2 }
+                ]
+            })
+        );
+    }
+}
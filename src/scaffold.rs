@@ -0,0 +1,467 @@
+//! Job files for `cigen new job`
+//!
+//! [`JobOptions`] collects what a new job can pick from the config (logical
+//! image names, services, source file groups, caches), [`prompt_job`] asks
+//! for each choice through a [`Prompter`], and [`render_job`] writes the
+//! commented job YAML. The prompter reads answers line by line from any
+//! reader, so tests and scripts can pipe them in.
+
+use anyhow::{Result, bail};
+use serde_yaml::{Mapping, Value};
+use std::io::{BufRead, Write};
+
+use crate::format::{JOB_KEY_ORDER, format_yaml};
+use crate::schema::{CigenConfig, unknown_reference_message};
+
+/// Asks questions on `output` and reads one line per answer from `input`.
+/// A blank answer, or the end of the input, keeps the default.
+pub struct Prompter<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Prompter<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self { input, output }
+    }
+
+    /// Ask `question`, showing `default` in brackets
+    pub fn ask(&mut self, question: &str, default: &str) -> Result<String> {
+        write!(self.output, "{question} [{default}]: ")?;
+        self.output.flush()?;
+        let mut answer = String::new();
+        if self.input.read_line(&mut answer)? == 0 {
+            writeln!(self.output)?;
+        }
+        let answer = answer.trim();
+        Ok(if answer.is_empty() {
+            default.to_string()
+        } else {
+            answer.to_string()
+        })
+    }
+
+    /// Ask until `check` accepts the answer, showing its error between tries
+    fn ask_until<T>(
+        &mut self,
+        question: &str,
+        default: &str,
+        check: impl Fn(&str) -> Result<T>,
+    ) -> Result<T> {
+        loop {
+            let answer = self.ask(question, default)?;
+            match check(&answer) {
+                Ok(value) => return Ok(value),
+                // A bad default would be asked about forever, so give up on it
+                Err(error) if answer == default => return Err(error),
+                Err(error) => writeln!(self.output, "{error}")?,
+            }
+        }
+    }
+}
+
+/// What a new job can pick from the config
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct JobOptions {
+    /// Logical image names from `docker_build` and `docker_images`
+    pub images: Vec<String>,
+    pub services: Vec<String>,
+    pub source_file_groups: Vec<String>,
+    pub caches: Vec<String>,
+}
+
+impl JobOptions {
+    pub fn from_config(config: &CigenConfig) -> Self {
+        let mut images: Vec<String> = config
+            .docker_build
+            .iter()
+            .flat_map(|build| build.images.iter().map(|image| image.name.clone()))
+            .chain(mapping_keys(config.raw.get("docker_images")))
+            .collect();
+        images.sort();
+        images.dedup();
+        let mut source_file_groups: Vec<String> =
+            config.source_file_groups.keys().cloned().collect();
+        source_file_groups.sort();
+        let mut caches: Vec<String> = config.caches.keys().cloned().collect();
+        caches.sort();
+        Self {
+            images,
+            services: mapping_keys(config.raw.get("services")),
+            source_file_groups,
+            caches,
+        }
+    }
+
+    /// Error unless every service, source file group, and cache is defined
+    pub fn check(&self, choices: &JobChoices) -> Result<()> {
+        check_names("service", &choices.services, &self.services)?;
+        if let Some(group) = &choices.source_file_group {
+            check_names(
+                "source file group",
+                std::slice::from_ref(group),
+                &self.source_file_groups,
+            )?;
+        }
+        check_names("cache", &choices.cache, &self.caches)
+    }
+}
+
+/// The fields a new job is created with
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobChoices {
+    pub image: String,
+    pub services: Vec<String>,
+    /// Group from `source_file_groups`, referenced as `source_files: '@<group>'`
+    pub source_file_group: Option<String>,
+    pub cache: Vec<String>,
+    pub parallelism: Option<u32>,
+}
+
+impl Default for JobChoices {
+    fn default() -> Self {
+        Self {
+            image: "ubuntu-latest".to_string(),
+            services: Vec::new(),
+            source_file_group: None,
+            cache: Vec::new(),
+            parallelism: None,
+        }
+    }
+}
+
+impl JobChoices {
+    /// The choices an existing job file makes, for `--from`
+    pub fn from_job(job: &Mapping) -> Self {
+        let strings = |key: &str| match job.get(key) {
+            Some(Value::String(value)) => vec![value.clone()],
+            Some(Value::Sequence(values)) => values
+                .iter()
+                .filter_map(|value| value.as_str().map(str::to_string))
+                .collect(),
+            // `cache: { gems: {...} }` names caches by key
+            Some(Value::Mapping(map)) => map
+                .keys()
+                .filter_map(|key| key.as_str().map(str::to_string))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let source_files = strings("source_files");
+        let source_file_group = match source_files.as_slice() {
+            [only] => only.strip_prefix('@').map(str::to_string),
+            _ => None,
+        };
+        Self {
+            image: job
+                .get("image")
+                .and_then(Value::as_str)
+                .map_or_else(|| Self::default().image, str::to_string),
+            services: strings("services"),
+            source_file_group,
+            cache: strings("cache"),
+            parallelism: job
+                .get("parallelism")
+                .and_then(Value::as_u64)
+                .and_then(|n| u32::try_from(n).ok()),
+        }
+    }
+}
+
+/// Ask for each choice, offering the config's names and starting from
+/// `defaults`
+pub fn prompt_job<R: BufRead, W: Write>(
+    prompter: &mut Prompter<R, W>,
+    options: &JobOptions,
+    defaults: JobChoices,
+) -> Result<JobChoices> {
+    let mut choices = defaults;
+
+    let question = match options.images.as_slice() {
+        [] => "Image".to_string(),
+        images => format!("Image (built images: {})", images.join(", ")),
+    };
+    choices.image = prompter.ask(&question, &choices.image)?;
+
+    if !options.services.is_empty() {
+        let question = format!("Services ({}; - for none)", options.services.join(", "));
+        choices.services = prompter.ask_until(&question, &choices.services.join(", "), |a| {
+            let services = split_list(a);
+            check_names("service", &services, &options.services)?;
+            Ok(services)
+        })?;
+    }
+
+    if !options.source_file_groups.is_empty() {
+        let question = format!(
+            "Source file group ({}; - for none)",
+            options.source_file_groups.join(", ")
+        );
+        let default = choices.source_file_group.clone().unwrap_or_default();
+        choices.source_file_group = prompter.ask_until(&question, &default, |a| {
+            let group = a.trim_start_matches('@');
+            if group.is_empty() || group == "-" {
+                return Ok(None);
+            }
+            check_names(
+                "source file group",
+                &[group.to_string()],
+                &options.source_file_groups,
+            )?;
+            Ok(Some(group.to_string()))
+        })?;
+    }
+
+    if !options.caches.is_empty() {
+        let question = format!("Caches ({}; - for none)", options.caches.join(", "));
+        choices.cache = prompter.ask_until(&question, &choices.cache.join(", "), |a| {
+            let caches = split_list(a);
+            check_names("cache", &caches, &options.caches)?;
+            Ok(caches)
+        })?;
+    }
+
+    let default = choices.parallelism.unwrap_or(1).to_string();
+    choices.parallelism = prompter.ask_until("Parallelism", &default, |a| match a.parse() {
+        Ok(0) | Err(_) => bail!("Parallelism must be a whole number of at least 1"),
+        Ok(1) => Ok(None),
+        Ok(n) => Ok(Some(n)),
+    })?;
+
+    Ok(choices)
+}
+
+/// The job file for `choices`, with a comment on each field. `base` is the
+/// job being cloned; its other keys, such as `steps`, are kept.
+pub fn render_job(
+    job_id: &str,
+    workflow: &str,
+    choices: &JobChoices,
+    base: Option<(&str, &Mapping)>,
+) -> Result<String> {
+    let mut text = format!("# Job '{job_id}' in the '{workflow}' workflow\n");
+    if let Some((from, _)) = base {
+        text.push_str(&format!("# Created from '{from}'\n"));
+    }
+    text.push('\n');
+
+    let mut field = |comment: &str, key: &str, value: Value| -> Result<()> {
+        let mut entry = Mapping::new();
+        entry.insert(Value::String(key.to_string()), value);
+        if !comment.is_empty() {
+            text.push_str(&format!("# {comment}\n"));
+        }
+        text.push_str(&serde_yaml::to_string(&entry)?);
+        text.push('\n');
+        Ok(())
+    };
+    let list =
+        |items: &[String]| Value::Sequence(items.iter().cloned().map(Value::String).collect());
+
+    field(
+        "Docker image, or a docker_build image name",
+        "image",
+        Value::String(choices.image.clone()),
+    )?;
+    if let Some(parallelism) = choices.parallelism {
+        field(
+            "Containers the job runs in at once",
+            "parallelism",
+            Value::Number(parallelism.into()),
+        )?;
+    }
+    if !choices.services.is_empty() {
+        field(
+            "Services from the config's `services:`, started alongside the job",
+            "services",
+            list(&choices.services),
+        )?;
+    }
+    if !choices.cache.is_empty() {
+        field(
+            "Caches from the config's `caches:`, restored before the steps and saved after",
+            "cache",
+            list(&choices.cache),
+        )?;
+    }
+    if let Some(group) = &choices.source_file_group {
+        field(
+            "The job is skipped when none of these files changed since its last passing run",
+            "source_files",
+            Value::String(format!("@{group}")),
+        )?;
+    }
+
+    let chosen = ["image", "parallelism", "services", "cache", "source_files"];
+    let mut has_steps = false;
+    for (key, value) in base.map(|(_, job)| job).into_iter().flatten() {
+        let Some(key) = key.as_str() else { continue };
+        if chosen.contains(&key) {
+            continue;
+        }
+        has_steps |= key == "steps";
+        field("", key, value.clone())?;
+    }
+    if !has_steps {
+        let mut step = Mapping::new();
+        step.insert(
+            Value::String("run".to_string()),
+            Value::String(format!("echo \"Add the steps for {job_id}\"")),
+        );
+        field(
+            "Commands the job runs, after checkout",
+            "steps",
+            Value::Sequence(vec![Value::Mapping(step)]),
+        )?;
+    }
+
+    format_yaml(&text, JOB_KEY_ORDER)
+}
+
+fn mapping_keys(value: Option<&Value>) -> Vec<String> {
+    let mut keys: Vec<String> = value
+        .and_then(Value::as_mapping)
+        .into_iter()
+        .flat_map(|map| map.keys())
+        .filter_map(|key| key.as_str().map(str::to_string))
+        .collect();
+    keys.sort();
+    keys
+}
+
+/// A comma- or space-separated answer, where `-` is an empty list
+fn split_list(answer: &str) -> Vec<String> {
+    answer
+        .split([',', ' '])
+        .filter(|item| !item.is_empty() && *item != "-")
+        .map(str::to_string)
+        .collect()
+}
+
+fn check_names(kind: &str, names: &[String], known: &[String]) -> Result<()> {
+    for name in names {
+        if !known.contains(name) {
+            bail!(
+                "{}",
+                unknown_reference_message(
+                    &format!("Unknown {kind} '{name}'"),
+                    name,
+                    known.iter().map(String::as_str),
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn options() -> JobOptions {
+        JobOptions {
+            images: vec!["ci_ruby".to_string()],
+            services: vec!["postgres".to_string(), "redis".to_string()],
+            source_file_groups: vec!["ruby".to_string()],
+            caches: vec!["gems".to_string()],
+        }
+    }
+
+    fn prompt(answers: &str, defaults: JobChoices) -> (JobChoices, String) {
+        let mut output = Vec::new();
+        let mut prompter = Prompter::new(Cursor::new(answers), &mut output);
+        let choices = prompt_job(&mut prompter, &options(), defaults).unwrap();
+        (choices, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn answers_fill_in_each_choice() {
+        let (choices, output) = prompt(
+            "ci_ruby\npostgres, reddis\npostgres, redis\n@ruby\ngems\n4\n",
+            JobChoices::default(),
+        );
+        assert_eq!(
+            choices,
+            JobChoices {
+                image: "ci_ruby".to_string(),
+                services: vec!["postgres".to_string(), "redis".to_string()],
+                source_file_group: Some("ruby".to_string()),
+                cache: vec!["gems".to_string()],
+                parallelism: Some(4),
+            }
+        );
+        assert!(
+            output.contains("Image (built images: ci_ruby) [ubuntu-latest]: "),
+            "{output}"
+        );
+        assert!(
+            output.contains("Unknown service 'reddis'. Did you mean 'redis'?"),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn blank_answers_and_end_of_input_keep_the_defaults() {
+        let defaults = JobChoices {
+            image: "cimg/ruby:3.3".to_string(),
+            services: vec!["postgres".to_string()],
+            parallelism: Some(2),
+            ..Default::default()
+        };
+        let (choices, _) = prompt("\n\n", defaults.clone());
+        assert_eq!(choices, defaults);
+
+        let (choices, _) = prompt("\n-\n", defaults);
+        assert!(choices.services.is_empty());
+    }
+
+    #[test]
+    fn cloned_jobs_keep_their_other_keys() {
+        let base: Mapping = serde_yaml::from_str(
+            "image: cimg/ruby:3.3\nservices: postgres\nsource_files: '@ruby'\nneeds: [setup]\nsteps:\n  - run: bundle exec rspec\n",
+        )
+        .unwrap();
+        let mut choices = JobChoices::from_job(&base);
+        assert_eq!(choices.services, ["postgres"]);
+        assert_eq!(choices.source_file_group.as_deref(), Some("ruby"));
+        choices.parallelism = Some(2);
+
+        let yaml = render_job(
+            "test/rspec_system",
+            "test",
+            &choices,
+            Some(("test/rspec", &base)),
+        )
+        .unwrap();
+        assert!(
+            yaml.starts_with(
+                "# Job 'test/rspec_system' in the 'test' workflow\n# Created from 'test/rspec'\n"
+            ),
+            "{yaml}"
+        );
+        let job: Mapping = serde_yaml::from_str(&yaml).unwrap();
+        let keys: Vec<&str> = job.keys().filter_map(Value::as_str).collect();
+        assert_eq!(
+            keys,
+            [
+                "image",
+                "needs",
+                "parallelism",
+                "services",
+                "source_files",
+                "steps"
+            ]
+        );
+        assert_eq!(job["source_files"], "@ruby");
+        assert_eq!(job["steps"][0]["run"], "bundle exec rspec");
+        assert!(yaml.contains("# Containers the job runs in at once\nparallelism: 2\n"));
+    }
+
+    #[test]
+    fn new_jobs_get_a_placeholder_step() {
+        let yaml = render_job("lint", "test", &JobChoices::default(), None).unwrap();
+        assert!(yaml.contains("image: ubuntu-latest"), "{yaml}");
+        let job: Mapping = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(job["steps"][0]["run"], "echo \"Add the steps for lint\"");
+    }
+}
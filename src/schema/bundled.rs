@@ -0,0 +1,85 @@
+//! JSON Schemas compiled into the binary
+//!
+//! Editors pick these up through a `# yaml-language-server: $schema=...`
+//! comment; `cigen schema export` writes them to disk for offline use.
+
+/// A JSON Schema file embedded in the cigen binary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BundledSchema {
+    pub file_name: &'static str,
+    pub contents: &'static str,
+}
+
+macro_rules! bundled {
+    ($file_name:literal, $path:literal) => {
+        BundledSchema {
+            file_name: $file_name,
+            contents: include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/schemas/", $path)),
+        }
+    };
+}
+
+/// Schemas for cigen's own configuration files. They reference each other
+/// with relative `$ref`s, so they must be written to the same directory.
+pub const CIGEN_SCHEMAS: &[BundledSchema] = &[
+    bundled!("config-schema.json", "v1/config-schema.json"),
+    bundled!("config-base-schema.json", "v1/config-base-schema.json"),
+    bundled!("definitions.json", "v1/definitions.json"),
+    bundled!("job-schema.json", "v1/job-schema.json"),
+    bundled!("command-schema.json", "v1/command-schema.json"),
+    bundled!(
+        "workflow-config-schema.json",
+        "v1/workflow-config-schema.json"
+    ),
+];
+
+/// Where editors fetch the CircleCI config schema (same as the bundled copy)
+pub const CIRCLECI_SCHEMA_URL: &str = "https://json.schemastore.org/circleciconfig.json";
+
+/// Where editors fetch the GitHub Actions workflow schema
+pub const GITHUB_ACTIONS_SCHEMA_URL: &str = "https://json.schemastore.org/github-workflow.json";
+
+/// Where editors fetch the GitHub Actions action metadata (`action.yml`) schema
+pub const GITHUB_ACTION_SCHEMA_URL: &str = "https://json.schemastore.org/github-action.json";
+
+/// Providers that have a schema bundled for offline export
+pub const BUNDLED_PROVIDERS: &[&str] = &["circleci"];
+
+/// Bundled schema for the files a provider generates
+pub fn provider_schema(provider: &str) -> Option<BundledSchema> {
+    match provider {
+        "circleci" => Some(bundled!(
+            "circleci-config.json",
+            "vendor/circleci-publicschema.json"
+        )),
+        _ => None,
+    }
+}
+
+/// Header line that points YAML language servers at `url`
+pub fn schema_comment(url: &str) -> String {
+    format!("# yaml-language-server: $schema={url}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundled_schemas_are_valid_json() {
+        let provider_schemas = BUNDLED_PROVIDERS
+            .iter()
+            .map(|provider| provider_schema(provider).unwrap());
+        for schema in CIGEN_SCHEMAS.iter().copied().chain(provider_schemas) {
+            serde_json::from_str::<serde_json::Value>(schema.contents)
+                .unwrap_or_else(|error| panic!("{} is not valid JSON: {error}", schema.file_name));
+        }
+    }
+
+    #[test]
+    fn test_schema_comment_is_a_yaml_comment() {
+        let yaml = format!("{}version: 2.1\n", schema_comment(CIRCLECI_SCHEMA_URL));
+        let value: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(value["version"], serde_yaml::Value::from(2.1));
+    }
+}
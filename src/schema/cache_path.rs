@@ -0,0 +1,167 @@
+//! Cache paths in the form each provider's cache steps expect
+//!
+//! `~`, `$HOME` and `${HOME}` all become `~/`, which both CircleCI's
+//! `save_cache` and GitHub's `actions/cache` expand to the home directory.
+//! Relative paths are relative to the job's `working_directory`, which is
+//! joined on: a `~/` or absolute working directory makes the path absolute or
+//! home-relative, and a relative one (a subdirectory of the checkout) is
+//! prefixed, since both providers resolve relative cache paths from the
+//! checkout root.
+//!
+//! `.` and `..` are resolved, and a path that climbs out of the home
+//! directory, `/`, or the directory it is relative to is an error. Paths with
+//! other variables or provider templates are left alone.
+
+use anyhow::{Result, bail};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Root {
+    Home,
+    Absolute,
+    /// The checkout root
+    Relative,
+}
+
+impl Root {
+    fn describe(self) -> &'static str {
+        match self {
+            Root::Home => "the home directory",
+            Root::Absolute => "/",
+            Root::Relative => "the directory it is relative to",
+        }
+    }
+}
+
+/// `path` for a job running in `working_directory` (empty when unset)
+pub fn normalize_cache_path(path: &str, working_directory: &str) -> Result<String> {
+    let Some((root, parts)) = split(path) else {
+        return Ok(path.to_string());
+    };
+    let (root, base) = match root {
+        Root::Relative => match split(working_directory) {
+            Some((root, base)) => (root, base),
+            None => return Ok(path.to_string()),
+        },
+        root => (root, Vec::new()),
+    };
+
+    let mut resolved: Vec<&str> = Vec::new();
+    for part in base.iter().chain(&parts) {
+        match *part {
+            "." => {}
+            ".." => {
+                if resolved.pop().is_none() {
+                    bail!(
+                        "Cache path '{path}' leaves {}; use a `~/` or absolute path",
+                        root.describe()
+                    );
+                }
+            }
+            part => resolved.push(part),
+        }
+    }
+
+    let joined = resolved.join("/");
+    Ok(match root {
+        Root::Home if joined.is_empty() => "~".to_string(),
+        Root::Home => format!("~/{joined}"),
+        Root::Absolute => format!("/{joined}"),
+        Root::Relative if joined.is_empty() => ".".to_string(),
+        Root::Relative => joined,
+    })
+}
+
+/// The path's root and its components, or `None` for paths with variables or
+/// templates cigen can't resolve
+fn split(path: &str) -> Option<(Root, Vec<&str>)> {
+    let path = path.trim();
+    if path.contains("{{") {
+        return None;
+    }
+    let (root, rest) = if let Some(rest) = ["~", "${HOME}", "$HOME"]
+        .iter()
+        .find_map(|home| path.strip_prefix(home))
+        .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+    {
+        (Root::Home, rest)
+    } else if path.starts_with(['~', '$']) {
+        return None;
+    } else if let Some(rest) = path.strip_prefix('/') {
+        (Root::Absolute, rest)
+    } else {
+        (Root::Relative, path)
+    };
+    let parts = rest.split('/').filter(|part| !part.is_empty()).collect();
+    Some((root, parts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(path: &str, working_directory: &str) -> String {
+        normalize_cache_path(path, working_directory).unwrap()
+    }
+
+    #[test]
+    fn home_paths_use_tilde() {
+        assert_eq!(normalize("~/.cache/yarn", ""), "~/.cache/yarn");
+        assert_eq!(normalize("$HOME/.bundle/", "~/app"), "~/.bundle");
+        assert_eq!(
+            normalize("${HOME}/.m2/./repository", ""),
+            "~/.m2/repository"
+        );
+        assert_eq!(normalize("~", ""), "~");
+    }
+
+    #[test]
+    fn absolute_paths_are_kept() {
+        assert_eq!(normalize("/usr/local/lib", "~/app"), "/usr/local/lib");
+        assert_eq!(normalize("/opt/cache/../tools", ""), "/opt/tools");
+    }
+
+    #[test]
+    fn relative_paths_follow_the_working_directory() {
+        assert_eq!(normalize("vendor/bundle", ""), "vendor/bundle");
+        assert_eq!(normalize("./node_modules", ""), "node_modules");
+        assert_eq!(normalize("vendor/bundle", "~/app"), "~/app/vendor/bundle");
+        assert_eq!(
+            normalize("../shared/cache", "/srv/app/"),
+            "/srv/shared/cache"
+        );
+        // A relative working directory is a subdirectory of the checkout
+        assert_eq!(normalize("vendor/bundle", "web"), "web/vendor/bundle");
+        assert_eq!(normalize("../shared", "web"), "shared");
+    }
+
+    #[test]
+    fn templated_paths_are_left_alone() {
+        for path in [
+            "$GOPATH/pkg",
+            "~deploy/.cache",
+            "{{ .Environment.CACHE_DIR }}",
+        ] {
+            assert_eq!(normalize(path, "~/app"), path);
+        }
+        assert_eq!(normalize("vendor", "$APP_DIR"), "vendor");
+    }
+
+    #[test]
+    fn escaping_paths_are_rejected() {
+        for (path, working_directory, leaves) in [
+            ("~/../etc", "", "the home directory"),
+            ("../../../x", "~/app", "the home directory"),
+            ("/../etc", "", "/"),
+            ("../shared", "", "the directory it is relative to"),
+            ("../../shared", "web", "the directory it is relative to"),
+        ] {
+            let error = normalize_cache_path(path, working_directory)
+                .unwrap_err()
+                .to_string();
+            assert!(
+                error.contains(&format!("'{path}' leaves {leaves}")),
+                "{path}: {error}"
+            );
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! `cloud_auth:` on a job: short-lived cloud credentials from the provider's
+//! OIDC token, set up before the job's steps
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+/// ```yaml
+/// cloud_auth:
+///   aws:
+///     role_arn: arn:aws:iam::123456789012:role/deploy
+///     region: us-east-1
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CloudAuth {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aws: Option<AwsAuth>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gcp: Option<GcpAuth>,
+}
+
+/// AWS role assumed with the job's OIDC token
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AwsAuth {
+    pub role_arn: String,
+
+    /// Region for the credentials; `AWS_DEFAULT_REGION` from the job's
+    /// environment when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+}
+
+/// Google Cloud service account impersonated through workload identity federation
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GcpAuth {
+    /// `projects/<number>/locations/global/workloadIdentityPools/<pool>/providers/<provider>`
+    pub workload_identity_provider: String,
+
+    /// Email of the service account to impersonate
+    pub service_account: String,
+
+    /// Project gcloud uses by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_id: Option<String>,
+}
+
+/// The parts of a GCP workload identity provider's resource name
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorkloadIdentityProvider<'a> {
+    pub project_number: &'a str,
+    pub pool: &'a str,
+    pub provider: &'a str,
+}
+
+impl<'a> WorkloadIdentityProvider<'a> {
+    pub fn parse(name: &'a str) -> Option<Self> {
+        let parts: Vec<&str> = name.split('/').collect();
+        match parts.as_slice() {
+            [
+                "projects",
+                project_number,
+                "locations",
+                "global",
+                "workloadIdentityPools",
+                pool,
+                "providers",
+                provider,
+            ] if [project_number, pool, provider]
+                .iter()
+                .all(|part| !part.is_empty()) =>
+            {
+                Some(Self {
+                    project_number: *project_number,
+                    pool: *pool,
+                    provider: *provider,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `cloud_auth` needs a cloud, and the fields each cloud can't work without.
+/// Checked on the raw job mapping so errors name the missing field plainly.
+pub fn check_cloud_auth(job: &Value) -> Result<()> {
+    let Some(cloud_auth) = job.get("cloud_auth") else {
+        return Ok(());
+    };
+    let Value::Mapping(clouds) = cloud_auth else {
+        bail!("cloud_auth must be a mapping with `aws` or `gcp`");
+    };
+    if clouds.is_empty() {
+        bail!("cloud_auth must configure `aws` or `gcp`");
+    }
+    let field = |cloud: &str, name: &str| {
+        cloud_auth
+            .get(cloud)
+            .and_then(|options| options.get(name))
+            .and_then(Value::as_str)
+            .filter(|value| !value.trim().is_empty())
+    };
+    if cloud_auth.get("aws").is_some() && field("aws", "role_arn").is_none() {
+        bail!("cloud_auth.aws requires role_arn, the ARN of the role to assume");
+    }
+    if cloud_auth.get("gcp").is_some() {
+        let Some(provider) = field("gcp", "workload_identity_provider") else {
+            bail!("cloud_auth.gcp requires workload_identity_provider");
+        };
+        if WorkloadIdentityProvider::parse(provider).is_none() {
+            bail!(
+                "cloud_auth.gcp workload_identity_provider '{provider}' must look like projects/<number>/locations/global/workloadIdentityPools/<pool>/providers/<provider>"
+            );
+        }
+        if field("gcp", "service_account").is_none() {
+            bail!(
+                "cloud_auth.gcp requires service_account, the email of the account to impersonate"
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(text: &str) -> Result<()> {
+        check_cloud_auth(&serde_yaml::from_str(text).unwrap())
+    }
+
+    #[test]
+    fn cloud_auth_requires_the_role_and_provider() {
+        assert!(check("image: rust").is_ok());
+        assert!(check("cloud_auth: {aws: {role_arn: 'arn:aws:iam::1:role/ci'}}").is_ok());
+        assert_eq!(
+            check("cloud_auth: {aws: {region: us-east-1}}")
+                .unwrap_err()
+                .to_string(),
+            "cloud_auth.aws requires role_arn, the ARN of the role to assume"
+        );
+        assert!(check("cloud_auth: {}").is_err());
+        assert!(
+            check("cloud_auth: {gcp: {workload_identity_provider: my-pool, service_account: ci@p.iam.gserviceaccount.com}}")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn workload_identity_providers_split_into_their_parts() {
+        assert_eq!(
+            WorkloadIdentityProvider::parse(
+                "projects/123456/locations/global/workloadIdentityPools/ci/providers/circleci"
+            ),
+            Some(WorkloadIdentityProvider {
+                project_number: "123456",
+                pool: "ci",
+                provider: "circleci",
+            })
+        );
+        assert_eq!(
+            WorkloadIdentityProvider::parse("projects/123456/locations/global"),
+            None
+        );
+    }
+}
@@ -1,11 +1,17 @@
+use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
 use serde_yaml::{Mapping, Value};
 use std::collections::HashMap;
 
 use super::step::Step;
+use super::suggest::unknown_reference_message;
+
+/// Parameter type whose value is a list of steps
+pub const STEPS_PARAMETER_TYPE: &str = "steps";
 
 /// Definition of a reusable command (CircleCI style)
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[non_exhaustive]
 pub struct CommandDefinition {
     /// Optional description for the command
     #[serde(default)]
@@ -26,8 +32,9 @@ pub struct CommandDefinition {
 
 /// Parameter definition inside a command
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[non_exhaustive]
 pub struct CommandParameter {
-    /// Declared parameter type (string, boolean, integer)
+    /// Declared parameter type (string, boolean, integer, enum, steps)
     #[serde(default, rename = "type")]
     pub parameter_type: Option<String>,
 
@@ -43,3 +50,171 @@ pub struct CommandParameter {
     #[serde(default, flatten)]
     pub extra: Mapping,
 }
+
+impl CommandParameter {
+    /// Whether the parameter takes a list of steps
+    pub fn is_steps(&self) -> bool {
+        self.parameter_type.as_deref() == Some(STEPS_PARAMETER_TYPE)
+    }
+}
+
+impl CommandDefinition {
+    /// Check `steps` parameter defaults and the steps that splice parameters in
+    pub fn validate(&self) -> Result<()> {
+        for (name, parameter) in &self.parameters {
+            if parameter.is_steps()
+                && let Some(default) = &parameter.default
+            {
+                serde_yaml::from_value::<Vec<Step>>(default.clone()).with_context(|| {
+                    format!("Default for parameter '{name}' must be a list of steps")
+                })?;
+            }
+        }
+
+        for step in &self.steps {
+            let Step::Custom(Value::String(text)) = step else {
+                continue;
+            };
+            let Some(name) = steps_parameter_reference(text) else {
+                continue;
+            };
+            match self.parameters.get(name) {
+                Some(parameter) if parameter.is_steps() => {}
+                Some(_) => bail!(
+                    "Step '{text}' splices in parameter '{name}', which needs `type: {STEPS_PARAMETER_TYPE}`"
+                ),
+                None => bail!("Step '{text}' uses undeclared parameter '{name}'"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the arguments a step passes when it invokes the command as
+    /// `name`: only declared parameters, and every one without a default
+    pub fn check_arguments(&self, name: &str, arguments: Option<&Mapping>) -> Result<()> {
+        for argument in arguments.into_iter().flat_map(Mapping::keys) {
+            let argument = argument.as_str().unwrap_or_default();
+            if !self.parameters.contains_key(argument) {
+                bail!(
+                    "{}",
+                    unknown_reference_message(
+                        &format!("Command '{name}' has no parameter '{argument}'"),
+                        argument,
+                        self.parameters.keys().map(String::as_str),
+                    )
+                );
+            }
+        }
+        let mut missing: Vec<&str> = self
+            .parameters
+            .iter()
+            .filter(|(parameter, definition)| {
+                definition.default.is_none()
+                    && !arguments
+                        .is_some_and(|arguments| arguments.contains_key(parameter.as_str()))
+            })
+            .map(|(parameter, _)| parameter.as_str())
+            .collect();
+        missing.sort_unstable();
+        if let Some(parameter) = missing.first() {
+            bail!("Command '{name}' needs parameter '{parameter}', which has no default");
+        }
+        Ok(())
+    }
+}
+
+/// The parameter a `- << parameters.name >>` step splices its steps in from
+pub fn steps_parameter_reference(text: &str) -> Option<&str> {
+    let name = text
+        .trim()
+        .strip_prefix("<<")?
+        .strip_suffix(">>")?
+        .trim()
+        .strip_prefix("parameters.")?
+        .trim();
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'));
+    valid.then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(yaml: &str) -> CommandDefinition {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn steps_parameters_are_spliced_in_by_reference() {
+        assert_eq!(
+            steps_parameter_reference("<< parameters.steps >>"),
+            Some("steps")
+        );
+        assert_eq!(
+            steps_parameter_reference("<<parameters.after-build>>"),
+            Some("after-build")
+        );
+        assert_eq!(steps_parameter_reference("echo << parameters.x >>"), None);
+        assert_eq!(steps_parameter_reference("<< pipeline.number >>"), None);
+
+        command(
+            "parameters:\n  steps:\n    type: steps\n    default: [checkout, {run: make}]\nsteps:\n  - << parameters.steps >>\n",
+        )
+        .validate()
+        .unwrap();
+    }
+
+    #[test]
+    fn invocations_pass_declared_parameters() {
+        let definition = command(
+            "parameters:\n  env:\n    type: string\n  verbose:\n    type: boolean\n    default: false\nsteps:\n  - run: ./setup.sh\n",
+        );
+        let arguments = |yaml: &str| serde_yaml::from_str::<Mapping>(yaml).unwrap();
+
+        definition
+            .check_arguments("set_env", Some(&arguments("env: test")))
+            .unwrap();
+        let error = definition
+            .check_arguments("set_env", None)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            "Command 'set_env' needs parameter 'env', which has no default"
+        );
+        let error = definition
+            .check_arguments("set_env", Some(&arguments("env: test\nverbos: true")))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with(
+                "Command 'set_env' has no parameter 'verbos'. Did you mean 'verbose'?"
+            ),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn invalid_steps_parameters_are_rejected() {
+        for (yaml, message) in [
+            (
+                "parameters:\n  steps:\n    type: steps\n    default: make test\n",
+                "Default for parameter 'steps' must be a list of steps",
+            ),
+            (
+                "parameters:\n  steps:\n    type: string\nsteps:\n  - << parameters.steps >>\n",
+                "splices in parameter 'steps', which needs `type: steps`",
+            ),
+            (
+                "steps:\n  - << parameters.steps >>\n",
+                "uses undeclared parameter 'steps'",
+            ),
+        ] {
+            let error = command(yaml).validate().unwrap_err().to_string();
+            assert!(error.contains(message), "{error}");
+        }
+    }
+}
@@ -0,0 +1,459 @@
+//! Condition expressions (`if:` on steps, and workflow `run_when`
+//! expressions on CircleCI)
+//!
+//! A small, provider-neutral expression language:
+//!
+//! ```text
+//! branch == "main"
+//! param.run_e2e == true
+//! env.DEPLOY_TOKEN defined
+//! branch != "main" && !(env.SKIP_E2E defined)
+//! ```
+//!
+//! Providers compile the parsed [`Condition`] into their native syntax.
+
+use anyhow::{Result, bail};
+use std::fmt;
+
+/// Parsed step condition
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// `branch == "main"` / `branch != "main"`
+    Branch {
+        op: Comparison,
+        value: String,
+    },
+    /// `param.name == <literal>`; a bare `param.name` means `== true`
+    Param {
+        name: String,
+        op: Comparison,
+        value: Literal,
+    },
+    /// `env.NAME defined`
+    EnvDefined {
+        name: String,
+    },
+    /// `env.NAME == "value"`
+    Env {
+        name: String,
+        op: Comparison,
+        value: String,
+    },
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+}
+
+/// Literal on the right-hand side of a `param.*` comparison
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    String(String),
+    Bool(bool),
+    Number(i64),
+}
+
+impl Condition {
+    /// Parse a condition expression
+    pub fn parse(expression: &str) -> Result<Self> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            expression,
+        };
+        let condition = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            bail!("Unexpected {token} in condition '{expression}'");
+        }
+        Ok(condition)
+    }
+
+    /// Whether the expression only depends on values known when the pipeline
+    /// is configured (branch, parameters), as opposed to runtime environment.
+    pub fn is_static(&self) -> bool {
+        match self {
+            Condition::Branch { .. } | Condition::Param { .. } => true,
+            Condition::EnvDefined { .. } | Condition::Env { .. } => false,
+            Condition::Not(inner) => inner.is_static(),
+            Condition::And(left, right) | Condition::Or(left, right) => {
+                left.is_static() && right.is_static()
+            }
+        }
+    }
+
+    /// Whether the expression only depends on runtime environment variables
+    pub fn is_runtime(&self) -> bool {
+        match self {
+            Condition::Branch { .. } | Condition::Param { .. } => false,
+            Condition::EnvDefined { .. } | Condition::Env { .. } => true,
+            Condition::Not(inner) => inner.is_runtime(),
+            Condition::And(left, right) | Condition::Or(left, right) => {
+                left.is_runtime() && right.is_runtime()
+            }
+        }
+    }
+
+    /// Split a chain of `&&` into its operands
+    pub fn conjuncts(&self) -> Vec<&Condition> {
+        match self {
+            Condition::And(left, right) => {
+                let mut parts = left.conjuncts();
+                parts.extend(right.conjuncts());
+                parts
+            }
+            other => vec![other],
+        }
+    }
+}
+
+/// Whether an expression looks like it was written in the cigen condition
+/// language (as opposed to a provider-native expression).
+pub fn is_condition_expression(expression: &str) -> bool {
+    let trimmed = expression
+        .trim_start()
+        .trim_start_matches(['!', '('])
+        .trim_start();
+    let trimmed = trimmed.strip_prefix("not ").unwrap_or(trimmed).trim_start();
+    (trimmed.starts_with("branch") && !trimmed[6..].starts_with(is_ident_char))
+        || trimmed.starts_with("param.")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    String(String),
+    Number(i64),
+    Eq,
+    Ne,
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "'{ident}'"),
+            Token::String(value) => write!(f, "string \"{value}\""),
+            Token::Number(value) => write!(f, "number {value}"),
+            Token::Eq => write!(f, "'=='"),
+            Token::Ne => write!(f, "'!='"),
+            Token::And => write!(f, "'&&'"),
+            Token::Or => write!(f, "'||'"),
+            Token::Not => write!(f, "'!'"),
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+        }
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-'
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' | '\n' | '\r' => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '=' | '!' | '&' | '|' => {
+                chars.next();
+                let next = chars.peek().copied();
+                let token = match (c, next) {
+                    ('=', Some('=')) => Token::Eq,
+                    ('!', Some('=')) => Token::Ne,
+                    ('&', Some('&')) => Token::And,
+                    ('|', Some('|')) => Token::Or,
+                    ('!', _) => {
+                        tokens.push(Token::Not);
+                        continue;
+                    }
+                    _ => bail!("Unexpected '{c}' in condition '{expression}'"),
+                };
+                chars.next();
+                tokens.push(token);
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(ch) if ch == c => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => value.push(escaped),
+                            None => bail!("Unterminated string in condition '{expression}'"),
+                        },
+                        Some(ch) => value.push(ch),
+                        None => bail!("Unterminated string in condition '{expression}'"),
+                    }
+                }
+                tokens.push(Token::String(value));
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut number = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_ascii_digit() || (number.is_empty() && ch == '-') {
+                        number.push(ch);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let value = number
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid number in condition '{expression}'"))?;
+                tokens.push(Token::Number(value));
+            }
+            c if is_ident_char(c) => {
+                let mut ident = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if is_ident_char(ch) {
+                        ident.push(ch);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(match ident.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Ident(ident),
+                });
+            }
+            other => bail!("Unexpected '{other}' in condition '{expression}'"),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    expression: &'a str,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Condition> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            let right = self.parse_and()?;
+            left = Condition::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Condition> {
+        let mut left = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            let right = self.parse_unary()?;
+            left = Condition::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Condition> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Condition::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Condition> {
+        let expression = self.expression;
+        match self.next() {
+            Some(Token::LParen) => {
+                let inner = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(inner),
+                    _ => bail!("Missing ')' in condition '{expression}'"),
+                }
+            }
+            Some(Token::Ident(subject)) => self.parse_comparison(&subject),
+            Some(token) => bail!("Unexpected {token} in condition '{expression}'"),
+            None => bail!("Condition '{expression}' ends unexpectedly"),
+        }
+    }
+
+    fn parse_comparison(&mut self, subject: &str) -> Result<Condition> {
+        let expression = self.expression;
+
+        if subject == "branch" {
+            let op = self.parse_operator(subject)?;
+            return match self.next() {
+                Some(Token::String(value)) => Ok(Condition::Branch { op, value }),
+                _ => bail!("'branch' must be compared to a string in condition '{expression}'"),
+            };
+        }
+
+        if let Some(name) = subject.strip_prefix("param.") {
+            validate_name(name, "parameter", expression)?;
+            if !matches!(self.peek(), Some(Token::Eq | Token::Ne)) {
+                return Ok(Condition::Param {
+                    name: name.to_string(),
+                    op: Comparison::Eq,
+                    value: Literal::Bool(true),
+                });
+            }
+            let op = self.parse_operator(subject)?;
+            let value = match self.next() {
+                Some(Token::String(value)) => Literal::String(value),
+                Some(Token::Number(value)) => Literal::Number(value),
+                Some(Token::Ident(ident)) if ident == "true" => Literal::Bool(true),
+                Some(Token::Ident(ident)) if ident == "false" => Literal::Bool(false),
+                _ => bail!(
+                    "'{subject}' must be compared to a string, number, or boolean in condition '{expression}'"
+                ),
+            };
+            return Ok(Condition::Param {
+                name: name.to_string(),
+                op,
+                value,
+            });
+        }
+
+        if let Some(name) = subject.strip_prefix("env.") {
+            validate_name(name, "environment variable", expression)?;
+            if matches!(self.peek(), Some(Token::Ident(ident)) if ident == "defined") {
+                self.next();
+                return Ok(Condition::EnvDefined {
+                    name: name.to_string(),
+                });
+            }
+            let op = self.parse_operator(subject)?;
+            return match self.next() {
+                Some(Token::String(value)) => Ok(Condition::Env {
+                    name: name.to_string(),
+                    op,
+                    value,
+                }),
+                _ => bail!("'{subject}' must be compared to a string in condition '{expression}'"),
+            };
+        }
+
+        bail!(
+            "Unknown value '{subject}' in condition '{expression}' (expected branch, param.<name>, or env.<NAME>)"
+        )
+    }
+
+    fn parse_operator(&mut self, subject: &str) -> Result<Comparison> {
+        match self.next() {
+            Some(Token::Eq) => Ok(Comparison::Eq),
+            Some(Token::Ne) => Ok(Comparison::Ne),
+            _ => bail!(
+                "Expected '==' or '!=' after '{subject}' in condition '{}'",
+                self.expression
+            ),
+        }
+    }
+}
+
+fn validate_name(name: &str, kind: &str, expression: &str) -> Result<()> {
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        bail!("Invalid {kind} name '{name}' in condition '{expression}'");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_simple_conditions() {
+        assert_eq!(
+            Condition::parse(r#"branch == "main""#).unwrap(),
+            Condition::Branch {
+                op: Comparison::Eq,
+                value: "main".to_string()
+            }
+        );
+        assert_eq!(
+            Condition::parse("param.run_e2e == true").unwrap(),
+            Condition::Param {
+                name: "run_e2e".to_string(),
+                op: Comparison::Eq,
+                value: Literal::Bool(true)
+            }
+        );
+        assert_eq!(
+            Condition::parse("env.FOO defined").unwrap(),
+            Condition::EnvDefined {
+                name: "FOO".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_boolean_operators() {
+        let condition =
+            Condition::parse(r#"branch != 'main' && !(env.SKIP defined || env.FAST == "1")"#)
+                .unwrap();
+        let parts = condition.conjuncts();
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].is_static());
+        assert!(parts[1].is_runtime());
+        assert!(!condition.is_static());
+        assert!(!condition.is_runtime());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = Condition::parse("runner.os == 'Linux'").unwrap_err();
+        assert!(error.to_string().contains("Unknown value 'runner.os'"));
+
+        let error = Condition::parse("branch = 'main'").unwrap_err();
+        assert!(error.to_string().contains("Unexpected '='"));
+
+        let error = Condition::parse("branch == main").unwrap_err();
+        assert!(error.to_string().contains("compared to a string"));
+    }
+
+    #[test]
+    fn test_is_condition_expression() {
+        assert!(is_condition_expression("branch == 'main'"));
+        assert!(is_condition_expression("!param.deploy"));
+        assert!(!is_condition_expression("runner.os == 'Linux'"));
+        assert!(!is_condition_expression("branches == 'x'"));
+        assert!(!is_condition_expression("matrix.use-cross"));
+    }
+}
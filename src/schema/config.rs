@@ -1,14 +1,27 @@
 use anyhow::Context;
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_yaml::{Mapping, Value};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
 
+use super::cloud_auth::check_cloud_auth;
 use super::command::CommandDefinition;
-use super::job::Job;
+use super::docker_build::DockerBuildConfig;
+use super::job::{
+    Job, check_branches, check_cleanup, check_executor_conflict, check_test_splitting, group_need,
+    split_need,
+};
+use super::step::Step;
+use super::step_shape::check_steps;
+use super::suggest::unknown_reference_message;
 use super::workflow::{WorkflowConditionKind, WorkflowConfig};
+use super::yaml::{parse_yaml, parse_yaml_value};
 
 /// Main cigen.yml configuration
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[non_exhaustive]
 pub struct CigenConfig {
     /// Project metadata
     #[serde(default)]
@@ -37,6 +50,54 @@ pub struct CigenConfig {
     #[serde(default)]
     pub caches: HashMap<String, CacheDefinition>,
 
+    /// Version woven into every generated cache key; bump it to bust all caches
+    #[serde(default)]
+    pub cache_version: Option<u32>,
+
+    /// Scheduled workflow that fills dependency caches ahead of the jobs
+    /// that restore them
+    #[serde(default)]
+    pub cache_warmup: Option<CacheWarmup>,
+
+    /// Package manager overrides and additions, keyed by manager name
+    #[serde(default)]
+    pub package_managers: HashMap<String, PackageManagerDefinition>,
+
+    /// Files `{{ <tool>_version }}` is read from, keyed by tool. Replaces the
+    /// built-in sources for that tool.
+    #[serde(default)]
+    pub version_sources: HashMap<String, Vec<VersionSource>>,
+
+    /// Monorepo projects jobs can belong to with `project:`
+    #[serde(default)]
+    pub projects: Vec<String>,
+
+    /// Settings for the groups jobs join with `group:`, keyed by group name
+    #[serde(default)]
+    pub groups: HashMap<String, JobGroup>,
+
+    /// How the dynamic setup job finds the projects affected by a change
+    #[serde(default)]
+    pub project_detection: Option<ProjectDetection>,
+
+    /// Shell commands run before and after `cigen generate`
+    #[serde(default)]
+    pub hooks: Hooks,
+
+    /// Where to report job results; workflows can override it
+    #[serde(default)]
+    pub notifications: Option<Notifications>,
+
+    /// Steps every job runs around its own, like a secrets scan first and an
+    /// attestation last
+    #[serde(default)]
+    pub global_steps: GlobalSteps,
+
+    /// Accept any job `resource_class`, not only CircleCI's classes,
+    /// self-hosted runner classes, and `resource_classes` aliases
+    #[serde(default)]
+    pub allow_custom_resource_classes: bool,
+
     /// Runner definitions
     #[serde(default)]
     pub runners: HashMap<String, RunnerDefinition>,
@@ -49,6 +110,26 @@ pub struct CigenConfig {
     #[serde(default)]
     pub workflows: HashMap<String, WorkflowConfig>,
 
+    /// Environment variables applied to every job (workflow and job env take precedence)
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// Template variables available to job files as `{{ name }}`
+    #[serde(default)]
+    pub vars: HashMap<String, serde_yaml::Value>,
+
+    /// Docker images built in CI and referenced by jobs
+    #[serde(default)]
+    pub docker_build: Option<DockerBuildConfig>,
+
+    /// Extra plugins: binary paths, or names resolved from `$CIGEN_PLUGIN_PATH` and `~/.cigen/plugins`
+    #[serde(default)]
+    pub plugins: Vec<String>,
+
+    /// Directory the config's relative paths resolve against (set by the loader)
+    #[serde(skip)]
+    pub project_root: Option<PathBuf>,
+
     /// Raw merged configuration (for provider-specific logic)
     #[serde(skip)]
     pub raw: Mapping,
@@ -80,28 +161,335 @@ pub enum ProjectType {
 }
 
 /// Cache definition
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[non_exhaustive]
 pub struct CacheDefinition {
     /// Paths to cache
     pub paths: Vec<String>,
 
-    /// Components for cache key
+    /// Literal cache key components, after the cache name and architecture
+    #[serde(default)]
     pub key_parts: Vec<String>,
 
+    /// Files whose checksums end the cache key, most significant first
+    #[serde(default)]
+    pub checksum_sources: Vec<String>,
+
+    /// How many less specific keys to fall back to when the exact key misses
+    #[serde(default)]
+    pub restore_key_levels: usize,
+
+    /// Overrides the top-level `cache_version` for this cache
+    #[serde(default)]
+    pub cache_version: Option<u32>,
+
+    /// Command that fills the cache in the `cache_warmup` workflow
+    #[serde(default)]
+    pub warmup_command: Option<String>,
+
     /// Cache backend
     #[serde(default = "default_cache_backend")]
     pub backend: CacheBackend,
 }
 
+/// A package manager jobs opt into with `packages:`. Fields left unset keep
+/// the built-in defaults for a manager cigen already knows.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PackageManagerDefinition {
+    /// Lockfile that must exist for the package, checksummed into the cache key
+    #[serde(default)]
+    pub lockfile: Option<String>,
+
+    /// Command that installs the packages
+    #[serde(default)]
+    pub install: Option<String>,
+
+    /// Name of the cache holding installed packages
+    #[serde(default)]
+    pub cache: Option<String>,
+
+    /// Paths the cache saves
+    #[serde(default)]
+    pub cache_paths: Option<Vec<String>>,
+}
+
+/// A file a tool's version is read from
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct VersionSource {
+    /// File relative to the project root
+    pub file: String,
+
+    /// Regex whose first capture group is the version. Without one, the
+    /// file's first non-empty line is the version.
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+/// Shell commands `cigen generate` runs from the project root. `{output_dir}`
+/// and `{provider}` are replaced before running.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    /// Run after the config is loaded, before anything is generated
+    #[serde(default)]
+    pub pre_generate: Vec<String>,
+
+    /// Run after the generated files are written
+    #[serde(default)]
+    pub post_generate: Vec<String>,
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.pre_generate.is_empty() && self.post_generate.is_empty()
+    }
+}
+
+/// Steps run around every job's own steps. A workflow or job opts out with
+/// `inherit_global_steps: false`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GlobalSteps {
+    /// Run after the checkout and cache restores, before the job's steps
+    #[serde(default)]
+    pub before: Vec<Step>,
+
+    /// Run after the job's steps, before cache saves and the completion marker
+    #[serde(default)]
+    pub after: Vec<Step>,
+}
+
+impl GlobalSteps {
+    pub fn is_empty(&self) -> bool {
+        self.before.is_empty() && self.after.is_empty()
+    }
+}
+
+/// Where to report the results of a workflow's jobs
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Notifications {
+    #[serde(default)]
+    pub slack: Option<SlackNotification>,
+}
+
+/// Slack messages about job results. Credentials come from the CI provider's
+/// secrets, never from the config.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SlackNotification {
+    /// Channel to post to, e.g. `#ci`
+    pub channel: String,
+
+    /// Results that send a message (default: failure)
+    #[serde(default = "default_notify_events", rename = "on")]
+    pub events: Vec<NotifyEvent>,
+
+    /// CircleCI context providing `SLACK_ACCESS_TOKEN` to the slack orb
+    #[serde(default)]
+    pub context: Option<String>,
+
+    /// GitHub Actions secret holding the Slack incoming webhook URL
+    #[serde(default = "default_webhook_secret")]
+    pub webhook_secret: String,
+}
+
+/// A job result worth a notification
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyEvent {
+    Failure,
+    Success,
+    /// A success after the previous run failed
+    Fixed,
+}
+
+impl NotifyEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NotifyEvent::Failure => "failure",
+            NotifyEvent::Success => "success",
+            NotifyEvent::Fixed => "fixed",
+        }
+    }
+}
+
+fn default_notify_events() -> Vec<NotifyEvent> {
+    vec![NotifyEvent::Failure]
+}
+
+fn default_webhook_secret() -> String {
+    "SLACK_WEBHOOK_URL".to_string()
+}
+
+/// The `cache_warmup` workflow: one job per cache that restores it, installs
+/// the dependencies, and saves it under the key the other jobs restore
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CacheWarmup {
+    /// Cron schedule the workflow runs on, in UTC (e.g. `0 4 * * *`)
+    pub schedule: String,
+
+    /// Caches to warm: names under `caches:` with a `warmup_command`, or the
+    /// cache of a package manager (e.g. `gems` for bundler)
+    pub caches: Vec<String>,
+}
+
+/// How `group:<name>` needs on a group are generated
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct JobGroup {
+    /// Add a `<name>_gate` job that needs every job in the group, and have
+    /// dependents need it instead of each job
+    #[serde(default)]
+    pub gate: bool,
+}
+
+/// Command the dynamic setup job runs to list affected projects, one per line
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectDetection {
+    /// Monorepo tool, which picks the default command
+    pub tool: ProjectTool,
+
+    /// Replaces the tool's default command. `$BASE` holds the base ref.
+    #[serde(default)]
+    pub command: Option<String>,
+
+    /// Ref affected projects are computed against (default: origin/main)
+    #[serde(default)]
+    pub base: Option<String>,
+}
+
+/// Monorepo tool that knows which projects a change affects
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectTool {
+    Nx,
+    Turbo,
+}
+
+impl ProjectDetection {
+    /// The command that prints the affected projects
+    pub fn command(&self) -> &str {
+        match (&self.command, self.tool) {
+            (Some(command), _) => command,
+            (None, ProjectTool::Nx) => "npx nx show projects --affected --base=\"$BASE\"",
+            (None, ProjectTool::Turbo) => {
+                "TURBO_SCM_BASE=\"$BASE\" npx turbo ls --affected --output=json | jq -r '.packages.items[].name'"
+            }
+        }
+    }
+
+    pub fn base(&self) -> &str {
+        self.base.as_deref().unwrap_or("origin/main")
+    }
+}
+
+/// Top-level `caches:` entries that configure backends rather than define caches
+pub const RESERVED_CACHE_NAMES: [&str; 2] = ["artifacts", "job_status"];
+
+/// Prefix a cache key with `v<version>-`. Every generated cache key goes
+/// through here, so bumping `cache_version` busts all of them at once.
+pub fn versioned_cache_key(version: Option<u32>, key: &str) -> String {
+    match version {
+        Some(version) => format!("v{version}-{key}"),
+        None => key.to_string(),
+    }
+}
+
+/// Cache key component the provider replaces with the runner's architecture
+pub const CACHE_KEY_ARCH: &str = "{{ arch }}";
+
+/// `{{ checksum "<file>" }}` components of a cache key
+static CHECKSUM_COMPONENT: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"\{\{\s*checksum\s+"([^"]+)"\s*\}\}"#).expect("valid regex"));
+
+/// Cache key component for the checksum of `source`, relative to the job's
+/// working directory
+fn checksum_key_component(source: &str) -> String {
+    format!("{{{{ checksum \"{source}\" }}}}")
+}
+
+/// Files a cache key checksums, in the order they appear
+pub fn cache_key_checksum_sources(key: &str) -> Vec<&str> {
+    CHECKSUM_COMPONENT
+        .captures_iter(key)
+        .filter_map(|captures| captures.get(1))
+        .map(|source| source.as_str())
+        .collect()
+}
+
+/// `key` with each checksum source passed through `map`
+pub fn map_cache_key_checksum_sources(key: &str, map: impl Fn(&str) -> String) -> String {
+    CHECKSUM_COMPONENT
+        .replace_all(key, |captures: &regex::Captures| {
+            checksum_key_component(&map(&captures[1]))
+        })
+        .into_owned()
+}
+
+impl CacheDefinition {
+    /// Exact cache key: `[v<version>-]<name>-{{ arch }}-<key_parts>-<checksums>`,
+    /// where this cache's `cache_version` wins over `default_version`
+    pub fn key(&self, name: &str, default_version: Option<u32>) -> String {
+        self.key_components(name, self.checksum_sources.len(), default_version)
+            .join("-")
+    }
+
+    /// Prefixes to restore from when the exact key misses. Each level drops
+    /// one more trailing checksum, and the last level drops them all.
+    pub fn restore_keys(&self, name: &str, default_version: Option<u32>) -> Vec<String> {
+        let checksums = self.checksum_sources.len();
+        let mut keys = Vec::new();
+        for level in 1..=self.restore_key_levels {
+            let kept = if level == self.restore_key_levels {
+                0
+            } else {
+                checksums.saturating_sub(level)
+            };
+            let key = format!(
+                "{}-",
+                self.key_components(name, kept, default_version).join("-")
+            );
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        keys
+    }
+
+    fn key_components(
+        &self,
+        name: &str,
+        checksums: usize,
+        default_version: Option<u32>,
+    ) -> Vec<String> {
+        let name = versioned_cache_key(self.cache_version.or(default_version), name);
+        let mut components = vec![name, CACHE_KEY_ARCH.to_string()];
+        components.extend(self.key_parts.iter().cloned());
+        components.extend(
+            self.checksum_sources
+                .iter()
+                .take(checksums)
+                .map(|source| checksum_key_component(source)),
+        );
+        components
+    }
+}
+
 fn default_cache_backend() -> CacheBackend {
     CacheBackend::Native
 }
 
 /// Cache backend
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum CacheBackend {
     /// Provider's native caching
+    #[default]
     Native,
     /// Redis-based cache
     Redis,
@@ -119,12 +507,79 @@ pub struct RunnerDefinition {
 impl CigenConfig {
     /// Load configuration from YAML string
     pub fn from_yaml(yaml: &str) -> anyhow::Result<Self> {
-        let mut config: CigenConfig = serde_yaml::from_str(yaml)?;
+        let mut config: CigenConfig = parse_yaml(yaml)?;
         config.raw = extract_mapping(yaml)?;
+        if let Some(Value::Mapping(jobs)) = config.raw.get("jobs") {
+            for (job_id, job) in jobs {
+                let job_id = job_id.as_str().unwrap_or("?");
+                let warnings = check_executor_conflict(job)
+                    .and_then(|()| check_test_splitting(job))
+                    .and_then(|()| check_cloud_auth(job))
+                    .and_then(|()| check_branches(job))
+                    .and_then(|()| check_cleanup(job))
+                    .and_then(|()| Ok(check_steps(job)?))
+                    .with_context(|| format!("Invalid job '{job_id}'"))?;
+                for warning in warnings {
+                    tracing::warn!("Job '{job_id}': {warning}");
+                }
+            }
+        }
+        if let Some(Value::Mapping(commands)) = config.raw.get("commands") {
+            for (name, command) in commands {
+                let name = name.as_str().unwrap_or("?");
+                for warning in
+                    check_steps(command).with_context(|| format!("Invalid command '{name}'"))?
+                {
+                    tracing::warn!("Command '{name}': {warning}");
+                }
+            }
+        }
         config.validate()?;
         Ok(config)
     }
 
+    /// `group:<name>` needs must name a group some job is in, and groups
+    /// configured under `groups:` must have jobs
+    pub fn check_groups(&self) -> anyhow::Result<()> {
+        let members: BTreeSet<&str> = self
+            .jobs
+            .values()
+            .filter_map(|job| job.group.as_deref())
+            .collect();
+        let mut configured: Vec<&String> = self.groups.keys().collect();
+        configured.sort();
+        if let Some(empty) = configured
+            .into_iter()
+            .find(|group| !members.contains(group.as_str()))
+        {
+            anyhow::bail!(
+                "Group '{empty}' is configured under groups, but no job sets group: {empty}"
+            );
+        }
+
+        let mut job_ids: Vec<&String> = self.jobs.keys().collect();
+        job_ids.sort();
+        for job_id in job_ids {
+            for group in self.jobs[job_id]
+                .needs
+                .iter()
+                .filter_map(|need| group_need(need))
+            {
+                if !members.contains(group) {
+                    anyhow::bail!(
+                        "{}",
+                        unknown_reference_message(
+                            &format!("Job '{job_id}' needs unknown group '{group}'"),
+                            group,
+                            members.iter().copied(),
+                        )
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Validate the configuration
     pub fn validate(&self) -> anyhow::Result<()> {
         // Validate at least one job is defined
@@ -132,27 +587,55 @@ impl CigenConfig {
             anyhow::bail!("Configuration must define at least one job");
         }
 
+        self.check_groups()?;
+
         // Validate job references in needs
         for (job_id, job) in &self.jobs {
-            for needed_job in &job.needs {
+            for need in job.needs.iter().filter(|need| group_need(need).is_none()) {
+                let (needed_job, _) = split_need(need);
                 if !self.jobs.contains_key(needed_job) {
                     anyhow::bail!(
-                        "Job '{}' references unknown job '{}' in needs",
-                        job_id,
-                        needed_job
+                        "{}",
+                        unknown_reference_message(
+                            &format!(
+                                "Job '{job_id}' references unknown job '{needed_job}' in needs"
+                            ),
+                            needed_job,
+                            self.jobs.keys().map(String::as_str),
+                        )
                     );
                 }
             }
 
             // Check for self-reference
-            if job.needs.contains(job_id) {
+            if job.needs.iter().any(|need| split_need(need).0 == job_id) {
                 anyhow::bail!("Job '{}' cannot depend on itself", job_id);
             }
+
+            if let Some(test_results) = &job.test_results {
+                for (cache_name, cache) in &self.caches {
+                    if cache
+                        .paths
+                        .iter()
+                        .any(|path| paths_overlap(path, test_results))
+                    {
+                        anyhow::bail!(
+                            "Job '{job_id}' stores test results in '{test_results}', which is also cached by '{cache_name}'"
+                        );
+                    }
+                }
+            }
+        }
+
+        for (name, command) in &self.commands {
+            command
+                .validate()
+                .with_context(|| format!("Invalid command '{name}'"))?;
         }
 
         let providers = self.get_providers();
         for (workflow_id, workflow) in &self.workflows {
-            for condition in &workflow.run_when {
+            for condition in workflow.run_when.iter().chain(&workflow.run_unless) {
                 condition.validate().with_context(|| {
                     format!(
                         "Invalid condition in workflow '{}': {:?}",
@@ -180,6 +663,26 @@ impl CigenConfig {
                     };
 
                 for provider in target_providers {
+                    if provider == "circleci"
+                        && let Some(
+                            kind @ (WorkflowConditionKind::Env | WorkflowConditionKind::Variable),
+                        ) = condition.kind()
+                    {
+                        let kind = if kind == WorkflowConditionKind::Env {
+                            "env"
+                        } else {
+                            "variable"
+                        };
+                        anyhow::bail!(
+                            "Workflow '{workflow_id}': {kind} conditions are not supported on CircleCI; use a pipeline parameter"
+                        );
+                    }
+                    if provider == "github" && condition.kind() == Some(WorkflowConditionKind::Env)
+                    {
+                        anyhow::bail!(
+                            "Workflow '{workflow_id}': env conditions are not supported on GitHub Actions, where a job's `if:` can't read `env`; use a variable or a parameter"
+                        );
+                    }
                     if !provider_supports_condition(provider, condition.kind()) {
                         anyhow::bail!(
                             "Workflow '{}' uses a {:?} condition that is not supported by provider '{}'",
@@ -213,8 +716,16 @@ impl CigenConfig {
 fn provider_supports_condition(provider: &str, kind: Option<WorkflowConditionKind>) -> bool {
     let kind = kind.unwrap_or(WorkflowConditionKind::Parameter);
     match provider {
-        "circleci" => matches!(kind, WorkflowConditionKind::Parameter),
-        "github" => matches!(kind, WorkflowConditionKind::Expression),
+        "circleci" => matches!(
+            kind,
+            WorkflowConditionKind::Parameter | WorkflowConditionKind::Expression
+        ),
+        "github" => matches!(
+            kind,
+            WorkflowConditionKind::Parameter
+                | WorkflowConditionKind::Variable
+                | WorkflowConditionKind::Expression
+        ),
         "buildkite" => {
             // Buildkite currently has no workflow condition support; fail explicitly.
             false
@@ -224,7 +735,7 @@ fn provider_supports_condition(provider: &str, kind: Option<WorkflowConditionKin
 }
 
 fn extract_mapping(yaml: &str) -> anyhow::Result<Mapping> {
-    let value: Value = serde_yaml::from_str(yaml)?;
+    let value = parse_yaml_value(yaml)?;
     match value {
         Value::Mapping(map) => Ok(map),
         other => {
@@ -235,6 +746,19 @@ fn extract_mapping(yaml: &str) -> anyhow::Result<Mapping> {
     }
 }
 
+/// Whether one of two relative paths is the other or inside it: `tmp` and
+/// `tmp/junit/` overlap, `tmp/junit` and `tmp/junit-old` don't
+fn paths_overlap(a: &str, b: &str) -> bool {
+    let components = |path: &str| -> Vec<String> {
+        path.split('/')
+            .filter(|component| !component.is_empty() && *component != ".")
+            .map(str::to_string)
+            .collect()
+    };
+    let (a, b) = (components(a), components(b));
+    a.starts_with(&b) || b.starts_with(&a)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -316,6 +840,24 @@ jobs:
                 .to_string()
                 .contains("unknown job 'nonexistent'")
         );
+
+        // Architecture selectors are checked by job
+        let yaml = r#"
+jobs:
+  build:
+    arch: amd64
+  test:
+    needs:
+      - build@amd64
+  deploy:
+    needs:
+      - release@amd64
+"#;
+        let error = CigenConfig::from_yaml(yaml).unwrap_err().to_string();
+        assert!(
+            error.contains("Job 'deploy' references unknown job 'release'"),
+            "{error}"
+        );
     }
 
     #[test]
@@ -336,4 +878,145 @@ jobs:
                 .contains("cannot depend on itself")
         );
     }
+
+    #[test]
+    fn test_cache_key_from_checksum_sources() {
+        let yaml = r#"
+caches:
+  gems:
+    paths: [vendor/bundle]
+    checksum_sources: [Gemfile.lock, .ruby-version]
+    restore_key_levels: 3
+
+jobs:
+  test: {}
+"#;
+
+        let config = CigenConfig::from_yaml(yaml).unwrap();
+        let gems = &config.caches["gems"];
+        assert_eq!(
+            gems.key("gems", None),
+            r#"gems-{{ arch }}-{{ checksum "Gemfile.lock" }}-{{ checksum ".ruby-version" }}"#
+        );
+        // Levels past the number of checksums collapse into the catch-all prefix
+        assert_eq!(
+            gems.restore_keys("gems", None),
+            vec![
+                r#"gems-{{ arch }}-{{ checksum "Gemfile.lock" }}-"#.to_string(),
+                "gems-{{ arch }}-".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cache_key_without_restore_levels() {
+        let cache = CacheDefinition {
+            paths: vec!["node_modules".into()],
+            key_parts: vec!["v2".into()],
+            checksum_sources: vec!["pnpm-lock.yaml".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            cache.key("pnpm", None),
+            r#"pnpm-{{ arch }}-v2-{{ checksum "pnpm-lock.yaml" }}"#
+        );
+        assert!(cache.restore_keys("pnpm", None).is_empty());
+    }
+
+    #[test]
+    fn test_cache_key_checksum_sources_round_trip() {
+        let key = r#"gems-{{ arch }}-{{ checksum "Gemfile.lock" }}-{{checksum ".ruby-version"}}"#;
+        assert_eq!(
+            cache_key_checksum_sources(key),
+            ["Gemfile.lock", ".ruby-version"]
+        );
+        assert_eq!(
+            map_cache_key_checksum_sources(key, |source| format!("app/{source}")),
+            r#"gems-{{ arch }}-{{ checksum "app/Gemfile.lock" }}-{{ checksum "app/.ruby-version" }}"#
+        );
+    }
+
+    #[test]
+    fn test_cache_version_prefixes_keys() {
+        let yaml = r#"
+cache_version: 3
+caches:
+  gems:
+    paths: [vendor/bundle]
+    checksum_sources: [Gemfile.lock]
+    restore_key_levels: 1
+  assets:
+    paths: [public/assets]
+    cache_version: 7
+
+jobs:
+  test: {}
+"#;
+
+        let config = CigenConfig::from_yaml(yaml).unwrap();
+        let gems = &config.caches["gems"];
+        assert_eq!(
+            gems.key("gems", config.cache_version),
+            r#"v3-gems-{{ arch }}-{{ checksum "Gemfile.lock" }}"#
+        );
+        assert_eq!(
+            gems.restore_keys("gems", config.cache_version),
+            vec!["v3-gems-{{ arch }}-"]
+        );
+        assert_eq!(
+            config.caches["assets"].key("assets", config.cache_version),
+            "v7-assets-{{ arch }}"
+        );
+    }
+
+    #[test]
+    fn test_validation_test_results_cached() {
+        let yaml = r#"
+caches:
+  junit:
+    paths:
+      - tmp/junit/
+    key_parts: []
+
+jobs:
+  test:
+    test_results: tmp/junit
+"#;
+
+        let result = CigenConfig::from_yaml(yaml);
+        assert!(result.is_err());
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("also cached by 'junit'")
+        );
+    }
+
+    #[test]
+    fn test_validation_test_results_inside_a_cached_directory() {
+        let config = |cached: &str, test_results: &str| {
+            CigenConfig::from_yaml(&format!(
+                "caches:\n  tmp:\n    paths: [{cached}]\n    key_parts: []\njobs:\n  test:\n    test_results: {test_results}\n"
+            ))
+        };
+        let error = config("tmp", "tmp/junit").unwrap_err().to_string();
+        assert!(error.contains("also cached by 'tmp'"), "{error}");
+        assert!(config("./tmp/junit/xml", "tmp/junit/").is_err());
+        assert!(config("tmp/junit-old", "tmp/junit").is_ok());
+    }
+
+    #[test]
+    fn test_validation_unknown_job_suggests_closest() {
+        let yaml = r#"
+jobs:
+  rspec: {}
+  lint:
+    needs:
+      - rpsec
+"#;
+
+        let error = CigenConfig::from_yaml(yaml).unwrap_err().to_string();
+        assert!(error.contains("Did you mean 'rspec'?"));
+    }
 }
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// `docker_build:` section: images built by CI and consumed by jobs via `image: <name>`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DockerBuildConfig {
+    /// Set to false to keep the definitions without generating build jobs
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Enable Docker layer caching on the build jobs
+    #[serde(default)]
+    pub layer_caching: bool,
+
+    /// Publish a multi-arch manifest (`repo/image:hash`) for images built for several architectures
+    #[serde(default)]
+    pub manifest: bool,
+
+    /// Image used to run the build jobs
+    #[serde(default)]
+    pub builder_image: Option<String>,
+
+    /// Have the setup job check the registry for each image and only build the missing ones
+    #[serde(default)]
+    pub verify_images: bool,
+
+    /// Registry the built images are tagged for
+    pub registry: DockerRegistry,
+
+    /// Images to build
+    #[serde(default)]
+    pub images: Vec<DockerImage>,
+}
+
+/// Registry settings for built images
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DockerRegistry {
+    /// Repository prefix (e.g. `docker.io/acme`); images are tagged `<repo>/<name>:<tag>`
+    pub repo: String,
+
+    /// Push built images to the registry
+    #[serde(default = "default_true")]
+    pub push: bool,
+
+    /// Environment variables build and manifest jobs log in to the registry with
+    #[serde(default)]
+    pub login: RegistryLogin,
+}
+
+/// Names of the environment variables holding the registry credentials. The
+/// password is piped to `docker login --password-stdin`, so neither value is
+/// written to the generated config.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryLogin {
+    #[serde(default = "default_username_env")]
+    pub username_env: String,
+
+    #[serde(default = "default_password_env")]
+    pub password_env: String,
+}
+
+impl Default for RegistryLogin {
+    fn default() -> Self {
+        Self {
+            username_env: default_username_env(),
+            password_env: default_password_env(),
+        }
+    }
+}
+
+/// A single image built by `docker_build`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DockerImage {
+    /// Logical name referenced by jobs (`image: <name>`)
+    pub name: String,
+
+    /// Dockerfile path relative to the project root
+    #[serde(default = "default_dockerfile")]
+    pub dockerfile: String,
+
+    /// Build context relative to the project root
+    #[serde(default = "default_context")]
+    pub context: String,
+
+    /// Architectures to build for
+    #[serde(default = "default_architectures", alias = "architectures")]
+    pub arch: Vec<String>,
+
+    /// `--build-arg` values
+    #[serde(default)]
+    pub build_args: BTreeMap<String, String>,
+
+    /// Files (globs) whose contents determine the image tag
+    #[serde(default)]
+    pub hash_sources: Vec<String>,
+
+    /// Other `docker_build` images this image is built from
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+
+    /// `--cache-from` sources (e.g. `type=registry,ref=repo/image:buildcache`)
+    #[serde(default)]
+    pub cache_from: Vec<String>,
+
+    /// `--cache-to` destinations
+    #[serde(default)]
+    pub cache_to: Vec<String>,
+
+    /// Shorthand for a registry cache at `<repo>/<name>:buildcache-<arch>`
+    #[serde(default)]
+    pub registry_cache: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_username_env() -> String {
+    "CIGEN_REGISTRY_USERNAME".to_string()
+}
+
+fn default_password_env() -> String {
+    "CIGEN_REGISTRY_PASSWORD".to_string()
+}
+
+fn default_dockerfile() -> String {
+    "Dockerfile".to_string()
+}
+
+fn default_context() -> String {
+    ".".to_string()
+}
+
+fn default_architectures() -> Vec<String> {
+    vec!["amd64".to_string()]
+}
@@ -0,0 +1,128 @@
+//! Top-level `instrumentation:` options that providers apply to generated steps
+
+use anyhow::{Result, bail};
+use serde_yaml::Value;
+
+use super::shell::shell_quote;
+
+/// Directory holding [`STEP_TIMINGS_LOG`]
+const STEP_TIMINGS_DIR: &str = "/tmp/cigen";
+
+/// Where timed steps append their durations
+pub const STEP_TIMINGS_LOG: &str = "/tmp/cigen/timings.log";
+
+/// Configured at the root of the config:
+///
+/// ```yaml
+/// instrumentation:
+///   step_timing: true
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Instrumentation {
+    /// Time every generated run step and store the log as an artifact
+    pub step_timing: bool,
+}
+
+impl Instrumentation {
+    pub fn from_raw_config(raw_config: &Value) -> Result<Self> {
+        let mut instrumentation = Self::default();
+        let Some(options) = raw_config.get("instrumentation") else {
+            return Ok(instrumentation);
+        };
+        match options.get("step_timing") {
+            None | Some(Value::Null) => {}
+            Some(Value::Bool(enabled)) => instrumentation.step_timing = *enabled,
+            Some(other) => {
+                bail!("instrumentation.step_timing must be true or false, got {other:?}")
+            }
+        }
+        Ok(instrumentation)
+    }
+}
+
+/// `command` with a line appended to [`STEP_TIMINGS_LOG`] saying how long it
+/// took: `cigen-timing: <name> <n>ms`. The command runs in a subshell and the
+/// line is written from the step's EXIT trap, so steps that fail, `exit` early
+/// or set their own EXIT trap are timed too, and the step's exit status is
+/// unchanged. POSIX `sh` is enough; without GNU `date`'s `%N` (macOS, BSD) the
+/// durations are rounded to whole seconds.
+pub fn timed_command(name: &str, command: &str) -> String {
+    format!(
+        "cigen_timing_step={name}\n\
+         cigen_timing_now() {{ cigen_timing_ms=$(date +%s%3N); case $cigen_timing_ms in *N) echo $(($(date +%s) * 1000)) ;; *) echo \"$cigen_timing_ms\" ;; esac; }}\n\
+         cigen_timing_start=$(cigen_timing_now)\n\
+         trap 'mkdir -p {STEP_TIMINGS_DIR} && echo \"cigen-timing: $cigen_timing_step $(($(cigen_timing_now) - cigen_timing_start))ms\" >> {STEP_TIMINGS_LOG}' EXIT\n\
+         (\n\
+         {command}\n\
+         )\n",
+        name = shell_quote(name),
+        command = command.trim_end_matches('\n'),
+    )
+}
+
+/// Name to log for a run step without one: its first non-blank command line
+pub fn default_step_name(command: &str) -> &str {
+    command
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or("run")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_timing_is_off_by_default() {
+        let parse =
+            |text: &str| Instrumentation::from_raw_config(&serde_yaml::from_str(text).unwrap());
+        assert!(!parse("jobs: {}").unwrap().step_timing);
+        assert!(!parse("instrumentation: {}").unwrap().step_timing);
+        assert!(
+            parse("instrumentation: {step_timing: true}")
+                .unwrap()
+                .step_timing
+        );
+        assert!(parse("instrumentation: {step_timing: sometimes}").is_err());
+    }
+
+    #[test]
+    fn timed_commands_quote_the_step_name() {
+        let command = timed_command("Bob's tests; rm -rf /", "bundle exec rspec\n");
+        assert!(
+            command.starts_with("cigen_timing_step='Bob'\\''s tests; rm -rf /'\n"),
+            "{command}"
+        );
+        assert!(
+            command.ends_with("\n(\nbundle exec rspec\n)\n"),
+            "{command}"
+        );
+        assert_eq!(default_step_name("\n  npm ci\nnpm test"), "npm ci");
+    }
+
+    #[test]
+    fn failing_steps_with_their_own_exit_trap_are_timed() {
+        let name = format!("trapped step {}", std::process::id());
+        for shell in [["bash", "-eo", "pipefail"], ["sh", "-e", "-u"]] {
+            let output = std::process::Command::new(shell[0])
+                .args(&shell[1..])
+                .arg("-c")
+                .arg(timed_command(
+                    &name,
+                    "trap 'echo cleaned up' EXIT\nfalse\necho unreachable",
+                ))
+                .output()
+                .unwrap();
+            assert_eq!(output.status.code(), Some(1), "{shell:?}");
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "cleaned up\n");
+            let log = std::fs::read_to_string(STEP_TIMINGS_LOG).unwrap();
+            assert!(
+                log.lines()
+                    .any(|line| line.starts_with(&format!("cigen-timing: {name} "))
+                        && line.ends_with("ms")),
+                "{log}"
+            );
+        }
+    }
+}
@@ -1,9 +1,11 @@
 use serde::de::{self, Deserializer};
-use serde::{Deserialize, Serialize};
-use serde_yaml::Value;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_yaml::{Mapping, Value};
 use std::collections::HashMap;
+use std::path::PathBuf;
 
-use super::step::{Artifact, Step};
+use super::cloud_auth::CloudAuth;
+use super::step::{Artifact, RetryPolicy, Step};
 
 /// Package requirement for a job
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -42,6 +44,7 @@ impl PackageSpec {
 
 /// Job definition
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[non_exhaustive]
 pub struct Job {
     /// Job dependencies
     #[serde(default)]
@@ -59,26 +62,64 @@ pub struct Job {
     #[serde(default)]
     pub services: Vec<String>,
 
+    /// Caches restored before the steps and saved after them
+    #[serde(
+        default,
+        deserialize_with = "deserialize_job_caches",
+        serialize_with = "serialize_job_caches",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub cache: Vec<JobCache>,
+
     /// Environment variables
     #[serde(default, alias = "env")]
     pub environment: HashMap<String, String>,
 
-    /// Checkout configuration overrides (applied to the auto checkout step)
-    #[serde(default)]
+    /// Checkout configuration overrides (applied to the auto checkout step).
+    /// `checkout: false` is stored as `{enabled: false}`.
+    #[serde(default, deserialize_with = "deserialize_checkout")]
     pub checkout: Option<HashMap<String, Value>>,
 
     /// Job steps
     #[serde(default)]
     pub steps: Vec<Step>,
 
+    /// Run steps for teardown, after the steps and before the job records
+    /// its completion. They run even when an earlier step failed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cleanup: Vec<Step>,
+
+    /// Whether `cleanup` runs after every job or only after failed ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cleanup_on: Option<CleanupOn>,
+
+    /// Whether the job runs the top-level `global_steps`; follows its
+    /// workflow's `inherit_global_steps` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inherit_global_steps: Option<bool>,
+
     /// Source files that trigger this job (for skip logic)
     #[serde(
         default,
-        deserialize_with = "deserialize_source_files",
+        deserialize_with = "deserialize_string_or_list",
         skip_serializing_if = "Vec::is_empty"
     )]
     pub source_files: Vec<String>,
 
+    /// Monorepo project the job belongs to; the job is left out when
+    /// `project_detection` doesn't list the project as affected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+
+    /// Group the job belongs to; other jobs can need the whole group with
+    /// `group:<name>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+
+    /// Git submodule paths whose pinned commits are part of the job hash
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub source_submodules: Vec<String>,
+
     /// Skip conditions
     #[serde(default)]
     pub skip_if: Option<SkipConditions>,
@@ -87,6 +128,20 @@ pub struct Job {
     #[serde(default)]
     pub trigger: Option<JobTrigger>,
 
+    /// Branches the job runs on, as names or `/regex/` patterns matching the
+    /// whole branch name. Runs on every branch when empty.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_string_or_list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub branches: Vec<String>,
+
+    /// Condition expression (same language as a step's `if:`) that skips
+    /// the job when it holds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unless: Option<String>,
+
     /// Docker image or runner class (e.g. "rust:latest", "ubuntu-latest")
     #[serde(default = "default_image")]
     pub image: String,
@@ -95,10 +150,51 @@ pub struct Job {
     #[serde(default)]
     pub runner: Option<String>,
 
+    /// Target CPU architecture (e.g. amd64, arm64). Matrix jobs take this from an `arch` dimension.
+    #[serde(default, alias = "arch", skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<String>,
+
     /// Artifacts to store
     #[serde(default)]
     pub artifacts: Vec<Artifact>,
 
+    /// Directory containing JUnit XML test results to publish
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_results: Option<String>,
+
+    /// Split a test suite across the job's `parallelism` containers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_splitting: Option<TestSplitting>,
+
+    /// Remote Docker engine for jobs that build or run containers (CircleCI `setup_remote_docker`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_docker: Option<RemoteDocker>,
+
+    /// Execution environment (docker by default, or a machine/macOS VM)
+    #[serde(
+        default,
+        deserialize_with = "deserialize_executor",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub executor: Option<JobExecutor>,
+
+    /// Directory the job's commands run in, relative to the checkout unless absolute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_directory: Option<String>,
+
+    /// Retry each of the job's run steps that doesn't set its own `retry`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+
+    /// Cloud credentials from the provider's OIDC token, set up before the steps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cloud_auth: Option<CloudAuth>,
+
+    /// Keys merged into the generated job last, per provider (e.g. `circleci`,
+    /// `github`), for settings cigen doesn't model or to replace ones it produced
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub provider_overrides: HashMap<String, Mapping>,
+
     /// Additional unspecified job fields to preserve pass-through metadata
     #[serde(default, flatten)]
     pub extra: HashMap<String, Value>,
@@ -107,9 +203,236 @@ pub struct Job {
     #[serde(default, skip_serializing)]
     pub workflow: Option<String>,
 
+    /// File the job was defined in (set by loader, used to locate diagnostics)
+    #[serde(skip)]
+    pub source_file: Option<PathBuf>,
+
+    /// Key of the job's entry in `source_file` when that file defines several
+    /// jobs (set by loader, used to locate diagnostics)
+    #[serde(skip)]
+    pub source_section: Option<String>,
+
     /// Stage this job belongs to (set by loader from directory structure)
     #[serde(default, skip_serializing)]
     pub stage: Option<String>,
+
+    /// Config shard the job is generated into, from 1 (set by the orchestrator
+    /// with `--shard-count`)
+    #[serde(skip)]
+    pub shard: Option<u32>,
+
+    /// The job's id in the config, e.g. `test/rspec` (set by the orchestrator
+    /// when it expands jobs, whose names may drop the stage)
+    #[serde(skip)]
+    pub job_id: Option<String>,
+
+    /// Name shared by the variants of a matrix job (set by the orchestrator
+    /// when it expands the matrix)
+    #[serde(skip)]
+    pub matrix_job: Option<String>,
+
+    /// Matrix values this variant was expanded with (set by the orchestrator)
+    #[serde(skip)]
+    pub matrix_values: HashMap<String, String>,
+}
+
+impl Default for Job {
+    fn default() -> Self {
+        Self {
+            needs: Vec::new(),
+            matrix: None,
+            packages: Vec::new(),
+            services: Vec::new(),
+            cache: Vec::new(),
+            environment: HashMap::new(),
+            checkout: None,
+            steps: Vec::new(),
+            cleanup: Vec::new(),
+            cleanup_on: None,
+            inherit_global_steps: None,
+            source_files: Vec::new(),
+            source_submodules: Vec::new(),
+            project: None,
+            group: None,
+            skip_if: None,
+            trigger: None,
+            branches: Vec::new(),
+            unless: None,
+            image: default_image(),
+            runner: None,
+            architecture: None,
+            artifacts: Vec::new(),
+            test_results: None,
+            test_splitting: None,
+            remote_docker: None,
+            executor: None,
+            working_directory: None,
+            retry: None,
+            cloud_auth: None,
+            provider_overrides: HashMap::new(),
+            extra: HashMap::new(),
+            workflow: None,
+            source_file: None,
+            source_section: None,
+            stage: None,
+            shard: None,
+            job_id: None,
+            matrix_job: None,
+            matrix_values: HashMap::new(),
+        }
+    }
+}
+
+impl Job {
+    /// Approval jobs (`type: approval`) gate a workflow on a manual approval
+    /// and run nothing themselves
+    pub fn is_approval(&self) -> bool {
+        self.extra.get("type").and_then(Value::as_str) == Some("approval")
+    }
+
+    /// The first field that gives an approval job something to run
+    pub fn approval_conflict(&self) -> Option<&'static str> {
+        [
+            ("steps", !self.steps.is_empty()),
+            ("cache", !self.cache.is_empty()),
+            ("services", !self.services.is_empty()),
+            ("packages", !self.packages.is_empty()),
+            ("matrix", self.matrix.is_some()),
+            ("arch", self.architecture.is_some()),
+            ("test_splitting", self.test_splitting.is_some()),
+            ("executor", self.executor.is_some()),
+        ]
+        .into_iter()
+        .find_map(|(field, set)| set.then_some(field))
+    }
+}
+
+/// A cache used by a job, from `cache: gems`, `cache: [gems, node_modules]`,
+/// or `cache: { gems: { paths: [...], save: false } }`
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[non_exhaustive]
+pub struct JobCache {
+    /// Cache name, looked up in the top-level `caches:`
+    #[serde(skip)]
+    pub name: String,
+    /// Paths to cache instead of the definition's
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+    /// Restore the cache before the job's steps
+    pub restore: bool,
+    /// Save the cache after the job's steps
+    pub save: bool,
+    /// When the save step runs; the provider's default when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub save_when: Option<SaveWhen>,
+}
+
+/// When a job's `save_cache` step runs, relative to the job's outcome
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SaveWhen {
+    /// Save even if an earlier step failed, e.g. a partially installed bundle
+    Always,
+    /// Save only after every earlier step passed
+    OnSuccess,
+    /// Save only when an earlier step failed
+    #[serde(rename = "on_fail", alias = "on_failure")]
+    OnFailure,
+}
+
+impl SaveWhen {
+    /// The CircleCI `when:` value
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SaveWhen::Always => "always",
+            SaveWhen::OnSuccess => "on_success",
+            SaveWhen::OnFailure => "on_fail",
+        }
+    }
+}
+
+/// When a job's `cleanup` steps run
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupOn {
+    /// After every run, whether or not the steps passed
+    #[default]
+    Always,
+    /// Only when an earlier step failed
+    Failure,
+}
+
+/// Test files split across a job's parallel containers and run by one command
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TestSplitting {
+    /// Glob selecting the test files (e.g. "spec/**/*_spec.rb")
+    pub glob: String,
+
+    /// How files are balanced between containers
+    #[serde(default)]
+    pub by: SplitBy,
+
+    /// Command run with this container's files in place of `{files}`
+    pub command_template: String,
+}
+
+/// How test files are balanced between containers. Providers without timing
+/// data fall back to splitting by name.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitBy {
+    /// Historical timings from stored test results
+    #[default]
+    Timings,
+    /// File name order
+    Name,
+    /// File size
+    Filesize,
+}
+
+impl SplitBy {
+    /// The `circleci tests split --split-by` value
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SplitBy::Timings => "timings",
+            SplitBy::Name => "name",
+            SplitBy::Filesize => "filesize",
+        }
+    }
+}
+
+impl JobCache {
+    /// Restore and save the named cache with its defined paths
+    pub fn named(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            paths: Vec::new(),
+            restore: true,
+            save: true,
+            save_when: None,
+        }
+    }
+}
+
+/// Directory where providers record the pinned commit of each `source_submodules` entry
+pub const SUBMODULE_COMMIT_DIR: &str = "/tmp/cigen/submodules";
+
+/// File holding the pinned commit of the submodule at `path` (slashes become underscores)
+pub fn submodule_commit_file(path: &str) -> String {
+    format!(
+        "{SUBMODULE_COMMIT_DIR}/{}.commit",
+        path.trim_matches('/').replace('/', "_")
+    )
+}
+
+/// Split a `needs` entry into the job and the architecture it selects:
+/// `build@arm64` needs only the arm64 variant of `build`
+pub fn split_need(need: &str) -> (&str, Option<&str>) {
+    match need.split_once('@') {
+        Some((job, architecture)) => (job, Some(architecture)),
+        None => (need, None),
+    }
 }
 
 fn deserialize_packages<'de, D>(deserializer: D) -> Result<Vec<PackageSpec>, D::Error>
@@ -143,25 +466,170 @@ fn value_to_package(value: Value) -> Result<PackageSpec, serde_yaml::Error> {
     }
 }
 
-fn deserialize_source_files<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+fn deserialize_string_or_list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
 where
     D: Deserializer<'de>,
 {
     #[derive(Deserialize)]
     #[serde(untagged)]
-    enum SourceFilesInput {
+    enum StringOrList {
         Single(String),
         Multiple(Vec<String>),
     }
 
-    let value = Option::<SourceFilesInput>::deserialize(deserializer)?;
+    let value = Option::<StringOrList>::deserialize(deserializer)?;
     Ok(match value {
-        Some(SourceFilesInput::Single(pattern)) => vec![pattern],
-        Some(SourceFilesInput::Multiple(patterns)) => patterns,
+        Some(StringOrList::Single(item)) => vec![item],
+        Some(StringOrList::Multiple(items)) => items,
         None => Vec::new(),
     })
 }
 
+fn deserialize_checkout<'de, D>(deserializer: D) -> Result<Option<HashMap<String, Value>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Bool(enabled)) => Ok(Some(HashMap::from([(
+            "enabled".to_string(),
+            Value::Bool(enabled),
+        )]))),
+        Some(Value::Mapping(map)) => serde_yaml::from_value(Value::Mapping(map))
+            .map(Some)
+            .map_err(de::Error::custom),
+        Some(other) => Err(de::Error::custom(format!(
+            "checkout must be a boolean or a mapping, got {other:?}"
+        ))),
+    }
+}
+
+/// Written back in the mapping form, which keeps every option
+fn serialize_job_caches<S>(caches: &[JobCache], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_map(caches.iter().map(|cache| (&cache.name, cache)))
+}
+
+fn deserialize_job_caches<'de, D>(deserializer: D) -> Result<Vec<JobCache>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Paths {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Options {
+        #[serde(default = "enabled")]
+        restore: bool,
+        #[serde(default = "enabled")]
+        save: bool,
+        #[serde(default)]
+        save_when: Option<SaveWhen>,
+        #[serde(default, alias = "path")]
+        paths: Option<Paths>,
+    }
+
+    fn enabled() -> bool {
+        true
+    }
+
+    fn paths(paths: Option<Paths>) -> Vec<String> {
+        match paths {
+            Some(Paths::One(path)) => vec![path],
+            Some(Paths::Many(paths)) => paths,
+            None => Vec::new(),
+        }
+    }
+
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::String(name)) => Ok(vec![JobCache::named(name)]),
+        Some(Value::Sequence(names)) => names
+            .into_iter()
+            .map(|name| match name {
+                Value::String(name) => Ok(JobCache::named(name)),
+                other => Err(de::Error::custom(format!(
+                    "cache names must be strings, got {other:?}"
+                ))),
+            })
+            .collect(),
+        Some(Value::Mapping(caches)) => caches
+            .into_iter()
+            .map(|(name, options)| {
+                let Value::String(name) = name else {
+                    return Err(de::Error::custom(format!(
+                        "cache names must be strings, got {name:?}"
+                    )));
+                };
+                let mut cache = JobCache::named(name);
+                match options {
+                    Value::Null => {}
+                    Value::String(_) | Value::Sequence(_) => {
+                        cache.paths =
+                            paths(serde_yaml::from_value(options).map_err(de::Error::custom)?);
+                    }
+                    options => {
+                        let options: Options =
+                            serde_yaml::from_value(options).map_err(de::Error::custom)?;
+                        cache.restore = options.restore;
+                        cache.save = options.save;
+                        cache.save_when = options.save_when;
+                        cache.paths = paths(options.paths);
+                    }
+                }
+                Ok(cache)
+            })
+            .collect(),
+        Some(other) => Err(de::Error::custom(format!(
+            "cache must be a name, a list of names, or a mapping, got {other:?}"
+        ))),
+    }
+}
+
+fn deserialize_executor<'de, D>(deserializer: D) -> Result<Option<JobExecutor>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(kind)) => match kind.as_str() {
+            "docker" => Ok(Some(JobExecutor::Docker)),
+            "machine" => Ok(Some(JobExecutor::Machine(MachineExecutor::default()))),
+            "macos" => Err(de::Error::custom(
+                "the macos executor needs an Xcode version: `executor: { macos: { xcode: \"15.3\" } }`",
+            )),
+            _ => Ok(Some(JobExecutor::Named(kind))),
+        },
+        Some(Value::Mapping(map)) if map.len() == 1 => {
+            let (kind, options) = map.into_iter().next().unwrap();
+            match kind.as_str() {
+                Some("docker") => Ok(Some(JobExecutor::Docker)),
+                Some("machine") => serde_yaml::from_value(options)
+                    .map(|machine: Option<MachineExecutor>| {
+                        Some(JobExecutor::Machine(machine.unwrap_or_default()))
+                    })
+                    .map_err(de::Error::custom),
+                Some("macos") => serde_yaml::from_value(options)
+                    .map(|macos| Some(JobExecutor::Macos(macos)))
+                    .map_err(de::Error::custom),
+                _ => Err(de::Error::custom(format!(
+                    "unknown executor {kind:?} (expected docker, machine, or macos)"
+                ))),
+            }
+        }
+        Some(other) => Err(de::Error::custom(format!(
+            "executor must be docker, machine, an executor name, or a mapping with a single machine or macos key, got {other:?}"
+        ))),
+    }
+}
+
 fn default_image() -> String {
     "ubuntu-latest".to_string()
 }
@@ -169,6 +637,7 @@ fn default_image() -> String {
 /// Matrix configuration
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(untagged)]
+#[non_exhaustive]
 pub enum JobMatrix {
     /// Standard Cartesian dimensions: { key: [v1, v2] }
     Dimensions(HashMap<String, Vec<String>>),
@@ -183,6 +652,158 @@ pub enum MatrixDimension {
     List(Vec<String>),
 }
 
+/// Remote Docker engine settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct RemoteDocker {
+    /// Docker engine version (e.g. "24.0"); the provider default when unset
+    #[serde(default)]
+    pub version: Option<String>,
+
+    /// Reuse image layers between runs
+    #[serde(default)]
+    pub layer_caching: bool,
+}
+
+/// Where a job's steps run. `docker` runs `image` as the primary container.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobExecutor {
+    Docker,
+    Machine(MachineExecutor),
+    Macos(MacosExecutor),
+    /// An executor defined under the config's top-level `executors:`
+    Named(String),
+}
+
+impl JobExecutor {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Docker => "docker",
+            Self::Machine(_) => "machine",
+            Self::Macos(_) => "macos",
+            Self::Named(_) => "named",
+        }
+    }
+}
+
+/// A named executor supplies the job's image, so a job can't set both.
+/// Checked on the raw job mapping because `image` has a default.
+pub fn check_executor_conflict(job: &Value) -> anyhow::Result<()> {
+    let executor = job.get("executor").and_then(Value::as_str);
+    if let Some(name) = executor.filter(|name| !matches!(*name, "docker" | "machine" | "macos"))
+        && job.get("image").is_some()
+    {
+        anyhow::bail!(
+            "Job sets both image and executor '{name}'; the executor defines the image, so remove one of them"
+        );
+    }
+    Ok(())
+}
+
+/// `test_splitting` needs something to split across and somewhere to put the files
+pub fn check_test_splitting(job: &Value) -> anyhow::Result<()> {
+    let Some(splitting) = job.get("test_splitting") else {
+        return Ok(());
+    };
+    let parallelism = job.get("parallelism").and_then(Value::as_u64).unwrap_or(1);
+    if parallelism < 2 {
+        anyhow::bail!("Job sets test_splitting, so it needs parallelism: 2 or more");
+    }
+    if let Some(template) = splitting.get("command_template").and_then(Value::as_str)
+        && !template.contains("{files}")
+    {
+        anyhow::bail!("test_splitting command_template must contain {{files}}");
+    }
+    Ok(())
+}
+
+/// Prefix of a `needs` entry that names a group of jobs
+pub const GROUP_NEED_PREFIX: &str = "group:";
+
+/// The group a `group:<name>` need refers to, or `None` for a job need
+pub fn group_need(need: &str) -> Option<&str> {
+    need.strip_prefix(GROUP_NEED_PREFIX)
+}
+
+/// `cleanup` entries are plain run steps; `cleanup_on` decides when they run,
+/// so they can't carry their own `if`
+pub fn check_cleanup(job: &Value) -> anyhow::Result<()> {
+    let Some(Value::Sequence(steps)) = job.get("cleanup") else {
+        return Ok(());
+    };
+    for step in steps {
+        if step.get("run").is_none() {
+            anyhow::bail!(
+                "cleanup steps must be run steps, found {}",
+                step_summary(step)
+            );
+        }
+        if step.get("if").is_some() {
+            anyhow::bail!(
+                "cleanup steps can't use `if`; set cleanup_on: failure to run them only when the job fails"
+            );
+        }
+    }
+    Ok(())
+}
+
+fn step_summary(step: &Value) -> String {
+    match step {
+        Value::Mapping(map) => map
+            .keys()
+            .next()
+            .and_then(Value::as_str)
+            .map(|key| format!("a `{key}` step"))
+            .unwrap_or_else(|| "an empty step".to_string()),
+        Value::String(name) => format!("'{name}'"),
+        _ => "a step that is not a mapping".to_string(),
+    }
+}
+
+/// The regex of a `/regex/` branch pattern, or `None` for a branch name
+pub fn branch_regex(pattern: &str) -> Option<&str> {
+    pattern
+        .strip_prefix('/')
+        .and_then(|rest| rest.strip_suffix('/'))
+        .filter(|regex| !regex.is_empty())
+}
+
+/// `branches` entries must be non-empty, and `/regex/` patterns must compile
+pub fn check_branches(job: &Value) -> anyhow::Result<()> {
+    let patterns = match job.get("branches") {
+        None | Some(Value::Null) => return Ok(()),
+        Some(Value::String(pattern)) => vec![pattern.as_str()],
+        Some(Value::Sequence(items)) => items.iter().filter_map(Value::as_str).collect(),
+        Some(_) => anyhow::bail!("branches must be a branch name or a list of them"),
+    };
+    for pattern in patterns {
+        if pattern.trim().is_empty() {
+            anyhow::bail!("branches can't contain an empty branch name");
+        }
+        if let Some(regex) = branch_regex(pattern) {
+            regex::Regex::new(&format!("^(?:{regex})$")).map_err(|error| {
+                anyhow::anyhow!("branches pattern '{pattern}' is not a valid regex: {error}")
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Linux VM executor
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct MachineExecutor {
+    /// VM image (e.g. "ubuntu-2204:current"); the provider default when unset
+    #[serde(default)]
+    pub image: Option<String>,
+}
+
+/// macOS VM executor
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MacosExecutor {
+    /// Xcode version (e.g. "15.3")
+    pub xcode: String,
+}
+
 /// Skip conditions
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct SkipConditions {
@@ -251,6 +872,54 @@ steps:
         assert_eq!(job.steps.len(), 1);
     }
 
+    #[test]
+    fn test_checkout_false() {
+        let job: Job = serde_yaml::from_str("checkout: false\nsteps: []\n").unwrap();
+        assert_eq!(
+            job.checkout,
+            Some(HashMap::from([("enabled".to_string(), Value::Bool(false))]))
+        );
+    }
+
+    #[test]
+    fn test_cache_forms_survive_round_trip() {
+        let yaml = r#"
+cache:
+  gems: null
+  assets: public/assets
+  node_modules:
+    save: false
+    save_when: always
+"#;
+
+        let job: Job = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(job.cache.len(), 3);
+        assert_eq!(job.cache[1].paths, vec!["public/assets"]);
+        assert!(!job.cache[2].save);
+
+        let reparsed: Job = serde_yaml::from_str(&serde_yaml::to_string(&job).unwrap()).unwrap();
+        assert_eq!(reparsed.cache, job.cache);
+    }
+
+    #[test]
+    fn test_cache_save_when() {
+        for (value, expected) in [
+            ("always", SaveWhen::Always),
+            ("on_success", SaveWhen::OnSuccess),
+            ("on_fail", SaveWhen::OnFailure),
+            ("on_failure", SaveWhen::OnFailure),
+        ] {
+            let yaml = format!("cache:\n  gems:\n    save_when: {value}\n");
+            let job: Job = serde_yaml::from_str(&yaml).unwrap();
+            assert_eq!(job.cache[0].save_when, Some(expected));
+        }
+
+        let error = serde_yaml::from_str::<Job>("cache:\n  gems:\n    save_when: sometimes\n")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("unknown variant `sometimes`"), "{error}");
+    }
+
     #[test]
     fn test_packages_string() {
         let yaml = r#"
@@ -305,6 +974,78 @@ matrix:
         }
     }
 
+    #[test]
+    fn test_executor_shapes() {
+        let job: Job = serde_yaml::from_str("executor: machine\n").unwrap();
+        assert_eq!(
+            job.executor,
+            Some(JobExecutor::Machine(MachineExecutor::default()))
+        );
+
+        let job: Job =
+            serde_yaml::from_str("executor:\n  machine:\n    image: ubuntu-2204:current\n")
+                .unwrap();
+        assert_eq!(
+            job.executor,
+            Some(JobExecutor::Machine(MachineExecutor {
+                image: Some("ubuntu-2204:current".to_string())
+            }))
+        );
+
+        let job: Job = serde_yaml::from_str("executor:\n  macos:\n    xcode: \"15.3\"\n").unwrap();
+        assert_eq!(
+            job.executor,
+            Some(JobExecutor::Macos(MacosExecutor {
+                xcode: "15.3".to_string()
+            }))
+        );
+
+        let error = serde_yaml::from_str::<Job>("executor: macos\n").unwrap_err();
+        assert!(
+            error.to_string().contains("needs an Xcode version"),
+            "{error}"
+        );
+        let job: Job = serde_yaml::from_str("executor: ruby_large\n").unwrap();
+        assert_eq!(
+            job.executor,
+            Some(JobExecutor::Named("ruby_large".to_string()))
+        );
+
+        let conflict: Value =
+            serde_yaml::from_str("image: cimg/ruby:3.3\nexecutor: ruby_large\n").unwrap();
+        let error = check_executor_conflict(&conflict).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("sets both image and executor 'ruby_large'"),
+            "{error}"
+        );
+        let machine: Value =
+            serde_yaml::from_str("image: cimg/ruby:3.3\nexecutor: machine\n").unwrap();
+        assert!(check_executor_conflict(&machine).is_ok());
+    }
+
+    #[test]
+    fn test_test_splitting() {
+        let job: Job = serde_yaml::from_str(
+            "parallelism: 2\ntest_splitting:\n  glob: \"spec/**/*_spec.rb\"\n  command_template: bundle exec rspec {files}\n",
+        )
+        .unwrap();
+        let splitting = job.test_splitting.unwrap();
+        assert_eq!(splitting.by, SplitBy::Timings);
+        assert_eq!(splitting.glob, "spec/**/*_spec.rb");
+
+        let no_files: Value = serde_yaml::from_str(
+            "parallelism: 2\ntest_splitting:\n  glob: \"spec/**/*_spec.rb\"\n  command_template: bundle exec rspec\n",
+        )
+        .unwrap();
+        let error = check_test_splitting(&no_files).unwrap_err();
+        assert!(
+            error.to_string().contains("must contain {files}"),
+            "{error}"
+        );
+    }
+
     #[test]
     fn test_job_with_services() {
         let yaml = r#"
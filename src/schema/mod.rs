@@ -1,16 +1,73 @@
 /// CIGen schema types for cigen.yml
 ///
 /// This module defines the data structures for parsing and validating cigen.yml configuration files.
+mod bundled;
+mod cache_path;
+mod cloud_auth;
 mod command;
+mod condition;
 mod config;
+mod docker_build;
+mod instrumentation;
 mod job;
+mod output;
+mod resource_class;
+mod service;
+mod shell;
 mod step;
+mod step_shape;
+mod suggest;
 mod workflow;
+mod yaml;
 
-pub use command::{CommandDefinition, CommandParameter};
-pub use config::{CacheDefinition, CigenConfig, ProjectConfig, RunnerDefinition};
-pub use job::{Job, JobMatrix, JobTrigger, MatrixDimension, PackageSpec, SkipConditions};
+pub use bundled::{
+    BUNDLED_PROVIDERS, BundledSchema, CIGEN_SCHEMAS, CIRCLECI_SCHEMA_URL, GITHUB_ACTION_SCHEMA_URL,
+    GITHUB_ACTIONS_SCHEMA_URL, provider_schema, schema_comment,
+};
+pub use cache_path::normalize_cache_path;
+pub use cloud_auth::{AwsAuth, CloudAuth, GcpAuth, WorkloadIdentityProvider, check_cloud_auth};
+pub use command::{
+    CommandDefinition, CommandParameter, STEPS_PARAMETER_TYPE, steps_parameter_reference,
+};
+pub use condition::{Comparison, Condition, Literal, is_condition_expression};
+pub use config::{
+    CACHE_KEY_ARCH, CacheDefinition, CacheWarmup, CigenConfig, GlobalSteps, Hooks, JobGroup,
+    Notifications, NotifyEvent, PackageManagerDefinition, ProjectConfig, ProjectDetection,
+    ProjectTool, RESERVED_CACHE_NAMES, RunnerDefinition, SlackNotification, VersionSource,
+    cache_key_checksum_sources, map_cache_key_checksum_sources, versioned_cache_key,
+};
+pub use docker_build::{DockerBuildConfig, DockerImage, DockerRegistry, RegistryLogin};
+pub use instrumentation::{Instrumentation, STEP_TIMINGS_LOG, default_step_name, timed_command};
+pub use job::{
+    CleanupOn, GROUP_NEED_PREFIX, Job, JobCache, JobExecutor, JobMatrix, JobTrigger,
+    MachineExecutor, MacosExecutor, MatrixDimension, PackageSpec, RemoteDocker,
+    SUBMODULE_COMMIT_DIR, SaveWhen, SkipConditions, SplitBy, TestSplitting, branch_regex,
+    check_branches, check_cleanup, check_executor_conflict, check_test_splitting, group_need,
+    split_need, submodule_commit_file,
+};
+pub use output::{
+    OUTPUT_DIR_FLAG, OutputConfig, WORKFLOW_PLACEHOLDER, check_output_filename, output_file_path,
+    resolve_output_path,
+};
+pub use resource_class::{
+    CIRCLECI_RESOURCE_CLASSES, is_self_hosted_resource_class, resource_class_weight,
+    unknown_resource_class_message,
+};
+pub use service::{
+    DEFAULT_WAIT_TIMEOUT_SECS, ServicePort, ServiceWait, parse_service_ports,
+    wait_for_service_command,
+};
+pub use shell::shell_quote;
 pub use step::{
-    Artifact, RestoreCacheDefinition, RunStepOptions, SaveCacheDefinition, Step, UsesStep,
+    Artifact, RestoreCacheDefinition, RetryKind, RetryPolicy, RetryWhen, RunStepOptions,
+    SaveCacheDefinition, Step, UsesStep,
+};
+pub use step_shape::{BUILTIN_STEPS, StepShapeError, check_step, check_steps};
+pub use suggest::{did_you_mean, unknown_reference_help, unknown_reference_message};
+pub use workflow::{
+    DEFAULT_WORKFLOW, StageDefinition, WorkflowCondition, WorkflowConditionKind, WorkflowConfig,
+    WorkflowJobSteps,
+};
+pub use yaml::{
+    ExpandedValueError, expand_merge_keys, from_expanded_value, parse_yaml, parse_yaml_value,
 };
-pub use workflow::{StageDefinition, WorkflowCondition, WorkflowConditionKind, WorkflowConfig};
@@ -0,0 +1,239 @@
+//! Where each provider's generated files are written
+//!
+//! A provider writes into its own directory (`.circleci`, `.github/workflows`,
+//! `.woodpecker`) unless `output_path` names another, and names its entry file
+//! after `output_filename`: CircleCI's setup config (`config.yml`), or each
+//! workflow's file on GitHub Actions and Woodpecker, where `{workflow}` is
+//! replaced with the workflow id. Both can be set at the root of the config or
+//! in a provider's section (`circleci:`, `github:`).
+//!
+//! `cigen generate --output DIR` writes everything under DIR. Plugins get DIR
+//! as the [`OUTPUT_DIR_FLAG`] plan flag, so paths they embed in the generated
+//! configs (like CircleCI's continuation config path) match where the files
+//! end up.
+
+use anyhow::{Result, bail};
+use serde_yaml::Value;
+use std::path::{Path, PathBuf};
+
+/// Plan flag carrying `cigen generate --output`
+pub const OUTPUT_DIR_FLAG: &str = "output_dir";
+
+/// Placeholder replaced with the workflow id in output file names
+pub const WORKFLOW_PLACEHOLDER: &str = "{workflow}";
+
+/// `output_path` and `output_filename` as a provider sees them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputConfig {
+    /// Directory the provider writes into, relative to the project root
+    pub path: Option<String>,
+    /// Name of the entry file, relative to `path`
+    pub filename: Option<String>,
+}
+
+impl OutputConfig {
+    pub fn from_raw_config(raw_config: &Value) -> Result<Self> {
+        let setting = |key: &str| -> Result<Option<String>> {
+            match raw_config.get(key) {
+                None | Some(Value::Null) => Ok(None),
+                Some(Value::String(value)) => Ok(Some(value.clone())),
+                Some(_) => bail!("`{key}` must be a string"),
+            }
+        };
+        let config = Self {
+            path: setting("output_path")?,
+            filename: setting("output_filename")?,
+        };
+        if let Some(path) = &config.path {
+            check_relative_path("output_path", path)?;
+        }
+        if let Some(filename) = &config.filename {
+            check_output_filename("output_filename", filename)?;
+        }
+        Ok(config)
+    }
+
+    /// Check that providers writing one file per workflow give each workflow
+    /// its own file
+    pub fn check_per_workflow(&self, workflows: &[&str]) -> Result<()> {
+        if let Some(filename) = &self.filename
+            && !filename.contains(WORKFLOW_PLACEHOLDER)
+            && let [first, second, ..] = workflows
+        {
+            bail!(
+                "`output_filename` '{filename}' has no {WORKFLOW_PLACEHOLDER}, so workflows '{first}' and '{second}' would overwrite each other"
+            );
+        }
+        Ok(())
+    }
+
+    /// The same directory with another file in it, e.g. CircleCI's continued
+    /// config beside its setup config
+    pub fn with_filename(&self, filename: &str) -> Self {
+        Self {
+            path: self.path.clone(),
+            filename: Some(filename.to_string()),
+        }
+    }
+}
+
+/// Check a file name setting: relative, and inside the output directory
+pub fn check_output_filename(key: &str, filename: &str) -> Result<()> {
+    check_relative_path(key, filename)?;
+    if filename.is_empty() || filename.ends_with('/') {
+        bail!("`{key}` '{filename}' must name a file");
+    }
+    Ok(())
+}
+
+fn check_relative_path(key: &str, path: &str) -> Result<()> {
+    if path.starts_with('/') || path.split('/').any(|part| part == "..") {
+        bail!("`{key}` '{path}' must be a path inside the project");
+    }
+    Ok(())
+}
+
+/// Directory and entry file name `provider` uses when the config sets neither
+fn provider_defaults(provider: &str) -> (&'static str, &'static str) {
+    match provider {
+        "circleci" => (".circleci", "config.yml"),
+        "github" | "github-actions" => (".github/workflows", "{workflow}.yml"),
+        "woodpecker" => (".woodpecker", "{workflow}.yaml"),
+        _ => (".", "{workflow}.yml"),
+    }
+}
+
+/// Path of `provider`'s file for `workflow`, relative to the project root.
+/// With `cli_output` (`generate --output`), the path it is written to under
+/// that directory instead, for paths embedded in the generated configs.
+pub fn resolve_output_path(
+    provider: &str,
+    workflow: &str,
+    output: &OutputConfig,
+    cli_output: Option<&str>,
+) -> String {
+    let (default_dir, default_filename) = provider_defaults(provider);
+    let filename = output
+        .filename
+        .as_deref()
+        .unwrap_or(default_filename)
+        .replace(WORKFLOW_PLACEHOLDER, workflow);
+    let dir = output.path.as_deref().unwrap_or(default_dir);
+    let path: PathBuf = Path::new(dir)
+        .join(filename)
+        .components()
+        .filter(|component| !matches!(component, std::path::Component::CurDir))
+        .collect();
+    let path = path.to_string_lossy().into_owned();
+    match cli_output {
+        Some(output_dir) => output_file_path(Path::new(output_dir), &path)
+            .to_string_lossy()
+            .into_owned(),
+        None => path,
+    }
+}
+
+/// Where a generated file at `path` is written under `output_dir`. A first
+/// component repeating the directory's name isn't repeated, so `--output
+/// .circleci` writes `.circleci/config.yml`.
+pub fn output_file_path(output_dir: &Path, path: &str) -> PathBuf {
+    let mut relative_path = PathBuf::from(path);
+
+    if output_dir.as_os_str() != "."
+        && relative_path.is_relative()
+        && let Some(output_name) = output_dir.file_name()
+        && let Ok(stripped) = relative_path.strip_prefix(output_name)
+    {
+        relative_path = stripped.to_path_buf();
+    }
+
+    if output_dir.as_os_str() == "." {
+        relative_path
+    } else if relative_path.as_os_str().is_empty() {
+        output_dir.to_path_buf()
+    } else {
+        output_dir.join(&relative_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(yaml: &str) -> OutputConfig {
+        OutputConfig::from_raw_config(&serde_yaml::from_str(yaml).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn paths_follow_provider_defaults_and_config() {
+        let default = OutputConfig::default();
+        assert_eq!(
+            resolve_output_path("circleci", "ci", &default, None),
+            ".circleci/config.yml"
+        );
+        assert_eq!(
+            resolve_output_path("github", "deploy", &default, None),
+            ".github/workflows/deploy.yml"
+        );
+        assert_eq!(
+            resolve_output_path("woodpecker", "ci", &default, None),
+            ".woodpecker/ci.yaml"
+        );
+
+        let configured = output("output_path: ./build\noutput_filename: \"{workflow}_ci.yml\"\n");
+        assert_eq!(
+            resolve_output_path("github", "deploy", &configured, None),
+            "build/deploy_ci.yml"
+        );
+        assert_eq!(
+            resolve_output_path(
+                "circleci",
+                "ci",
+                &configured.with_filename("main.yml"),
+                None
+            ),
+            "build/main.yml"
+        );
+    }
+
+    #[test]
+    fn cli_output_moves_the_written_path() {
+        let default = OutputConfig::default();
+        assert_eq!(
+            resolve_output_path("circleci", "ci", &default, Some("out")),
+            "out/.circleci/config.yml"
+        );
+        // The provider directory isn't repeated under an output dir of the same name
+        assert_eq!(
+            resolve_output_path(
+                "circleci",
+                "ci",
+                &default.with_filename("main.yml"),
+                Some(".circleci")
+            ),
+            ".circleci/main.yml"
+        );
+        assert_eq!(
+            resolve_output_path("github", "ci", &default, Some(".")),
+            ".github/workflows/ci.yml"
+        );
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_project() {
+        let raw: Value = serde_yaml::from_str("output_path: ../elsewhere\n").unwrap();
+        let error = OutputConfig::from_raw_config(&raw).unwrap_err().to_string();
+        assert!(error.contains("inside the project"), "{error}");
+
+        let raw: Value = serde_yaml::from_str("output_filename: 3\n").unwrap();
+        assert!(OutputConfig::from_raw_config(&raw).is_err());
+
+        let shared = output("output_filename: ci.yml\n");
+        assert!(shared.check_per_workflow(&["ci"]).is_ok());
+        let error = shared
+            .check_per_workflow(&["ci", "deploy"])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("would overwrite each other"), "{error}");
+    }
+}
@@ -0,0 +1,129 @@
+//! CircleCI resource classes jobs can request with `resource_class:`
+
+use super::suggest::did_you_mean;
+
+/// CircleCI's documented resource classes with rough credits per minute, for
+/// docker, machine (Linux, Windows, GPU), and macOS executors
+pub const CIRCLECI_RESOURCE_CLASSES: &[(&str, u64)] = &[
+    ("small", 5),
+    ("medium", 10),
+    ("medium+", 15),
+    ("large", 20),
+    ("xlarge", 40),
+    ("2xlarge", 80),
+    ("2xlarge+", 100),
+    ("arm.medium", 10),
+    ("arm.large", 20),
+    ("arm.xlarge", 40),
+    ("arm.2xlarge", 80),
+    ("gpu.nvidia.small", 160),
+    ("gpu.nvidia.small.gen2", 160),
+    ("gpu.nvidia.small.multi", 320),
+    ("gpu.nvidia.medium", 240),
+    ("gpu.nvidia.medium.multi", 480),
+    ("gpu.nvidia.large", 1000),
+    ("windows.medium", 40),
+    ("windows.large", 120),
+    ("windows.xlarge", 210),
+    ("windows.2xlarge", 500),
+    ("windows.gpu.nvidia.medium", 500),
+    ("macos.m1.medium.gen1", 150),
+    ("macos.m1.large.gen1", 250),
+    ("macos.m2pro.medium", 150),
+    ("macos.m2pro.large", 200),
+    ("macos.m4pro.medium", 200),
+    ("macos.m4pro.large", 400),
+];
+
+/// Rough credits per minute for a known resource class
+pub fn resource_class_weight(class: &str) -> Option<u64> {
+    CIRCLECI_RESOURCE_CLASSES
+        .iter()
+        .find(|(known, _)| *known == class)
+        .map(|(_, weight)| *weight)
+}
+
+/// Self-hosted runner resource classes are `<namespace>/<name>`
+pub fn is_self_hosted_resource_class(class: &str) -> bool {
+    let valid = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    class
+        .split_once('/')
+        .is_some_and(|(namespace, name)| valid(namespace) && valid(name))
+}
+
+/// Error for a `resource_class` that isn't a CircleCI class, a self-hosted
+/// runner class, a `resource_classes` alias, or a pipeline parameter
+pub fn unknown_resource_class_message<'a, I>(class: &str, aliases: I) -> Option<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let aliases: Vec<&str> = aliases.into_iter().collect();
+    if resource_class_weight(class).is_some()
+        || is_self_hosted_resource_class(class)
+        || aliases.contains(&class)
+        || class.contains("<<")
+    {
+        return None;
+    }
+
+    let candidates = CIRCLECI_RESOURCE_CLASSES
+        .iter()
+        .map(|(known, _)| *known)
+        .chain(aliases.iter().copied());
+    let mut message = format!("Unknown resource_class '{class}'");
+    if let Some(suggestion) = did_you_mean(class, candidates) {
+        message.push_str(&format!(". Did you mean '{suggestion}'?"));
+    }
+    message.push_str(
+        "\nSelf-hosted runner classes are <namespace>/<name>; set allow_custom_resource_classes: true to allow other values",
+    );
+    Some(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_classes_and_aliases_are_valid() {
+        for class in [
+            "medium",
+            "arm.large",
+            "macos.m1.medium.gen1",
+            "windows.medium",
+        ] {
+            assert_eq!(unknown_resource_class_message(class, []), None, "{class}");
+        }
+        assert_eq!(unknown_resource_class_message("build", ["build"]), None);
+        assert_eq!(
+            unknown_resource_class_message("<< parameters.size >>", []),
+            None
+        );
+    }
+
+    #[test]
+    fn typos_suggest_the_closest_class() {
+        let message = unknown_resource_class_message("meduim", []).unwrap();
+        assert!(
+            message.starts_with("Unknown resource_class 'meduim'. Did you mean 'medium'?"),
+            "{message}"
+        );
+        let message = unknown_resource_class_message("arm.lage", []).unwrap();
+        assert!(message.contains("Did you mean 'arm.large'?"), "{message}");
+    }
+
+    #[test]
+    fn self_hosted_classes_are_namespaced() {
+        assert!(is_self_hosted_resource_class("acme/arm-runner"));
+        assert!(is_self_hosted_resource_class("docspring/linux.large"));
+        assert!(!is_self_hosted_resource_class("acme/"));
+        assert!(!is_self_hosted_resource_class("acme/arm/runner"));
+        assert!(!is_self_hosted_resource_class("large"));
+        assert_eq!(unknown_resource_class_message("acme/arm-runner", []), None);
+    }
+}
@@ -0,0 +1,203 @@
+//! Structured fields of top-level `services:` definitions that providers share
+
+use anyhow::{Context, Result, bail};
+use serde_yaml::Value;
+use std::fmt;
+
+/// How long a `wait_for` step waits for a service by default
+pub const DEFAULT_WAIT_TIMEOUT_SECS: u64 = 60;
+
+/// A port from a service's `ports:` list: `5432`, `"15432:5432"`, or
+/// `"127.0.0.1:15432:5432/tcp"`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServicePort {
+    /// Port published on the host, when one is given
+    pub host: Option<u16>,
+    /// Port the service listens on inside its container
+    pub container: u16,
+}
+
+impl ServicePort {
+    pub fn parse(value: &Value) -> Result<Self> {
+        let text = match value {
+            Value::Number(number) => number.to_string(),
+            Value::String(text) => text.clone(),
+            _ => bail!("Invalid port {value:?}; expected a number or \"host:container\""),
+        };
+        let spec = text.split('/').next().unwrap_or_default();
+        let port = |part: &str| {
+            part.trim()
+                .parse::<u16>()
+                .with_context(|| format!("Invalid port '{text}'"))
+        };
+        let (host, container) = match spec.rsplit_once(':') {
+            Some((host, container)) => {
+                // An IP to bind to can come first
+                let host = host.rsplit(':').next().unwrap_or(host);
+                (Some(port(host)?), port(container)?)
+            }
+            None => (None, port(spec)?),
+        };
+        Ok(Self { host, container })
+    }
+}
+
+impl fmt::Display for ServicePort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.host {
+            Some(host) => write!(f, "{host}:{}", self.container),
+            None => write!(f, "{}", self.container),
+        }
+    }
+}
+
+/// Every port in a service's `ports:` value (a list or a single port)
+pub fn parse_service_ports(service: &str, value: Option<&Value>) -> Result<Vec<ServicePort>> {
+    let ports = match value {
+        None | Some(Value::Null) => return Ok(Vec::new()),
+        Some(Value::Sequence(ports)) => ports.iter().map(ServicePort::parse).collect(),
+        Some(port) => ServicePort::parse(port).map(|port| vec![port]),
+    };
+    ports.with_context(|| format!("Invalid ports for service '{service}'"))
+}
+
+/// `wait_for:` on a service: jobs using it wait until its ports accept connections
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServiceWait {
+    pub timeout_secs: u64,
+}
+
+impl ServiceWait {
+    /// `wait_for: true`, or `wait_for: { timeout: 90s }` (seconds, or a number
+    /// with an `s` or `m` suffix)
+    pub fn parse(service: &str, value: Option<&Value>) -> Result<Option<Self>> {
+        let timeout = match value {
+            None | Some(Value::Null) | Some(Value::Bool(false)) => return Ok(None),
+            Some(Value::Bool(true)) => None,
+            Some(Value::Mapping(options)) => options.get("timeout"),
+            Some(other) => bail!(
+                "Invalid wait_for for service '{service}': expected true or {{ timeout: <duration> }}, got {other:?}"
+            ),
+        };
+        let timeout_secs = match timeout {
+            None => DEFAULT_WAIT_TIMEOUT_SECS,
+            Some(Value::Number(seconds)) => seconds.as_u64().with_context(|| {
+                format!("Invalid wait_for timeout for service '{service}': {seconds}")
+            })?,
+            Some(Value::String(duration)) => parse_duration_secs(duration).with_context(|| {
+                format!("Invalid wait_for timeout for service '{service}': '{duration}'")
+            })?,
+            Some(other) => bail!("Invalid wait_for timeout for service '{service}': {other:?}"),
+        };
+        Ok(Some(Self { timeout_secs }))
+    }
+}
+
+fn parse_duration_secs(duration: &str) -> Result<u64> {
+    let duration = duration.trim();
+    let (number, unit) = match duration.strip_suffix('m') {
+        Some(minutes) => (minutes, 60),
+        None => (duration.strip_suffix('s').unwrap_or(duration), 1),
+    };
+    Ok(number.trim().parse::<u64>()? * unit)
+}
+
+/// Shell commands that poll `host` on each port until it accepts TCP
+/// connections, failing after the timeout. `ports` can hold expressions the
+/// provider expands. Needs bash for `/dev/tcp`.
+pub fn wait_for_service_command(
+    service: &str,
+    host: &str,
+    ports: &[String],
+    wait: ServiceWait,
+) -> String {
+    let mut lines = vec![format!("deadline=$((SECONDS + {}))", wait.timeout_secs)];
+    for port in ports {
+        lines.extend([
+            format!("echo \"Waiting for {service} on {host}:{port}\""),
+            format!("until (echo > /dev/tcp/{host}/{port}) >/dev/null 2>&1; do"),
+            "  if [ \"$SECONDS\" -ge \"$deadline\" ]; then".to_string(),
+            format!(
+                "    echo \"{service} didn't accept connections on {host}:{port} within {}s\" >&2",
+                wait.timeout_secs
+            ),
+            "    exit 1".to_string(),
+            "  fi".to_string(),
+            "  sleep 1".to_string(),
+            "done".to_string(),
+        ]);
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(text: &str) -> Value {
+        serde_yaml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn ports_parse_host_and_container() {
+        assert_eq!(
+            parse_service_ports(
+                "db",
+                Some(&yaml("[5432, \"15432:5432\", \"127.0.0.1:6380:6379/tcp\"]"))
+            )
+            .unwrap(),
+            [
+                ServicePort {
+                    host: None,
+                    container: 5432
+                },
+                ServicePort {
+                    host: Some(15432),
+                    container: 5432
+                },
+                ServicePort {
+                    host: Some(6380),
+                    container: 6379
+                },
+            ]
+        );
+        assert_eq!(
+            ServicePort::parse(&yaml("\"15432:5432\""))
+                .unwrap()
+                .to_string(),
+            "15432:5432"
+        );
+        let error = format!(
+            "{:#}",
+            parse_service_ports("db", Some(&yaml("[postgres]"))).unwrap_err()
+        );
+        assert_eq!(
+            error,
+            "Invalid ports for service 'db': Invalid port 'postgres': invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn wait_for_takes_a_flag_or_a_timeout() {
+        let wait = |text: &str| ServiceWait::parse("db", Some(&yaml(text)));
+        assert_eq!(wait("false").unwrap(), None);
+        assert_eq!(
+            wait("true").unwrap(),
+            Some(ServiceWait { timeout_secs: 60 })
+        );
+        assert_eq!(
+            wait("{timeout: 90s}").unwrap(),
+            Some(ServiceWait { timeout_secs: 90 })
+        );
+        assert_eq!(
+            wait("{timeout: 2m}").unwrap(),
+            Some(ServiceWait { timeout_secs: 120 })
+        );
+        assert_eq!(
+            wait("{timeout: 30}").unwrap(),
+            Some(ServiceWait { timeout_secs: 30 })
+        );
+        assert!(wait("{timeout: soon}").is_err());
+        assert!(wait("yes please").is_err());
+    }
+}
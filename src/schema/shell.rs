@@ -0,0 +1,17 @@
+//! Quoting for values spliced into generated shell commands
+
+/// `value` as one single-quoted shell word, with no expansion inside it
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_survive_single_quotes_and_expansions() {
+        assert_eq!(shell_quote("rspec"), "'rspec'");
+        assert_eq!(shell_quote("it's $HOME"), r"'it'\''s $HOME'");
+    }
+}
@@ -1,34 +1,86 @@
 use serde::{Deserialize, Serialize};
-use serde_yaml::Value;
+use serde_yaml::{Mapping, Value};
 use std::collections::HashMap;
 
 /// Job step
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(untagged)]
+#[non_exhaustive]
 pub enum Step {
     /// Uses a module
     Uses(UsesStep),
 
     /// Simple run command
-    SimpleRun { run: String },
+    SimpleRun {
+        run: String,
+        #[serde(default, rename = "if", skip_serializing_if = "Option::is_none")]
+        condition: Option<String>,
+    },
 
     /// Run step with options (matches { run: { name, command } })
-    RunWithOptions { run: RunStepOptions },
+    RunWithOptions {
+        run: RunStepOptions,
+        #[serde(default, rename = "if", skip_serializing_if = "Option::is_none")]
+        condition: Option<String>,
+    },
 
     /// CircleCI restore_cache step
     RestoreCache {
         restore_cache: RestoreCacheDefinition,
+        #[serde(default, rename = "if", skip_serializing_if = "Option::is_none")]
+        condition: Option<String>,
     },
 
     /// CircleCI save_cache step
-    SaveCache { save_cache: SaveCacheDefinition },
+    SaveCache {
+        save_cache: SaveCacheDefinition,
+        #[serde(default, rename = "if", skip_serializing_if = "Option::is_none")]
+        condition: Option<String>,
+    },
 
     /// Any other step type - preserved as raw YAML value
     Custom(Value),
 }
 
+impl Step {
+    /// The step's `if:` condition, whether written on the step or inside `run:`
+    pub fn condition(&self) -> Option<&str> {
+        match self {
+            Step::Uses(uses) => uses.condition.as_deref(),
+            Step::SimpleRun { condition, .. }
+            | Step::RestoreCache { condition, .. }
+            | Step::SaveCache { condition, .. } => condition.as_deref(),
+            Step::RunWithOptions { run, condition } => {
+                condition.as_deref().or(run.condition.as_deref())
+            }
+            Step::Custom(Value::Mapping(map)) if map.len() > 1 => {
+                map.get("if").and_then(Value::as_str)
+            }
+            Step::Custom(_) => None,
+        }
+    }
+
+    /// The step name and arguments of a step that isn't a run, uses, or cache
+    /// step: a built-in like `checkout`, a command, or an orb's `alias/command`.
+    /// `None` for `- << parameters.name >>` splices.
+    pub fn invocation(&self) -> Option<(&str, Option<&Mapping>)> {
+        match self {
+            Step::Custom(Value::String(name)) => {
+                (!name.trim_start().starts_with("<<")).then_some((name.as_str(), None))
+            }
+            Step::Custom(Value::Mapping(map)) => map.iter().find_map(|(key, value)| {
+                key.as_str()
+                    .filter(|key| *key != "if")
+                    .map(|key| (key, value.as_mapping()))
+            }),
+            _ => None,
+        }
+    }
+}
+
 /// Run step options (for complex run steps)
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[non_exhaustive]
 pub struct RunStepOptions {
     /// Step name (optional)
     #[serde(default)]
@@ -44,10 +96,49 @@ pub struct RunStepOptions {
     /// Conditional execution
     #[serde(default, rename = "if")]
     pub condition: Option<String>,
+
+    /// Run the command again when it fails (overrides the job's `retry`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+}
+
+/// `retry:` on a run step or a job: run the command again when it fails
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Attempts after the first one
+    pub max: u32,
+
+    /// Failures worth another attempt; any failure by default
+    #[serde(default = "default_retry_when")]
+    pub when: Vec<RetryWhen>,
+
+    /// Seconds to wait before each retry
+    #[serde(default)]
+    pub backoff_seconds: u32,
+}
+
+fn default_retry_when() -> Vec<RetryWhen> {
+    vec![RetryWhen::Kind(RetryKind::Failure)]
+}
+
+/// An entry of `retry.when`: `failure` for any non-zero exit, or an exit code
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum RetryWhen {
+    Kind(RetryKind),
+    ExitCode(u8),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryKind {
+    Failure,
 }
 
 /// Uses step (module invocation)
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[non_exhaustive]
 pub struct UsesStep {
     /// Module reference with version (e.g., docker/build@>=1.1)
     pub uses: String,
@@ -117,7 +208,7 @@ mod tests {
 
         let step: Step = serde_yaml::from_str(yaml).unwrap();
         match step {
-            Step::SimpleRun { run } => {
+            Step::SimpleRun { run, .. } => {
                 assert_eq!(run, "bundle exec rspec");
             }
             _ => panic!("Expected SimpleRun"),
@@ -134,7 +225,7 @@ run:
 
         let step: Step = serde_yaml::from_str(yaml).unwrap();
         match step {
-            Step::RunWithOptions { run } => {
+            Step::RunWithOptions { run, .. } => {
                 assert_eq!(run.name, Some("Run tests".to_string()));
                 assert_eq!(run.command, "bundle exec rspec");
             }
@@ -184,7 +275,7 @@ restore_cache:
 
         let step: Step = serde_yaml::from_str(yaml).unwrap();
         match step {
-            Step::RestoreCache { restore_cache } => {
+            Step::RestoreCache { restore_cache, .. } => {
                 assert_eq!(restore_cache.name.as_deref(), Some("restore gems"));
                 assert_eq!(restore_cache.keys, vec!["gems-v1"]);
             }
@@ -192,6 +283,21 @@ restore_cache:
         }
     }
 
+    #[test]
+    fn test_step_level_condition() {
+        let step: Step =
+            serde_yaml::from_str("run: ./deploy.sh\nif: branch == \"main\"\n").unwrap();
+        assert_eq!(step.condition(), Some("branch == \"main\""));
+
+        let step: Step =
+            serde_yaml::from_str("run:\n  command: ./notify.sh\n  if: param.notify\n").unwrap();
+        assert_eq!(step.condition(), Some("param.notify"));
+
+        let step: Step =
+            serde_yaml::from_str("store_artifacts:\n  path: log\nif: env.CI defined\n").unwrap();
+        assert_eq!(step.condition(), Some("env.CI defined"));
+    }
+
     #[test]
     fn test_custom_step() {
         let yaml = r#"
@@ -0,0 +1,472 @@
+//! Structural checks for steps, run on the raw YAML before it becomes [`Step`]s
+//!
+//! Steps deserialize leniently: a misspelled key inside `run:` is dropped and
+//! a step no variant matches is kept as a raw value, so a typo like `comand:`
+//! would otherwise only show up when the provider rejects the generated config.
+//!
+//! [`Step`]: super::Step
+
+use serde_yaml::{Mapping, Value};
+
+use super::suggest::did_you_mean;
+
+/// Step kinds with a shape to check, besides `uses`
+const KINDS: [&str; 7] = [
+    "run",
+    "restore_cache",
+    "save_cache",
+    "store_artifacts",
+    "store_test_results",
+    "when",
+    "unless",
+];
+
+/// Keys of a run step's options
+const RUN_KEYS: [&str; 5] = ["name", "command", "env", "if", "retry"];
+
+/// CircleCI run options that are allowed on run steps but not passed on to
+/// providers yet (`cigen migrate` flags them where it keeps them)
+const CIRCLECI_RUN_KEYS: [&str; 7] = [
+    "working_directory",
+    "shell",
+    "background",
+    "no_output_timeout",
+    "when",
+    "max_auto_reruns",
+    "auto_rerun_delay",
+];
+
+/// GitHub Actions step options allowed beside `run` and `uses`. Only `if`
+/// (and `name` on `uses`) is passed on to providers so far; `cigen migrate`
+/// flags the rest where it keeps them.
+const GITHUB_STEP_KEYS: [&str; 8] = [
+    "name",
+    "if",
+    "env",
+    "id",
+    "working-directory",
+    "shell",
+    "timeout-minutes",
+    "continue-on-error",
+];
+
+/// Steps CircleCI provides without a `commands:` declaration
+pub const BUILTIN_STEPS: [&str; 13] = [
+    "run",
+    "checkout",
+    "setup_remote_docker",
+    "save_cache",
+    "restore_cache",
+    "store_artifacts",
+    "store_test_results",
+    "persist_to_workspace",
+    "attach_workspace",
+    "add_ssh_keys",
+    "deploy",
+    "when",
+    "unless",
+];
+
+/// A problem with a step's shape, with the key it's about. Returned as the
+/// error for steps that can't work, and in the warnings [`check_steps`]
+/// returns for keys providers would ignore.
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct StepShapeError {
+    pub message: String,
+    pub key: String,
+    /// The list (`steps` or `cleanup`) and index of the top-level step it's in
+    pub step: Option<(&'static str, usize)>,
+}
+
+impl StepShapeError {
+    fn new(message: impl Into<String>, key: &str) -> Self {
+        Self {
+            message: message.into(),
+            key: key.to_string(),
+            step: None,
+        }
+    }
+}
+
+/// Check the `steps` and `cleanup` of a job or command definition. Returns
+/// the warnings: unknown keys, which providers drop.
+pub fn check_steps(definition: &Value) -> Result<Vec<StepShapeError>, StepShapeError> {
+    let mut warnings = Vec::new();
+    for list in ["steps", "cleanup"] {
+        let Some(Value::Sequence(steps)) = definition.get(list) else {
+            continue;
+        };
+        for (index, step) in steps.iter().enumerate() {
+            let start = warnings.len();
+            check_step_into(step, &mut warnings).map_err(|mut error| {
+                error.step = Some((list, index));
+                error
+            })?;
+            for warning in &mut warnings[start..] {
+                warning.step = Some((list, index));
+            }
+        }
+    }
+    Ok(warnings)
+}
+
+/// Check a step of a known kind (run, uses, the cache steps, store_artifacts,
+/// store_test_results) for the keys it needs and takes, returning the
+/// warnings. Command invocations, orb steps, and plain step names are checked
+/// once the commands are loaded.
+pub fn check_step(step: &Value) -> Result<Vec<StepShapeError>, StepShapeError> {
+    let mut warnings = Vec::new();
+    check_step_into(step, &mut warnings)?;
+    Ok(warnings)
+}
+
+fn check_step_into(step: &Value, warnings: &mut Vec<StepShapeError>) -> Result<(), StepShapeError> {
+    let Value::Mapping(step) = step else {
+        return Ok(());
+    };
+    if step.contains_key("uses") {
+        check_step_keys("uses", step, &["with"], warnings)?;
+        if !matches!(step.get("uses"), Some(Value::String(_))) {
+            return Err(StepShapeError::new(
+                "`uses` must name a module, like `docker/build@1.0`",
+                "uses",
+            ));
+        }
+        return Ok(());
+    }
+
+    let Some((kind, options)) = KINDS
+        .iter()
+        .find_map(|kind| step.get(*kind).map(|options| (*kind, options)))
+    else {
+        return Ok(());
+    };
+    match kind {
+        "run" => {
+            check_step_keys(kind, step, &[], warnings)?;
+            check_run(options, warnings)
+        }
+        "restore_cache" => {
+            check_step_keys(kind, step, &[], warnings)?;
+            let allowed = ["name", "key", "keys", "restore_keys"];
+            let options = check_options(kind, options, &allowed, warnings)?;
+            if !options.contains_key("key") && !options.contains_key("keys") {
+                return Err(
+                    misspelled(kind, options, &["key", "keys"]).unwrap_or_else(|| {
+                        StepShapeError::new("restore_cache step needs `key` or `keys`", kind)
+                    }),
+                );
+            }
+            Ok(())
+        }
+        "save_cache" => {
+            check_step_keys(kind, step, &[], warnings)?;
+            let allowed = ["name", "key", "paths", "when"];
+            let options = check_options(kind, options, &allowed, warnings)?;
+            require(kind, options, "key")?;
+            require(kind, options, "paths")
+        }
+        "store_artifacts" => {
+            check_step_keys(kind, step, &[], warnings)?;
+            let options = check_options(kind, options, &["path", "destination"], warnings)?;
+            require(kind, options, "path")
+        }
+        "store_test_results" => {
+            check_step_keys(kind, step, &[], warnings)?;
+            let options = check_options(kind, options, &["path"], warnings)?;
+            require(kind, options, "path")
+        }
+        "when" | "unless" => match options.get("steps") {
+            Some(Value::Sequence(steps)) => steps
+                .iter()
+                .try_for_each(|step| check_step_into(step, warnings)),
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+fn check_run(options: &Value, warnings: &mut Vec<StepShapeError>) -> Result<(), StepShapeError> {
+    let options = match options {
+        Value::String(_) => return Ok(()),
+        Value::Mapping(options) => options,
+        _ => {
+            return Err(StepShapeError::new(
+                "`run` must be a command, or a mapping with a `command`",
+                "run",
+            ));
+        }
+    };
+    for key in options.keys() {
+        let key = key_name("run", key)?;
+        if !RUN_KEYS.contains(&key) && !CIRCLECI_RUN_KEYS.contains(&key) {
+            warnings.push(unknown_key("run", key, &RUN_KEYS));
+        }
+    }
+    match options.get("command") {
+        Some(Value::String(_)) => Ok(()),
+        Some(_) => Err(StepShapeError::new(
+            "run step `command` must be a string",
+            "command",
+        )),
+        None => Err(misspelled("run", options, &["command"])
+            .unwrap_or_else(|| StepShapeError::new("run step needs a `command`", "run"))),
+    }
+}
+
+/// Keys next to the step's kind: `if`, the GitHub step options beside `run`
+/// and `uses`, and `extra`. Another step kind is an error, since only one of
+/// them would run; anything else is a warning, and an option of the kind
+/// written beside it is pointed back under it.
+fn check_step_keys(
+    kind: &str,
+    step: &Mapping,
+    extra: &[&str],
+    warnings: &mut Vec<StepShapeError>,
+) -> Result<(), StepShapeError> {
+    let github_keys: &[&str] = match kind {
+        "run" | "uses" => &GITHUB_STEP_KEYS,
+        _ => &["if"],
+    };
+    for key in step.keys() {
+        let key = key_name(kind, key)?;
+        if key == kind || github_keys.contains(&key) || extra.contains(&key) {
+            continue;
+        }
+        if key == "uses" || KINDS.contains(&key) || BUILTIN_STEPS.contains(&key) {
+            return Err(StepShapeError::new(
+                format!("A step can't have both `{kind}` and `{key}`"),
+                key,
+            ));
+        }
+        let options: &[&str] = match kind {
+            "run" => &RUN_KEYS,
+            "restore_cache" | "save_cache" => &["name", "key"],
+            _ => &[],
+        };
+        if options.contains(&key) {
+            warnings.push(StepShapeError::new(
+                format!("`{key}` belongs under `{kind}:`, not next to it"),
+                key,
+            ));
+        } else {
+            let allowed: Vec<&str> = github_keys.iter().chain(extra).copied().collect();
+            warnings.push(unknown_key(kind, key, &allowed));
+        }
+    }
+    Ok(())
+}
+
+/// The step's options, with a warning for each key it doesn't take
+fn check_options<'a>(
+    kind: &str,
+    options: &'a Value,
+    allowed: &[&str],
+    warnings: &mut Vec<StepShapeError>,
+) -> Result<&'a Mapping, StepShapeError> {
+    let Value::Mapping(options) = options else {
+        return Err(StepShapeError::new(
+            format!("`{kind}` must be a mapping of its options"),
+            kind,
+        ));
+    };
+    for key in options.keys() {
+        let key = key_name(kind, key)?;
+        if !allowed.contains(&key) {
+            warnings.push(unknown_key(kind, key, allowed));
+        }
+    }
+    Ok(options)
+}
+
+fn require(kind: &str, options: &Mapping, key: &str) -> Result<(), StepShapeError> {
+    if options.contains_key(key) {
+        return Ok(());
+    }
+    Err(misspelled(kind, options, &[key])
+        .unwrap_or_else(|| StepShapeError::new(format!("{kind} step needs `{key}`"), kind)))
+}
+
+/// For a missing required key, the option that's likely a typo of it
+fn misspelled(kind: &str, options: &Mapping, wanted: &[&str]) -> Option<StepShapeError> {
+    options
+        .keys()
+        .filter_map(Value::as_str)
+        .find(|key| did_you_mean(key, wanted.iter().copied()).is_some())
+        .map(|key| unknown_key(kind, key, wanted))
+}
+
+fn key_name<'a>(kind: &str, key: &'a Value) -> Result<&'a str, StepShapeError> {
+    key.as_str().ok_or_else(|| {
+        StepShapeError::new(format!("{kind} step has a key that isn't a string"), kind)
+    })
+}
+
+fn unknown_key(kind: &str, key: &str, allowed: &[&str]) -> StepShapeError {
+    let mut message = format!("Unknown key `{key}` in {kind} step");
+    if let Some(suggestion) = did_you_mean(key, allowed.iter().copied()) {
+        message.push_str(&format!(". Did you mean `{suggestion}`?"));
+    }
+    StepShapeError::new(message, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(yaml: &str) -> Result<(), String> {
+        let warnings =
+            check_step(&serde_yaml::from_str(yaml).unwrap()).map_err(|error| error.to_string())?;
+        assert!(warnings.is_empty(), "unexpected warnings: {warnings:?}");
+        Ok(())
+    }
+
+    fn warnings(yaml: &str) -> Vec<String> {
+        check_step(&serde_yaml::from_str(yaml).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|warning| warning.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn run_steps() {
+        assert!(check("run: make test").is_ok());
+        assert!(check("run:\n  name: Test\n  command: make test\n  env: {CI: 'true'}\n").is_ok());
+        assert!(check("run:\n  command: make\n  no_output_timeout: 20m\n").is_ok());
+        assert!(check("run: ./deploy.sh\nif: branch == \"main\"\n").is_ok());
+        assert!(check("run: pnpm test\nworking-directory: app\n").is_ok());
+        assert!(check("name: Test\nrun: make test\nenv: {CI: 'true'}\nshell: bash\n").is_ok());
+
+        assert_eq!(
+            check("run:\n  name: Test\n  comand: make test\n").unwrap_err(),
+            "Unknown key `comand` in run step. Did you mean `command`?"
+        );
+        assert_eq!(
+            check("run:\n  name: Test\n").unwrap_err(),
+            "run step needs a `command`"
+        );
+        assert_eq!(
+            check("run: make\ncheckout: {}\n").unwrap_err(),
+            "A step can't have both `run` and `checkout`"
+        );
+        assert!(check("run: [make]").is_err());
+    }
+
+    #[test]
+    fn unknown_run_keys_are_warnings() {
+        assert_eq!(
+            warnings("run:\n  command: make\n  environment: {CI: 'true'}\n"),
+            ["Unknown key `environment` in run step. Did you mean `env`?"]
+        );
+        assert_eq!(
+            warnings("run: make\nretry: {max: 2}\n"),
+            ["`retry` belongs under `run:`, not next to it"]
+        );
+        assert_eq!(
+            warnings("run: make\nshel: bash\n"),
+            ["Unknown key `shel` in run step. Did you mean `shell`?"]
+        );
+    }
+
+    #[test]
+    fn uses_steps() {
+        assert!(
+            check("name: Build\nuses: docker/build@1.0\nwith:\n  push: false\nid: build\n").is_ok()
+        );
+        assert_eq!(
+            warnings("uses: docker/build@1.0\nwiht: {}\n"),
+            ["Unknown key `wiht` in uses step. Did you mean `with`?"]
+        );
+        assert!(check("uses: [docker/build]").is_err());
+    }
+
+    #[test]
+    fn restore_cache_steps() {
+        assert!(check("restore_cache:\n  keys: [gems-v1]\n").is_ok());
+        assert!(check("restore_cache:\n  name: Restore\n  key: gems-v1\n").is_ok());
+        assert_eq!(
+            check("restore_cache:\n  name: Restore\n").unwrap_err(),
+            "restore_cache step needs `key` or `keys`"
+        );
+        assert_eq!(
+            check("restore_cache:\n  kyes: [gems-v1]\n").unwrap_err(),
+            "Unknown key `kyes` in restore_cache step. Did you mean `keys`?"
+        );
+    }
+
+    #[test]
+    fn save_cache_steps() {
+        assert!(check("save_cache:\n  key: gems-v1\n  paths: [vendor/bundle]\n").is_ok());
+        assert_eq!(
+            check("save_cache:\n  key: gems-v1\n").unwrap_err(),
+            "save_cache step needs `paths`"
+        );
+        assert_eq!(
+            check("save_cache:\n  key: gems-v1\n  path: vendor/bundle\n").unwrap_err(),
+            "Unknown key `path` in save_cache step. Did you mean `paths`?"
+        );
+        assert_eq!(
+            check("save_cache: gems-v1").unwrap_err(),
+            "`save_cache` must be a mapping of its options"
+        );
+        assert_eq!(
+            warnings("save_cache:\n  key: gems-v1\n  paths: [tmp]\n  ttl: 7d\n"),
+            ["Unknown key `ttl` in save_cache step"]
+        );
+    }
+
+    #[test]
+    fn store_steps() {
+        assert!(check("store_artifacts:\n  path: log\n  destination: logs\n").is_ok());
+        assert!(check("store_artifacts:\n  path: log\nif: env.CI defined\n").is_ok());
+        assert_eq!(
+            check("store_artifacts:\n  destination: logs\n").unwrap_err(),
+            "store_artifacts step needs `path`"
+        );
+        assert!(check("store_test_results:\n  path: test-results\n").is_ok());
+        assert_eq!(
+            check("store_test_results:\n  paths: test-results\n").unwrap_err(),
+            "Unknown key `paths` in store_test_results step. Did you mean `path`?"
+        );
+    }
+
+    #[test]
+    fn other_steps_are_left_to_the_invocation_check() {
+        assert!(check("checkout").is_ok());
+        assert!(check("setup_database").is_ok());
+        assert!(check("set_env:\n  env: test\n").is_ok());
+        assert!(check("node/install-packages: {}").is_ok());
+        assert!(check("persist_to_workspace:\n  root: .\n  paths: [dist]\n").is_ok());
+    }
+
+    #[test]
+    fn nested_and_cleanup_steps_are_checked() {
+        assert_eq!(
+            check("when:\n  condition: true\n  steps:\n    - run:\n        comand: make\n")
+                .unwrap_err(),
+            "Unknown key `comand` in run step. Did you mean `command`?"
+        );
+
+        let job: Value = serde_yaml::from_str(
+            "steps:\n  - run: make\n  - run:\n      command: make\n      nmae: Lint\ncleanup:\n  - run:\n      comand: Stop\n",
+        )
+        .unwrap();
+        let error = check_steps(&job).unwrap_err();
+        assert_eq!(error.key, "comand");
+        assert_eq!(error.step, Some(("cleanup", 0)));
+
+        let job: Value = serde_yaml::from_str(
+            "steps:\n  - run: make\n  - run:\n      command: make\n      nmae: Lint\n",
+        )
+        .unwrap();
+        let warnings = check_steps(&job).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].to_string(),
+            "Unknown key `nmae` in run step. Did you mean `name`?"
+        );
+        assert_eq!(warnings[0].step, Some(("steps", 1)));
+    }
+}
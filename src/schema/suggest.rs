@@ -0,0 +1,149 @@
+//! "Did you mean" suggestions for unknown references (jobs, services, source file groups, ...)
+
+/// Build an error message for an unknown reference.
+///
+/// The closest match (if any) is suggested first, followed by the full list of
+/// available names: `Unknown cache 'gem'. Did you mean 'gems'?`
+pub fn unknown_reference_message<'a, I>(message: &str, name: &str, candidates: I) -> String
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut output = message.to_string();
+    if let Some(help) = unknown_reference_help(name, candidates) {
+        let separator = if help.starts_with("Did you mean") {
+            ". "
+        } else {
+            "\n"
+        };
+        output.push_str(separator);
+        output.push_str(&help);
+    }
+    output
+}
+
+/// The suggestion and list of available names that [`unknown_reference_message`]
+/// appends, for errors that show them as a separate help:
+/// `Did you mean 'gems'?\nAvailable: gems, npm, pip`
+pub fn unknown_reference_help<'a, I>(name: &str, candidates: I) -> Option<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut available: Vec<&str> = candidates.into_iter().collect();
+    available.sort_unstable();
+    available.dedup();
+
+    let mut lines = Vec::new();
+    if let Some(suggestion) = did_you_mean(name, available.iter().copied()) {
+        lines.push(format!("Did you mean '{suggestion}'?"));
+    }
+    if !available.is_empty() {
+        lines.push(format!("Available: {}", available.join(", ")));
+    }
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+/// Return the closest candidate to `name`, if any is close enough to be a likely typo.
+///
+/// Candidates sharing a prefix with `name` rank ahead of edit-distance matches;
+/// ties are broken by distance and then alphabetically.
+pub fn did_you_mean<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let threshold = (name.chars().count() / 3).max(1);
+
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .filter_map(|candidate| {
+            let is_prefix = !name.is_empty()
+                && (candidate.starts_with(name) || name.starts_with(candidate))
+                && !candidate.is_empty();
+            let distance = edit_distance(name, candidate);
+            if is_prefix || distance <= threshold {
+                Some(((!is_prefix, distance, candidate), candidate))
+            } else {
+                None
+            }
+        })
+        .min_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, candidate)| candidate)
+}
+
+/// Optimal string alignment distance (Levenshtein plus adjacent transpositions).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("gems", "gems"), 0);
+        assert_eq!(edit_distance("gem", "gems"), 1);
+        assert_eq!(edit_distance("reids", "redis"), 1);
+        assert_eq!(edit_distance("abc", "xyz"), 3);
+    }
+
+    #[test]
+    fn test_prefix_match_beats_transposition() {
+        // "node" is a prefix of "node_modules" (distance 8) while "nodr" is a
+        // single substitution away; the prefix match should still win.
+        let candidates = ["nodr", "node_modules"];
+        assert_eq!(did_you_mean("node", candidates), Some("node_modules"));
+    }
+
+    #[test]
+    fn test_transposition_suggested() {
+        let candidates = ["postgres", "redis", "minio"];
+        assert_eq!(did_you_mean("reids", candidates), Some("redis"));
+    }
+
+    #[test]
+    fn test_closest_distance_wins() {
+        let candidates = ["bundler", "bundle_x", "yarn"];
+        assert_eq!(did_you_mean("bundlr", candidates), Some("bundler"));
+    }
+
+    #[test]
+    fn test_no_suggestion_for_distant_names() {
+        let candidates = ["postgres", "redis"];
+        assert_eq!(did_you_mean("elasticsearch", candidates), None);
+    }
+
+    #[test]
+    fn test_message_leads_with_suggestion() {
+        let message =
+            unknown_reference_message("Unknown cache 'gem'", "gem", ["npm", "gems", "pip"]);
+        assert_eq!(
+            message,
+            "Unknown cache 'gem'. Did you mean 'gems'?\nAvailable: gems, npm, pip"
+        );
+    }
+}
@@ -3,6 +3,12 @@ use serde::{Deserialize, Serialize};
 use serde_yaml::{Mapping, Value};
 use std::collections::HashMap;
 
+use super::config::Notifications;
+use super::step::Step;
+
+/// Workflow of the jobs that don't name one (single-file configs)
+pub const DEFAULT_WORKFLOW: &str = "ci";
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct StageDefinition {
     pub name: String,
@@ -12,6 +18,7 @@ pub struct StageDefinition {
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(default)]
+#[non_exhaustive]
 pub struct WorkflowConfig {
     pub dynamic: bool,
     pub output_path: Option<String>,
@@ -19,6 +26,8 @@ pub struct WorkflowConfig {
     pub setup: bool,
     pub checkout: Option<Value>,
     pub run_when: Vec<WorkflowCondition>,
+    /// Skips the workflow when all of these hold; ANDed with `run_when`
+    pub run_unless: Vec<WorkflowCondition>,
     #[serde(default)]
     pub stages: Vec<StageDefinition>,
     #[serde(default)]
@@ -27,6 +36,14 @@ pub struct WorkflowConfig {
     pub default_stage_prefix: bool,
     #[serde(default = "default_stage_prefix_separator")]
     pub stage_prefix_separator: String,
+    /// Environment variables for every job in this workflow (overrides the global `env`)
+    pub env: HashMap<String, String>,
+    /// Steps this workflow adds around some of its jobs, keyed by job id
+    pub job_steps: HashMap<String, WorkflowJobSteps>,
+    /// Replaces the top-level `notifications` for this workflow
+    pub notifications: Option<Notifications>,
+    /// Whether this workflow's jobs run the top-level `global_steps`
+    pub inherit_global_steps: bool,
     #[serde(flatten)]
     pub extra: HashMap<String, Value>,
     #[serde(skip)]
@@ -46,22 +63,72 @@ impl Default for WorkflowConfig {
             setup: false,
             checkout: None,
             run_when: Vec::new(),
+            run_unless: Vec::new(),
             stages: Vec::new(),
             stage_prefix: false,
             default_stage_prefix: false,
             stage_prefix_separator: default_stage_prefix_separator(),
+            env: HashMap::new(),
+            job_steps: HashMap::new(),
+            notifications: None,
+            inherit_global_steps: true,
             extra: HashMap::new(),
             raw: Value::Mapping(Mapping::new()),
         }
     }
 }
 
+/// Steps run before and after a job's own steps when it runs in a workflow,
+/// without editing the job file
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WorkflowJobSteps {
+    /// Run before everything else in the job, including the checkout
+    #[serde(default)]
+    pub pre_steps: Vec<Step>,
+    /// Run after everything else in the job
+    #[serde(default)]
+    pub post_steps: Vec<Step>,
+}
+
+/// Workflow config keys cigen handles itself, which providers shouldn't copy
+/// into generated files
+const CIGEN_WORKFLOW_KEYS: [&str; 14] = [
+    "dynamic",
+    "output_path",
+    "output_filename",
+    "setup",
+    "checkout",
+    "run_when",
+    "run_unless",
+    "stages",
+    "stage_prefix",
+    "default_stage_prefix",
+    "stage_prefix_separator",
+    "job_steps",
+    "notifications",
+    "inherit_global_steps",
+];
+
 impl WorkflowConfig {
     pub fn from_value(value: Value) -> Result<Self> {
         let mut config: WorkflowConfig = serde_yaml::from_value(value.clone())?;
         config.raw = value;
         Ok(config)
     }
+
+    /// The raw config without the keys cigen handles itself, such as `on:`
+    /// and `permissions:` for GitHub Actions
+    pub fn provider_metadata(&self) -> Value {
+        let mut raw = self.raw.clone();
+        if let Value::Mapping(map) = &mut raw {
+            map.retain(|key, _| {
+                !key.as_str()
+                    .is_some_and(|key| CIGEN_WORKFLOW_KEYS.contains(&key))
+            });
+        }
+        raw
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
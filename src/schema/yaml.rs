@@ -0,0 +1,243 @@
+//! YAML parsing helpers shared by the loader and schema
+
+use anyhow::{Result, bail};
+use serde::de::DeserializeOwned;
+use serde_yaml::{Mapping, Value};
+
+const MERGE_KEY: &str = "<<";
+
+/// Parse YAML into `T`, expanding `<<` merge keys first.
+///
+/// Documents without merge keys are deserialized straight from the source so
+/// serde_yaml errors keep their line and column information. Errors in the
+/// others name the key path instead (see [`from_expanded_value`]).
+pub fn parse_yaml<T: DeserializeOwned>(content: &str) -> Result<T> {
+    let mut value: Value = serde_yaml::from_str(content)?;
+    if !contains_merge_keys(&value) {
+        return Ok(serde_yaml::from_str(content)?);
+    }
+
+    expand_merge_keys(&mut value)?;
+    Ok(from_expanded_value(value)?)
+}
+
+/// Error deserializing a document after its merge keys were expanded
+#[derive(Debug, thiserror::Error)]
+#[error("{}{message}", path.as_ref().map(|path| format!("{path}: ")).unwrap_or_default())]
+pub struct ExpandedValueError {
+    /// Key path of the offending value, e.g. `steps[0].run`
+    pub path: Option<String>,
+    pub message: String,
+}
+
+/// Deserialize an expanded document. serde_yaml only tracks where an error is
+/// when it reads text, so a failure is read again from the value written back
+/// out as YAML to find the key path. That text's line and column aren't the
+/// user's, so they're left out.
+pub fn from_expanded_value<T: DeserializeOwned>(value: Value) -> Result<T, ExpandedValueError> {
+    let error = match serde_yaml::from_value::<T>(value.clone()) {
+        Ok(parsed) => return Ok(parsed),
+        Err(error) => error,
+    };
+    let message = error.to_string();
+    let path = serde_yaml::to_string(&value)
+        .ok()
+        .and_then(|text| serde_yaml::from_str::<T>(&text).err())
+        .and_then(|keyed| {
+            let keyed = keyed.to_string();
+            let keyed = match keyed.rsplit_once(" at line ") {
+                Some((keyed, _)) => keyed.to_string(),
+                None => keyed,
+            };
+            keyed
+                .strip_suffix(&message)?
+                .strip_suffix(": ")
+                .map(str::to_string)
+        });
+    Err(ExpandedValueError { path, message })
+}
+
+/// Parse YAML into a `Value` with `<<` merge keys expanded
+pub fn parse_yaml_value(content: &str) -> Result<Value> {
+    let mut value: Value = serde_yaml::from_str(content)?;
+    expand_merge_keys(&mut value)?;
+    Ok(value)
+}
+
+/// Resolve `<<` merge keys according to the YAML merge key spec.
+///
+/// The merge value may be a mapping or a sequence of mappings. Keys defined
+/// directly on the mapping always win; for a sequence, earlier mappings take
+/// precedence over later ones. Nested mappings are expanded depth-first so
+/// merged anchors may themselves use merge keys.
+pub fn expand_merge_keys(value: &mut Value) -> Result<()> {
+    match value {
+        Value::Mapping(map) => {
+            for (_, child) in map.iter_mut() {
+                expand_merge_keys(child)?;
+            }
+
+            let Some(merge_value) = map.remove(MERGE_KEY) else {
+                return Ok(());
+            };
+
+            let sources = match merge_value {
+                Value::Mapping(source) => vec![source],
+                Value::Sequence(items) => items
+                    .into_iter()
+                    .map(|item| match item {
+                        Value::Mapping(source) => Ok(source),
+                        other => bail!(
+                            "YAML merge key '<<' expects mappings, found {}",
+                            describe(&other)
+                        ),
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+                other => bail!(
+                    "YAML merge key '<<' expects a mapping or a sequence of mappings, found {}",
+                    describe(&other)
+                ),
+            };
+
+            let mut merged = Mapping::new();
+            for source in sources {
+                for (key, value) in source {
+                    if !merged.contains_key(&key) {
+                        merged.insert(key, value);
+                    }
+                }
+            }
+            for (key, value) in std::mem::take(map) {
+                merged.insert(key, value);
+            }
+            *map = merged;
+        }
+        Value::Sequence(items) => {
+            for item in items {
+                expand_merge_keys(item)?;
+            }
+        }
+        Value::Tagged(tagged) => expand_merge_keys(&mut tagged.value)?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn contains_merge_keys(value: &Value) -> bool {
+    match value {
+        Value::Mapping(map) => map
+            .iter()
+            .any(|(key, child)| key.as_str() == Some(MERGE_KEY) || contains_merge_keys(child)),
+        Value::Sequence(items) => items.iter().any(contains_merge_keys),
+        Value::Tagged(tagged) => contains_merge_keys(&tagged.value),
+        _ => false,
+    }
+}
+
+fn describe(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Sequence(_) => "a sequence",
+        Value::Mapping(_) => "a mapping",
+        Value::Tagged(_) => "a tagged value",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::Job;
+
+    #[test]
+    fn test_merge_key_with_override() {
+        let yaml = r#"
+defaults: &defaults
+  image: cimg/ruby:3.3
+  resource_class: medium
+job:
+  <<: *defaults
+  resource_class: large
+"#;
+        let value = parse_yaml_value(yaml).unwrap();
+        let job = &value["job"];
+        assert_eq!(job["image"].as_str(), Some("cimg/ruby:3.3"));
+        assert_eq!(job["resource_class"].as_str(), Some("large"));
+        assert!(job.get("<<").is_none());
+    }
+
+    #[test]
+    fn test_sequence_of_merges_prefers_earlier_entries() {
+        let yaml = r#"
+a: &a
+  image: first
+b: &b
+  image: second
+  parallelism: 2
+job:
+  <<: [*a, *b]
+"#;
+        let value = parse_yaml_value(yaml).unwrap();
+        assert_eq!(value["job"]["image"].as_str(), Some("first"));
+        assert_eq!(value["job"]["parallelism"].as_u64(), Some(2));
+    }
+
+    #[test]
+    fn test_nested_merges() {
+        let yaml = r#"
+base: &base
+  image: cimg/base:current
+  environment:
+    RAILS_ENV: test
+ruby: &ruby
+  <<: *base
+  image: cimg/ruby:3.3
+job:
+  <<: *ruby
+  environment:
+    <<: { RAILS_ENV: test, LOG_LEVEL: debug }
+    LOG_LEVEL: info
+"#;
+        let value = parse_yaml_value(yaml).unwrap();
+        let job = &value["job"];
+        assert_eq!(job["image"].as_str(), Some("cimg/ruby:3.3"));
+        assert_eq!(job["environment"]["RAILS_ENV"].as_str(), Some("test"));
+        assert_eq!(job["environment"]["LOG_LEVEL"].as_str(), Some("info"));
+    }
+
+    #[test]
+    fn test_parse_job_with_merge_key() {
+        let yaml = r#"
+x-defaults: &defaults
+  image: cimg/node:20.0
+  env:
+    NODE_ENV: test
+<<: *defaults
+steps:
+  - run: npm test
+"#;
+        let job: Job = parse_yaml(yaml).unwrap();
+        assert_eq!(job.image, "cimg/node:20.0");
+        assert_eq!(job.environment.get("NODE_ENV").unwrap(), "test");
+    }
+
+    #[test]
+    fn test_errors_after_merging_name_the_key_path() {
+        let yaml = "x-defaults: &defaults\n  image: cimg/node:20.0\n<<: *defaults\nenv:\n  NODE_ENV: [test]\n";
+        let error = parse_yaml::<Job>(yaml).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "env.NODE_ENV: invalid type: sequence, expected a string"
+        );
+    }
+
+    #[test]
+    fn test_invalid_merge_value() {
+        let yaml = "job:\n  <<: not-a-mapping\n";
+        let error = parse_yaml_value(yaml).unwrap_err().to_string();
+        assert!(error.contains("found a string"));
+    }
+}
@@ -0,0 +1,362 @@
+//! Size and complexity numbers for `cigen stats`
+//!
+//! Counts come from the loaded config and from the files each provider
+//! generates for it, so they follow whatever generation injects. Steps a job
+//! has beyond its own `steps:` count as injected; the CircleCI credits weight
+//! is the sum of each job's resource class weight times its parallelism.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_yaml::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::orchestrator::JobDAG;
+use crate::schema::{CigenConfig, DEFAULT_WORKFLOW, resource_class_weight};
+
+/// How many of the largest jobs to report per provider
+pub const LARGEST_JOBS: usize = 5;
+
+/// Resource class CircleCI uses when a job doesn't set one
+const DEFAULT_RESOURCE_CLASS: &str = "medium";
+
+#[derive(Debug, Serialize)]
+pub struct ConfigStats {
+    pub workflows: usize,
+    /// Jobs as written, before matrix expansion
+    pub jobs: usize,
+    /// Jobs after matrix expansion
+    pub expanded_jobs: usize,
+    pub providers: Vec<ProviderStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProviderStats {
+    pub provider: String,
+    pub files: Vec<FileStats>,
+    pub total_bytes: usize,
+    pub jobs: usize,
+    pub user_steps: usize,
+    pub injected_steps: usize,
+    /// The jobs with the most steps, largest first
+    pub largest_jobs: Vec<JobStats>,
+    pub cache_keys: usize,
+    /// Only estimated for CircleCI
+    pub credits: Option<CreditsEstimate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileStats {
+    pub path: String,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct JobStats {
+    pub name: String,
+    pub steps: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreditsEstimate {
+    pub weight: u64,
+    /// Resource classes without a weight, left out of the estimate
+    pub unknown_resource_classes: Vec<String>,
+}
+
+/// Collect the stats for `config`. `generated` maps each provider to the files
+/// it generated (path -> content).
+pub fn collect_stats(
+    config: &CigenConfig,
+    generated: &BTreeMap<String, HashMap<String, String>>,
+) -> Result<ConfigStats> {
+    let dag = JobDAG::build(config).context("Failed to build dependency graph")?;
+
+    // Generated job name -> the number of steps the user wrote for it
+    let mut user_steps = HashMap::new();
+    for (instance_id, concrete) in dag.jobs() {
+        let steps = config
+            .jobs
+            .get(&concrete.job_id)
+            .map_or(0, |job| job.steps.len());
+        user_steps.insert(instance_id.clone(), steps);
+        user_steps.insert(instance_id.replace('/', "_"), steps);
+    }
+
+    let mut workflows: BTreeSet<&str> = config.workflows.keys().map(String::as_str).collect();
+    workflows.extend(
+        config
+            .jobs
+            .values()
+            .map(|job| job.workflow.as_deref().unwrap_or(DEFAULT_WORKFLOW)),
+    );
+
+    let providers = generated
+        .iter()
+        .map(|(provider, files)| provider_stats(provider, files, &user_steps))
+        .collect::<Result<_>>()?;
+
+    Ok(ConfigStats {
+        workflows: workflows.len(),
+        jobs: config.jobs.len(),
+        expanded_jobs: dag.jobs().len(),
+        providers,
+    })
+}
+
+fn provider_stats(
+    provider: &str,
+    files: &HashMap<String, String>,
+    user_steps: &HashMap<String, usize>,
+) -> Result<ProviderStats> {
+    let mut paths: Vec<&String> = files.keys().collect();
+    paths.sort();
+
+    let mut stats = ProviderStats {
+        provider: provider.to_string(),
+        files: Vec::new(),
+        total_bytes: 0,
+        jobs: 0,
+        user_steps: 0,
+        injected_steps: 0,
+        largest_jobs: Vec::new(),
+        cache_keys: 0,
+        credits: None,
+    };
+    let mut jobs = Vec::new();
+    let mut cache_keys = BTreeSet::new();
+    let mut credits = CreditsEstimate {
+        weight: 0,
+        unknown_resource_classes: Vec::new(),
+    };
+
+    for path in paths {
+        let content = &files[path];
+        stats.files.push(FileStats {
+            path: path.clone(),
+            bytes: content.len(),
+        });
+        stats.total_bytes += content.len();
+        if !(path.ends_with(".yml") || path.ends_with(".yaml")) {
+            continue;
+        }
+
+        let document: Value = serde_yaml::from_str(content)
+            .with_context(|| format!("{provider} generated invalid YAML in {path}"))?;
+        let Some(generated_jobs) = document.get("jobs").and_then(Value::as_mapping) else {
+            continue;
+        };
+        for (name, job) in generated_jobs {
+            let name = name.as_str().unwrap_or_default().to_string();
+            let steps = job
+                .get("steps")
+                .and_then(Value::as_sequence)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let own = user_steps.get(&name).copied().unwrap_or(0).min(steps.len());
+            stats.user_steps += own;
+            stats.injected_steps += steps.len() - own;
+            for step in steps {
+                cache_keys.extend(step_cache_keys(step));
+            }
+            if provider == "circleci" {
+                add_credits(&mut credits, job);
+            }
+            jobs.push(JobStats {
+                name,
+                steps: steps.len(),
+            });
+        }
+    }
+
+    stats.jobs = jobs.len();
+    jobs.sort_by(|a, b| b.steps.cmp(&a.steps).then_with(|| a.name.cmp(&b.name)));
+    jobs.truncate(LARGEST_JOBS);
+    stats.largest_jobs = jobs;
+    stats.cache_keys = cache_keys.len();
+    if provider == "circleci" {
+        credits.unknown_resource_classes.sort();
+        credits.unknown_resource_classes.dedup();
+        stats.credits = Some(credits);
+    }
+    Ok(stats)
+}
+
+/// Primary keys of a CircleCI `save_cache`/`restore_cache` step or a GitHub
+/// `actions/cache` step. Fallback prefixes aren't counted.
+fn step_cache_keys(step: &Value) -> Vec<String> {
+    let key = |value: &Value| {
+        value
+            .get("key")
+            .or_else(|| value.get("keys").and_then(|keys| keys.get(0)))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+    for name in ["save_cache", "restore_cache"] {
+        if let Some(cache) = step.get(name) {
+            return key(cache).into_iter().collect();
+        }
+    }
+    let uses_cache = step
+        .get("uses")
+        .and_then(Value::as_str)
+        .is_some_and(|uses| uses.starts_with("actions/cache"));
+    if uses_cache && let Some(with) = step.get("with") {
+        return key(with).into_iter().collect();
+    }
+    Vec::new()
+}
+
+fn add_credits(credits: &mut CreditsEstimate, job: &Value) {
+    let resource_class = job
+        .get("resource_class")
+        .and_then(Value::as_str)
+        .unwrap_or(DEFAULT_RESOURCE_CLASS);
+    let parallelism = job.get("parallelism").and_then(Value::as_u64).unwrap_or(1);
+    match resource_class_weight(resource_class) {
+        Some(weight) => credits.weight += weight * parallelism,
+        None => credits
+            .unknown_resource_classes
+            .push(resource_class.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+jobs:
+  rspec:
+    image: cimg/ruby:3.3
+    workflow: test
+    matrix:
+      arch: [amd64, arm64]
+    steps:
+      - run: bundle exec rspec
+  lint:
+    image: cimg/node:20.0
+    workflow: test
+    steps:
+      - run: yarn install
+      - run: yarn lint
+"#;
+
+    const CIRCLECI: &str = r#"
+version: '2.1'
+jobs:
+  setup:
+    docker:
+    - image: cimg/base:current
+    steps:
+    - checkout
+  lint:
+    docker:
+    - image: cimg/node:20.0
+    resource_class: small
+    steps:
+    - checkout
+    - restore_cache:
+        keys:
+        - v1-yarn-{{ checksum "yarn.lock" }}
+        - v1-yarn-
+    - run: yarn install
+    - save_cache:
+        key: v1-yarn-{{ checksum "yarn.lock" }}
+        paths: [node_modules]
+    - run: yarn lint
+  rspec-amd64:
+    docker:
+    - image: cimg/ruby:3.3
+    resource_class: large
+    parallelism: 4
+    steps:
+    - checkout
+    - run: bundle exec rspec
+  rspec-arm64:
+    machine: true
+    resource_class: acme/arm-runner
+    steps:
+    - checkout
+    - run: bundle exec rspec
+"#;
+
+    #[test]
+    fn counts_jobs_steps_caches_and_credits() {
+        let config = CigenConfig::from_yaml(CONFIG).unwrap();
+        let generated = BTreeMap::from([(
+            "circleci".to_string(),
+            HashMap::from([(".circleci/main.yml".to_string(), CIRCLECI.to_string())]),
+        )]);
+
+        let stats = collect_stats(&config, &generated).unwrap();
+        assert_eq!(stats.workflows, 1);
+        assert_eq!(stats.jobs, 2);
+        assert_eq!(stats.expanded_jobs, 3);
+
+        let circleci = &stats.providers[0];
+        assert_eq!(circleci.total_bytes, CIRCLECI.len());
+        assert_eq!(circleci.jobs, 4);
+        // lint 2 + rspec 1 + rspec 1
+        assert_eq!(circleci.user_steps, 4);
+        // 4 checkouts + lint's two cache steps
+        assert_eq!(circleci.injected_steps, 6);
+        assert_eq!(
+            circleci.largest_jobs[..2],
+            [
+                JobStats {
+                    name: "lint".to_string(),
+                    steps: 5
+                },
+                JobStats {
+                    name: "rspec-amd64".to_string(),
+                    steps: 2
+                },
+            ]
+        );
+        assert_eq!(circleci.cache_keys, 1);
+
+        let credits = circleci.credits.as_ref().unwrap();
+        // setup (medium) 10 + lint (small) 5 + rspec-amd64 (large x4) 80
+        assert_eq!(credits.weight, 95);
+        assert_eq!(credits.unknown_resource_classes, ["acme/arm-runner"]);
+    }
+
+    #[test]
+    fn github_cache_keys_come_from_actions_cache() {
+        let config = CigenConfig::from_yaml(CONFIG).unwrap();
+        let workflow = r#"
+jobs:
+  lint:
+    runs-on: ubuntu-latest
+    steps:
+    - uses: actions/checkout@v4
+    - uses: actions/cache@v4
+      with:
+        path: node_modules
+        key: yarn-${{ hashFiles('yarn.lock') }}
+    - uses: actions/cache/restore@v4
+      with:
+        path: vendor/bundle
+        key: gems-${{ hashFiles('Gemfile.lock') }}
+    - run: yarn lint
+"#;
+        let generated = BTreeMap::from([(
+            "github".to_string(),
+            HashMap::from([
+                (
+                    ".github/workflows/test.yml".to_string(),
+                    workflow.to_string(),
+                ),
+                ("README.txt".to_string(), "not yaml: [".to_string()),
+            ]),
+        )]);
+
+        let stats = collect_stats(&config, &generated).unwrap();
+        let github = &stats.providers[0];
+        assert_eq!(github.files.len(), 2);
+        assert_eq!(github.cache_keys, 2);
+        assert_eq!(github.user_steps, 2);
+        assert_eq!(github.injected_steps, 2);
+        assert!(github.credits.is_none());
+    }
+}
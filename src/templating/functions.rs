@@ -0,0 +1,150 @@
+//! Functions available to templates
+//!
+//! - `checksum(path)`: SHA-256 of a file at generate time, for keys over files
+//!   a provider can't checksum at runtime
+//! - `file_exists(path)`: whether a file exists, for conditionals
+//! - `read_file(path, max_bytes=65536)`: a small file's contents
+//!
+//! Paths resolve against the project root, the parent of `.cigen/`. A missing
+//! file fails the template that asked for it.
+
+use minijinja::value::Kwargs;
+use minijinja::{Environment, Error, ErrorKind};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Largest file `read_file` returns unless `max_bytes` says otherwise
+pub const DEFAULT_READ_LIMIT: usize = 64 * 1024;
+
+/// Register the functions on `env`, resolving paths against `root`
+pub(super) fn register(env: &mut Environment<'static>, root: &Path) {
+    let checksum_root = root.to_path_buf();
+    env.add_function("checksum", move |path: String| {
+        checksum(&checksum_root, &path)
+    });
+    let exists_root = root.to_path_buf();
+    env.add_function("file_exists", move |path: String| {
+        exists_root.join(path).is_file()
+    });
+    let read_root = root.to_path_buf();
+    env.add_function("read_file", move |path: String, kwargs: Kwargs| {
+        let max_bytes = kwargs
+            .get::<Option<usize>>("max_bytes")?
+            .unwrap_or(DEFAULT_READ_LIMIT);
+        kwargs.assert_all_used()?;
+        read_file(&read_root, &path, max_bytes)
+    });
+}
+
+fn checksum(root: &Path, path: &str) -> Result<String, Error> {
+    let contents = std::fs::read(resolve(root, path, "checksum")?)
+        .map_err(|err| file_error("checksum", path, &err.to_string()))?;
+    Ok(hex::encode(Sha256::digest(&contents)))
+}
+
+fn read_file(root: &Path, path: &str, max_bytes: usize) -> Result<String, Error> {
+    let file = resolve(root, path, "read_file")?;
+    let size = file
+        .metadata()
+        .map_err(|err| file_error("read_file", path, &err.to_string()))?
+        .len();
+    if size > max_bytes as u64 {
+        return Err(file_error(
+            "read_file",
+            path,
+            &format!(
+                "file is {size} bytes, over the limit of {max_bytes} (raise it with max_bytes=...)"
+            ),
+        ));
+    }
+    std::fs::read_to_string(&file).map_err(|err| file_error("read_file", path, &err.to_string()))
+}
+
+fn resolve(root: &Path, path: &str, function: &str) -> Result<PathBuf, Error> {
+    let file = root.join(path);
+    if !file.is_file() {
+        return Err(file_error(
+            function,
+            path,
+            &format!("no such file {}", file.display()),
+        ));
+    }
+    Ok(file)
+}
+
+fn file_error(function: &str, path: &str, detail: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidOperation,
+        format!("{function}('{path}'): {detail}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn render(root: &Path, template: &str) -> Result<String, Error> {
+        let mut env = Environment::new();
+        register(&mut env, root);
+        env.render_str(template, ())
+    }
+
+    #[test]
+    fn checksum_hashes_the_file() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("Gemfile.lock"), "abc").unwrap();
+        assert_eq!(
+            render(dir.path(), "{{ checksum('Gemfile.lock') }}").unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn file_exists_checks_the_project_root() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("yarn.lock"), "").unwrap();
+        assert_eq!(
+            render(
+                dir.path(),
+                "{{ file_exists('yarn.lock') }} {{ file_exists('package-lock.json') }}"
+            )
+            .unwrap(),
+            "true false"
+        );
+    }
+
+    #[test]
+    fn read_file_is_size_limited() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("hosts.txt"), "127.0.0.1 db\n").unwrap();
+        assert_eq!(
+            render(dir.path(), "{{ read_file('hosts.txt') | trim }}").unwrap(),
+            "127.0.0.1 db"
+        );
+
+        let error = render(dir.path(), "{{ read_file('hosts.txt', max_bytes=4) }}")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("read_file('hosts.txt'): file is 13 bytes, over the limit of 4"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn missing_files_are_template_errors() {
+        let dir = tempdir().unwrap();
+        for template in [
+            "{{ checksum('missing.lock') }}",
+            "{{ read_file('missing.lock') }}",
+        ] {
+            let error = render(dir.path(), template).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidOperation);
+            assert!(
+                error.to_string().contains("('missing.lock'): no such file"),
+                "{error}"
+            );
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! `{% include %}` and `{% include_raw %}` in `.j2` job files
+//!
+//! Includes are inlined before the file is rendered. A path resolves against
+//! the including file's directory first, then `.cigen/templates/`. `include`
+//! pulls in another template, whose own includes are resolved in turn;
+//! `include_raw` inlines a file verbatim, without rendering it. When the
+//! directive is alone on its line, the inlined lines are indented to match, so
+//! a shell script can sit under `run: |`.
+
+use anyhow::{Context, Result, bail};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How deep `include`s may nest before cigen assumes a cycle
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+static INCLUDE_DIRECTIVE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"\{%-?\s*(include_raw|include)\s+(?:"([^"]*)"|'([^']*)')\s*-?%\}"#)
+        .expect("valid regex")
+});
+
+/// A template with its includes inlined. `include_raw` contents stand in as
+/// placeholders until the template has been rendered.
+#[derive(Debug, Default)]
+pub(super) struct Expanded {
+    pub text: String,
+    pub raw: Vec<String>,
+}
+
+impl Expanded {
+    /// Put the `include_raw` contents back into rendered text. Contents that
+    /// look like templates are kept in a `{% raw %}` block, so rendering the
+    /// job again per variant leaves them alone.
+    pub fn restore_raw(&self, rendered: String) -> String {
+        self.raw
+            .iter()
+            .enumerate()
+            .fold(rendered, |rendered, (index, contents)| {
+                let contents = if contents.contains("{{") || contents.contains("{%") {
+                    // `+` keeps the whitespace around the tags
+                    format!("{{%+ raw %}}{contents}{{% endraw +%}}")
+                } else {
+                    contents.clone()
+                };
+                rendered.replace(&raw_placeholder(index), &contents)
+            })
+    }
+}
+
+/// Inline the includes of `text`, read from `file`
+pub(super) fn expand_includes(
+    text: &str,
+    file: &Path,
+    template_dir: Option<&Path>,
+) -> Result<Expanded> {
+    let mut expanded = Expanded::default();
+    expanded.text = expand(
+        text,
+        file,
+        template_dir,
+        &mut vec![file.to_path_buf()],
+        &mut expanded.raw,
+    )?;
+    Ok(expanded)
+}
+
+fn expand(
+    text: &str,
+    file: &Path,
+    template_dir: Option<&Path>,
+    chain: &mut Vec<PathBuf>,
+    raw: &mut Vec<String>,
+) -> Result<String> {
+    let mut output = String::with_capacity(text.len());
+    let mut last = 0;
+    for captures in INCLUDE_DIRECTIVE.captures_iter(text) {
+        let directive = captures.get(0).expect("whole match");
+        output.push_str(&text[last..directive.start()]);
+        last = directive.end();
+
+        let kind = &captures[1];
+        let name = captures
+            .get(2)
+            .or_else(|| captures.get(3))
+            .expect("quoted path")
+            .as_str();
+        let path = resolve(name, file, template_dir)
+            .with_context(|| format!("{}: {kind} \"{name}\" not found", file.display()))?;
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("{}: failed to read {kind} \"{name}\"", file.display()))?;
+        let contents = contents.strip_suffix('\n').unwrap_or(&contents);
+        let indent = line_indent(text, directive.start());
+
+        if kind == "include_raw" {
+            raw.push(indent_continuation(contents, indent));
+            output.push_str(&raw_placeholder(raw.len() - 1));
+            continue;
+        }
+
+        chain.push(path.clone());
+        if chain.len() > MAX_INCLUDE_DEPTH + 1 {
+            bail!(
+                "Includes nest more than {MAX_INCLUDE_DEPTH} deep: {}",
+                chain
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            );
+        }
+        let nested = expand(contents, &path, template_dir, chain, raw)?;
+        chain.pop();
+        output.push_str(&indent_continuation(&nested, indent));
+    }
+    output.push_str(&text[last..]);
+    Ok(output)
+}
+
+/// `name` relative to the including file's directory, then the template dir
+fn resolve(name: &str, file: &Path, template_dir: Option<&Path>) -> Result<PathBuf> {
+    let mut candidates = Vec::new();
+    if let Some(dir) = file.parent() {
+        candidates.push(dir.join(name));
+    }
+    if let Some(dir) = template_dir {
+        candidates.push(dir.join(name));
+    }
+    if let Some(found) = candidates.iter().find(|path| path.is_file()) {
+        return Ok(found.clone());
+    }
+    bail!(
+        "looked for {}",
+        candidates
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" and ")
+    )
+}
+
+/// The whitespace before `offset` on its line, when nothing else precedes it
+fn line_indent(text: &str, offset: usize) -> &str {
+    let line_start = text[..offset].rfind('\n').map_or(0, |index| index + 1);
+    let prefix = &text[line_start..offset];
+    if prefix.chars().all(|c| c == ' ' || c == '\t') {
+        prefix
+    } else {
+        ""
+    }
+}
+
+/// Indent every line after the first, leaving blank lines empty
+fn indent_continuation(text: &str, indent: &str) -> String {
+    if indent.is_empty() {
+        return text.to_string();
+    }
+    text.split('\n')
+        .enumerate()
+        .map(|(index, line)| {
+            if index == 0 || line.is_empty() {
+                line.to_string()
+            } else {
+                format!("{indent}{line}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn raw_placeholder(index: usize) -> String {
+    format!("\u{0}cigen-raw-{index}\u{0}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn includes_resolve_relative_then_from_template_dir() {
+        let dir = tempdir().unwrap();
+        let jobs = dir.path().join("workflows/ci/jobs");
+        let templates = dir.path().join("templates");
+        fs::create_dir_all(&jobs).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        fs::write(
+            jobs.join("setup.yml"),
+            "- checkout\n{% include \"deps.yml\" %}\n",
+        )
+        .unwrap();
+        fs::write(templates.join("deps.yml"), "- run: bundle install\n").unwrap();
+        fs::write(templates.join("test.sh"), "set -e\n\nbin/rspec {{ raw }}\n").unwrap();
+
+        let text = "steps:\n  {% include 'setup.yml' %}\n  - run: |\n      {% include_raw \"test.sh\" %}\n";
+        let expanded = expand_includes(text, &jobs.join("rspec.yml.j2"), Some(&templates)).unwrap();
+        assert_eq!(
+            expanded.restore_raw(expanded.text.clone()),
+            "steps:\n  - checkout\n  - run: bundle install\n  - run: |\n      {%+ raw %}set -e\n\n      bin/rspec {{ raw }}{% endraw +%}\n"
+        );
+    }
+
+    #[test]
+    fn missing_include_names_the_including_file() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("rspec.yml.j2");
+        let error = format!(
+            "{:#}",
+            expand_includes("{% include_raw \"missing.sh\" %}", &file, None).unwrap_err()
+        );
+        assert!(
+            error.starts_with(&format!(
+                "{}: include_raw \"missing.sh\" not found: looked for {}",
+                file.display(),
+                dir.path().join("missing.sh").display()
+            )),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn include_cycles_stop_at_the_depth_limit() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.j2"), "{% include \"b.j2\" %}").unwrap();
+        fs::write(dir.path().join("b.j2"), "{% include \"a.j2\" %}").unwrap();
+
+        let error = expand_includes(
+            "{% include \"a.j2\" %}",
+            &dir.path().join("job.yml.j2"),
+            None,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            error.starts_with("Includes nest more than 10 deep: "),
+            "{error}"
+        );
+        assert!(error.contains("a.j2 -> "), "{error}");
+        assert!(error.contains("b.j2 -> "), "{error}");
+    }
+}
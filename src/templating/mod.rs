@@ -0,0 +1,545 @@
+//! Template rendering for job definitions
+//!
+//! Job files may reference `{{ name }}` variables from `vars:`, including
+//! nested values (`{{ db.postgres.version }}`), along with per-job metadata
+//! (`job_name`, `workflow_name`, `architecture`, `matrix`). Rendering happens
+//! after matrix expansion so each variant sees its own architecture. GitHub
+//! Actions `${{ ... }}` expressions and CircleCI cache key templates
+//! (`{{ checksum "Gemfile.lock" }}`, `{{ .Branch }}`, `{{ epoch }}`,
+//! `{{ arch }}`) are left untouched.
+//!
+//! Loops and conditionals (`{% ... %}`) only work in `.j2` job files, which are
+//! rendered as a whole before they are parsed; see [`TemplateEngine::render_template_file`].
+//! Those files can also `{% include %}` other files.
+//!
+//! Templates can call `checksum(path)`, `file_exists(path)` and
+//! `read_file(path)`; see [`functions`]. `{{ <tool>_version }}` variables that
+//! `vars:` doesn't define are read from version files; see [`versions`].
+
+use anyhow::{Context, Result, bail};
+use minijinja::value::Object;
+use minijinja::{Environment, UndefinedBehavior};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_yaml::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub mod functions;
+mod includes;
+pub mod versions;
+
+use crate::schema::{Job, VersionSource};
+use versions::VersionResolver;
+
+/// Extension of job files that are rendered as a whole before parsing
+pub const TEMPLATE_EXTENSION: &str = "j2";
+
+/// Architecture assumed when a job doesn't specify one
+pub const DEFAULT_ARCHITECTURE: &str = "amd64";
+
+/// Placeholder used to shield `${{ ... }}` expressions from the template engine
+const GITHUB_EXPRESSION_PLACEHOLDER: &str = "\u{0}cigen-gh-expr\u{0}";
+
+/// CircleCI's own cache key templates, which must reach the provider verbatim
+static CIRCLECI_KEY_TEMPLATE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"\{\{\s*(?:checksum\s+"[^"]*"|\.[A-Za-z_][A-Za-z0-9_.]*|epoch|arch)\s*\}\}"#)
+        .expect("valid regex")
+});
+
+/// Per-job values exposed to templates alongside `vars:`
+#[derive(Debug, Clone, Default)]
+pub struct JobMetadata {
+    /// Generated job name (the matrix instance id)
+    pub job_name: String,
+    /// Workflow the job belongs to
+    pub workflow_name: String,
+    /// Resolved architecture for this variant
+    pub architecture: Option<String>,
+    /// Matrix values for this variant
+    pub matrix: HashMap<String, String>,
+}
+
+/// Renders `{{ ... }}` templates in config values
+pub struct TemplateEngine {
+    env: Environment<'static>,
+    vars: BTreeMap<String, Value>,
+    template_dir: Option<PathBuf>,
+    versions: VersionResolver,
+}
+
+impl TemplateEngine {
+    pub fn new(vars: &HashMap<String, Value>) -> Self {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.set_keep_trailing_newline(true);
+        // `{% ... %}` lines in `.j2` files leave no blank lines in the YAML
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        functions::register(&mut env, Path::new("."));
+
+        Self {
+            env,
+            vars: vars
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            template_dir: None,
+            versions: VersionResolver::default(),
+        }
+    }
+
+    /// Resolve paths given to template functions against `root`
+    pub fn with_project_root(mut self, root: &Path) -> Self {
+        functions::register(&mut self.env, root);
+        self.versions.set_root(root);
+        self
+    }
+
+    /// Read `{{ <tool>_version }}` from `sources` as well as the built-in
+    /// version sources
+    pub fn with_version_sources(mut self, sources: &HashMap<String, Vec<VersionSource>>) -> Self {
+        self.versions = VersionResolver::new(sources, self.versions.root());
+        self
+    }
+
+    /// Also resolve `.j2` includes against `dir` (normally `.cigen/templates/`)
+    pub fn with_template_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.template_dir = Some(dir.into());
+        self
+    }
+
+    /// Render every templated string in a job definition
+    pub fn render_job(&self, job: &Job, metadata: &JobMetadata) -> Result<Job> {
+        let context = self.job_context(metadata);
+
+        let mut value = serde_yaml::to_value(job).context("Failed to serialize job")?;
+        if !contains_template(&value) {
+            return Ok(job.clone());
+        }
+        let from_template_file = job.source_file.as_deref().is_some_and(is_template_file);
+        if !from_template_file && contains_statement(&value) {
+            // Only split job files can be renamed to `.j2`
+            let file = job
+                .source_file
+                .as_deref()
+                .and_then(Path::file_name)
+                .map(|name| name.to_string_lossy().into_owned())
+                .filter(|name| !name.starts_with("cigen."));
+            match file {
+                Some(file) => bail!(
+                    "Job '{}' uses `{{% ... %}}` in {file}, but loops and conditionals only work in `.{TEMPLATE_EXTENSION}` job files; rename it to {file}.{TEMPLATE_EXTENSION}",
+                    metadata.job_name
+                ),
+                None => bail!(
+                    "Job '{}' uses `{{% ... %}}`, but loops and conditionals only work in `.{TEMPLATE_EXTENSION}` job files",
+                    metadata.job_name
+                ),
+            }
+        }
+        self.render_value(&mut value, &context).with_context(|| {
+            format!("Failed to render templates in job '{}'", metadata.job_name)
+        })?;
+
+        let mut rendered: Job = serde_yaml::from_value(value).with_context(|| {
+            format!(
+                "Job '{}' is invalid after rendering templates",
+                metadata.job_name
+            )
+        })?;
+        // Fields that are not serialized are carried over from the source job
+        rendered.workflow = job.workflow.clone();
+        rendered.stage = job.stage.clone();
+        rendered.architecture = job.architecture.clone();
+        rendered.source_file = job.source_file.clone();
+        rendered.source_section = job.source_section.clone();
+        Ok(rendered)
+    }
+
+    /// Render the values of a config-wide `env:` (no `workflow_name`) or a
+    /// workflow's `env:` against `vars:`. The per-job values (`job_name`,
+    /// `architecture`, `matrix`) differ between the jobs sharing it, so they're
+    /// undefined here.
+    pub fn render_env(
+        &self,
+        env: &mut HashMap<String, String>,
+        workflow_name: Option<&str>,
+    ) -> Result<()> {
+        let mut context = self.vars_map();
+        if let Some(workflow_name) = workflow_name {
+            context.insert(
+                "workflow_name".to_string(),
+                minijinja::Value::from(workflow_name),
+            );
+        }
+        let context = minijinja::Value::from_serialize(&context);
+        for (name, value) in env.iter_mut() {
+            if is_template(value) {
+                *value = self
+                    .render_str(value, &context)
+                    .with_context(|| format!("Failed to render `{name}`"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Render the `.j2` job file at `path` before it is parsed, with includes,
+    /// loops and conditionals over `vars:`. The per-variant values
+    /// (`job_name`, `architecture`, `matrix`) aren't known yet, so they are
+    /// written back out as `{{ ... }}` for [`Self::render_job`] to fill in.
+    pub fn render_template_file(
+        &self,
+        path: &Path,
+        text: &str,
+        workflow_name: &str,
+    ) -> Result<String> {
+        let expanded = includes::expand_includes(text, path, self.template_dir.as_deref())?;
+        let mut context = self.vars_map();
+        context.insert(
+            "workflow_name".to_string(),
+            minijinja::Value::from(workflow_name),
+        );
+        for name in ["job_name", "architecture", "matrix"] {
+            context.insert(
+                name.to_string(),
+                minijinja::Value::from_object(Deferred(name.to_string())),
+            );
+        }
+        let rendered =
+            self.render_str(&expanded.text, &minijinja::Value::from_serialize(&context))?;
+        Ok(expanded.restore_raw(rendered))
+    }
+
+    /// Render a single template string against `context`
+    pub fn render_str(&self, template: &str, context: &minijinja::Value) -> Result<String> {
+        let mut preserved = Vec::new();
+        let shielded = CIRCLECI_KEY_TEMPLATE
+            .replace_all(template, |captures: &regex::Captures| {
+                preserved.push(captures[0].to_string());
+                format!("\u{0}cigen-key-{}\u{0}", preserved.len() - 1)
+            })
+            .replace("${{", GITHUB_EXPRESSION_PLACEHOLDER);
+        if !is_template(&shielded) {
+            return Ok(template.to_string());
+        }
+
+        let context = self.with_versions(&shielded, context)?;
+        let mut rendered = self
+            .env
+            .render_str(&shielded, context)
+            .map_err(|err| anyhow::anyhow!("{err:#}"))
+            .with_context(|| format!("Failed to render template '{template}'"))?
+            .replace(GITHUB_EXPRESSION_PLACEHOLDER, "${{");
+        for (index, original) in preserved.iter().enumerate() {
+            rendered = rendered.replace(&format!("\u{0}cigen-key-{index}\u{0}"), original);
+        }
+        Ok(rendered)
+    }
+
+    /// `context` plus the `<tool>_version` variables `template` uses that
+    /// nothing defines, read from version sources
+    fn with_versions(
+        &self,
+        template: &str,
+        context: &minijinja::Value,
+    ) -> Result<minijinja::Value> {
+        // Syntax errors are reported when the template is rendered
+        let Ok(parsed) = self.env.template_from_str(template) else {
+            return Ok(context.clone());
+        };
+        let mut versions = BTreeMap::new();
+        for variable in parsed.undeclared_variables(false) {
+            let Some(tool) = self.versions.tool_for(&variable) else {
+                continue;
+            };
+            if context
+                .get_attr(&variable)
+                .is_ok_and(|value| !value.is_undefined())
+            {
+                continue;
+            }
+            let version = self.versions.resolve(tool)?.version;
+            versions.insert(variable, version);
+        }
+        if versions.is_empty() {
+            return Ok(context.clone());
+        }
+        Ok(minijinja::context! {
+            ..context.clone(),
+            ..minijinja::Value::from_serialize(&versions)
+        })
+    }
+
+    fn vars_map(&self) -> BTreeMap<String, minijinja::Value> {
+        self.vars
+            .iter()
+            .map(|(key, value)| (key.clone(), minijinja::Value::from_serialize(value)))
+            .collect()
+    }
+
+    fn job_context(&self, metadata: &JobMetadata) -> minijinja::Value {
+        let mut context = self.vars_map();
+        context.insert(
+            "job_name".to_string(),
+            minijinja::Value::from(metadata.job_name.clone()),
+        );
+        context.insert(
+            "workflow_name".to_string(),
+            minijinja::Value::from(metadata.workflow_name.clone()),
+        );
+        context.insert(
+            "architecture".to_string(),
+            minijinja::Value::from(
+                metadata
+                    .architecture
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_ARCHITECTURE.to_string()),
+            ),
+        );
+        context.insert(
+            "matrix".to_string(),
+            minijinja::Value::from_serialize(&metadata.matrix),
+        );
+        minijinja::Value::from_serialize(&context)
+    }
+
+    fn render_value(&self, value: &mut Value, context: &minijinja::Value) -> Result<()> {
+        match value {
+            Value::String(text) if is_template(text) => {
+                *text = self.render_str(text, context)?;
+            }
+            Value::Sequence(items) => {
+                for item in items {
+                    self.render_value(item, context)?;
+                }
+            }
+            Value::Mapping(map) => {
+                for (_, child) in map.iter_mut() {
+                    self.render_value(child, context)?;
+                }
+            }
+            Value::Tagged(tagged) => self.render_value(&mut tagged.value, context)?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// A value only known per job variant, rendered back as `{{ name }}` (or
+/// `{{ matrix.<key> }}`) when a `.j2` file is rendered before parsing
+#[derive(Debug)]
+struct Deferred(String);
+
+impl Object for Deferred {
+    fn get_value(self: &Arc<Self>, key: &minijinja::Value) -> Option<minijinja::Value> {
+        Some(minijinja::Value::from_object(Deferred(format!(
+            "{}.{key}",
+            self.0
+        ))))
+    }
+
+    fn render(self: &Arc<Self>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{{{ {} }}}}", self.0)
+    }
+}
+
+/// Whether `path` is a `.j2` template file
+pub fn is_template_file(path: &Path) -> bool {
+    path.extension().and_then(|extension| extension.to_str()) == Some(TEMPLATE_EXTENSION)
+}
+
+/// Whether a string contains template syntax (ignoring `${{ ... }}` expressions)
+fn is_template(text: &str) -> bool {
+    let shielded = text.replace("${{", "");
+    shielded.contains("{{") || shielded.contains("{%")
+}
+
+/// Whether any string in `value` uses `{% ... %}` statements
+fn contains_statement(value: &Value) -> bool {
+    match value {
+        Value::String(text) => text.contains("{%"),
+        Value::Sequence(items) => items.iter().any(contains_statement),
+        Value::Mapping(map) => map.values().any(contains_statement),
+        Value::Tagged(tagged) => contains_statement(&tagged.value),
+        _ => false,
+    }
+}
+
+fn contains_template(value: &Value) -> bool {
+    match value {
+        Value::String(text) => is_template(text),
+        Value::Sequence(items) => items.iter().any(contains_template),
+        Value::Mapping(map) => map.values().any(contains_template),
+        Value::Tagged(tagged) => contains_template(&tagged.value),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(arch: Option<&str>) -> JobMetadata {
+        JobMetadata {
+            job_name: "build".to_string(),
+            workflow_name: "ci".to_string(),
+            architecture: arch.map(str::to_string),
+            matrix: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_render_job_metadata() {
+        let job: Job = serde_yaml::from_str(
+            r#"
+steps:
+  - run: echo "{{ job_name }} in {{ workflow_name }} on {{ architecture }}"
+"#,
+        )
+        .unwrap();
+        let engine = TemplateEngine::new(&HashMap::new());
+
+        let rendered = engine.render_job(&job, &metadata(Some("arm64"))).unwrap();
+        let yaml = serde_yaml::to_string(&rendered.steps).unwrap();
+        assert!(yaml.contains("build in ci on arm64"), "{yaml}");
+
+        let rendered = engine.render_job(&job, &metadata(None)).unwrap();
+        let yaml = serde_yaml::to_string(&rendered.steps).unwrap();
+        assert!(yaml.contains("on amd64"), "{yaml}");
+    }
+
+    #[test]
+    fn test_vars_and_github_expressions() {
+        let mut vars = HashMap::new();
+        vars.insert("ruby_version".to_string(), Value::String("3.3".to_string()));
+        let engine = TemplateEngine::new(&vars);
+        let context = engine.job_context(&metadata(None));
+
+        let rendered = engine
+            .render_str(
+                "ruby-{{ ruby_version }}-${{ hashFiles('Gemfile.lock') }}\n",
+                &context,
+            )
+            .unwrap();
+        assert_eq!(rendered, "ruby-3.3-${{ hashFiles('Gemfile.lock') }}\n");
+    }
+
+    #[test]
+    fn test_circleci_key_templates_are_preserved() {
+        let engine = TemplateEngine::new(&HashMap::new());
+        let context = engine.job_context(&metadata(Some("arm64")));
+
+        let key =
+            r#"gems-{{ architecture }}-{{ checksum "Gemfile.lock" }}-{{ .Branch }}-{{ arch }}"#;
+        assert_eq!(
+            engine.render_str(key, &context).unwrap(),
+            r#"gems-arm64-{{ checksum "Gemfile.lock" }}-{{ .Branch }}-{{ arch }}"#
+        );
+
+        let native_only = r#"v1-{{ checksum "package.json" }}-{{ epoch }}"#;
+        assert_eq!(
+            engine.render_str(native_only, &context).unwrap(),
+            native_only
+        );
+    }
+
+    #[test]
+    fn test_undefined_variable_names_job() {
+        let job: Job = serde_yaml::from_str("steps:\n  - run: echo {{ missing }}\n").unwrap();
+        let engine = TemplateEngine::new(&HashMap::new());
+        let error = format!(
+            "{:#}",
+            engine.render_job(&job, &metadata(None)).unwrap_err()
+        );
+        assert!(error.contains("job 'build'"), "{error}");
+        assert!(error.contains("missing"), "{error}");
+    }
+
+    #[test]
+    fn test_nested_vars() {
+        let vars: HashMap<String, Value> =
+            serde_yaml::from_str("db:\n  postgres:\n    version: '16'\n").unwrap();
+        let job: Job = serde_yaml::from_str(
+            "image: postgres:{{ db.postgres.version }}\nsteps:\n  - run: echo {{ db.postgres.version }}\n",
+        )
+        .unwrap();
+
+        let rendered = TemplateEngine::new(&vars)
+            .render_job(&job, &metadata(None))
+            .unwrap();
+        assert_eq!(rendered.image, "postgres:16");
+    }
+
+    #[test]
+    fn test_statements_need_template_files() {
+        let mut job: Job = serde_yaml::from_str(
+            "steps:\n  - run: \"{% for v in versions %}echo {{ v }};{% endfor %}\"\n",
+        )
+        .unwrap();
+        job.source_file = Some(".cigen/workflows/ci/jobs/build.yml".into());
+        let engine = TemplateEngine::new(&HashMap::new());
+
+        let error = engine
+            .render_job(&job, &metadata(None))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            "Job 'build' uses `{% ... %}` in build.yml, but loops and conditionals only work in `.j2` job files; rename it to build.yml.j2"
+        );
+    }
+
+    #[test]
+    fn test_template_file_defers_variant_values() {
+        let vars: HashMap<String, Value> =
+            serde_yaml::from_str("versions: ['3.2', '3.3']\ndeploy: false\n").unwrap();
+        let engine = TemplateEngine::new(&vars);
+        let text = r#"steps:
+{% for version in versions %}
+  - run: echo {{ version }} on {{ architecture }} in {{ workflow_name }}
+{% endfor %}
+{% if deploy %}
+  - run: ./deploy
+{% endif %}
+  - run: echo {{ matrix.ruby }} {{ checksum "Gemfile.lock" }}
+"#;
+
+        let rendered = engine
+            .render_template_file(Path::new("rspec.yml.j2"), text, "ci")
+            .unwrap();
+        assert_eq!(
+            rendered,
+            r#"steps:
+  - run: echo 3.2 on {{ architecture }} in ci
+  - run: echo 3.3 on {{ architecture }} in ci
+  - run: echo {{ matrix.ruby }} {{ checksum "Gemfile.lock" }}
+"#
+        );
+    }
+
+    #[test]
+    fn test_function_errors_name_the_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let job: Job =
+            serde_yaml::from_str("steps:\n  - run: echo {{ checksum('vendor/missing.lock') }}\n")
+                .unwrap();
+        let engine = TemplateEngine::new(&HashMap::new()).with_project_root(dir.path());
+
+        let error = format!(
+            "{:#}",
+            engine.render_job(&job, &metadata(None)).unwrap_err()
+        );
+        assert!(error.contains("job 'build'"), "{error}");
+        assert!(
+            error
+                .contains("Failed to render template 'echo {{ checksum('vendor/missing.lock') }}'"),
+            "{error}"
+        );
+        assert!(
+            error.contains("checksum('vendor/missing.lock'): no such file"),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,320 @@
+//! Tool versions read from the project's version files
+//!
+//! A template that uses `{{ ruby_version }}` (or any `<tool>_version`) without
+//! defining it under `vars:` gets the version from the tool's version sources:
+//! files tried in order, relative to the project root. A source's `pattern`
+//! is a regex whose first capture group is the version, with `^` and `$`
+//! matching at line boundaries; without one, the first non-empty line is the
+//! version. `version_sources:` in the config replaces a tool's built-in list.
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::schema::VersionSource;
+
+/// Suffix that turns a tool name into its template variable
+pub const VERSION_SUFFIX: &str = "_version";
+
+/// A built-in source: the file and its pattern
+type DefaultSource = (&'static str, Option<&'static str>);
+
+/// Built-in version sources, in priority order
+const DEFAULT_SOURCES: &[(&str, &[DefaultSource])] = &[
+    (
+        "ruby",
+        &[
+            (".ruby-version", Some(r"^(?:ruby-)?(\S+)")),
+            (".tool-versions", Some(r"^ruby\s+(\S+)")),
+            ("Gemfile", Some(r#"^\s*ruby ['"](.+)['"]"#)),
+        ],
+    ),
+    (
+        "bundler",
+        &[("Gemfile.lock", Some(r"^BUNDLED WITH\s+(\S+)"))],
+    ),
+    (
+        "node",
+        &[
+            (".nvmrc", Some(r"^v?(\S+)")),
+            (".node-version", Some(r"^v?(\S+)")),
+            (".tool-versions", Some(r"^nodejs\s+(\S+)")),
+        ],
+    ),
+    (
+        "python",
+        &[
+            (".python-version", None),
+            (".tool-versions", Some(r"^python\s+(\S+)")),
+        ],
+    ),
+    (
+        "go",
+        &[(".go-version", None), ("go.mod", Some(r"^go\s+(\S+)"))],
+    ),
+    (
+        "rust",
+        &[
+            ("rust-toolchain.toml", Some(r#"^channel\s*=\s*"([^"]+)""#)),
+            ("rust-toolchain", None),
+        ],
+    ),
+    (
+        "java",
+        &[
+            (".java-version", None),
+            (".tool-versions", Some(r"^java\s+(\S+)")),
+        ],
+    ),
+];
+
+/// A version and the file it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedVersion {
+    pub version: String,
+    /// Source file, as configured
+    pub source: String,
+}
+
+/// None of a tool's version sources had a version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnresolvedVersion {
+    pub tool: String,
+    /// Each source file with why it had no version
+    pub checked: Vec<(String, &'static str)>,
+}
+
+impl fmt::Display for UnresolvedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tool = &self.tool;
+        write!(
+            f,
+            "No {tool} version for `{tool}{VERSION_SUFFIX}`: checked {}. Add one of these files, set `{tool}{VERSION_SUFFIX}` under `vars:`, or list other files under `version_sources.{tool}`",
+            self.checked_summary()
+        )
+    }
+}
+
+impl std::error::Error for UnresolvedVersion {}
+
+impl UnresolvedVersion {
+    /// The checked files with why each had no version
+    pub fn checked_summary(&self) -> String {
+        if self.checked.is_empty() {
+            return "no files (the tool has no version sources)".to_string();
+        }
+        self.checked
+            .iter()
+            .map(|(file, reason)| format!("{file} ({reason})"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Reads tool versions from version sources
+#[derive(Debug, Clone)]
+pub struct VersionResolver {
+    sources: BTreeMap<String, Vec<VersionSource>>,
+    root: PathBuf,
+}
+
+impl Default for VersionResolver {
+    fn default() -> Self {
+        Self::new(&HashMap::new(), Path::new("."))
+    }
+}
+
+impl VersionResolver {
+    /// The built-in sources with `configured` replacing them per tool
+    pub fn new(configured: &HashMap<String, Vec<VersionSource>>, root: &Path) -> Self {
+        let mut sources: BTreeMap<String, Vec<VersionSource>> = DEFAULT_SOURCES
+            .iter()
+            .map(|(tool, files)| {
+                let files = files
+                    .iter()
+                    .map(|(file, pattern)| VersionSource {
+                        file: file.to_string(),
+                        pattern: pattern.map(str::to_string),
+                    })
+                    .collect();
+                (tool.to_string(), files)
+            })
+            .collect();
+        sources.extend(
+            configured
+                .iter()
+                .map(|(tool, files)| (tool.clone(), files.clone())),
+        );
+        Self {
+            sources,
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// Resolve source files against `root`
+    pub fn set_root(&mut self, root: &Path) {
+        self.root = root.to_path_buf();
+    }
+
+    /// Directory source files are resolved against
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Tools with version sources, sorted
+    pub fn tools(&self) -> impl Iterator<Item = &str> {
+        self.sources.keys().map(String::as_str)
+    }
+
+    /// The tool `variable` reads, when it is `<tool>_version` for a tool with
+    /// version sources
+    pub fn tool_for<'a>(&self, variable: &'a str) -> Option<&'a str> {
+        variable
+            .strip_suffix(VERSION_SUFFIX)
+            .filter(|tool| self.sources.contains_key(*tool))
+    }
+
+    /// Read `tool`'s version from the first source that has one. Fails with
+    /// [`UnresolvedVersion`] when none does.
+    pub fn resolve(&self, tool: &str) -> Result<ResolvedVersion> {
+        let mut checked = Vec::new();
+        for source in self.sources.get(tool).into_iter().flatten() {
+            let path = self.root.join(&source.file);
+            let Ok(contents) = std::fs::read_to_string(&path) else {
+                checked.push((source.file.clone(), "missing"));
+                continue;
+            };
+            match extract_version(&contents, source.pattern.as_deref()).with_context(|| {
+                format!(
+                    "Invalid pattern for version_sources.{tool} ({})",
+                    source.file
+                )
+            })? {
+                Some(version) => {
+                    return Ok(ResolvedVersion {
+                        version,
+                        source: source.file.clone(),
+                    });
+                }
+                None => checked.push((source.file.clone(), "no version found")),
+            }
+        }
+        Err(UnresolvedVersion {
+            tool: tool.to_string(),
+            checked,
+        }
+        .into())
+    }
+}
+
+/// The version in `contents`: the first capture group of `pattern` (or its
+/// whole match), or else the first non-empty line
+fn extract_version(contents: &str, pattern: Option<&str>) -> Result<Option<String>> {
+    let version = match pattern {
+        Some(pattern) => {
+            let regex = Regex::new(&format!("(?m){pattern}"))?;
+            regex.captures(contents).and_then(|captures| {
+                captures
+                    .get(1)
+                    .or_else(|| captures.get(0))
+                    .map(|version| version.as_str().trim().to_string())
+            })
+        }
+        None => contents
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(str::to_string),
+    };
+    Ok(version.filter(|version| !version.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn sources_are_tried_in_order() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("Gemfile"),
+            "source \"https://rubygems.org\"\nruby '3.2.4'\n",
+        )
+        .unwrap();
+        let resolver = VersionResolver::new(&HashMap::new(), dir.path());
+        assert_eq!(
+            resolver.resolve("ruby").unwrap(),
+            ResolvedVersion {
+                version: "3.2.4".to_string(),
+                source: "Gemfile".to_string(),
+            }
+        );
+
+        std::fs::write(dir.path().join(".ruby-version"), "ruby-3.3.1\n").unwrap();
+        assert_eq!(resolver.resolve("ruby").unwrap().version, "3.3.1");
+    }
+
+    #[test]
+    fn bundler_and_node_defaults() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("Gemfile.lock"),
+            "GEM\n  specs:\n\nBUNDLED WITH\n   2.5.6\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join(".nvmrc"), "v20.11.0\n").unwrap();
+        let resolver = VersionResolver::new(&HashMap::new(), dir.path());
+        assert_eq!(resolver.resolve("bundler").unwrap().version, "2.5.6");
+        assert_eq!(resolver.resolve("node").unwrap().version, "20.11.0");
+    }
+
+    #[test]
+    fn configured_sources_replace_the_defaults() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join(".nvmrc"), "20\n").unwrap();
+        std::fs::write(
+            dir.path().join("versions.env"),
+            "RUBY=3.1\nNODE_VERSION=18.19.0\n",
+        )
+        .unwrap();
+        let configured = HashMap::from([(
+            "node".to_string(),
+            vec![VersionSource {
+                file: "versions.env".to_string(),
+                pattern: Some("^NODE_VERSION=(.+)$".to_string()),
+            }],
+        )]);
+        let resolver = VersionResolver::new(&configured, dir.path());
+        assert_eq!(
+            resolver.resolve("node").unwrap(),
+            ResolvedVersion {
+                version: "18.19.0".to_string(),
+                source: "versions.env".to_string(),
+            }
+        );
+        assert_eq!(resolver.tool_for("node_version"), Some("node"));
+        assert_eq!(resolver.tool_for("deno_version"), None);
+    }
+
+    #[test]
+    fn missing_versions_list_the_checked_files() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join(".tool-versions"), "nodejs 20.11.0\n").unwrap();
+        let resolver = VersionResolver::new(&HashMap::new(), dir.path());
+        let error = resolver.resolve("ruby").unwrap_err();
+        let unresolved = error.downcast_ref::<UnresolvedVersion>().unwrap();
+        assert_eq!(
+            unresolved.checked_summary(),
+            ".ruby-version (missing), .tool-versions (no version found), Gemfile (missing)"
+        );
+        assert!(
+            error
+                .to_string()
+                .starts_with("No ruby version for `ruby_version`: checked .ruby-version (missing)"),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! The supported library API (`cigen::api`). Its surface is pinned by the
+//! `api__public_api_is_unchanged` snapshot: a change that breaks it must be a
+//! deliberate semver bump.
+
+use anyhow::Result;
+use assert_cmd::prelude::*;
+use cigen::api::{self, GenerateOptions, Severity};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use syn::{Attribute, Fields, ImplItem, Item, ItemImpl, Type, UseTree, Visibility};
+use tempfile::tempdir;
+
+/// `cigen::api` as its users see it: every item with its public fields,
+/// variants and method signatures, without docs or bodies
+fn public_api() -> String {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+    let parse = |path: PathBuf| syn::parse_file(&fs::read_to_string(path).unwrap()).unwrap();
+    let api = parse(root.join("api.rs"));
+    let mut schema: Vec<Item> = Vec::new();
+    for entry in fs::read_dir(root.join("schema")).unwrap() {
+        schema.extend(parse(entry.unwrap().path()).items);
+    }
+
+    let mut reexports = Vec::new();
+    for item in &api.items {
+        if let Item::Use(item) = item
+            && matches!(item.vis, Visibility::Public(_))
+        {
+            use_names(&item.tree, &mut reexports);
+        }
+    }
+    reexports.sort();
+
+    let mut items = Vec::new();
+    let defined_in_api = api.items.iter().filter_map(type_name).collect::<Vec<_>>();
+    for name in &defined_in_api {
+        items.extend(type_items(name, &api.items));
+    }
+    items.extend(api.items.iter().filter_map(|item| match item {
+        Item::Fn(function) if matches!(function.vis, Visibility::Public(_)) => {
+            let mut function = function.clone();
+            function.attrs.clear();
+            function.block.stmts.clear();
+            Some(Item::Fn(function))
+        }
+        _ => None,
+    }));
+    for name in &reexports {
+        let found = type_items(name, &schema);
+        assert!(
+            !found.is_empty(),
+            "re-exported `{name}` not found in src/schema"
+        );
+        items.extend(found);
+    }
+
+    let file = syn::File {
+        shebang: None,
+        attrs: Vec::new(),
+        items,
+    };
+    prettyplease::unparse(&file)
+        .lines()
+        .map(|line| match line.strip_suffix(" {}") {
+            // Only private fields left
+            Some(item) if item.starts_with("pub struct ") => {
+                format!("{item} {{ /* private fields */ }}\n")
+            }
+            Some(item) if item.starts_with("impl ") => format!("{line}\n"),
+            // A function without its body
+            Some(signature) => format!("{signature};\n"),
+            None => format!("{line}\n"),
+        })
+        .collect()
+}
+
+fn use_names(tree: &UseTree, names: &mut Vec<String>) {
+    match tree {
+        UseTree::Path(path) => use_names(&path.tree, names),
+        UseTree::Name(name) => names.push(name.ident.to_string()),
+        UseTree::Rename(rename) => names.push(rename.rename.to_string()),
+        UseTree::Group(group) => group.items.iter().for_each(|tree| use_names(tree, names)),
+        UseTree::Glob(_) => panic!("glob re-exports can't be snapshotted"),
+    }
+}
+
+fn type_name(item: &Item) -> Option<String> {
+    match item {
+        Item::Struct(item) if matches!(item.vis, Visibility::Public(_)) => {
+            Some(item.ident.to_string())
+        }
+        Item::Enum(item) if matches!(item.vis, Visibility::Public(_)) => {
+            Some(item.ident.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// The definition of type `name` among `items` and its impl blocks, keeping
+/// only what other crates can use
+fn type_items(name: &str, items: &[Item]) -> Vec<Item> {
+    let mut found = Vec::new();
+    for item in items {
+        match item {
+            Item::Struct(item) if item.ident == name => {
+                let mut item = item.clone();
+                item.attrs.retain(api_attribute);
+                let fields = match &mut item.fields {
+                    Fields::Named(fields) => Some(&mut fields.named),
+                    Fields::Unnamed(fields) => Some(&mut fields.unnamed),
+                    Fields::Unit => None,
+                };
+                if let Some(fields) = fields {
+                    *fields = std::mem::take(fields)
+                        .into_iter()
+                        .filter(|field| matches!(field.vis, Visibility::Public(_)))
+                        .map(|mut field| {
+                            field.attrs.clear();
+                            field
+                        })
+                        .collect();
+                }
+                found.push(Item::Struct(item));
+            }
+            Item::Enum(item) if item.ident == name => {
+                let mut item = item.clone();
+                item.attrs.retain(api_attribute);
+                for variant in &mut item.variants {
+                    variant.attrs.clear();
+                    variant
+                        .fields
+                        .iter_mut()
+                        .for_each(|field| field.attrs.clear());
+                }
+                found.push(Item::Enum(item));
+            }
+            Item::Impl(item) if impl_self_name(item).as_deref() == Some(name) => {
+                let mut item = item.clone();
+                item.attrs.clear();
+                if item.trait_.is_some() {
+                    item.items.clear();
+                } else {
+                    item.items.retain_mut(|item| match item {
+                        ImplItem::Fn(function) if matches!(function.vis, Visibility::Public(_)) => {
+                            function.attrs.clear();
+                            function.block.stmts.clear();
+                            true
+                        }
+                        _ => false,
+                    });
+                    if item.items.is_empty() {
+                        continue;
+                    }
+                }
+                found.push(Item::Impl(item));
+            }
+            _ => {}
+        }
+    }
+    found
+}
+
+fn impl_self_name(item: &ItemImpl) -> Option<String> {
+    match item.self_ty.as_ref() {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident.to_string()),
+        _ => None,
+    }
+}
+
+/// Derives and `#[non_exhaustive]` are part of the API; docs and serde
+/// attributes aren't
+fn api_attribute(attribute: &Attribute) -> bool {
+    attribute.path().is_ident("derive") || attribute.path().is_ident("non_exhaustive")
+}
+
+#[test]
+fn public_api_is_unchanged() {
+    // A change here must be a deliberate semver bump; review the snapshot
+    // diff and accept it with `cargo insta accept`
+    insta::assert_snapshot!(public_api());
+}
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+        .join(".cigen")
+}
+
+#[test]
+fn generate_matches_the_cli() -> Result<()> {
+    for (name, provider) in [("caches", "circleci"), ("shell_github", "github")] {
+        let config_path = fixture(name);
+        let output = tempdir()?;
+        Command::cargo_bin("cigen")?
+            .current_dir(config_path.parent().unwrap())
+            .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+            .args(["generate", "--config", ".cigen", "--output"])
+            .arg(output.path())
+            .assert()
+            .success();
+
+        let config = api::load_config(&config_path)?;
+        assert_eq!(config.path(), config_path);
+        let mut options = GenerateOptions::default();
+        options.output_dir = Some(output.path().to_string_lossy().into_owned());
+        let files = api::generate(provider, &config, &options)?;
+        assert!(!files.is_empty(), "{name}: no files generated");
+        for file in &files {
+            let written = fs::read_to_string(output.path().join(&file.path))?;
+            assert_eq!(file.content, written, "{name}: {}", file.path);
+        }
+        let mut paths: Vec<&str> = files.iter().map(|file| file.path.as_str()).collect();
+        paths.sort_unstable();
+        assert_eq!(
+            paths,
+            files
+                .iter()
+                .map(|file| file.path.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn validate_reports_load_errors_and_lint_warnings() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().join(".cigen");
+    fs::create_dir_all(root.join("workflows/ci/jobs")).unwrap();
+    fs::write(root.join("config.yml"), "provider: circleci\n").unwrap();
+    fs::write(
+        root.join("workflows/ci/jobs/test.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+    )
+    .unwrap();
+
+    let diagnostics = api::validate(&root);
+    assert_eq!(diagnostics.len(), 1, "{diagnostics:#?}");
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(diagnostics[0].code.as_deref(), Some("CIGEN-W001"));
+
+    fs::write(
+        root.join("workflows/ci/jobs/test.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run:\n      comand: make test\n",
+    )
+    .unwrap();
+    let diagnostics = api::validate(&root);
+    assert_eq!(diagnostics.len(), 1, "{diagnostics:#?}");
+    let error = &diagnostics[0];
+    assert_eq!(error.severity, Severity::Error);
+    assert_eq!(error.code, None);
+    assert!(error.message.contains("Unknown key `comand`"), "{error:?}");
+    let location = error.location.as_ref().expect("error should be located");
+    assert!(location.file.ends_with("test.yml"), "{location:?}");
+    assert_eq!((location.line, location.column), (4, 7));
+}
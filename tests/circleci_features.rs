@@ -0,0 +1,2651 @@
+use assert_cmd::prelude::*;
+use serde_yaml::Value;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::{TempDir, tempdir};
+
+fn repo_root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Write a split `.cigen` config with the given root config and `main` workflow jobs.
+fn write_config(root_config: &str, jobs: &[(&str, &str)]) -> TempDir {
+    let dir = tempdir().expect("failed to create tempdir");
+    let config_dir = dir.path().join(".cigen");
+    let jobs_dir = config_dir.join("workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(config_dir.join("config.yml"), root_config).unwrap();
+    for (name, content) in jobs {
+        fs::write(jobs_dir.join(format!("{name}.yml")), content).unwrap();
+    }
+    dir
+}
+
+fn generate_command(project: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("cigen").expect("cigen binary not found");
+    cmd.arg("generate")
+        .arg("--config")
+        .arg(project.join(".cigen"))
+        .arg("--output")
+        .arg(project.join("out"))
+        .current_dir(repo_root())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1");
+    cmd
+}
+
+fn generate(project: &Path) -> Value {
+    generate_command(project).assert().success();
+
+    let yaml = fs::read_to_string(project.join("out/.circleci/main.yml")).unwrap();
+    serde_yaml::from_str(&yaml).unwrap()
+}
+
+fn job_steps<'a>(main: &'a Value, job_id: &str) -> &'a Vec<Value> {
+    main["jobs"][job_id]["steps"]
+        .as_sequence()
+        .unwrap_or_else(|| panic!("job {job_id} has no steps"))
+}
+
+#[test]
+fn test_results_emit_store_test_results_and_split_command() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "rspec",
+            "image: cimg/ruby:3.3\nparallelism: 4\ntest_results: tmp/junit\nsteps:\n  - run: bundle exec rspec\n",
+        )],
+    );
+    let main = generate(project.path());
+
+    let steps = job_steps(&main, "rspec");
+    assert!(
+        steps
+            .iter()
+            .any(|step| step["store_test_results"]["path"].as_str() == Some("tmp/junit"))
+    );
+    assert!(
+        steps
+            .iter()
+            .any(|step| step["cigen_prepare_test_results"]["path"].as_str() == Some("tmp/junit"))
+    );
+    assert!(main["commands"].get("cigen_prepare_test_results").is_some());
+}
+
+#[test]
+fn resource_class_aliases_resolve_per_architecture() {
+    let project = write_config(
+        "provider: circleci\nresource_classes:\n  large:\n    amd64: large\n    arm64: arm.large\n",
+        &[
+            (
+                "build",
+                "image: cimg/base:current\nresource_class: large\nmatrix:\n  arch: [amd64, arm64]\nsteps:\n  - run: make\n",
+            ),
+            (
+                "lint",
+                "image: cimg/base:current\nresource_class: 2xlarge\narch: arm64\nsteps:\n  - run: make lint\n",
+            ),
+        ],
+    );
+    let main = generate(project.path());
+
+    assert_eq!(
+        main["jobs"]["build-amd64"]["resource_class"].as_str(),
+        Some("large")
+    );
+    assert_eq!(
+        main["jobs"]["build-arm64"]["resource_class"].as_str(),
+        Some("arm.large")
+    );
+    assert_eq!(
+        main["jobs"]["lint"]["resource_class"].as_str(),
+        Some("2xlarge")
+    );
+}
+
+const PLAINTEXT_AUTH_CONFIG: &str = r#"provider: circleci
+docker:
+  default_auth: hub
+  auth:
+    hub:
+      username: $DOCKERHUB_USERNAME
+      password: hunter2
+"#;
+
+#[test]
+fn plaintext_docker_auth_emits_warning() {
+    let project = write_config(
+        PLAINTEXT_AUTH_CONFIG,
+        &[("build", "image: cimg/base:current\nsteps:\n  - run: make\n")],
+    );
+
+    let output = generate_command(project.path()).assert().success();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(stderr.contains("CIRCLECI_PLAINTEXT_SECRET"), "{stderr}");
+    assert!(stderr.contains("docker.auth.hub.password"), "{stderr}");
+    assert!(!stderr.contains("docker.auth.hub.username"), "{stderr}");
+}
+
+#[test]
+fn plaintext_docker_auth_fails_with_strict_secrets() {
+    let project = write_config(
+        &format!("{PLAINTEXT_AUTH_CONFIG}strict_secrets: true\n"),
+        &[("build", "image: cimg/base:current\nsteps:\n  - run: make\n")],
+    );
+
+    generate_command(project.path()).assert().failure();
+}
+
+#[test]
+fn docker_auth_from_env_is_attached_to_images() {
+    let project = write_config(
+        "provider: circleci\ndocker:\n  default_auth: hub\n  auth:\n    hub:\n      username: $DOCKERHUB_USERNAME\n      password: ${DOCKERHUB_TOKEN}\n",
+        &[("build", "image: cimg/base:current\nsteps:\n  - run: make\n")],
+    );
+
+    let output = generate_command(project.path()).assert().success();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(!stderr.contains("CIRCLECI_PLAINTEXT_SECRET"), "{stderr}");
+
+    let yaml = fs::read_to_string(project.path().join("out/.circleci/main.yml")).unwrap();
+    let main: Value = serde_yaml::from_str(&yaml).unwrap();
+    assert_eq!(
+        main["jobs"]["build"]["docker"][0]["auth"]["password"].as_str(),
+        Some("${DOCKERHUB_TOKEN}")
+    );
+}
+
+#[test]
+fn env_precedence_is_job_then_workflow_then_global() {
+    let project = write_config(
+        "provider: circleci\nenv:\n  LEVEL: global\n  WORKFLOW_OR_GLOBAL: global\n  GLOBAL_ONLY: global\n",
+        &[(
+            "build",
+            "image: cimg/base:current\nenv:\n  LEVEL: job\nsteps:\n  - run: make\n",
+        )],
+    );
+    fs::write(
+        project.path().join(".cigen/workflows/main/config.yml"),
+        "env:\n  LEVEL: workflow\n  WORKFLOW_OR_GLOBAL: workflow\n",
+    )
+    .unwrap();
+
+    let main = generate(project.path());
+    let environment = &main["jobs"]["build"]["environment"];
+    assert_eq!(environment["LEVEL"].as_str(), Some("job"));
+    assert_eq!(environment["WORKFLOW_OR_GLOBAL"].as_str(), Some("workflow"));
+    assert_eq!(environment["GLOBAL_ONLY"].as_str(), Some("global"));
+}
+
+#[test]
+fn global_and_workflow_env_render_templates_like_job_environment() {
+    let project = write_config(
+        "provider: circleci\nvars:\n  ruby: \"3.3\"\nenv:\n  RUBY_VERSION: \"{{ ruby }}\"\ncircleci:\n  env:\n    GEM_HOME: \"/gems/{{ ruby }}\"\n",
+        &[(
+            "build",
+            "image: cimg/base:current\nenvironment:\n  JOB_RUBY: \"{{ ruby }}\"\nsteps:\n  - run: make\n",
+        )],
+    );
+    fs::write(
+        project.path().join(".cigen/workflows/main/config.yml"),
+        "env:\n  DEPLOY_TAG: \"{{ workflow_name }}-{{ ruby }}\"\n",
+    )
+    .unwrap();
+
+    let main = generate(project.path());
+    let environment = &main["jobs"]["build"]["environment"];
+    assert_eq!(environment["RUBY_VERSION"].as_str(), Some("3.3"));
+    assert_eq!(environment["GEM_HOME"].as_str(), Some("/gems/3.3"));
+    assert_eq!(environment["DEPLOY_TAG"].as_str(), Some("main-3.3"));
+    assert_eq!(environment["JOB_RUBY"].as_str(), Some("3.3"));
+}
+
+#[test]
+fn job_metadata_is_available_to_templates_per_variant() {
+    let project = write_config(
+        "provider: circleci\nvars:\n  cache_version: v2\n",
+        &[(
+            "build",
+            r#"image: cimg/base:current
+matrix:
+  arch: [amd64, arm64]
+steps:
+  - restore_cache:
+      key: gems-{{ architecture }}-{{ cache_version }}
+  - run: echo "{{ job_name }} in {{ workflow_name }}"
+"#,
+        )],
+    );
+    let main = generate(project.path());
+
+    let cache_key = |job_id: &str| {
+        job_steps(&main, job_id)
+            .iter()
+            .find_map(|step| step["restore_cache"]["key"].as_str())
+            .unwrap_or_else(|| panic!("job {job_id} has no restore_cache step"))
+            .to_string()
+    };
+    assert_eq!(cache_key("build-amd64"), "gems-amd64-v2");
+    assert_eq!(cache_key("build-arm64"), "gems-arm64-v2");
+
+    let steps = serde_yaml::to_string(job_steps(&main, "build-arm64")).unwrap();
+    assert!(steps.contains("build-arm64 in main"), "{steps}");
+}
+
+#[test]
+fn step_conditions_compile_to_when_and_shell_guards() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "deploy",
+            r#"image: cimg/base:current
+steps:
+  - run:
+      name: Deploy
+      command: ./deploy.sh
+    if: branch == "main" && env.DEPLOY_TOKEN defined
+  - run: ./notify.sh
+    if: param.notify == true
+"#,
+        )],
+    );
+    let main = generate(project.path());
+    let steps = job_steps(&main, "deploy");
+
+    let deploy = steps
+        .iter()
+        .find(|step| step["when"]["steps"][0]["run"]["name"].as_str() == Some("Deploy"))
+        .expect("deploy step wrapped in when");
+    assert_eq!(
+        deploy["when"]["condition"]["equal"][1].as_str(),
+        Some("<< pipeline.git.branch >>")
+    );
+    let command = deploy["when"]["steps"][0]["run"]["command"]
+        .as_str()
+        .unwrap();
+    assert!(
+        command.starts_with("if [ -n \"${DEPLOY_TOKEN:-}\" ]; then\n./deploy.sh\n"),
+        "{command}"
+    );
+
+    assert!(steps.iter().any(|step| {
+        step["when"]["condition"]["equal"][1].as_str() == Some("<< pipeline.parameters.notify >>")
+    }));
+}
+
+#[test]
+fn unsupported_step_condition_names_job_and_step() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "deploy",
+            "image: cimg/base:current\nsteps:\n  - run: ./deploy.sh\n  - run: ./notify.sh\n    if: runner.os == 'Linux'\n",
+        )],
+    );
+
+    generate_command(project.path())
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Unsupported condition on step 2 of job 'deploy'",
+        ))
+        .stderr(predicates::str::contains("Unknown value 'runner.os'"));
+}
+
+#[test]
+fn remote_docker_is_set_up_right_after_checkout() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "image",
+            r#"image: cimg/base:current
+remote_docker:
+  version: "24.0"
+  layer_caching: true
+steps:
+  - restore_cache:
+      key: docker-v1
+  - run: docker build .
+"#,
+        )],
+    );
+    let main = generate(project.path());
+    let steps = job_steps(&main, "image");
+
+    let position = |predicate: &dyn Fn(&Value) -> bool| {
+        steps
+            .iter()
+            .position(predicate)
+            .expect("step should be present")
+    };
+    let checkout = position(&|step| {
+        step.as_str() == Some("checkout") || step.get("cigen_shallow_checkout").is_some()
+    });
+    let remote_docker = position(&|step| step.get("setup_remote_docker").is_some());
+    let restore = position(&|step| step.get("restore_cache").is_some());
+    assert_eq!(remote_docker, checkout + 1);
+    assert!(remote_docker < restore);
+
+    let options = &steps[remote_docker]["setup_remote_docker"];
+    assert_eq!(options["version"].as_str(), Some("24.0"));
+    assert_eq!(options["docker_layer_caching"].as_bool(), Some(true));
+}
+
+#[test]
+fn docker_build_manifest_job_requires_arch_builds_and_feeds_consumers() {
+    let project = write_config(
+        r#"provider: circleci
+docker_build:
+  manifest: true
+  registry:
+    repo: docker.io/acme
+  images:
+    - name: ci_base
+      arch: [amd64, arm64]
+"#,
+        &[("test", "image: ci_base\nsteps:\n  - run: make test\n")],
+    );
+    fs::write(project.path().join("Dockerfile"), "FROM alpine:3.19\n").unwrap();
+    let main = generate(project.path());
+
+    let requires = |job_id: &str| -> Vec<String> {
+        main["workflows"]["main"]["jobs"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .find_map(|entry| entry.get(job_id))
+            .and_then(|job| job["requires"].as_sequence())
+            .map(|requires| {
+                requires
+                    .iter()
+                    .map(|job| job.as_str().unwrap().to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let mut manifest_requires = requires("manifest_ci_base");
+    manifest_requires.sort();
+    assert_eq!(
+        manifest_requires,
+        vec!["build_ci_base-amd64", "build_ci_base-arm64"]
+    );
+    assert_eq!(requires("test"), vec!["manifest_ci_base"]);
+
+    let image = main["jobs"]["test"]["docker"][0]["image"].as_str().unwrap();
+    let hash = image
+        .strip_prefix("docker.io/acme/ci_base:")
+        .expect("consumer should use the manifest tag");
+    assert!(hash.chars().all(|c| c.is_ascii_hexdigit()), "{image}");
+
+    let manifest_steps = job_steps(&main, "manifest_ci_base");
+    assert!(manifest_steps.iter().any(|step| {
+        step["run"]["command"].as_str().or(step["run"].as_str())
+            == Some(&format!(
+                "docker buildx imagetools create -t {image} {image}-amd64 {image}-arm64"
+            ))
+    }));
+    let build_steps = job_steps(&main, "build_ci_base-arm64");
+    assert!(build_steps.iter().any(|step| {
+        step["run"]["command"]
+            .as_str()
+            .or(step["run"].as_str())
+            .is_some_and(|command| command.contains(&format!("-t {image}-arm64")))
+    }));
+}
+
+fn first_steps(main: &Value, job_id: &str, count: usize) -> Vec<Value> {
+    job_steps(main, job_id)
+        .iter()
+        .take(count)
+        .cloned()
+        .collect()
+}
+
+#[test]
+fn checkout_variants_with_post_steps() {
+    let project = write_config(
+        "provider: circleci\ncheckout:\n  post:\n    - run: git lfs pull\n",
+        &[
+            (
+                "standard",
+                "image: cimg/base:current\nsteps:\n  - run: make\n",
+            ),
+            (
+                "shallow",
+                "image: cimg/base:current\ncheckout:\n  shallow: true\nsteps:\n  - run: make\n",
+            ),
+            (
+                "custom",
+                "image: cimg/base:current\ncheckout:\n  command: my_mirror_checkout\nsteps:\n  - run: make\n",
+            ),
+            (
+                "disabled",
+                "image: cimg/base:current\ncheckout: false\nsteps:\n  - run: make\n",
+            ),
+        ],
+    );
+    let commands_dir = project.path().join(".cigen/commands");
+    fs::create_dir_all(&commands_dir).unwrap();
+    fs::write(
+        commands_dir.join("my_mirror_checkout.yml"),
+        "steps:\n  - run: git clone --reference /mirror \"$CIRCLE_REPOSITORY_URL\" .\n",
+    )
+    .unwrap();
+    let main = generate(project.path());
+
+    let post: Value = serde_yaml::from_str("run: git lfs pull").unwrap();
+    let standard = first_steps(&main, "standard", 2);
+    assert_eq!(standard[0].as_str(), Some("checkout"));
+    assert_eq!(standard[1], post);
+
+    let shallow = first_steps(&main, "shallow", 2);
+    assert!(shallow[0].get("cigen_shallow_checkout").is_some());
+    assert_eq!(shallow[1], post);
+
+    let custom = first_steps(&main, "custom", 2);
+    assert_eq!(custom[0].as_str(), Some("my_mirror_checkout"));
+    assert_eq!(custom[1], post);
+    assert!(main["commands"].get("my_mirror_checkout").is_some());
+
+    let disabled = job_steps(&main, "disabled");
+    assert!(!disabled.contains(&Value::String("checkout".into())));
+    assert!(!disabled.contains(&post));
+}
+
+#[test]
+fn steps_parameters_keep_their_place_in_the_command() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "test",
+            "image: cimg/base:current\nsteps:\n  - with_retry:\n      steps:\n        - run:\n            name: Test\n            command: make test\n            env:\n              RAILS_ENV: test\n",
+        )],
+    );
+    let commands_dir = project.path().join(".cigen/commands");
+    fs::create_dir_all(&commands_dir).unwrap();
+    fs::write(
+        commands_dir.join("with_retry.yml"),
+        "parameters:\n  steps:\n    type: steps\n    default:\n      - run: make\nsteps:\n  - run: echo before\n  - << parameters.steps >>\n  - run: echo after\n",
+    )
+    .unwrap();
+    let main = generate(project.path());
+
+    let command = &main["commands"]["with_retry"];
+    assert_eq!(
+        command["parameters"]["steps"]["type"].as_str(),
+        Some("steps")
+    );
+    assert_eq!(
+        command["parameters"]["steps"]["default"][0]["run"]["command"].as_str(),
+        Some("make")
+    );
+    assert_eq!(command["steps"][1].as_str(), Some("<< parameters.steps >>"));
+
+    let invocation = job_steps(&main, "test")
+        .iter()
+        .find_map(|step| step.get("with_retry"))
+        .expect("with_retry invocation");
+    let run = &invocation["steps"][0]["run"];
+    assert_eq!(run["name"].as_str(), Some("Test"));
+    assert_eq!(run["command"].as_str(), Some("make test"));
+    assert_eq!(run["environment"]["RAILS_ENV"].as_str(), Some("test"));
+
+    fs::write(
+        commands_dir.join("with_retry.yml"),
+        "parameters:\n  steps:\n    type: string\nsteps:\n  - << parameters.steps >>\n",
+    )
+    .unwrap();
+    let output = generate_command(project.path()).assert().failure();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains("splices in parameter 'steps', which needs `type: steps`"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn unknown_checkout_command_is_rejected() {
+    let project = write_config(
+        "provider: circleci\ncheckout:\n  command: my_mirror_checkout\n",
+        &[("build", "image: cimg/base:current\nsteps:\n  - run: make\n")],
+    );
+
+    let output = generate_command(project.path()).assert().failure();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains("Checkout command 'my_mirror_checkout' is not defined"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn source_submodules_are_initialized_and_hashed() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "test",
+            "image: cimg/base:current\nsource_submodules:\n  - vendor/engine\nsteps:\n  - run: make test\n",
+        )],
+    );
+    let main = generate(project.path());
+    let steps = job_steps(&main, "test");
+
+    assert_eq!(steps[0].as_str(), Some("checkout"));
+    assert_eq!(
+        steps[1]["run"]["command"].as_str(),
+        Some("git submodule update --init -- 'vendor/engine'")
+    );
+
+    let write_commit = steps
+        .iter()
+        .position(|step| {
+            step["cigen_write_submodule_commit_hash"]["path"].as_str() == Some("vendor/engine")
+        })
+        .expect("submodule commit step should be present");
+    let compute_hash = steps
+        .iter()
+        .position(|step| step["run"]["name"].as_str() == Some("Compute job hash"))
+        .expect("hash step should be present");
+    assert!(write_commit < compute_hash);
+    assert!(
+        main["commands"]
+            .get("cigen_write_submodule_commit_hash")
+            .is_some()
+    );
+}
+
+#[test]
+fn working_directory_is_where_checkout_and_hash_steps_run() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "test",
+            "image: cimg/node:20.11\nworking_directory: ~/app\nsource_files:\n  - src/**/*\nsteps:\n  - run: npm test\n",
+        )],
+    );
+    let main = generate(project.path());
+    let job = &main["jobs"]["test"];
+    assert_eq!(job["working_directory"].as_str(), Some("~/app"));
+
+    // The checkout lands in the working directory, so the hash step runs from
+    // the repository root without changing directory
+    let steps = job_steps(&main, "test");
+    assert_eq!(steps[0].as_str(), Some("checkout"));
+    let compute_hash = steps
+        .iter()
+        .find(|step| step["run"]["name"].as_str() == Some("Compute job hash"))
+        .expect("hash step should be present");
+    assert!(compute_hash["run"].get("working_directory").is_none());
+    assert!(
+        compute_hash["run"]["command"]
+            .as_str()
+            .unwrap()
+            .contains("cigen hash --job 'test' --config .cigen")
+    );
+}
+
+#[test]
+fn machine_executor_replaces_docker() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[
+            (
+                "default_image",
+                "executor: machine\nresource_class: large\nsteps:\n  - run: docker compose up -d\n",
+            ),
+            (
+                "pinned_image",
+                "executor:\n  machine:\n    image: ubuntu-2404:2024.05.1\nsteps:\n  - run: make test\n",
+            ),
+        ],
+    );
+    let main = generate(project.path());
+
+    let job = &main["jobs"]["default_image"];
+    assert!(job.get("docker").is_none());
+    assert_eq!(
+        job["machine"]["image"].as_str(),
+        Some("ubuntu-2204:current")
+    );
+    assert_eq!(job["resource_class"].as_str(), Some("large"));
+    assert_eq!(
+        main["jobs"]["pinned_image"]["machine"]["image"].as_str(),
+        Some("ubuntu-2404:2024.05.1")
+    );
+}
+
+#[test]
+fn macos_executor_requires_macos_resource_class() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "ios",
+            "executor:\n  macos:\n    xcode: \"15.3\"\nresource_class: macos.m1.medium.gen1\nsteps:\n  - run: xcodebuild test\n",
+        )],
+    );
+    let main = generate(project.path());
+    let job = &main["jobs"]["ios"];
+    assert!(job.get("docker").is_none());
+    assert_eq!(job["macos"]["xcode"].as_str(), Some("15.3"));
+    assert_eq!(job["resource_class"].as_str(), Some("macos.m1.medium.gen1"));
+
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "ios",
+            "executor:\n  macos:\n    xcode: \"15.3\"\nresource_class: large\nsteps:\n  - run: xcodebuild test\n",
+        )],
+    );
+    generate_command(project.path())
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "resource_class 'large' is not a macOS resource class",
+        ));
+}
+
+#[test]
+fn vm_executors_reject_services() {
+    let project = write_config(
+        "provider: circleci\nservices:\n  postgres:\n    image: postgres:16\n",
+        &[(
+            "test",
+            "executor: machine\nservices:\n  - postgres\nsteps:\n  - run: make test\n",
+        )],
+    );
+    generate_command(project.path())
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Job 'test' uses the machine executor, which can't run service containers",
+        ))
+        .stderr(predicates::str::contains("test.yml:2:1"));
+}
+
+#[test]
+fn named_executors_are_defined_once_and_referenced_per_architecture() {
+    let project = write_config(
+        r#"provider: circleci
+executors:
+  ruby:
+    image: cimg/ruby:3.3
+    resource_class: large
+    architectures:
+      arm64:
+        resource_class: arm.large
+  unused:
+    machine: true
+"#,
+        &[(
+            "rspec",
+            "executor: ruby\nmatrix:\n  arch: [amd64, arm64]\nsteps:\n  - run: bundle exec rspec\n",
+        )],
+    );
+    let main = generate(project.path());
+
+    assert_eq!(
+        main["jobs"]["rspec-amd64"]["executor"].as_str(),
+        Some("ruby")
+    );
+    assert_eq!(
+        main["jobs"]["rspec-arm64"]["executor"].as_str(),
+        Some("ruby_arm64")
+    );
+    assert!(main["jobs"]["rspec-amd64"].get("docker").is_none());
+
+    let executors = main["executors"].as_mapping().unwrap();
+    assert_eq!(executors.len(), 2, "{executors:?}");
+    assert_eq!(
+        main["executors"]["ruby"]["docker"][0]["image"].as_str(),
+        Some("cimg/ruby:3.3")
+    );
+    assert_eq!(
+        main["executors"]["ruby_arm64"]["resource_class"].as_str(),
+        Some("arm.large")
+    );
+}
+
+#[test]
+fn named_executor_references_are_validated() {
+    let project = write_config(
+        "provider: circleci\nexecutors:\n  ruby:\n    image: cimg/ruby:3.3\n",
+        &[(
+            "rspec",
+            "executor: rubby\nsteps:\n  - run: bundle exec rspec\n",
+        )],
+    );
+    generate_command(project.path())
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Unknown CircleCI executor 'rubby' referenced by job 'rspec'",
+        ))
+        .stderr(predicates::str::contains("Did you mean 'ruby'?"))
+        .stderr(predicates::str::contains("rspec.yml:1:11"));
+
+    let project = write_config(
+        "provider: circleci\nexecutors:\n  ruby:\n    image: cimg/ruby:3.3\n",
+        &[(
+            "rspec",
+            "image: cimg/ruby:3.2\nexecutor: ruby\nsteps:\n  - run: bundle exec rspec\n",
+        )],
+    );
+    generate_command(project.path())
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Job sets both image and executor 'ruby'",
+        ));
+}
+
+#[test]
+fn relative_working_directory_keeps_the_checkout_at_the_job_root() {
+    let project = write_config(
+        "provider: circleci\ncaches:\n  npm:\n    paths: [node_modules]\n    checksum_sources: [package-lock.json, /etc/os-release]\n",
+        &[(
+            "test",
+            "image: cimg/node:20.11\nworking_directory: frontend\nsource_files:\n  - src/**/*\ntest_results: reports/junit\ncache:\n  npm: null\nsteps:\n  - run: npm test\n  - run:\n      name: Lint docs\n      command: npm run lint\n",
+        )],
+    );
+    let main = generate(project.path());
+    let job = &main["jobs"]["test"];
+    assert!(job.get("working_directory").is_none());
+
+    let steps = job_steps(&main, "test");
+    assert_eq!(steps[0].as_str(), Some("checkout"));
+    let compute_hash = steps
+        .iter()
+        .find(|step| step["run"]["name"].as_str() == Some("Compute job hash"))
+        .expect("hash step should be present");
+    assert!(compute_hash["run"].get("working_directory").is_none());
+
+    // The job's own steps run in the subdirectory of the checkout
+    let user_steps: Vec<_> = steps
+        .iter()
+        .filter(|step| {
+            step["run"]["command"]
+                .as_str()
+                .is_some_and(|command| command.starts_with("npm "))
+        })
+        .collect();
+    assert_eq!(user_steps.len(), 2);
+    for step in user_steps {
+        assert_eq!(step["run"]["working_directory"].as_str(), Some("frontend"));
+    }
+    // Cache paths and checksum files are read from the job root
+    let save = steps
+        .iter()
+        .find(|step| step["save_cache"]["name"].as_str() == Some("Save npm cache"))
+        .expect("save_cache should be present");
+    assert_eq!(
+        save["save_cache"]["paths"][0].as_str(),
+        Some("frontend/node_modules")
+    );
+    let key = save["save_cache"]["key"].as_str().unwrap();
+    assert!(
+        key.contains(
+            r#"{{ checksum "frontend/package-lock.json" }}-{{ checksum "/etc/os-release" }}"#
+        ),
+        "{key}"
+    );
+    let store = steps
+        .iter()
+        .find(|step| step.get("store_test_results").is_some())
+        .expect("store_test_results should be present");
+    assert_eq!(
+        store["store_test_results"]["path"].as_str(),
+        Some("frontend/reports/junit")
+    );
+}
+
+#[test]
+fn unknown_service_error_points_at_job_file() {
+    let project = write_config(
+        "provider: circleci\nservices:\n  postgres:\n    image: cimg/postgres:16.2\n",
+        &[(
+            "rspec",
+            "image: cimg/ruby:3.3\nservices:\n  - postgrse\nsteps:\n  - run: bundle exec rspec\n",
+        )],
+    );
+
+    let output = generate_command(project.path()).assert().failure();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains("Unknown CircleCI service 'postgrse' referenced by job 'rspec'"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("rspec.yml:3:5"), "{stderr}");
+    assert!(stderr.contains("- postgrse"), "{stderr}");
+}
+
+#[test]
+fn wait_for_services_wait_right_after_checkout() {
+    let project = write_config(
+        r#"provider: circleci
+services:
+  postgres:
+    image: cimg/postgres:16.2
+    ports: ["5432:5432"]
+    wait_for: { timeout: 90s }
+  redis:
+    image: cimg/redis:7.2
+    ports: [6379]
+"#,
+        &[(
+            "rspec",
+            "image: cimg/ruby:3.3\nservices: [postgres, redis]\nsteps:\n  - run: bundle exec rspec\n",
+        )],
+    );
+
+    let main = generate(project.path());
+    let steps = job_steps(&main, "rspec");
+    let names: Vec<&str> = steps
+        .iter()
+        .map(|step| match step {
+            Value::String(name) => name.as_str(),
+            Value::Mapping(map) => map.keys().next().and_then(Value::as_str).unwrap_or(""),
+            _ => "",
+        })
+        .collect();
+    let wait = names.iter().position(|name| *name == "run").unwrap();
+    assert_eq!(names[..wait], ["checkout"], "{names:?}");
+    assert_eq!(
+        steps[wait]["run"]["name"].as_str(),
+        Some("Wait for postgres")
+    );
+    let command = steps[wait]["run"]["command"].as_str().unwrap();
+    assert!(
+        command.starts_with("deadline=$((SECONDS + 90))\n"),
+        "{command}"
+    );
+    assert!(
+        command.contains("until (echo > /dev/tcp/localhost/5432) >/dev/null 2>&1; do"),
+        "{command}"
+    );
+    // redis doesn't set wait_for, and the job's own steps come after the wait
+    assert_eq!(
+        steps[wait + 1]["run"]
+            .as_str()
+            .or(steps[wait + 1]["run"]["command"].as_str()),
+        Some("bundle exec rspec")
+    );
+}
+
+#[test]
+fn wait_for_needs_ports() {
+    let project = write_config(
+        "provider: circleci\nservices:\n  postgres:\n    image: cimg/postgres:16.2\n    wait_for: true\n",
+        &[(
+            "rspec",
+            "image: cimg/ruby:3.3\nservices: [postgres]\nsteps:\n  - run: bundle exec rspec\n",
+        )],
+    );
+    generate_command(project.path())
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Service 'postgres' sets wait_for but lists no ports to wait for",
+        ));
+}
+
+#[test]
+fn generate_restricts_output_to_named_workflow() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "test",
+            "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+        )],
+    );
+    let release_dir = project.path().join(".cigen/workflows/release/jobs");
+    fs::create_dir_all(&release_dir).unwrap();
+    fs::write(
+        release_dir.join("deploy.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run: make deploy\n",
+    )
+    .unwrap();
+
+    generate_command(project.path())
+        .arg("release")
+        .assert()
+        .success();
+    let yaml = fs::read_to_string(project.path().join("out/.circleci/main.yml")).unwrap();
+    let main: Value = serde_yaml::from_str(&yaml).unwrap();
+    assert!(main["jobs"].get("deploy").is_some());
+    assert!(main["jobs"].get("test").is_none());
+    assert!(main["workflows"].get("main").is_none());
+
+    let output = generate_command(project.path())
+        .args(["--workflow", "relase"])
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains("Unknown workflow 'relase'. Did you mean 'release'?"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("Available: main, release"), "{stderr}");
+}
+
+#[test]
+fn per_workflow_output_writes_one_standalone_config_per_workflow() {
+    let project = write_config(
+        "provider: circleci\noutput:\n  per_workflow: true\n  filename_template: \"{workflow}_config.yml\"\n",
+        &[(
+            "test",
+            "image: cimg/base:stable\nsteps:\n  - configure_git_user\n  - run: make test\n",
+        )],
+    );
+    let commands_dir = project.path().join(".cigen/commands");
+    fs::create_dir_all(&commands_dir).unwrap();
+    fs::write(
+        commands_dir.join("configure_git_user.yml"),
+        "steps:\n  - run: git config --global user.name CI\n",
+    )
+    .unwrap();
+    let release_dir = project.path().join(".cigen/workflows/release/jobs");
+    fs::create_dir_all(&release_dir).unwrap();
+    fs::write(
+        release_dir.join("deploy.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run: make deploy\n",
+    )
+    .unwrap();
+
+    // Generation validates every file on its own before writing it
+    generate_command(project.path()).assert().success();
+    let out = project.path().join("out/.circleci");
+    assert!(!out.join("main.yml").exists());
+    let read = |name: &str| -> Value {
+        serde_yaml::from_str(&fs::read_to_string(out.join(name)).unwrap()).unwrap()
+    };
+
+    let main = read("main_config.yml");
+    assert!(main["jobs"].get("test").is_some());
+    assert!(main["jobs"].get("deploy").is_none());
+    assert!(main["workflows"].get("main").is_some());
+    assert!(main["commands"].get("configure_git_user").is_some());
+    assert!(main.get("orbs").is_none());
+
+    let release = read("release_config.yml");
+    assert!(release["jobs"].get("deploy").is_some());
+    assert!(release["jobs"].get("test").is_none());
+    assert!(release["workflows"].get("release").is_some());
+    assert!(release.get("commands").is_none());
+
+    let setup = read("config.yml");
+    let parameter = &setup["parameters"]["workflow"];
+    assert_eq!(parameter["type"].as_str(), Some("enum"));
+    assert_eq!(
+        parameter["enum"],
+        serde_yaml::from_str::<Value>("[main, release]").unwrap()
+    );
+    assert_eq!(parameter["default"].as_str(), Some("main"));
+
+    let steps = job_steps(&setup, "setup");
+    assert!(steps.iter().any(|step| {
+        step["continuation/continue"]["configuration_path"].as_str()
+            == Some(
+                out.join("<< pipeline.parameters.workflow >>_config.yml")
+                    .to_str()
+                    .unwrap(),
+            )
+    }));
+    assert!(steps.iter().any(
+        |step| step["run"]["command"].as_str().is_some_and(|command| {
+            command.contains("cigen generate << pipeline.parameters.workflow >>")
+        })
+    ));
+}
+
+#[test]
+fn output_settings_and_cli_output_move_every_path_the_setup_job_uses() {
+    let project = write_config(
+        "provider: circleci\ncircleci:\n  output_path: ci\n  output_filename: setup.yml\n",
+        &[(
+            "test",
+            "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+        )],
+    );
+    generate_command(project.path()).assert().success();
+
+    let out = project.path().join("out/ci");
+    assert!(out.join("main.yml").exists());
+    assert!(!project.path().join("out/.circleci").exists());
+    let setup: Value =
+        serde_yaml::from_str(&fs::read_to_string(out.join("setup.yml")).unwrap()).unwrap();
+    let steps = job_steps(&setup, "setup");
+
+    // The setup job regenerates into the same directory and continues from there
+    let main = out.join("main.yml");
+    assert!(
+        steps.iter().any(
+            |step| step["continuation/continue"]["configuration_path"].as_str() == main.to_str()
+        )
+    );
+    let regenerates = format!(
+        "cigen generate main --output '{}'",
+        project.path().join("out").display()
+    );
+    assert!(steps.iter().any(|step| {
+        step["run"]["command"]
+            .as_str()
+            .is_some_and(|command| command.contains(&regenerates))
+    }));
+}
+
+#[test]
+fn internal_validator_rejects_configs_circleci_would_reject() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "test",
+            "image: cimg/base:stable\nsteps:\n  - notify_slack:\n      channel: ci\n",
+        )],
+    );
+    let release_dir = project.path().join(".cigen/workflows/release/jobs");
+    fs::create_dir_all(&release_dir).unwrap();
+    fs::write(
+        release_dir.join("deploy.yml"),
+        "image: cimg/base:stable\nneeds: [test]\nsteps:\n  - run: make deploy\n",
+    )
+    .unwrap();
+
+    let output = generate_command(project.path()).assert().failure();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains(
+            ".circleci/main.yml at /jobs/test/steps/1: command 'notify_slack' is not declared under commands"
+        ),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains(
+            ".circleci/main.yml at /workflows/release/jobs/0/deploy/requires/0: requires 'test', which is not a job in this workflow"
+        ),
+        "{stderr}"
+    );
+    assert!(!project.path().join("out/.circleci/main.yml").exists());
+}
+
+#[test]
+fn cache_version_prefixes_job_cache_and_job_status_keys() {
+    let project = write_config(
+        "provider: circleci\ncache_version: 3\ncaches:\n  gems:\n    paths: [vendor/bundle]\n    checksum_sources: [Gemfile.lock]\n    restore_key_levels: 1\n",
+        &[(
+            "rspec",
+            "image: cimg/ruby:3.3\ncache: gems\nsource_files: [app/**/*.rb]\nsteps:\n  - run: bundle exec rspec\n",
+        )],
+    );
+    let main = generate(project.path());
+    let steps = job_steps(&main, "rspec");
+    let step = |kind: &str, name: &str| {
+        steps
+            .iter()
+            .find(|step| step[kind]["name"].as_str() == Some(name))
+            .unwrap_or_else(|| panic!("missing {kind} step {name}"))[kind]
+            .clone()
+    };
+
+    let restore = step("restore_cache", "Restore gems cache");
+    let keys: Vec<&str> = restore["keys"]
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .filter_map(Value::as_str)
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            r#"v3-gems-{{ arch }}-{{ checksum "Gemfile.lock" }}"#,
+            "v3-gems-{{ arch }}-",
+        ]
+    );
+    assert_eq!(
+        step("save_cache", "Save gems cache")["key"].as_str(),
+        Some(r#"v3-gems-{{ arch }}-{{ checksum "Gemfile.lock" }}"#)
+    );
+    let status_key = step("save_cache", "Persist job status")["key"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(
+        status_key.starts_with("v3-linux-") && status_key.contains("-job_status-exists-rspec-"),
+        "{status_key}"
+    );
+
+    let setup = fs::read_to_string(project.path().join("out/.circleci/config.yml")).unwrap();
+    assert!(setup.contains(&status_key), "{setup}");
+    assert!(!setup.contains("exists-v1"), "{setup}");
+}
+
+#[test]
+fn packages_restore_install_and_save_before_user_steps() {
+    let output = tempdir().unwrap();
+    let mut cmd = Command::cargo_bin("cigen").expect("cigen binary not found");
+    cmd.arg("generate")
+        .arg("--config")
+        .arg(repo_root().join("integration_tests/circleci_packages/.cigen"))
+        .arg("--output")
+        .arg(output.path())
+        .current_dir(repo_root())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .assert()
+        .success();
+
+    let yaml = fs::read_to_string(output.path().join(".circleci/main.yml")).unwrap();
+    let main: Value = serde_yaml::from_str(&yaml).unwrap();
+    let steps: Vec<String> = job_steps(&main, "test")
+        .iter()
+        .filter_map(|step| {
+            ["restore_cache", "save_cache", "run"]
+                .iter()
+                .find_map(|kind| {
+                    step[*kind]["name"]
+                        .as_str()
+                        .or(step[*kind]["command"].as_str())
+                })
+                .map(str::to_string)
+        })
+        .collect();
+    let position = |name: &str| {
+        steps
+            .iter()
+            .position(|step| step == name)
+            .unwrap_or_else(|| panic!("missing step {name} in {steps:?}"))
+    };
+
+    assert!(position("Restore gems cache") < position("Install bundler packages"));
+    assert!(position("Install bundler packages") < position("Save gems cache"));
+    assert!(position("Save gems cache") < position("Restore npm cache"));
+    assert!(position("Restore npm cache") < position("Install npm packages"));
+    assert!(position("Install npm packages") < position("Save npm cache"));
+    assert!(position("Save npm cache") < position("bundle exec rake"));
+
+    let command = |name: &str| {
+        job_steps(&main, "test")
+            .iter()
+            .find(|step| step["run"]["name"].as_str() == Some(name))
+            .and_then(|step| step["run"]["command"].as_str())
+            .unwrap()
+            .to_string()
+    };
+    assert!(command("Install bundler packages").contains("bundle check || bundle install"));
+    assert_eq!(command("Install npm packages"), "npm ci --no-audit");
+}
+
+#[test]
+fn test_splitting_runs_this_containers_share_of_the_glob() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "rspec",
+            "image: cimg/ruby:3.3\nparallelism: 4\ntest_results: tmp/junit\ntest_splitting:\n  glob: \"spec/**/*_spec.rb\"\n  by: timings\n  command_template: bundle exec rspec --format RspecJunitFormatter {files}\n",
+        )],
+    );
+    let main = generate(project.path());
+
+    assert_eq!(main["jobs"]["rspec"]["parallelism"].as_u64(), Some(4));
+    let steps = job_steps(&main, "rspec");
+    let split = steps
+        .iter()
+        .position(|step| step["run"]["name"].as_str() == Some("Run split tests"))
+        .expect("split step");
+    assert_eq!(
+        steps[split]["run"]["command"].as_str(),
+        Some(
+            "TEST_FILES=$(circleci tests glob 'spec/**/*_spec.rb' | circleci tests split --split-by=timings)\n# shellcheck disable=SC2086\nbundle exec rspec --format RspecJunitFormatter $TEST_FILES\n"
+        )
+    );
+    assert!(steps[split + 1].get("store_test_results").is_some());
+}
+
+#[test]
+fn test_splitting_requires_parallelism() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "rspec",
+            "image: cimg/ruby:3.3\ntest_splitting:\n  glob: \"spec/**/*_spec.rb\"\n  command_template: bundle exec rspec {files}\n",
+        )],
+    );
+
+    let output = generate_command(project.path()).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("needs parallelism: 2 or more"), "{stderr}");
+}
+
+const APPROVAL_JOBS: [(&str, &str); 3] = [
+    (
+        "build",
+        "image: cimg/base:current\nmatrix:\n  arch: [amd64, arm64]\nsteps:\n  - run: make\n",
+    ),
+    ("hold", "type: approval\nneeds: [build]\n"),
+    (
+        "release",
+        "image: cimg/base:current\nmatrix:\n  arch: [amd64, arm64]\nneeds: [hold]\nsteps:\n  - run: ./release.sh\n",
+    ),
+];
+
+#[test]
+fn approval_job_sits_between_architecture_variants() {
+    let project = write_config("provider: circleci\n", &APPROVAL_JOBS);
+    let main = generate(project.path());
+
+    let entries = main["workflows"]["main"]["jobs"].as_sequence().unwrap();
+    let entry = |name: &str| {
+        entries
+            .iter()
+            .find_map(|entry| entry.get(name))
+            .unwrap_or_else(|| panic!("workflow has no {name} entry"))
+    };
+    assert_eq!(
+        serde_yaml::to_string(entry("hold")).unwrap(),
+        "type: approval\nrequires:\n- build-amd64\n- build-arm64\n"
+    );
+    for release in ["release-amd64", "release-arm64"] {
+        assert_eq!(
+            serde_yaml::to_string(entry(release)).unwrap(),
+            "requires:\n- hold\n"
+        );
+    }
+    assert!(main["jobs"].get("hold").is_none());
+}
+
+#[test]
+fn approval_job_with_steps_is_rejected_at_the_key() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[
+            ("build", "image: cimg/base:current\nsteps:\n  - run: make\n"),
+            (
+                "hold",
+                "type: approval\nneeds: [build]\nsteps:\n  - run: echo waiting\n",
+            ),
+        ],
+    );
+
+    let output = generate_command(project.path()).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Approval job 'hold' can't declare `steps`"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("hold.yml:3:1]"), "{stderr}");
+    assert!(stderr.contains("3 │ steps:"), "{stderr}");
+}
+
+const JOB_STEPS_WORKFLOW: &str = r#"job_steps:
+  release:
+    pre_steps:
+      - run: echo starting release
+    post_steps:
+      - run:
+          name: Notify Slack
+          command: ./notify-slack.sh
+"#;
+
+#[test]
+fn workflow_job_steps_become_pre_and_post_steps() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[
+            ("build", "image: cimg/base:current\nsteps:\n  - run: make\n"),
+            (
+                "release",
+                "image: cimg/base:current\nneeds: [build]\nsteps:\n  - run: ./release.sh\n",
+            ),
+        ],
+    );
+    fs::write(
+        project.path().join(".cigen/workflows/main/config.yml"),
+        JOB_STEPS_WORKFLOW,
+    )
+    .unwrap();
+    let main = generate(project.path());
+
+    let entries = main["workflows"]["main"]["jobs"].as_sequence().unwrap();
+    assert_eq!(entries[0].as_str(), Some("build"));
+    assert_eq!(
+        serde_yaml::to_string(&entries[1]).unwrap(),
+        "release:\n  requires:\n  - build\n  pre-steps:\n  - run:\n      command: echo starting release\n  post-steps:\n  - run:\n      name: Notify Slack\n      command: ./notify-slack.sh\n"
+    );
+    // The job definition itself is unchanged
+    let steps = job_steps(&main, "release");
+    assert_eq!(
+        steps.last().unwrap()["run"]["command"].as_str(),
+        Some("./release.sh")
+    );
+}
+
+#[test]
+fn workflow_job_steps_must_name_a_workflow_job() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "release",
+            "image: cimg/base:current\nsteps:\n  - run: ./release.sh\n",
+        )],
+    );
+    fs::write(
+        project.path().join(".cigen/workflows/main/config.yml"),
+        "job_steps:\n  relase:\n    post_steps:\n      - run: ./notify-slack.sh\n",
+    )
+    .unwrap();
+
+    let output = generate_command(project.path()).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Workflow 'main' adds job_steps to unknown job 'relase'"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("release"), "{stderr}");
+}
+
+#[test]
+fn slack_notifications_add_the_orb_and_a_notify_post_step() {
+    let project = write_config(
+        "provider: circleci\nnotifications:\n  slack:\n    channel: \"#ci\"\n    on: [failure, fixed]\n    context: slack-secrets\n",
+        &[
+            ("build", "image: cimg/base:current\nsteps:\n  - run: make\n"),
+            ("hold", "type: approval\nneeds: [build]\n"),
+        ],
+    );
+    let output = generate_command(project.path()).output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("CircleCI can't tell a fixed build from any other pass"),
+        "{stderr}"
+    );
+    let main: Value = serde_yaml::from_str(
+        &fs::read_to_string(project.path().join("out/.circleci/main.yml")).unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        main["orbs"]["slack"].as_str(),
+        Some("circleci/slack@4.13.3")
+    );
+    let entries = main["workflows"]["main"]["jobs"].as_sequence().unwrap();
+    assert_eq!(
+        serde_yaml::to_string(&entries[0]).unwrap(),
+        "build:\n  post-steps:\n  - slack/notify:\n      channel: '#ci'\n      event: always\n  context:\n  - slack-secrets\n"
+    );
+    assert_eq!(
+        serde_yaml::to_string(&entries[1]).unwrap(),
+        "hold:\n  type: approval\n  requires:\n  - build\n"
+    );
+}
+
+#[test]
+fn slack_orb_is_only_added_when_notifications_are_configured() {
+    let jobs = [("build", "image: cimg/base:current\nsteps:\n  - run: make\n")];
+    let project = write_config("provider: circleci\n", &jobs);
+    let main = generate(project.path());
+    assert!(main["orbs"].get("slack").is_none());
+    assert_eq!(main["workflows"]["main"]["jobs"][0].as_str(), Some("build"));
+
+    // A workflow's own notifications replace the top-level ones
+    let project = write_config(
+        "provider: circleci\nnotifications:\n  slack:\n    channel: \"#ci\"\n",
+        &jobs,
+    );
+    fs::write(
+        project.path().join(".cigen/workflows/main/config.yml"),
+        "notifications:\n  slack:\n    channel: \"#releases\"\n    on: [success]\n",
+    )
+    .unwrap();
+    let main = generate(project.path());
+    assert_eq!(
+        serde_yaml::to_string(&main["workflows"]["main"]["jobs"][0]["build"]["post-steps"])
+            .unwrap(),
+        "- slack/notify:\n    channel: '#releases'\n    event: pass\n"
+    );
+}
+
+#[test]
+fn provider_overrides_add_keys_and_replace_generated_ones() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "test",
+            "image: cimg/base:current\nresource_class: medium\nenvironment:\n  RAILS_ENV: test\nsteps:\n  - run: make test\nprovider_overrides:\n  circleci:\n    circleci_ip_ranges: true\n    resource_class: xlarge\n    environment:\n      TZ: UTC\n  github:\n    timeout-minutes: 30\n",
+        )],
+    );
+    let output = generate_command(project.path()).output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "Job 'test': provider_overrides.circleci.resource_class replaces the value cigen generated"
+        ),
+        "{stderr}"
+    );
+    assert!(
+        !stderr.contains("provider_overrides.circleci.environment"),
+        "{stderr}"
+    );
+    let main: Value = serde_yaml::from_str(
+        &fs::read_to_string(project.path().join("out/.circleci/main.yml")).unwrap(),
+    )
+    .unwrap();
+
+    let job = &main["jobs"]["test"];
+    assert_eq!(job["circleci_ip_ranges"].as_bool(), Some(true));
+    assert_eq!(job["resource_class"].as_str(), Some("xlarge"));
+    assert_eq!(job["environment"]["RAILS_ENV"].as_str(), Some("test"));
+    assert_eq!(job["environment"]["TZ"].as_str(), Some("UTC"));
+    assert!(job.get("timeout-minutes").is_none());
+    assert!(job.get("provider_overrides").is_none());
+}
+
+#[test]
+fn workflow_expression_conditions_compile_to_when() {
+    let project = write_config(
+        "provider: circleci\nparameters:\n  deploy:\n    type: boolean\n    default: false\n",
+        &[("build", "image: cimg/base:current\nsteps:\n  - run: make\n")],
+    );
+    fs::write(
+        project.path().join(".cigen/workflows/main/config.yml"),
+        "run_when:\n  - expression: (param.deploy || branch == \"main\") && !param.dry_run\n",
+    )
+    .unwrap();
+    let main = generate(project.path());
+
+    let expected: Value = serde_yaml::from_str(
+        r#"
+and:
+  - or:
+      - equal: [true, "<< pipeline.parameters.deploy >>"]
+      - equal: [main, "<< pipeline.git.branch >>"]
+  - not:
+      equal: [true, "<< pipeline.parameters.dry_run >>"]
+"#,
+    )
+    .unwrap();
+    assert_eq!(main["workflows"]["main"]["when"], expected);
+}
+
+#[test]
+fn run_unless_negates_the_workflow_condition() {
+    let project = write_config(
+        "provider: circleci\nparameters:\n  deploy:\n    type: boolean\n    default: false\n",
+        &[("build", "image: cimg/base:current\nsteps:\n  - run: make\n")],
+    );
+    let config = project.path().join(".cigen/workflows/main/config.yml");
+    fs::write(&config, "run_unless:\n  - expression: param.dry_run\n").unwrap();
+    let main = generate(project.path());
+    let workflow = &main["workflows"]["main"];
+    assert!(workflow.get("when").is_none());
+    let expected: Value =
+        serde_yaml::from_str(r#"equal: [true, "<< pipeline.parameters.dry_run >>"]"#).unwrap();
+    assert_eq!(workflow["unless"], expected);
+
+    fs::write(
+        &config,
+        "run_when:\n  - parameter: deploy\nrun_unless:\n  - expression: param.dry_run\n",
+    )
+    .unwrap();
+    let main = generate(project.path());
+    let workflow = &main["workflows"]["main"];
+    assert!(workflow.get("unless").is_none());
+    let expected: Value = serde_yaml::from_str(
+        r#"
+and:
+  - equal: [true, "<< pipeline.parameters.deploy >>"]
+  - not:
+      equal: [true, "<< pipeline.parameters.dry_run >>"]
+"#,
+    )
+    .unwrap();
+    assert_eq!(workflow["when"], expected);
+}
+
+#[test]
+fn job_unless_halts_the_job_before_its_pre_steps() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "deploy",
+            "image: cimg/base:current\nunless: branch == \"staging\" && env.SKIP_DEPLOY defined\nsteps:\n  - run: ./deploy.sh\n",
+        )],
+    );
+    let main = generate(project.path());
+
+    let entry = &main["workflows"]["main"]["jobs"][0]["deploy"];
+    let expected: Value = serde_yaml::from_str(
+        r#"
+when:
+  condition:
+    equal: [staging, "<< pipeline.git.branch >>"]
+  steps:
+    - run:
+        name: 'Skip job unless: branch == "staging" && env.SKIP_DEPLOY defined'
+        command: |
+          if [ -n "${SKIP_DEPLOY:-}" ]; then
+          circleci-agent step halt
+          fi
+"#,
+    )
+    .unwrap();
+    assert_eq!(entry["pre-steps"][0], expected);
+}
+
+#[test]
+fn global_steps_run_between_cache_restores_and_saves() {
+    let project = write_config(
+        "provider: circleci\ncaches:\n  assets:\n    paths: [public/assets]\n    checksum_sources: [Gemfile.lock]\nglobal_steps:\n  before:\n    - run:\n        name: Scan for secrets\n        command: gitleaks detect\n  after:\n    - run:\n        name: Attest\n        command: ./attest.sh\n",
+        &[
+            (
+                "test",
+                "image: cimg/ruby:3.3\npackages: [bundler]\ncache: [assets]\nsource_files: [app/**]\nsteps:\n  - run:\n      name: Test\n      command: bundle exec rspec\n",
+            ),
+            (
+                "lint",
+                "image: cimg/ruby:3.3\ninherit_global_steps: false\nsteps:\n  - run:\n      name: Lint\n      command: rubocop\n",
+            ),
+        ],
+    );
+    fs::write(project.path().join("Gemfile.lock"), "").unwrap();
+    let main = generate(project.path());
+
+    let labels = |job_id: &str| -> Vec<String> {
+        job_steps(&main, job_id)
+            .iter()
+            .map(|step| match step {
+                Value::String(name) => name.clone(),
+                Value::Mapping(map) => {
+                    let (key, value) = map.iter().next().unwrap();
+                    let key = key.as_str().unwrap();
+                    match value.get("name").and_then(Value::as_str) {
+                        Some(name) => format!("{key}: {name}"),
+                        None => key.to_string(),
+                    }
+                }
+                other => panic!("unexpected step {other:?}"),
+            })
+            .collect()
+    };
+    assert_eq!(
+        labels("test"),
+        [
+            "checkout",
+            "run: Compute job hash",
+            "restore_cache: Restore assets cache",
+            "restore_cache: Restore gems cache",
+            "run: Install bundler packages",
+            "save_cache: Save gems cache",
+            "run: Scan for secrets",
+            "run: Test",
+            "run: Attest",
+            "save_cache: Save assets cache",
+            "run: Record job completion",
+            "save_cache: Persist job status",
+        ]
+    );
+    assert_eq!(labels("lint"), ["checkout", "run: Lint"]);
+}
+
+#[test]
+fn shard_count_splits_jobs_into_connected_shards() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[
+            (
+                "build",
+                "image: cimg/base:stable\nsteps:\n  - run: make build\n",
+            ),
+            (
+                "test",
+                "image: cimg/base:stable\nneeds: [build]\nsteps:\n  - run: make test\n",
+            ),
+            (
+                "lint",
+                "image: cimg/base:stable\nsteps:\n  - run: make lint\n",
+            ),
+        ],
+    );
+
+    generate_command(project.path())
+        .args(["--shard-count", "2"])
+        .assert()
+        .success();
+    let out = project.path().join("out/.circleci");
+    assert!(!out.join("main.yml").exists());
+    let read = |name: &str| -> Value {
+        serde_yaml::from_str(&fs::read_to_string(out.join(name)).unwrap()).unwrap()
+    };
+
+    let first = read("main_1.yml");
+    assert!(first["jobs"].get("build").is_some());
+    assert!(first["jobs"].get("test").is_some());
+    assert!(first["jobs"].get("lint").is_none());
+    let second = read("main_2.yml");
+    assert_eq!(
+        second["workflows"]["main"]["jobs"],
+        serde_yaml::from_str::<Value>("[lint]").unwrap()
+    );
+
+    let setup = read("config.yml");
+    assert_eq!(
+        setup["parameters"]["cigen_shard"]["type"].as_str(),
+        Some("integer")
+    );
+    let steps = job_steps(&setup, "setup");
+    let commands: Vec<&str> = steps
+        .iter()
+        .filter_map(|step| step["run"]["command"].as_str())
+        .collect();
+    assert!(
+        commands
+            .iter()
+            .any(|command| command.contains("cigen generate main --shard-count 2"))
+    );
+    assert!(commands.iter().any(|command| {
+        command.contains(&format!(
+            "cp \"{out}/main_$shard.yml\" \"{out}/main.yml\"",
+            out = out.display()
+        ))
+    }));
+
+    let error = generate_command(project.path())
+        .args(["--shard-count", "3"])
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .clone();
+    let error = String::from_utf8(error).unwrap();
+    assert!(
+        error.contains("Jobs 'build', 'test' depend on each other"),
+        "{error}"
+    );
+}
+
+#[test]
+fn oversized_config_fails_with_the_largest_jobs() {
+    // 40 jobs with 100 KB commands add up to about 4 MB
+    let command = "echo ".to_string() + &"x".repeat(100 * 1024);
+    let jobs: Vec<(String, String)> = (0..40)
+        .map(|index| {
+            (
+                format!("job{index:02}"),
+                format!("image: cimg/base:stable\nsteps:\n  - run: {command}\n"),
+            )
+        })
+        .collect();
+    let jobs: Vec<(&str, &str)> = jobs
+        .iter()
+        .map(|(name, content)| (name.as_str(), content.as_str()))
+        .collect();
+    let project = write_config("provider: circleci\n", &jobs);
+
+    let error = generate_command(project.path())
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .clone();
+    let error = String::from_utf8(error).unwrap();
+    assert!(
+        error.contains(".circleci/main.yml is too large: its continuation payload is 3.9 MiB"),
+        "{error}"
+    );
+    assert!(
+        error.contains("over the limit of 3.0 MiB (3145728 bytes)"),
+        "{error}"
+    );
+    assert!(error.contains("  job00: 100."), "{error}");
+    assert!(!project.path().join("out/.circleci").exists());
+
+    generate_command(project.path())
+        .args(["--max-config-size", "8000000"])
+        .assert()
+        .success();
+    let setup: Value = serde_yaml::from_str(
+        &fs::read_to_string(project.path().join("out/.circleci/config.yml")).unwrap(),
+    )
+    .unwrap();
+    let generates_with_limit = job_steps(&setup, "setup").iter().any(|step| {
+        step["run"]["command"].as_str().is_some_and(|command| {
+            command.contains("cigen generate main --max-config-size 8000000")
+        })
+    });
+    assert!(generates_with_limit);
+}
+
+/// Serve a registry that wants a bearer token (granted for `bot:secret`) and
+/// has every `ci_base` tag but nothing else; returns its URL
+fn mock_image_registry() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let realm = format!("{url}/token");
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut authorization = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':')
+                    && name.eq_ignore_ascii_case("authorization")
+                {
+                    authorization = value.trim().to_string();
+                }
+            }
+
+            let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+            let (status, headers, body) = if path.starts_with("/token") {
+                if authorization == "Basic Ym90OnNlY3JldA==" {
+                    ("200 OK", String::new(), r#"{"token":"pull-token"}"#)
+                } else {
+                    ("401 Unauthorized", String::new(), "")
+                }
+            } else if authorization != "Bearer pull-token" {
+                (
+                    "401 Unauthorized",
+                    format!(
+                        "WWW-Authenticate: Bearer realm=\"{realm}\",service=\"registry.test\"\r\n"
+                    ),
+                    "",
+                )
+            } else if path.starts_with("/v2/acme/ci_base/manifests/") {
+                ("200 OK", String::new(), "")
+            } else {
+                ("404 Not Found", String::new(), "")
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+        }
+    });
+    url
+}
+
+#[test]
+fn verify_images_only_builds_unpublished_images() {
+    let project = write_config(
+        r#"provider: circleci
+docker_build:
+  verify_images: true
+  registry:
+    repo: registry.test/acme
+  images:
+    - name: ci_base
+    - name: app
+      dockerfile: app.Dockerfile
+"#,
+        &[
+            ("test", "image: ci_base\nsteps:\n  - run: make test\n"),
+            ("deploy", "image: app\nsteps:\n  - run: make deploy\n"),
+        ],
+    );
+    fs::write(project.path().join("Dockerfile"), "FROM alpine:3.19\n").unwrap();
+    fs::write(project.path().join("app.Dockerfile"), "FROM alpine:3.20\n").unwrap();
+
+    generate_command(project.path())
+        .arg("--verify-images")
+        .env("CIGEN_DOCKER_REGISTRY_URL", mock_image_registry())
+        .env("CIGEN_REGISTRY_USERNAME", "bot")
+        .env("CIGEN_REGISTRY_PASSWORD", "secret")
+        .assert()
+        .success();
+    let main: Value = serde_yaml::from_str(
+        &fs::read_to_string(project.path().join("out/.circleci/main.yml")).unwrap(),
+    )
+    .unwrap();
+    assert!(main["jobs"].get("build_ci_base").is_none());
+    assert!(main["jobs"].get("build_app").is_some());
+    assert!(
+        main["jobs"]["test"]["docker"][0]["image"]
+            .as_str()
+            .unwrap()
+            .starts_with("registry.test/acme/ci_base:")
+    );
+    let requires = |job_id: &str| {
+        main["workflows"]["main"]["jobs"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .find_map(|entry| entry.get(job_id))
+            .and_then(|job| job.get("requires"))
+            .cloned()
+    };
+    assert_eq!(requires("test"), None);
+    assert_eq!(
+        requires("deploy"),
+        Some(Value::Sequence(vec![Value::String("build_app".into())]))
+    );
+
+    // The setup job checks the registry when it regenerates the config
+    let setup: Value = serde_yaml::from_str(
+        &fs::read_to_string(project.path().join("out/.circleci/config.yml")).unwrap(),
+    )
+    .unwrap();
+    let verifies = job_steps(&setup, "setup").iter().any(|step| {
+        step["run"]["command"]
+            .as_str()
+            .is_some_and(|command| command.contains("cigen generate main --verify-images"))
+    });
+    assert!(verifies);
+}
+
+#[test]
+fn step_timing_wraps_run_steps_and_stores_the_log() {
+    let jobs = [(
+        "rspec",
+        "image: cimg/ruby:3.3\ncache: gems\nsource_files: [app/**/*.rb]\nsteps:\n  - run:\n      name: Bob's specs\n      command: bundle exec rspec\n",
+    )];
+    let caches =
+        "caches:\n  gems:\n    paths: [vendor/bundle]\n    checksum_sources: [Gemfile.lock]\n";
+    let project = write_config(
+        &format!("provider: circleci\ninstrumentation:\n  step_timing: true\n{caches}"),
+        &jobs,
+    );
+    let main = generate(project.path());
+    let steps = job_steps(&main, "rspec");
+
+    let run_steps: Vec<&Value> = steps.iter().filter_map(|step| step.get("run")).collect();
+    assert!(run_steps.len() > 1, "{steps:?}");
+    for run in &run_steps {
+        let command = run["command"].as_str().or(run.as_str()).unwrap();
+        assert!(command.starts_with("cigen_timing_step="), "{command}");
+    }
+    let user = run_steps
+        .iter()
+        .find(|run| run["name"].as_str() == Some("Bob's specs"))
+        .unwrap();
+    assert!(
+        user["command"]
+            .as_str()
+            .unwrap()
+            .starts_with("cigen_timing_step='Bob'\\''s specs'\n")
+    );
+    // Injected hash steps are timed; checkout and cache steps are left alone
+    let hash = run_steps
+        .iter()
+        .find(|run| run["name"].as_str() == Some("Compute job hash"))
+        .unwrap();
+    assert!(hash["command"].as_str().unwrap().contains("cigen-timing:"));
+    assert_eq!(steps[0].as_str(), Some("checkout"));
+    assert!(
+        steps
+            .iter()
+            .any(|step| step["restore_cache"]["name"].as_str() == Some("Restore gems cache"))
+    );
+    let store = &steps.last().unwrap()["store_artifacts"];
+    assert_eq!(store["path"].as_str(), Some("/tmp/cigen/timings.log"));
+    assert_eq!(store["when"].as_str(), Some("always"));
+
+    // Off by default
+    let project = write_config(&format!("provider: circleci\n{caches}"), &jobs);
+    generate(project.path());
+    let yaml = fs::read_to_string(project.path().join("out/.circleci/main.yml")).unwrap();
+    assert!(!yaml.contains("cigen_timing"), "{yaml}");
+    assert!(!yaml.contains("timings.log"), "{yaml}");
+}
+
+#[test]
+fn retry_wraps_run_commands_in_a_retry_loop() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "deploy",
+            r#"image: cimg/base:stable
+retry: {max: 2, backoff_seconds: 10}
+steps:
+  - run: ./scripts/fetch-assets.sh
+  - run:
+      name: Upload
+      command: |
+        echo "it's \"quoted\""
+        ./upload.sh
+      retry: {max: 4, when: [75]}
+"#,
+        )],
+    );
+    let main = generate(project.path());
+    let commands: Vec<&str> = job_steps(&main, "deploy")
+        .iter()
+        .filter_map(|step| step["run"]["command"].as_str())
+        .collect();
+    assert_eq!(commands.len(), 2, "{commands:?}");
+    assert!(
+        commands[0].starts_with(
+            "cigen_retry_script=$(mktemp)\ncat > \"$cigen_retry_script\" <<'CIGEN_RETRY_EOF'\n./scripts/fetch-assets.sh\nCIGEN_RETRY_EOF\nfor cigen_retry_attempt in $(seq 1 3); do\n"
+        ),
+        "{}",
+        commands[0]
+    );
+    assert!(commands[0].contains("  sleep 10\n"), "{}", commands[0]);
+    // The step's own retry replaces the job's, and its command is kept verbatim
+    assert!(
+        commands[1].contains(
+            r#"<<'CIGEN_RETRY_EOF'
+echo "it's \"quoted\""
+./upload.sh
+CIGEN_RETRY_EOF
+"#
+        ),
+        "{}",
+        commands[1]
+    );
+    assert!(commands[1].contains("$(seq 1 5)"), "{}", commands[1]);
+    assert!(
+        commands[1].contains(r#"case "$cigen_retry_status" in 75) ;; *) break ;; esac"#),
+        "{}",
+        commands[1]
+    );
+}
+
+#[test]
+fn cloud_auth_sets_up_credentials_through_orbs() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[
+            (
+                "deploy",
+                "image: cimg/base:stable\ncloud_auth:\n  aws:\n    role_arn: arn:aws:iam::123456789012:role/deploy\n    region: us-east-1\n  gcp:\n    workload_identity_provider: projects/123456/locations/global/workloadIdentityPools/ci/providers/circleci\n    service_account: deploy@example.iam.gserviceaccount.com\nsteps:\n  - run: aws s3 sync public s3://assets\n",
+            ),
+            (
+                "test",
+                "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+            ),
+        ],
+    );
+    let main = generate(project.path());
+
+    assert_eq!(
+        main["orbs"]["aws-cli"].as_str(),
+        Some("circleci/aws-cli@5.1.1")
+    );
+    assert_eq!(
+        main["orbs"]["gcp-cli"].as_str(),
+        Some("circleci/gcp-cli@3.3.1")
+    );
+    let steps = job_steps(&main, "deploy");
+    let setup = steps
+        .iter()
+        .position(|step| step.get("aws-cli/setup").is_some())
+        .unwrap_or_else(|| panic!("{steps:?}"));
+    assert_eq!(
+        steps[setup]["aws-cli/setup"]["role_arn"].as_str(),
+        Some("arn:aws:iam::123456789012:role/deploy")
+    );
+    assert_eq!(
+        steps[setup]["aws-cli/setup"]["region"].as_str(),
+        Some("us-east-1")
+    );
+    assert_eq!(
+        steps[setup + 1]["gcp-cli/setup"]["use_oidc"].as_bool(),
+        Some(true)
+    );
+    // Credentials are ready before the job's own steps
+    assert_eq!(
+        steps[setup + 2]["run"]["command"].as_str(),
+        Some("aws s3 sync public s3://assets")
+    );
+    let environment = &main["jobs"]["deploy"]["environment"];
+    assert_eq!(environment["OIDC_WIP_ID"].as_str(), Some("ci"));
+    assert_eq!(
+        environment["OIDC_SERVICE_ACCOUNT_EMAIL"].as_str(),
+        Some("deploy@example.iam.gserviceaccount.com")
+    );
+    assert!(
+        !job_steps(&main, "test")
+            .iter()
+            .any(|step| step.get("aws-cli/setup").is_some())
+    );
+
+    // No orbs without cloud_auth
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "test",
+            "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+        )],
+    );
+    let main = generate(project.path());
+    assert!(main["orbs"].get("aws-cli").is_none(), "{:?}", main["orbs"]);
+    assert!(main["orbs"].get("gcp-cli").is_none(), "{:?}", main["orbs"]);
+}
+
+#[test]
+fn cloud_auth_requires_a_role_arn() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "deploy",
+            "image: cimg/base:stable\ncloud_auth:\n  aws:\n    region: us-east-1\nsteps:\n  - run: ./deploy.sh\n",
+        )],
+    );
+    generate_command(project.path())
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "cloud_auth.aws requires role_arn",
+        ));
+}
+
+#[test]
+fn branches_filter_the_workflow_entry_and_guard_the_status_probe() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[
+            (
+                "deploy",
+                "image: cimg/base:stable\nbranches: [main, /release-.*/]\nsource_files: [deploy/**]\nsteps:\n  - run: ./deploy.sh\n",
+            ),
+            (
+                "test",
+                "image: cimg/base:stable\nsource_files: [src/**]\nsteps:\n  - run: make test\n",
+            ),
+        ],
+    );
+    let main = generate(project.path());
+
+    let entries = main["workflows"]["main"]["jobs"].as_sequence().unwrap();
+    let deploy = entries
+        .iter()
+        .find_map(|entry| entry.get("deploy"))
+        .expect("deploy entry");
+    assert_eq!(
+        deploy["filters"]["branches"]["only"],
+        serde_yaml::from_str::<Value>("[main, /release-.*/]").unwrap()
+    );
+    assert!(entries.iter().any(|entry| entry.as_str() == Some("test")));
+
+    let setup: Value = serde_yaml::from_str(
+        &fs::read_to_string(project.path().join("out/.circleci/config.yml")).unwrap(),
+    )
+    .unwrap();
+    // The job-status lookups are left out when `skip_cache` is set
+    let steps = job_steps(&setup, "setup")
+        .iter()
+        .find_map(|step| step["unless"]["steps"].as_sequence())
+        .expect("job-status lookups");
+    let step = |kind: &str, name: &str| {
+        steps
+            .iter()
+            .find(|step| step[kind]["name"].as_str() == Some(name))
+            .unwrap_or_else(|| panic!("missing {kind} step {name}"))[kind]
+            .clone()
+    };
+    let command = |name: &str| step("run", name)["command"].as_str().unwrap().to_string();
+    let guard = r#"if [ "${CIRCLE_BRANCH:-}" = 'main' ] || grep -Eqx 'release-.*' <<< "${CIRCLE_BRANCH:-}"; then"#;
+
+    let hash = command("Hash sources for deploy");
+    assert!(hash.contains(guard), "{hash}");
+    assert!(hash.contains("JOB_HASH=branch-filtered"), "{hash}");
+    let probe = command("Probe exists: deploy");
+    assert!(probe.contains(&format!("{guard}\nif [ -f")), "{probe}");
+    let keys = step("restore_cache", "Restore job status: deploy")["keys"].clone();
+    assert_eq!(keys.as_sequence().unwrap().len(), 1, "{keys:?}");
+
+    for name in ["Hash sources for test", "Probe exists: test"] {
+        let unguarded = command(name);
+        assert!(!unguarded.contains("CIRCLE_BRANCH"), "{unguarded}");
+    }
+    let keys = step("restore_cache", "Restore job status: test")["keys"].clone();
+    assert_eq!(keys.as_sequence().unwrap().len(), 2, "{keys:?}");
+}
+
+#[test]
+fn branches_reject_invalid_regexes() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "deploy",
+            "image: cimg/base:stable\nbranches: /release-(/\nsteps:\n  - run: ./deploy.sh\n",
+        )],
+    );
+    let output = generate_command(project.path()).assert().failure();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains("branches pattern '/release-(/' is not a valid regex"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn setup_install_strategy_picks_the_setup_image_and_install_steps() {
+    let job =
+        "image: cimg/ruby:3.3\nsource_files: [app/**/*.rb]\nsteps:\n  - run: bundle exec rspec\n";
+    let setup_steps = |root_config: &str| -> Value {
+        let project = write_config(root_config, &[("rspec", job)]);
+        generate(project.path());
+        let setup = fs::read_to_string(project.path().join("out/.circleci/config.yml")).unwrap();
+        serde_yaml::from_str::<Value>(&setup).unwrap()["jobs"]["setup"].clone()
+    };
+    let step_names = |setup: &Value| -> Vec<String> {
+        setup["steps"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .filter_map(|step| step.as_mapping()?.values().next()?["name"].as_str())
+            .map(str::to_string)
+            .collect()
+    };
+
+    let download = setup_steps(
+        "provider: circleci\nsetup_options:\n  install_strategy: download\n  cigen_version: 0.4.2\n",
+    );
+    assert_eq!(
+        download["docker"][0]["image"].as_str(),
+        Some("cimg/base:stable")
+    );
+    assert!(step_names(&download).contains(&"Download cigen 0.4.2".to_string()));
+
+    let compile = setup_steps(
+        "provider: circleci\ncache_version: 2\nsetup_options:\n  install_strategy: compile\n",
+    );
+    let names = step_names(&compile);
+    let position = |name: &str| names.iter().position(|step| step == name).unwrap();
+    assert!(position("Restore cigen build cache") < position("Compile cigen"));
+    assert!(position("Compile cigen") < position("Save cigen build cache"));
+    let save = compile["steps"]
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .find_map(|step| step.get("save_cache"))
+        .unwrap();
+    assert_eq!(
+        save["key"].as_str(),
+        Some(r#"v2-cigen-cargo-{{ arch }}-{{ checksum "Cargo.lock" }}"#)
+    );
+
+    let path = setup_steps("provider: circleci\n");
+    assert_eq!(path["docker"][0]["image"].as_str(), Some("cimg/rust:1.76"));
+    assert!(!step_names(&path).iter().any(|name| name.contains("cigen")));
+}
+
+#[test]
+fn setup_continuation_uses_the_configured_orb_or_the_api() {
+    let setup_config = |setup_options: &str| -> Value {
+        let project = write_config(
+            &format!(
+                "provider: circleci\nparameters:\n  deploy:\n    type: boolean\n    default: false\nsetup_options:\n{setup_options}"
+            ),
+            &[(
+                "test",
+                "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+            )],
+        );
+        generate(project.path());
+        let setup = fs::read_to_string(project.path().join("out/.circleci/config.yml")).unwrap();
+        serde_yaml::from_str(&setup).unwrap()
+    };
+    let last_step = |config: &Value| {
+        config["jobs"]["setup"]["steps"]
+            .as_sequence()
+            .unwrap()
+            .last()
+            .unwrap()
+            .clone()
+    };
+    let parameters_step = |config: &Value| {
+        let steps = config["jobs"]["setup"]["steps"].as_sequence().unwrap();
+        steps[steps.len() - 2].clone()
+    };
+
+    let orb = setup_config("  image: cimg/base:2024.01\n  continuation_orb_version: 1.1.0\n");
+    assert_eq!(
+        orb["jobs"]["setup"]["docker"][0]["image"].as_str(),
+        Some("cimg/base:2024.01")
+    );
+    assert_eq!(
+        orb["orbs"]["continuation"].as_str(),
+        Some("circleci/continuation@1.1.0")
+    );
+    assert_eq!(
+        last_step(&orb)["continuation/continue"]["parameters"].as_str(),
+        Some("/tmp/pipeline-parameters.json")
+    );
+    let write = &parameters_step(&orb)["run"];
+    assert_eq!(
+        write["environment"]["PIPELINE_PARAMETER_DEPLOY"].as_str(),
+        Some("<< pipeline.parameters.deploy >>")
+    );
+
+    let api = setup_config("  continuation: api\n");
+    assert!(api.get("orbs").is_none(), "{api:?}");
+    let run = &last_step(&api)["run"];
+    assert_eq!(parameters_step(&api)["run"], *write);
+    assert!(
+        run["command"]
+            .as_str()
+            .unwrap()
+            .contains("--slurpfile parameters /tmp/pipeline-parameters.json")
+    );
+    assert!(
+        run["command"]
+            .as_str()
+            .unwrap()
+            .contains("https://circleci.com/api/v2/pipeline/continue")
+    );
+
+    let project = write_config(
+        "provider: circleci\nsetup_options:\n  continuation: api\n  continuation_orb_version: 1.1.0\n",
+        &[(
+            "test",
+            "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+        )],
+    );
+    let output = generate_command(project.path()).assert().failure();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains("continuation_orb_version only applies to continuation: orb"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn no_job_status_cache_reruns_jobs_from_fresh_dependency_caches() {
+    let project = write_config(
+        "provider: circleci\ncaches:\n  gems:\n    paths: [vendor/bundle]\n    key_parts: []\n    checksum_sources: [Gemfile.lock]\n",
+        &[(
+            "test",
+            "image: cimg/ruby:3.3\nsource_files:\n  - app/**/*\ncache: [gems]\nsteps:\n  - run: bundle exec rspec\n",
+        )],
+    );
+    let step_names = |main: &Value| -> Vec<String> {
+        job_steps(main, "test")
+            .iter()
+            .filter_map(|step| step.as_mapping()?.values().next()?.get("name"))
+            .filter_map(|name| name.as_str().map(str::to_string))
+            .collect()
+    };
+    let cache_keys = |main: &Value| -> Vec<String> {
+        job_steps(main, "test")
+            .iter()
+            .filter(|step| {
+                [&step["restore_cache"]["name"], &step["save_cache"]["name"]]
+                    .iter()
+                    .any(|name| name.as_str().is_some_and(|name| name.contains("gems")))
+            })
+            .flat_map(|step| {
+                let restore = step["restore_cache"]["keys"].as_sequence().cloned();
+                let save = step["save_cache"]["key"].clone();
+                restore.unwrap_or_default().into_iter().chain([save])
+            })
+            .filter_map(|key| key.as_str().map(str::to_string))
+            .collect()
+    };
+
+    let main = generate(project.path());
+    let names = step_names(&main);
+    for name in [
+        "Compute job hash",
+        "Record job completion",
+        "Persist job status",
+    ] {
+        assert!(names.iter().any(|n| n == name), "{name} missing: {names:?}");
+    }
+    let keys = cache_keys(&main);
+    assert!(!keys.is_empty());
+    assert!(keys.iter().all(|key| key.starts_with("gems-")), "{keys:?}");
+
+    generate_command(project.path())
+        .args(["--no-job-status-cache", "--cache-nonce", "pipeline-7"])
+        .assert()
+        .success();
+    let yaml = fs::read_to_string(project.path().join("out/.circleci/main.yml")).unwrap();
+    let main: Value = serde_yaml::from_str(&yaml).unwrap();
+    let names = step_names(&main);
+    for name in [
+        "Compute job hash",
+        "Record job completion",
+        "Persist job status",
+    ] {
+        assert!(
+            !names.iter().any(|n| n == name),
+            "{name} present: {names:?}"
+        );
+    }
+    assert!(names.iter().any(|n| n == "Restore gems cache"), "{names:?}");
+    let keys = cache_keys(&main);
+    assert!(!keys.is_empty());
+    assert!(
+        keys.iter().all(|key| key.starts_with("pipeline-7-gems-")),
+        "{keys:?}"
+    );
+
+    generate_command(project.path())
+        .args(["--cache-nonce", "{{ epoch }}"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Invalid cache nonce"));
+}
+
+#[test]
+fn skip_cache_parameter_regenerates_without_the_job_status_cache() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "test",
+            "image: cimg/base:current\nsource_files:\n  - src/**/*\nsteps:\n  - run: make test\n",
+        )],
+    );
+    generate_command(project.path()).assert().success();
+    let yaml = fs::read_to_string(project.path().join("out/.circleci/config.yml")).unwrap();
+    let setup: Value = serde_yaml::from_str(&yaml).unwrap();
+    assert_eq!(
+        setup["parameters"]["skip_cache"]["type"].as_str(),
+        Some("boolean")
+    );
+
+    let steps = setup["jobs"]["setup"]["steps"]
+        .as_sequence()
+        .expect("setup job steps");
+    let status = steps
+        .iter()
+        .find_map(|step| step.get("unless"))
+        .expect("job-status lookups should sit in an unless block");
+    assert_eq!(
+        status["condition"].as_str(),
+        Some("<< pipeline.parameters.skip_cache >>")
+    );
+    assert!(
+        status["steps"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .any(|step| { step["run"]["name"].as_str() == Some("Hash sources for test") })
+    );
+
+    let generate = steps
+        .iter()
+        .find(|step| step["run"]["name"].as_str() == Some("Generate filtered main"))
+        .expect("generate step");
+    let command = generate["run"]["command"].as_str().unwrap();
+    // The setup job regenerates into the directory it was generated into
+    let output = project.path().join("out");
+    assert!(
+        command.contains(&format!(
+            "if [ \"<< pipeline.parameters.skip_cache >>\" = \"true\" ]; then\n  cigen generate main --output '{}' --no-job-status-cache --cache-nonce \"pipeline-<< pipeline.number >>\"\n",
+            output.display()
+        )),
+        "{command}"
+    );
+    assert!(!yaml.contains("circleci step halt"));
+}
+
+#[test]
+fn cache_paths_resolve_against_the_working_directory() {
+    let project = write_config(
+        "provider: circleci\ncaches:\n  gems:\n    paths: [vendor/bundle, $HOME/.bundle]\n    checksum_sources: [Gemfile.lock]\n",
+        &[(
+            "test",
+            "image: cimg/ruby:3.3\nworking_directory: ~/app\ncache:\n  gems: null\n  yarn:\n    paths: [./node_modules, ~/.cache/yarn, /usr/local/lib]\nsteps:\n  - run: bundle exec rspec\n",
+        )],
+    );
+    let main = generate(project.path());
+    let paths = |name: &str| -> Vec<String> {
+        job_steps(&main, "test")
+            .iter()
+            .find(|step| step["save_cache"]["name"].as_str() == Some(name))
+            .unwrap_or_else(|| panic!("missing {name}"))["save_cache"]["paths"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|path| path.as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(
+        paths("Save gems cache"),
+        ["~/app/vendor/bundle", "~/.bundle"]
+    );
+    assert_eq!(
+        paths("Save yarn cache"),
+        ["~/app/node_modules", "~/.cache/yarn", "/usr/local/lib"]
+    );
+
+    let project = write_config(
+        "provider: circleci\n",
+        &[(
+            "test",
+            "image: cimg/ruby:3.3\nworking_directory: ~/app\ncache:\n  shared: ../../../shared\nsteps:\n  - run: make\n",
+        )],
+    );
+    generate_command(project.path())
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Cache path '../../../shared' leaves the home directory",
+        ));
+}
+
+#[test]
+fn cleanup_runs_after_steps_and_before_the_job_records_completion() {
+    let project = write_config(
+        "provider: circleci\n",
+        &[
+            (
+                "test",
+                "image: cimg/ruby:3.3\nsource_files:\n  - app/**/*\nsteps:\n  - run: bundle exec rspec\ncleanup:\n  - run:\n      name: Drop test database\n      command: dropdb --if-exists \"test_$CIRCLE_BUILD_NUM\"\n",
+            ),
+            (
+                "build",
+                "image: cimg/base:current\nsteps:\n  - run: make image\ncleanup_on: failure\ncleanup:\n  - run: docker buildx rm builder\n",
+            ),
+        ],
+    );
+    let main = generate(project.path());
+
+    let names: Vec<&str> = job_steps(&main, "test")
+        .iter()
+        .filter_map(|step| step.as_mapping()?.values().next()?.get("name")?.as_str())
+        .collect();
+    let position = |name: &str| {
+        names
+            .iter()
+            .position(|n| *n == name)
+            .unwrap_or_else(|| panic!("{name} missing: {names:?}"))
+    };
+    assert!(position("Drop test database") < position("Record job completion"));
+    assert!(position("Record job completion") < position("Persist job status"));
+
+    let cleanup = |job: &str| {
+        job_steps(&main, job)
+            .iter()
+            .find(|step| {
+                step["run"]["command"]
+                    .as_str()
+                    .is_some_and(|command| command.contains("drop") || command.contains("rm"))
+            })
+            .unwrap_or_else(|| panic!("{job} has no cleanup step"))["run"]["when"]
+            .clone()
+    };
+    assert_eq!(cleanup("test").as_str(), Some("always"));
+    assert_eq!(cleanup("build").as_str(), Some("on_fail"));
+    // A failed job still never records completion
+    let marker = job_steps(&main, "test")
+        .iter()
+        .find(|step| step["run"]["name"].as_str() == Some("Record job completion"))
+        .unwrap();
+    assert_eq!(marker["run"]["when"].as_str(), Some("on_success"));
+
+    let invalid = write_config(
+        "provider: circleci\n",
+        &[(
+            "test",
+            "image: cimg/base:current\nsteps:\n  - run: make test\ncleanup:\n  - save_cache:\n      key: x\n      paths: [tmp]\n",
+        )],
+    );
+    let output = generate_command(invalid.path()).assert().failure();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains("cleanup steps must be run steps"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn group_needs_expand_or_go_through_a_gate_job() {
+    let jobs = [
+        (
+            "rspec",
+            "image: cimg/ruby:3.3\ngroup: tests\nsteps:\n  - run: bundle exec rspec\n",
+        ),
+        (
+            "jest",
+            "image: cimg/node:20.11\ngroup: tests\nsteps:\n  - run: npx jest\n",
+        ),
+        (
+            "deploy",
+            "image: cimg/base:current\nrequires: [group:tests]\nsteps:\n  - run: ./deploy\n",
+        ),
+    ];
+    let requires = |main: &Value, job_id: &str| -> Vec<String> {
+        let mut requires: Vec<String> = main["workflows"]["main"]["jobs"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .find_map(|entry| entry.get(job_id))
+            .and_then(|job| job["requires"].as_sequence())
+            .map(|requires| {
+                requires
+                    .iter()
+                    .map(|job| job.as_str().unwrap().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        requires.sort();
+        requires
+    };
+
+    let simple = write_config("provider: circleci\n", &jobs);
+    let main = generate(simple.path());
+    assert_eq!(requires(&main, "deploy"), ["jest", "rspec"]);
+    assert!(main["jobs"].get("tests_gate").is_none());
+
+    let gated = write_config(
+        "provider: circleci\ngroups:\n  tests:\n    gate: true\n",
+        &jobs,
+    );
+    let main = generate(gated.path());
+    assert_eq!(requires(&main, "deploy"), ["tests_gate"]);
+    assert_eq!(requires(&main, "tests_gate"), ["jest", "rspec"]);
+    assert!(
+        !job_steps(&main, "tests_gate")
+            .iter()
+            .any(|step| step.as_str() == Some("checkout"))
+    );
+
+    let failure = |root_config: &str, jobs: &[(&str, &str)]| {
+        let project = write_config(root_config, jobs);
+        let output = generate_command(project.path()).assert().failure();
+        String::from_utf8_lossy(&output.get_output().stderr).to_string()
+    };
+    let stderr = failure(
+        "provider: circleci\n",
+        &[
+            jobs[0],
+            (
+                "deploy",
+                "image: cimg/base:current\nneeds: [group:tsets]\nsteps:\n  - run: ./deploy\n",
+            ),
+        ],
+    );
+    assert!(
+        stderr.contains("needs unknown group 'tsets'") && stderr.contains("tests"),
+        "{stderr}"
+    );
+    let stderr = failure(
+        "provider: circleci\ngroups:\n  smoke:\n    gate: true\n",
+        &jobs,
+    );
+    assert!(
+        stderr.contains("Group 'smoke' is configured under groups, but no job sets group: smoke"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn setup_jobs_generate_main_without_a_main_workflow() {
+    let project = tempdir().unwrap();
+    let ci_dir = project.path().join(".cigen/workflows/ci/jobs");
+    fs::create_dir_all(&ci_dir).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: circleci\n",
+    )
+    .unwrap();
+    fs::write(
+        ci_dir.join("test.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+    )
+    .unwrap();
+
+    generate_command(project.path()).assert().success();
+    let setup = fs::read_to_string(project.path().join("out/.circleci/config.yml")).unwrap();
+    assert!(setup.contains("cigen generate main"), "{setup}");
+
+    // What the setup job runs: the continued config, with the `ci` workflow
+    generate_command(project.path())
+        .arg("main")
+        .assert()
+        .success();
+    let yaml = fs::read_to_string(project.path().join("out/.circleci/main.yml")).unwrap();
+    let main: Value = serde_yaml::from_str(&yaml).unwrap();
+    assert!(main["jobs"].get("test").is_some(), "{yaml}");
+    assert!(main["workflows"].get("ci").is_some(), "{yaml}");
+}
+
+#[test]
+fn save_when_on_failure_renders_circleci_on_fail() {
+    let project = write_config(
+        "provider: circleci\ncaches:\n  gems:\n    paths: [vendor/bundle]\n    checksum_sources: [Gemfile.lock]\n",
+        &[(
+            "test",
+            "image: cimg/ruby:3.3\ncache:\n  gems:\n    save_when: on_failure\nsteps:\n  - run: bundle exec rspec\n",
+        )],
+    );
+    let main = generate(project.path());
+    let save = job_steps(&main, "test")
+        .iter()
+        .find(|step| step.get("save_cache").is_some())
+        .expect("save_cache step");
+    assert_eq!(save["save_cache"]["when"].as_str(), Some("on_fail"));
+}
+
+#[test]
+fn cache_warmup_runs_instead_of_the_other_workflows() {
+    let project = write_config(
+        "provider: circleci\ncache_warmup:\n  schedule: \"0 4 * * *\"\n  caches: [node_modules]\ncaches:\n  node_modules:\n    paths: [node_modules]\n    checksum_sources: [package-lock.json]\n    warmup_command: npm ci\n",
+        &[(
+            "test",
+            "image: cimg/node:20.11\ncache:\n  node_modules: null\nsteps:\n  - run: npm test\n",
+        )],
+    );
+    fs::write(project.path().join("package-lock.json"), "{}").unwrap();
+    let main = generate(project.path());
+    let workflows = &main["workflows"];
+
+    let warmup: Value =
+        serde_yaml::from_str("equal: [true, << pipeline.parameters.cache_warmup >>]").unwrap();
+    assert_eq!(workflows["cache_warmup"]["when"], warmup);
+    assert_eq!(workflows["main"]["unless"], warmup);
+
+    let steps = job_steps(&main, "warm_node_modules");
+    assert!(
+        steps
+            .iter()
+            .any(|step| step["save_cache"]["name"].as_str() == Some("Save node_modules cache"))
+    );
+}
@@ -89,7 +89,7 @@ fn assert_has_job_status_steps(job_id: &str, job_value: &Value) {
             let key_matches = save_cache_map
                 .get(&Value::String("key".into()))
                 .and_then(Value::as_str)
-                .map(|value| value.contains("job_status-exists-v1"))
+                .map(|value| value.contains("job_status-exists-"))
                 .unwrap_or(false);
             let when_matches = save_cache_map
                 .get(&Value::String("when".into()))
@@ -163,7 +163,9 @@ fn circleci_job_status_steps_for_rails_fixture() {
     let output = tempdir().expect("failed to create tempdir");
     run_generate(&config_dir, output.path());
 
-    let jobs = load_jobs_map(&output.path().join(".circleci/main.yml"));
+    // The fixture sets `output_path: ./build`
+
+    let jobs = load_jobs_map(&output.path().join("build/main.yml"));
 
     for job_id in &with_sources {
         let sanitized_id = job_id.replace(['/', '\\'], "_");
@@ -200,3 +200,1461 @@ fn hash_job_mode_reflects_config_and_source_changes() -> Result<(), Box<dyn std:
     assert_ne!(digest_one.trim(), digest_two.trim());
     Ok(())
 }
+
+#[test]
+fn hash_docker_base_matches_generated_image_tags() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::create_dir_all(dir.path().join("config"))?;
+    fs::write(
+        dir.path().join(".cigen/config.yml"),
+        "provider: circleci\ndocker_build:\n  registry:\n    repo: docker.io/acme\n  images:\n    - name: ci_base\n      arch: [amd64]\n      hash_sources: [Gemfile.lock, \"config/*.yml\"]\n    - name: app\n      arch: [amd64]\n      depends_on: [ci_base]\n",
+    )?;
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: ci_base\nsteps:\n  - run: make test\n",
+    )?;
+    fs::write(dir.path().join("Dockerfile"), "FROM alpine:3.19\n")?;
+    fs::write(dir.path().join("Gemfile.lock"), "GEM\n")?;
+    fs::write(dir.path().join("config/a.yml"), "a: 1\n")?;
+    fs::write(dir.path().join("config/b.yml"), "b: 2\n")?;
+
+    let mut generate = Command::cargo_bin("cigen")?;
+    generate
+        .current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .arg("generate");
+    generate.assert().success();
+    let main = fs::read_to_string(dir.path().join(".circleci/main.yml"))?;
+
+    let mut image = Command::cargo_bin("cigen")?;
+    image
+        .current_dir(dir.path())
+        .args(["hash", "--image", "ci_base"]);
+    let hash = String::from_utf8(image.assert().success().get_output().stdout.clone())?;
+    let hash = hash.trim();
+    assert!(
+        main.contains(&format!("image: docker.io/acme/ci_base:{hash}-amd64")),
+        "{hash}\n{main}"
+    );
+
+    let mut all = Command::cargo_bin("cigen")?;
+    all.current_dir(dir.path()).args(["hash", "--docker-base"]);
+    let lines = String::from_utf8(all.assert().success().get_output().stdout.clone())?;
+    let lines: Vec<&str> = lines.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], format!("ci_base={hash}"));
+    assert!(lines[1].starts_with("app="), "{lines:?}");
+
+    let mut json = Command::cargo_bin("cigen")?;
+    json.current_dir(dir.path())
+        .args(["hash", "--image", "ci_base", "--json"]);
+    let report: Value = serde_json::from_slice(&json.assert().success().get_output().stdout)?;
+    let entry = &report["images"][0];
+    assert_eq!(entry["hash"].as_str(), Some(hash));
+    assert_eq!(
+        entry["patterns"][0]["pattern"].as_str(),
+        Some("Gemfile.lock")
+    );
+    assert_eq!(entry["patterns"][0]["files"].as_u64(), Some(1));
+    assert_eq!(entry["patterns"][1]["files"].as_u64(), Some(2));
+
+    let mut unknown = Command::cargo_bin("cigen")?;
+    unknown
+        .current_dir(dir.path())
+        .args(["hash", "--image", "ci_bse"]);
+    let stderr = String::from_utf8(unknown.assert().failure().get_output().stderr.clone())?;
+    assert!(
+        stderr.contains("Unknown docker_build image 'ci_bse'. Did you mean 'ci_base'?"),
+        "{stderr}"
+    );
+    Ok(())
+}
+
+#[test]
+fn list_jobs_outputs_filtered_json() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/test/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(dir.path().join(".cigen/config.yml"), "provider: circleci\n")?;
+    fs::write(
+        jobs_dir.join("rspec.yml"),
+        "image: cimg/ruby:3.3\nparallelism: 4\nmatrix:\n  arch: [amd64, arm64]\n",
+    )?;
+    fs::write(jobs_dir.join("jest.yml"), "image: cimg/node:20.0\n")?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path()).args([
+        "list",
+        "jobs",
+        "--workflow",
+        "test",
+        "--format",
+        "json",
+        "--filter",
+        "image=ruby",
+    ]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let jobs: Value = serde_json::from_slice(&output)?;
+
+    let jobs = jobs.as_array().expect("jobs array");
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0]["id"], "rspec");
+    assert_eq!(jobs[0]["parallelism"], 4);
+    assert_eq!(
+        jobs[0]["architectures"],
+        serde_json::json!(["amd64", "arm64"])
+    );
+    Ok(())
+}
+
+#[test]
+fn list_jobs_summarizes_the_resolved_jobs() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/test/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(
+        dir.path().join(".cigen/config.yml"),
+        "provider: circleci\ncaches:\n  gems:\n    paths: [vendor/bundle]\n    checksum_sources: [Gemfile.lock]\n",
+    )?;
+    fs::write(
+        jobs_dir.join("rspec.yml"),
+        "image: cimg/ruby:{{ matrix.ruby }}\ncache: gems\nmatrix:\n  - { arch: arm64, ruby: \"3.3\" }\n  - { arch: amd64, ruby: \"3.3\" }\n  - { arch: arm64, ruby: \"3.2\" }\nsteps:\n  - run: bundle exec rspec\n",
+    )?;
+    fs::write(
+        jobs_dir.join("jest.yml"),
+        "image: cimg/node:20.0\nneeds: [rspec]\nsteps:\n  - run: npm test\n",
+    )?;
+
+    let output = Command::cargo_bin("cigen")?
+        .current_dir(dir.path())
+        .args(["list", "jobs", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let jobs: Value = serde_json::from_slice(&output)?;
+
+    let rspec = &jobs[1];
+    assert_eq!(rspec["id"], "rspec");
+    assert_eq!(
+        rspec["architectures"],
+        serde_json::json!(["amd64", "arm64"])
+    );
+    assert_eq!(rspec["image"], "cimg/ruby:3.2,cimg/ruby:3.3");
+    assert_eq!(rspec["caches"], serde_json::json!(["gems"]));
+    // `needs` on a matrix job names the job, not its variants
+    assert_eq!(jobs[0]["id"], "jest");
+    assert_eq!(jobs[0]["needs"], serde_json::json!(["rspec"]));
+    Ok(())
+}
+
+#[test]
+fn list_jobs_rejects_unknown_filter_field() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    fs::create_dir_all(dir.path().join(".cigen/workflows/test/jobs"))?;
+    fs::write(dir.path().join(".cigen/config.yml"), "provider: circleci\n")?;
+    fs::write(
+        dir.path().join(".cigen/workflows/test/jobs/rspec.yml"),
+        "image: cimg/ruby:3.3\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .args(["list", "jobs", "--filter", "colour=red"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn inspect_job_prints_resolved_variant() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(dir.path().join(".cigen/config.yml"), "provider: circleci\n")?;
+    fs::write(
+        jobs_dir.join("build.yml"),
+        "image: cimg/base:current\nmatrix:\n  arch: [amd64, arm64]\nsteps:\n  - run: make\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["inspect", "job", "build", "--arch", "arm64", "--explain"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let stdout = String::from_utf8(output)?;
+
+    assert!(stdout.contains("jobs.build-arm64"), "{stdout}");
+    assert!(stdout.contains("- checkout"), "{stdout}");
+    assert!(stdout.contains("steps: job file"), "{stdout}");
+
+    let mut ambiguous = Command::cargo_bin("cigen")?;
+    ambiguous
+        .current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["inspect", "job", "build"]);
+    ambiguous.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn inspect_job_explain_names_the_layer_of_each_field() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let workflow_dir = dir.path().join(".cigen/workflows/main");
+    fs::create_dir_all(&workflow_dir)?;
+    fs::write(
+        dir.path().join(".cigen/config.yml"),
+        "provider: circleci\nenv:\n  CI_MODE: strict\nglobal_steps:\n  before:\n    - run: scan-secrets\n",
+    )?;
+    fs::write(
+        workflow_dir.join("config.yml"),
+        "env:\n  DEPLOY_ENV: staging\n",
+    )?;
+    fs::write(
+        workflow_dir.join("jobs.yml"),
+        "build:\n  image: cimg/base:current\n  steps:\n    - run: make\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["inspect", "job", "build", "--explain"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let stdout = String::from_utf8(output)?;
+
+    assert!(
+        stdout.contains("image: job file (workflows/main/jobs.yml, build)"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("environment: config.yml env, workflows/main/config.yml env"),
+        "{stdout}"
+    );
+    assert!(
+        stdout
+            .contains("steps: job file (workflows/main/jobs.yml, build), config.yml global_steps"),
+        "{stdout}"
+    );
+    Ok(())
+}
+
+#[test]
+fn audit_caches_reports_races_and_missing_checksum_files() -> Result<(), Box<dyn std::error::Error>>
+{
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(dir.path().join("Gemfile.lock"), "")?;
+    fs::write(
+        dir.path().join(".cigen/config.yml"),
+        "provider: circleci\ncaches:\n  gems:\n    paths: [vendor/bundle]\n    checksum_sources: [Gemfile.lock]\n",
+    )?;
+    fs::write(
+        jobs_dir.join("rspec.yml"),
+        "matrix:\n  ruby: [\"3.2\", \"3.3\"]\ncache: gems\nsteps:\n  - run: bundle exec rspec\n",
+    )?;
+    fs::write(
+        jobs_dir.join("docs.yml"),
+        "working_directory: docs\ncache: gems\nsteps:\n  - run: bundle exec jekyll build\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["audit", "caches", "--format", "json"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let audit: Value = serde_json::from_slice(&output)?;
+
+    assert_eq!(
+        audit["write_races"][0]["jobs"],
+        serde_json::json!(["rspec-3_2", "rspec-3_3"])
+    );
+    assert_eq!(audit["missing_checksum_files"][0]["job"], "docs");
+    assert_eq!(
+        audit["missing_checksum_files"][0]["file"],
+        "docs/Gemfile.lock"
+    );
+    assert_eq!(audit["distinct_keys"], 2);
+
+    let mut text = Command::cargo_bin("cigen")?;
+    text.current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["audit", "caches"]);
+    let output = text.assert().success().get_output().stdout.clone();
+    let stdout = String::from_utf8(output)?;
+    assert!(stdout.starts_with("KEY"), "{stdout}");
+    assert!(stdout.contains("Write races (1):"), "{stdout}");
+    Ok(())
+}
+
+#[test]
+fn new_job_writes_a_job_from_piped_answers() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/test/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(
+        dir.path().join(".cigen/config.yml"),
+        "provider: github\nservices:\n  postgres:\n    image: postgres:16\n",
+    )?;
+    fs::write(
+        jobs_dir.join("rspec.yml"),
+        "image: cimg/ruby:3.3\nservices: [postgres]\nsteps:\n  - run: bundle exec rspec\n",
+    )?;
+
+    // assert_cmd's Command, which can pipe answers to stdin
+    let mut cmd = assert_cmd::Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .args([
+            "new",
+            "job",
+            "--workflow",
+            "test",
+            "--name",
+            "test/rspec_system",
+        ])
+        .args(["--from", "rspec"])
+        .write_stdin("cimg/ruby:3.3-browsers\npostgre\npostgres\n2\n");
+    let output = cmd.assert().success().get_output().stderr.clone();
+    let prompts = String::from_utf8(output)?;
+    assert!(
+        prompts.contains("Unknown service 'postgre'. Did you mean 'postgres'?"),
+        "{prompts}"
+    );
+
+    let job = fs::read_to_string(jobs_dir.join("test/rspec_system.yml"))?;
+    assert!(
+        job.starts_with("# Job 'test/rspec_system' in the 'test' workflow\n"),
+        "{job}"
+    );
+    assert!(job.contains("image: cimg/ruby:3.3-browsers\n"), "{job}");
+    assert!(job.contains("parallelism: 2\n"), "{job}");
+    assert!(job.contains("  - run: bundle exec rspec\n"), "{job}");
+
+    let mut again = Command::cargo_bin("cigen")?;
+    again
+        .current_dir(dir.path())
+        .args(["new", "job", "-w", "test", "-n", "rspec", "--no-input"]);
+    again.assert().failure();
+    assert!(fs::read_to_string(jobs_dir.join("rspec.yml"))?.contains("bundle exec rspec"));
+    Ok(())
+}
+
+#[test]
+fn run_executes_a_job_after_the_jobs_it_needs() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(jobs_dir.join("test"))?;
+    fs::write(
+        dir.path().join(".cigen/config.yml"),
+        "provider: github\nenv:\n  GREETING: hello\n",
+    )?;
+    fs::write(
+        jobs_dir.join("setup.yml"),
+        "steps:\n  - run: echo \"$GREETING\" > setup.txt\n",
+    )?;
+    fs::write(
+        jobs_dir.join("test/rspec.yml"),
+        "needs: [setup]\nmatrix:\n  ruby: [\"3.2\", \"3.3\"]\nsteps:\n  - run: echo \"{{ matrix.ruby }}\" >> rubies.txt\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .args(["run", "--job", "test/rspec"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let stdout = String::from_utf8(output)?;
+
+    assert!(stdout.contains("passed  setup"), "{stdout}");
+    assert_eq!(fs::read_to_string(dir.path().join("setup.txt"))?, "hello\n");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("rubies.txt"))?,
+        "3.2\n3.3\n"
+    );
+
+    fs::write(jobs_dir.join("setup.yml"), "steps:\n  - run: exit 1\n")?;
+    let mut failing = Command::cargo_bin("cigen")?;
+    failing
+        .current_dir(dir.path())
+        .args(["run", "--job", "test/rspec"]);
+    let output = failing.assert().failure().get_output().stdout.clone();
+    let stdout = String::from_utf8(output)?;
+    assert!(stdout.contains("blocked  rspec-3_2"), "{stdout}");
+    Ok(())
+}
+
+#[test]
+fn generate_var_flags_override_config_vars() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(
+        dir.path().join(".cigen/config.yml"),
+        "provider: circleci\nvars:\n  ruby_version: '3.2'\n  label: config\n  region: config\n",
+    )?;
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: cimg/ruby:{{ ruby_version }}\nsteps:\n  - run: echo {{ label }} {{ region }}\n",
+    )?;
+    fs::write(
+        dir.path().join("vars.yml"),
+        "ruby_version: '3.1'\nregion: eu\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args([
+            "generate",
+            "--var-file",
+            "vars.yml",
+            "--var",
+            "ruby_version=3.3",
+            "--var",
+            "label=a=b",
+        ]);
+    cmd.assert().success();
+
+    let main = fs::read_to_string(dir.path().join(".circleci/main.yml"))?;
+    assert!(main.contains("image: cimg/ruby:3.3"), "{main}");
+    assert!(main.contains("echo a=b eu"), "{main}");
+
+    let mut malformed = Command::cargo_bin("cigen")?;
+    malformed
+        .current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["generate", "--var", "ruby_version"]);
+    let output = malformed.assert().failure().get_output().stderr.clone();
+    let stderr = String::from_utf8(output)?;
+    assert!(
+        stderr.contains("Invalid --var 'ruby_version': expected NAME=VALUE"),
+        "{stderr}"
+    );
+    Ok(())
+}
+
+#[test]
+fn generate_renders_nested_vars_and_j2_job_files() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let cigen_dir = dir.path().join(".cigen");
+    let jobs_dir = cigen_dir.join("workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(
+        cigen_dir.join("config.yml"),
+        "provider: circleci\nvars:\n  db:\n    postgres:\n      version: '15'\n  suites: [models, requests]\n",
+    )?;
+    fs::write(
+        jobs_dir.join("migrate.yml"),
+        "image: cimg/postgres:{{ db.postgres.version }}\nsteps:\n  - run: echo waiting for postgres {{ db.postgres.version }}\n",
+    )?;
+    fs::write(
+        jobs_dir.join("rspec.yml.j2"),
+        "image: cimg/ruby:3.3\nsteps:\n{% for suite in suites %}\n  - run: bundle exec rspec spec/{{ suite }} --tag {{ job_name }}\n{% endfor %}\n",
+    )?;
+
+    fs::write(dir.path().join("vars.yml"), "suites: [system, models]\n")?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["generate", "--var-file", "vars.yml"]);
+    cmd.assert().success();
+
+    let main = fs::read_to_string(dir.path().join(".circleci/main.yml"))?;
+    assert!(main.contains("image: cimg/postgres:15"), "{main}");
+    assert!(main.contains("waiting for postgres 15"), "{main}");
+    assert!(
+        main.contains("bundle exec rspec spec/system --tag rspec"),
+        "{main}"
+    );
+    assert!(
+        main.contains("bundle exec rspec spec/models --tag rspec"),
+        "{main}"
+    );
+    assert!(!main.contains("spec/requests"), "{main}");
+
+    fs::write(
+        jobs_dir.join("lint.yml"),
+        "image: cimg/ruby:3.3\nsteps:\n  - run: \"{% if true %}rubocop{% endif %}\"\n",
+    )?;
+    let mut plain = Command::cargo_bin("cigen")?;
+    plain
+        .current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .arg("generate");
+    let output = plain.assert().failure().get_output().stderr.clone();
+    let stderr = String::from_utf8(output)?;
+    assert!(
+        stderr.contains(
+            "loops and conditionals only work in `.j2` job files; rename it to lint.yml.j2"
+        ),
+        "{stderr}"
+    );
+    Ok(())
+}
+
+#[test]
+fn generate_keeps_include_raw_files_verbatim() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let cigen_dir = dir.path().join(".cigen");
+    fs::create_dir_all(cigen_dir.join("workflows/main/jobs"))?;
+    fs::create_dir_all(cigen_dir.join("templates"))?;
+    fs::write(cigen_dir.join("config.yml"), "provider: github\n")?;
+    fs::write(
+        cigen_dir.join("templates/report.sh"),
+        "set -e\necho \"${{ github.sha }} {{ not_a_var }}\"\n",
+    )?;
+    fs::write(
+        cigen_dir.join("workflows/main/jobs/report.yml.j2"),
+        "image: ubuntu-latest\nsteps:\n  - run: |\n      {% include_raw \"report.sh\" %}\n  - run: echo {{ job_name }}\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path()).arg("generate");
+    cmd.assert().success();
+
+    let main = fs::read_to_string(dir.path().join(".github/workflows/main.yml"))?;
+    assert!(
+        main.contains("set -e\n        echo \"${{ github.sha }} {{ not_a_var }}\"\n"),
+        "{main}"
+    );
+    assert!(main.contains("run: echo report"), "{main}");
+    Ok(())
+}
+
+#[test]
+fn image_versions_come_from_version_files() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(dir.path().join(".cigen/config.yml"), "provider: circleci\n")?;
+    fs::write(
+        jobs_dir.join("rspec.yml"),
+        "image: cimg/ruby:{{ ruby_version }}-node\nsteps:\n  - run: bundle exec rspec\n",
+    )?;
+    fs::write(
+        jobs_dir.join("jest.yml"),
+        "image: cimg/node:{{ node_version }}\nsteps:\n  - run: yarn jest\n",
+    )?;
+    fs::write(
+        dir.path().join("Gemfile"),
+        "source \"https://rubygems.org\"\nruby \"3.3.4\"\n",
+    )?;
+    fs::write(dir.path().join(".nvmrc"), "v20.11.1\n")?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["generate", "--stdout"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let stdout = String::from_utf8(output)?;
+    assert!(stdout.contains("image: cimg/ruby:3.3.4-node"), "{stdout}");
+    assert!(stdout.contains("image: cimg/node:20.11.1"), "{stdout}");
+
+    let mut inspect = Command::cargo_bin("cigen")?;
+    inspect
+        .current_dir(dir.path())
+        .args(["inspect", "versions", "--var", "python_version=3.12"]);
+    let output = inspect.assert().success().get_output().stdout.clone();
+    let stdout = String::from_utf8(output)?;
+    let lines: Vec<Vec<&str>> = stdout
+        .lines()
+        .map(|line| line.split_whitespace().take(3).collect())
+        .collect();
+    assert!(
+        lines.contains(&vec!["ruby_version", "3.3.4", "Gemfile"]),
+        "{stdout}"
+    );
+    assert!(
+        lines.contains(&vec!["node_version", "20.11.1", ".nvmrc"]),
+        "{stdout}"
+    );
+    assert!(
+        lines.contains(&vec!["python_version", "3.12", "vars"]),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("go_version  ") && stdout.contains("not found: .go-version (missing)"),
+        "{stdout}"
+    );
+
+    fs::remove_file(dir.path().join(".nvmrc"))?;
+    let mut missing = Command::cargo_bin("cigen")?;
+    missing
+        .current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["generate", "--stdout"]);
+    let output = missing.assert().failure().get_output().stderr.clone();
+    let stderr = String::from_utf8(output)?;
+    assert!(
+        stderr.contains(
+            "No node version for `node_version`: checked .nvmrc (missing), .node-version (missing), .tool-versions (missing)"
+        ),
+        "{stderr}"
+    );
+    Ok(())
+}
+
+#[test]
+fn generate_stdout_prints_files_without_writing_them() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(dir.path().join(".cigen/config.yml"), "provider: circleci\n")?;
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["generate", "--stdout"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let stdout = String::from_utf8(output)?;
+
+    assert!(
+        stdout.starts_with("--- # path: .circleci/config.yml\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("\n--- # path: .circleci/main.yml\n"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("make test"), "{stdout}");
+    assert!(!stdout.contains("Loading config"), "{stdout}");
+    assert!(!dir.path().join(".circleci").exists());
+
+    let mut failing = Command::cargo_bin("cigen")?;
+    failing
+        .current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["generate", "missing", "--stdout"]);
+    let output = failing.assert().failure().get_output().stdout.clone();
+    assert!(output.is_empty(), "{}", String::from_utf8_lossy(&output));
+    Ok(())
+}
+
+#[test]
+fn quiet_generate_prints_nothing_on_success() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(dir.path().join(".cigen/config.yml"), "provider: circleci\n")?;
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["--quiet", "generate"]);
+    let output = cmd.assert().success().get_output().clone();
+
+    assert!(
+        output.stdout.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    assert!(
+        output.stderr.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(dir.path().join(".circleci/main.yml").exists());
+    Ok(())
+}
+
+#[test]
+fn stats_reports_generated_config_as_json() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/test/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(dir.path().join(".cigen/config.yml"), "provider: circleci\n")?;
+    fs::write(
+        jobs_dir.join("rspec.yml"),
+        "image: cimg/ruby:3.3\nresource_class: large\nmatrix:\n  arch: [amd64, arm64]\nsteps:\n  - run: bundle exec rspec\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["stats", "--format", "json"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let stats: Value = serde_json::from_slice(&output)?;
+
+    assert_eq!(stats["workflows"], 1);
+    assert_eq!(stats["jobs"], 1);
+    assert_eq!(stats["expanded_jobs"], 2);
+    let circleci = &stats["providers"][0];
+    assert_eq!(circleci["provider"], "circleci");
+    let files: Vec<&str> = circleci["files"]
+        .as_array()
+        .expect("files")
+        .iter()
+        .map(|file| file["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(files, [".circleci/config.yml", ".circleci/main.yml"]);
+    assert_eq!(circleci["user_steps"], 2);
+    assert_eq!(circleci["largest_jobs"][0]["name"], "setup");
+    // Two large jobs and the medium setup job
+    assert_eq!(circleci["credits"]["weight"], 50);
+    assert!(!dir.path().join(".circleci").exists());
+    Ok(())
+}
+
+#[test]
+fn lint_reports_findings_and_denies_warnings() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/test/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(
+        dir.path().join(".cigen/config.yml"),
+        "provider: circleci\nservices:\n  redis:\n    image: redis:7\n",
+    )?;
+    fs::write(
+        jobs_dir.join("rspec.yml"),
+        "image: cimg/ruby:3.3\nsource_files: [app/**/*.rb]\nsteps:\n  - run: bundle exec rspec\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path()).arg("lint");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let stdout = String::from_utf8(output)?;
+    assert!(stdout.contains("CIGEN-W003"), "{stdout}");
+    assert!(
+        stdout.contains("Service 'redis' is defined, but no job uses it"),
+        "{stdout}"
+    );
+    assert!(stdout.contains(".cigen/config.yml:3:3"), "{stdout}");
+    assert!(stdout.ends_with("1 lint warning\n"), "{stdout}");
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .args(["lint", "--deny", "warnings"]);
+    let output = cmd.assert().failure().get_output().stderr.clone();
+    let stderr = String::from_utf8(output)?;
+    assert!(
+        stderr.contains("1 lint warning (denied by --deny warnings)"),
+        "{stderr}"
+    );
+
+    // Clean configs pass
+    fs::write(dir.path().join(".cigen/config.yml"), "provider: circleci\n")?;
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .args(["lint", "--deny", "warnings"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    assert_eq!(String::from_utf8(output)?, "No lint warnings\n");
+    Ok(())
+}
+
+#[test]
+fn schema_export_writes_cigen_and_provider_schemas() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path()).args([
+        "schema",
+        "export",
+        "--provider",
+        "circleci",
+        "--out",
+        "schemas/",
+    ]);
+    cmd.assert().success();
+
+    let mut files: Vec<String> = fs::read_dir(dir.path().join("schemas"))?
+        .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+        .collect::<Result<_, _>>()?;
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            "circleci-config.json",
+            "command-schema.json",
+            "config-base-schema.json",
+            "config-schema.json",
+            "definitions.json",
+            "job-schema.json",
+            "workflow-config-schema.json",
+        ]
+    );
+    let schema: Value = serde_json::from_str(&fs::read_to_string(
+        dir.path().join("schemas/config-schema.json"),
+    )?)?;
+    assert_eq!(
+        schema["$id"],
+        "https://cigen.dev/schemas/v1/config-schema.json"
+    );
+
+    let mut unknown = Command::cargo_bin("cigen")?;
+    unknown
+        .current_dir(dir.path())
+        .args(["schema", "export", "--provider", "jenkins"]);
+    let output = unknown.assert().failure().get_output().stderr.clone();
+    let stderr = String::from_utf8(output)?;
+    assert!(
+        stderr.contains("No bundled schema for provider 'jenkins' (available: circleci)"),
+        "{stderr}"
+    );
+    Ok(())
+}
+
+#[test]
+fn generated_circleci_files_start_with_schema_comment() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(dir.path().join(".cigen/config.yml"), "provider: circleci\n")?;
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .arg("generate");
+    cmd.assert().success();
+
+    for file in ["config.yml", "main.yml"] {
+        let contents = fs::read_to_string(dir.path().join(".circleci").join(file))?;
+        assert!(
+            contents.starts_with(
+                "# yaml-language-server: $schema=https://json.schemastore.org/circleciconfig.json\n"
+            ),
+            "{contents}"
+        );
+        let parsed: serde_yaml::Value = serde_yaml::from_str(&contents)?;
+        assert_eq!(parsed["version"].as_str(), Some("2.1"));
+    }
+    Ok(())
+}
+
+#[test]
+fn generated_files_have_a_header_that_verify_checks() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(dir.path().join(".cigen/config.yml"), "provider: circleci\n")?;
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+    )?;
+    let cigen = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("cigen").unwrap();
+        cmd.current_dir(dir.path())
+            .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+            .args(args);
+        cmd
+    };
+
+    // Nothing generated yet
+    let output = cigen(&["verify", "--hash-only"]).assert().failure();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains("No files generated by cigen found"),
+        "{stderr}"
+    );
+
+    cigen(&["generate"]).assert().success();
+    let main = fs::read_to_string(dir.path().join(".circleci/main.yml"))?;
+    let header: Vec<&str> = main.lines().skip(1).take(6).collect();
+    assert_eq!(header[0], "# DO NOT EDIT — generated by cigen from .cigen/");
+    assert_eq!(
+        header[1],
+        format!("# cigen version: {}", env!("CARGO_PKG_VERSION"))
+    );
+    let hash = header[2].strip_prefix("# config hash: ").unwrap();
+    assert_eq!(hash.len(), 64);
+    assert_eq!(header[3..], ["# workflows: main", "# jobs: 1", "#"]);
+    assert!(!main.contains("# generated at:"));
+    cigen(&["verify", "--hash-only"]).assert().success();
+
+    // Regenerating an unchanged config gives identical files
+    cigen(&["generate"]).assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join(".circleci/main.yml"))?,
+        main
+    );
+
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run: make spec\n",
+    )?;
+    let output = cigen(&["verify", "--hash-only"]).assert().failure().code(1);
+    let stdout = String::from_utf8_lossy(&output.get_output().stdout).to_string();
+    assert_eq!(stdout, ".circleci/config.yml\n.circleci/main.yml\n");
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains("2 of 2 generated file(s) are out of date"),
+        "{stderr}"
+    );
+
+    cigen(&["generate", "--timestamp"]).assert().success();
+    let main = fs::read_to_string(dir.path().join(".circleci/main.yml"))?;
+    assert!(main.contains("\n# generated at: 20"), "{main}");
+    cigen(&["verify", "--hash-only"]).assert().success();
+    Ok(())
+}
+
+#[test]
+fn verify_regenerates_and_compares_with_the_files_on_disk() -> Result<(), Box<dyn std::error::Error>>
+{
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(dir.path().join(".cigen/config.yml"), "provider: circleci\n")?;
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+    )?;
+    let cigen = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("cigen").unwrap();
+        cmd.current_dir(dir.path())
+            .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+            .args(args);
+        cmd
+    };
+
+    let output = cigen(&["verify"]).assert().failure().code(1);
+    let stdout = String::from_utf8_lossy(&output.get_output().stdout).to_string();
+    assert_eq!(
+        stdout,
+        ".circleci/config.yml: missing\n.circleci/main.yml: missing\n"
+    );
+
+    cigen(&["generate", "--timestamp"]).assert().success();
+    cigen(&["verify"]).assert().success();
+
+    // A hand edit is drift even though the config hasn't changed
+    let main_path = dir.path().join(".circleci/main.yml");
+    let generated = fs::read_to_string(&main_path)?;
+    fs::write(&main_path, generated.replace("make test", "make tests"))?;
+    let output = cigen(&["verify", "--provider", "circleci"])
+        .assert()
+        .failure()
+        .code(1);
+    let stdout = String::from_utf8_lossy(&output.get_output().stdout).to_string();
+    assert!(
+        stdout.starts_with(".circleci/main.yml: differs from line "),
+        "{stdout}"
+    );
+    assert!(stdout.ends_with("(+1 -1 lines)\n"), "{stdout}");
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains("1 of 2 generated file(s) differ from what the config generates"),
+        "{stderr}"
+    );
+
+    cigen(&["verify", "--fix"]).assert().success();
+    assert!(fs::read_to_string(&main_path)?.contains("make test\n"));
+    cigen(&["verify"]).assert().success();
+
+    let output = cigen(&["verify", "--provider", "github"])
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains("Provider 'github' isn't configured"),
+        "{stderr}"
+    );
+    Ok(())
+}
+
+#[test]
+fn profiles_are_listed_and_applied_by_generate() -> Result<(), Box<dyn std::error::Error>> {
+    let fixture =
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("integration_tests/profiles");
+
+    let mut list = Command::cargo_bin("cigen")?;
+    list.current_dir(&fixture)
+        .args(["list", "profiles", "--format", "json"]);
+    let output = list.assert().success().get_output().stdout.clone();
+    let profiles: Value = serde_json::from_slice(&output)?;
+    assert_eq!(profiles[0]["name"], "production");
+    assert_eq!(
+        profiles[0]["job_overlays"],
+        serde_json::json!(["deploy/deploy"])
+    );
+
+    let mut generate = Command::cargo_bin("cigen")?;
+    generate
+        .current_dir(&fixture)
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["generate", "--profile", "production", "--stdout"]);
+    let output = generate.assert().success().get_output().stdout.clone();
+    let stdout = String::from_utf8(output)?;
+    assert!(stdout.contains("./deploy.sh --confirm"), "{stdout}");
+    assert!(stdout.contains("DEPLOY_ENV: production"), "{stdout}");
+    Ok(())
+}
+
+#[test]
+fn generate_changed_since_leaves_out_unchanged_jobs() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let repo = dir.path();
+    let git = |args: &[&str]| -> Result<(), Box<dyn std::error::Error>> {
+        let status = Command::new("git")
+            .args(args)
+            .env("GIT_AUTHOR_NAME", "CI Agent")
+            .env("GIT_AUTHOR_EMAIL", "ci@example.com")
+            .env("GIT_COMMITTER_NAME", "CI Agent")
+            .env("GIT_COMMITTER_EMAIL", "ci@example.com")
+            .current_dir(repo)
+            .status()?;
+        assert!(status.success(), "git {:?} failed", args);
+        Ok(())
+    };
+
+    let jobs_dir = repo.join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::create_dir_all(repo.join("api"))?;
+    fs::create_dir_all(repo.join("web"))?;
+    fs::write(repo.join(".cigen/config.yml"), "provider: circleci\n")?;
+    fs::write(
+        jobs_dir.join("api.yml"),
+        "image: cimg/base:stable\nsource_files: [api/]\nsteps:\n  - run: make api\n",
+    )?;
+    fs::write(
+        jobs_dir.join("web.yml"),
+        "image: cimg/base:stable\nsource_files: [\"web/**/*.ts\"]\nsteps:\n  - run: make web\n",
+    )?;
+    fs::write(
+        jobs_dir.join("deploy.yml"),
+        "image: cimg/base:stable\nneeds: [api, web]\nsteps:\n  - run: make deploy\n",
+    )?;
+    fs::write(repo.join("api/main.go"), "package main\n")?;
+    fs::write(repo.join("web/app.ts"), "export {}\n")?;
+
+    git(&["init", "-b", "main"])?;
+    git(&["add", "."])?;
+    git(&["commit", "-m", "initial"])?;
+    git(&["checkout", "-b", "feature"])?;
+    fs::write(repo.join("web/app.ts"), "export const x = 1\n")?;
+    git(&["commit", "-am", "change web"])?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(repo)
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["generate", "--stdout", "--changed-since", "main"]);
+    let assert = cmd.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone())?;
+    let stderr = String::from_utf8(assert.get_output().stderr.clone())?;
+
+    let main = stdout
+        .split("--- # path: .circleci/main.yml\n")
+        .nth(1)
+        .expect("main.yml in output");
+    let main: serde_yaml::Value = serde_yaml::from_str(main)?;
+    let jobs = main["jobs"].as_mapping().unwrap();
+    assert!(jobs.contains_key("web"), "{stdout}");
+    assert!(jobs.contains_key("deploy"), "{stdout}");
+    assert!(!jobs.contains_key("api"), "{stdout}");
+    assert!(
+        stderr.contains("1 file(s) changed since main: including 2 job(s), excluding 1"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("  - api"), "{stderr}");
+    Ok(())
+}
+
+#[test]
+fn generate_only_projects_file_keeps_affected_project_jobs()
+-> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let repo = dir.path();
+    let jobs_dir = repo.join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(
+        repo.join(".cigen/config.yml"),
+        "provider: circleci\nprojects: [api, web]\nproject_detection:\n  tool: nx\n",
+    )?;
+    fs::write(
+        jobs_dir.join("api.yml"),
+        "image: cimg/base:stable\nproject: api\nsteps:\n  - run: make api\n",
+    )?;
+    fs::write(
+        jobs_dir.join("web.yml"),
+        "image: cimg/base:stable\nproject: web\nsteps:\n  - run: make web\n",
+    )?;
+    fs::write(
+        jobs_dir.join("deploy.yml"),
+        "image: cimg/base:stable\nneeds: [api, web]\nsteps:\n  - run: make deploy\n",
+    )?;
+    let projects_file = repo.join("affected_projects.txt");
+    fs::write(&projects_file, "web\n")?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(repo)
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .env("CIGEN_ONLY_PROJECTS_FILE", &projects_file)
+        .args(["generate", "--stdout"]);
+    let assert = cmd.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone())?;
+    let stderr = String::from_utf8(assert.get_output().stderr.clone())?;
+
+    let main = stdout
+        .split("--- # path: .circleci/main.yml\n")
+        .nth(1)
+        .expect("main.yml in output");
+    let main: serde_yaml::Value = serde_yaml::from_str(main)?;
+    let jobs = main["jobs"].as_mapping().unwrap();
+    assert!(jobs.contains_key("web"), "{stdout}");
+    assert!(jobs.contains_key("deploy"), "{stdout}");
+    assert!(!jobs.contains_key("api"), "{stdout}");
+    assert!(
+        stderr.contains("1 affected project(s): including 2 job(s), excluding 1"),
+        "{stderr}"
+    );
+
+    let setup = stdout
+        .split("--- # path: .circleci/config.yml\n")
+        .nth(1)
+        .and_then(|rest| rest.split("--- # path:").next())
+        .expect("config.yml in output");
+    assert!(
+        setup.contains(
+            r#"npx nx show projects --affected --base="$BASE" > /tmp/cigen/affected_projects.txt"#
+        ),
+        "{setup}"
+    );
+    assert!(
+        setup.contains(r#"export CIGEN_ONLY_PROJECTS_FILE="/tmp/cigen/affected_projects.txt""#),
+        "{setup}"
+    );
+    Ok(())
+}
+
+#[test]
+fn generate_skip_jobs_file_drops_jobs_and_rewires_requires()
+-> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let repo = dir.path();
+    let jobs_dir = repo.join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(repo.join(".cigen/config.yml"), "provider: circleci\n")?;
+    for (name, needs) in [
+        ("build", "[]"),
+        ("test", "[build]"),
+        ("lint", "[build]"),
+        ("deploy", "[test, lint]"),
+    ] {
+        fs::write(
+            jobs_dir.join(format!("{name}.yml")),
+            format!("image: cimg/base:stable\nneeds: {needs}\nsteps:\n  - run: make {name}\n"),
+        )?;
+    }
+    let skip_file = repo.join("skip.txt");
+    fs::write(&skip_file, "test\n\nretired\n")?;
+
+    let generate =
+        |extra: &[&str]| -> Result<(serde_yaml::Value, String), Box<dyn std::error::Error>> {
+            let mut cmd = Command::cargo_bin("cigen")?;
+            cmd.current_dir(repo)
+                .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+                .env("CIGEN_SKIP_JOBS_FILE", &skip_file)
+                .args(["generate", "--stdout"])
+                .args(extra);
+            let assert = cmd.assert().success();
+            let stdout = String::from_utf8(assert.get_output().stdout.clone())?;
+            let stderr = String::from_utf8(assert.get_output().stderr.clone())?;
+            let main = stdout
+                .split("--- # path: .circleci/main.yml\n")
+                .nth(1)
+                .expect("main.yml in output");
+            Ok((serde_yaml::from_str(main)?, stderr))
+        };
+    let requires = |main: &serde_yaml::Value, job: &str| -> Vec<String> {
+        main["workflows"]["main"]["jobs"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .find_map(|entry| entry.get(job))
+            .and_then(|entry| entry["requires"].as_sequence())
+            .map(|requires| {
+                requires
+                    .iter()
+                    .filter_map(|need| need.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    // The skipped job's dependents require what it required
+    let (main, stderr) = generate(&[])?;
+    let jobs = main["jobs"].as_mapping().unwrap();
+    assert!(!jobs.contains_key("test"));
+    assert_eq!(requires(&main, "deploy"), ["build", "lint"]);
+    assert!(stderr.contains("Skipping 1 job(s): test"), "{stderr}");
+    assert!(
+        stderr.contains("Not skipping 'retired': no job has that name"),
+        "{stderr}"
+    );
+
+    let (main, stderr) = generate(&["--skip-jobs", "build,lint"])?;
+    let jobs = main["jobs"].as_mapping().unwrap();
+    assert_eq!(jobs.len(), 1, "{jobs:?}");
+    assert!(requires(&main, "deploy").is_empty());
+    assert!(
+        stderr.contains("Skipping 3 job(s): build, lint, test"),
+        "{stderr}"
+    );
+    Ok(())
+}
+
+#[test]
+fn unknown_job_project_is_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(
+        dir.path().join(".cigen/config.yml"),
+        "provider: circleci\nprojects: [api, web]\n",
+    )?;
+    fs::write(
+        jobs_dir.join("web.yml"),
+        "image: cimg/base:stable\nproject: wbe\nsteps:\n  - run: make web\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path()).args(["generate", "--stdout"]);
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "Job 'web' belongs to unknown project 'wbe'. Did you mean 'web'?",
+    ));
+    Ok(())
+}
+
+#[test]
+fn generate_runs_hooks_from_the_project_root() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(
+        dir.path().join(".cigen/config.yml"),
+        r#"provider: circleci
+hooks:
+  pre_generate:
+    - echo {provider} > pre_marker
+  post_generate:
+    - test -f {output_dir}/.circleci/main.yml && echo written > post_marker
+"#,
+    )?;
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+    )?;
+
+    Command::cargo_bin("cigen")?
+        .current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["generate", "--dry-run"])
+        .assert()
+        .success();
+    assert!(!dir.path().join("pre_marker").exists());
+    assert!(!dir.path().join("post_marker").exists());
+    assert!(!dir.path().join(".circleci").exists());
+
+    // Run from elsewhere: hooks still start in the project root
+    let output = tempdir()?;
+    Command::cargo_bin("cigen")?
+        .current_dir(output.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args(["generate", "--config"])
+        .arg(dir.path().join(".cigen"))
+        .arg("--output")
+        .arg(output.path())
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("pre_marker"))?,
+        "circleci\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("post_marker"))?,
+        "written\n"
+    );
+
+    let inspect = Command::cargo_bin("cigen")?
+        .current_dir(dir.path())
+        .args(["inspect", "hooks", "--output", "/tmp/out"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(
+        String::from_utf8(inspect)?,
+        "pre_generate:\n- echo circleci > pre_marker\npost_generate:\n- test -f /tmp/out/.circleci/main.yml && echo written > post_marker\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn failing_hook_aborts_generate_with_its_stderr() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(
+        dir.path().join(".cigen/config.yml"),
+        "provider: circleci\nhooks:\n  pre_generate:\n    - echo 'lint failed' >&2; exit 1\n",
+    )?;
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+    )?;
+
+    let output = Command::cargo_bin("cigen")?
+        .current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .arg("generate")
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .clone();
+    let stderr = String::from_utf8(output)?;
+    assert!(stderr.contains("pre_generate hook"), "{stderr}");
+    assert!(stderr.contains("lint failed"), "{stderr}");
+    assert!(!dir.path().join(".circleci").exists());
+    Ok(())
+}
+
+#[test]
+fn generate_writes_a_json_report_and_json_logs() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(dir.path().join(".cigen/config.yml"), "provider: circleci\n")?;
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run: make test\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .args([
+            "--log-format",
+            "json",
+            "-v",
+            "generate",
+            "--report",
+            "report.json",
+        ]);
+    let output = cmd.assert().success().get_output().clone();
+
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(!stderr.trim().is_empty());
+    for line in stderr.lines() {
+        let event: Value =
+            serde_json::from_str(line).unwrap_or_else(|error| panic!("not JSON ({error}): {line}"));
+        assert!(event["level"].is_string(), "{line}");
+        assert!(event["fields"]["message"].is_string(), "{line}");
+    }
+
+    let report: Value = serde_json::from_str(&fs::read_to_string(dir.path().join("report.json"))?)?;
+    assert_eq!(report["schema_version"], 1);
+
+    let main = fs::read(dir.path().join(".circleci/main.yml"))?;
+    let files = report["files"].as_array().unwrap();
+    let file = files
+        .iter()
+        .find(|file| file["path"] == ".circleci/main.yml")
+        .unwrap();
+    assert_eq!(file["bytes"], main.len() as u64);
+    assert_eq!(file["sha256"], hex::encode(Sha256::digest(&main)));
+
+    assert_eq!(report["workflows"][0]["name"], "main");
+    assert_eq!(report["workflows"][0]["jobs"], serde_json::json!(["test"]));
+    assert_eq!(report["skipped_jobs"], serde_json::json!([]));
+    assert!(report["diagnostics"].is_array());
+    let phases: Vec<&str> = report["phases"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|phase| phase["phase"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        phases,
+        ["load", "validate", "generate", "provider-validate"]
+    );
+    Ok(())
+}
+
+#[test]
+fn generate_reports_features_the_provider_degrades() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir)?;
+    fs::write(dir.path().join(".cigen/config.yml"), "provider: github\n")?;
+    fs::write(
+        jobs_dir.join("build.yml"),
+        "image: ubuntu-latest\nremote_docker: {}\nsteps:\n  - run: make build\n  - persist_to_workspace:\n      root: .\n      paths: [dist]\n",
+    )?;
+    fs::write(
+        jobs_dir.join("rspec.yml"),
+        "image: ubuntu-latest\nparallelism: 2\ntest_splitting:\n  glob: \"spec/**/*_spec.rb\"\n  command_template: bundle exec rspec {files}\n",
+    )?;
+    fs::write(
+        jobs_dir.join("hold.yml"),
+        "type: approval\nneeds: [build]\n",
+    )?;
+    fs::write(
+        jobs_dir.join("lint.yml"),
+        "image: ubuntu-latest\nsteps:\n  - run: make lint\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .args(["generate", "--report", "report.json"]);
+    let output = cmd.assert().success().get_output().clone();
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains("Features github doesn't support natively:"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("build: workspaces (dropped), remote_docker (dropped)"),
+        "{stderr}"
+    );
+
+    let report: Value = serde_json::from_str(&fs::read_to_string(dir.path().join("report.json"))?)?;
+    let degradations: Vec<(&str, &str, &str)> = report["degradations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| {
+            (
+                entry["job"].as_str().unwrap(),
+                entry["feature"].as_str().unwrap(),
+                entry["support"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        degradations,
+        [
+            ("build", "workspaces", "dropped"),
+            ("build", "remote_docker", "dropped"),
+            ("hold", "approval", "emulated"),
+            ("rspec", "test_splitting", "emulated"),
+        ]
+    );
+
+    // Strict mode fails before anything is written
+    fs::remove_dir_all(dir.path().join(".github"))?;
+    let mut cmd = Command::cargo_bin("cigen")?;
+    cmd.current_dir(dir.path())
+        .args(["generate", "--strict-capabilities"]);
+    let output = cmd.assert().failure().get_output().clone();
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains("hold: approval (emulated)") && stderr.contains("--strict-capabilities"),
+        "{stderr}"
+    );
+    assert!(!dir.path().join(".github").exists());
+    Ok(())
+}
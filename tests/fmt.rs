@@ -0,0 +1,94 @@
+use assert_cmd::prelude::*;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::{TempDir, tempdir};
+
+/// Copy the messy fixture into a tempdir so formatting can rewrite it
+fn messy_project() -> TempDir {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("integration_tests/fmt_messy");
+    let dir = tempdir().expect("failed to create tempdir");
+    for entry in walkdir::WalkDir::new(&fixture) {
+        let entry = entry.unwrap();
+        let target = dir
+            .path()
+            .join(entry.path().strip_prefix(&fixture).unwrap());
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target).unwrap();
+        } else {
+            fs::copy(entry.path(), &target).unwrap();
+        }
+    }
+    dir
+}
+
+fn cigen_fmt(project: &Path, check: bool) -> Command {
+    let mut cmd = Command::cargo_bin("cigen").expect("cigen binary not found");
+    cmd.current_dir(project).arg("fmt");
+    if check {
+        cmd.arg("--check");
+    }
+    cmd
+}
+
+#[test]
+fn check_lists_unformatted_files_without_changing_them() {
+    let project = messy_project();
+    let job_path = project.path().join(".cigen/workflows/test/jobs/rspec.yml");
+    let before = fs::read_to_string(&job_path).unwrap();
+
+    let output = cigen_fmt(project.path(), true).assert().failure();
+    let stdout = String::from_utf8_lossy(&output.get_output().stdout).to_string();
+    assert_eq!(
+        stdout,
+        ".cigen/commands/bundle.yml\n.cigen/config.yml\n.cigen/workflows/test/jobs/rspec.yml\n"
+    );
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains("3 of 4 file(s) need formatting"),
+        "{stderr}"
+    );
+    assert_eq!(fs::read_to_string(&job_path).unwrap(), before);
+}
+
+#[test]
+fn fmt_rewrites_files_canonically_and_is_idempotent() {
+    let project = messy_project();
+    cigen_fmt(project.path(), false).assert().success();
+
+    let read = |path: &str| fs::read_to_string(project.path().join(".cigen").join(path)).unwrap();
+    assert_eq!(
+        read("workflows/test/jobs/rspec.yml"),
+        "\
+image: cimg/ruby:3.3
+requires:
+  - lint
+services: [postgres]
+steps:
+  # Run the suite
+  - run:
+      name: RSpec
+      command: |
+        bundle exec rspec
+"
+    );
+    assert_eq!(
+        read("config.yml"),
+        "\
+# Messy on purpose: used by tests/fmt.rs
+provider: \"circleci\"
+output: .circleci
+vars:
+  ruby_version: \"3.3\"
+"
+    );
+    assert_eq!(
+        read("commands/bundle.yml"),
+        "description: \"Install gems\"\nsteps:\n  - run: bundle install\n"
+    );
+
+    let formatted = read("workflows/test/jobs/rspec.yml");
+    cigen_fmt(project.path(), true).assert().success();
+    cigen_fmt(project.path(), false).assert().success();
+    assert_eq!(read("workflows/test/jobs/rspec.yml"), formatted);
+}
@@ -0,0 +1,757 @@
+use assert_cmd::prelude::*;
+use serde_yaml::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::tempdir;
+
+fn repo_root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn generate_command(config: &Path, output: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("cigen").expect("cigen binary not found");
+    cmd.arg("generate")
+        .arg("--config")
+        .arg(config)
+        .arg("--output")
+        .arg(output)
+        .current_dir(repo_root());
+    cmd
+}
+
+#[test]
+fn services_become_github_service_containers() {
+    let output = tempdir().unwrap();
+    let config = repo_root().join("integration_tests/github_services/.cigen");
+    generate_command(&config, output.path()).assert().success();
+
+    let yaml = fs::read_to_string(output.path().join(".github/workflows/ci.yml")).unwrap();
+    let workflow: Value = serde_yaml::from_str(&yaml).unwrap();
+    let services = &workflow["jobs"]["test"]["services"];
+
+    let postgres = &services["postgres"];
+    assert_eq!(postgres["image"].as_str(), Some("postgres:16"));
+    assert_eq!(postgres["env"]["POSTGRES_USER"].as_str(), Some("app"));
+    assert_eq!(postgres["ports"][0].as_str(), Some("5432:5432"));
+    assert_eq!(
+        postgres["options"].as_str(),
+        Some(
+            "--health-cmd \"pg_isready -U app\" --health-interval 10s --health-timeout 5s --health-retries 5"
+        )
+    );
+
+    let redis = &services["redis"];
+    assert_eq!(redis["image"].as_str(), Some("redis:7"));
+    assert_eq!(redis["ports"][0].as_str(), Some("6379:6379"));
+    assert_eq!(
+        redis["options"].as_str(),
+        Some("--health-cmd \"redis-cli ping\" --health-interval 10s")
+    );
+    assert_eq!(
+        services.as_mapping().map(|services| services.len()),
+        Some(2)
+    );
+}
+
+#[test]
+fn unknown_service_error_points_at_job_file() {
+    let project = tempdir().unwrap();
+    let jobs_dir = project.path().join(".cigen/workflows/ci/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: github\nservices:\n  postgres:\n    image: postgres:16\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: ubuntu-latest\nservices:\n  - postgrse\nsteps:\n  - run: bin/rails test\n",
+    )
+    .unwrap();
+
+    let output = generate_command(&project.path().join(".cigen"), &project.path().join("out"))
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains("Unknown GitHub Actions service 'postgrse' referenced by job 'test'"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("test.yml:3:5"), "{stderr}");
+    assert!(stderr.contains("- postgrse"), "{stderr}");
+}
+
+const CHECKOUT_SHA: &str = "11bd71901bbe5b1630ceea73d27597364c9af683";
+
+fn write_pinned_project(lockfile: Option<&str>) -> tempfile::TempDir {
+    let project = tempdir().unwrap();
+    let cigen_dir = project.path().join(".cigen");
+    let jobs_dir = cigen_dir.join("workflows/ci/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(
+        cigen_dir.join("config.yml"),
+        "provider: github\ngithub_actions:\n  pin_actions: true\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: ubuntu-latest\nsteps:\n  - uses: ruby/setup-ruby@v1\n  - run: bundle exec rspec\n",
+    )
+    .unwrap();
+    if let Some(lockfile) = lockfile {
+        fs::write(cigen_dir.join("actions.lock.yml"), lockfile).unwrap();
+    }
+    project
+}
+
+#[test]
+fn pinned_actions_use_locked_commits_offline() {
+    let project = write_pinned_project(Some(&format!(
+        "actions/checkout@v4: {CHECKOUT_SHA}\nruby/setup-ruby@v1: 2654679fe7f7c29875c669398a8ec0791b8a64a1\n"
+    )));
+    let output = tempdir().unwrap();
+    generate_command(&project.path().join(".cigen"), output.path())
+        .arg("--offline")
+        .assert()
+        .success();
+
+    let yaml = fs::read_to_string(output.path().join(".github/workflows/ci.yml")).unwrap();
+    assert!(
+        yaml.contains(&format!("uses: actions/checkout@{CHECKOUT_SHA} # v4\n")),
+        "{yaml}"
+    );
+    assert!(
+        yaml.contains("uses: ruby/setup-ruby@2654679fe7f7c29875c669398a8ec0791b8a64a1 # v1\n"),
+        "{yaml}"
+    );
+    assert!(!yaml.contains("@v4\n"), "{yaml}");
+}
+
+#[test]
+fn offline_generation_lists_unpinned_actions() {
+    let project = write_pinned_project(Some(&format!("actions/checkout@v4: {CHECKOUT_SHA}\n")));
+    let output = tempdir().unwrap();
+    let result = generate_command(&project.path().join(".cigen"), output.path())
+        .arg("--offline")
+        .output()
+        .unwrap();
+
+    assert!(!result.status.success());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("These actions aren't pinned in actions.lock.yml: ruby/setup-ruby@v1."),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn approval_jobs_become_environment_gated_jobs() {
+    let project = tempdir().unwrap();
+    let jobs_dir = project.path().join(".cigen/workflows/deploy/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: github\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("build.yml"),
+        "image: ubuntu-latest\nmatrix:\n  arch: [amd64, arm64]\nsteps:\n  - run: make\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("hold.yml"),
+        "type: approval\nneeds: [build]\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("release.yml"),
+        "image: ubuntu-latest\nneeds: [hold]\nsteps:\n  - run: ./release.sh\n",
+    )
+    .unwrap();
+
+    let output = tempdir().unwrap();
+    generate_command(&project.path().join(".cigen"), output.path())
+        .assert()
+        .success();
+
+    let yaml = fs::read_to_string(output.path().join(".github/workflows/deploy.yml")).unwrap();
+    assert!(
+        yaml.contains(
+            "  # Approval job: waits for a reviewer to approve the 'hold' environment.\n  # Add required reviewers to it under Settings → Environments.\n  hold:\n"
+        ),
+        "{yaml}"
+    );
+    let workflow: Value = serde_yaml::from_str(&yaml).unwrap();
+    let hold = &workflow["jobs"]["hold"];
+    assert_eq!(hold["environment"].as_str(), Some("hold"));
+    assert!(hold.get("type").is_none());
+    assert_eq!(
+        serde_yaml::to_string(&hold["needs"]).unwrap(),
+        "- build-amd64\n- build-arm64\n"
+    );
+    assert_eq!(hold["steps"].as_sequence().unwrap().len(), 1);
+    assert_eq!(
+        serde_yaml::to_string(&workflow["jobs"]["release"]["needs"]).unwrap(),
+        "- hold\n"
+    );
+}
+
+#[test]
+fn workflow_job_steps_wrap_the_github_job_steps() {
+    let project = tempdir().unwrap();
+    let workflow_dir = project.path().join(".cigen/workflows/ci");
+    fs::create_dir_all(workflow_dir.join("jobs")).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: github\n",
+    )
+    .unwrap();
+    fs::write(
+        workflow_dir.join("config.yml"),
+        "job_steps:\n  release:\n    pre_steps:\n      - run: echo starting release\n    post_steps:\n      - run:\n          name: Notify Slack\n          command: ./notify-slack.sh\n",
+    )
+    .unwrap();
+    fs::write(
+        workflow_dir.join("jobs/build.yml"),
+        "image: ubuntu-latest\nsteps:\n  - run: make\n",
+    )
+    .unwrap();
+    fs::write(
+        workflow_dir.join("jobs/release.yml"),
+        "image: ubuntu-latest\nneeds: [build]\nsteps:\n  - run: ./release.sh\n",
+    )
+    .unwrap();
+
+    let output = tempdir().unwrap();
+    generate_command(&project.path().join(".cigen"), output.path())
+        .assert()
+        .success();
+
+    let yaml = fs::read_to_string(output.path().join(".github/workflows/ci.yml")).unwrap();
+    let workflow: Value = serde_yaml::from_str(&yaml).unwrap();
+    let release = &workflow["jobs"]["release"];
+    assert_eq!(
+        serde_yaml::to_string(&release["needs"]).unwrap(),
+        "- build\n"
+    );
+    let steps = release["steps"].as_sequence().unwrap();
+    assert_eq!(steps[0]["run"].as_str(), Some("echo starting release"));
+    assert_eq!(steps[1]["uses"].as_str(), Some("actions/checkout@v4"));
+    let last = steps.last().unwrap();
+    assert_eq!(last["name"].as_str(), Some("Notify Slack"));
+    assert_eq!(last["run"].as_str(), Some("./notify-slack.sh"));
+    assert_eq!(steps[steps.len() - 2]["run"].as_str(), Some("./release.sh"));
+
+    let build_steps = workflow["jobs"]["build"]["steps"].as_sequence().unwrap();
+    assert_eq!(build_steps.len(), 2);
+    assert!(workflow.get("job_steps").is_none(), "{yaml}");
+}
+
+#[test]
+fn slack_notifications_add_a_final_webhook_job() {
+    let project = tempdir().unwrap();
+    let jobs_dir = project.path().join(".cigen/workflows/ci/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: github\nnotifications:\n  slack:\n    channel: \"#ci\"\n    webhook_secret: CI_SLACK_WEBHOOK\n",
+    )
+    .unwrap();
+    for job in ["lint", "test"] {
+        fs::write(
+            jobs_dir.join(format!("{job}.yml")),
+            format!("image: ubuntu-latest\nsteps:\n  - run: make {job}\n"),
+        )
+        .unwrap();
+    }
+
+    let output = tempdir().unwrap();
+    generate_command(&project.path().join(".cigen"), output.path())
+        .assert()
+        .success();
+
+    let yaml = fs::read_to_string(output.path().join(".github/workflows/ci.yml")).unwrap();
+    let workflow: Value = serde_yaml::from_str(&yaml).unwrap();
+    assert!(workflow.get("notifications").is_none());
+    let notify = &workflow["jobs"]["notify_slack"];
+    assert_eq!(notify["if"].as_str(), Some("${{ failure() }}"));
+    assert_eq!(
+        serde_yaml::to_string(&notify["needs"]).unwrap(),
+        "- lint\n- test\n"
+    );
+    let step = &notify["steps"][0];
+    assert_eq!(
+        step["env"]["SLACK_WEBHOOK_URL"].as_str(),
+        Some("${{ secrets.CI_SLACK_WEBHOOK }}")
+    );
+    assert_eq!(step["env"]["NEEDS"].as_str(), Some("${{ toJSON(needs) }}"));
+    let script = step["run"].as_str().unwrap();
+    assert!(script.contains("curl -fsS -X POST"), "{script}");
+    assert!(!script.contains("gh run list"), "{script}");
+    assert!(notify.get("permissions").is_none());
+}
+
+#[test]
+fn provider_overrides_merge_into_the_github_job() {
+    let project = tempdir().unwrap();
+    let jobs_dir = project.path().join(".cigen/workflows/ci/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: github\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: ubuntu-latest\nsteps:\n  - run: make test\nprovider_overrides:\n  github:\n    timeout-minutes: 30\n    runs-on: ubuntu-24.04-arm\n  circleci:\n    circleci_ip_ranges: true\n",
+    )
+    .unwrap();
+
+    let output = tempdir().unwrap();
+    let result = generate_command(&project.path().join(".cigen"), output.path())
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("provider_overrides.github.runs-on replaces the value cigen generated"),
+        "{stderr}"
+    );
+
+    let yaml = fs::read_to_string(output.path().join(".github/workflows/ci.yml")).unwrap();
+    let workflow: Value = serde_yaml::from_str(&yaml).unwrap();
+    let job = &workflow["jobs"]["test"];
+    assert_eq!(job["timeout-minutes"].as_u64(), Some(30));
+    assert_eq!(job["runs-on"].as_str(), Some("ubuntu-24.04-arm"));
+    assert!(job.get("circleci_ip_ranges").is_none());
+}
+
+#[test]
+fn output_filename_names_each_workflow_file() {
+    let project = tempdir().unwrap();
+    for workflow in ["ci", "deploy"] {
+        let jobs_dir = project
+            .path()
+            .join(format!(".cigen/workflows/{workflow}/jobs"));
+        fs::create_dir_all(&jobs_dir).unwrap();
+        fs::write(
+            jobs_dir.join(format!("{workflow}.yml")),
+            "image: ubuntu-latest\nsteps:\n  - run: make\n",
+        )
+        .unwrap();
+    }
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: github\noutput_filename: \"{workflow}_generated.yml\"\n",
+    )
+    .unwrap();
+
+    let output = tempdir().unwrap();
+    generate_command(&project.path().join(".cigen"), output.path())
+        .assert()
+        .success();
+    let workflows = output.path().join(".github/workflows");
+    assert!(workflows.join("ci_generated.yml").exists());
+    assert!(workflows.join("deploy_generated.yml").exists());
+    assert!(!workflows.join("ci.yml").exists());
+}
+
+#[test]
+fn parameter_conditions_become_dispatch_inputs_and_job_guards() {
+    let project = tempdir().unwrap();
+    let workflow_dir = project.path().join(".cigen/workflows/deploy");
+    fs::create_dir_all(workflow_dir.join("jobs")).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: github\nparameters:\n  deploy:\n    type: boolean\n    default: false\n    description: Deploy after building\n",
+    )
+    .unwrap();
+    fs::write(
+        workflow_dir.join("config.yml"),
+        "run_when:\n  - parameter: deploy\n",
+    )
+    .unwrap();
+    fs::write(
+        workflow_dir.join("jobs/build.yml"),
+        "image: ubuntu-latest\nsteps:\n  - run: make\n",
+    )
+    .unwrap();
+    fs::write(
+        workflow_dir.join("jobs/release.yml"),
+        "image: ubuntu-latest\nneeds: [build]\nsteps:\n  - run: ./release.sh\n",
+    )
+    .unwrap();
+
+    let output = tempdir().unwrap();
+    generate_command(&project.path().join(".cigen"), output.path())
+        .assert()
+        .success();
+
+    let yaml = fs::read_to_string(output.path().join(".github/workflows/deploy.yml")).unwrap();
+    let workflow: Value = serde_yaml::from_str(&yaml).unwrap();
+    assert_eq!(
+        serde_yaml::to_string(&workflow["on"]["workflow_dispatch"]).unwrap(),
+        "inputs:\n  deploy:\n    description: Deploy after building\n    type: boolean\n    default: false\n"
+    );
+    assert!(workflow["on"].get("push").is_some());
+    for job in ["build", "release"] {
+        assert_eq!(
+            workflow["jobs"][job]["if"].as_str(),
+            Some("${{ inputs.deploy == true }}"),
+            "{job}"
+        );
+    }
+    assert!(workflow.get("run_when").is_none());
+}
+
+#[test]
+fn run_unless_and_job_unless_negate_their_conditions() {
+    let project = tempdir().unwrap();
+    let workflow_dir = project.path().join(".cigen/workflows/deploy");
+    fs::create_dir_all(workflow_dir.join("jobs")).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: github\nparameters:\n  deploy:\n    type: boolean\n    default: false\n  dry_run:\n    type: boolean\n    default: false\n",
+    )
+    .unwrap();
+    fs::write(
+        workflow_dir.join("config.yml"),
+        "run_when:\n  - parameter: deploy\nrun_unless:\n  - parameter: dry_run\n",
+    )
+    .unwrap();
+    fs::write(
+        workflow_dir.join("jobs/build.yml"),
+        "image: ubuntu-latest\nsteps:\n  - run: make\n",
+    )
+    .unwrap();
+    fs::write(
+        workflow_dir.join("jobs/release.yml"),
+        "image: ubuntu-latest\nneeds: [build]\nunless: branch == \"staging\"\nsteps:\n  - run: ./release.sh\n",
+    )
+    .unwrap();
+
+    let output = tempdir().unwrap();
+    generate_command(&project.path().join(".cigen"), output.path())
+        .assert()
+        .success();
+
+    let yaml = fs::read_to_string(output.path().join(".github/workflows/deploy.yml")).unwrap();
+    let workflow: Value = serde_yaml::from_str(&yaml).unwrap();
+    let inputs = &workflow["on"]["workflow_dispatch"]["inputs"];
+    assert!(inputs.get("deploy").is_some());
+    assert!(inputs.get("dry_run").is_some());
+    assert_eq!(
+        workflow["jobs"]["build"]["if"].as_str(),
+        Some("${{ (inputs.deploy == true) && !(inputs.dry_run == true) }}")
+    );
+    assert_eq!(
+        workflow["jobs"]["release"]["if"].as_str(),
+        Some(
+            "${{ (!(github.ref_name == 'staging')) && ((inputs.deploy == true) && !(inputs.dry_run == true)) }}"
+        )
+    );
+    assert!(workflow.get("run_unless").is_none());
+}
+
+#[test]
+fn global_steps_run_after_the_skip_check_and_caches() {
+    let project = tempdir().unwrap();
+    let workflow_dir = project.path().join(".cigen/workflows/ci");
+    fs::create_dir_all(workflow_dir.join("jobs")).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: github\nglobal_steps:\n  before:\n    - run:\n        name: Scan for secrets\n        command: gitleaks detect\n  after:\n    - run:\n        name: Attest\n        command: ./attest.sh\n",
+    )
+    .unwrap();
+    // The workflow opts out, and the test job opts back in
+    fs::write(
+        workflow_dir.join("config.yml"),
+        "inherit_global_steps: false\n",
+    )
+    .unwrap();
+    fs::write(
+        workflow_dir.join("jobs/test.yml"),
+        "image: ubuntu-latest\npackages: [node]\nsource_files: [app/**]\ninherit_global_steps: true\nsteps:\n  - run:\n      name: Test\n      command: npm test\n",
+    )
+    .unwrap();
+    fs::write(
+        workflow_dir.join("jobs/lint.yml"),
+        "image: ubuntu-latest\nsteps:\n  - run:\n      name: Lint\n      command: npm run lint\n",
+    )
+    .unwrap();
+
+    let output = tempdir().unwrap();
+    generate_command(&project.path().join(".cigen"), output.path())
+        .assert()
+        .success();
+
+    let yaml = fs::read_to_string(output.path().join(".github/workflows/ci.yml")).unwrap();
+    let workflow: Value = serde_yaml::from_str(&yaml).unwrap();
+    let names = |job: &str| -> Vec<String> {
+        workflow["jobs"][job]["steps"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|step| step["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(
+        names("test"),
+        [
+            "Checkout repository",
+            "Compute source hash",
+            "Restore skip cache",
+            "Skip job (cached)",
+            "Prepare Node runtime for actions",
+            "Restore pnpm cache",
+            "Scan for secrets",
+            "Test",
+            "Attest",
+            "Record job completion",
+            "Save skip cache",
+        ]
+    );
+    assert_eq!(names("lint"), ["Checkout repository", "Lint"]);
+    assert!(workflow.get("inherit_global_steps").is_none());
+}
+
+#[test]
+fn step_timing_wraps_run_steps_and_uploads_the_log() {
+    let project = tempdir().unwrap();
+    let jobs_dir = project.path().join(".cigen/workflows/ci/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: github\ninstrumentation:\n  step_timing: true\ncaches:\n  gems:\n    paths: [vendor/bundle]\n    checksum_sources: [Gemfile.lock]\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: ubuntu-latest\ncache: gems\nsource_files: [app/**/*.rb]\nsteps:\n  - run:\n      name: Bob's specs\n      command: bundle exec rspec\n",
+    )
+    .unwrap();
+
+    let output = tempdir().unwrap();
+    generate_command(&project.path().join(".cigen"), output.path())
+        .assert()
+        .success();
+    let yaml = fs::read_to_string(output.path().join(".github/workflows/ci.yml")).unwrap();
+    let workflow: Value = serde_yaml::from_str(&yaml).unwrap();
+    let steps = workflow["jobs"]["test"]["steps"].as_sequence().unwrap();
+    let step = |name: &str| {
+        steps
+            .iter()
+            .find(|step| step["name"].as_str() == Some(name))
+            .unwrap_or_else(|| panic!("missing step {name} in {yaml}"))
+    };
+
+    assert!(
+        step("Bob's specs")["run"]
+            .as_str()
+            .unwrap()
+            .starts_with("cigen_timing_step='Bob'\\''s specs'\n")
+    );
+    // Injected run steps are timed; checkout and cache steps are left alone
+    let timed = steps
+        .iter()
+        .filter(|step| {
+            step["run"]
+                .as_str()
+                .is_some_and(|run| run.starts_with("cigen_timing_step="))
+        })
+        .count();
+    assert!(timed > 1, "{yaml}");
+    assert_eq!(steps[0]["uses"].as_str(), Some("actions/checkout@v4"));
+    assert!(steps.iter().any(|step| {
+        step["uses"]
+            .as_str()
+            .is_some_and(|uses| uses.starts_with("actions/cache"))
+    }));
+
+    let upload = steps.last().unwrap();
+    assert_eq!(upload["uses"].as_str(), Some("actions/upload-artifact@v4"));
+    assert_eq!(upload["if"].as_str(), Some("always()"));
+    assert_eq!(
+        upload["with"]["path"].as_str(),
+        Some("/tmp/cigen/timings.log")
+    );
+}
+
+#[test]
+fn step_timing_leaves_steps_of_other_shells_alone() {
+    let project = tempdir().unwrap();
+    let jobs_dir = project.path().join(".cigen/workflows/ci/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: github\ninstrumentation:\n  step_timing: true\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("windows.yml"),
+        "image: windows-latest\nsteps:\n  - run: Write-Host hi\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("python.yml"),
+        "image: ubuntu-latest\nsteps:\n  - run: print('hi')\nprovider_overrides:\n  github:\n    defaults:\n      run:\n        shell: python\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("sh.yml"),
+        "image: ubuntu-latest\nsteps:\n  - run: echo hi\nprovider_overrides:\n  github:\n    defaults:\n      run:\n        shell: sh -e {0}\n",
+    )
+    .unwrap();
+
+    let output = tempdir().unwrap();
+    generate_command(&project.path().join(".cigen"), output.path())
+        .assert()
+        .success();
+    let yaml = fs::read_to_string(output.path().join(".github/workflows/ci.yml")).unwrap();
+    let workflow: Value = serde_yaml::from_str(&yaml).unwrap();
+    let run = |job: &str| {
+        workflow["jobs"][job]["steps"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .find_map(|step| step["run"].as_str())
+            .unwrap_or_else(|| panic!("no run step in {job}: {yaml}"))
+            .to_string()
+    };
+
+    assert_eq!(run("windows"), "Write-Host hi");
+    assert_eq!(run("python"), "print('hi')");
+    assert!(run("sh").starts_with("cigen_timing_step="), "{yaml}");
+}
+
+#[test]
+fn cloud_auth_requests_an_oidc_token_for_the_job() {
+    let project = tempdir().unwrap();
+    let jobs_dir = project.path().join(".cigen/workflows/ci/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: github\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("deploy.yml"),
+        "image: ubuntu-latest\ncloud_auth:\n  aws:\n    role_arn: arn:aws:iam::123456789012:role/deploy\n    region: us-east-1\nsteps:\n  - run: aws s3 sync public s3://assets\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: ubuntu-latest\nsteps:\n  - run: make test\n",
+    )
+    .unwrap();
+
+    let output = tempdir().unwrap();
+    generate_command(&project.path().join(".cigen"), output.path())
+        .assert()
+        .success();
+    let yaml = fs::read_to_string(output.path().join(".github/workflows/ci.yml")).unwrap();
+    let workflow: Value = serde_yaml::from_str(&yaml).unwrap();
+
+    let deploy = &workflow["jobs"]["deploy"];
+    assert_eq!(deploy["permissions"]["id-token"].as_str(), Some("write"));
+    assert_eq!(deploy["permissions"]["contents"].as_str(), Some("read"));
+    let steps = deploy["steps"].as_sequence().unwrap();
+    let credentials = steps
+        .iter()
+        .position(|step| step["uses"].as_str() == Some("aws-actions/configure-aws-credentials@v4"))
+        .unwrap_or_else(|| panic!("{yaml}"));
+    assert_eq!(
+        steps[credentials]["with"]["role-to-assume"].as_str(),
+        Some("arn:aws:iam::123456789012:role/deploy")
+    );
+    assert_eq!(
+        steps[credentials]["with"]["aws-region"].as_str(),
+        Some("us-east-1")
+    );
+    let user_step = steps
+        .iter()
+        .position(|step| step["run"].as_str() == Some("aws s3 sync public s3://assets"))
+        .unwrap();
+    assert!(credentials < user_step);
+
+    // Jobs without cloud_auth keep the default token permissions
+    let test = &workflow["jobs"]["test"];
+    assert!(test.get("permissions").is_none(), "{yaml}");
+    assert!(
+        !test["steps"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .any(|step| step.get("uses").is_some_and(|uses| uses
+                .as_str()
+                .is_some_and(|uses| uses.starts_with("aws-actions/"))))
+    );
+}
+
+#[test]
+fn branches_become_a_job_condition() {
+    let project = tempdir().unwrap();
+    let jobs_dir = project.path().join(".cigen/workflows/ci/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: github\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("deploy.yml"),
+        "image: ubuntu-latest\nbranches: [main, production]\nsteps:\n  - run: ./deploy.sh\n",
+    )
+    .unwrap();
+
+    let output = tempdir().unwrap();
+    generate_command(&project.path().join(".cigen"), output.path())
+        .assert()
+        .success();
+    let yaml = fs::read_to_string(output.path().join(".github/workflows/ci.yml")).unwrap();
+    let workflow: Value = serde_yaml::from_str(&yaml).unwrap();
+    assert_eq!(
+        workflow["jobs"]["deploy"]["if"].as_str(),
+        Some("${{ github.ref_name == 'main' || github.ref_name == 'production' }}")
+    );
+
+    fs::write(
+        jobs_dir.join("deploy.yml"),
+        "image: ubuntu-latest\nbranches: /release-.*/\nsteps:\n  - run: ./deploy.sh\n",
+    )
+    .unwrap();
+    let failure = generate_command(&project.path().join(".cigen"), output.path())
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&failure.get_output().stderr).to_string();
+    assert!(stderr.contains("can't match regexes"), "{stderr}");
+}
+
+#[test]
+fn cache_warmup_is_skipped_with_a_warning() {
+    let project = tempdir().unwrap();
+    let jobs_dir = project.path().join(".cigen/workflows/ci/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: github\ncache_warmup:\n  schedule: \"0 4 * * *\"\n  caches: [node_modules]\ncaches:\n  node_modules:\n    paths: [node_modules]\n    checksum_sources: [package-lock.json]\n    warmup_command: npm ci\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: ubuntu-latest\ncache:\n  node_modules: null\nsteps:\n  - run: npm test\n",
+    )
+    .unwrap();
+    fs::write(project.path().join("package-lock.json"), "{}").unwrap();
+
+    let output = project.path().join("out");
+    generate_command(&project.path().join(".cigen"), &output)
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("GITHUB_CACHE_WARMUP_SKIPPED"));
+    let workflows = output.join(".github/workflows");
+    assert!(workflows.join("ci.yml").exists());
+    assert!(!workflows.join("cache_warmup.yml").exists());
+}
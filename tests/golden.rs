@@ -0,0 +1,136 @@
+//! Golden-file tests for full config generation.
+//!
+//! Each `tests/fixtures/<name>/.cigen` tree is loaded and generated in memory,
+//! and every generated file is compared against the copy committed under
+//! `tests/fixtures/<name>/expected/`. After an intended output change, rerun
+//! with `UPDATE_GOLDEN=1` to rewrite the expected files, then review the diff.
+use cigen::loader::load_split_config;
+use cigen::orchestrator::WorkflowOrchestrator;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const EXPECTED_DIR: &str = "expected";
+
+fn fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Provider binaries are built next to the test binary's `deps/` directory
+fn plugin_dir() -> PathBuf {
+    let exe = std::env::current_exe().expect("test binary path");
+    exe.parent()
+        .and_then(Path::parent)
+        .expect("target directory")
+        .to_path_buf()
+}
+
+async fn generate(fixture: &Path) -> BTreeMap<String, String> {
+    let config = load_split_config(&fixture.join(".cigen"))
+        .unwrap_or_else(|error| panic!("failed to load {}: {error:#}", fixture.display()));
+    let result = WorkflowOrchestrator::new(plugin_dir())
+        .execute(config)
+        .await
+        .unwrap_or_else(|error| panic!("failed to generate {}: {error:#}", fixture.display()));
+    result.files.into_iter().collect()
+}
+
+/// Expected files, keyed by their path relative to `expected/`
+fn read_expected(dir: &Path) -> BTreeMap<String, String> {
+    let mut files = BTreeMap::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let relative = path.strip_prefix(dir).unwrap();
+            files.insert(
+                relative.to_string_lossy().replace('\\', "/"),
+                fs::read_to_string(&path).unwrap(),
+            );
+        }
+    }
+    files
+}
+
+fn write_expected(dir: &Path, files: &BTreeMap<String, String>) {
+    let _ = fs::remove_dir_all(dir);
+    for (path, content) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+}
+
+/// The first line where `actual` differs from `expected`
+fn first_difference(expected: &str, actual: &str) -> String {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => break,
+            (expected, actual) if expected != actual => {
+                return format!(
+                    "line {line}:\n  expected: {}\n  actual:   {}",
+                    expected.unwrap_or("<end of file>"),
+                    actual.unwrap_or("<end of file>")
+                );
+            }
+            _ => {}
+        }
+    }
+    "trailing newline differs".to_string()
+}
+
+#[tokio::test]
+async fn generated_configs_match_golden_files() {
+    let update = std::env::var("UPDATE_GOLDEN").is_ok_and(|value| value == "1");
+    let mut fixtures: Vec<PathBuf> = fs::read_dir(fixtures_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.join(".cigen").is_dir())
+        .collect();
+    fixtures.sort();
+    assert!(
+        fixtures.len() >= 5,
+        "expected at least five golden fixtures"
+    );
+
+    let mut failures = Vec::new();
+    for fixture in &fixtures {
+        let name = fixture.file_name().unwrap().to_string_lossy();
+        let expected_dir = fixture.join(EXPECTED_DIR);
+        let actual = generate(fixture).await;
+        if update {
+            write_expected(&expected_dir, &actual);
+            continue;
+        }
+
+        let expected = read_expected(&expected_dir);
+        for (path, content) in &actual {
+            match expected.get(path) {
+                None => failures.push(format!("{name}: unexpected file {path}")),
+                Some(golden) if golden != content => failures.push(format!(
+                    "{name}: {path} differs at {}",
+                    first_difference(golden, content)
+                )),
+                Some(_) => {}
+            }
+        }
+        for path in expected.keys().filter(|path| !actual.contains_key(*path)) {
+            failures.push(format!("{name}: missing file {path}"));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "generated configs differ from the golden files (rerun with UPDATE_GOLDEN=1 to accept):\n{}",
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,522 @@
+use cigen::loader::{discover_profiles, load_split_config, load_split_config_with_profile};
+use cigen::plugin::diagnostics::error_location;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+fn write(root: &Path, relative: &str, content: &str) {
+    let path = root.join(relative);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+#[test]
+fn job_files_expand_yaml_merge_keys() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, "config.yml", "provider: circleci\n");
+    write(
+        root,
+        "workflows/main/jobs/rspec.yml",
+        r#"
+x-defaults: &defaults
+  image: cimg/ruby:3.3
+  resource_class: medium
+  environment:
+    RAILS_ENV: test
+
+x-large: &large
+  resource_class: large
+
+<<: [*large, *defaults]
+environment:
+  <<: { RAILS_ENV: test, LOG_LEVEL: debug }
+  LOG_LEVEL: info
+steps:
+  - run: bundle exec rspec
+"#,
+    );
+
+    let config = load_split_config(root).unwrap();
+    let job = config.jobs.get("rspec").unwrap();
+
+    assert_eq!(job.image, "cimg/ruby:3.3");
+    assert_eq!(
+        job.extra.get("resource_class").and_then(|v| v.as_str()),
+        Some("large")
+    );
+    assert_eq!(job.environment.get("LOG_LEVEL").unwrap(), "info");
+    assert_eq!(job.environment.get("RAILS_ENV").unwrap(), "test");
+    assert!(!job.extra.contains_key("x-defaults"));
+    assert!(!job.extra.contains_key("<<"));
+}
+
+#[test]
+fn job_parse_errors_keep_line_numbers() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, "config.yml", "provider: circleci\n");
+    write(
+        root,
+        "workflows/main/jobs/broken.yml",
+        "image: cimg/base:current\nsteps: not-a-list\n",
+    );
+
+    let error = format!("{:#}", load_split_config(root).unwrap_err());
+    assert!(error.contains("line 2"), "{error}");
+}
+
+#[test]
+fn template_job_files_inline_includes() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(
+        root,
+        "config.yml",
+        "provider: circleci\nvars:\n  ruby: '3.3'\n",
+    );
+    write(root, "templates/ruby.yml", "image: cimg/ruby:{{ ruby }}\n");
+    write(
+        root,
+        "workflows/main/jobs/scripts/seed.sh",
+        "#!/bin/bash\nbin/rails db:seed\n",
+    );
+    write(
+        root,
+        "workflows/main/jobs/seed.yml.j2",
+        "{% include \"ruby.yml\" %}\nsteps:\n  - run: |\n      {% include_raw \"scripts/seed.sh\" %}\n",
+    );
+
+    let config = load_split_config(root).unwrap();
+    let job = config.jobs.get("seed").unwrap();
+    assert_eq!(job.image, "cimg/ruby:3.3");
+    assert_eq!(
+        serde_yaml::to_string(&job.steps).unwrap(),
+        "- run: |\n    #!/bin/bash\n    bin/rails db:seed\n"
+    );
+}
+
+fn profiles_fixture() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("integration_tests/profiles/.cigen")
+}
+
+#[test]
+fn base_config_ignores_profile_overlays() {
+    let config = load_split_config(&profiles_fixture()).unwrap();
+
+    assert_eq!(config.env["DEPLOY_ENV"], "staging");
+    let mut job_ids: Vec<&String> = config.jobs.keys().collect();
+    job_ids.sort();
+    assert_eq!(job_ids, ["deploy", "smoke_test"]);
+    assert_eq!(
+        config.jobs["deploy"].extra["resource_class"].as_str(),
+        Some("medium")
+    );
+}
+
+#[test]
+fn profile_overlays_merge_over_config_and_jobs() {
+    let config = load_split_config_with_profile(&profiles_fixture(), Some("production")).unwrap();
+
+    // Scalars are overridden and maps merged
+    assert_eq!(config.env["DEPLOY_ENV"], "production");
+    assert_eq!(config.env["LOG_LEVEL"], "info");
+
+    let deploy = &config.jobs["deploy"];
+    assert_eq!(deploy.extra["resource_class"].as_str(), Some("large"));
+    assert_eq!(deploy.environment["REPLICAS"], "3");
+    assert_eq!(deploy.environment["REGION"], "us-east-1");
+    // Lists are replaced
+    assert_eq!(deploy.steps.len(), 1);
+    let steps = serde_yaml::to_string(&deploy.steps).unwrap();
+    assert!(steps.contains("./deploy.sh --confirm"), "{steps}");
+    assert!(
+        deploy
+            .source_file
+            .as_ref()
+            .is_some_and(|path| path.ends_with("deploy.yml"))
+    );
+
+    // Jobs without an overlay load unchanged
+    assert_eq!(config.jobs["smoke_test"].needs, ["deploy"]);
+}
+
+#[test]
+fn profiles_are_discovered_and_validated() {
+    let profiles = discover_profiles(&profiles_fixture()).unwrap();
+    assert_eq!(profiles.keys().collect::<Vec<_>>(), ["production"]);
+    let production = &profiles["production"];
+    assert!(
+        production
+            .config_overlay
+            .as_ref()
+            .is_some_and(|path| path.ends_with("overlays/production.yml"))
+    );
+    assert_eq!(production.job_overlays, ["deploy/deploy"]);
+
+    let error = load_split_config_with_profile(&profiles_fixture(), Some("prodution")).unwrap_err();
+    let error = format!("{error:#}");
+    assert!(error.contains("Unknown profile 'prodution'"), "{error}");
+    assert!(error.contains("Did you mean 'production'?"), "{error}");
+}
+
+#[test]
+fn dotted_job_files_are_overlays_only_for_known_profiles() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, "config.yml", "provider: circleci\n");
+    write(
+        root,
+        "workflows/ci/jobs/build.yml",
+        "steps:\n  - run: make\n",
+    );
+    write(
+        root,
+        "workflows/ci/jobs/build.arm.yml",
+        "resource_class: arm.medium\nsteps:\n  - run: make arm\n",
+    );
+
+    // No `arm` profile, so `build.arm.yml` is a job of its own
+    let config = load_split_config(root).unwrap();
+    let mut job_ids: Vec<&String> = config.jobs.keys().collect();
+    job_ids.sort();
+    assert_eq!(job_ids, ["build", "build.arm"]);
+    assert!(discover_profiles(root).unwrap().is_empty());
+
+    // Selecting the profile makes it an overlay
+    let config = load_split_config_with_profile(root, Some("arm")).unwrap();
+    assert_eq!(config.jobs.keys().collect::<Vec<_>>(), ["build"]);
+    assert_eq!(
+        config.jobs["build"].extra["resource_class"].as_str(),
+        Some("arm.medium")
+    );
+
+    // So does declaring it
+    write(root, "overlays/arm.yml", "{}\n");
+    let config = load_split_config(root).unwrap();
+    assert_eq!(config.jobs.keys().collect::<Vec<_>>(), ["build"]);
+    assert_eq!(
+        discover_profiles(root).unwrap()["arm"].job_overlays,
+        ["ci/build"]
+    );
+}
+
+#[test]
+fn job_names_are_unique_across_workflows() {
+    // Providers put every job in one `jobs:` map (CircleCI's continued
+    // config), so a second `test` would silently replace the first
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, "config.yml", "provider: circleci\n");
+    write(
+        root,
+        "workflows/ci/jobs/test.yml",
+        "image: cimg/ruby:3.3\nsteps:\n  - run: make test\n",
+    );
+    write(
+        root,
+        "workflows/nightly/jobs/test.yml",
+        "image: cimg/node:20.0\nsteps:\n  - run: make slow-test\n",
+    );
+
+    let error = format!("{:#}", load_split_config(root).unwrap_err());
+    assert!(
+        error.contains("Job 'test' is defined in both workflow '"),
+        "{error}"
+    );
+    assert!(
+        error.contains("'ci'") && error.contains("'nightly'"),
+        "{error}"
+    );
+}
+
+#[test]
+fn config_errors_point_at_the_fragment_that_set_the_value() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(
+        root,
+        "config.yml",
+        "provider: circleci\ncaches:\n  gems:\n    paths: [vendor/bundle]\n",
+    );
+    write(root, "config/env.yml", "env:\n  CI: 'true'\n");
+    write(
+        root,
+        "config/caches.yml",
+        "caches:\n  node:\n    paths: node_modules\n    restore_key_levels: lots\n",
+    );
+
+    let error = load_split_config(root).unwrap_err();
+    let message = format!("{error:#}");
+    assert!(
+        message.contains("Invalid definition for cache 'node'"),
+        "{message}"
+    );
+    let location = error_location(&error).expect("error should carry a location");
+    assert!(location.file.ends_with("config/caches.yml"), "{location:?}");
+    assert_eq!((location.line, location.column), (2, 3));
+
+    // A section that doesn't deserialize is located the same way
+    write(root, "config/caches.yml", "cache_version: latest\n");
+    let error = load_split_config(root).unwrap_err();
+    let location = error_location(&error).expect("error should carry a location");
+    assert!(location.file.ends_with("config/caches.yml"), "{location:?}");
+    assert!(
+        format!("{error:#}").contains("`cache_version`"),
+        "{error:#}"
+    );
+
+    // Values set in config.yml itself still point at config.yml
+    fs::remove_file(root.join("config/caches.yml")).unwrap();
+    write(
+        root,
+        "config.yml",
+        "provider: circleci\ncaches:\n  gems:\n    paths: {}\n",
+    );
+    let error = load_split_config(root).unwrap_err();
+    let location = error_location(&error).expect("error should carry a location");
+    assert!(location.file.ends_with("config.yml"), "{location:?}");
+    assert!(!location.file.contains("config/"), "{location:?}");
+    assert_eq!(location.line, 3);
+}
+
+#[test]
+fn jobs_yml_defines_every_job_of_a_workflow() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, "config.yml", "provider: circleci\n");
+    write(
+        root,
+        "workflows/ci/jobs.yml",
+        r#"
+x-ruby: &ruby
+  image: cimg/ruby:3.3
+
+lint:
+  <<: *ruby
+  steps:
+    - run: bundle exec rubocop
+
+tests/rspec:
+  <<: *ruby
+  needs: [lint]
+  steps:
+    - run: bundle exec rspec
+"#,
+    );
+
+    let config = load_split_config(root).unwrap();
+    let mut job_ids: Vec<&str> = config.jobs.keys().map(String::as_str).collect();
+    job_ids.sort();
+    assert_eq!(job_ids, ["lint", "tests/rspec"]);
+
+    let rspec = &config.jobs["tests/rspec"];
+    assert_eq!(rspec.image, "cimg/ruby:3.3");
+    assert_eq!(rspec.workflow.as_deref(), Some("ci"));
+    assert_eq!(rspec.stage.as_deref(), Some("tests"));
+    assert_eq!(rspec.needs, ["lint"]);
+    assert!(rspec.source_file.as_ref().unwrap().ends_with("jobs.yml"));
+    assert_eq!(rspec.source_section.as_deref(), Some("tests/rspec"));
+    assert_eq!(config.jobs["lint"].stage.as_deref(), Some("default"));
+
+    // Errors point into the job's own entry, not the first match in the file
+    write(
+        root,
+        "workflows/ci/jobs.yml",
+        "lint:\n  steps:\n    - run: make lint\nhold:\n  type: approval\n  steps:\n    - run: make deploy\n",
+    );
+    let error = load_split_config(root).unwrap_err();
+    assert!(
+        format!("{error:#}").contains("Approval job 'hold' can't declare `steps`"),
+        "{error:#}"
+    );
+    let location = error_location(&error).expect("error should carry a location");
+    assert!(location.file.ends_with("jobs.yml"), "{location:?}");
+    assert_eq!((location.line, location.column), (6, 3));
+}
+
+#[test]
+fn errors_in_merged_jobs_point_at_the_offending_key() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, "config.yml", "provider: circleci\n");
+    write(
+        root,
+        "workflows/ci/jobs/build.yml",
+        "x-defaults: &defaults\n  image: cimg/base:current\n<<: *defaults\nimage: [cimg/base]\nsteps:\n  - run: make\n",
+    );
+
+    let error = load_split_config(root).unwrap_err();
+    assert!(
+        format!("{error:#}").contains("image: invalid type: sequence, expected a string"),
+        "{error:#}"
+    );
+    let location = error_location(&error).expect("error should carry a location");
+    assert!(location.file.ends_with("build.yml"), "{location:?}");
+    assert_eq!((location.line, location.column), (4, 1));
+
+    // A value the job only gets through the merge points into the anchor
+    write(
+        root,
+        "workflows/ci/jobs/build.yml",
+        "x-defaults: &defaults\n  image: [cimg/base]\n<<: *defaults\nsteps:\n  - run: make\n",
+    );
+    let error = load_split_config(root).unwrap_err();
+    let location = error_location(&error).expect("error should carry a location");
+    assert_eq!((location.line, location.column), (2, 3));
+}
+
+#[test]
+fn workflows_define_jobs_in_jobs_yml_or_a_jobs_directory() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, "config.yml", "provider: circleci\n");
+    write(
+        root,
+        "workflows/ci/jobs.yml",
+        "lint:\n  steps:\n    - run: make lint\n",
+    );
+    write(
+        root,
+        "workflows/ci/jobs/test.yml",
+        "steps:\n  - run: make test\n",
+    );
+
+    let error = format!("{:#}", load_split_config(root).unwrap_err());
+    assert!(
+        error.contains("Workflow 'ci' has both") && error.contains("and a jobs/ directory"),
+        "{error}"
+    );
+}
+
+#[test]
+fn resource_classes_are_checked_against_circleci_classes() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(
+        root,
+        "config.yml",
+        "provider: circleci\nresource_classes:\n  build:\n    amd64: xlarge\n    arm64: arm.xlarge\n",
+    );
+    write(
+        root,
+        "workflows/ci/jobs.yml",
+        "lint:\n  resource_class: small\n  steps:\n    - run: make lint\n\
+         build:\n  resource_class: build\n  steps:\n    - run: make\n\
+         deploy:\n  resource_class: acme/deploy-runner\n  steps:\n    - run: make deploy\n",
+    );
+    load_split_config(root).unwrap();
+
+    write(
+        root,
+        "workflows/ci/jobs/test.yml",
+        "steps:\n  - run: make test\nresource_class: meduim\n",
+    );
+    fs::remove_file(root.join("workflows/ci/jobs.yml")).unwrap();
+    let error = load_split_config(root).unwrap_err();
+    assert!(
+        format!("{error:#}")
+            .contains("Job 'test': Unknown resource_class 'meduim'. Did you mean 'medium'?"),
+        "{error:#}"
+    );
+    let location = error_location(&error).expect("error should carry a location");
+    assert!(location.file.ends_with("test.yml"), "{location:?}");
+    assert_eq!((location.line, location.column), (3, 17));
+
+    write(
+        root,
+        "config.yml",
+        "provider: circleci\nallow_custom_resource_classes: true\n",
+    );
+    load_split_config(root).unwrap();
+}
+
+#[test]
+fn malformed_steps_are_rejected_at_the_key() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, "config.yml", "provider: circleci\n");
+    write(
+        root,
+        "workflows/ci/jobs.yml",
+        "lint:\n  steps:\n    - run: make lint\n\
+         test:\n  steps:\n    - run:\n        name: Test\n        comand: make test\n",
+    );
+    let error = load_split_config(root).unwrap_err();
+    assert!(
+        format!("{error:#}").contains("Unknown key `comand` in run step. Did you mean `command`?"),
+        "{error:#}"
+    );
+    let location = error_location(&error).expect("error should carry a location");
+    assert!(location.file.ends_with("jobs.yml"), "{location:?}");
+    assert_eq!((location.line, location.column), (8, 9));
+
+    write(
+        root,
+        "workflows/ci/jobs.yml",
+        "test:\n  steps:\n    - save_cache:\n        key: gems-v1\n",
+    );
+    let error = format!("{:#}", load_split_config(root).unwrap_err());
+    assert!(error.contains("save_cache step needs `paths`"), "{error}");
+
+    // The key also appears in an earlier step: point at the step that's wrong
+    write(
+        root,
+        "workflows/ci/jobs.yml",
+        "test:\n  steps:\n    - name: Lint\n      run: make lint\n    - run:\n        name: Test\n",
+    );
+    let error = load_split_config(root).unwrap_err();
+    assert!(
+        format!("{error:#}").contains("run step needs a `command`"),
+        "{error:#}"
+    );
+    let location = error_location(&error).expect("error should carry a location");
+    assert_eq!((location.line, location.column), (5, 7));
+
+    // Keys providers ignore are only warnings
+    write(
+        root,
+        "workflows/ci/jobs.yml",
+        "test:\n  steps:\n    - run:\n        command: make test\n        nmae: Test\n",
+    );
+    load_split_config(root).unwrap();
+}
+
+#[test]
+fn the_repos_own_config_loads() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join(".cigen");
+    let config = load_split_config(&root).unwrap();
+    assert!(config.jobs.keys().any(|id| id.ends_with("release_create")));
+}
+
+#[test]
+fn command_invocations_pass_declared_parameters() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, "config.yml", "provider: circleci\n");
+    write(
+        root,
+        "commands/set_env.yml",
+        "parameters:\n  env:\n    type: string\nsteps:\n  - run: echo << parameters.env >>\n",
+    );
+    write(
+        root,
+        "workflows/ci/jobs/test.yml",
+        "steps:\n  - set_env:\n      env: test\n  - run: make test\n",
+    );
+    load_split_config(root).unwrap();
+
+    write(
+        root,
+        "workflows/ci/jobs/test.yml",
+        "steps:\n  - set_env:\n      enviroment: test\n",
+    );
+    let error = load_split_config(root).unwrap_err();
+    assert!(
+        format!("{error:#}")
+            .contains("Job 'test': Command 'set_env' has no parameter 'enviroment'"),
+        "{error:#}"
+    );
+    let location = error_location(&error).expect("error should carry a location");
+    assert_eq!(location.line, 2);
+}
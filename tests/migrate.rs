@@ -0,0 +1,214 @@
+use assert_cmd::prelude::*;
+use serde_yaml::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+/// Job names run by each workflow, with migrate's `<workflow>_` prefix removed
+fn workflow_jobs(config: &Value) -> BTreeMap<String, BTreeSet<String>> {
+    let mut result = BTreeMap::new();
+    for (workflow, definition) in config["workflows"].as_mapping().unwrap() {
+        let workflow = workflow.as_str().unwrap();
+        let names = definition["jobs"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|entry| match entry {
+                Value::String(job) => job.clone(),
+                Value::Mapping(map) => {
+                    let (job, invocation) = map.iter().next().unwrap();
+                    invocation["name"]
+                        .as_str()
+                        .unwrap_or_else(|| job.as_str().unwrap())
+                        .to_string()
+                }
+                other => panic!("unexpected workflow entry {other:?}"),
+            })
+            .map(|name| {
+                name.strip_prefix(&format!("{workflow}_"))
+                    .unwrap_or(&name)
+                    .to_string()
+            })
+            .collect();
+        result.insert(workflow.to_string(), names);
+    }
+    result
+}
+
+#[test]
+fn migrated_circleci_config_generates_the_same_jobs() {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("integration_tests/migrate_circleci/.circleci/config.yml");
+    let project = tempdir().unwrap();
+    let original_source = fs::read_to_string(&fixture).unwrap();
+    fs::create_dir_all(project.path().join(".circleci")).unwrap();
+    fs::write(
+        project.path().join(".circleci/config.yml"),
+        &original_source,
+    )
+    .unwrap();
+
+    let output = Command::cargo_bin("cigen")
+        .unwrap()
+        .current_dir(project.path())
+        .args(["migrate", "circleci", "--input", ".circleci/config.yml"])
+        .assert()
+        .success();
+    let summary = String::from_utf8_lossy(&output.get_output().stdout).to_string();
+    assert!(summary.contains("Translated (10):"), "{summary}");
+    assert!(
+        summary.contains("workflows/test/jobs/rspec.yml: `store_test_results` step"),
+        "{summary}"
+    );
+    assert!(
+        summary.contains("workflows/release/jobs/deploy.yml: `context` (under x-circleci)"),
+        "{summary}"
+    );
+
+    let rspec =
+        fs::read_to_string(project.path().join(".cigen/workflows/test/jobs/rspec.yml")).unwrap();
+    assert!(
+        rspec
+            .contains("# TODO(cigen-migrate): `store_test_results` passed through as raw CircleCI"),
+        "{rspec}"
+    );
+
+    // Migrating again without --force must not clobber the new tree
+    Command::cargo_bin("cigen")
+        .unwrap()
+        .current_dir(project.path())
+        .args(["migrate", "circleci"])
+        .assert()
+        .failure();
+
+    Command::cargo_bin("cigen")
+        .unwrap()
+        .current_dir(project.path())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1")
+        .arg("generate")
+        .assert()
+        .success();
+
+    let original: Value = serde_yaml::from_str(&original_source).unwrap();
+    let generated: Value = serde_yaml::from_str(
+        &fs::read_to_string(project.path().join(".circleci/main.yml")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(workflow_jobs(&generated), workflow_jobs(&original));
+
+    let generated_jobs: BTreeSet<&str> = generated["jobs"]
+        .as_mapping()
+        .unwrap()
+        .keys()
+        .filter_map(Value::as_str)
+        .collect();
+    assert_eq!(
+        generated_jobs,
+        BTreeSet::from([
+            "deploy",
+            "release_lint",
+            "rspec",
+            "rspec_system",
+            "test_lint"
+        ])
+    );
+    let rspec_system = &generated["jobs"]["rspec_system"];
+    assert_eq!(rspec_system["parallelism"], 2);
+    assert_eq!(
+        rspec_system["steps"][2]["run"]["command"],
+        "bundle exec rspec spec/system"
+    );
+    let deploy = generated["workflows"]["release"]["jobs"]
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .find_map(|entry| entry.get("deploy"))
+        .expect("release workflow runs deploy");
+    assert_eq!(deploy["requires"][0], "hold");
+}
+
+/// Job ids per workflow file, with migrate's `<workflow>_` prefix removed
+fn github_jobs(dir: &Path) -> BTreeMap<String, BTreeSet<String>> {
+    let mut result = BTreeMap::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let workflow = path.file_stem().unwrap().to_str().unwrap().to_string();
+        let parsed: Value = serde_yaml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let jobs = parsed["jobs"]
+            .as_mapping()
+            .unwrap()
+            .keys()
+            .map(|job| {
+                let job = job.as_str().unwrap();
+                job.strip_prefix(&format!("{workflow}_"))
+                    .unwrap_or(job)
+                    .to_string()
+            })
+            .collect();
+        result.insert(workflow, jobs);
+    }
+    result
+}
+
+#[test]
+fn migrated_github_workflows_generate_the_same_jobs() {
+    let fixture =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("integration_tests/migrate_github/.github");
+    let project = tempdir().unwrap();
+    let workflows = project.path().join(".github/workflows");
+    fs::create_dir_all(&workflows).unwrap();
+    for entry in fs::read_dir(fixture.join("workflows")).unwrap() {
+        let entry = entry.unwrap();
+        fs::copy(entry.path(), workflows.join(entry.file_name())).unwrap();
+    }
+    let original = github_jobs(&workflows);
+
+    let output = Command::cargo_bin("cigen")
+        .unwrap()
+        .current_dir(project.path())
+        .args(["migrate", "github"])
+        .assert()
+        .success();
+    let summary = String::from_utf8_lossy(&output.get_output().stdout).to_string();
+    assert!(
+        summary.contains("workflows/ci/jobs/test.yml: `actions/cache` step -> packages: [node]"),
+        "{summary}"
+    );
+    assert!(
+        summary.contains("workflows/release/jobs/publish.yml: `environment` (under x-github)"),
+        "{summary}"
+    );
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains(".github/workflows/ci.yml:49: job 'test' uses the local action `./.github/actions/report`"),
+        "{stderr}"
+    );
+
+    let test =
+        fs::read_to_string(project.path().join(".cigen/workflows/ci/jobs/test.yml")).unwrap();
+    assert!(
+        test.contains("# TODO(cigen-migrate): the generated step won't have `working-directory`"),
+        "{test}"
+    );
+
+    // Generating overwrites the original workflow files in place
+    Command::cargo_bin("cigen")
+        .unwrap()
+        .current_dir(project.path())
+        .arg("generate")
+        .assert()
+        .success();
+    assert_eq!(github_jobs(&workflows), original);
+
+    let ci: Value =
+        serde_yaml::from_str(&fs::read_to_string(workflows.join("ci.yml")).unwrap()).unwrap();
+    assert_eq!(ci["on"]["push"]["branches"][0], "main");
+    assert_eq!(ci["jobs"]["test"]["container"]["image"], "node:20");
+    assert_eq!(ci["jobs"]["test"]["timeout-minutes"], 20);
+    assert_eq!(
+        ci["jobs"]["test"]["services"]["postgres"]["image"],
+        "postgres:16"
+    );
+}
@@ -0,0 +1,76 @@
+use assert_cmd::prelude::*;
+use serde_yaml::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::tempdir;
+
+fn repo_root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn generate_command(config: &Path, output: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("cigen").expect("cigen binary not found");
+    cmd.arg("generate")
+        .arg("--config")
+        .arg(config)
+        .arg("--output")
+        .arg(output)
+        .current_dir(repo_root())
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1");
+    cmd
+}
+
+fn read_yaml(path: &Path) -> Value {
+    let yaml = fs::read_to_string(path).unwrap_or_else(|_| panic!("missing {}", path.display()));
+    serde_yaml::from_str(&yaml).unwrap()
+}
+
+#[test]
+fn one_run_generates_every_provider_with_its_own_settings() {
+    let output = tempdir().unwrap();
+    let config = repo_root().join("integration_tests/multi_provider/.cigen");
+    generate_command(&config, output.path()).assert().success();
+
+    let circleci = read_yaml(&output.path().join(".circleci/main.yml"));
+    let environment = &circleci["jobs"]["test"]["environment"];
+    assert_eq!(environment["RAILS_ENV"].as_str(), Some("test"));
+    assert_eq!(environment["TZ"].as_str(), Some("America/New_York"));
+    let setup = fs::read_to_string(output.path().join(".circleci/config.yml")).unwrap();
+    assert!(setup.contains("v2-linux-"), "{setup}");
+
+    let github = read_yaml(&output.path().join(".github/workflows/ci.yml"));
+    let env = &github["env"];
+    assert_eq!(env["RAILS_ENV"].as_str(), Some("test"));
+    assert_eq!(env["TZ"].as_str(), Some("Europe/London"));
+    let github_yaml = serde_yaml::to_string(&github).unwrap();
+    assert!(!github_yaml.contains("v2-"), "{github_yaml}");
+}
+
+#[test]
+fn every_failing_provider_is_reported() {
+    let project = tempdir().unwrap();
+    let jobs_dir = project.path().join(".cigen/workflows/ci/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "providers: [circleci, github-actions]\n",
+    )
+    .unwrap();
+    // CircleCI rejects the macOS resource class, GitHub Actions the regex branch
+    fs::write(
+        jobs_dir.join("deploy.yml"),
+        "image: cimg/base:stable\nbranches: /release-.*/\nresource_class: macos.m1.medium.gen1\nsteps:\n  - run: ./deploy.sh\n",
+    )
+    .unwrap();
+
+    let output = tempdir().unwrap();
+    let assert = generate_command(&project.path().join(".cigen"), output.path())
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).to_string();
+    assert!(stderr.contains("2 providers failed"), "{stderr}");
+    assert!(stderr.contains("requires `executor: { macos"), "{stderr}");
+    assert!(stderr.contains("can't match regexes"), "{stderr}");
+    assert!(!output.path().join(".circleci").exists());
+}
@@ -0,0 +1,170 @@
+use assert_cmd::prelude::*;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::Command;
+use tempfile::{TempDir, tempdir};
+
+fn write_project(root_config: &str) -> TempDir {
+    let dir = tempdir().expect("failed to create tempdir");
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(dir.path().join(".cigen/config.yml"), root_config).unwrap();
+    fs::write(
+        jobs_dir.join("notify.yml"),
+        "image: cimg/base:stable\nsteps:\n  - slack/notify\n",
+    )
+    .unwrap();
+    dir
+}
+
+fn cigen(project: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("cigen").expect("cigen binary not found");
+    cmd.current_dir(project).env("CIGEN_SKIP_CIRCLECI_CLI", "1");
+    cmd
+}
+
+/// Serve canned GraphQL responses for `requests` registry queries; returns the URL
+fn mock_registry(requests: usize) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/graphql-unstable", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming().take(requests) {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':')
+                    && name.eq_ignore_ascii_case("content-length")
+                {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let body = String::from_utf8(body).unwrap();
+
+            let versions = if body.contains("circleci/slack") {
+                r#"[{"version":"4.12.5"},{"version":"4.13.0"},{"version":"5.0.0"}]"#
+            } else {
+                r#"[{"version":"1.0.0"},{"version":"1.1.0"}]"#
+            };
+            let response = format!(r#"{{"data":{{"orb":{{"versions":{versions}}}}}}}"#);
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                response.len()
+            )
+            .unwrap();
+        }
+    });
+    url
+}
+
+#[test]
+fn lock_resolves_orbs_through_the_registry() {
+    let project = write_project("provider: circleci\norbs:\n  slack: circleci/slack@4\n");
+    cigen(project.path())
+        .args(["orbs", "lock"])
+        .env("CIGEN_ORB_REGISTRY_URL", mock_registry(2))
+        .assert()
+        .success();
+
+    let lockfile = fs::read_to_string(project.path().join(".cigen/orbs.lock.yml")).unwrap();
+    assert!(
+        lockfile.starts_with("# Generated by `cigen orbs lock`"),
+        "{lockfile}"
+    );
+    let locked: serde_yaml::Value = serde_yaml::from_str(&lockfile).unwrap();
+    assert_eq!(locked["slack"].as_str(), Some("circleci/slack@4.13.0"));
+    assert_eq!(
+        locked["continuation"].as_str(),
+        Some("circleci/continuation@1.0.0")
+    );
+}
+
+#[test]
+fn offline_lock_requires_exact_pins() {
+    let project = write_project("provider: circleci\norbs:\n  slack: circleci/slack@4.12.5\n");
+    cigen(project.path())
+        .args(["orbs", "lock", "--offline"])
+        .assert()
+        .success();
+    let locked: serde_yaml::Value = serde_yaml::from_str(
+        &fs::read_to_string(project.path().join(".cigen/orbs.lock.yml")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(locked["slack"].as_str(), Some("circleci/slack@4.12.5"));
+
+    let floating = write_project("provider: circleci\norbs:\n  slack: circleci/slack@4\n");
+    let output = cigen(floating.path())
+        .args(["orbs", "lock", "--offline"])
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(stderr.contains("--offline needs X.Y.Z pins"), "{stderr}");
+}
+
+#[test]
+fn outdated_lists_newer_versions_of_locked_orbs() {
+    let project = write_project("provider: circleci\norbs:\n  slack: circleci/slack@4\n");
+    fs::write(
+        project.path().join(".cigen/orbs.lock.yml"),
+        "continuation: circleci/continuation@1.1.0\nslack: circleci/slack@4.12.5\n",
+    )
+    .unwrap();
+
+    let output = cigen(project.path())
+        .args(["orbs", "outdated"])
+        .env("CIGEN_ORB_REGISTRY_URL", mock_registry(2))
+        .assert()
+        .success();
+    let stdout = String::from_utf8_lossy(&output.get_output().stdout).to_string();
+    assert_eq!(
+        stdout,
+        "slack: circleci/slack@4.12.5 -> circleci/slack@5.0.0 (2 newer)\n"
+    );
+}
+
+#[test]
+fn generation_prefers_locked_versions_and_warns_on_mismatch() {
+    let project = write_project(
+        "provider: circleci\norbs:\n  slack: circleci/slack@4\n  node: circleci/node@5.0.0\n",
+    );
+    fs::write(
+        project.path().join(".cigen/orbs.lock.yml"),
+        "continuation: circleci/continuation@1.1.0\nnode: circleci/node@5.2.0\nslack: circleci/slack@4.12.5\n",
+    )
+    .unwrap();
+
+    let output = cigen(project.path()).arg("generate").assert().success();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(
+        stderr.contains(
+            "Orb 'node' is circleci/node@5.0.0 in the config but circleci/node@5.2.0 in orbs.lock.yml"
+        ),
+        "{stderr}"
+    );
+
+    let read = |name: &str| -> serde_yaml::Value {
+        let path = project.path().join(".circleci").join(name);
+        serde_yaml::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    };
+    let main = read("main.yml");
+    assert_eq!(
+        main["orbs"]["slack"].as_str(),
+        Some("circleci/slack@4.12.5")
+    );
+    assert_eq!(main["orbs"]["node"].as_str(), Some("circleci/node@5.0.0"));
+    let setup = read("config.yml");
+    assert_eq!(
+        setup["orbs"]["continuation"].as_str(),
+        Some("circleci/continuation@1.1.0")
+    );
+}
@@ -0,0 +1,140 @@
+/// Plugin crash handling: crash reports, restart-and-replay, and `--no-plugin-retry`
+///
+/// Uses the `crashing_plugin` example (tests/support/crashing_plugin.rs), which
+/// reads its behaviour from a `crash-mode` file next to the binary.
+use anyhow::Result;
+use assert_cmd::prelude::*;
+use cigen::plugin::PluginManager;
+use cigen::plugin::protocol::{GenerateRequest, PlanRequest};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+
+fn crashing_plugin_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/debug/examples/crashing_plugin")
+}
+
+/// Copy the crashing plugin into a fresh plugin directory with the given mode
+fn plugin_dir(mode: &str) -> Option<TempDir> {
+    let source = crashing_plugin_path();
+    if !source.exists() {
+        eprintln!(
+            "Skipping test: crashing plugin not found at {}",
+            source.display()
+        );
+        return None;
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::copy(&source, dir.path().join("cigen-provider-circleci")).unwrap();
+    fs::write(dir.path().join("crash-mode"), mode).unwrap();
+    Some(dir)
+}
+
+fn plugin_binary(dir: &Path) -> PathBuf {
+    dir.join("cigen-provider-circleci")
+}
+
+#[tokio::test]
+async fn crash_reports_exit_status_and_stderr_tail() -> Result<()> {
+    let Some(dir) = plugin_dir("always") else {
+        return Ok(());
+    };
+
+    let mut manager = PluginManager::new();
+    manager.set_retry_crashed(false);
+    let plugin_id = manager.spawn(plugin_binary(dir.path())).await?;
+
+    let error = manager
+        .send_plan(&plugin_id, PlanRequest::default())
+        .await
+        .unwrap_err();
+    let message = format!("{error:#}");
+    assert!(
+        message.contains("Plugin 'provider/circleci' exited (exit status: 3) during plan request"),
+        "{message}"
+    );
+    assert!(
+        message.contains("crashing_plugin: simulated crash"),
+        "{message}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn crashed_plugin_is_restarted_and_request_replayed() -> Result<()> {
+    let Some(dir) = plugin_dir("once") else {
+        return Ok(());
+    };
+
+    let mut manager = PluginManager::new();
+    let plugin_id = manager.spawn(plugin_binary(dir.path())).await?;
+
+    manager
+        .send_plan(&plugin_id, PlanRequest::default())
+        .await?;
+    manager
+        .send_generate(&plugin_id, GenerateRequest::default())
+        .await?;
+    assert!(dir.path().join("crashed").exists());
+
+    manager.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn restart_gives_up_when_plugin_crashes_again() -> Result<()> {
+    let Some(dir) = plugin_dir("always") else {
+        return Ok(());
+    };
+
+    let mut manager = PluginManager::new();
+    let plugin_id = manager.spawn(plugin_binary(dir.path())).await?;
+
+    let error = manager
+        .send_plan(&plugin_id, PlanRequest::default())
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{error:#}").contains("crashed again after restarting"),
+        "{error:#}"
+    );
+    Ok(())
+}
+
+#[test]
+fn no_plugin_retry_fails_on_first_crash() {
+    let Some(dir) = plugin_dir("always") else {
+        return;
+    };
+    let project = tempfile::tempdir().unwrap();
+    let jobs_dir = project.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(
+        project.path().join(".cigen/config.yml"),
+        "provider: circleci\n",
+    )
+    .unwrap();
+    fs::write(
+        jobs_dir.join("build.yml"),
+        "image: cimg/base:current\nsteps:\n  - run: make\n",
+    )
+    .unwrap();
+
+    let output = Command::cargo_bin("cigen")
+        .unwrap()
+        .arg("generate")
+        .arg("--no-plugin-retry")
+        .arg("--config")
+        .arg(project.path().join(".cigen"))
+        .arg("--output")
+        .arg(project.path().join("out"))
+        .env("CIGEN_PLUGIN_DIR", dir.path())
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(stderr.contains("exited (exit status: 3)"), "{stderr}");
+    assert!(!stderr.contains("Restarting plugin"), "{stderr}");
+    assert_eq!(stderr.matches("simulated crash").count(), 2, "{stderr}");
+}
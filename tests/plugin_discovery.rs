@@ -0,0 +1,201 @@
+/// Plugin discovery: `plugins:` in config, `$CIGEN_PLUGIN_PATH`, compatibility
+/// checks, and `cigen list plugins`
+///
+/// Uses the `fixture_plugin` example (tests/support/fixture_plugin.rs), which
+/// reads its handshake from a `<binary name>.json` file next to the binary.
+use assert_cmd::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+
+fn repo_root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn fixture_plugin_path() -> PathBuf {
+    repo_root().join("target/debug/examples/fixture_plugin")
+}
+
+/// Install the fixture plugin as `dir/<file_name>` with the given handshake
+fn install_plugin(dir: &Path, file_name: &str, info: &str) -> bool {
+    let source = fixture_plugin_path();
+    if !source.exists() {
+        eprintln!(
+            "Skipping test: fixture plugin not found at {}",
+            source.display()
+        );
+        return false;
+    }
+    fs::create_dir_all(dir).unwrap();
+    fs::copy(&source, dir.join(file_name)).unwrap();
+    fs::write(dir.join(format!("{file_name}.json")), info).unwrap();
+    true
+}
+
+/// Split `.cigen` config with a single CircleCI-style job
+fn write_project(root_config: &str) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let jobs_dir = dir.path().join(".cigen/workflows/main/jobs");
+    fs::create_dir_all(&jobs_dir).unwrap();
+    fs::write(dir.path().join(".cigen/config.yml"), root_config).unwrap();
+    fs::write(
+        jobs_dir.join("test.yml"),
+        "image: cimg/base:stable\nsteps:\n  - run: echo test\n",
+    )
+    .unwrap();
+    dir
+}
+
+fn cigen(project: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("cigen").expect("cigen binary not found");
+    cmd.current_dir(project)
+        .env("HOME", project)
+        .env("CIGEN_PLUGIN_PATH", project.join("plugins"))
+        .env("CIGEN_PLUGIN_DIR", repo_root().join("target/debug"))
+        .env("CIGEN_SKIP_CIRCLECI_CLI", "1");
+    cmd
+}
+
+fn generate(project: &Path) -> Command {
+    let mut cmd = cigen(project);
+    cmd.arg("generate")
+        .arg("--config")
+        .arg(project.join(".cigen"))
+        .arg("--output")
+        .arg(project.join("out"));
+    cmd
+}
+
+#[test]
+fn configured_plugin_by_name_replaces_bundled_provider() {
+    let project = write_project("provider: acme\nplugins:\n  - cigen-provider-acme\n");
+    if !install_plugin(
+        &project.path().join("plugins"),
+        "cigen-provider-acme",
+        r#"{"name": "provider/acme", "capabilities": ["provider:acme"]}"#,
+    ) {
+        return;
+    }
+
+    generate(project.path()).assert().success();
+
+    let output = fs::read_to_string(project.path().join("out/acme.txt")).unwrap();
+    assert_eq!(output, "generated by provider/acme\n");
+}
+
+#[test]
+fn configured_plugin_by_path_resolves_against_project() {
+    let project = write_project("provider: acme\nplugins:\n  - ./bin/acme\n");
+    if !install_plugin(
+        &project.path().join("bin"),
+        "acme",
+        r#"{"name": "provider/acme", "capabilities": ["provider:acme"]}"#,
+    ) {
+        return;
+    }
+
+    generate(project.path()).assert().success();
+    assert!(project.path().join("out/acme.txt").exists());
+}
+
+#[test]
+fn conflicting_provider_plugins_are_rejected() {
+    let project =
+        write_project("providers:\n  - acme\n  - circleci\nplugins:\n  - cigen-provider-acme\n");
+    if !install_plugin(
+        &project.path().join("plugins"),
+        "cigen-provider-acme",
+        r#"{"name": "provider/acme", "capabilities": ["provider:acme"], "conflicts_with": ["provider:*"]}"#,
+    ) {
+        return;
+    }
+
+    let output = generate(project.path()).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Plugins 'provider/acme'")
+            && stderr.contains(
+                "declares conflicts_with 'provider:*', which matches 'provider:circleci'"
+            ),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn missing_plugin_requirement_is_rejected() {
+    let project = write_project("provider: acme\nplugins:\n  - cigen-provider-acme\n");
+    if !install_plugin(
+        &project.path().join("plugins"),
+        "cigen-provider-acme",
+        r#"{"name": "provider/acme", "capabilities": ["provider:acme"], "requires": ["lang:*"]}"#,
+    ) {
+        return;
+    }
+
+    let output = generate(project.path()).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr
+            .contains("Plugin 'provider/acme' requires 'lang:*', which no loaded plugin provides"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn unknown_plugin_name_lists_search_path() {
+    let project = write_project("provider: acme\nplugins:\n  - cigen-provider-missing\n");
+
+    let output = generate(project.path()).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Plugin 'cigen-provider-missing' not found; searched")
+            && stderr.contains(".cigen/plugins"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn list_plugins_reports_discovered_and_bundled_plugins() {
+    let project = write_project("provider: circleci\n");
+    if !install_plugin(
+        &project.path().join(".cigen/plugins"),
+        "cigen-lang-acme",
+        r#"{"name": "lang/acme", "capabilities": ["lang:acme", "cache:acme"]}"#,
+    ) {
+        return;
+    }
+
+    let output = cigen(project.path())
+        .args(["list", "plugins", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let plugins: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let plugins = plugins.as_array().unwrap();
+
+    let acme = plugins
+        .iter()
+        .find(|plugin| plugin["name"] == "lang/acme")
+        .expect("plugin from ~/.cigen/plugins should be listed");
+    assert_eq!(acme["version"], "0.0.1");
+    assert_eq!(
+        acme["capabilities"],
+        serde_json::json!(["lang:acme", "cache:acme"])
+    );
+    assert!(
+        acme["path"]
+            .as_str()
+            .unwrap()
+            .ends_with(".cigen/plugins/cigen-lang-acme")
+    );
+    assert!(
+        plugins
+            .iter()
+            .any(|plugin| plugin["name"] == "provider/circleci"),
+        "bundled providers should be listed: {plugins:?}"
+    );
+}
@@ -76,7 +76,7 @@ async fn test_plugin_metadata() -> Result<()> {
     // Verify metadata
     assert_eq!(metadata.name, "provider/github");
     assert_eq!(metadata.version, "0.1.0");
-    assert_eq!(metadata.protocol, 1);
+    assert_eq!(metadata.protocol, 2);
     assert!(
         metadata
             .capabilities
@@ -0,0 +1,191 @@
+//! Lints every run command in the golden fixtures' generated configs with
+//! `shellcheck -s bash`, so quoting mistakes in generated shell fail the build.
+//!
+//! The fixtures under `tests/fixtures/*/expected` are kept in sync with the
+//! generators by `tests/golden.rs`. The lint needs `shellcheck`, so it's
+//! ignored by a plain `cargo test`; CI installs shellcheck and runs it with
+//! `cargo test --test shellcheck -- --ignored`.
+use serde_yaml::Value;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// CircleCI fills in `<< parameters.x >>` before the shell runs, which can
+/// leave constant comparisons behind
+const EXCLUDED_CHECKS: &str = "SC2050";
+
+fn fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn shellcheck_installed() -> bool {
+    Command::new("shellcheck")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+fn yaml_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            yaml_files(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "yml") {
+            files.push(path);
+        }
+    }
+}
+
+/// Run commands in a CircleCI or GitHub Actions config, as (step name, command)
+fn run_commands(value: &Value, commands: &mut Vec<(String, String)>) {
+    match value {
+        Value::Mapping(map) => {
+            let name = map.get("name").and_then(Value::as_str).unwrap_or("run");
+            match map.get("run") {
+                Some(Value::String(command)) => {
+                    commands.push((name.to_string(), command.clone()));
+                }
+                Some(Value::Mapping(run)) => {
+                    if let Some(command) = run.get("command").and_then(Value::as_str) {
+                        let name = run.get("name").and_then(Value::as_str).unwrap_or(name);
+                        commands.push((name.to_string(), command.to_string()));
+                    }
+                }
+                _ => {}
+            }
+            for (key, value) in map {
+                if key.as_str() != Some("run") {
+                    run_commands(value, commands);
+                }
+            }
+        }
+        Value::Sequence(items) => {
+            for item in items {
+                run_commands(item, commands);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// `command` with provider template expressions (`<< parameters.x >>`,
+/// `${{ matrix.x }}`) replaced by a plain word. Heredocs and here-strings are
+/// left alone.
+fn without_templates(command: &str) -> String {
+    let mut script = String::new();
+    let mut rest = command;
+    'scan: while !rest.is_empty() {
+        for (open, close) in [("<< ", " >>"), ("${{", "}}")] {
+            let Some(tail) = rest.strip_prefix(open) else {
+                continue;
+            };
+            let Some(end) = tail.find(close) else {
+                continue;
+            };
+            let inner = &tail[..end];
+            let is_expression = if open == "<< " {
+                !inner.is_empty()
+                    && inner
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+            } else {
+                !inner.contains('\n')
+            };
+            if is_expression {
+                script.push_str("cigen_template_value");
+                rest = &tail[end + close.len()..];
+                continue 'scan;
+            }
+        }
+        let next = rest.chars().next().unwrap();
+        script.push(next);
+        rest = &rest[next.len_utf8()..];
+    }
+    script
+}
+
+fn shellcheck(script: &str) -> String {
+    let mut child = Command::new("shellcheck")
+        .args([
+            "-s",
+            "bash",
+            "-S",
+            "info",
+            "-e",
+            EXCLUDED_CHECKS,
+            "-f",
+            "gcc",
+            "-",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run shellcheck");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(format!("#!/usr/bin/env bash\n{script}").as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+#[test]
+fn without_templates_replaces_provider_expressions() {
+    assert_eq!(
+        without_templates("[ \"<< pipeline.parameters.x >>\" = true ] && echo ${{ matrix.shard }}"),
+        "[ \"cigen_template_value\" = true ] && echo cigen_template_value"
+    );
+    let heredoc = "cat <<'EOF' >> log\nx\nEOF\ngrep -q x <<< \"$y\" >> out\n";
+    assert_eq!(without_templates(heredoc), heredoc);
+}
+
+#[test]
+#[ignore = "needs shellcheck; run with `cargo test --test shellcheck -- --ignored`"]
+fn generated_run_commands_pass_shellcheck() {
+    assert!(
+        shellcheck_installed(),
+        "shellcheck isn't installed; install it to lint the generated shell"
+    );
+
+    let mut files = Vec::new();
+    for fixture in fs::read_dir(fixtures_dir()).unwrap() {
+        yaml_files(&fixture.unwrap().path().join("expected"), &mut files);
+    }
+    files.sort();
+    assert!(!files.is_empty(), "no generated fixture configs found");
+
+    let mut failures = Vec::new();
+    let mut checked = 0;
+    for file in &files {
+        let config: Value = serde_yaml::from_str(&fs::read_to_string(file).unwrap())
+            .unwrap_or_else(|error| panic!("{}: {error}", file.display()));
+        let mut commands = Vec::new();
+        run_commands(&config, &mut commands);
+        for (name, command) in commands {
+            checked += 1;
+            let findings = shellcheck(&without_templates(&command));
+            if !findings.is_empty() {
+                let relative = file.strip_prefix(fixtures_dir()).unwrap_or(file);
+                failures.push(format!(
+                    "{} step '{name}':\n{findings}\n--- command ---\n{command}",
+                    relative.display()
+                ));
+            }
+        }
+    }
+
+    assert!(checked > 0, "no run commands found in the fixtures");
+    assert!(
+        failures.is_empty(),
+        "shellcheck found problems in {} generated run command(s):\n\n{}",
+        failures.len(),
+        failures.join("\n\n")
+    );
+}
@@ -0,0 +1,55 @@
+//! Provider plugin that crashes on demand, used to test plugin crash handling.
+//!
+//! Behaviour is read from a `crash-mode` file next to the binary:
+//! - `always`: exit with status 3 on every plan request
+//! - `once`: crash on the first plan request only (leaves a `crashed` marker)
+//! - anything else: answer requests with an empty result
+use anyhow::Result;
+use cigen::plugin::framing::{receive_message, send_message};
+use cigen::plugin::protocol::{
+    GenerateRequest, GenerateResult, Hello, PlanRequest, PlanResult, PluginInfo,
+};
+use std::path::PathBuf;
+
+fn main() -> Result<()> {
+    let exe = PathBuf::from(std::env::args().next().unwrap_or_default());
+    let dir = exe.parent().map(PathBuf::from).unwrap_or_default();
+    let mode = std::fs::read_to_string(dir.join("crash-mode")).unwrap_or_default();
+    let marker = dir.join("crashed");
+
+    let mut stdin = std::io::stdin().lock();
+    let mut stdout = std::io::stdout().lock();
+
+    let _hello: Hello = receive_message(&mut stdin)?;
+    send_message(
+        &PluginInfo {
+            name: "provider/circleci".to_string(),
+            version: "0.0.0".to_string(),
+            protocol: 1,
+            capabilities: vec!["provider:circleci".to_string()],
+            ..Default::default()
+        },
+        &mut stdout,
+    )?;
+
+    while let Ok(_plan) = receive_message::<PlanRequest, _>(&mut stdin) {
+        let crash = match mode.trim() {
+            "always" => true,
+            "once" => !marker.exists(),
+            _ => false,
+        };
+        if crash {
+            std::fs::write(&marker, "")?;
+            eprintln!("crashing_plugin: simulated crash");
+            std::process::exit(3);
+        }
+        send_message(&PlanResult::default(), &mut stdout)?;
+
+        let Ok(_generate) = receive_message::<GenerateRequest, _>(&mut stdin) else {
+            break;
+        };
+        send_message(&GenerateResult::default(), &mut stdout)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,71 @@
+//! Configurable provider plugin, used to test plugin discovery and compatibility.
+//!
+//! The handshake is read from a `<binary name>.json` file next to the binary:
+//! `{"name": "...", "capabilities": [...], "requires": [...], "conflicts_with": [...]}`.
+//! Generate requests produce a single `<name>.txt` fragment naming the plugin.
+use anyhow::{Context, Result};
+use cigen::plugin::framing::{receive_message, send_message};
+use cigen::plugin::protocol::{
+    Fragment, GenerateRequest, GenerateResult, Hello, PlanRequest, PlanResult, PluginInfo,
+};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+#[derive(Deserialize)]
+struct FixtureInfo {
+    name: String,
+    #[serde(default)]
+    capabilities: Vec<String>,
+    #[serde(default)]
+    requires: Vec<String>,
+    #[serde(default)]
+    conflicts_with: Vec<String>,
+}
+
+fn main() -> Result<()> {
+    let exe = PathBuf::from(std::env::args().next().unwrap_or_default());
+    let info_path = exe.with_extension("json");
+    let info: FixtureInfo = serde_json::from_str(
+        &std::fs::read_to_string(&info_path)
+            .with_context(|| format!("Failed to read {}", info_path.display()))?,
+    )?;
+
+    let mut stdin = std::io::stdin().lock();
+    let mut stdout = std::io::stdout().lock();
+
+    let _hello: Hello = receive_message(&mut stdin)?;
+    send_message(
+        &PluginInfo {
+            name: info.name.clone(),
+            version: "0.0.1".to_string(),
+            protocol: 1,
+            capabilities: info.capabilities,
+            requires: info.requires,
+            conflicts_with: info.conflicts_with,
+            ..Default::default()
+        },
+        &mut stdout,
+    )?;
+
+    while let Ok(_plan) = receive_message::<PlanRequest, _>(&mut stdin) {
+        send_message(&PlanResult::default(), &mut stdout)?;
+
+        let Ok(generate) = receive_message::<GenerateRequest, _>(&mut stdin) else {
+            break;
+        };
+        let fragment = Fragment {
+            path: format!("{}.txt", generate.target),
+            content: format!("generated by {}\n", info.name),
+            ..Default::default()
+        };
+        send_message(
+            &GenerateResult {
+                fragments: vec![fragment],
+                ..Default::default()
+            },
+            &mut stdout,
+        )?;
+    }
+
+    Ok(())
+}
@@ -12,15 +12,15 @@ jobs:
 }
 
 #[test]
-fn github_parameter_conditions_are_rejected() {
+fn github_parameter_conditions_are_supported() {
     let yaml = format!(
         "{}\nworkflows:\n  main:\n    run_when:\n      - provider: github\n        parameter: run_docs\n",
         base_config_head()
     );
 
     assert!(
-        CigenConfig::from_yaml(&yaml).is_err(),
-        "parameter conditions should be rejected for GitHub"
+        CigenConfig::from_yaml(&yaml).is_ok(),
+        "parameter conditions become workflow_dispatch inputs on GitHub"
     );
 }
 
@@ -44,8 +44,50 @@ fn github_env_conditions_are_rejected() {
         base_config_head()
     );
 
+    let error = CigenConfig::from_yaml(&yaml).unwrap_err().to_string();
+    assert!(
+        error.contains("env conditions are not supported on GitHub Actions"),
+        "{error}"
+    );
+}
+
+#[test]
+fn circleci_expression_conditions_are_supported() {
+    let yaml = format!(
+        "{}\nworkflows:\n  main:\n    run_when:\n      - provider: circleci\n        expression: param.deploy && !param.dry_run\n",
+        base_config_head()
+    );
+
+    assert!(
+        CigenConfig::from_yaml(&yaml).is_ok(),
+        "expected expression condition for CircleCI to be accepted"
+    );
+}
+
+#[test]
+fn circleci_env_conditions_suggest_a_pipeline_parameter() {
+    let yaml = format!(
+        "{}\nworkflows:\n  main:\n    run_when:\n      - provider: circleci\n        env: FEATURE_FLAG\n",
+        base_config_head()
+    );
+
+    let error = CigenConfig::from_yaml(&yaml).unwrap_err().to_string();
+    assert_eq!(
+        error,
+        "Workflow 'main': env conditions are not supported on CircleCI; use a pipeline parameter"
+    );
+}
+
+#[test]
+fn run_unless_conditions_are_validated_like_run_when() {
+    let yaml = format!(
+        "{}\nworkflows:\n  main:\n    run_unless:\n      - provider: circleci\n        env: FEATURE_FLAG\n",
+        base_config_head()
+    );
+
+    let error = CigenConfig::from_yaml(&yaml).unwrap_err().to_string();
     assert!(
-        CigenConfig::from_yaml(&yaml).is_err(),
-        "env-based conditions should be rejected for GitHub"
+        error.contains("env conditions are not supported on CircleCI"),
+        "{error}"
     );
 }